@@ -0,0 +1,73 @@
+//! Throughput of the hot paths of a share: the SV1 `mining.submit` line parsed and translated to
+//! a SV2 `SubmitSharesExtended` by the translator, then the header it makes up hashed and checked
+//! against the target of the channel, as the channel factories of the translator and the pool do.
+//! Run with `cargo bench`, criterion compares each run with the one before.
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use potato::proxy_wallet::{downstream_sv1::Sv1Frame, proxy::bridge::translate_submit};
+use roles_logic_sv2::mining_sv2::Target;
+use stratum_common::bitcoin::{consensus::deserialize, hashes::Hash, BlockHeader};
+use sv1_api::{client_to_server::Submit, json_rpc, utils::HexU32Be};
+
+const SUBMIT: &str = concat!(
+    r#"{"id":4,"method":"mining.submit","#,
+    r#""params":["rig.1","7","00000001","5f5e1000","0000abcd","00002000"]}"#
+);
+
+/// The header of the genesis block.
+const HEADER: &str = concat!(
+    "0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b2",
+    "7ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a29ab5f49ffff001d1dac2b7c"
+);
+
+/// Difficulty 1 as the translator sets it, little endian.
+fn target() -> Target {
+    let mut target = [0xff; 32];
+    target[28..].fill(0);
+    target.into()
+}
+
+fn submit() -> Submit<'static> {
+    let Ok(Sv1Frame::Single(json_rpc::Message::StandardRequest(request))) =
+        serde_json::from_str::<Sv1Frame>(SUBMIT)
+    else {
+        unreachable!("a mining.submit request")
+    };
+    request.try_into().expect("a valid mining.submit")
+}
+
+fn translation(c: &mut Criterion) {
+    let mut group = c.benchmark_group("translation");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("parse mining.submit", |b| {
+        b.iter(|| serde_json::from_str::<Sv1Frame>(black_box(SUBMIT)).unwrap())
+    });
+    let submit = submit();
+    let mask = Some(HexU32Be(0x1fffe000));
+    group.bench_function("mining.submit to SubmitSharesExtended", |b| {
+        b.iter(|| {
+            translate_submit(1, black_box(submit.clone()), mask.clone(), 0x2000_0000).unwrap()
+        })
+    });
+    group.finish();
+}
+
+fn validation(c: &mut Criterion) {
+    let mut group = c.benchmark_group("validation");
+    group.throughput(Throughput::Elements(1));
+    let header: BlockHeader = deserialize(&hex::decode(HEADER).unwrap()).unwrap();
+    group.bench_function("header hash", |b| {
+        b.iter(|| black_box(&header).block_hash())
+    });
+    let hash = header.block_hash().into_inner();
+    let target = target();
+    group.bench_function("hash against target", |b| {
+        b.iter(|| {
+            let hash: Target = black_box(hash).into();
+            &hash <= black_box(&target)
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, translation, validation);
+criterion_main!(benches);
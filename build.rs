@@ -0,0 +1,22 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // the gRPC API is part of the pool, the LND client of the mint
+    if std::env::var_os("CARGO_FEATURE_MINT").is_none() {
+        return Ok(());
+    }
+    // a protoc shipped with the build, so none has to be installed
+    if std::env::var_os("PROTOC").is_none() {
+        std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+    }
+    tonic_build::configure()
+        .build_server(false)
+        .compile_protos(
+            &["proto/lnd/lightning.proto", "proto/lnd/router.proto"],
+            &["proto/lnd"],
+        )?;
+    if std::env::var_os("CARGO_FEATURE_POOL").is_some() {
+        tonic_build::configure()
+            .build_client(false)
+            .compile_protos(&["proto/control.proto"], &["proto"])?;
+    }
+    Ok(())
+}
@@ -0,0 +1,36 @@
+#![no_main]
+//! Lines as miners send them to the translator, handled as the translator handles them: parsed as
+//! a single message or a batch, then answered or passed on to the bridge.
+use libfuzzer_sys::fuzz_target;
+use potato::{
+    proxy_wallet::proxy_config::{
+        DownstreamDifficultyConfig, SubmissionPipelineConfig, UpstreamDifficultyConfig,
+    },
+    sim::{script::Sv1Script, Simulation},
+};
+
+const SUBSCRIBE: &str = r#"{"id":1,"method":"mining.subscribe","params":["cgminer/4.10.0"]}"#;
+const AUTHORIZE: &str = r#"{"id":2,"method":"mining.authorize","params":["rig.1","x"]}"#;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(line) = std::str::from_utf8(data) else {
+        return;
+    };
+    Simulation::new(0, 1_700_000_000).run(async {
+        let script = Sv1Script::new(
+            1,
+            vec![0, 0, 0, 1],
+            4,
+            "1",
+            DownstreamDifficultyConfig::new(10_000_000.0, 6.0, 0, 0),
+            UpstreamDifficultyConfig::new(60, 0.0, 0, false),
+            &SubmissionPipelineConfig::default(),
+        );
+        // first thing on the connection, then once subscribed and authorized as most methods
+        // are only handled then
+        for line in [line, SUBSCRIBE, AUTHORIZE, line] {
+            let _ = script.send(line).await;
+        }
+        let _ = (script.answers(), script.passed_on());
+    });
+});
@@ -0,0 +1,37 @@
+#![no_main]
+//! Frames as miners send them to the pool, once decrypted: the header is read, the payload
+//! parsed as the message its type says, and the first frame of a connection handled as the
+//! `SetupConnection` it has to be.
+use libfuzzer_sys::fuzz_target;
+use potato::pool_mint::mining_pool::{setup_connection::SetupConnectionHandler, StdFrame};
+use roles_logic_sv2::{parsers::PoolMessages, utils::Mutex};
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    sync::Arc,
+};
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(mut frame) = StdFrame::from_bytes(data.to_vec().into()) else {
+        return;
+    };
+    let Some(header) = frame.get_header() else {
+        return;
+    };
+    let _ = PoolMessages::try_from((header.msg_type(), frame.payload()));
+
+    let Ok(frame) = StdFrame::from_bytes(data.to_vec().into()) else {
+        return;
+    };
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .expect("a current thread runtime always builds");
+    runtime.block_on(async {
+        let (sender, mut receiver) = async_channel::unbounded();
+        let (mut responses, _responses) = async_channel::unbounded();
+        let _ = sender.send(frame.into()).await;
+        let handler = Arc::new(Mutex::new(SetupConnectionHandler::new()));
+        let address = SocketAddr::from((Ipv4Addr::LOCALHOST, 34254));
+        let _ =
+            SetupConnectionHandler::setup(handler, &mut receiver, &mut responses, address).await;
+    });
+});
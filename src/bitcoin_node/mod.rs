@@ -1,13 +1,31 @@
+//! A Bitcoin Core node run by the process, on Unix and Windows alike:
+//!
+//! - `bitcoind` is the one `BITCOIND` names, else the first on the `PATH` (`bitcoind.exe` on
+//!   Windows), else where the Windows installer puts it;
+//! - it is given its data directory as an absolute path, never a `\\?\` one that it rejects;
+//! - it is stopped over RPC, as there is no SIGTERM on Windows, and killed if it doesn't stop in
+//!   `STOP_TIMEOUT`, or if dropped.
+//!
+//! The harness of the integration tests (see `crate::testing`) runs one on regtest serving
+//! templates over SV2, which takes a build with the template provider such as the `sv2` branch of
+//! github.com/Sjors/bitcoin.
+use crate::retry::{Retry, RetryPolicy};
 use anyhow::Result;
 use bitcoincore_rpc::{Auth, Client as BitcoinCoreClient, RpcApi};
-use std::path::PathBuf;
-use std::time::Duration;
+use std::{
+    env,
+    path::{self, PathBuf},
+    time::Duration,
+};
 use stratum_common::bitcoin;
-use tokio::fs;
-use tracing::{debug, info};
+use tokio::{fs, process::Child};
+use tracing::{debug, info, warn};
+
+/// How long the node has to stop once asked to before it is killed.
+pub const STOP_TIMEOUT: Duration = Duration::from_secs(60);
 
 const BITCOIN_CONF_TEMPLATE: &str = r#"
-regtest=1
+{chain}=1
 fallbackfee=0.0004
 txindex=1
 server=1
@@ -19,44 +37,85 @@ rpcworkqueue=1024
 rpcthreads=64
 deprecatedrpc=warnings
 
-[regtest]
+[{section}]
 port={p2p_port}
 bind=127.0.0.1:{p2p_port}
 rpcport={rpc_port}
 rpcbind=127.0.0.1:{rpc_port}
-"#;
+{options}"#;
 
+/// A Bitcoin Core node run by the process, with its data in `data_dir`.
 pub struct BitcoinNode {
     client: BitcoinCoreClient,
     data_dir: PathBuf,
+    child: Child,
 }
 
 impl BitcoinNode {
+    /// Starts `bitcoind` on `network`, regtest, testnet or signet, with its data in `data_dir`.
     pub async fn new(data_dir: PathBuf, network: bitcoin::Network) -> Result<Self> {
-        fs::create_dir_all(&data_dir).await?;
-
         let rpc_port = match network {
             bitcoin::Network::Regtest => 18443,
             bitcoin::Network::Testnet => 18332,
             bitcoin::Network::Signet => 38332,
             _ => return Err(anyhow::anyhow!("Unsupported network")),
         };
+        Self::start(data_dir, network, rpc_port, "").await
+    }
+
+    /// Starts `bitcoind` on regtest with its data in `data_dir`, its RPC on `rpc_port` and the 3
+    /// ports after it for P2P and ZMQ, serving templates over SV2 on `sv2_port`.
+    pub async fn regtest_with_template_provider(
+        data_dir: PathBuf,
+        rpc_port: u16,
+        sv2_port: u16,
+    ) -> Result<Self> {
+        // a template every second, whatever the fees
+        let options = format!(
+            "sv2=1\nsv2bind=127.0.0.1\nsv2port={}\nsv2interval=1\nsv2feedelta=0\n",
+            sv2_port
+        );
+        Self::start(data_dir, bitcoin::Network::Regtest, rpc_port, &options).await
+    }
+
+    /// Starts `bitcoind` with `options` added to the ones of `network`.
+    async fn start(
+        data_dir: PathBuf,
+        network: bitcoin::Network,
+        rpc_port: u16,
+        options: &str,
+    ) -> Result<Self> {
+        let data_dir = path::absolute(data_dir)?;
+        fs::create_dir_all(&data_dir).await?;
+
+        // the option selecting the chain, and the section of its options
+        let (chain, section) = match network {
+            bitcoin::Network::Regtest => ("regtest", "regtest"),
+            bitcoin::Network::Testnet => ("testnet", "test"),
+            bitcoin::Network::Signet => ("signet", "signet"),
+            _ => return Err(anyhow::anyhow!("Unsupported network")),
+        };
 
         let p2p_port = rpc_port + 1;
         let zmq_block_port = rpc_port + 2;
         let zmq_tx_port = rpc_port + 3;
 
         let conf = BITCOIN_CONF_TEMPLATE
+            .replace("{chain}", chain)
+            .replace("{section}", section)
             .replace("{rpc_port}", &rpc_port.to_string())
             .replace("{p2p_port}", &p2p_port.to_string())
             .replace("{zmq_block_port}", &zmq_block_port.to_string())
-            .replace("{zmq_tx_port}", &zmq_tx_port.to_string());
+            .replace("{zmq_tx_port}", &zmq_tx_port.to_string())
+            .replace("{options}", options);
 
         fs::write(data_dir.join("bitcoin.conf"), conf).await?;
 
-        let bitcoind_path = which::which("bitcoind")?;
+        let bitcoind_path = bitcoind_path()?;
+        info!("Starting {}", bitcoind_path.display());
         let mut cmd = tokio::process::Command::new(bitcoind_path);
-        cmd.arg(format!("-datadir={}", data_dir.display()));
+        cmd.arg(format!("-datadir={}", data_dir.display()))
+            .kill_on_drop(true);
 
         let child = cmd.spawn()?;
 
@@ -64,55 +123,88 @@ impl BitcoinNode {
         let auth = Auth::UserPass("bitcoin".to_string(), "bitcoin".to_string());
         let client = BitcoinCoreClient::new(&rpc_url, auth)?;
 
-        Ok(Self { client, data_dir })
+        Ok(Self {
+            client,
+            data_dir,
+            child,
+        })
     }
 
-    pub async fn wait_for_ready(&self, initial_sync: bool) -> Result<()> {
-        use tokio::time::sleep;
+    /// RPC client of the node.
+    pub fn client(&self) -> &BitcoinCoreClient {
+        &self.client
+    }
 
-        const MAX_WAIT: Duration = Duration::from_secs(8 * 60);
-        const INITIAL_WAIT: Duration = Duration::from_secs(1);
-        const MAX_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+    /// Waits for the node to answer RPCs, and to finish its initial sync if `initial_sync`.
+    pub async fn wait_for_ready(&self, initial_sync: bool) -> Result<()> {
+        /// Attempts of about 8 minutes, backing off to 30s.
+        const MAX_ATTEMPTS: u32 = 20;
         const SYNC_CHECK_INTERVAL: Duration = Duration::from_secs(3600);
 
+        // starting up, not down: no circuit breaker
+        let retry = Retry::new(
+            "bitcoin_node",
+            &RetryPolicy {
+                max_attempts: if initial_sync { 0 } else { MAX_ATTEMPTS },
+                breaker_threshold: 0,
+                ..RetryPolicy::default()
+            },
+        );
         let start = std::time::Instant::now();
-        let mut wait_time = INITIAL_WAIT;
-
         loop {
-            match self.client.get_blockchain_info() {
-                Ok(info) => {
-                    let elapsed = start.elapsed();
-                    if initial_sync && info.initial_block_download {
-                        let progress = info.verification_progress * 100.0;
-                        info!("Bitcoin Core syncing... {:.2}% complete", progress);
-                        sleep(SYNC_CHECK_INTERVAL).await;
-                        continue;
-                    }
-                    debug!("Bitcoin Core ready after {:?}", elapsed);
-                    return Ok(());
-                }
-                Err(e) => {
-                    debug!("Waiting for Bitcoin Core: {}", e);
-
-                    if !initial_sync && start.elapsed() >= MAX_WAIT {
-                        return Err(anyhow::anyhow!(
-                            "Timeout waiting for bitcoind after {:?}",
-                            MAX_WAIT
-                        ));
-                    }
-
-                    sleep(if initial_sync {
-                        SYNC_CHECK_INTERVAL
-                    } else {
-                        wait_time
-                    })
-                    .await;
-
-                    if !initial_sync {
-                        wait_time = std::cmp::min(wait_time * 2, MAX_RETRY_INTERVAL);
-                    }
-                }
+            let info = retry
+                .run(|| async { self.client.get_blockchain_info() })
+                .await
+                .map_err(|e| anyhow::anyhow!("Timeout waiting for bitcoind: {}", e))?;
+            if initial_sync && info.initial_block_download {
+                let progress = info.verification_progress * 100.0;
+                info!("Bitcoin Core syncing... {:.2}% complete", progress);
+                tokio::time::sleep(SYNC_CHECK_INTERVAL).await;
+                continue;
+            }
+            debug!("Bitcoin Core ready after {:?}", start.elapsed());
+            return Ok(());
+        }
+    }
+
+    /// Stops the node, killing it if it doesn't within `STOP_TIMEOUT`.
+    pub async fn stop(mut self) -> Result<()> {
+        if let Err(e) = self.client.stop() {
+            warn!("Bitcoin Core didn't take the stop RPC: {}", e);
+        }
+        match tokio::time::timeout(STOP_TIMEOUT, self.child.wait()).await {
+            Ok(status) => {
+                info!("Bitcoin Core in {} stopped", self.data_dir.display());
+                status?;
+            }
+            Err(_) => {
+                warn!(
+                    "Bitcoin Core still running {}s after the stop RPC, killing it",
+                    STOP_TIMEOUT.as_secs()
+                );
+                self.child.kill().await?;
             }
         }
+        Ok(())
+    }
+}
+
+/// The `bitcoind` to run, see the module.
+fn bitcoind_path() -> Result<PathBuf> {
+    if let Some(path) = env::var_os("BITCOIND") {
+        return Ok(PathBuf::from(path));
+    }
+    if let Ok(path) = which::which("bitcoind") {
+        return Ok(path);
+    }
+    #[cfg(windows)]
+    if let Some(program_files) = env::var_os("ProgramFiles") {
+        let path = PathBuf::from(program_files).join(r"Bitcoin\daemon\bitcoind.exe");
+        if path.is_file() {
+            return Ok(path);
+        }
     }
+    Err(anyhow::anyhow!(
+        "bitcoind not found on the PATH, set BITCOIND to where it is"
+    ))
 }
@@ -0,0 +1,484 @@
+//! Maintenance commands run instead of the pool and proxy, see `configuration::Command`.
+#[cfg(feature = "tui")]
+use crate::tui;
+use crate::{
+    configuration::{Command, EventsCommand, MintCommand, WalletCommand},
+    control,
+    dirs::Dirs,
+    error::MintResult,
+    pool_mint::{
+        maturity,
+        mining_pool::PoolConfiguration,
+        mint::{
+            audit::{self, AuditReport},
+            backup::{self, BackupSummary},
+            db::{self, MintDb},
+            lightning::LightningBackend,
+            wallet::Wallet,
+        },
+    },
+    status::{
+        events, history,
+        report::{self, Report},
+        server::StatusReport,
+    },
+};
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    io::{self, Write},
+    time::Duration,
+};
+use tracing::{error, info, warn};
+// `cpuminer`, mining on a translator with the miners of `crate::testing`
+#[cfg(all(feature = "proxy", feature = "node"))]
+use {
+    crate::testing::Sv1Miner,
+    std::{
+        net::SocketAddr,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    },
+};
+
+/// Environment variable read for the backup passphrase before prompting for it.
+const PASSPHRASE_ENV: &str = "POTATO_BACKUP_PASSPHRASE";
+/// Environment variable read for the mnemonic of a restored wallet before prompting for it.
+const MNEMONIC_ENV: &str = "POTATO_WALLET_MNEMONIC";
+/// Environment variable read for the credential of a claimed account before prompting for it.
+const CREDENTIAL_ENV: &str = "POTATO_ACCOUNT_CREDENTIAL";
+/// How long `healthcheck` waits for the control API, a process not answering being unhealthy.
+const HEALTHCHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub async fn run(
+    command: Command,
+    pool_settings: &PoolConfiguration,
+    dirs: &Dirs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match command {
+        Command::Mint {
+            command: MintCommand::Backup { output },
+        } => {
+            let passphrase = passphrase()?;
+            let (data, summary) = backup::export(&pool_settings.mint, &passphrase)?;
+            fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&output)?
+                .write_all(&data)?;
+            info!("Mint backup written to {}", output);
+            log_summary(&summary);
+        }
+        Command::Mint {
+            command: MintCommand::Restore { input, force },
+        } => {
+            let data = fs::read(&input)?;
+            let passphrase = passphrase()?;
+            let summary = backup::restore(&pool_settings.mint, &data, &passphrase, force)?;
+            info!("Mint restored from {}", input);
+            log_summary(&summary);
+        }
+        Command::Mint {
+            command: MintCommand::Audit { output, books_only },
+        } => {
+            let mut report = audit::books(&pool_settings.mint)?;
+            if !books_only {
+                let lightning = match &pool_settings.mint.lightning {
+                    Some(config) => {
+                        let balance = match LightningBackend::new(&config.backend, &config.retry) {
+                            Ok(backend) => backend.balance().await,
+                            Err(e) => Err(e),
+                        };
+                        unless_failed(&mut report, "Lightning", balance)
+                    }
+                    None => None,
+                };
+                let onchain = match &pool_settings.bitcoin_rpc {
+                    Some(config) => {
+                        let balance = maturity::wallet_balance(config).await;
+                        unless_failed(&mut report, "Bitcoin Core wallet", balance)
+                    }
+                    None => None,
+                };
+                if pool_settings.mint.lightning.is_none() && pool_settings.bitcoin_rpc.is_none() {
+                    warn!(
+                        "Neither lightning nor bitcoin_rpc configured, held reserves not checked"
+                    );
+                }
+                report.check_reserves(lightning, onchain);
+            }
+            let json = serde_json::to_string_pretty(&report)?;
+            match output {
+                Some(output) => fs::write(&output, json)?,
+                None => println!("{}", json),
+            }
+            for discrepancy in &report.discrepancies {
+                error!("Mint audit: {}", discrepancy);
+            }
+            if !report.is_solvent() {
+                return Err(format!(
+                    "mint audit found {} discrepancies",
+                    report.discrepancies.len()
+                )
+                .into());
+            }
+            info!("Mint audit found no discrepancies");
+        }
+        Command::Mint {
+            command: MintCommand::Migrate { to },
+        } => {
+            let version = db::migrate_database(&pool_settings.mint.db_path, to)?;
+            info!(
+                "Mint database {} at schema version {}",
+                pool_settings.mint.db_path, version
+            );
+        }
+        Command::Mint {
+            command: MintCommand::Credential { account },
+        } => {
+            let result = control::request(
+                &pool_settings.control_address,
+                &pool_settings.control_token_path,
+                serde_json::json!({ "command": "issue_credential", "account": account }),
+            )
+            .await?;
+            println!("{}", result["credential"].as_str().unwrap_or_default());
+        }
+        Command::Wallet {
+            path,
+            mint_url,
+            command: WalletCommand::Restore,
+        } => {
+            let mint_url = mint_url.unwrap_or_else(|| pool_settings.mint.url());
+            let mnemonic = prompt("the wallet mnemonic", MNEMONIC_ENV)?;
+            let path = dirs.data_path(&path);
+            let (_, restored) = Wallet::restore(&path, &mint_url, &mnemonic).await?;
+            info!("Wallet {} restored with {}", path, amounts(&restored));
+        }
+        Command::Wallet {
+            path,
+            mint_url,
+            command,
+        } => {
+            let mint_url = mint_url.unwrap_or_else(|| pool_settings.mint.url());
+            let mut wallet = Wallet::open(&dirs.data_path(&path), &mint_url)?;
+            run_wallet(&mut wallet, command).await?;
+        }
+        Command::Events {
+            command:
+                EventsCommand::Query {
+                    since,
+                    until,
+                    types,
+                    limit,
+                },
+        } => {
+            let Some(config) = &pool_settings.event_history else {
+                return Err("no event_history in the pool mint config".into());
+            };
+            let now_ms = events::now() * 1000;
+            let since_ms = history::parse_since(&since, now_ms)?;
+            let until_ms = until
+                .map(|until| history::parse_since(&until, now_ms))
+                .transpose()?;
+            let types: Vec<String> = types
+                .iter()
+                .flat_map(|types| types.split(','))
+                .map(|kind| kind.trim().to_string())
+                .collect();
+            for event in history::query(&config.path, since_ms, until_ms, &types, limit)? {
+                println!("{}", event);
+            }
+        }
+        Command::Status { address, json } => {
+            let address = address.unwrap_or_else(|| pool_settings.control_address.clone());
+            let result = control::request(
+                &address,
+                &pool_settings.control_token_path,
+                serde_json::json!({ "command": "status_report" }),
+            )
+            .await?;
+            let report: StatusReport = serde_json::from_value(result)?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                println!("{}", report.summary(events::now()));
+            }
+        }
+        Command::Report {
+            last,
+            difficulty,
+            json,
+        } => {
+            let Some(config) = &pool_settings.event_history else {
+                return Err("no event_history in the pool mint config".into());
+            };
+            let now_ms = events::now() * 1000;
+            let since_ms = history::parse_since(&last, now_ms)?;
+            let difficulty = match (difficulty, &pool_settings.bitcoin_rpc) {
+                (Some(difficulty), _) => Some(difficulty),
+                (None, Some(bitcoin_rpc)) => {
+                    match maturity::network_difficulty(bitcoin_rpc).await {
+                        Ok(difficulty) => Some(difficulty),
+                        Err(e) => {
+                            warn!("Failed to get the network difficulty, luck unknown: {}", e);
+                            None
+                        }
+                    }
+                }
+                (None, None) => None,
+            };
+            let payouts = MintDb::open(&pool_settings.mint.db_path)?
+                .payout_totals(since_ms / 1000, now_ms / 1000)?;
+            let events = report::events(&config.path, since_ms, now_ms)?;
+            let report = Report::new(since_ms / 1000, now_ms / 1000, &events, difficulty, payouts);
+            if json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                println!("{}", report.summary());
+            }
+        }
+        Command::Healthcheck { address } => {
+            let address = address.unwrap_or_else(|| pool_settings.control_address.clone());
+            let request = control::request(
+                &address,
+                &pool_settings.control_token_path,
+                serde_json::json!({ "command": "health" }),
+            );
+            let health = tokio::time::timeout(HEALTHCHECK_TIMEOUT, request)
+                .await
+                .map_err(|_| format!("the control API at {} did not answer", address))??;
+            let checks = health["checks"].as_object().cloned().unwrap_or_default();
+            for (name, check) in checks {
+                let state = match check["ok"].as_bool() {
+                    Some(true) => "ok",
+                    _ => "failing",
+                };
+                let detail = check["detail"].as_str().unwrap_or_default();
+                println!("{}: {}, {}", name, state, detail);
+            }
+            if health["ready"].as_bool() != Some(true) {
+                return Err("not ready".into());
+            }
+        }
+        #[cfg(feature = "tui")]
+        Command::Tui { url } => {
+            let url = match (url, &pool_settings.status_address) {
+                (Some(url), _) => url,
+                // the API listening on every interface is reached locally
+                (None, Some(address)) => {
+                    format!("http://{}", address.replace("0.0.0.0", "127.0.0.1"))
+                }
+                (None, None) => {
+                    return Err("no status_address in the pool mint config, pass --url".into())
+                }
+            };
+            tui::run(&url).await?;
+        }
+        #[cfg(all(feature = "proxy", feature = "node"))]
+        Command::Cpuminer {
+            address,
+            workers,
+            user,
+            shares,
+        } => cpuminer(address, workers, &user, shares).await?,
+    }
+    Ok(())
+}
+
+/// Shares and blocks found by the workers of `cpuminer`, so far.
+#[cfg(all(feature = "proxy", feature = "node"))]
+#[derive(Debug, Default)]
+struct Found {
+    accepted: AtomicUsize,
+    rejected: AtomicUsize,
+    blocks: AtomicUsize,
+}
+
+/// Mines with `workers` miners on the translator at `address`, each until it submitted `shares`
+/// or the command is interrupted, then prints what they found. Fails if a worker did.
+#[cfg(all(feature = "proxy", feature = "node"))]
+async fn cpuminer(
+    address: SocketAddr,
+    workers: usize,
+    user: &str,
+    shares: Option<usize>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let found = Arc::new(Found::default());
+    let mut miners = tokio::task::JoinSet::new();
+    for n in 1..=workers.max(1) {
+        let worker = format!("{}.{}", user, n);
+        let found = found.clone();
+        miners.spawn(async move {
+            let mut miner = Sv1Miner::connect(address, &worker).await?;
+            info!("{} mining on {}", worker, address);
+            while shares.is_none_or(|shares| miner.accepted + miner.rejected < shares) {
+                let blocks = miner.blocks;
+                let (result, counter) = match miner.mine(1).await? {
+                    0 => ("rejected", &found.rejected),
+                    _ => ("accepted", &found.accepted),
+                };
+                info!("{}: share {}", worker, result);
+                counter.fetch_add(1, Ordering::Relaxed);
+                if miner.blocks > blocks {
+                    info!("{}: found a block", worker);
+                    found.blocks.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            Ok::<_, io::Error>(())
+        });
+    }
+    let mut failed = 0;
+    let finished = async {
+        while let Some(joined) = miners.join_next().await {
+            let result = joined.map_err(io::Error::other).and_then(|mined| mined);
+            if let Err(e) = result {
+                error!("Worker failed: {}", e);
+                failed += 1;
+            }
+        }
+    };
+    tokio::select! {
+        _ = finished => {}
+        _ = tokio::signal::ctrl_c() => info!("Interrupted"),
+    }
+    println!(
+        "{} shares accepted, {} rejected, {} blocks found",
+        found.accepted.load(Ordering::Relaxed),
+        found.rejected.load(Ordering::Relaxed),
+        found.blocks.load(Ordering::Relaxed),
+    );
+    match failed {
+        0 => Ok(()),
+        failed => Err(format!("{} of {} workers failed", failed, workers.max(1)).into()),
+    }
+}
+
+async fn run_wallet(
+    wallet: &mut Wallet,
+    command: WalletCommand,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match command {
+        WalletCommand::Balance { account } => {
+            info!("Wallet holds {}", amounts(&wallet.balance()));
+            if let Some(account) = account {
+                let credential = prompt("the credential of the account", CREDENTIAL_ENV)?;
+                let balance = wallet.account_balance(&account, &credential).await?;
+                info!("Pool owes {} {}", account, amounts(&balance));
+            }
+        }
+        WalletCommand::Receive {
+            token: Some(token),
+            preimage,
+            ..
+        } => {
+            let received = wallet.receive(&token, preimage.as_deref()).await?;
+            info!("Received {}", amounts(&received));
+        }
+        WalletCommand::Receive { account, .. } => {
+            let account = account.ok_or("a token or an account is needed")?;
+            let credential = prompt("the credential of the account", CREDENTIAL_ENV)?;
+            let received = wallet.claim(&account, &credential).await?;
+            info!("Claimed {} for {}", amounts(&received), account);
+        }
+        WalletCommand::Check { token } => {
+            let check = wallet.check(&token).await?;
+            info!("Unspent: {}", amounts(&check.unspent));
+            if !check.pending.is_empty() {
+                warn!(
+                    "Reserved by a melt in progress: {}",
+                    amounts(&check.pending)
+                );
+            }
+            if !check.spent.is_empty() {
+                warn!("Already spent: {}", amounts(&check.spent));
+            }
+            if !check.pending.is_empty() || !check.spent.is_empty() {
+                return Err("the token is not fully unspent".into());
+            }
+        }
+        WalletCommand::Mnemonic => println!("{}", wallet.mnemonic()),
+        WalletCommand::Restore => unreachable!("restored before the wallet is opened"),
+        WalletCommand::Melt { invoice } => {
+            let quote = wallet.melt(&invoice).await?;
+            info!("Melt quote {} is {:?}", quote.quote, quote.state);
+        }
+        WalletCommand::MeltOnchain { address, amount } => {
+            let quote = wallet.melt_onchain(&address, amount).await?;
+            info!(
+                "Melt quote {} is {:?}, paid out with the next batch",
+                quote.quote, quote.state
+            );
+        }
+        WalletCommand::Export {
+            amount,
+            unit,
+            hash,
+            buyer,
+            locktime,
+        } => {
+            let lock = match hash {
+                Some(hash) => {
+                    let hash = <[u8; 32]>::try_from(hex::decode(&hash)?)
+                        .map_err(|_| "the hash must be 32 bytes")?;
+                    Some(wallet.hash_lock(hash, buyer, locktime))
+                }
+                None => None,
+            };
+            let token = wallet.export(&unit, amount, lock.as_ref()).await?;
+            println!("{}", token.encode()?);
+        }
+    }
+    Ok(())
+}
+
+/// The balance read from `source`, or `None` with the failure recorded as a discrepancy, since
+/// funds that can't be checked can't be counted on.
+fn unless_failed(report: &mut AuditReport, source: &str, balance: MintResult<u64>) -> Option<u64> {
+    match balance {
+        Ok(balance) => Some(balance),
+        Err(e) => {
+            report
+                .discrepancies
+                .push(format!("{} balance unavailable: {}", source, e));
+            None
+        }
+    }
+}
+
+fn amounts(amounts: &BTreeMap<String, u64>) -> String {
+    if amounts.values().all(|amount| *amount == 0) {
+        return "nothing".to_string();
+    }
+    amounts
+        .iter()
+        .filter(|(_, amount)| **amount > 0)
+        .map(|(unit, amount)| format!("{} {}", amount, unit))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn passphrase() -> io::Result<String> {
+    prompt("the backup passphrase", PASSPHRASE_ENV)
+}
+
+/// Reads `what` from the environment variable `var`, or else from stdin.
+fn prompt(what: &str, var: &str) -> io::Result<String> {
+    if let Ok(value) = env::var(var) {
+        return Ok(value);
+    }
+    info!("Please enter {} (or set {}): ", what, var);
+    io::stdout().flush()?;
+    let mut input = String::new();
+    io::stdin().read_line(&mut input)?;
+    Ok(input.trim_end_matches(['\r', '\n']).to_string())
+}
+
+fn log_summary(summary: &BackupSummary) {
+    info!(
+        "Backup taken at {}: {} keysets, {} issued signatures, {} spent proofs",
+        summary.created_at, summary.keysets, summary.signatures, summary.spent_proofs
+    );
+}
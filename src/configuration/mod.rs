@@ -1,27 +1,92 @@
-use crate::pool_mint::mining_pool::{CoinbaseOutput, PoolConfiguration};
-use crate::proxy_wallet::proxy_config::{
-    DownstreamDifficultyConfig, ProxyConfig, UpstreamDifficultyConfig,
+use crate::{
+    dirs::Dirs,
+    logging::{LogFileArgs, LogFormat},
+    status::diagnostics::ConsoleArgs,
 };
 use clap::Parser;
-use core::panic;
 use ext_config::{Config, File, FileFormat};
-use key_utils::Secp256k1PublicKey;
-use std::io::{self, Write};
-use std::str::FromStr;
-use stratum_common::bitcoin::secp256k1::Secp256k1;
-use stratum_common::bitcoin::util::bip32::{self, DerivationPath, ExtendedPubKey};
+use std::path::PathBuf;
 use stratum_common::bitcoin::Network;
-use tracing::{error, info, warn};
+
+// the configuration of the pool and the maintenance commands run on its files
+#[cfg(feature = "otlp")]
+use crate::otlp::OtlpArgs;
+#[cfg(feature = "pool")]
+use {
+    crate::{
+        pool_mint::{
+            mining_pool::{
+                default_control_address, default_control_token_path, CoinbaseOutput,
+                PoolConfiguration, TemplateChannels,
+            },
+            mint::{seed::SeedConfig, MintConfig},
+        },
+        ratelimit::RateLimitsConfig,
+        retry::RetryPolicy,
+    },
+    clap::Subcommand,
+    core::panic,
+    key_utils::Secp256k1PublicKey,
+    roles_logic_sv2::utils::Mutex,
+    std::{
+        io::{self, Write},
+        str::FromStr,
+        sync::Arc,
+    },
+    stratum_common::bitcoin::{
+        secp256k1::Secp256k1,
+        util::bip32::{self, DerivationPath, ExtendedPubKey},
+    },
+    tokio::sync::Notify,
+    tracing::{error, info, warn},
+};
+// the configuration of the translator
+#[cfg(feature = "proxy")]
+use crate::proxy_wallet::proxy_config::ProxyConfig;
+#[cfg(all(feature = "pool", feature = "proxy"))]
+use crate::proxy_wallet::proxy_config::{
+    ChannelCapacities, DownstreamDifficultyConfig, SubmissionPipelineConfig,
+    UpstreamDifficultyConfig,
+};
 
 #[derive(Parser, Debug)]
 #[clap(author = "Gary Krause", version, about)]
-/// Application configuration
+/// Application configuration, of the pool, the translator or both as the binary was built with
 pub struct Args {
     /// whether to be verbose
     #[arg(short = 'v')]
     pub verbose: bool,
 
-    /// Path to the proxy wallet configuration file
+    /// Format logs are written in, `json` for log pipelines
+    #[arg(long = "log-format", value_enum, default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+
+    #[command(flatten)]
+    pub log_file: LogFileArgs,
+
+    #[cfg(feature = "otlp")]
+    #[command(flatten)]
+    pub otlp: OtlpArgs,
+
+    #[command(flatten)]
+    pub console: ConsoleArgs,
+
+    /// Directory configuration files are read from, `~/.config/potato` if unset
+    #[arg(long = "config-dir")]
+    pub config_dir: Option<PathBuf>,
+
+    /// Directory the files of the mint, history, logs of connections and crash bundles are
+    /// written to, `~/.local/share/potato` if unset
+    #[arg(long = "data-dir")]
+    pub data_dir: Option<PathBuf>,
+
+    /// Directory crash bundles are written to when the process panics, in the data directory if
+    /// relative
+    #[arg(long = "crash-dir", default_value = "crashes")]
+    pub crash_dir: PathBuf,
+
+    /// Path to the proxy wallet configuration file, in the config directory if relative
+    #[cfg(feature = "proxy")]
     #[arg(
         short = 'p',
         long = "proxy-config",
@@ -29,7 +94,8 @@ pub struct Args {
     )]
     pub proxy_config_path: String,
 
-    /// Path to the pool mint configuration file
+    /// Path to the pool mint configuration file, in the config directory if relative
+    #[cfg(feature = "pool")]
     #[arg(
         short = 'm',
         long = "pool-mint-config",
@@ -38,10 +104,12 @@ pub struct Args {
     pub pool_mint_config_path: String,
 
     /// The coinbase output address where mining rewards will be sent (SLIP-132 format)
+    #[cfg(feature = "pool")]
     #[arg(short = 'c', long = "coinbase-output")]
     pub coinbase_output: Option<String>,
 
     /// The derivation path for the coinbase output (e.g. m/0/0)
+    #[cfg(feature = "pool")]
     #[arg(short = 'd', long = "derivation-path", default_value = "m/84/1/0")]
     pub derivation_path: String,
 
@@ -52,9 +120,233 @@ pub struct Args {
     /// Whether bitcoind is performing initial sync (extends wait time indefinitely)
     #[arg(long = "initial-sync")]
     pub initial_sync: bool,
+
+    /// Restarts of the pool or the translator failing in a row before shutting down
+    #[arg(long = "max-restarts", default_value_t = 5)]
+    pub max_restarts: u32,
+
+    /// Seconds the pool and the translator get to drain on SIGTERM or Ctrl-C before the process
+    /// exits anyway
+    #[arg(long = "shutdown-timeout", default_value_t = 30)]
+    pub shutdown_timeout: u64,
+
+    /// Unix socket, relative to the data directory, a new process takes the listeners of this
+    /// one over through, see `crate::handoff`
+    #[arg(long = "handoff-socket", default_value = "handoff.sock")]
+    pub handoff_socket: PathBuf,
+
+    /// Takes the listeners of the pool and the translator over from the process serving
+    /// `--handoff-socket`, which shuts down once this one accepts on them
+    #[arg(long = "takeover")]
+    pub takeover: bool,
+
+    /// Runs a maintenance command instead of the pool and proxy
+    #[cfg(feature = "pool")]
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[cfg(feature = "pool")]
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Mint maintenance
+    Mint {
+        #[command(subcommand)]
+        command: MintCommand,
+    },
+    /// Wallet for claiming and redeeming the tokens of a miner
+    Wallet {
+        /// File the wallet keeps its tokens in, in the data directory if relative
+        #[arg(long, default_value = "wallet.json")]
+        path: String,
+        /// URL of the mint API, the one of the pool mint config if unset
+        #[arg(long)]
+        mint_url: Option<String>,
+        #[command(subcommand)]
+        command: WalletCommand,
+    },
+    /// Events recorded in the `event_history` of the pool mint config
+    Events {
+        #[command(subcommand)]
+        command: EventsCommand,
+    },
+    /// Summary of the running pool, asked of its control API
+    Status {
+        /// Address of the control API, the `control_address` of the pool mint config if unset
+        #[arg(long)]
+        address: Option<String>,
+        /// Prints the status as JSON, as served by the status API `/v1/status`
+        #[arg(long)]
+        json: bool,
+    },
+    /// Uptime, effective hashrate, share acceptance, luck and payouts over a period, from the
+    /// `event_history` and the mint database of the pool mint config
+    Report {
+        /// Period reported, ending now, e.g. `24h` or `7d`, or a unix time it starts at
+        #[arg(long, default_value = "7d")]
+        last: String,
+        /// Network difficulty luck is computed at, asked of `bitcoin_rpc` if unset
+        #[arg(long)]
+        difficulty: Option<f64>,
+        /// Prints the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Exits with 0 if the running pool is ready, as asked of its control API, 1 otherwise: a
+    /// container `HEALTHCHECK` needing neither curl nor the status API
+    Healthcheck {
+        /// Address of the control API, the `control_address` of the pool mint config if unset
+        #[arg(long)]
+        address: Option<String>,
+    },
+    /// Live dashboard of the running pool in the terminal
+    #[cfg(feature = "tui")]
+    Tui {
+        /// URL of the status API, the one at `status_address` of the pool mint config if unset
+        #[arg(long)]
+        url: Option<String>,
+    },
+    /// Mines on the translator with the CPU, a smoke test of a regtest pool end to end. The
+    /// workers must be pinned to a low difficulty, e.g. `cpuminer*` in the `pinned_workers` of
+    /// the proxy config
+    #[cfg(all(feature = "proxy", feature = "node"))]
+    Cpuminer {
+        /// Address of the SV1 port of the translator
+        #[arg(long, default_value = "127.0.0.1:34255")]
+        address: std::net::SocketAddr,
+        /// Miners run side by side, each connected as a worker of its own
+        #[arg(long, default_value_t = 1)]
+        workers: usize,
+        /// Stratum user the workers mine for, as `<user>.<n>`
+        #[arg(long, default_value = "cpuminer")]
+        user: String,
+        /// Shares each worker submits before stopping, mines until interrupted if unset
+        #[arg(long)]
+        shares: Option<usize>,
+    },
+}
+
+#[cfg(feature = "pool")]
+#[derive(Subcommand, Debug)]
+pub enum WalletCommand {
+    /// Shows the tokens held, and with an account what the pool owes it
+    Balance {
+        /// Account (stratum user) to show the balances of, with the credential the operator
+        /// issued it, read from POTATO_ACCOUNT_CREDENTIAL or prompted for
+        #[arg(long)]
+        account: Option<String>,
+    },
+    /// Redeems a token, or without one claims what the pool owes an account
+    Receive {
+        /// `cashuA` token
+        #[arg(required_unless_present = "account")]
+        token: Option<String>,
+        /// Account (stratum user) to claim the payouts and balances of, with the credential the
+        /// operator issued it, read from POTATO_ACCOUNT_CREDENTIAL or prompted for
+        #[arg(long)]
+        account: Option<String>,
+        /// Hex preimage unlocking a token locked to its hash, e.g. of a paid Lightning invoice
+        #[arg(long)]
+        preimage: Option<String>,
+    },
+    /// Checks with the mint whether a token is still unspent, without redeeming it. Fails if
+    /// any of it is spent or reserved
+    Check {
+        /// `cashuA` token
+        token: String,
+    },
+    /// Prints the mnemonic the wallet derives its secrets from, to write down
+    Mnemonic,
+    /// Creates the wallet from a mnemonic, recovering its unspent tokens from the mint. The
+    /// mnemonic is read from POTATO_WALLET_MNEMONIC or prompted for
+    Restore,
+    /// Pays a Lightning invoice with sat tokens
+    Melt {
+        /// bolt11 invoice
+        invoice: String,
+    },
+    /// Melts sat tokens into a payout to a Bitcoin address, sent with the next on-chain batch of
+    /// the mint less its fee
+    MeltOnchain { address: String, amount: u64 },
+    /// Prints a token of the amount, to hand to another wallet
+    Export {
+        amount: u64,
+        #[arg(long, default_value = "sat")]
+        unit: String,
+        /// Locks the token to the preimage of this hex sha256 hash, e.g. the payment hash of a
+        /// Lightning invoice the receiver has to pay
+        #[arg(long)]
+        hash: Option<String>,
+        /// Key that has to sign along with the preimage
+        #[arg(long, requires = "hash")]
+        buyer: Option<secp256k1::PublicKey>,
+        /// Unix time after which this wallet can receive the token back
+        #[arg(long, requires = "hash")]
+        locktime: Option<u64>,
+    },
+}
+
+#[cfg(feature = "pool")]
+#[derive(Subcommand, Debug)]
+pub enum EventsCommand {
+    /// Prints the events recorded in a time range as JSON lines, oldest first
+    Query {
+        /// Start of the range, a unix time or a time ago such as 30m, 2h or 7d
+        #[arg(long)]
+        since: String,
+        /// End of the range, now if unset
+        #[arg(long)]
+        until: Option<String>,
+        /// Comma separated types of the events to print, all if unset
+        #[arg(long = "type")]
+        types: Option<String>,
+        /// Most events printed
+        #[arg(long, default_value_t = 10_000)]
+        limit: u64,
+    },
 }
 
-fn derive_child_public_key(
+#[cfg(feature = "pool")]
+#[derive(Subcommand, Debug)]
+pub enum MintCommand {
+    /// Writes an encrypted backup of the mint keys and database, the pool may keep running
+    Backup {
+        /// File the backup is written to
+        output: String,
+    },
+    /// Restores the mint from an encrypted backup, the pool must be stopped
+    Restore {
+        /// Backup file to restore
+        input: String,
+        /// Replace the mint files if they exist
+        #[arg(long)]
+        force: bool,
+    },
+    /// Reconciles the tokens and balances the mint owes against the matured rewards and the
+    /// funds held by the Lightning node and Bitcoin Core wallet. Prints a JSON report and fails
+    /// on any discrepancy
+    Audit {
+        /// File the report is written to instead of stdout
+        #[arg(long)]
+        output: Option<String>,
+        /// Only audit the books, without asking the Lightning node and Bitcoin Core
+        #[arg(long)]
+        books_only: bool,
+    },
+    /// Migrates the mint database to the latest schema of this release, or reverts it to an
+    /// earlier one before downgrading. The pool must be stopped
+    Migrate {
+        /// Schema version to migrate to
+        #[arg(long)]
+        to: Option<u32>,
+    },
+    /// Issues an account a new credential, revoking the one it had, and prints it. Miners claim
+    /// the account's tokens with it. The pool must be running
+    Credential { account: String },
+}
+
+#[cfg(feature = "pool")]
+pub(crate) fn derive_child_public_key(
     xpub: &ExtendedPubKey,
     path: &str,
 ) -> Result<ExtendedPubKey, bip32::Error> {
@@ -68,11 +360,13 @@ fn derive_child_public_key(
     Ok(child_pub_key)
 }
 
-fn validate_xpub(input: &str) -> Result<ExtendedPubKey, String> {
+#[cfg(feature = "pool")]
+pub(crate) fn validate_xpub(input: &str) -> Result<ExtendedPubKey, String> {
     slip132::FromSlip132::from_slip132_str(input)
         .map_err(|x| format!("Invalid SLIP-132 extended public key: {:?}", x))
 }
 
+#[cfg(feature = "pool")]
 fn prompt_for_coinbase_output() -> io::Result<String> {
     let coinbase_output: ExtendedPubKey;
     loop {
@@ -117,6 +411,7 @@ fn prompt_for_coinbase_output() -> io::Result<String> {
     }
 }
 
+#[cfg(feature = "pool")]
 pub fn create_default_pool_config() -> PoolConfiguration {
     PoolConfiguration {
         listen_address: "0.0.0.0:34254".to_string(),
@@ -125,31 +420,62 @@ pub fn create_default_pool_config() -> PoolConfiguration {
             Secp256k1PublicKey::from_str("9auqWEzQDVyd2oe1JVGFLMLHZtCo2FFqZwtKA5gd9xbuEu7PH72")
                 .unwrap(),
         ),
+        tp_retry: RetryPolicy::default(),
+        template_channels: TemplateChannels::default(),
         authority_public_key: Secp256k1PublicKey::from_str(
             "9auqWEzQDVyd2oe1JVGFLMLHZtCo2FFqZwtKA5gd9xbuEu7PH72",
         )
         .unwrap(),
-        authority_secret_key: "mkDLTBBRxdBv998612qipDYoTK3YUrqLe8uWw7gu3iXbSrn2n"
-            .parse()
-            .unwrap(),
+        authority_secret_key: Some(
+            "mkDLTBBRxdBv998612qipDYoTK3YUrqLe8uWw7gu3iXbSrn2n"
+                .parse()
+                .unwrap(),
+        ),
+        authority_signer: None,
         cert_validity_sec: 3600,
+        cert_renew_before_sec: None,
+        authority_rotation: None,
         coinbase_outputs: vec![CoinbaseOutput::new(
             "P2WPKH".to_string(),
             "032a384861cb109a7b69b550601e4935ee30903be6b281f058a3c65c657938f8f8".to_string(),
         )],
         pool_signature: "potato".to_string(),
+        mint: MintConfig::default(),
+        control_address: default_control_address(),
+        control_token_path: default_control_token_path(),
+        status_address: None,
+        alerts: None,
+        statsd: None,
+        event_history: None,
+        nostr_status: None,
+        grpc: None,
+        bitcoin_rpc: None,
+        memory_budget: None,
+        audit_log: None,
+        tip_lag: None,
+        hooks: vec![],
+        accounts: vec![],
+        snapshot: None,
+        rate_limits: RateLimitsConfig::default(),
+        port_mapping: None,
         #[cfg(feature = "test_only_allow_unencrypted")]
         test_only_listen_address_plain: "0.0.0.0:34250".to_string(),
     }
 }
 
+#[cfg(all(feature = "pool", feature = "proxy"))]
 pub fn create_default_proxy_config(pool_config: &PoolConfiguration) -> ProxyConfig {
     // Parse the pool's listen address
 
     ProxyConfig {
         upstream_address: "127.0.0.1".to_string(),
         upstream_port: 34254,
+        upstream_srv: None,
         upstream_authority_pubkey: pool_config.authority_public_key,
+        upstream_next_authority_pubkey: pool_config
+            .authority_rotation
+            .as_ref()
+            .map(|rotation| rotation.next_public_key),
         downstream_address: "0.0.0.0".to_string(),
         downstream_port: 34255,
         max_supported_version: 2,
@@ -160,6 +486,7 @@ pub fn create_default_proxy_config(pool_config: &PoolConfiguration) -> ProxyConf
             shares_per_minute: 6.0,
             submits_since_last_update: 0,
             timestamp_of_last_update: 0,
+            pinned_workers: vec![],
         },
         upstream_difficulty_config: UpstreamDifficultyConfig {
             channel_diff_update_interval: 60,
@@ -167,14 +494,24 @@ pub fn create_default_proxy_config(pool_config: &PoolConfiguration) -> ProxyConf
             timestamp_of_last_update: 0,
             should_aggregate: false,
         },
+        submission_pipeline: SubmissionPipelineConfig::default(),
+        channels: ChannelCapacities::default(),
+        payout_tokens_path: ProxyConfig::default_payout_tokens_path(),
+        upstream_retry: RetryPolicy::forever(),
+        rate_limits: pool_config.rate_limits.translator.clone(),
     }
 }
 
+/// The translator configuration of `config_path` for a translator mining on the pool of its own
+/// process, whose authority keys and rate limits it takes. Without the file it mines with the
+/// defaults of `create_default_proxy_config`.
+#[cfg(all(feature = "pool", feature = "proxy"))]
 pub fn load_or_create_proxy_config(
     config_path: &str,
     pool_config: &PoolConfiguration,
+    dirs: &Dirs,
 ) -> Result<ProxyConfig, Box<dyn std::error::Error>> {
-    match Config::builder()
+    let mut proxy_config = match Config::builder()
         .add_source(File::new(config_path, FileFormat::Toml))
         .build()
     {
@@ -184,30 +521,144 @@ pub fn load_or_create_proxy_config(
                     "Overriding proxy upstream authority public key from config file with pool's authority key"
                 );
             proxy_config.upstream_authority_pubkey = pool_config.authority_public_key;
-            Ok(proxy_config)
+            proxy_config.upstream_next_authority_pubkey = pool_config
+                .authority_rotation
+                .as_ref()
+                .map(|rotation| rotation.next_public_key);
+            proxy_config.rate_limits = pool_config.rate_limits.translator.clone();
+            proxy_config
         }
         Err(e) => {
             warn!("Failed to load proxy config ({}), using defaults", e);
-            Ok(create_default_proxy_config(pool_config))
+            create_default_proxy_config(pool_config)
         }
-    }
+    };
+    proxy_config.payout_tokens_path = dirs.data_path(&proxy_config.payout_tokens_path);
+    Ok(proxy_config)
+}
+
+/// The translator configuration of `config_path` for a translator mining on the pool of another
+/// process, which the file has to name along with its authority key.
+#[cfg(feature = "proxy")]
+pub fn load_proxy_config(
+    config_path: &str,
+    dirs: &Dirs,
+) -> Result<ProxyConfig, Box<dyn std::error::Error>> {
+    let mut proxy_config: ProxyConfig = Config::builder()
+        .add_source(File::new(config_path, FileFormat::Toml))
+        .build()?
+        .try_deserialize()?;
+    proxy_config.payout_tokens_path = dirs.data_path(&proxy_config.payout_tokens_path);
+    Ok(proxy_config)
 }
 
+#[cfg(feature = "pool")]
 pub fn load_or_create_pool_config(
     config_path: &str,
+    dirs: &Dirs,
 ) -> Result<PoolConfiguration, Box<dyn std::error::Error>> {
-    match Config::builder()
+    let mut pool_config = match Config::builder()
         .add_source(File::new(config_path, FileFormat::Toml))
         .build()
     {
-        Ok(config) => Ok(config.try_deserialize::<PoolConfiguration>()?),
+        Ok(config) => config.try_deserialize::<PoolConfiguration>()?,
         Err(e) => {
             warn!("Failed to load pool config ({}), using defaults", e);
-            Ok(create_default_pool_config())
+            create_default_pool_config()
+        }
+    };
+    resolve_pool_paths(&mut pool_config, dirs);
+    Ok(pool_config)
+}
+
+/// Takes the files the pool writes in the data directory, see `crate::dirs`.
+#[cfg(feature = "pool")]
+fn resolve_pool_paths(config: &mut PoolConfiguration, dirs: &Dirs) {
+    let mint = &mut config.mint;
+    for path in [
+        &mut mint.master_secret_path,
+        &mut mint.db_path,
+        &mut mint.keysets_path,
+        &mut mint.journal_path,
+    ] {
+        *path = dirs.data_path(path);
+    }
+    if let Some(SeedConfig::Mnemonic { path, .. }) = &mut mint.seed {
+        *path = dirs.data_path(path);
+    }
+    if let Some(nostr) = &mut mint.nostr {
+        nostr.secret_key_path = dirs.data_path(&nostr.secret_key_path);
+    }
+    if let Some(history) = &mut config.event_history {
+        history.path = dirs.data_path(&history.path);
+    }
+    if let Some(audit_log) = &mut config.audit_log {
+        audit_log.path = dirs.data_path(&audit_log.path);
+    }
+    if let Some(snapshot) = &mut config.snapshot {
+        snapshot.path = dirs.data_path(&snapshot.path);
+    }
+    config.control_token_path = dirs.data_path(&config.control_token_path);
+    if let Some(grpc) = &mut config.grpc {
+        grpc.token_path = grpc.token_path.as_deref().map(|path| dirs.data_path(path));
+    }
+}
+
+/// Pool configuration the pool is started with, replaced by `reload` with the one of its file
+/// for the restart it asks the supervisor for, see `crate::grpc`.
+#[cfg(feature = "pool")]
+#[derive(Debug, Clone)]
+pub struct ReloadablePoolConfig {
+    path: String,
+    dirs: Dirs,
+    current: Arc<Mutex<PoolConfiguration>>,
+    restart: Arc<Notify>,
+}
+
+#[cfg(feature = "pool")]
+impl ReloadablePoolConfig {
+    pub fn new(path: String, dirs: Dirs, config: PoolConfiguration) -> Self {
+        Self {
+            path,
+            dirs,
+            current: Arc::new(Mutex::new(config)),
+            restart: Arc::new(Notify::new()),
         }
     }
+
+    pub fn current(&self) -> Result<PoolConfiguration, String> {
+        self.current
+            .safe_lock(|config| config.clone())
+            .map_err(|e| e.to_string())
+    }
+
+    /// Notified when the pool has to restart with the configuration reloaded.
+    pub fn restart(&self) -> Arc<Notify> {
+        self.restart.clone()
+    }
+
+    /// Re-reads the configuration file and restarts the pool with it. The coinbase outputs, given
+    /// on the command line, are kept. A file that can't be read leaves the pool running as is.
+    pub fn reload(&self) -> Result<(), String> {
+        let mut config = Config::builder()
+            .add_source(File::new(&self.path, FileFormat::Toml))
+            .build()
+            .and_then(|config| config.try_deserialize::<PoolConfiguration>())
+            .map_err(|e| format!("failed to reload {}: {}", self.path, e))?;
+        resolve_pool_paths(&mut config, &self.dirs);
+        self.current
+            .safe_lock(|current| {
+                config.coinbase_outputs = current.coinbase_outputs.clone();
+                *current = config;
+            })
+            .map_err(|e| e.to_string())?;
+        info!("Reloaded {}, restarting the pool", self.path);
+        self.restart.notify_one();
+        Ok(())
+    }
 }
 
+#[cfg(feature = "pool")]
 pub fn process_coinbase_output(
     coinbase_output: Option<String>,
     derivation_path: String,
@@ -0,0 +1,485 @@
+//! Local control API. Accepts newline delimited JSON requests on a loopback TCP socket and answers
+//! each one with a single JSON line, e.g.
+//! `echo "{\"token\":\"$(cat control_token)\",\"command\":\"rotate_keyset\"}" | nc 127.0.0.1 34260`.
+//! `potato status` asks it for the `status_report` of the running process, see `request`, and
+//! `potato healthcheck` for its `health`.
+//!
+//! Every request carries the token of `control_token_path`, generated on first start and only
+//! readable by its owner, so neither another local user nor a page of a browser posting to the
+//! socket can manage the mint. The connection is closed on the first line that isn't a request
+//! with that token.
+#[cfg(feature = "proxy")]
+use crate::proxy_wallet::proxy_config;
+use crate::{
+    error::{MintError, PoolError, PoolResult},
+    logging, net,
+    pool_mint::mint::Mint,
+    ratelimit::{RateLimitConfig, RateLimiter},
+    status::{events, health::health, server::StatusReport},
+};
+use roles_logic_sv2::utils::Mutex;
+use secp256k1::{PublicKey, XOnlyPublicKey};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{fs, net::SocketAddr, str::FromStr, sync::Arc};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum ControlRequest {
+    /// Keysets, issued signatures, spent proofs, spent proof lookup counters and the fees
+    /// collected per unit and operation.
+    Report,
+    /// What the events published on the event bus add up to, see `status::events`.
+    Status,
+    /// The runtime state of the process as served by the status API `/v1/status`.
+    StatusReport,
+    /// The checks of the readiness of the process as served by the status API `/readyz`.
+    Health,
+    /// Replaces the filter of what is logged by `filter`, in the syntax of `RUST_LOG`, or shows
+    /// it without, see `logging::set_filter`.
+    LogFilter {
+        #[serde(default)]
+        filter: Option<String>,
+    },
+    /// Pins the SV1 workers of the translator matching `worker_pattern` to `difficulty` from their
+    /// next job, or drops that pin without `difficulty`, and shows the workers pinned at runtime,
+    /// see `proxy_config::pin_workers`.
+    #[cfg(feature = "proxy")]
+    PinWorkers {
+        #[serde(default)]
+        worker_pattern: Option<String>,
+        #[serde(default)]
+        difficulty: Option<f64>,
+    },
+    /// Lists every keyset with its lifecycle state.
+    Keysets,
+    /// Generates a pending keyset to be activated later, for `unit` or every unit.
+    GenerateKeyset {
+        #[serde(default)]
+        unit: Option<String>,
+    },
+    ActivateKeyset {
+        id: String,
+    },
+    DeprecateKeyset {
+        id: String,
+    },
+    /// Generates a keyset and activates it in place of the current one, for `unit` or every unit.
+    RotateKeyset {
+        #[serde(default)]
+        unit: Option<String>,
+    },
+    /// Running totals of `account`, or of every account, see `accounts`.
+    Accounts {
+        #[serde(default)]
+        account: Option<String>,
+    },
+    /// Issues `account` a new credential, revoking the one it had, see `credentials`.
+    IssueCredential {
+        account: String,
+    },
+    /// Binds the Nostr key payouts of `account` are sent to, like `SetAccountPubkey`.
+    SetNostrKey {
+        account: String,
+        pubkey: String,
+        #[serde(default)]
+        signature: Option<String>,
+    },
+    /// Cross-checks the shares of every round against what it credited, crediting the shares
+    /// never credited with `repair`, see `reconcile`.
+    Reconcile {
+        #[serde(default)]
+        repair: bool,
+    },
+    /// Binds the key payouts of `account` are locked to. A key already bound is only replaced
+    /// with its `signature` on the new one, see `credentials::verify_rebind`.
+    SetAccountPubkey {
+        account: String,
+        pubkey: String,
+        #[serde(default)]
+        signature: Option<String>,
+    },
+}
+
+/// A request along with the token of its caller.
+#[derive(Debug, Deserialize)]
+struct AuthenticatedRequest {
+    token: String,
+    #[serde(flatten)]
+    request: ControlRequest,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ControlResponse {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl ControlResponse {
+    fn ok(result: Value) -> Self {
+        Self {
+            ok: true,
+            result: Some(result),
+            error: None,
+        }
+    }
+
+    fn err(error: String) -> Self {
+        Self {
+            ok: false,
+            result: None,
+            error: Some(error),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ControlServer {
+    mint: Arc<Mutex<Mint>>,
+    limiter: Arc<RateLimiter>,
+    /// Sent with every request, see the module.
+    token: Arc<str>,
+}
+
+impl ControlServer {
+    pub fn new(mint: Arc<Mutex<Mint>>, rate_limits: &RateLimitConfig, token: String) -> Self {
+        Self {
+            mint,
+            limiter: Arc::new(RateLimiter::new("control", rate_limits)),
+            token: token.into(),
+        }
+    }
+
+    /// Serves the control API on `address` until `cancel_token` is cancelled.
+    pub async fn serve(self, address: &str, cancel_token: CancellationToken) -> PoolResult<()> {
+        let listener = net::listen(address)?;
+        let local_addr = listener.local_addr()?;
+        if !local_addr.ip().is_loopback() {
+            warn!(
+                "Control API listening on non loopback address {}, anyone who can reach it can manage the mint",
+                local_addr
+            );
+        }
+        info!("Control API listening on {}", local_addr);
+        events::publish(events::Event::ListenerBound {
+            listener: events::Listener::Control,
+            address: local_addr.to_string(),
+        });
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => {
+                    let (stream, peer) = accepted?;
+                    let server = self.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = server.handle_connection(stream, peer).await {
+                            debug!("Control connection {} closed: {}", peer, e);
+                        }
+                    });
+                }
+                _ = cancel_token.cancelled() => break,
+            }
+        }
+        Ok(())
+    }
+
+    async fn handle_connection(self, stream: TcpStream, peer: SocketAddr) -> PoolResult<()> {
+        debug!("Control connection from {}", peer);
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        while let Some(line) = lines.next_line().await? {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let (response, close) = match serde_json::from_str::<AuthenticatedRequest>(&line) {
+                Ok(_) if !self.limiter.allow_ip(peer.ip()) => {
+                    (ControlResponse::err("rate limited".to_string()), false)
+                }
+                Ok(AuthenticatedRequest { token, .. }) if !same_token(&token, &self.token) => {
+                    warn!("Control connection {} sent an invalid token", peer);
+                    (ControlResponse::err("invalid token".to_string()), true)
+                }
+                Ok(AuthenticatedRequest { request, .. }) => (self.handle(request), false),
+                Err(e) => (
+                    ControlResponse::err(format!("invalid request: {}", e)),
+                    true,
+                ),
+            };
+            let mut response =
+                serde_json::to_string(&response).map_err(|e| PoolError::Custom(e.to_string()))?;
+            response.push('\n');
+            writer.write_all(response.as_bytes()).await?;
+            if close {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn handle(&self, request: ControlRequest) -> ControlResponse {
+        info!("Control request: {:?}", request);
+        // answered without locking the mint
+        if let ControlRequest::LogFilter { filter } = request {
+            let result = match filter {
+                Some(filter) => logging::set_filter(&filter),
+                None => logging::filter().ok_or_else(|| "logging is not initialized".to_string()),
+            };
+            return match result {
+                Ok(filter) => {
+                    info!("Log filter: {}", filter);
+                    ControlResponse::ok(json!({ "filter": filter }))
+                }
+                Err(e) => ControlResponse::err(e),
+            };
+        }
+        #[cfg(feature = "proxy")]
+        if let ControlRequest::PinWorkers {
+            worker_pattern,
+            difficulty,
+        } = request
+        {
+            let result = match (worker_pattern, difficulty) {
+                (Some(worker_pattern), difficulty) => {
+                    proxy_config::pin_workers(worker_pattern, difficulty)
+                }
+                (None, Some(_)) => Err("difficulty without a worker_pattern".to_string()),
+                (None, None) => Ok(()),
+            };
+            return match result {
+                Ok(()) => ControlResponse::ok(
+                    json!({ "pinned_workers": proxy_config::pinned_at_runtime() }),
+                ),
+                Err(e) => ControlResponse::err(e),
+            };
+        }
+        let result = self
+            .mint
+            .safe_lock(|mint| -> Result<Value, MintError> {
+                match request {
+                    ControlRequest::Report => Ok(json!(mint.report()?)),
+                    ControlRequest::Status => Ok(json!(events::snapshot())),
+                    ControlRequest::StatusReport => {
+                        Ok(json!(StatusReport::new(events::snapshot(), events::now())))
+                    }
+                    ControlRequest::Health => Ok(json!(health(
+                        &events::snapshot(),
+                        Some(mint.database_health())
+                    ))),
+                    ControlRequest::LogFilter { .. } => unreachable!("handled above"),
+                    #[cfg(feature = "proxy")]
+                    ControlRequest::PinWorkers { .. } => unreachable!("handled above"),
+                    ControlRequest::Keysets => Ok(json!(mint.keyset_infos())),
+                    ControlRequest::GenerateKeyset { unit } => {
+                        let ids = units(mint, unit)
+                            .iter()
+                            .map(|unit| mint.generate_keyset(unit))
+                            .collect::<Result<Vec<_>, _>>()?;
+                        Ok(json!({ "ids": ids }))
+                    }
+                    ControlRequest::ActivateKeyset { id } => {
+                        mint.activate_keyset(&id)?;
+                        Ok(json!({ "id": id }))
+                    }
+                    ControlRequest::DeprecateKeyset { id } => {
+                        mint.deprecate_keyset(&id)?;
+                        Ok(json!({ "id": id }))
+                    }
+                    ControlRequest::RotateKeyset { unit } => {
+                        let ids = units(mint, unit)
+                            .iter()
+                            .map(|unit| mint.rotate_keyset(unit))
+                            .collect::<Result<Vec<_>, _>>()?;
+                        Ok(json!({ "ids": ids }))
+                    }
+                    ControlRequest::Accounts { account } => match account {
+                        Some(account) => Ok(json!([mint.account_statement(&account)?])),
+                        None => Ok(json!(mint.account_statements()?)),
+                    },
+                    ControlRequest::IssueCredential { account } => {
+                        let credential = mint.issue_credential(&account)?;
+                        Ok(json!({ "account": account, "credential": credential }))
+                    }
+                    ControlRequest::SetNostrKey {
+                        account,
+                        pubkey,
+                        signature,
+                    } => {
+                        let key = XOnlyPublicKey::from_str(&pubkey)?;
+                        mint.register_nostr_key(&account, &key, signature.as_deref())?;
+                        Ok(json!({ "account": account, "pubkey": pubkey }))
+                    }
+                    ControlRequest::Reconcile { repair } => Ok(json!(mint.reconcile(repair)?)),
+                    ControlRequest::SetAccountPubkey {
+                        account,
+                        pubkey,
+                        signature,
+                    } => {
+                        let key = PublicKey::from_str(&pubkey)
+                            .map_err(|e| MintError::SpendingConditions(e.to_string()))?;
+                        mint.register_pubkey(&account, &key, signature.as_deref())?;
+                        Ok(json!({ "account": account, "pubkey": pubkey }))
+                    }
+                }
+            })
+            .map_err(|e| MintError::PoisonLock(e.to_string()))
+            .and_then(|r| r);
+        match result {
+            Ok(value) => ControlResponse::ok(value),
+            Err(e) => ControlResponse::err(e.to_string()),
+        }
+    }
+}
+
+/// Sends `request` to the control API at `address` with the token of `token_path`, returning the
+/// result it answers with.
+pub async fn request(address: &str, token_path: &str, mut request: Value) -> Result<Value, String> {
+    let token = fs::read_to_string(token_path)
+        .map_err(|e| format!("failed to read the control token {}: {}", token_path, e))?;
+    request["token"] = Value::String(token.trim().to_string());
+    let mut stream = TcpStream::connect(address)
+        .await
+        .map_err(|e| format!("failed to connect to the control API at {}: {}", address, e))?;
+    let mut line = request.to_string();
+    line.push('\n');
+    stream
+        .write_all(line.as_bytes())
+        .await
+        .map_err(|e| e.to_string())?;
+    let mut response = String::new();
+    BufReader::new(stream)
+        .read_line(&mut response)
+        .await
+        .map_err(|e| e.to_string())?;
+    let response: ControlResponse =
+        serde_json::from_str(&response).map_err(|e| format!("invalid response: {}", e))?;
+    match response {
+        ControlResponse {
+            ok: true,
+            result: Some(result),
+            ..
+        } => Ok(result),
+        response => Err(response.error.unwrap_or_else(|| "no result".to_string())),
+    }
+}
+
+/// Whether `given` is `token`, comparing every byte not to tell how much of it was right by the
+/// time taken.
+pub(crate) fn same_token(given: &str, token: &str) -> bool {
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Loads the token at `path`, generating and saving a new one only its owner can read on first
+/// start.
+#[cfg(feature = "pool")]
+pub(crate) fn load_or_create_token(path: &str) -> PoolResult<String> {
+    use std::{io::Write, path::Path};
+    if Path::new(path).exists() {
+        let token = fs::read_to_string(path)?.trim().to_string();
+        if token.is_empty() {
+            return Err(PoolError::Custom(format!("empty token in {}", path)));
+        }
+        return Ok(token);
+    }
+    let token = hex::encode(rand::random::<[u8; 32]>());
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)?.write_all(token.as_bytes())?;
+    info!("Generated new token at {}", path);
+    Ok(token)
+}
+
+/// `unit` if given, every unit of the mint otherwise.
+fn units(mint: &Mint, unit: Option<String>) -> Vec<String> {
+    match unit {
+        Some(unit) => vec![unit],
+        None => mint.units().to_vec(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pool_mint::mint::MintConfig;
+    use tokio::{io::AsyncReadExt, net::TcpListener};
+
+    /// What the server answers to `lines`, until it closes the connection.
+    async fn answers(lines: &str) -> String {
+        let mint = Mint::from_master_secret(&[1; 32], &MintConfig::default()).unwrap();
+        let server = ControlServer::new(
+            Arc::new(Mutex::new(mint)),
+            &RateLimitConfig::default(),
+            "s3cret".to_string(),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, peer) = listener.accept().await.unwrap();
+            let _ = server.handle_connection(stream, peer).await;
+        });
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        stream.write_all(lines.as_bytes()).await.unwrap();
+        stream.shutdown().await.unwrap();
+        let mut answers = String::new();
+        stream.read_to_string(&mut answers).await.unwrap();
+        answers
+    }
+
+    #[tokio::test]
+    async fn asks_every_request_for_the_token() {
+        let answers = answers(concat!(
+            "{\"token\":\"s3cret\",\"command\":\"keysets\"}\n",
+            "{\"token\":\"s3cre\",\"command\":\"rotate_keyset\"}\n",
+            "{\"token\":\"s3cret\",\"command\":\"keysets\"}\n",
+        ))
+        .await;
+        let answers: Vec<ControlResponse> = answers
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(answers.len(), 2);
+        assert!(answers[0].ok);
+        assert_eq!(answers[1].error.as_deref(), Some("invalid token"));
+    }
+
+    #[tokio::test]
+    async fn closes_the_connection_on_a_line_that_is_no_request() {
+        // as posted by a browser to the socket
+        let answers = answers(concat!(
+            "POST / HTTP/1.1\n",
+            "{\"token\":\"s3cret\",\"command\":\"keysets\"}\n",
+        ))
+        .await;
+        assert_eq!(answers.lines().count(), 1);
+        assert!(answers.contains("invalid request"));
+    }
+
+    #[cfg(feature = "pool")]
+    #[test]
+    fn generates_the_token_once() {
+        let dir = std::env::temp_dir().join(format!("potato-control-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("token");
+        let path = path.to_str().unwrap();
+        let token = load_or_create_token(path).unwrap();
+        assert_eq!(token.len(), 64);
+        assert_eq!(load_or_create_token(path).unwrap(), token);
+        assert!(same_token(&token, &token));
+        assert!(!same_token(&token[1..], &token));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,192 @@
+//! Crash bundles, written when the process panics so a bug report carries what led to it. The
+//! panic hook writes a directory `crash-<unix time>-<pid>` in `--crash-dir` holding:
+//!
+//! - `panic.txt`, the panic message, where and in which thread it happened, and the backtrace;
+//! - `logs.txt`, the last `CRASH_LOG_LINES` lines logged, in the `--log-format` of stdout;
+//! - the config files, with the values of keys naming secrets redacted;
+//! - `status.json`, the event bus snapshot: miners and upstreams connected, listeners bound.
+//!
+//! then hands the panic on to the default hook. The process is not exited: a panic in the task of
+//! a connection only ends that connection, and one of a whole run of the pool or the translator
+//! fails it for the supervisor to restart (see `supervisor`).
+use crate::status::events;
+use once_cell::sync::Lazy;
+use roles_logic_sv2::utils::Mutex;
+use std::{
+    backtrace::Backtrace,
+    collections::VecDeque,
+    fmt::Write as _,
+    fs,
+    io::{self, Write},
+    panic::{self, PanicHookInfo},
+    path::{Path, PathBuf},
+    process, thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tracing_subscriber::fmt::MakeWriter;
+
+/// Lines logged last kept for the crash bundle.
+pub const CRASH_LOG_LINES: usize = 500;
+/// Words in the keys of config values redacted from crash bundles, unless the key names a path.
+const SECRET_KEYS: [&str; 6] = [
+    "secret",
+    "password",
+    "passphrase",
+    "api_key",
+    "token",
+    "mnemonic",
+];
+
+static RECENT_LOGS: Lazy<Mutex<VecDeque<String>>> = Lazy::new(|| Mutex::new(VecDeque::new()));
+
+/// Log writer keeping the last `CRASH_LOG_LINES` lines logged for the crash bundle.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RecentLogs;
+
+impl<'a> MakeWriter<'a> for RecentLogs {
+    type Writer = RecentLine;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RecentLine(Vec::new())
+    }
+}
+
+/// One event logged, kept once written out.
+pub struct RecentLine(Vec<u8>);
+
+impl Write for RecentLine {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for RecentLine {
+    fn drop(&mut self) {
+        if self.0.is_empty() {
+            return;
+        }
+        let line = String::from_utf8_lossy(&self.0).trim_end().to_string();
+        let _ = RECENT_LOGS.safe_lock(|logs| {
+            logs.push_back(line);
+            while logs.len() > CRASH_LOG_LINES {
+                logs.pop_front();
+            }
+        });
+    }
+}
+
+/// Writes a crash bundle to `dir` on panic, with the config files at `configs`, before the
+/// default hook reports it.
+pub fn install(dir: PathBuf, configs: Vec<PathBuf>) {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        match write_bundle(&dir, &configs, info) {
+            Ok(bundle) => eprintln!("Crash bundle written to {}", bundle.display()),
+            Err(e) => eprintln!(
+                "Failed to write the crash bundle to {}: {}",
+                dir.display(),
+                e
+            ),
+        }
+        default_hook(info);
+    }));
+}
+
+fn write_bundle(dir: &Path, configs: &[PathBuf], info: &PanicHookInfo) -> io::Result<PathBuf> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let bundle = dir.join(format!("crash-{}-{}", now, process::id()));
+    fs::create_dir_all(&bundle)?;
+
+    let mut panic = String::new();
+    let _ = writeln!(panic, "potato {}", env!("CARGO_PKG_VERSION"));
+    let _ = writeln!(
+        panic,
+        "thread '{}' panicked",
+        thread::current().name().unwrap_or("<unnamed>")
+    );
+    if let Some(location) = info.location() {
+        let _ = writeln!(panic, "at {}", location);
+    }
+    let _ = writeln!(panic, "{}\n", message(info));
+    let _ = writeln!(panic, "{}", Backtrace::force_capture());
+    fs::write(bundle.join("panic.txt"), panic)?;
+
+    let logs = RECENT_LOGS
+        .safe_lock(|logs| logs.iter().cloned().collect::<Vec<_>>())
+        .unwrap_or_default();
+    fs::write(bundle.join("logs.txt"), logs.join("\n"))?;
+
+    for config in configs {
+        let (Some(name), Ok(content)) = (config.file_name(), fs::read_to_string(config)) else {
+            continue;
+        };
+        fs::write(bundle.join(name), redact(&content))?;
+    }
+
+    let status = serde_json::to_string_pretty(&events::snapshot()).map_err(io::Error::other)?;
+    fs::write(bundle.join("status.json"), status)?;
+    Ok(bundle)
+}
+
+fn message(info: &PanicHookInfo) -> String {
+    let payload = info.payload();
+    match (
+        payload.downcast_ref::<&str>(),
+        payload.downcast_ref::<String>(),
+    ) {
+        (Some(message), _) => message.to_string(),
+        (_, Some(message)) => message.clone(),
+        _ => "Box<dyn Any>".to_string(),
+    }
+}
+
+/// `config` with the values of keys naming secrets replaced, commented out examples included.
+fn redact(config: &str) -> String {
+    config
+        .lines()
+        .map(|line| match line.split_once('=') {
+            Some((key, _)) if is_secret(key.trim_start_matches(['#', ' ']).trim()) => {
+                format!("{}= \"<redacted>\"", key)
+            }
+            _ => line.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn is_secret(key: &str) -> bool {
+    let key = key.to_lowercase();
+    !key.ends_with("_path") && SECRET_KEYS.iter().any(|word| key.contains(word))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn redacts_the_secrets_of_the_config() {
+        let config = "authority_public_key = \"9auq\"\n\
+            authority_secret_key = \"mkDL\"\n\
+            master_secret_path = \"mint_master_secret\"\n\
+            [bitcoin_rpc]\n\
+            # password = \"bitcoin\"\n\
+            url = \"http://127.0.0.1:48332\"";
+        assert_eq!(
+            redact(config),
+            "authority_public_key = \"9auq\"\n\
+            authority_secret_key = \"<redacted>\"\n\
+            master_secret_path = \"mint_master_secret\"\n\
+            [bitcoin_rpc]\n\
+            # password = \"<redacted>\"\n\
+            url = \"http://127.0.0.1:48332\""
+        );
+    }
+}
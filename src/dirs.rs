@@ -0,0 +1,117 @@
+//! Where the process keeps its files, rather than the working directory. Configuration files are
+//! read from the config directory, and what the process writes goes to the data directory: the
+//! files of the mint, the event history, the audit log, crash bundles, payout tokens, wallets and
+//! the data of its bitcoind.
+//!
+//! - `--config-dir`, else `$XDG_CONFIG_HOME/potato`, else `~/.config/potato`;
+//! - `--data-dir`, else `$XDG_DATA_HOME/potato`, else `~/.local/share/potato`;
+//! - both default to `%APPDATA%\potato` on Windows.
+//!
+//! Relative paths, of the command line or of the configuration files, are taken in these
+//! directories, absolute ones as they are. A file found in the working directory but not in them,
+//! as left there by earlier versions, is still used from there with a warning to move it, so an
+//! upgrade never starts the mint on a new empty database. `--data-dir .` keeps writing to the
+//! working directory.
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+use tracing::warn;
+
+/// Name of the directories of the process in the base directories.
+const APP_NAME: &str = "potato";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dirs {
+    pub config: PathBuf,
+    pub data: PathBuf,
+}
+
+impl Dirs {
+    /// The directories given on the command line, the default ones otherwise.
+    pub fn new(config: Option<PathBuf>, data: Option<PathBuf>) -> Self {
+        Self {
+            config: config.unwrap_or_else(|| base_dir("XDG_CONFIG_HOME", ".config").join(APP_NAME)),
+            data: data.unwrap_or_else(|| base_dir("XDG_DATA_HOME", ".local/share").join(APP_NAME)),
+        }
+    }
+
+    pub fn create(&self) -> io::Result<()> {
+        fs::create_dir_all(&self.config)?;
+        fs::create_dir_all(&self.data)
+    }
+
+    /// Where the configuration file `path` is.
+    pub fn config_path(&self, path: &str) -> String {
+        resolve(&self.config, path)
+    }
+
+    /// Where the file or directory `path` the process writes is.
+    pub fn data_path(&self, path: &str) -> String {
+        resolve(&self.data, path)
+    }
+}
+
+#[cfg(not(windows))]
+fn base_dir(var: &str, default: &str) -> PathBuf {
+    match env::var_os(var) {
+        // relative ones are to be ignored
+        Some(dir) if Path::new(&dir).is_absolute() => PathBuf::from(dir),
+        _ => env::var_os("HOME")
+            .map(PathBuf::from)
+            .unwrap_or_default()
+            .join(default),
+    }
+}
+
+#[cfg(windows)]
+fn base_dir(_var: &str, _default: &str) -> PathBuf {
+    env::var_os("APPDATA")
+        .map(PathBuf::from)
+        .unwrap_or_default()
+}
+
+/// `path` in `dir` if relative, see the module.
+fn resolve(dir: &Path, path: &str) -> String {
+    if Path::new(path).is_absolute() {
+        return path.to_string();
+    }
+    let resolved = dir.join(path);
+    if !resolved.exists() && Path::new(path).exists() {
+        warn!(
+            "{} found in the working directory and used from there, move it to {}",
+            path,
+            dir.display()
+        );
+        return path.to_string();
+    }
+    resolved.to_string_lossy().into_owned()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn takes_relative_paths_in_the_directories_unless_left_in_the_working_directory() {
+        let root = env::temp_dir().join(format!("potato-dirs-{}", std::process::id()));
+        let dirs = Dirs::new(Some(root.join("config")), Some(root.join("data")));
+        dirs.create().unwrap();
+        assert!(dirs.config.is_dir() && dirs.data.is_dir());
+
+        let db = root.join("data").join("mint.sqlite");
+        assert_eq!(dirs.data_path("mint.sqlite"), db.to_string_lossy());
+        let absolute = root.join("elsewhere.sqlite");
+        let absolute = absolute.to_str().unwrap();
+        assert_eq!(dirs.data_path(absolute), absolute);
+
+        // tests run in the directory of the crate, left there by an earlier version
+        assert_eq!(dirs.config_path("Cargo.toml"), "Cargo.toml");
+        fs::write(root.join("config").join("Cargo.toml"), "").unwrap();
+        assert_eq!(
+            dirs.config_path("Cargo.toml"),
+            root.join("config").join("Cargo.toml").to_string_lossy()
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
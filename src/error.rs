@@ -1,304 +1,363 @@
+//! Errors of the pool, the translator and the mint. They own what they carry, the SV1 and SV2
+//! messages they were raised on summarized rather than borrowed, so they are `'static` and cross
+//! channels, status events and APIs as they are.
 use ext_config::ConfigError;
 use roles_logic_sv2::{
     mining_sv2::{ExtendedExtranonce, NewExtendedMiningJob, SetCustomMiningJob},
     parsers::Mining,
 };
-use std::{convert::From, fmt::Debug, sync::MutexGuard};
-use std::{fmt, sync::PoisonError};
+use std::sync::{MutexGuard, PoisonError};
+#[cfg(feature = "proxy")]
 use sv1_api::server_to_client::{Notify, SetDifficulty};
 
 use stratum_common::bitcoin::util::uint::ParseLengthError;
 
-pub type ProxyResult<'a, T> = core::result::Result<T, Error<'a>>;
-
-#[derive(Debug)]
-pub enum ChannelSendError<'a> {
-    SubmitSharesExtended(
-        async_channel::SendError<roles_logic_sv2::mining_sv2::SubmitSharesExtended<'a>>,
-    ),
-    SetNewPrevHash(async_channel::SendError<roles_logic_sv2::mining_sv2::SetNewPrevHash<'a>>),
-    NewExtendedMiningJob(async_channel::SendError<NewExtendedMiningJob<'a>>),
-    Notify(tokio::sync::broadcast::error::SendError<Notify<'a>>),
-    V1Message(async_channel::SendError<sv1_api::Message>),
+#[cfg(feature = "proxy")]
+use crate::proxy_wallet::downstream_sv1::Sv1Frame;
+use crate::supervisor::Failure;
+
+pub type ProxyResult<T> = core::result::Result<T, Error>;
+
+/// The message a channel send failed on, its payload dropped with the closed channel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChannelSendError {
+    SubmitSharesExtended,
+    SetNewPrevHash,
+    NewExtendedMiningJob,
+    Notify,
+    V1Message,
     General(String),
-    Extranonce(async_channel::SendError<(ExtendedExtranonce, u32)>),
-    SetCustomMiningJob(
-        async_channel::SendError<roles_logic_sv2::mining_sv2::SetCustomMiningJob<'a>>,
-    ),
-    NewTemplate(
-        async_channel::SendError<(
-            roles_logic_sv2::template_distribution_sv2::SetNewPrevHash<'a>,
-            Vec<u8>,
-        )>,
-    ),
+    Extranonce,
+    SetCustomMiningJob,
+    NewTemplate,
+}
+
+/// Owned summary of an SV2 mining error message.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum Sv2MiningError {
+    #[error("OpenMiningChannelError of request {request_id}: {error_code}")]
+    OpenMiningChannel { request_id: u32, error_code: String },
+    #[error("UpdateChannelError of channel {channel_id}: {error_code}")]
+    UpdateChannel { channel_id: u32, error_code: String },
+    #[error("SubmitSharesError of channel {channel_id}, share {sequence_number}: {error_code}")]
+    SubmitShares {
+        channel_id: u32,
+        sequence_number: u32,
+        error_code: String,
+    },
+    #[error("SetCustomMiningJobError of channel {channel_id}, request {request_id}: {error_code}")]
+    SetCustomMiningJob {
+        channel_id: u32,
+        request_id: u32,
+        error_code: String,
+    },
+    /// Any other message, given as an error.
+    #[error("{0}")]
+    Other(String),
+}
+
+impl From<&Mining<'_>> for Sv2MiningError {
+    fn from(message: &Mining<'_>) -> Self {
+        let code = |error_code: Vec<u8>| String::from_utf8_lossy(&error_code).into_owned();
+        match message {
+            Mining::OpenMiningChannelError(m) => Sv2MiningError::OpenMiningChannel {
+                request_id: m.request_id,
+                error_code: code(m.error_code.to_vec()),
+            },
+            Mining::UpdateChannelError(m) => Sv2MiningError::UpdateChannel {
+                channel_id: m.channel_id,
+                error_code: code(m.error_code.to_vec()),
+            },
+            Mining::SubmitSharesError(m) => Sv2MiningError::SubmitShares {
+                channel_id: m.channel_id,
+                sequence_number: m.sequence_number,
+                error_code: code(m.error_code.to_vec()),
+            },
+            Mining::SetCustomMiningJobError(m) => Sv2MiningError::SetCustomMiningJob {
+                channel_id: m.channel_id,
+                request_id: m.request_id,
+                error_code: code(m.error_code.to_vec()),
+            },
+            other => Sv2MiningError::Other(format!("{:?}", other)),
+        }
+    }
 }
 
-#[derive(Debug)]
-pub enum Error<'a> {
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Standard Error: `{0:?}`")]
     VecToSlice32(Vec<u8>),
     /// Errors on bad CLI argument input.
+    #[error("Bad CLI arg input")]
     BadCliArgs,
     /// Errors on bad `serde_json` serialize/deserialize.
-    BadSerdeJson(serde_json::Error),
+    #[error("Bad serde json: `{0:?}`")]
+    BadSerdeJson(#[from] serde_json::Error),
     /// Errors on bad `config` TOML deserialize.
-    BadConfigDeserialize(ConfigError),
+    #[error("Bad `config` TOML deserialize: `{0:?}`")]
+    BadConfigDeserialize(#[from] ConfigError),
     /// Errors from `binary_sv2` crate.
+    #[error("Binary SV2 error: `{0:?}`")]
     BinarySv2(binary_sv2::Error),
     /// Errors on bad noise handshake.
+    #[error("Noise error: `{0:?}")]
     CodecNoise(codec_sv2::noise_sv2::Error),
     /// Errors from `framing_sv2` crate.
+    #[error("Framing SV2 error: `{0:?}`")]
     FramingSv2(framing_sv2::Error),
     /// Errors on bad `TcpStream` connection.
-    Io(std::io::Error),
+    #[error("I/O error: `{0:?}")]
+    Io(#[from] std::io::Error),
     /// Errors due to invalid extranonce from upstream
+    #[error("Invalid Extranonce error: `{0:?}")]
     InvalidExtranonce(String),
     /// Errors on bad `String` to `int` conversion.
-    ParseInt(std::num::ParseIntError),
+    #[error("Bad convert from `String` to `int`: `{0:?}`")]
+    ParseInt(#[from] std::num::ParseIntError),
     /// Errors from `roles_logic_sv2` crate.
+    #[error("Roles SV2 Logic Error: `{0:?}`")]
     RolesSv2Logic(roles_logic_sv2::errors::Error),
+    #[error("Upstream parse incoming error: `{0:?}`")]
     UpstreamIncoming(roles_logic_sv2::errors::Error),
-    /// SV1 protocol library error
-    V1Protocol(sv1_api::error::Error<'a>),
+    /// SV1 protocol library error, as its debug output.
+    #[error("V1 Protocol Error: `{0}`")]
+    V1Protocol(String),
     #[allow(dead_code)]
+    #[error("Subprotocol Mining Error: `{0:?}`")]
     SubprotocolMining(String),
     // Locking Errors
+    #[error("Poison Lock error")]
     PoisonLock,
     // Channel Receiver Error
-    ChannelErrorReceiver(async_channel::RecvError),
-    TokioChannelErrorRecv(tokio::sync::broadcast::error::RecvError),
+    #[error("Channel receive error: `{0:?}`")]
+    ChannelErrorReceiver(#[from] async_channel::RecvError),
+    #[error("Channel receive error: `{0:?}`")]
+    TokioChannelErrorRecv(#[from] tokio::sync::broadcast::error::RecvError),
     // Channel Sender Errors
-    ChannelErrorSender(ChannelSendError<'a>),
+    #[error("Channel send error: `{0:?}`")]
+    ChannelErrorSender(ChannelSendError),
+    #[error("U256 Conversion Error: `{0:?}`")]
     Uint256Conversion(ParseLengthError),
-    SetDifficultyToMessage(SetDifficulty),
-    Infallible(std::convert::Infallible),
+    #[error("Error converting SetDifficulty to Message: `{0}`")]
+    SetDifficultyToMessage(String),
+    #[error("Infallible Error:`{0:?}`")]
+    Infallible(#[from] std::convert::Infallible),
     // used to handle SV2 protocol error messages from pool
     #[allow(clippy::enum_variant_names)]
-    Sv2ProtocolError(Mining<'a>),
+    #[error("Received Sv2 Protocol Error from upstream: `{0}`")]
+    Sv2ProtocolError(Sv2MiningError),
     #[allow(clippy::enum_variant_names)]
+    #[error("Impossible to get target from hashrate: `{0:?}`")]
     TargetError(roles_logic_sv2::errors::Error),
+    #[error("Received an sv1 message that is longer than max len")]
     Sv1MessageTooLong,
-    MiningPoolError(PoolError),
-}
-
-impl fmt::Display for Error<'_> {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    /// The pool can't be reached, retried as configured.
+    #[error("Upstream unreachable: {0}")]
+    Unreachable(String),
+    /// The noise handshake with the pool failed with every authority key trusted.
+    #[error("Handshake with the upstream failed: {0}")]
+    Handshake(String),
+    /// The listener of the miners can't bind its address.
+    #[error("Can't bind {0}")]
+    Bind(String),
+    #[error("Pool error: `{0:?}`")]
+    MiningPoolError(#[from] PoolError),
+}
+
+impl Error {
+    /// Stable code of the error, for monitoring rules and clients to match on rather than its
+    /// message. Codes are never renamed, those of the pool errors are given as is.
+    pub fn code(&self) -> &'static str {
         use Error::*;
         match self {
-            BadCliArgs => write!(f, "Bad CLI arg input"),
-            BadSerdeJson(ref e) => write!(f, "Bad serde json: `{:?}`", e),
-            BadConfigDeserialize(ref e) => write!(f, "Bad `config` TOML deserialize: `{:?}`", e),
-            BinarySv2(ref e) => write!(f, "Binary SV2 error: `{:?}`", e),
-            CodecNoise(ref e) => write!(f, "Noise error: `{:?}", e),
-            FramingSv2(ref e) => write!(f, "Framing SV2 error: `{:?}`", e),
-            InvalidExtranonce(ref e) => write!(f, "Invalid Extranonce error: `{:?}", e),
-            Io(ref e) => write!(f, "I/O error: `{:?}", e),
-            ParseInt(ref e) => write!(f, "Bad convert from `String` to `int`: `{:?}`", e),
-            RolesSv2Logic(ref e) => write!(f, "Roles SV2 Logic Error: `{:?}`", e),
-            V1Protocol(ref e) => write!(f, "V1 Protocol Error: `{:?}`", e),
-            SubprotocolMining(ref e) => write!(f, "Subprotocol Mining Error: `{:?}`", e),
-            UpstreamIncoming(ref e) => write!(f, "Upstream parse incoming error: `{:?}`", e),
-            PoisonLock => write!(f, "Poison Lock error"),
-            ChannelErrorReceiver(ref e) => write!(f, "Channel receive error: `{:?}`", e),
-            TokioChannelErrorRecv(ref e) => write!(f, "Channel receive error: `{:?}`", e),
-            ChannelErrorSender(ref e) => write!(f, "Channel send error: `{:?}`", e),
-            Uint256Conversion(ref e) => write!(f, "U256 Conversion Error: `{:?}`", e),
-            SetDifficultyToMessage(ref e) => {
-                write!(f, "Error converting SetDifficulty to Message: `{:?}`", e)
-            }
-            VecToSlice32(ref e) => write!(f, "Standard Error: `{:?}`", e),
-            Infallible(ref e) => write!(f, "Infallible Error:`{:?}`", e),
-            Sv2ProtocolError(ref e) => {
-                write!(f, "Received Sv2 Protocol Error from upstream: `{:?}`", e)
-            }
-            TargetError(ref e) => {
-                write!(f, "Impossible to get target from hashrate: `{:?}`", e)
-            }
-            Sv1MessageTooLong => {
-                write!(f, "Received an sv1 message that is longer than max len")
-            }
-            MiningPoolError(pool_error) => write!(f, "Pool error: `{:?}`", pool_error),
+            VecToSlice32(_) => "invalid_bytes",
+            BadCliArgs => "bad_cli_args",
+            BadSerdeJson(_) => "bad_json",
+            BadConfigDeserialize(_) => "bad_config",
+            BinarySv2(_) => "binary_sv2",
+            CodecNoise(_) => "noise",
+            FramingSv2(_) => "framing_sv2",
+            Io(_) => "io",
+            InvalidExtranonce(_) => "invalid_extranonce",
+            ParseInt(_) => "parse_int",
+            RolesSv2Logic(_) => "roles_logic",
+            UpstreamIncoming(_) => "upstream_incoming",
+            V1Protocol(_) => "sv1_protocol",
+            SubprotocolMining(_) => "subprotocol_mining",
+            PoisonLock => "poison_lock",
+            ChannelErrorReceiver(_) | TokioChannelErrorRecv(_) => "channel_recv",
+            ChannelErrorSender(_) => "channel_send",
+            Uint256Conversion(_) => "uint256_conversion",
+            SetDifficultyToMessage(_) => "set_difficulty",
+            Infallible(e) => match *e {},
+            Sv2ProtocolError(_) => "sv2_protocol_error",
+            TargetError(_) => "invalid_target",
+            Sv1MessageTooLong => "sv1_message_too_long",
+            Unreachable(_) => "unreachable",
+            Handshake(_) => "handshake",
+            Bind(_) => "bind",
+            MiningPoolError(e) => e.code(),
+        }
+    }
+
+    /// Whether restarting can't get past the error, the configuration or the state of the
+    /// process being wrong, so the whole process shuts down (see `crate::supervisor`).
+    pub fn is_fatal(&self) -> bool {
+        match self {
+            Error::BadCliArgs | Error::BadConfigDeserialize(_) | Error::PoisonLock => true,
+            Error::MiningPoolError(e) => e.is_fatal(),
+            _ => false,
+        }
+    }
+}
+
+impl From<Error> for Failure {
+    fn from(e: Error) -> Self {
+        match e.is_fatal() {
+            true => Failure::fatal(e.code(), e),
+            false => Failure::new(e.code(), e),
         }
     }
 }
 
-impl From<binary_sv2::Error> for Error<'_> {
+impl From<binary_sv2::Error> for Error {
     fn from(e: binary_sv2::Error) -> Self {
         Error::BinarySv2(e)
     }
 }
 
-impl From<codec_sv2::noise_sv2::Error> for Error<'_> {
+impl From<codec_sv2::noise_sv2::Error> for Error {
     fn from(e: codec_sv2::noise_sv2::Error) -> Self {
         Error::CodecNoise(e)
     }
 }
 
-impl From<framing_sv2::Error> for Error<'_> {
+impl From<framing_sv2::Error> for Error {
     fn from(e: framing_sv2::Error) -> Self {
         Error::FramingSv2(e)
     }
 }
 
-impl From<std::io::Error> for Error<'_> {
-    fn from(e: std::io::Error) -> Self {
-        Error::Io(e)
-    }
-}
-
-impl From<std::num::ParseIntError> for Error<'_> {
-    fn from(e: std::num::ParseIntError) -> Self {
-        Error::ParseInt(e)
-    }
-}
-
-impl From<roles_logic_sv2::errors::Error> for Error<'_> {
+impl From<roles_logic_sv2::errors::Error> for Error {
     fn from(e: roles_logic_sv2::errors::Error) -> Self {
         Error::RolesSv2Logic(e)
     }
 }
 
-impl From<serde_json::Error> for Error<'_> {
-    fn from(e: serde_json::Error) -> Self {
-        Error::BadSerdeJson(e)
-    }
-}
-
-impl From<ConfigError> for Error<'_> {
-    fn from(e: ConfigError) -> Self {
-        Error::BadConfigDeserialize(e)
-    }
-}
-
-impl<'a> From<sv1_api::error::Error<'a>> for Error<'a> {
-    fn from(e: sv1_api::error::Error<'a>) -> Self {
-        Error::V1Protocol(e)
-    }
-}
-
-impl From<async_channel::RecvError> for Error<'_> {
-    fn from(e: async_channel::RecvError) -> Self {
-        Error::ChannelErrorReceiver(e)
-    }
-}
-
-impl From<tokio::sync::broadcast::error::RecvError> for Error<'_> {
-    fn from(e: tokio::sync::broadcast::error::RecvError) -> Self {
-        Error::TokioChannelErrorRecv(e)
+#[cfg(feature = "proxy")]
+impl From<sv1_api::error::Error<'_>> for Error {
+    fn from(e: sv1_api::error::Error<'_>) -> Self {
+        Error::V1Protocol(format!("{:?}", e))
     }
 }
 
 //*** LOCK ERRORS ***
-impl<T> From<PoisonError<T>> for Error<'_> {
+impl<T> From<PoisonError<T>> for Error {
     fn from(_e: PoisonError<T>) -> Self {
         Error::PoisonLock
     }
 }
 
 // *** CHANNEL SENDER ERRORS ***
-impl<'a> From<async_channel::SendError<roles_logic_sv2::mining_sv2::SubmitSharesExtended<'a>>>
-    for Error<'a>
+impl From<async_channel::SendError<roles_logic_sv2::mining_sv2::SubmitSharesExtended<'_>>>
+    for Error
 {
     fn from(
-        e: async_channel::SendError<roles_logic_sv2::mining_sv2::SubmitSharesExtended<'a>>,
+        _e: async_channel::SendError<roles_logic_sv2::mining_sv2::SubmitSharesExtended<'_>>,
     ) -> Self {
-        Error::ChannelErrorSender(ChannelSendError::SubmitSharesExtended(e))
+        Error::ChannelErrorSender(ChannelSendError::SubmitSharesExtended)
     }
 }
 
-impl<'a> From<async_channel::SendError<roles_logic_sv2::mining_sv2::SetNewPrevHash<'a>>>
-    for Error<'a>
-{
-    fn from(e: async_channel::SendError<roles_logic_sv2::mining_sv2::SetNewPrevHash<'a>>) -> Self {
-        Error::ChannelErrorSender(ChannelSendError::SetNewPrevHash(e))
+impl From<async_channel::SendError<roles_logic_sv2::mining_sv2::SetNewPrevHash<'_>>> for Error {
+    fn from(_e: async_channel::SendError<roles_logic_sv2::mining_sv2::SetNewPrevHash<'_>>) -> Self {
+        Error::ChannelErrorSender(ChannelSendError::SetNewPrevHash)
     }
 }
 
-impl<'a> From<tokio::sync::broadcast::error::SendError<Notify<'a>>> for Error<'a> {
-    fn from(e: tokio::sync::broadcast::error::SendError<Notify<'a>>) -> Self {
-        Error::ChannelErrorSender(ChannelSendError::Notify(e))
+#[cfg(feature = "proxy")]
+impl From<tokio::sync::broadcast::error::SendError<Notify<'_>>> for Error {
+    fn from(_e: tokio::sync::broadcast::error::SendError<Notify<'_>>) -> Self {
+        Error::ChannelErrorSender(ChannelSendError::Notify)
     }
 }
 
-impl From<async_channel::SendError<sv1_api::Message>> for Error<'_> {
-    fn from(e: async_channel::SendError<sv1_api::Message>) -> Self {
-        Error::ChannelErrorSender(ChannelSendError::V1Message(e))
+#[cfg(feature = "proxy")]
+impl From<async_channel::SendError<Sv1Frame>> for Error {
+    fn from(_e: async_channel::SendError<Sv1Frame>) -> Self {
+        Error::ChannelErrorSender(ChannelSendError::V1Message)
     }
 }
 
-impl From<async_channel::SendError<(ExtendedExtranonce, u32)>> for Error<'_> {
-    fn from(e: async_channel::SendError<(ExtendedExtranonce, u32)>) -> Self {
-        Error::ChannelErrorSender(ChannelSendError::Extranonce(e))
+impl From<async_channel::SendError<(ExtendedExtranonce, u32)>> for Error {
+    fn from(_e: async_channel::SendError<(ExtendedExtranonce, u32)>) -> Self {
+        Error::ChannelErrorSender(ChannelSendError::Extranonce)
     }
 }
 
-impl<'a> From<async_channel::SendError<NewExtendedMiningJob<'a>>> for Error<'a> {
-    fn from(e: async_channel::SendError<NewExtendedMiningJob<'a>>) -> Self {
-        Error::ChannelErrorSender(ChannelSendError::NewExtendedMiningJob(e))
+impl From<async_channel::SendError<NewExtendedMiningJob<'_>>> for Error {
+    fn from(_e: async_channel::SendError<NewExtendedMiningJob<'_>>) -> Self {
+        Error::ChannelErrorSender(ChannelSendError::NewExtendedMiningJob)
     }
 }
 
-impl<'a> From<async_channel::SendError<SetCustomMiningJob<'a>>> for Error<'a> {
-    fn from(e: async_channel::SendError<SetCustomMiningJob<'a>>) -> Self {
-        Error::ChannelErrorSender(ChannelSendError::SetCustomMiningJob(e))
+impl From<async_channel::SendError<SetCustomMiningJob<'_>>> for Error {
+    fn from(_e: async_channel::SendError<SetCustomMiningJob<'_>>) -> Self {
+        Error::ChannelErrorSender(ChannelSendError::SetCustomMiningJob)
     }
 }
 
-impl<'a>
+impl
     From<
         async_channel::SendError<(
-            roles_logic_sv2::template_distribution_sv2::SetNewPrevHash<'a>,
+            roles_logic_sv2::template_distribution_sv2::SetNewPrevHash<'_>,
             Vec<u8>,
         )>,
-    > for Error<'a>
+    > for Error
 {
     fn from(
-        e: async_channel::SendError<(
-            roles_logic_sv2::template_distribution_sv2::SetNewPrevHash<'a>,
+        _e: async_channel::SendError<(
+            roles_logic_sv2::template_distribution_sv2::SetNewPrevHash<'_>,
             Vec<u8>,
         )>,
     ) -> Self {
-        Error::ChannelErrorSender(ChannelSendError::NewTemplate(e))
+        Error::ChannelErrorSender(ChannelSendError::NewTemplate)
     }
 }
 
-impl From<Vec<u8>> for Error<'_> {
+impl From<Vec<u8>> for Error {
     fn from(e: Vec<u8>) -> Self {
         Error::VecToSlice32(e)
     }
 }
 
-impl From<ParseLengthError> for Error<'_> {
+impl From<ParseLengthError> for Error {
     fn from(e: ParseLengthError) -> Self {
         Error::Uint256Conversion(e)
     }
 }
 
-impl From<SetDifficulty> for Error<'_> {
+#[cfg(feature = "proxy")]
+impl From<SetDifficulty> for Error {
     fn from(e: SetDifficulty) -> Self {
-        Error::SetDifficultyToMessage(e)
+        Error::SetDifficultyToMessage(format!("{:?}", e))
     }
 }
 
-impl From<std::convert::Infallible> for Error<'_> {
-    fn from(e: std::convert::Infallible) -> Self {
-        Error::Infallible(e)
+impl From<Mining<'_>> for Error {
+    fn from(e: Mining<'_>) -> Self {
+        Error::Sv2ProtocolError((&e).into())
     }
 }
 
-impl<'a> From<Mining<'a>> for Error<'a> {
-    fn from(e: Mining<'a>) -> Self {
-        Error::Sv2ProtocolError(e)
-    }
-}
-
-impl From<async_channel::SendError<()>> for Error<'_> {
+impl From<async_channel::SendError<()>> for Error {
     fn from(e: async_channel::SendError<()>) -> Self {
         Error::ChannelErrorSender(ChannelSendError::General(e.to_string()))
     }
 }
 
 impl From<async_channel::SendError<roles_logic_sv2::template_distribution_sv2::NewTemplate<'_>>>
-    for Error<'_>
+    for Error
 {
     fn from(
         e: async_channel::SendError<roles_logic_sv2::template_distribution_sv2::NewTemplate<'_>>,
@@ -307,9 +366,8 @@ impl From<async_channel::SendError<roles_logic_sv2::template_distribution_sv2::N
     }
 }
 
-impl
-    From<async_channel::SendError<roles_logic_sv2::template_distribution_sv2::SetNewPrevHash<'_>>>
-    for Error<'_>
+impl From<async_channel::SendError<roles_logic_sv2::template_distribution_sv2::SetNewPrevHash<'_>>>
+    for Error
 {
     fn from(
         e: async_channel::SendError<roles_logic_sv2::template_distribution_sv2::SetNewPrevHash<'_>>,
@@ -318,64 +376,88 @@ impl
     }
 }
 
-#[derive(std::fmt::Debug)]
+#[derive(Debug, thiserror::Error)]
 pub enum PoolError {
-    Io(std::io::Error),
-    ChannelSend(Box<dyn std::marker::Send + Debug>),
-    ChannelRecv(async_channel::RecvError),
+    #[error("I/O error: `{0:?}")]
+    Io(#[from] std::io::Error),
+    /// The type of the message a channel send failed on.
+    #[error("Channel send failed: `{0}`")]
+    ChannelSend(&'static str),
+    #[error("Channel recv failed: `{0:?}`")]
+    ChannelRecv(#[from] async_channel::RecvError),
+    #[error("Binary SV2 error: `{0:?}`")]
     BinarySv2(binary_sv2::Error),
+    #[error("Codec SV2 error: `{0:?}")]
     Codec(codec_sv2::Error),
+    #[error("Noise SV2 error: `{0:?}")]
     Noise(noise_sv2::Error),
+    #[error("Roles Logic SV2 error: `{0:?}`")]
     RolesLogic(roles_logic_sv2::Error),
+    #[error("Framing SV2 error: `{0:?}`")]
     Framing(codec_sv2::framing_sv2::Error),
+    #[error("Poison lock: {0:?}")]
     PoisonLock(String),
+    #[error("Component shutdown: {0:?}")]
     ComponentShutdown(String),
+    #[error("Custom SV2 error: `{0:?}`")]
     Custom(String),
-    Sv2ProtocolError((u32, Mining<'static>)),
-}
-
-impl From<PoolError> for Error<'static> {
-    fn from(val: PoolError) -> Self {
-        Error::MiningPoolError(val)
-    }
-}
-
-impl std::fmt::Display for PoolError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    /// The error message sent to the downstream `.0`.
+    #[error("Received Sv2 Protocol Error from upstream: `{0:?}`")]
+    Sv2ProtocolError((u32, Sv2MiningError)),
+    #[error("Mint error: `{0}`")]
+    Mint(#[from] MintError),
+    /// A listener can't bind its address.
+    #[error("Can't bind {0}")]
+    Bind(String),
+    /// The template provider can't be reached, retried as configured.
+    #[error("Template provider unreachable: {0}")]
+    Unreachable(String),
+}
+
+impl PoolError {
+    /// Stable code of the error, see `Error::code`. Those of the mint errors are given as is.
+    pub fn code(&self) -> &'static str {
         use PoolError::*;
         match self {
-            Io(ref e) => write!(f, "I/O error: `{:?}", e),
-            ChannelSend(ref e) => write!(f, "Channel send failed: `{:?}`", e),
-            ChannelRecv(ref e) => write!(f, "Channel recv failed: `{:?}`", e),
-            BinarySv2(ref e) => write!(f, "Binary SV2 error: `{:?}`", e),
-            Codec(ref e) => write!(f, "Codec SV2 error: `{:?}", e),
-            Framing(ref e) => write!(f, "Framing SV2 error: `{:?}`", e),
-            Noise(ref e) => write!(f, "Noise SV2 error: `{:?}", e),
-            RolesLogic(ref e) => write!(f, "Roles Logic SV2 error: `{:?}`", e),
-            PoisonLock(ref e) => write!(f, "Poison lock: {:?}", e),
-            ComponentShutdown(ref e) => write!(f, "Component shutdown: {:?}", e),
-            Custom(ref e) => write!(f, "Custom SV2 error: `{:?}`", e),
-            Sv2ProtocolError(ref e) => {
-                write!(f, "Received Sv2 Protocol Error from upstream: `{:?}`", e)
-            }
+            Io(_) => "io",
+            ChannelSend(_) => "channel_send",
+            ChannelRecv(_) => "channel_recv",
+            BinarySv2(_) => "binary_sv2",
+            Codec(_) => "codec_sv2",
+            Noise(_) => "noise",
+            RolesLogic(_) => "roles_logic",
+            Framing(_) => "framing_sv2",
+            PoisonLock(_) => "poison_lock",
+            ComponentShutdown(_) => "component_shutdown",
+            Custom(_) => "custom",
+            Sv2ProtocolError(_) => "sv2_protocol_error",
+            Mint(e) => e.code(),
+            Bind(_) => "bind",
+            Unreachable(_) => "unreachable",
         }
     }
-}
-
-pub type PoolResult<T> = Result<T, PoolError>;
 
-impl From<std::io::Error> for PoolError {
-    fn from(e: std::io::Error) -> PoolError {
-        PoolError::Io(e)
+    /// Whether restarting can't get past the error, see `Error::is_fatal`.
+    pub fn is_fatal(&self) -> bool {
+        match self {
+            PoolError::PoisonLock(_) => true,
+            PoolError::Mint(e) => e.is_fatal(),
+            _ => false,
+        }
     }
 }
 
-impl From<async_channel::RecvError> for PoolError {
-    fn from(e: async_channel::RecvError) -> PoolError {
-        PoolError::ChannelRecv(e)
+impl From<PoolError> for Failure {
+    fn from(e: PoolError) -> Self {
+        match e.is_fatal() {
+            true => Failure::fatal(e.code(), e),
+            false => Failure::new(e.code(), e),
+        }
     }
 }
 
+pub type PoolResult<T> = Result<T, PoolError>;
+
 impl From<binary_sv2::Error> for PoolError {
     fn from(e: binary_sv2::Error) -> PoolError {
         PoolError::BinarySv2(e)
@@ -400,9 +482,9 @@ impl From<roles_logic_sv2::Error> for PoolError {
     }
 }
 
-impl<T: 'static + std::marker::Send + Debug> From<async_channel::SendError<T>> for PoolError {
-    fn from(e: async_channel::SendError<T>) -> PoolError {
-        PoolError::ChannelSend(Box::new(e))
+impl<T> From<async_channel::SendError<T>> for PoolError {
+    fn from(_e: async_channel::SendError<T>) -> PoolError {
+        PoolError::ChannelSend(std::any::type_name::<T>())
     }
 }
 
@@ -423,8 +505,332 @@ impl<T> From<PoisonError<MutexGuard<'_, T>>> for PoolError {
     }
 }
 
-impl From<(u32, Mining<'static>)> for PoolError {
-    fn from(e: (u32, Mining<'static>)) -> Self {
-        PoolError::Sv2ProtocolError(e)
+impl From<(u32, Mining<'_>)> for PoolError {
+    fn from((downstream_id, message): (u32, Mining<'_>)) -> Self {
+        PoolError::Sv2ProtocolError((downstream_id, (&message).into()))
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum MintError {
+    #[error("I/O error: `{0:?}`")]
+    Io(#[from] std::io::Error),
+    #[error("Secp256k1 error: `{0:?}`")]
+    Secp256k1(#[from] secp256k1::Error),
+    #[error("Hex decoding error: `{0:?}`")]
+    Hex(#[from] hex::FromHexError),
+    /// No point found for a message, practically impossible.
+    #[error("No curve point found for message")]
+    HashToCurve,
+    #[error("Invalid mint master secret in {0}")]
+    InvalidMasterSecret(String),
+    /// A seed that can't be read, or that doesn't derive the master secret already in use.
+    #[error("Invalid mint seed: {0}")]
+    InvalidSeed(String),
+    /// Configured keyset amounts that aren't a set of powers of two, see `mint::amounts`.
+    #[error("Invalid keyset amounts: {0}")]
+    InvalidAmounts(String),
+    #[error("Unknown keyset `{0}`")]
+    UnknownKeyset(String),
+    /// The keyset exists but is not in a state allowing the operation.
+    #[error("Keyset `{0}` is not usable for this")]
+    InactiveKeyset(String),
+    #[error("Unsupported amount {0}")]
+    UnsupportedAmount(u64),
+    #[error("Insufficient balance: requested {requested}, available {available}")]
+    InsufficientBalance { requested: u64, available: u64 },
+    #[error("Invalid proof")]
+    InvalidProof,
+    #[error("Proof already spent")]
+    ProofAlreadySpent,
+    /// The proof is reserved by a melt in progress.
+    #[error("Proof is pending")]
+    ProofPending,
+    #[error("Duplicate inputs provided")]
+    DuplicateInputs,
+    #[error("Blinded message of output already signed")]
+    OutputAlreadySigned,
+    #[error("Transaction is not balanced: inputs {inputs}, outputs and fees {outputs}")]
+    UnbalancedTransaction { inputs: u64, outputs: u64 },
+    #[error("Unsupported unit `{0}`")]
+    UnsupportedUnit(String),
+    /// Inputs or outputs of more than one unit, or of different units where they must match.
+    #[error("Inputs and outputs of different units")]
+    MixedUnits,
+    #[error("Unsupported payment method `{0}`")]
+    UnsupportedMethod(String),
+    #[error("Unknown quote `{0}`")]
+    UnknownQuote(String),
+    #[error("Quote `{0}` is not paid")]
+    QuoteNotPaid(String),
+    #[error("Tokens already issued for quote `{0}`")]
+    QuoteAlreadyIssued(String),
+    #[error("Quote `{0}` is expired")]
+    QuoteExpired(String),
+    #[error("Quote `{0}` is pending")]
+    QuotePending(String),
+    #[error("Lightning payment failed: {0}")]
+    Lightning(String),
+    /// An on-chain melt to an address or of an amount the mint doesn't pay out, see
+    /// `mint::onchain`.
+    #[error("On-chain melt refused: {0}")]
+    Onchain(String),
+    /// A request to another mint failed, see `mint::client`.
+    #[error("Mint request failed: {0}")]
+    MintRequest(String),
+    /// A direct message no relay took, see `mint::nostr`.
+    #[error("Nostr delivery failed: {0}")]
+    Nostr(String),
+    /// A wallet file that can't be read or doesn't belong to the mint, see `mint::wallet`.
+    #[error("Wallet error: {0}")]
+    Wallet(String),
+    /// A block solution whose coinbase transaction can't be decoded.
+    #[error("Invalid coinbase transaction: {0}")]
+    InvalidCoinbase(String),
+    #[error("Bitcoin Core RPC error: {0}")]
+    BitcoinRpc(String),
+    #[error("Mint storage error: `{0}`")]
+    Storage(String),
+    /// An error of the CDK mint without a counterpart here, see `mint::cdk_mint`.
+    #[error("CDK mint error: {0}")]
+    Cdk(String),
+    /// A backup that can't be decrypted, or that doesn't match the keys it carries.
+    #[error("Mint backup error: {0}")]
+    Backup(String),
+    /// A serialized token that can't be read.
+    #[error("Invalid token: {0}")]
+    InvalidToken(String),
+    /// The witness of a proof doesn't meet the NUT-10 spending conditions of its secret.
+    #[error("Spending conditions not met: {0}")]
+    SpendingConditions(String),
+    /// A request issuing out of an account without the credential of the account, see
+    /// `mint::credentials`.
+    #[error("Account `{0}` not authorized: missing or wrong credential")]
+    Unauthorized(String),
+    /// The account already has a public key, only replaced with a signature of that key.
+    #[error("Account `{0}` already has a public key, replacing it takes its signature")]
+    PubkeyAlreadyRegistered(String),
+    /// Too many requests of a client address or account, see `crate::ratelimit`.
+    #[error("Rate limit exceeded by {0}")]
+    RateLimited(String),
+    /// Sat issuance halted as the reserves fall short of the configured ratio, see
+    /// `mint::reserves`.
+    #[error(
+        "Sat issuance halted: reserve ratio of {ratio_ppk} ppk below the required \
+         {min_ratio_ppk} ppk"
+    )]
+    InsufficientReserves { ratio_ppk: u64, min_ratio_ppk: u64 },
+    #[cfg(feature = "mint")]
+    #[error("Mint database error: `{0:?}`")]
+    Database(#[from] rusqlite::Error),
+    #[error("Poison lock: {0:?}")]
+    PoisonLock(String),
+}
+
+impl MintError {
+    /// Stable code of the error, see `Error::code`. The mint API answers it along with the
+    /// NUT-00 code, which most errors don't have.
+    pub fn code(&self) -> &'static str {
+        use MintError::*;
+        match self {
+            Io(_) => "io",
+            Secp256k1(_) => "secp256k1",
+            Hex(_) => "hex",
+            HashToCurve => "hash_to_curve",
+            InvalidMasterSecret(_) => "invalid_master_secret",
+            InvalidSeed(_) => "invalid_seed",
+            InvalidAmounts(_) => "invalid_amounts",
+            UnknownKeyset(_) => "unknown_keyset",
+            InactiveKeyset(_) => "inactive_keyset",
+            UnsupportedAmount(_) => "unsupported_amount",
+            InsufficientBalance { .. } => "insufficient_balance",
+            InvalidProof => "invalid_proof",
+            ProofAlreadySpent => "proof_already_spent",
+            ProofPending => "proof_pending",
+            DuplicateInputs => "duplicate_inputs",
+            OutputAlreadySigned => "output_already_signed",
+            UnbalancedTransaction { .. } => "unbalanced_transaction",
+            UnsupportedUnit(_) => "unsupported_unit",
+            MixedUnits => "mixed_units",
+            UnsupportedMethod(_) => "unsupported_method",
+            UnknownQuote(_) => "unknown_quote",
+            QuoteNotPaid(_) => "quote_not_paid",
+            QuoteAlreadyIssued(_) => "quote_already_issued",
+            QuoteExpired(_) => "quote_expired",
+            QuotePending(_) => "quote_pending",
+            Lightning(_) => "lightning",
+            Onchain(_) => "onchain",
+            MintRequest(_) => "mint_request",
+            Nostr(_) => "nostr",
+            Wallet(_) => "wallet",
+            InvalidCoinbase(_) => "invalid_coinbase",
+            BitcoinRpc(_) => "bitcoin_rpc",
+            Storage(_) => "storage",
+            Cdk(_) => "cdk",
+            Backup(_) => "backup",
+            InvalidToken(_) => "invalid_token",
+            SpendingConditions(_) => "spending_conditions",
+            Unauthorized(_) => "unauthorized",
+            PubkeyAlreadyRegistered(_) => "pubkey_already_registered",
+            RateLimited(_) => "rate_limited",
+            InsufficientReserves { .. } => "insufficient_reserves",
+            #[cfg(feature = "mint")]
+            Database(_) => "database",
+            PoisonLock(_) => "poison_lock",
+        }
+    }
+
+    /// Whether restarting can't get past the error, the keys of the mint being wrong or its
+    /// state poisoned, see `Error::is_fatal`.
+    pub fn is_fatal(&self) -> bool {
+        matches!(
+            self,
+            MintError::InvalidMasterSecret(_)
+                | MintError::InvalidSeed(_)
+                | MintError::PoisonLock(_)
+        )
+    }
+}
+
+pub type MintResult<T> = Result<T, MintError>;
+
+impl<T> From<PoisonError<MutexGuard<'_, T>>> for MintError {
+    fn from(e: PoisonError<MutexGuard<T>>) -> MintError {
+        MintError::PoisonLock(e.to_string())
+    }
+}
+
+/// Why `crate::run` failed, by class. Each class exits the process with a code of its own, for
+/// supervisors and scripts to tell a misconfiguration, which only an operator fixes, from a
+/// failure restarting later may get past:
+///
+/// | code | class      | failure                                                        |
+/// |------|------------|----------------------------------------------------------------|
+/// | 0    |            | none, shut down on a signal                                    |
+/// | 1    | `Other`    | any other, e.g. a maintenance command that failed              |
+/// | 2    |            | bad command line arguments, exited with by `clap` itself       |
+/// | 69   | `Node`     | bitcoind, the template provider or the pool can't be reached   |
+/// | 75   | `Bind`     | a listener can't bind its address, or take it over             |
+/// | 76   | `Protocol` | a peer broke the SV1 or SV2 protocol                           |
+/// | 78   | `Config`   | the arguments, the configuration files or the keys are wrong   |
+///
+/// The codes are those of `sysexits.h`, and are never changed.
+#[derive(Debug, thiserror::Error)]
+pub enum AppError {
+    #[error("Configuration error: {0}")]
+    Config(String),
+    #[error("Bind error: {0}")]
+    Bind(String),
+    #[error("Node unavailable: {0}")]
+    Node(String),
+    #[error("Protocol error: {0}")]
+    Protocol(String),
+    #[error("{0}")]
+    Other(String),
+}
+
+impl AppError {
+    /// The code the process exits with, see `AppError`.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            AppError::Config(_) => 78,
+            AppError::Bind(_) => 75,
+            AppError::Node(_) => 69,
+            AppError::Protocol(_) => 76,
+            AppError::Other(_) => 1,
+        }
+    }
+
+    pub fn config(e: impl ToString) -> Self {
+        AppError::Config(e.to_string())
+    }
+}
+
+/// The class of the failure the pool or the translator was given up on, by its code.
+impl From<Failure> for AppError {
+    fn from(failure: Failure) -> Self {
+        let reason = format!("{} ({})", failure.reason, failure.code);
+        match failure.code {
+            "bad_cli_args"
+            | "bad_config"
+            | "invalid_master_secret"
+            | "invalid_seed"
+            | "invalid_amounts"
+            | "handshake" => AppError::Config(reason),
+            "bind" => AppError::Bind(reason),
+            "unreachable" | "bitcoin_rpc" => AppError::Node(reason),
+            "binary_sv2"
+            | "codec_sv2"
+            | "framing_sv2"
+            | "noise"
+            | "roles_logic"
+            | "upstream_incoming"
+            | "invalid_extranonce"
+            | "sv1_protocol"
+            | "sv1_message_too_long"
+            | "sv2_protocol_error"
+            | "subprotocol_mining" => AppError::Protocol(reason),
+            _ => AppError::Other(reason),
+        }
+    }
+}
+
+impl From<Box<dyn std::error::Error>> for AppError {
+    fn from(e: Box<dyn std::error::Error>) -> Self {
+        AppError::Other(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use roles_logic_sv2::mining_sv2::SubmitSharesError;
+
+    fn owned<E: std::error::Error + Send + 'static>(_e: &E) {}
+
+    #[test]
+    fn summarizes_the_messages_errors_are_raised_on() {
+        let message = Mining::SubmitSharesError(SubmitSharesError {
+            channel_id: 3,
+            sequence_number: 7,
+            error_code: "invalid-job-id"
+                .to_string()
+                .into_bytes()
+                .try_into()
+                .unwrap(),
+        });
+        let e = Error::from(message);
+        owned(&e);
+        assert_eq!(e.code(), "sv2_protocol_error");
+        assert!(e
+            .to_string()
+            .contains("SubmitSharesError of channel 3, share 7: invalid-job-id"));
+
+        #[cfg(feature = "proxy")]
+        {
+            let e = Error::from(sv1_api::error::Error::InvalidSubmission);
+            assert_eq!(e.to_string(), "V1 Protocol Error: `InvalidSubmission`");
+        }
+
+        let e = Error::from(PoolError::from(async_channel::SendError(5u32)));
+        owned(&e);
+        assert_eq!(e.code(), "channel_send");
+    }
+
+    #[test]
+    fn exits_with_the_code_of_the_class_of_failure() {
+        let exit_code = |e: Error| AppError::from(Failure::from(e)).exit_code();
+        assert_eq!(exit_code(Error::BadCliArgs), 78);
+        assert_eq!(
+            exit_code(PoolError::Bind("0.0.0.0:34254".to_string()).into()),
+            75
+        );
+        assert_eq!(exit_code(Error::Bind("0.0.0.0:34255".to_string())), 75);
+        assert_eq!(exit_code(Error::Unreachable("pool".to_string())), 69);
+        assert_eq!(exit_code(Error::Handshake("pool".to_string())), 78);
+        assert_eq!(exit_code(Error::Sv1MessageTooLong), 76);
+        assert_eq!(exit_code(Error::PoisonLock), 1);
+        let e = MintError::InvalidSeed("not a BIP39 mnemonic".to_string());
+        assert_eq!(exit_code(PoolError::Mint(e).into()), 78);
     }
 }
@@ -0,0 +1,323 @@
+//! gRPC control plane API, for orchestration systems managing fleets of potato instances (see
+//! `proto/control.proto`): the status of the pool, reloading its configuration, draining it of
+//! miners, kicking one, triggering payouts and rotating the mint keysets.
+//!
+//! Unlike the local control API (see `control`) it is meant to be reached over the network, so
+//! it can also authenticate callers with TLS, and refuses to start without authentication.
+//! Callers send the token in `token_path`, created on first start, as
+//! `authorization: Bearer <token>`, or present a client certificate signed by
+//! `tls.client_ca_path`, or both when both are configured.
+use crate::{
+    configuration::ReloadablePoolConfig,
+    control::{load_or_create_token, same_token},
+    error::{MintError, PoolError, PoolResult},
+    net,
+    pool_mint::{
+        mining_pool::{KickTarget, Pool},
+        mint::Mint,
+    },
+    status::{
+        events::{self, Event, Listener},
+        server::StatusReport,
+    },
+};
+use proto::{
+    control_server::{Control, ControlServer},
+    kick_request::Target,
+    DrainReply, DrainRequest, KickReply, KickRequest, ReloadConfigReply, ReloadConfigRequest,
+    RotateKeysetReply, RotateKeysetRequest, StatusReply, StatusRequest, TriggerPayoutsReply,
+    TriggerPayoutsRequest,
+};
+use roles_logic_sv2::utils::Mutex;
+use serde::Deserialize;
+use std::{fs, sync::Arc};
+use tokio::sync::Notify;
+use tokio_stream::wrappers::TcpListenerStream;
+use tokio_util::sync::CancellationToken;
+use tonic::{
+    metadata::{MetadataMap, MetadataValue},
+    transport::{Certificate, Identity, Server, ServerTlsConfig},
+    Code, Request, Response, Status,
+};
+use tracing::{info, warn};
+
+pub mod proto {
+    tonic::include_proto!("potato.control.v1");
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GrpcConfig {
+    pub address: String,
+    /// File holding the bearer token of callers, generated on first start. No token is asked
+    /// for if unset.
+    #[serde(default)]
+    pub token_path: Option<String>,
+    /// Serves over TLS. Served in plain text if unset.
+    #[serde(default)]
+    pub tls: Option<GrpcTlsConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GrpcTlsConfig {
+    /// PEM certificate chain and key of the server.
+    pub cert_path: String,
+    pub key_path: String,
+    /// PEM certificate of the CA client certificates have to be signed by. No client
+    /// certificate is asked for if unset.
+    #[serde(default)]
+    pub client_ca_path: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct GrpcServer {
+    mint: Arc<Mutex<Mint>>,
+    pool: Arc<Mutex<Pool>>,
+    /// Notified to pay out now, none if payouts are disabled.
+    payouts: Option<Arc<Notify>>,
+    reload: ReloadablePoolConfig,
+}
+
+impl GrpcServer {
+    pub fn new(
+        mint: Arc<Mutex<Mint>>,
+        pool: Arc<Mutex<Pool>>,
+        payouts: Option<Arc<Notify>>,
+        reload: ReloadablePoolConfig,
+    ) -> Self {
+        Self {
+            mint,
+            pool,
+            payouts,
+            reload,
+        }
+    }
+
+    /// Serves the gRPC API as configured by `config` until `cancel_token` is cancelled.
+    // interceptors answer with a `Status`, however large
+    #[allow(clippy::result_large_err)]
+    pub async fn serve(
+        self,
+        config: &GrpcConfig,
+        cancel_token: CancellationToken,
+    ) -> PoolResult<()> {
+        let token = config
+            .token_path
+            .as_deref()
+            .map(load_or_create_token)
+            .transpose()?;
+        let client_auth = config
+            .tls
+            .as_ref()
+            .is_some_and(|tls| tls.client_ca_path.is_some());
+        if token.is_none() && !client_auth {
+            return Err(PoolError::Custom(
+                "the gRPC API needs a token_path or a tls.client_ca_path to authenticate callers"
+                    .to_string(),
+            ));
+        }
+        let mut server = Server::builder();
+        if let Some(tls) = &config.tls {
+            server = server
+                .tls_config(tls_config(tls)?)
+                .map_err(|e| PoolError::Custom(format!("invalid gRPC TLS config: {}", e)))?;
+        }
+
+        let listener = net::listen(&config.address)?;
+        let local_addr = listener.local_addr()?;
+        if config.tls.is_none() && !local_addr.ip().is_loopback() {
+            warn!(
+                "gRPC API on non loopback address {} without TLS, its token travels in the clear",
+                local_addr
+            );
+        }
+        info!("gRPC API listening on {}", local_addr);
+        events::publish(Event::ListenerBound {
+            listener: Listener::Grpc,
+            address: local_addr.to_string(),
+        });
+        let service =
+            ControlServer::with_interceptor(self, move |request: Request<()>| match &token {
+                Some(token) => authorize(token, request.metadata())
+                    .map(|_| request)
+                    .map_err(Status::unauthenticated),
+                None => Ok(request),
+            });
+        server
+            .add_service(service)
+            .serve_with_incoming_shutdown(TcpListenerStream::new(listener), async move {
+                cancel_token.cancelled().await
+            })
+            .await
+            .map_err(|e| PoolError::Custom(e.to_string()))
+    }
+}
+
+#[tonic::async_trait]
+impl Control for GrpcServer {
+    async fn status(&self, _: Request<StatusRequest>) -> Result<Response<StatusReply>, Status> {
+        let draining = self.pool.safe_lock(|p| p.is_draining()).map_err(internal)?;
+        let snapshot = events::snapshot();
+        let blocks_found = snapshot.blocks_found;
+        let report = StatusReport::new(snapshot, events::now());
+        Ok(Response::new(StatusReply {
+            version: report.version,
+            uptime_secs: report.uptime_secs,
+            node_ready: report.node_ready,
+            tip: report.tip.unwrap_or_default(),
+            miners: report
+                .miners
+                .get(&Listener::Pool)
+                .copied()
+                .unwrap_or_default(),
+            workers: report.workers.len() as u64,
+            hashrate: report.hashrate,
+            shares_accepted: report.shares_accepted,
+            shares_rejected: report.shares_rejected,
+            blocks_found,
+            draining,
+        }))
+    }
+
+    async fn reload_config(
+        &self,
+        _: Request<ReloadConfigRequest>,
+    ) -> Result<Response<ReloadConfigReply>, Status> {
+        info!("gRPC: reloading the configuration");
+        self.reload.reload().map_err(Status::failed_precondition)?;
+        Ok(Response::new(ReloadConfigReply {}))
+    }
+
+    async fn drain(&self, request: Request<DrainRequest>) -> Result<Response<DrainReply>, Status> {
+        let drain = request.into_inner().drain;
+        let miners = self
+            .pool
+            .safe_lock(|p| {
+                p.set_draining(drain);
+                p.connections()
+            })
+            .map_err(internal)?;
+        match drain {
+            true => info!("gRPC: draining, {} miners still connected", miners),
+            false => info!("gRPC: accepting miners again"),
+        }
+        Ok(Response::new(DrainReply {
+            miners: miners as u64,
+        }))
+    }
+
+    async fn kick(&self, request: Request<KickRequest>) -> Result<Response<KickReply>, Status> {
+        let target = match request.into_inner().target {
+            Some(Target::ConnectionId(id)) => KickTarget::Connection(id),
+            Some(Target::Account(account)) => KickTarget::Account(account),
+            None => return Err(Status::invalid_argument("no connection or account to kick")),
+        };
+        let connection_ids = self
+            .pool
+            .safe_lock(|p| p.kick(&target))
+            .map_err(internal)?
+            .map_err(|e| coded(Code::Internal, e.code(), e))?;
+        if connection_ids.is_empty() {
+            return Err(Status::not_found(format!("no connection of {:?}", target)));
+        }
+        info!("gRPC: kicked connections {:?}", connection_ids);
+        Ok(Response::new(KickReply { connection_ids }))
+    }
+
+    async fn trigger_payouts(
+        &self,
+        _: Request<TriggerPayoutsRequest>,
+    ) -> Result<Response<TriggerPayoutsReply>, Status> {
+        let payouts = self
+            .payouts
+            .as_ref()
+            .ok_or_else(|| Status::failed_precondition("payouts are disabled"))?;
+        info!("gRPC: triggering payouts");
+        payouts.notify_one();
+        Ok(Response::new(TriggerPayoutsReply {}))
+    }
+
+    async fn rotate_keyset(
+        &self,
+        request: Request<RotateKeysetRequest>,
+    ) -> Result<Response<RotateKeysetReply>, Status> {
+        let unit = request.into_inner().unit;
+        let ids = self
+            .mint
+            .safe_lock(|mint| -> Result<Vec<String>, MintError> {
+                let units = match unit.is_empty() {
+                    true => mint.units().to_vec(),
+                    false => vec![unit],
+                };
+                units.iter().map(|unit| mint.rotate_keyset(unit)).collect()
+            })
+            .map_err(internal)?
+            .map_err(|e| coded(Code::InvalidArgument, e.code(), e))?;
+        info!("gRPC: rotated to keysets {:?}", ids);
+        Ok(Response::new(RotateKeysetReply { ids }))
+    }
+}
+
+/// The lock of the pool or the mint poisoned.
+fn internal(e: impl std::fmt::Display) -> Status {
+    coded(Code::Internal, "poison_lock", e)
+}
+
+/// `Status` telling `e`, with the stable code of the error (see `crate::error`) in the
+/// `error-code` metadata.
+fn coded(code: Code, error_code: &'static str, e: impl std::fmt::Display) -> Status {
+    let mut metadata = MetadataMap::new();
+    metadata.insert("error-code", MetadataValue::from_static(error_code));
+    Status::with_metadata(code, e.to_string(), metadata)
+}
+
+/// Checks `metadata` carries `token` as its bearer token, telling why not otherwise.
+fn authorize(token: &str, metadata: &MetadataMap) -> Result<(), &'static str> {
+    let given = metadata
+        .get("authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or("no bearer token")?;
+    match same_token(given, token) {
+        true => Ok(()),
+        false => Err("invalid bearer token"),
+    }
+}
+
+fn tls_config(config: &GrpcTlsConfig) -> PoolResult<ServerTlsConfig> {
+    let identity = Identity::from_pem(
+        fs::read_to_string(&config.cert_path)?,
+        fs::read_to_string(&config.key_path)?,
+    );
+    let mut tls = ServerTlsConfig::new().identity(identity);
+    if let Some(client_ca_path) = &config.client_ca_path {
+        tls = tls.client_ca_root(Certificate::from_pem(fs::read_to_string(client_ca_path)?));
+    }
+    Ok(tls)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn asks_for_the_bearer_token() {
+        let mut metadata = MetadataMap::new();
+        assert_eq!(authorize("s3cret", &metadata), Err("no bearer token"));
+        metadata.insert("authorization", "Bearer s3cre".parse().unwrap());
+        assert_eq!(authorize("s3cret", &metadata), Err("invalid bearer token"));
+        metadata.insert("authorization", "s3cret".parse().unwrap());
+        assert_eq!(authorize("s3cret", &metadata), Err("no bearer token"));
+        metadata.insert("authorization", "Bearer s3cret".parse().unwrap());
+        assert!(authorize("s3cret", &metadata).is_ok());
+
+        let dir = std::env::temp_dir().join(format!("potato-grpc-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("token");
+        let path = path.to_str().unwrap();
+        let _ = fs::remove_file(path);
+        let token = load_or_create_token(path).unwrap();
+        assert_eq!(token.len(), 64);
+        assert_eq!(load_or_create_token(path).unwrap(), token);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
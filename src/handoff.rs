@@ -0,0 +1,374 @@
+//! Zero-downtime restarts: a new potato process takes the listening sockets of the pool and the
+//! translator over from the running one, so no miner connecting meanwhile is refused or reset.
+//!
+//! - The running process serves `--handoff-socket`, a Unix socket in the data directory.
+//! - `potato --takeover` connects to it and is sent the listeners as file descriptors, along
+//!   with which is which. Its pool and translator accept on them instead of binding their
+//!   addresses, see `bind`.
+//! - Once both accept, it tells the old process, which stops accepting and shuts down as on
+//!   SIGTERM (see `crate::shutdown`). The connections it had are closed once drained and their
+//!   miners reconnect, to the new process.
+//!
+//! The sockets stay open all along, connections coming in while neither process accepts wait in
+//! their queue. SO_REUSEPORT is not used for this, as a listener closing resets the connections
+//! still in its queue. Off unix, the listeners are bound as usual and can't be handed over.
+use crate::{net, status::events::Listener};
+use once_cell::sync::Lazy;
+use roles_logic_sv2::utils::Mutex;
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    io,
+    net::{TcpListener, ToSocketAddrs},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+#[cfg(unix)]
+use {
+    serde::{Deserialize, Serialize},
+    std::{
+        io::{BufRead, BufReader, Write},
+        mem,
+        os::{
+            fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+            unix::{fs::PermissionsExt, net::UnixStream},
+        },
+        path::{Path, PathBuf},
+        ptr,
+        time::Duration,
+    },
+};
+
+/// How long the new process gets to accept on every listener it took over.
+#[cfg(unix)]
+pub const TAKEOVER_TIMEOUT: Duration = Duration::from_secs(120);
+/// Most listeners handed over at once.
+#[cfg(unix)]
+const MAX_LISTENERS: usize = 8;
+/// What the new process answers once it accepts on every listener.
+#[cfg(unix)]
+const READY: &str = "ready";
+
+static LISTENERS: Lazy<Mutex<Listeners>> = Lazy::new(|| Mutex::new(Listeners::default()));
+static HANDED_OVER: Lazy<CancellationToken> = Lazy::new(CancellationToken::new);
+
+#[derive(Debug, Default)]
+struct Listeners {
+    /// Bound by the pool or the translator, to hand over.
+    bound: BTreeMap<Listener, TcpListener>,
+    /// Taken over from the previous process, until bound.
+    inherited: BTreeMap<Listener, TcpListener>,
+}
+
+/// The message sending the listeners, their file descriptors attached in the same order.
+#[cfg(unix)]
+#[derive(Debug, Serialize, Deserialize)]
+struct Handover {
+    listeners: Vec<Listener>,
+}
+
+/// A non-blocking socket for `listener` to accept on at `address`: the one taken over from the
+/// previous process if any, else the one bound before, so a restart of the pool or the
+/// translator keeps its queue, else a new one.
+pub fn bind(listener: Listener, address: &str) -> io::Result<TcpListener> {
+    let addresses: Vec<_> = address.to_socket_addrs()?.collect();
+    let listens_on = |socket: &TcpListener| {
+        socket
+            .local_addr()
+            .is_ok_and(|local| addresses.contains(&local))
+    };
+    LISTENERS
+        .safe_lock(|listeners| {
+            let inherited = listeners.inherited.remove(&listener);
+            let socket = match inherited {
+                Some(socket) if listens_on(&socket) => {
+                    info!("{:?} accepting on the socket taken over", listener);
+                    socket
+                }
+                inherited => {
+                    if inherited.is_some() {
+                        warn!("{:?} no longer listens on the socket taken over", listener);
+                    }
+                    match listeners.bound.remove(&listener) {
+                        Some(socket) if listens_on(&socket) => socket,
+                        _ => net::bind(address)?,
+                    }
+                }
+            };
+            socket.set_nonblocking(true)?;
+            let accepting = socket.try_clone()?;
+            listeners.bound.insert(listener, socket);
+            Ok(accepting)
+        })
+        .map_err(|e| io::Error::other(e.to_string()))?
+}
+
+/// Whether the listeners were handed over to a new process.
+pub fn is_handed_over() -> bool {
+    HANDED_OVER.is_cancelled()
+}
+
+/// Waits for `accept` unless the listeners are handed over first, the new process accepting
+/// from then on.
+pub async fn accepting<F: Future>(accept: F) -> Option<F::Output> {
+    tokio::select! {
+        accepted = accept => Some(accepted),
+        _ = HANDED_OVER.cancelled() => None,
+    }
+}
+
+/// Listeners taken over but not bound yet.
+#[cfg(unix)]
+fn pending() -> usize {
+    LISTENERS
+        .safe_lock(|listeners| listeners.inherited.len())
+        .unwrap_or_default()
+}
+
+/// The connection to the previous process, to let it go once the listeners taken over accept.
+#[cfg(unix)]
+#[derive(Debug)]
+pub struct Takeover(UnixStream);
+
+#[cfg(unix)]
+impl Takeover {
+    fn release(self) -> io::Result<()> {
+        (&self.0).write_all(format!("{}\n", READY).as_bytes())
+    }
+}
+
+/// Takes the listeners over from the process serving `path`, see the module.
+#[cfg(unix)]
+pub async fn take_over(path: &Path) -> io::Result<Takeover> {
+    let path = path.to_path_buf();
+    let (stream, handover, fds) = tokio::task::spawn_blocking(move || {
+        let stream = UnixStream::connect(path)?;
+        let (payload, fds) = receive_with_fds(&stream)?;
+        let handover: Handover = serde_json::from_slice(&payload)?;
+        io::Result::Ok((stream, handover, fds))
+    })
+    .await??;
+    if handover.listeners.len() != fds.len() {
+        let e = format!(
+            "{} listeners but {} sockets",
+            handover.listeners.len(),
+            fds.len()
+        );
+        return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+    }
+    LISTENERS
+        .safe_lock(|listeners| {
+            for (listener, fd) in handover.listeners.into_iter().zip(fds) {
+                let socket = TcpListener::from(fd);
+                if let Ok(address) = socket.local_addr() {
+                    info!("Took the {:?} listener on {} over", listener, address);
+                }
+                listeners.inherited.insert(listener, socket);
+            }
+        })
+        .map_err(|e| io::Error::other(e.to_string()))?;
+    Ok(Takeover(stream))
+}
+
+/// Serves the listeners of the process at `path` to the next one until `cancel_token` is
+/// cancelled, first letting the previous process go through `takeover` once the listeners it
+/// handed over accept. Cancels `cancel_token` once they are handed over.
+#[cfg(unix)]
+pub async fn serve(path: PathBuf, takeover: Option<Takeover>, cancel_token: CancellationToken) {
+    if let Some(takeover) = takeover {
+        let bound = tokio::time::timeout(TAKEOVER_TIMEOUT, async {
+            while pending() > 0 {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        });
+        tokio::select! {
+            bound = bound => match bound {
+                Ok(()) => match takeover.release() {
+                    Ok(()) => info!("Handoff: accepting, the previous process stops"),
+                    Err(e) => warn!("Handoff: can't tell the previous process to stop: {}", e),
+                },
+                Err(_) => warn!(
+                    "Handoff: {} listeners taken over still not accepting, the previous process \
+                     keeps accepting too",
+                    pending()
+                ),
+            },
+            _ = cancel_token.cancelled() => return,
+        }
+    }
+    // a stale one, or the previous process', which no longer serves it
+    let _ = std::fs::remove_file(&path);
+    let server = match tokio::net::UnixListener::bind(&path) {
+        Ok(server) => server,
+        Err(e) => {
+            warn!("Handoff: can't listen on {}: {}", path.display(), e);
+            return;
+        }
+    };
+    if let Err(e) = std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)) {
+        warn!("Handoff: can't restrict {}: {}", path.display(), e);
+    }
+    info!("Handoff: listening on {}", path.display());
+    loop {
+        let stream = tokio::select! {
+            accepted = server.accept() => accepted.and_then(|(stream, _)| stream.into_std()),
+            _ = cancel_token.cancelled() => return,
+        };
+        let handed_over = match stream {
+            Ok(stream) => tokio::task::spawn_blocking(move || hand_over(stream))
+                .await
+                .unwrap_or_else(|e| Err(io::Error::other(e))),
+            Err(e) => Err(e),
+        };
+        match handed_over {
+            Ok(()) => {
+                info!("Handoff: listeners handed over, shutting down");
+                HANDED_OVER.cancel();
+                cancel_token.cancel();
+                return;
+            }
+            Err(e) => warn!("Handoff: takeover failed, still accepting: {}", e),
+        }
+    }
+}
+
+/// Sends the listeners bound over `stream`, then waits for the new process to accept on them.
+#[cfg(unix)]
+fn hand_over(stream: UnixStream) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    let bound = LISTENERS
+        .safe_lock(|listeners| {
+            listeners
+                .bound
+                .iter()
+                .map(|(listener, socket)| Ok((*listener, socket.try_clone()?)))
+                .collect::<io::Result<Vec<_>>>()
+        })
+        .map_err(|e| io::Error::other(e.to_string()))??;
+    let (listeners, sockets): (Vec<_>, Vec<_>) = bound.into_iter().unzip();
+    let fds: Vec<_> = sockets.iter().map(AsRawFd::as_raw_fd).collect();
+    send_with_fds(&stream, &serde_json::to_vec(&Handover { listeners })?, &fds)?;
+
+    stream.set_read_timeout(Some(TAKEOVER_TIMEOUT))?;
+    let mut answer = String::new();
+    BufReader::new(&stream).read_line(&mut answer)?;
+    match answer.trim() == READY {
+        true => Ok(()),
+        false => Err(io::Error::other("the new process quit before accepting")),
+    }
+}
+
+/// Sends `payload` over `stream`, `fds` attached as SCM_RIGHTS.
+#[cfg(unix)]
+fn send_with_fds(stream: &UnixStream, payload: &[u8], fds: &[RawFd]) -> io::Result<()> {
+    let fds_len = mem::size_of_val(fds) as u32;
+    // u64 words, for the header to be aligned
+    let mut control = vec![0u64; unsafe { libc::CMSG_SPACE(fds_len) } as usize / 8 + 1];
+    let mut iov = libc::iovec {
+        iov_base: payload.as_ptr() as *mut libc::c_void,
+        iov_len: payload.len(),
+    };
+    // SAFETY: every field is an integer or a pointer, zeroes being none and empty
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    if !fds.is_empty() {
+        msg.msg_control = control.as_mut_ptr().cast();
+        msg.msg_controllen = unsafe { libc::CMSG_SPACE(fds_len) } as _;
+        // SAFETY: the control buffer holds a header and `fds`, as sized by CMSG_SPACE
+        unsafe {
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            (*cmsg).cmsg_level = libc::SOL_SOCKET;
+            (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(fds_len) as _;
+            let data = libc::CMSG_DATA(cmsg).cast::<RawFd>();
+            ptr::copy_nonoverlapping(fds.as_ptr(), data, fds.len());
+        }
+    }
+    // SAFETY: `msg` points to `payload` and `control`, both alive until it returns
+    let sent = unsafe { libc::sendmsg(stream.as_raw_fd(), &msg, 0) };
+    match usize::try_from(sent) {
+        Ok(sent) if sent == payload.len() => Ok(()),
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::WriteZero,
+            "handover cut short",
+        )),
+        Err(_) => Err(io::Error::last_os_error()),
+    }
+}
+
+/// Receives a message sent by `send_with_fds` over `stream`, and the file descriptors attached.
+#[cfg(unix)]
+fn receive_with_fds(stream: &UnixStream) -> io::Result<(Vec<u8>, Vec<OwnedFd>)> {
+    let mut payload = vec![0u8; 4096];
+    let fds_len = (MAX_LISTENERS * mem::size_of::<RawFd>()) as u32;
+    let mut control = vec![0u64; unsafe { libc::CMSG_SPACE(fds_len) } as usize / 8 + 1];
+    let mut iov = libc::iovec {
+        iov_base: payload.as_mut_ptr().cast(),
+        iov_len: payload.len(),
+    };
+    // SAFETY: as in `send_with_fds`
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = mem::size_of_val(control.as_slice()) as _;
+    // not inherited by the processes this one spawns
+    #[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+    let flags = libc::MSG_CMSG_CLOEXEC;
+    #[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd")))]
+    let flags = 0;
+    // SAFETY: `msg` points to `payload` and `control`, both alive until it returns
+    let received = unsafe { libc::recvmsg(stream.as_raw_fd(), &mut msg, flags) };
+    let received = usize::try_from(received).map_err(|_| io::Error::last_os_error())?;
+
+    let mut fds = vec![];
+    // SAFETY: the headers are those the kernel wrote in `control`, walked as it laid them out
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                let data = libc::CMSG_DATA(cmsg).cast::<RawFd>();
+                let len = (*cmsg).cmsg_len as usize - libc::CMSG_LEN(0) as usize;
+                for i in 0..len / mem::size_of::<RawFd>() {
+                    fds.push(OwnedFd::from_raw_fd(ptr::read_unaligned(data.add(i))));
+                }
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+    if msg.msg_flags & libc::MSG_CTRUNC != 0 {
+        let e = format!("more than {} listeners", MAX_LISTENERS);
+        return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+    }
+    payload.truncate(received);
+    Ok((payload, fds))
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use super::*;
+    use std::{io::Read, net::TcpStream};
+
+    #[test]
+    fn hands_a_listener_over_to_another_socket() {
+        let (old, new) = UnixStream::pair().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        send_with_fds(&old, b"pool", &[listener.as_raw_fd()]).unwrap();
+        // what connects meanwhile waits in the queue of the socket
+        let mut miner = TcpStream::connect(address).unwrap();
+        drop(listener);
+
+        let (payload, fds) = receive_with_fds(&new).unwrap();
+        assert_eq!(payload, b"pool");
+        assert_eq!(fds.len(), 1);
+        let taken_over = TcpListener::from(fds.into_iter().next().unwrap());
+        assert_eq!(taken_over.local_addr().unwrap(), address);
+        let (mut accepted, _) = taken_over.accept().unwrap();
+        accepted.write_all(b"job").unwrap();
+        let mut job = [0; 3];
+        miner.read_exact(&mut job).unwrap();
+        assert_eq!(&job, b"job");
+    }
+}
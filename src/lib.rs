@@ -0,0 +1,352 @@
+//! potato, a Stratum V2 pool paying its miners in ecash, and the translator SV1 miners mine on it
+//! through: [`PoolSv2`], [`TranslatorSv2`] and [`BitcoinNode`], each behind the cargo feature of
+//! its role, and `run` for the binary.
+#[cfg(all(feature = "pool", feature = "proxy"))]
+use configuration::load_or_create_proxy_config;
+#[cfg(all(feature = "proxy", not(feature = "pool")))]
+use configuration::load_proxy_config;
+#[cfg(feature = "pool")]
+use {
+    configuration::{load_or_create_pool_config, process_coinbase_output, ReloadablePoolConfig},
+    pool_mint::mining_pool::CoinbaseOutput,
+    status::events::Listener,
+    tracing::warn,
+};
+#[cfg(any(feature = "pool", feature = "proxy"))]
+use {
+    dirs::Dirs,
+    error::AppError,
+    std::time::Duration,
+    stratum_common::bitcoin,
+    supervisor::{Failure, Supervisor},
+    tokio_util::sync::CancellationToken,
+    tracing::{debug, error, info},
+};
+
+/// Runs a Bitcoin Core node, see [`BitcoinNode`].
+#[cfg(feature = "node")]
+pub mod bitcoin_node;
+#[cfg(feature = "pool")]
+mod commands;
+/// Command line arguments and the configuration files of the pool and the translator.
+#[cfg(any(feature = "pool", feature = "proxy"))]
+pub mod configuration;
+/// Local control API of the running pool, see [`control::ControlServer`].
+#[cfg(feature = "mint")]
+pub mod control;
+/// Crash bundles written when the process panics.
+pub mod crash;
+/// Where the process keeps its configuration and data files, see [`dirs::Dirs`].
+pub mod dirs;
+/// Errors of the pool, the translator and the mint, with their stable codes.
+pub mod error;
+/// Authenticated gRPC control plane API of the pool.
+#[cfg(feature = "pool")]
+pub mod grpc;
+/// Hands the listeners over to a new process for restarts without downtime.
+pub mod handoff;
+/// Logs to stdout and rotated files, as text or JSON.
+pub mod logging;
+/// Addresses of the listeners and the upstreams: IPv6, hostnames and dual-stack listeners.
+pub mod net;
+/// Export of traces over OTLP.
+#[cfg(feature = "otlp")]
+pub mod otlp;
+/// The pool, its template provider connection and the mint, see [`PoolSv2`].
+#[cfg(feature = "wallet")]
+pub mod pool_mint;
+/// Port mappings of the listeners on the router of a home network, over UPnP or NAT-PMP.
+#[cfg(feature = "pool")]
+pub mod port_mapping;
+/// The translator and its SV1 miners, see [`TranslatorSv2`].
+#[cfg(feature = "proxy")]
+pub mod proxy_wallet;
+/// Token bucket rate limits of the listeners, per client address and per identity.
+pub mod ratelimit;
+/// Retries and circuit breakers of the dependencies of the process: bitcoind, the template
+/// provider, the pool and Lightning.
+pub mod retry;
+/// Maps of per-connection state split in shards, see [`sharded::ShardedMap`].
+pub mod sharded;
+/// Graceful shutdown on SIGTERM and SIGINT.
+pub mod shutdown;
+/// Time and randomness of the protocol handling, simulated with the `simulation` feature.
+pub mod sim;
+/// The state kept in memory, snapshot to disk and restored on start.
+pub mod snapshot;
+/// Starts the translator once the pool of the process serves miners.
+#[cfg(all(feature = "pool", feature = "proxy"))]
+pub mod startup;
+/// The event bus and what is built on it: status APIs, metrics, alerts and history.
+pub mod status;
+/// Restarts the pool and the translator when they fail, see [`supervisor::Supervisor`].
+pub mod supervisor;
+/// Runs the node, the pool and the translator in process for tests, see [`testing::Harness`].
+#[cfg(all(feature = "pool", feature = "proxy", feature = "node"))]
+pub mod testing;
+#[cfg(feature = "tui")]
+mod tui;
+
+#[cfg(feature = "node")]
+pub use bitcoin_node::BitcoinNode;
+#[cfg(any(feature = "pool", feature = "proxy"))]
+pub use configuration::Args;
+#[cfg(feature = "pool")]
+pub use pool_mint::PoolSv2;
+#[cfg(feature = "proxy")]
+pub use proxy_wallet::TranslatorSv2;
+
+/// Runs what `args` ask for: a maintenance command, or the pool and the translator the binary was
+/// built with until either fails too often in a row. A translator built without the pool mines on
+/// the pool its configuration file names.
+#[cfg(any(feature = "pool", feature = "proxy"))]
+pub async fn run(args: Args) -> Result<(), AppError> {
+    // Ensure mainnet is not allowed
+    if args.network == bitcoin::Network::Bitcoin {
+        error!("Mainnet is not supported");
+        return Err(AppError::config("Mainnet is not supported"));
+    }
+
+    // Initialize tracing subscriber, logging at the level of the verbose flag
+    let level = if args.verbose { "debug" } else { "info" };
+    #[cfg(feature = "otlp")]
+    let (otlp_layer, otlp_exporter) = otlp::setup(&args.otlp).unzip();
+    #[cfg(not(feature = "otlp"))]
+    let otlp_layer = None::<tracing_subscriber::layer::Identity>;
+    // held until exit, so the log file gets every line
+    let _log_guard = logging::init(
+        level,
+        args.log_format,
+        &args.log_file,
+        otlp_layer,
+        &args.console,
+    )
+    .map_err(|e| AppError::Config(format!("logging: {}", e)))?;
+    let dirs = Dirs::new(args.config_dir.clone(), args.data_dir.clone());
+    dirs.create()
+        .map_err(|e| AppError::Config(format!("directories: {}", e)))?;
+    #[cfg(feature = "pool")]
+    let pool_mint_config_path = dirs.config_path(&args.pool_mint_config_path);
+    #[cfg(feature = "proxy")]
+    let proxy_config_path = dirs.config_path(&args.proxy_config_path);
+    crash::install(
+        dirs.data_path(&args.crash_dir.to_string_lossy()).into(),
+        vec![
+            #[cfg(feature = "pool")]
+            pool_mint_config_path.clone().into(),
+            #[cfg(feature = "proxy")]
+            proxy_config_path.clone().into(),
+        ],
+    );
+
+    debug!("DEBUG {args:?}");
+    status::events::publish(status::events::Event::Started {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        network: args.network.to_string(),
+    });
+
+    // // Initialize Bitcoin Core
+    // info!(
+    //     "Starting Bitcoin Core{}...",
+    //     if args.initial_sync {
+    //         " (initial sync mode)"
+    //     } else {
+    //         ""
+    //     }
+    // );
+    // let bitcoin_data_dir = PathBuf::from(dirs.data_path("bitcoin_data"));
+    // let bitcoin_node = BitcoinNode::new(bitcoin_data_dir, args.network).await?;
+
+    // // Wait for Bitcoin Core to be ready
+    // info!("Waiting for Bitcoin Core to be ready...");
+    // bitcoin_node.wait_for_ready(args.initial_sync).await?;
+    // info!("Bitcoin Core is ready");
+
+    let cancel_token = CancellationToken::new();
+    tokio::spawn(shutdown::on_signal(cancel_token.clone()));
+
+    // Load or create default pool config
+    #[cfg(feature = "pool")]
+    let mut pool_settings =
+        load_or_create_pool_config(&pool_mint_config_path, &dirs).map_err(AppError::config)?;
+    #[cfg(feature = "pool")]
+    {
+        info!("PoolMint Config: {:?}", &pool_settings);
+        if let Some(command) = args.command {
+            return commands::run(command, &pool_settings, &dirs)
+                .await
+                .map_err(AppError::from);
+        }
+    }
+
+    // Load or create default proxy config, of the pool of this process if it runs one
+    #[cfg(all(feature = "pool", feature = "proxy"))]
+    let proxy_settings = load_or_create_proxy_config(&proxy_config_path, &pool_settings, &dirs)
+        .map_err(AppError::config)?;
+    #[cfg(all(feature = "proxy", not(feature = "pool")))]
+    let proxy_settings = load_proxy_config(&proxy_config_path, &dirs).map_err(AppError::config)?;
+    #[cfg(feature = "proxy")]
+    {
+        info!("ProxyWallet Config: {:?}", &proxy_settings);
+        info!("Using proxy config path: {}", proxy_config_path);
+    }
+    #[cfg(feature = "pool")]
+    info!("Using pool mint config path: {}", pool_mint_config_path);
+    info!("Using data directory: {}", dirs.data.display());
+
+    #[cfg(feature = "pool")]
+    {
+        let coinbase_output = process_coinbase_output(args.coinbase_output, args.derivation_path)
+            .map_err(AppError::config)?;
+
+        // Update pool settings with the validated coinbase output
+        let coinbase_output = CoinbaseOutput::new(
+            "P2WPKH".to_string(), // Using P2WPKH for SLIP-132 xpub
+            coinbase_output,
+        );
+        pool_settings.coinbase_outputs = vec![coinbase_output];
+    }
+
+    tokio::spawn(status::events::log_events(cancel_token.clone()));
+    tokio::spawn(status::heartbeat::watch(cancel_token.clone()));
+    #[cfg(feature = "otlp")]
+    if let Some(exporter) = otlp_exporter {
+        tokio::spawn(exporter.run(cancel_token.clone()));
+    }
+    // Status tasks of the pool, configured in its file
+    #[cfg(feature = "pool")]
+    let snapshot = pool_settings.snapshot.clone();
+    #[cfg(feature = "pool")]
+    {
+        if let Some(alerts) = pool_settings.alerts.clone() {
+            let mint = pool_settings.mint.clone();
+            tokio::spawn(status::alerts::run(alerts, mint, cancel_token.clone()));
+        }
+        if let Some(history) = pool_settings.event_history.clone() {
+            tokio::spawn(status::history::run(history, cancel_token.clone()));
+        }
+        if let Some(statsd) = pool_settings.statsd.clone() {
+            tokio::spawn(status::statsd::run(statsd, cancel_token.clone()));
+        }
+        if let Some(nostr_status) = pool_settings.nostr_status.clone() {
+            tokio::spawn(status::nostr::run(nostr_status, cancel_token.clone()));
+        }
+        if let Some(tip_lag) = pool_settings.tip_lag.clone() {
+            let Some(bitcoin_rpc) = pool_settings.bitcoin_rpc.clone() else {
+                return Err(AppError::config(
+                    "tip_lag needs bitcoin_rpc to ask the node its tip",
+                ));
+            };
+            tokio::spawn(status::tip::run(tip_lag, bitcoin_rpc, cancel_token.clone()));
+        }
+        if let Some(audit_log) = &pool_settings.audit_log {
+            let log = status::audit::AuditLog::open(audit_log)
+                .map_err(|e| AppError::Config(format!("audit log {}: {}", audit_log.path, e)))?;
+            tokio::spawn(log.run(cancel_token.clone()));
+        }
+        tokio::spawn(status::hooks::run(
+            pool_settings.hooks.clone(),
+            cancel_token.clone(),
+        ));
+        if let Some(port_mapping) = pool_settings.port_mapping.clone() {
+            let ports = [
+                (Listener::Pool, net::port(&pool_settings.listen_address)),
+                #[cfg(feature = "proxy")]
+                (Listener::Translator, Some(proxy_settings.downstream_port)),
+            ];
+            let ports = ports
+                .into_iter()
+                .filter_map(|(listener, port)| Some((listener, port?)))
+                .collect();
+            tokio::spawn(port_mapping::run(port_mapping, ports, cancel_token.clone()));
+        }
+        // Restore the state the last process left before the pool and the translator start
+        if let Some(snapshot) = snapshot.clone() {
+            snapshot::recover(&snapshot.path)
+                .map_err(|e| AppError::Other(format!("snapshot {}: {}", snapshot.path, e)))?;
+            tokio::spawn(snapshot::run(snapshot, cancel_token.clone()));
+        }
+    }
+
+    // Take the listeners over from the running process if asked to, and serve ours to the next
+    #[cfg(unix)]
+    {
+        let handoff_socket = dirs.data_path(&args.handoff_socket.to_string_lossy());
+        let takeover = match args.takeover {
+            true => Some(
+                handoff::take_over(handoff_socket.as_ref())
+                    .await
+                    .map_err(|e| {
+                        AppError::Bind(format!("taking over from {}: {}", handoff_socket, e))
+                    })?,
+            ),
+            false => None,
+        };
+        tokio::spawn(handoff::serve(
+            handoff_socket.into(),
+            takeover,
+            cancel_token.clone(),
+        ));
+    }
+    #[cfg(not(unix))]
+    if args.takeover {
+        return Err(AppError::config(
+            "--takeover needs unix to pass the listeners",
+        ));
+    }
+
+    // Restart the pool and the translator after failures until either fails too often, and the
+    // pool with its configuration reloaded through the gRPC API
+    let supervisor = Supervisor::new(args.max_restarts, cancel_token.clone());
+    // the translator mining on the pool connects once the pool serves miners, see `startup`
+    #[cfg(all(feature = "pool", feature = "proxy"))]
+    let gated = startup::mines_on(&proxy_settings, &pool_settings);
+    #[cfg(feature = "pool")]
+    let pool = {
+        let pool_config = ReloadablePoolConfig::new(pool_mint_config_path, dirs, pool_settings);
+        let restart = pool_config.restart();
+        supervisor
+            .clone()
+            .supervise_restartable("pool", restart, move |cancel_token| {
+                let pool = pool_config
+                    .current()
+                    .map(|config| PoolSv2::new(config, pool_config.clone(), cancel_token))
+                    .map_err(|e| Failure::fatal("poison_lock", e));
+                async move { pool?.start().await.map_err(Failure::from) }
+            })
+    };
+    #[cfg(not(feature = "pool"))]
+    let pool = std::future::ready(Ok(()));
+    #[cfg(feature = "proxy")]
+    let proxy = supervisor.supervise("translator", move |cancel_token| {
+        let proxy = TranslatorSv2::new(proxy_settings.clone(), cancel_token.clone());
+        async move {
+            #[cfg(feature = "pool")]
+            if gated && !startup::wait_for_pool(&cancel_token).await {
+                return Ok(());
+            }
+            proxy.start().await.map_err(Failure::from)
+        }
+    });
+    #[cfg(not(feature = "proxy"))]
+    let proxy = std::future::ready(Ok(()));
+    let timeout = Duration::from_secs(args.shutdown_timeout);
+    let given_up =
+        match shutdown::with_deadline(async { tokio::join!(pool, proxy) }, &cancel_token, timeout)
+            .await
+        {
+            Some((pool, proxy)) => {
+                info!("Shutdown complete");
+                pool.and(proxy)
+            }
+            None => Ok(()),
+        };
+    #[cfg(feature = "pool")]
+    if let Some(snapshot) = snapshot {
+        if let Err(e) = snapshot::write(&snapshot.path, true) {
+            warn!("Snapshot: can't write {}: {}", snapshot.path, e);
+        }
+    }
+
+    given_up.map_err(AppError::from)
+}
@@ -0,0 +1,534 @@
+//! Log output of the process. Logs are text for humans by default; `--log-format json` writes one
+//! JSON object per line instead, for Loki, Elastic and the like to ingest without parsing text.
+//! Every object carries the same fields:
+//!
+//! - `timestamp`, `level`, `target` and `message`;
+//! - `subsystem`: `pool`, `mint`, `translator` or `main` after the module logging, the crate name
+//!   for dependencies;
+//! - `connection_id` and `worker` of the downstream connection logging, null outside of one and
+//!   until the worker is known;
+//!
+//! then the other fields of the event and of the spans it is logged in. The spans of a share
+//! carry its `share_id` (see `share_id`), the same in the translator and the pool, to follow it
+//! from the `mining.submit` of the miner to the credit at the mint.
+//!
+//! What is logged is filtered by the directives `init` is given, in the syntax of `RUST_LOG`,
+//! which the control API `log_filter` replaces at runtime, e.g.
+//! `{"command":"log_filter","filter":"info,proxy_wallet=trace"}`, see `set_filter`.
+//!
+//! With `--log-file` logs are also written to a file, in the same format, rotated daily, hourly
+//! or once it grows past `--log-max-size`, keeping the `--log-retention` last rotated files. The
+//! file is written from a thread of its own, so a slow disk does not hold up the pool.
+use crate::{
+    crash::RecentLogs,
+    status::{
+        diagnostics::{self, ConsoleArgs},
+        events::ErrorEvents,
+    },
+};
+use clap::ValueEnum;
+use serde_json::{json, Map, Value};
+use std::{
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+use tracing::{
+    field::{Field, Visit},
+    span::Record,
+    Event, Subscriber,
+};
+use tracing_appender::{
+    non_blocking::{NonBlocking, WorkerGuard},
+    rolling::{RollingFileAppender, Rotation},
+};
+use tracing_subscriber::{
+    field::RecordFields,
+    fmt::{
+        format::Writer,
+        time::{FormatTime, SystemTime},
+        FmtContext, FormatEvent, FormatFields, FormattedFields, MakeWriter,
+    },
+    layer::SubscriberExt,
+    registry::LookupSpan,
+    reload,
+    util::SubscriberInitExt,
+    EnvFilter, Layer, Registry,
+};
+
+/// Handle replacing the filter of the subscriber, set once it is installed.
+static FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum LogRotation {
+    #[default]
+    Daily,
+    Hourly,
+    /// Once the file grows past `--log-max-size`.
+    Size,
+    Never,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct LogFileArgs {
+    /// File logs are also written to
+    #[arg(long = "log-file")]
+    pub path: Option<PathBuf>,
+
+    /// When the log file is rotated
+    #[arg(long = "log-rotation", value_enum, default_value_t = LogRotation::Daily)]
+    pub rotation: LogRotation,
+
+    /// Size in MB the log file is rotated at, with `--log-rotation size`
+    #[arg(long = "log-max-size", default_value_t = 100)]
+    pub max_size_mb: u64,
+
+    /// Rotated log files kept
+    #[arg(long = "log-retention", default_value_t = 7)]
+    pub retention: usize,
+}
+
+impl LogFileArgs {
+    /// Writer of the log file, if any, with the guard flushing it when dropped.
+    fn writer(&self) -> io::Result<Option<(NonBlocking, WorkerGuard)>> {
+        let Some(path) = &self.path else {
+            return Ok(None);
+        };
+        let rotation = match self.rotation {
+            LogRotation::Size => {
+                let file =
+                    SizeRollingFile::open(path, self.max_size_mb * 1024 * 1024, self.retention)?;
+                return Ok(Some(tracing_appender::non_blocking(file)));
+            }
+            LogRotation::Daily => Rotation::DAILY,
+            LogRotation::Hourly => Rotation::HOURLY,
+            LogRotation::Never => Rotation::NEVER,
+        };
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let prefix = path
+            .file_name()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "log file has no name"))?;
+        let appender = RollingFileAppender::builder()
+            .rotation(rotation)
+            .filename_prefix(prefix.to_string_lossy())
+            .max_log_files(self.retention.max(1))
+            .build(dir)
+            .map_err(io::Error::other)?;
+        Ok(Some(tracing_appender::non_blocking(appender)))
+    }
+}
+
+/// Installs the subscriber writing logs in `format`, filtered by `directives`, to stdout, the log
+/// file of `file` and the crash bundle, spans also going to `otlp` (the layer of `crate::otlp` in a
+/// build with the `otlp` feature), and serving tokio-console if `console` asks for it. Logs
+/// written after the returned guard is dropped may miss the file.
+pub fn init<L>(
+    directives: &str,
+    format: LogFormat,
+    file: &LogFileArgs,
+    otlp: Option<L>,
+    console: &ConsoleArgs,
+) -> io::Result<Option<WorkerGuard>>
+where
+    L: Layer<Registry> + Send + Sync,
+{
+    let console = diagnostics::console_layer(console)?;
+    let (file_writer, guard) = file.writer()?.unzip();
+    let filter = EnvFilter::builder()
+        .parse(qualify(directives))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let (filter, handle) = reload::Layer::new(filter);
+    let _ = FILTER.set(handle);
+    // filtered apart from tokio-console, which sees the spans of tokio whatever is logged
+    let logs = ErrorEvents
+        .and_then(otlp)
+        .and_then(layer(format, io::stdout, true))
+        .and_then(layer(format, RecentLogs, false))
+        .and_then(file_writer.map(|writer| layer(format, writer, false)))
+        .with_filter(filter);
+    tracing_subscriber::registry()
+        .with(logs)
+        .with(console)
+        .init();
+    Ok(guard)
+}
+
+/// Filter of what is logged, that `init` was given until replaced.
+pub fn filter() -> Option<String> {
+    FILTER.get()?.with_current(|filter| filter.to_string()).ok()
+}
+
+/// Replaces the filter of what is logged by `directives`, in the syntax of `RUST_LOG`, returning
+/// the filter now applied. Modules of this crate may be named without it, `proxy_wallet=trace`
+/// standing for `potato::proxy_wallet=trace`.
+pub fn set_filter(directives: &str) -> Result<String, String> {
+    let filter = EnvFilter::builder()
+        .parse(qualify(directives))
+        .map_err(|e| format!("invalid filter {:?}: {}", directives, e))?;
+    let applied = filter.to_string();
+    let handle = FILTER.get().ok_or("logging is not initialized")?;
+    handle.reload(filter).map_err(|e| e.to_string())?;
+    Ok(applied)
+}
+
+/// `directives` with a copy of every directive naming a target without a path, prefixed with the
+/// name of this crate. The copy matches nothing for targets of other crates.
+fn qualify(directives: &str) -> String {
+    directives
+        .split(',')
+        .map(str::trim)
+        .filter(|directive| !directive.is_empty())
+        .flat_map(|directive| {
+            let target = directive.split(['=', '[']).next().unwrap_or_default();
+            let qualified =
+                (directive.contains('=') && !target.is_empty() && !target.contains("::"))
+                    .then(|| format!("{}::{}", env!("CARGO_CRATE_NAME"), directive));
+            std::iter::once(directive.to_string()).chain(qualified)
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn layer<S, W>(format: LogFormat, writer: W, ansi: bool) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer()
+        .with_writer(writer)
+        .with_ansi(ansi);
+    match format {
+        LogFormat::Text => layer
+            .with_file(true)
+            .with_line_number(true)
+            .with_thread_ids(true)
+            .with_target(false)
+            .boxed(),
+        LogFormat::Json => layer
+            .fmt_fields(JsonFields)
+            .event_format(JsonFormat)
+            .boxed(),
+    }
+}
+
+/// Log file rotated once it grows past `max_size` bytes: `potato.log` moves to `potato.log.1`,
+/// which moves to `potato.log.2` and so on, the files past `retention` deleted.
+struct SizeRollingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    retention: usize,
+}
+
+impl SizeRollingFile {
+    fn open(path: &Path, max_size: u64, retention: usize) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            size,
+            max_size,
+            retention,
+        })
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", n));
+        path.into()
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.retention > 0 {
+            let _ = fs::remove_file(self.rotated(self.retention));
+            for n in (1..self.retention).rev() {
+                if self.rotated(n).exists() {
+                    fs::rename(self.rotated(n), self.rotated(n + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated(1))?;
+        }
+        self.file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for SizeRollingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Subsystem of the module at `target`.
+pub(crate) fn subsystem(target: &str) -> &str {
+    let mut path = target.split("::");
+    let krate = path.next().unwrap_or_default();
+    if krate != env!("CARGO_CRATE_NAME") {
+        return krate;
+    }
+    match (path.next(), path.next()) {
+        (Some("pool_mint"), Some("mint")) => "mint",
+        (Some("pool_mint"), _) => "pool",
+        (Some("proxy_wallet"), _) => "translator",
+        _ => "main",
+    }
+}
+
+/// Correlation id of a share, the hex of its nonce and ntime. The translator and the pool see
+/// both unchanged, where the job id and version are rewritten on the way, and a miner would have
+/// to find two shares of the same nonce in the same second for two to collide.
+pub fn share_id(nonce: u32, ntime: u32) -> String {
+    format!("{:08x}{:08x}", nonce, ntime)
+}
+
+/// Collects the fields it visits as JSON values.
+#[derive(Default)]
+pub(crate) struct JsonVisitor(pub(crate) Map<String, Value>);
+
+impl Visit for JsonVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), json!(format!("{:?}", value)));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+}
+
+/// Keeps the fields of spans as a JSON object, for `JsonFormat` to merge into its events.
+struct JsonFields;
+
+impl<'writer> FormatFields<'writer> for JsonFields {
+    fn format_fields<R: RecordFields>(
+        &self,
+        mut writer: Writer<'writer>,
+        fields: R,
+    ) -> fmt::Result {
+        let mut visitor = JsonVisitor::default();
+        fields.record(&mut visitor);
+        write!(writer, "{}", Value::Object(visitor.0))
+    }
+
+    fn add_fields(
+        &self,
+        current: &'writer mut FormattedFields<Self>,
+        fields: &Record<'_>,
+    ) -> fmt::Result {
+        let mut visitor = JsonVisitor(serde_json::from_str(&current.fields).unwrap_or_default());
+        fields.record(&mut visitor);
+        current.fields = Value::Object(visitor.0).to_string();
+        Ok(())
+    }
+}
+
+struct JsonFormat;
+
+impl<S, N> FormatEvent<S, N> for JsonFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let metadata = event.metadata();
+        let mut timestamp = String::new();
+        SystemTime.format_time(&mut Writer::new(&mut timestamp))?;
+        let mut visitor = JsonVisitor::default();
+        visitor.0.insert("timestamp".into(), json!(timestamp));
+        visitor
+            .0
+            .insert("level".into(), json!(metadata.level().as_str()));
+        visitor.0.insert("target".into(), json!(metadata.target()));
+        visitor
+            .0
+            .insert("subsystem".into(), json!(subsystem(metadata.target())));
+        visitor.0.insert("connection_id".into(), Value::Null);
+        visitor.0.insert("worker".into(), Value::Null);
+        // spans from the outermost in, so the innermost wins a field they share
+        if let Some(scope) = ctx.event_scope() {
+            for span in scope.from_root() {
+                let extensions = span.extensions();
+                let Some(fields) = extensions.get::<FormattedFields<N>>() else {
+                    continue;
+                };
+                if let Ok(Value::Object(fields)) = serde_json::from_str(&fields.fields) {
+                    visitor.0.extend(fields);
+                }
+            }
+        }
+        event.record(&mut visitor);
+        writeln!(writer, "{}", Value::Object(visitor.0))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{
+        io,
+        sync::{Arc, Mutex},
+    };
+    use tracing_subscriber::fmt::MakeWriter;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for Buffer {
+        type Writer = Self;
+
+        fn make_writer(&'a self) -> Self {
+            self.clone()
+        }
+    }
+
+    #[test]
+    fn logs_connection_fields_as_json() {
+        let buffer = Buffer::default();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(buffer.clone())
+            .with_ansi(false)
+            .fmt_fields(JsonFields)
+            .event_format(JsonFormat)
+            .finish();
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!(shares = 2, "outside");
+            let span = tracing::info_span!(
+                "connection",
+                connection_id = 7,
+                worker = tracing::field::Empty
+            );
+            let _entered = span.enter();
+            span.record("worker", "alice.rig1");
+            tracing::warn!("inside");
+        });
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["message"], "outside");
+        assert_eq!(lines[0]["shares"], 2);
+        assert_eq!(lines[0]["subsystem"], "main");
+        assert_eq!(lines[0]["connection_id"], Value::Null);
+        assert_eq!(lines[1]["level"], "WARN");
+        assert_eq!(lines[1]["connection_id"], 7);
+        assert_eq!(lines[1]["worker"], "alice.rig1");
+    }
+
+    #[test]
+    fn qualifies_the_modules_of_the_crate_in_filters() {
+        assert_eq!(
+            qualify("info, proxy_wallet=trace,tokio::net=warn"),
+            "info,proxy_wallet=trace,potato::proxy_wallet=trace,tokio::net=warn"
+        );
+        assert_eq!(
+            qualify("pool_mint[credit_share]=debug"),
+            "pool_mint[credit_share]=debug,potato::pool_mint[credit_share]=debug"
+        );
+        assert!(EnvFilter::builder()
+            .parse(qualify("proxy_wallet=trace"))
+            .is_ok());
+    }
+
+    #[test]
+    fn rotates_the_log_file_past_its_size() {
+        let dir = std::env::temp_dir().join(format!("potato-logs-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("potato.log");
+        let mut file = SizeRollingFile::open(&path, 10, 2).unwrap();
+        for line in ["first\n", "second\n", "third\n", "fourth\n"] {
+            file.write_all(line.as_bytes()).unwrap();
+        }
+        file.flush().unwrap();
+
+        let read = |path: PathBuf| fs::read_to_string(path).unwrap();
+        assert_eq!(read(path.clone()), "fourth\n");
+        assert_eq!(read(file.rotated(1)), "third\n");
+        assert_eq!(read(file.rotated(2)), "second\n");
+        assert!(!file.rotated(3).exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn names_subsystems_after_modules() {
+        let krate = env!("CARGO_CRATE_NAME");
+        assert_eq!(
+            subsystem(&format!("{}::pool_mint::mint::db", krate)),
+            "mint"
+        );
+        assert_eq!(
+            subsystem(&format!("{}::pool_mint::mining_pool", krate)),
+            "pool"
+        );
+        assert_eq!(
+            subsystem(&format!("{}::proxy_wallet::upstream_sv2", krate)),
+            "translator"
+        );
+        assert_eq!(subsystem(&format!("{}::control", krate)), "main");
+        assert_eq!(
+            subsystem("network_helpers_sv2::noise_connection"),
+            "network_helpers_sv2"
+        );
+    }
+}
@@ -0,0 +1,113 @@
+//! Addresses of the listeners and the upstreams. Every address is a socket address or a
+//! `host:port`, IPv6 literals in brackets (`[::1]:34254`), hostnames resolving to A or AAAA
+//! records alike. A listener binds the first address its host resolves to that it can bind, a
+//! connection tries every one in turn.
+//!
+//! A listener on the unspecified IPv6 address `[::]` is dual-stack, whatever the system default:
+//! it accepts IPv4 clients too, seen from IPv4-mapped addresses (`::ffff:192.0.2.1`) and told
+//! apart by their IPv4 address, see `client_ip`. `0.0.0.0` accepts IPv4 clients only, and a
+//! listener on any other IPv6 address IPv6 clients only.
+use socket2::{Domain, Protocol, Socket, Type};
+use std::{
+    io,
+    net::{IpAddr, SocketAddr, TcpListener, ToSocketAddrs},
+};
+
+/// Connections queued before the listener accepts them, that of tokio.
+const BACKLOG: i32 = 1024;
+
+/// The address of `port` on `host`, a hostname or an IP address, IPv6 literals bracketed.
+pub fn host_port(host: &str, port: u16) -> String {
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V6(ip)) => format!("[{}]:{}", ip, port),
+        _ => format!("{}:{}", host, port),
+    }
+}
+
+/// The port of `address`, a socket address or a `host:port`.
+pub fn port(address: &str) -> Option<u16> {
+    address.rsplit_once(':')?.1.parse().ok()
+}
+
+/// The address a client is told apart by, the IPv4 address of those connected from an
+/// IPv4-mapped address.
+pub fn client_ip(ip: IpAddr) -> IpAddr {
+    ip.to_canonical()
+}
+
+/// A listener on `address`, blocking, see the module.
+pub fn bind(address: &str) -> io::Result<TcpListener> {
+    let mut last_error = None;
+    for address in address.to_socket_addrs()? {
+        match bind_to(address) {
+            Ok(listener) => return Ok(listener),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} resolves to no address", address),
+        )
+    }))
+}
+
+/// A listener of the tokio runtime on `address`, see the module.
+pub fn listen(address: &str) -> io::Result<tokio::net::TcpListener> {
+    let listener = bind(address)?;
+    listener.set_nonblocking(true)?;
+    tokio::net::TcpListener::from_std(listener)
+}
+
+fn bind_to(address: SocketAddr) -> io::Result<TcpListener> {
+    let socket = Socket::new(
+        Domain::for_address(address),
+        Type::STREAM,
+        Some(Protocol::TCP),
+    )?;
+    if address.is_ipv6() {
+        socket.set_only_v6(!address.ip().is_unspecified())?;
+    }
+    // as `TcpListener::bind`, so a restarted process binds while connections close
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.bind(&address.into())?;
+    socket.listen(BACKLOG)?;
+    Ok(socket.into())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr, TcpStream};
+
+    #[test]
+    fn brackets_ipv6_literals() {
+        assert_eq!(host_port("127.0.0.1", 34254), "127.0.0.1:34254");
+        assert_eq!(host_port("::1", 34254), "[::1]:34254");
+        assert_eq!(
+            host_port("pool.example.com", 34254),
+            "pool.example.com:34254"
+        );
+        assert_eq!(port("[::]:34254"), Some(34254));
+        assert_eq!(port("pool.example.com"), None);
+        let mapped = IpAddr::V6(Ipv4Addr::new(192, 0, 2, 1).to_ipv6_mapped());
+        assert_eq!(client_ip(mapped), IpAddr::from([192, 0, 2, 1]));
+    }
+
+    #[test]
+    fn binds_dual_stack_on_the_unspecified_ipv6_address() {
+        // hosts without IPv6 have nothing to check
+        let Ok(listener) = bind("[::]:0") else {
+            return;
+        };
+        let port = listener.local_addr().unwrap().port();
+        let ipv4 = TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap();
+        let (_, peer) = listener.accept().unwrap();
+        assert_eq!(client_ip(peer.ip()), ipv4.local_addr().unwrap().ip());
+        let listener = bind("[::1]:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        assert!(TcpStream::connect((Ipv4Addr::LOCALHOST, port)).is_err());
+        assert!(TcpStream::connect((Ipv6Addr::LOCALHOST, port)).is_ok());
+    }
+}
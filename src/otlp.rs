@@ -0,0 +1,468 @@
+//! OpenTelemetry export, opt in with `--otlp-endpoint`. The spans of the process (shares
+//! submitted by the translator and credited by the pool, jobs distributed to the channels, calls
+//! to bitcoind) and the counters of the event bus are shipped to an OTLP collector over HTTP, JSON
+//! encoded to `/v1/traces` and `/v1/metrics`, every `--otlp-interval` seconds.
+//!
+//! Every subsystem is a resource of its own: what it exports carries `service.name` `potato` and
+//! `potato.subsystem` `pool`, `mint`, `translator` or `main`, named as in the logs. A span without
+//! a parent starts a trace of its own, but for the spans of a share: whichever subsystem they are
+//! in, those carrying a `share_id` (see `logging::share_id`) are put in the trace derived from it,
+//! so a share is traced from the translator to the mint. Their parent outside of that trace, such
+//! as the span of the connection, becomes a link.
+//!
+//! Spans are queued for export as they close. Past `SPAN_QUEUE_CAPACITY` spans waiting, new ones
+//! are dropped rather than slowing the pool down.
+use crate::{
+    logging::{subsystem, JsonVisitor},
+    status::events::{self, Snapshot},
+};
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use tracing::{
+    span::{Attributes, Id, Record},
+    warn, Subscriber,
+};
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+
+/// Closed spans waiting for export before new ones are dropped.
+pub const SPAN_QUEUE_CAPACITY: usize = 4096;
+
+/// `SPAN_KIND_INTERNAL`
+const SPAN_KIND: u8 = 1;
+/// `AGGREGATION_TEMPORALITY_CUMULATIVE`
+const CUMULATIVE: u8 = 2;
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct OtlpArgs {
+    /// OTLP/HTTP collector spans and metrics are exported to, e.g. `http://localhost:4318`
+    #[arg(long = "otlp-endpoint")]
+    pub endpoint: Option<String>,
+
+    /// Seconds between two exports to the OTLP collector
+    #[arg(long = "otlp-interval", default_value_t = 10)]
+    pub interval_secs: u64,
+}
+
+/// The layer queueing spans as they close and the exporter shipping them, if `args` has an
+/// endpoint.
+pub fn setup(args: &OtlpArgs) -> Option<(OtlpLayer, OtlpExporter)> {
+    let endpoint = args.endpoint.as_ref()?;
+    let (sender, receiver) = mpsc::channel(SPAN_QUEUE_CAPACITY);
+    let exporter = OtlpExporter {
+        endpoint: endpoint.trim_end_matches('/').to_string(),
+        interval: Duration::from_secs(args.interval_secs.max(1)),
+        spans: receiver,
+        client: reqwest::Client::new(),
+        started_at: now_nanos(),
+    };
+    Some((OtlpLayer { spans: sender }, exporter))
+}
+
+fn now_nanos() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or_default()
+}
+
+/// A span as exported, kept in the extensions of the span until it closes.
+#[derive(Debug, Clone)]
+struct SpanRecord {
+    trace_id: [u8; 16],
+    span_id: [u8; 8],
+    parent_span_id: Option<[u8; 8]>,
+    /// Spans of other traces this one follows from.
+    links: Vec<([u8; 16], [u8; 8])>,
+    name: &'static str,
+    subsystem: String,
+    start: u64,
+    end: u64,
+    attributes: Map<String, Value>,
+}
+
+#[derive(Debug)]
+pub struct OtlpLayer {
+    spans: mpsc::Sender<SpanRecord>,
+}
+
+impl<S> Layer<S> for OtlpLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let parent = span.parent().and_then(|parent| {
+            let extensions = parent.extensions();
+            let record = extensions.get::<SpanRecord>()?;
+            Some((record.trace_id, record.span_id))
+        });
+        let mut visitor = JsonVisitor::default();
+        attrs.record(&mut visitor);
+        let share_trace_id = visitor
+            .0
+            .get("share_id")
+            .and_then(Value::as_str)
+            .map(share_trace_id);
+        let trace_id = share_trace_id
+            .or(parent.map(|(trace_id, _)| trace_id))
+            .unwrap_or_else(rand::random);
+        let (parent, links) = match parent {
+            Some(parent) if parent.0 != trace_id => (None, vec![parent]),
+            parent => (parent, vec![]),
+        };
+        let record = SpanRecord {
+            trace_id,
+            span_id: rand::random(),
+            parent_span_id: parent.map(|(_, span_id)| span_id),
+            links,
+            name: attrs.metadata().name(),
+            subsystem: subsystem(attrs.metadata().target()).to_string(),
+            start: now_nanos(),
+            end: 0,
+            attributes: visitor.0,
+        };
+        span.extensions_mut().insert(record);
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut extensions = span.extensions_mut();
+        let Some(record) = extensions.get_mut::<SpanRecord>() else {
+            return;
+        };
+        let mut visitor = JsonVisitor(std::mem::take(&mut record.attributes));
+        values.record(&mut visitor);
+        record.attributes = visitor.0;
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let Some(mut record) = span.extensions_mut().remove::<SpanRecord>() else {
+            return;
+        };
+        record.end = now_nanos();
+        // a full queue drops the span
+        let _ = self.spans.try_send(record);
+    }
+}
+
+/// Trace of the spans of the share of `share_id`.
+fn share_trace_id(share_id: &str) -> [u8; 16] {
+    let hash = Sha256::digest(share_id.as_bytes());
+    let mut trace_id = [0; 16];
+    trace_id.copy_from_slice(&hash[..16]);
+    trace_id
+}
+
+#[derive(Debug)]
+pub struct OtlpExporter {
+    endpoint: String,
+    interval: Duration,
+    spans: mpsc::Receiver<SpanRecord>,
+    client: reqwest::Client,
+    /// Start of the cumulative metrics.
+    started_at: u64,
+}
+
+impl OtlpExporter {
+    /// Exports on schedule until `cancel_token` is cancelled, then a last time.
+    pub async fn run(mut self, cancel_token: CancellationToken) {
+        let mut interval = tokio::time::interval(self.interval);
+        loop {
+            let cancelled = tokio::select! {
+                _ = interval.tick() => false,
+                _ = cancel_token.cancelled() => true,
+            };
+            self.export().await;
+            if cancelled {
+                break;
+            }
+        }
+    }
+
+    async fn export(&mut self) {
+        let mut spans = vec![];
+        while let Ok(span) = self.spans.try_recv() {
+            spans.push(span);
+        }
+        if !spans.is_empty() {
+            if let Err(e) = self.post("v1/traces", traces(&spans)).await {
+                warn!("OTLP: exporting {} spans failed: {}", spans.len(), e);
+            }
+        }
+        let metrics = metrics(&events::snapshot(), self.started_at, now_nanos());
+        if let Err(e) = self.post("v1/metrics", metrics).await {
+            warn!("OTLP: exporting metrics failed: {}", e);
+        }
+    }
+
+    async fn post(&self, path: &str, body: Value) -> Result<(), reqwest::Error> {
+        self.client
+            .post(format!("{}/{}", self.endpoint, path))
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+fn resource(subsystem: &str) -> Value {
+    json!({
+        "attributes": attributes(&Map::from_iter([
+            ("service.name".to_string(), json!("potato")),
+            ("service.version".to_string(), json!(env!("CARGO_PKG_VERSION"))),
+            ("potato.subsystem".to_string(), json!(subsystem)),
+        ])),
+    })
+}
+
+fn scope() -> Value {
+    json!({ "name": "potato", "version": env!("CARGO_PKG_VERSION") })
+}
+
+/// Key values of `fields`, numbers as `intValue` or `doubleValue`.
+fn attributes(fields: &Map<String, Value>) -> Value {
+    fields
+        .iter()
+        .map(|(key, value)| {
+            let value = match value {
+                Value::Bool(value) => json!({ "boolValue": value }),
+                Value::Number(n) if n.is_f64() => json!({ "doubleValue": n }),
+                // 64 bit integers are strings in the JSON encoding of OTLP
+                Value::Number(n) => json!({ "intValue": n.to_string() }),
+                Value::String(value) => json!({ "stringValue": value }),
+                value => json!({ "stringValue": value.to_string() }),
+            };
+            json!({ "key": key, "value": value })
+        })
+        .collect()
+}
+
+/// `ExportTraceServiceRequest` of `spans`, a resource per subsystem.
+fn traces(spans: &[SpanRecord]) -> Value {
+    let mut by_subsystem: BTreeMap<&str, Vec<Value>> = BTreeMap::new();
+    for span in spans {
+        let links: Vec<Value> = span
+            .links
+            .iter()
+            .map(|(trace_id, span_id)| {
+                json!({ "traceId": hex::encode(trace_id), "spanId": hex::encode(span_id) })
+            })
+            .collect();
+        by_subsystem
+            .entry(span.subsystem.as_str())
+            .or_default()
+            .push(json!({
+                "traceId": hex::encode(span.trace_id),
+                "spanId": hex::encode(span.span_id),
+                "parentSpanId": span.parent_span_id.map(hex::encode).unwrap_or_default(),
+                "links": links,
+                "name": span.name,
+                "kind": SPAN_KIND,
+                "startTimeUnixNano": span.start.to_string(),
+                "endTimeUnixNano": span.end.to_string(),
+                "attributes": attributes(&span.attributes),
+            }));
+    }
+    let resource_spans: Vec<Value> = by_subsystem
+        .into_iter()
+        .map(|(subsystem, spans)| {
+            json!({
+                "resource": resource(subsystem),
+                "scopeSpans": [{ "scope": scope(), "spans": spans }],
+            })
+        })
+        .collect();
+    json!({ "resourceSpans": resource_spans })
+}
+
+/// A cumulative counter of `points`, each a value with its attributes.
+fn counter(name: &str, unit: &str, points: Vec<(u64, Value)>, start: u64, now: u64) -> Value {
+    let points: Vec<Value> = points
+        .into_iter()
+        .map(|(value, attributes)| {
+            json!({
+                "attributes": attributes,
+                "startTimeUnixNano": start.to_string(),
+                "timeUnixNano": now.to_string(),
+                "asInt": value.to_string(),
+            })
+        })
+        .collect();
+    json!({
+        "name": name,
+        "unit": unit,
+        "sum": {
+            "dataPoints": points,
+            "aggregationTemporality": CUMULATIVE,
+            "isMonotonic": true,
+        },
+    })
+}
+
+/// `ExportMetricsServiceRequest` of the counters of `snapshot`, counted since `start`.
+fn metrics(snapshot: &Snapshot, start: u64, now: u64) -> Value {
+    let pool = vec![
+        counter(
+            "potato.shares.accepted",
+            "{share}",
+            vec![(snapshot.shares_accepted, json!([]))],
+            start,
+            now,
+        ),
+        counter(
+            "potato.shares.weight",
+            "1",
+            vec![(snapshot.share_weight, json!([]))],
+            start,
+            now,
+        ),
+        counter(
+            "potato.channels.opened",
+            "{channel}",
+            vec![(snapshot.channels_opened, json!([]))],
+            start,
+            now,
+        ),
+        json!({
+            "name": "potato.node.ready",
+            "unit": "1",
+            "gauge": {
+                "dataPoints": [{
+                    "timeUnixNano": now.to_string(),
+                    "asInt": (snapshot.node_ready as u64).to_string(),
+                }],
+            },
+        }),
+    ];
+    let issued = snapshot
+        .issued
+        .iter()
+        .map(|(unit, amount)| {
+            let unit = Map::from_iter([("unit".to_string(), json!(unit))]);
+            (*amount, attributes(&unit))
+        })
+        .collect();
+    let mint = vec![counter("potato.mint.issued", "1", issued, start, now)];
+    let resource_metrics: Vec<Value> = [("pool", pool), ("mint", mint)]
+        .into_iter()
+        .map(|(subsystem, metrics)| {
+            json!({
+                "resource": resource(subsystem),
+                "scopeMetrics": [{ "scope": scope(), "metrics": metrics }],
+            })
+        })
+        .collect();
+    json!({ "resourceMetrics": resource_metrics })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[test]
+    fn exports_spans_with_their_trace() {
+        let (sender, mut receiver) = mpsc::channel(SPAN_QUEUE_CAPACITY);
+        let subscriber = tracing_subscriber::registry().with(OtlpLayer { spans: sender });
+        tracing::subscriber::with_default(subscriber, || {
+            let share = tracing::info_span!("submit_share", channel_id = 7);
+            let _entered = share.enter();
+            let credit = tracing::info_span!("credit_share", account = tracing::field::Empty);
+            credit.record("account", "alice");
+        });
+
+        let credit = receiver.try_recv().unwrap();
+        let share = receiver.try_recv().unwrap();
+        assert_eq!((share.name, credit.name), ("submit_share", "credit_share"));
+        assert_eq!(credit.trace_id, share.trace_id);
+        assert_eq!(credit.parent_span_id, Some(share.span_id));
+        assert!(share.start <= credit.start && credit.end <= share.end);
+
+        let request = traces(&[share, credit]);
+        let spans = &request["resourceSpans"][0]["scopeSpans"][0]["spans"];
+        let resource = request["resourceSpans"][0]["resource"]["attributes"]
+            .as_array()
+            .unwrap();
+        assert!(resource
+            .contains(&json!({ "key": "potato.subsystem", "value": { "stringValue": "main" } })));
+        assert_eq!(spans[0]["parentSpanId"], "");
+        assert_eq!(
+            spans[0]["attributes"],
+            json!([{ "key": "channel_id", "value": { "intValue": "7" } }])
+        );
+        assert_eq!(spans[1]["parentSpanId"], spans[0]["spanId"]);
+        assert_eq!(
+            spans[1]["attributes"],
+            json!([{ "key": "account", "value": { "stringValue": "alice" } }])
+        );
+    }
+
+    #[test]
+    fn traces_a_share_across_subsystems() {
+        let (sender, mut receiver) = mpsc::channel(SPAN_QUEUE_CAPACITY);
+        let subscriber = tracing_subscriber::registry().with(OtlpLayer { spans: sender });
+        let share_id = crate::logging::share_id(0xdeadbeef, 0x66000000);
+        tracing::subscriber::with_default(subscriber, || {
+            let connection = tracing::info_span!("connection", connection_id = 1);
+            let _entered = connection.enter();
+            tracing::info_span!("sv1_submit", share_id = %share_id).in_scope(|| {});
+            tracing::info_span!("validate_share", share_id = %share_id).in_scope(|| {
+                tracing::info_span!("credit_share").in_scope(|| {});
+            });
+        });
+
+        let submit = receiver.try_recv().unwrap();
+        let credit = receiver.try_recv().unwrap();
+        let validate = receiver.try_recv().unwrap();
+        let connection = receiver.try_recv().unwrap();
+        assert_eq!(share_id, "deadbeef66000000");
+        assert_eq!(submit.trace_id, share_trace_id(&share_id));
+        assert_eq!(validate.trace_id, submit.trace_id);
+        assert_eq!(credit.trace_id, submit.trace_id);
+        assert_eq!(credit.parent_span_id, Some(validate.span_id));
+        // the connection is a link, in a trace of its own
+        assert_ne!(connection.trace_id, submit.trace_id);
+        assert_eq!(submit.parent_span_id, None);
+        assert_eq!(
+            submit.links,
+            vec![(connection.trace_id, connection.span_id)]
+        );
+        let request = traces(&[submit]);
+        assert_eq!(
+            request["resourceSpans"][0]["scopeSpans"][0]["spans"][0]["links"][0]["spanId"],
+            hex::encode(connection.span_id)
+        );
+    }
+
+    #[test]
+    fn exports_event_bus_counters() {
+        let mut snapshot = Snapshot {
+            shares_accepted: 3,
+            ..Default::default()
+        };
+        snapshot.issued.insert("ehash".into(), 12);
+        let request = metrics(&snapshot, 1, 2);
+        let pool = &request["resourceMetrics"][0]["scopeMetrics"][0]["metrics"];
+        assert_eq!(pool[0]["name"], "potato.shares.accepted");
+        assert_eq!(pool[0]["sum"]["dataPoints"][0]["asInt"], "3");
+        let mint = &request["resourceMetrics"][1]["scopeMetrics"][0]["metrics"];
+        assert_eq!(
+            mint[0]["sum"]["dataPoints"][0]["attributes"],
+            json!([{ "key": "unit", "value": { "stringValue": "ehash" } }])
+        );
+    }
+}
@@ -0,0 +1,272 @@
+//! Follows the blocks found by the pool through Bitcoin Core until their coinbase matures, then
+//! has the mint pay out the round the block closed. A block that leaves the best chain, or never
+//! makes it there, voids its round instead, so the mint never backs tokens with orphaned rewards.
+//!
+//! Coinbase transactions are looked up by txid, so the node must run with `txindex=1`.
+//!
+//! With keyset epochs enabled, the chain tip is also followed so the ehash keyset rolls over on
+//! every difficulty adjustment, see `epochs`.
+//!
+//! Calls to the node are retried as the `retry` policy of its configuration says, see
+//! `crate::retry`.
+use crate::{
+    error::{MintError, MintResult},
+    pool_mint::mint::{
+        epochs::Epoch,
+        lifecycle::now_secs,
+        rounds::{Round, COINBASE_MATURITY},
+        Mint,
+    },
+    retry::{Retry, RetryPolicy},
+};
+use bitcoincore_rpc::{bitcoin::Txid, jsonrpc, Auth, Client, RpcApi};
+use roles_logic_sv2::utils::Mutex;
+use serde::Deserialize;
+use std::{str::FromStr, sync::Arc, time::Duration};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, info_span, warn};
+
+/// How often immature rounds are checked with the node.
+const CHECK_INTERVAL_SECS: u64 = 60;
+/// A found block still unknown to the node after this long was rejected or lost a race.
+const UNSEEN_TIMEOUT_SECS: u64 = 60 * 60;
+/// RPC code of `getrawtransaction` for a transaction it doesn't know.
+const RPC_INVALID_ADDRESS_OR_KEY: i32 = -5;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct BitcoinRpcConfig {
+    pub url: String,
+    pub user: String,
+    pub password: String,
+    #[serde(default)]
+    pub retry: RetryPolicy,
+}
+
+impl BitcoinRpcConfig {
+    /// How calls to the node are retried, all of them sharing its circuit breaker.
+    pub fn retry(&self) -> Retry {
+        Retry::new("bitcoind", &self.retry)
+    }
+}
+
+/// Where a coinbase stands in the node's best chain.
+#[derive(Debug, PartialEq, Eq)]
+enum CoinbaseStatus {
+    Unseen,
+    Confirmed {
+        height: u64,
+        block_hash: String,
+        confirmations: u32,
+    },
+}
+
+#[derive(Debug)]
+pub struct MaturityWatcher {
+    mint: Arc<Mutex<Mint>>,
+    client: Arc<Client>,
+    retry: Retry,
+}
+
+impl MaturityWatcher {
+    pub fn new(mint: Arc<Mutex<Mint>>, config: &BitcoinRpcConfig) -> MintResult<Self> {
+        Ok(Self {
+            mint,
+            client: Arc::new(client(config)?),
+            retry: config.retry(),
+        })
+    }
+
+    pub async fn run(self, cancel_token: CancellationToken) {
+        let mut ticker = tokio::time::interval(Duration::from_secs(CHECK_INTERVAL_SECS));
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {
+                    let checking = async {
+                        if let Err(e) = self.check_rounds().await {
+                            warn!("Mint: checking reward maturity failed: {}", e);
+                        }
+                        if let Err(e) = self.check_epoch().await {
+                            warn!("Mint: checking the difficulty epoch failed: {}", e);
+                        }
+                    };
+                    // a node not answering doesn't hold the shutdown, the next start checks again
+                    if cancel_token.run_until_cancelled(checking).await.is_none() {
+                        break;
+                    }
+                }
+                _ = cancel_token.cancelled() => break,
+            }
+        }
+    }
+
+    async fn check_rounds(&self) -> MintResult<()> {
+        let rounds = self
+            .mint
+            .safe_lock(|m| m.immature_rounds())
+            .map_err(MintError::from)??;
+        for round in rounds {
+            let Some(txid) = round.coinbase_txid.clone() else {
+                continue;
+            };
+            let status = self.coinbase_status(&txid).await?;
+            debug!(
+                "Mint: coinbase {} of round {} is {:?}",
+                txid, round.id, status
+            );
+            if let Err(e) = self.update_round(&round, status) {
+                error!("Mint: updating round {} failed: {}", round.id, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Rolls the ehash keyset over if the chain entered a new difficulty epoch.
+    async fn check_epoch(&self) -> MintResult<()> {
+        if !self
+            .mint
+            .safe_lock(|m| m.epochs_enabled())
+            .map_err(MintError::from)?
+        {
+            return Ok(());
+        }
+        let _span = info_span!("bitcoind_rpc", call = "epoch");
+        let epoch = call(&self.client, &self.retry, |client| {
+            let number = Epoch::number_at(client.get_block_count().map_err(rpc)?);
+            // the first block of the epoch, later ones may be at minimum difficulty on testnet
+            let hash = client
+                .get_block_hash(Epoch::start_height(number))
+                .map_err(rpc)?;
+            let header = client.get_block_header_info(&hash).map_err(rpc)?;
+            let bits = u32::from_str_radix(&header.bits, 16).map_err(rpc)?;
+            Ok(Epoch { number, bits })
+        })
+        .await?;
+        if let Some(id) = self
+            .mint
+            .safe_lock(|m| m.enter_epoch(epoch))
+            .map_err(MintError::from)??
+        {
+            info!(
+                "Mint: difficulty epoch {} (difficulty {:.0}) started, ehash keyset {}",
+                epoch.number,
+                epoch.difficulty(),
+                id
+            );
+        }
+        Ok(())
+    }
+
+    fn update_round(&self, round: &Round, status: CoinbaseStatus) -> MintResult<()> {
+        self.mint.safe_lock(|m| match status {
+            CoinbaseStatus::Confirmed { confirmations, .. }
+                if confirmations >= COINBASE_MATURITY =>
+            {
+                m.mature_round(round.id)
+            }
+            // rounds confirmed before block hashes were recorded get theirs too
+            CoinbaseStatus::Confirmed {
+                height, block_hash, ..
+            } => match round.block_hash {
+                Some(_) => Ok(()),
+                None => {
+                    info!(
+                        "Mint: block {} of round {} confirmed at {}",
+                        block_hash, round.id, height
+                    );
+                    m.set_round_block(round.id, height, &block_hash)
+                }
+            },
+            // it was in the best chain before, a reorg took it out
+            CoinbaseStatus::Unseen if round.height.is_some() => m.orphan_round(round.id),
+            CoinbaseStatus::Unseen => {
+                let ended_at = round.ended_at.unwrap_or(round.started_at);
+                if now_secs() > ended_at.saturating_add(UNSEEN_TIMEOUT_SECS) {
+                    m.orphan_round(round.id)
+                } else {
+                    Ok(())
+                }
+            }
+        })?
+    }
+
+    async fn coinbase_status(&self, txid: &str) -> MintResult<CoinbaseStatus> {
+        let txid = Txid::from_str(txid).map_err(rpc)?;
+        let _span = info_span!("bitcoind_rpc", call = "coinbase_status", %txid);
+        call(&self.client, &self.retry, move |client| {
+            let tx = match client.get_raw_transaction_info(&txid, None) {
+                Ok(tx) => tx,
+                Err(bitcoincore_rpc::Error::JsonRpc(jsonrpc::Error::Rpc(e)))
+                    if e.code == RPC_INVALID_ADDRESS_OR_KEY =>
+                {
+                    return Ok(CoinbaseStatus::Unseen);
+                }
+                Err(e) => return Err(rpc(e)),
+            };
+            match (tx.blockhash, tx.confirmations) {
+                (Some(blockhash), Some(confirmations)) if confirmations > 0 => {
+                    let header = client.get_block_header_info(&blockhash).map_err(rpc)?;
+                    Ok(CoinbaseStatus::Confirmed {
+                        height: header.height as u64,
+                        block_hash: blockhash.to_string(),
+                        confirmations,
+                    })
+                }
+                // known from a block that is not in the best chain anymore
+                _ => Ok(CoinbaseStatus::Unseen),
+            }
+        })
+        .await
+    }
+}
+
+/// Confirmed balance of the node's wallet, watch-only addresses included, so matured coinbase
+/// outputs count as soon as the wallet watches the pool's payout address.
+pub async fn wallet_balance(config: &BitcoinRpcConfig) -> MintResult<u64> {
+    let client = Arc::new(client(config)?);
+    let _span = info_span!("bitcoind_rpc", call = "wallet_balance");
+    call(&client, &config.retry(), |client| {
+        let balance = client.get_balance(None, Some(true)).map_err(rpc)?;
+        Ok(balance.to_sat())
+    })
+    .await
+}
+
+/// Difficulty of the next block of the node's best chain.
+pub async fn network_difficulty(config: &BitcoinRpcConfig) -> MintResult<f64> {
+    let client = Arc::new(client(config)?);
+    let _span = info_span!("bitcoind_rpc", call = "network_difficulty");
+    call(&client, &config.retry(), |client| {
+        client.get_difficulty().map_err(rpc)
+    })
+    .await
+}
+
+/// Calls the node with `call` on a blocking thread, the RPC client being blocking, as `retry`
+/// says.
+pub(crate) async fn call<T, F>(client: &Arc<Client>, retry: &Retry, call: F) -> MintResult<T>
+where
+    T: Send + 'static,
+    F: Fn(&Client) -> MintResult<T> + Send + Sync + 'static,
+{
+    let call = Arc::new(call);
+    retry
+        .run(|| {
+            let (client, call) = (client.clone(), call.clone());
+            async move {
+                tokio::task::spawn_blocking(move || call(&client))
+                    .await
+                    .map_err(rpc)?
+            }
+        })
+        .await
+        .map_err(|e| e.into_error(rpc))
+}
+
+pub(crate) fn client(config: &BitcoinRpcConfig) -> MintResult<Client> {
+    let auth = Auth::UserPass(config.user.clone(), config.password.clone());
+    Client::new(&config.url, auth).map_err(rpc)
+}
+
+pub(crate) fn rpc(e: impl std::fmt::Display) -> MintError {
+    MintError::BitcoinRpc(e.to_string())
+}
@@ -0,0 +1,383 @@
+//! Accounts of a pool mining for several tenants. The channels of the stratum users an account
+//! lists mine for it, and with the `coinbase` payout policy the account is paid in the coinbase of
+//! the blocks of the pool instead of through the mint: the shares of its users accrue to it rather
+//! than being credited at the mint. Every template the reward is split by the work accrued since
+//! the last block found, the shares of the users of no account accruing to the pool. Each account
+//! gets an output paying its part to the key derived from its xpub, the rest going to the first of
+//! the `coinbase_outputs` of the pool and through the mint as before.
+//!
+//! A block found settles the accounts its coinbase paid, their work starting over. A part below
+//! the `min_output_sats` of its account is left to the pool for that block, the work of the
+//! account accruing on to the next one. Accrued work is kept in memory, carried over restarts
+//! by `crate::snapshot` if enabled, every account starting over otherwise.
+use super::CoinbaseOutput;
+use crate::{
+    configuration::{derive_child_public_key, validate_xpub},
+    error::{PoolError, PoolResult},
+    pool_mint::mint::accounts::matches_pattern,
+    snapshot::Snapshot,
+};
+use roles_logic_sv2::{
+    template_distribution_sv2::NewTemplate,
+    utils::{CoinbaseOutput as Output, Mutex},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use stratum_common::bitcoin::{consensus::deserialize, Script, Transaction, TxOut};
+use tracing::info;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct PoolAccountConfig {
+    pub name: String,
+    /// Stratum users mining for the account, `*` matching any characters, e.g. `acme.*`. The
+    /// first account listing a user wins.
+    pub users: Vec<String>,
+    #[serde(default)]
+    pub payout: PayoutPolicy,
+    /// SLIP-132 extended public key the account is paid to, at `derivation_path`. Required by
+    /// the `coinbase` payout policy.
+    #[serde(default)]
+    pub xpub: Option<String>,
+    #[serde(default = "PoolAccountConfig::default_derivation_path")]
+    pub derivation_path: String,
+    /// Smallest output paid to the account, see the module.
+    #[serde(default = "PoolAccountConfig::default_min_output_sats")]
+    pub min_output_sats: u64,
+}
+
+impl PoolAccountConfig {
+    fn default_derivation_path() -> String {
+        "m/0/0".to_string()
+    }
+
+    /// Dust limit of a P2PKH output, above that of the P2WPKH outputs paid.
+    fn default_min_output_sats() -> u64 {
+        546
+    }
+}
+
+/// How the work of an account is paid.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PayoutPolicy {
+    /// In the coinbase, see the module.
+    #[default]
+    Coinbase,
+    /// Through the mint, as the users of no account.
+    Mint,
+}
+
+/// An account of the pool.
+#[derive(Debug)]
+struct Account {
+    name: String,
+    users: Vec<String>,
+    /// Script paying the account, none when paid through the mint.
+    script: Option<Script>,
+    min_output_sats: u64,
+    /// Weight of the shares accrued since the block that last paid the account.
+    accrued: u64,
+}
+
+/// The work accrued, as snapshot.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Accrued {
+    pool: u64,
+    accounts: BTreeMap<String, u64>,
+}
+
+/// The accounts of the pool and the work they accrued, see the module.
+#[derive(Debug, Default)]
+pub struct Accounts {
+    /// In the order of the config, the first listing a user winning.
+    accounts: Vec<Account>,
+    /// Weight of the shares of users of no account since the last block found.
+    pool_accrued: u64,
+    /// Outputs of the pool, the first one paid what the accounts aren't.
+    pool_outputs: Vec<TxOut>,
+    /// Outputs of the future templates, until a new prev hash activates one.
+    future: HashMap<u64, Vec<TxOut>>,
+}
+
+impl Accounts {
+    /// The accounts of `configs`, the templates paying the rest to `pool_outputs`.
+    pub fn new(pool_outputs: Vec<TxOut>, configs: &[PoolAccountConfig]) -> PoolResult<Self> {
+        let accounts = configs
+            .iter()
+            .map(|config| {
+                let script = match config.payout {
+                    PayoutPolicy::Coinbase => Some(script(config)?),
+                    PayoutPolicy::Mint => None,
+                };
+                Ok(Account {
+                    name: config.name.clone(),
+                    users: config.users.clone(),
+                    script,
+                    min_output_sats: config.min_output_sats,
+                    accrued: 0,
+                })
+            })
+            .collect::<PoolResult<_>>()?;
+        Ok(Self {
+            accounts,
+            pool_outputs,
+            ..Self::default()
+        })
+    }
+
+    /// Whether no account is paid in the coinbase.
+    pub fn is_empty(&self) -> bool {
+        self.paid_in_coinbase().next().is_none()
+    }
+
+    fn paid_in_coinbase(&self) -> impl Iterator<Item = (&Account, &Script)> {
+        self.accounts
+            .iter()
+            .filter_map(|account| Some((account, account.script.as_ref()?)))
+    }
+
+    /// The account paid in the coinbase the channels of `user` mine for, if any.
+    pub fn account_of(&self, user: &str) -> Option<String> {
+        let account = self.accounts.iter().find(|account| {
+            account
+                .users
+                .iter()
+                .any(|pattern| matches_pattern(pattern, user))
+        })?;
+        account.script.as_ref().map(|_| account.name.clone())
+    }
+
+    /// Accrues a share of `weight` to `account`, or to the pool for a user of no account.
+    pub fn accrue(&mut self, account: Option<&str>, weight: u64) {
+        let account = account.and_then(|name| self.accounts.iter_mut().find(|a| a.name == name));
+        let accrued = match account {
+            Some(account) => &mut account.accrued,
+            None => &mut self.pool_accrued,
+        };
+        *accrued = accrued.saturating_add(weight);
+    }
+
+    /// Outputs paying the accounts their part of `reward`, see the module.
+    pub fn outputs(&self, reward: u64) -> Vec<TxOut> {
+        let total = self
+            .paid_in_coinbase()
+            .fold(self.pool_accrued as u128, |total, (account, _)| {
+                total + account.accrued as u128
+            });
+        if total == 0 {
+            return vec![];
+        }
+        self.paid_in_coinbase()
+            .filter_map(|(account, script)| {
+                let value = (reward as u128 * account.accrued as u128 / total) as u64;
+                (value > 0 && value >= account.min_output_sats).then(|| TxOut {
+                    value,
+                    script_pubkey: script.clone(),
+                })
+            })
+            .collect()
+    }
+
+    /// The coinbase outputs of `template`, those of the pool then those of the accounts, lowering
+    /// what the template leaves to the first by what the accounts are paid. None without accounts,
+    /// the outputs of the pool staying as they are.
+    pub fn on_new_template(&mut self, template: &mut NewTemplate) -> Option<Vec<TxOut>> {
+        if self.is_empty() {
+            return None;
+        }
+        let paid = self.outputs(template.coinbase_tx_value_remaining);
+        template.coinbase_tx_value_remaining -= paid.iter().map(|output| output.value).sum::<u64>();
+        let outputs = [self.pool_outputs.clone(), paid].concat();
+        if template.future_template {
+            self.future.insert(template.template_id, outputs.clone());
+        }
+        Some(outputs)
+    }
+
+    /// The outputs of the future template `template_id` a new prev hash activates, for the jobs
+    /// of the channels opened after to pay the same as the template.
+    pub fn on_new_prev_hash(&mut self, template_id: u64) -> Option<Vec<TxOut>> {
+        let outputs = self.future.remove(&template_id);
+        self.future.clear();
+        outputs
+    }
+
+    /// Settles the accounts `coinbase` paid, returning what they were paid, and starts the work
+    /// of the pool over.
+    pub fn on_block_found(&mut self, coinbase: &[u8]) -> u64 {
+        self.pool_accrued = 0;
+        // unreadable coinbases are reported by `mint::rounds::coinbase_reward`
+        let Ok(coinbase) = deserialize::<Transaction>(coinbase) else {
+            return 0;
+        };
+        let mut paid = 0;
+        for output in coinbase.output.iter().filter(|output| output.value > 0) {
+            let mut paying = self
+                .accounts
+                .iter_mut()
+                .filter(|account| account.script.as_ref() == Some(&output.script_pubkey))
+                .peekable();
+            if paying.peek().is_some() {
+                paid += output.value;
+            }
+            for account in paying {
+                info!(
+                    "Account {} paid {} sat in the coinbase",
+                    account.name, output.value
+                );
+                account.accrued = 0;
+            }
+        }
+        paid
+    }
+
+    /// Bytes the outputs of the pool and of the accounts may add to the coinbase.
+    pub fn coinbase_size(&self) -> u32 {
+        let scripts = self
+            .pool_outputs
+            .iter()
+            .map(|output| &output.script_pubkey)
+            .chain(self.paid_in_coinbase().map(|(_, script)| script));
+        // 8 bytes of value, then the script after its length
+        scripts
+            .map(|script| 8 + varint_size(script.len()) + script.len())
+            .sum::<usize>() as u32
+    }
+}
+
+impl Snapshot for Mutex<Accounts> {
+    fn save(&self) -> Value {
+        let accrued = self.safe_lock(|accounts| Accrued {
+            pool: accounts.pool_accrued,
+            accounts: accounts
+                .accounts
+                .iter()
+                .map(|account| (account.name.clone(), account.accrued))
+                .collect(),
+        });
+        accrued
+            .ok()
+            .and_then(|accrued| serde_json::to_value(accrued).ok())
+            .unwrap_or_default()
+    }
+
+    /// Restores the work of the accounts still configured.
+    fn restore(&self, state: Value) -> Result<(), String> {
+        let accrued: Accrued = serde_json::from_value(state).map_err(|e| e.to_string())?;
+        self.safe_lock(|accounts| {
+            accounts.pool_accrued = accrued.pool;
+            for account in accounts.accounts.iter_mut() {
+                if let Some(work) = accrued.accounts.get(&account.name) {
+                    account.accrued = *work;
+                }
+            }
+        })
+        .map_err(|e| e.to_string())
+    }
+}
+
+fn varint_size(n: usize) -> usize {
+    match n {
+        0..=0xfc => 1,
+        0xfd..=0xffff => 3,
+        _ => 5,
+    }
+}
+
+/// The P2WPKH script paying the key `config` derives from its xpub.
+fn script(config: &PoolAccountConfig) -> PoolResult<Script> {
+    let invalid = |e: String| PoolError::Custom(format!("account {}: {}", config.name, e));
+    let xpub = config
+        .xpub
+        .as_deref()
+        .ok_or_else(|| invalid("no xpub to pay in the coinbase".to_string()))?;
+    let xpub = validate_xpub(xpub).map_err(invalid)?;
+    let key = derive_child_public_key(&xpub, &config.derivation_path)
+        .map_err(|e| invalid(e.to_string()))?;
+    let output = CoinbaseOutput::new("P2WPKH".to_string(), key.to_pub().inner.to_string());
+    let output: Output = (&output).try_into()?;
+    Ok(output.try_into()?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use stratum_common::bitcoin::{consensus::serialize, PackedLockTime, TxIn};
+
+    /// Master key of the first BIP32 test vector.
+    const XPUB: &str = concat!(
+        "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhe",
+        "PY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8"
+    );
+
+    fn account(name: &str, users: &[&str], key: u8, min_output_sats: u64) -> Account {
+        Account {
+            name: name.to_string(),
+            users: users.iter().map(|user| user.to_string()).collect(),
+            script: (key > 0).then(|| Script::from([&[0x00, 0x14][..], &[key; 20]].concat())),
+            min_output_sats,
+            accrued: 0,
+        }
+    }
+
+    #[test]
+    fn splits_the_reward_by_accrued_work() {
+        let mut accounts = Accounts {
+            accounts: vec![
+                // paid through the mint, its users are none of the accounts after it
+                account("solo", &["acme.solo"], 0, 0),
+                account("acme", &["acme.*"], 1, 100),
+                account("beta", &["beta"], 2, 250),
+            ],
+            ..Accounts::default()
+        };
+        assert_eq!(accounts.account_of("acme.s9"), Some("acme".to_string()));
+        assert_eq!(accounts.account_of("acme.solo"), None);
+        assert_eq!(accounts.account_of("beta.s9"), None);
+
+        accounts.accrue(None, 50);
+        accounts.accrue(Some("acme"), 30);
+        accounts.accrue(Some("beta"), 20);
+        let values = |outputs: Vec<TxOut>| -> Vec<u64> {
+            outputs.iter().map(|output| output.value).collect()
+        };
+        // beta's part is below its smallest output
+        assert_eq!(values(accounts.outputs(1000)), vec![300]);
+        assert_eq!(values(accounts.outputs(1250)), vec![375, 250]);
+        assert_eq!(values(accounts.outputs(1)), Vec::<u64>::new());
+
+        // a block paying acme only, beta's work accrues on
+        let coinbase = Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: vec![TxIn::default()],
+            output: [accounts.pool_outputs.clone(), accounts.outputs(1000)].concat(),
+        };
+        assert_eq!(accounts.on_block_found(&serialize(&coinbase)), 300);
+        accounts.accrue(None, 20);
+        assert_eq!(values(accounts.outputs(1000)), vec![500]);
+    }
+
+    #[test]
+    fn derives_the_output_of_an_account_from_its_xpub() {
+        let mut config = PoolAccountConfig {
+            name: "acme".to_string(),
+            users: vec!["acme.*".to_string()],
+            payout: PayoutPolicy::Coinbase,
+            xpub: Some(XPUB.to_string()),
+            derivation_path: "m/0/0".to_string(),
+            min_output_sats: 546,
+        };
+        let accounts = Accounts::new(vec![], &[config.clone()]).unwrap();
+        let script = accounts.accounts[0].script.as_ref().unwrap();
+        assert!(script.is_v0_p2wpkh());
+        // 8 bytes of value, 1 of length, 22 of script
+        assert_eq!(accounts.coinbase_size(), 31);
+
+        config.xpub = None;
+        assert!(Accounts::new(vec![], &[config.clone()]).is_err());
+        config.payout = PayoutPolicy::Mint;
+        assert!(Accounts::new(vec![], &[config]).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,538 @@
+//! Noise certificate of the pool and rotation of its authority key. In the handshake of every
+//! connection the pool hands a certificate signed by its authority key, which the miner checks
+//! against the key it trusts, only then. Rather than a certificate valid `cert_validity_sec` from
+//! every handshake, the pool signs one expiring at a set time, re-signed on schedule
+//! `cert_renew_before_sec` before it expires for `cert_validity_sec` more. Miners connecting are
+//! never handed a certificate about to expire, and those connected mine on as it isn't checked
+//! past the handshake.
+//!
+//! `authority_rotation` rotates the authority key at `rotate_at`: the certificate is re-signed by
+//! the next key then, whatever its expiry. Connections open stay open, those made from then on
+//! are handed the certificate of the next key. Miners and proxies trust both keys over the
+//! rotation so none is refused, see `upstream_next_authority_pubkey` of the translator, which
+//! trusts the next key of the pool of the process by itself. Once rotated, the rotation can stay
+//! configured until the next key replaces the authority key of the configuration.
+//!
+//! A mint seeded by the authority key (see `crate::pool_mint::mint::seed`) keeps the key it was
+//! seeded by: the next key doesn't derive its master secret and is refused once it replaces it.
+//!
+//! Certificates are signed through an [`AuthoritySigner`]: a [`KeySigner`] of
+//! `authority_secret_key` held in memory, or, with `[authority_signer]` configured instead, a
+//! [`SocketSigner`] asking a signing daemon (e.g. one in front of a PKCS#11 token), so the key
+//! is never in the process. A mint can't be seeded by a key the daemon holds. Each certificate
+//! certifies a static key generated for it and is handed in every handshake until the next
+//! renewal, so the signer is only asked on renewal; `noise_sv2` is patched to take it (see
+//! `vendor/noise_sv2`). The pool doesn't start if the first certificate can't be signed. A
+//! renewal that fails is retried, and once the certificate expires miners refuse the handshake.
+use super::{Pool, PoolConfiguration};
+use crate::{
+    error::{PoolError, PoolResult},
+    retry::{Retry, RetryPolicy},
+    sim,
+};
+use async_trait::async_trait;
+use codec_sv2::Responder;
+use key_utils::{Secp256k1PublicKey, Secp256k1SecretKey};
+use roles_logic_sv2::utils::Mutex;
+use secp256k1::{schnorr::Signature, Keypair, Message, Secp256k1};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{fmt::Debug, sync::Arc, time::Duration};
+#[cfg(unix)]
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::UnixStream,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+/// How long a renewal that failed waits before it is tried again.
+const RETRY_RENEWAL_SECS: u64 = 30;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct AuthorityRotationConfig {
+    pub next_public_key: Secp256k1PublicKey,
+    /// Signed by `[authority_signer]` if unset.
+    #[serde(default)]
+    pub next_secret_key: Option<Secp256k1SecretKey>,
+    /// Unix time the certificate is signed by the next key from.
+    pub rotate_at: u64,
+}
+
+/// Signing daemon holding the authority key, see [`SocketSigner`].
+#[derive(Debug, Deserialize, Clone)]
+pub struct AuthoritySignerConfig {
+    /// Unix socket the daemon listens on.
+    pub socket_path: String,
+    /// How connecting to the daemon is retried, see `crate::retry`.
+    #[serde(default)]
+    pub retry: RetryPolicy,
+}
+
+/// Signs the certificates handed in handshakes with an authority key.
+#[async_trait]
+pub trait AuthoritySigner: Debug + Send + Sync {
+    /// Key the signatures are made by.
+    fn public_key(&self) -> Secp256k1PublicKey;
+
+    /// BIP340 signature of `digest`, the SHA-256 of a certificate.
+    async fn sign(&self, digest: [u8; 32]) -> PoolResult<Signature>;
+}
+
+/// Signer of an authority key held in memory.
+#[derive(Debug, Clone)]
+pub struct KeySigner {
+    public_key: Secp256k1PublicKey,
+    secret_key: Secp256k1SecretKey,
+}
+
+impl KeySigner {
+    pub fn new(public_key: Secp256k1PublicKey, secret_key: Secp256k1SecretKey) -> Self {
+        Self {
+            public_key,
+            secret_key,
+        }
+    }
+}
+
+#[async_trait]
+impl AuthoritySigner for KeySigner {
+    fn public_key(&self) -> Secp256k1PublicKey {
+        self.public_key
+    }
+
+    async fn sign(&self, digest: [u8; 32]) -> PoolResult<Signature> {
+        let secp = Secp256k1::signing_only();
+        let keypair = Keypair::from_secret_key(&secp, &self.secret_key.0);
+        Ok(secp.sign_schnorr(&Message::from_digest(digest), &keypair))
+    }
+}
+
+/// Signer of a key held by a signing daemon, asked over its unix socket. Every signature is a
+/// connection of its own sending one JSON line, `{"public_key": "<key>", "digest": "<hex>"}`,
+/// the key in the base58 of the configuration, and reading one back, `{"signature": "<hex>"}`
+/// or `{"error": "<why>"}`. There are no unix sockets on Windows, a pool there keeps the key in
+/// memory.
+#[derive(Debug, Clone)]
+pub struct SocketSigner {
+    public_key: Secp256k1PublicKey,
+    socket_path: String,
+    #[cfg_attr(not(unix), allow(dead_code))]
+    retry: Retry,
+}
+
+#[derive(Debug, Serialize)]
+struct SignRequest {
+    public_key: String,
+    digest: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct SignResponse {
+    signature: Option<String>,
+    error: Option<String>,
+}
+
+impl SocketSigner {
+    pub fn new(public_key: Secp256k1PublicKey, config: &AuthoritySignerConfig) -> Self {
+        Self {
+            public_key,
+            socket_path: config.socket_path.clone(),
+            retry: Retry::new("authority signer", &config.retry),
+        }
+    }
+
+    fn failed(&self, why: impl std::fmt::Display) -> PoolError {
+        PoolError::Custom(format!("signer at {} failed: {}", self.socket_path, why))
+    }
+}
+
+#[async_trait]
+impl AuthoritySigner for SocketSigner {
+    fn public_key(&self) -> Secp256k1PublicKey {
+        self.public_key
+    }
+
+    #[cfg(unix)]
+    async fn sign(&self, digest: [u8; 32]) -> PoolResult<Signature> {
+        let stream = self
+            .retry
+            .run(|| UnixStream::connect(&self.socket_path))
+            .await
+            .map_err(|e| self.failed(e))?;
+        let (reader, mut writer) = stream.into_split();
+        let request = SignRequest {
+            public_key: self.public_key.to_string(),
+            digest: hex::encode(digest),
+        };
+        let mut line = serde_json::to_string(&request).map_err(|e| self.failed(e))?;
+        line.push('\n');
+        writer.write_all(line.as_bytes()).await?;
+        let mut answer = String::new();
+        BufReader::new(reader).read_line(&mut answer).await?;
+        let response: SignResponse = serde_json::from_str(&answer).map_err(|e| self.failed(e))?;
+        match (response.signature, response.error) {
+            (Some(signature), _) => signature.parse().map_err(|e| self.failed(e)),
+            (None, Some(error)) => Err(self.failed(error)),
+            (None, None) => Err(self.failed("empty answer")),
+        }
+    }
+
+    #[cfg(not(unix))]
+    async fn sign(&self, _digest: [u8; 32]) -> PoolResult<Signature> {
+        Err(self.failed("unix sockets are not supported on this platform"))
+    }
+}
+
+/// Signer of `public_key`: the daemon of `signer` if configured, or else `secret_key`.
+fn signer_of(
+    public_key: Secp256k1PublicKey,
+    secret_key: Option<Secp256k1SecretKey>,
+    signer: Option<&AuthoritySignerConfig>,
+) -> PoolResult<Arc<dyn AuthoritySigner>> {
+    match (secret_key, signer) {
+        (None, Some(config)) => Ok(Arc::new(SocketSigner::new(public_key, config))),
+        (Some(secret_key), None) => Ok(Arc::new(KeySigner::new(public_key, secret_key))),
+        _ => Err(PoolError::Custom(format!(
+            "authority key {} takes either its secret key or [authority_signer]",
+            public_key
+        ))),
+    }
+}
+
+/// A static key of the pool and the certificate of the authority over it.
+#[derive(Debug, Clone)]
+struct Certificate {
+    static_key: Keypair,
+    /// Signature noise message of `noise_sv2`: version, validity and signature.
+    message: [u8; 74],
+}
+
+/// Next key of a rotation and when it takes over.
+#[derive(Debug, Clone)]
+struct Rotation {
+    signer: Arc<dyn AuthoritySigner>,
+    rotate_at: u64,
+}
+
+/// Key signing the certificate of the pool and its expiry, see the module.
+#[derive(Debug, Clone)]
+pub struct Authority {
+    signer: Arc<dyn AuthoritySigner>,
+    rotation: Option<Rotation>,
+    validity_secs: u64,
+    renew_before_secs: u64,
+    not_valid_after: u64,
+    certificate: Option<Certificate>,
+}
+
+impl Authority {
+    /// The authority of `config`, its certificate signed at `now`.
+    pub async fn new(config: &PoolConfiguration, now: u64) -> PoolResult<Self> {
+        let signer = config.authority_signer.as_ref();
+        let rotation = match &config.authority_rotation {
+            Some(rotation) => Some(Rotation {
+                signer: signer_of(rotation.next_public_key, rotation.next_secret_key, signer)?,
+                rotate_at: rotation.rotate_at,
+            }),
+            None => None,
+        };
+        let signer = signer_of(
+            config.authority_public_key,
+            config.authority_secret_key,
+            signer,
+        )?;
+        Self::with_signer(config, signer, rotation, now).await
+    }
+
+    async fn with_signer(
+        config: &PoolConfiguration,
+        signer: Arc<dyn AuthoritySigner>,
+        rotation: Option<Rotation>,
+        now: u64,
+    ) -> PoolResult<Self> {
+        let validity_secs = config.cert_validity_sec.max(1);
+        let renew_before_secs = config
+            .cert_renew_before_sec
+            .unwrap_or(validity_secs / 10)
+            // renewed once half of its validity passed at the earliest
+            .min(validity_secs / 2);
+        let mut authority = Self {
+            signer,
+            rotation,
+            validity_secs,
+            renew_before_secs,
+            not_valid_after: 0,
+            certificate: None,
+        };
+        authority.renew(now).await?;
+        Ok(authority)
+    }
+
+    /// Key the certificate is signed by.
+    pub fn public_key(&self) -> Secp256k1PublicKey {
+        self.signer.public_key()
+    }
+
+    /// Unix time the certificate expires at.
+    pub fn not_valid_after(&self) -> u64 {
+        self.not_valid_after
+    }
+
+    /// Re-signs the certificate if it expires within `cert_renew_before_sec` of `now`, by the
+    /// next key from `rotate_at` on. Whether it was re-signed, and rotated. Left as it was if
+    /// the signer fails.
+    pub async fn renew(&mut self, now: u64) -> PoolResult<Renewal> {
+        let (signer, renewal) = match &self.rotation {
+            Some(rotation) if now >= rotation.rotate_at => {
+                (rotation.signer.clone(), Renewal::Rotated)
+            }
+            _ if now + self.renew_before_secs < self.not_valid_after => return Ok(Renewal::None),
+            _ => (self.signer.clone(), Renewal::Renewed),
+        };
+        let not_valid_after = now + self.validity_secs;
+        self.certificate = Some(certify(signer.as_ref(), now, not_valid_after).await?);
+        self.not_valid_after = not_valid_after;
+        if renewal == Renewal::Rotated {
+            self.signer = signer;
+            self.rotation = None;
+        }
+        Ok(renewal)
+    }
+
+    /// Seconds from `now` until the certificate is to be renewed or the key rotated.
+    pub fn renews_in(&self, now: u64) -> u64 {
+        let renewal = self.not_valid_after - self.renew_before_secs;
+        let due = match &self.rotation {
+            Some(rotation) => renewal.min(rotation.rotate_at),
+            None => renewal,
+        };
+        due.saturating_sub(now).max(1)
+    }
+
+    /// Responder of a handshake, handing the certificate.
+    pub fn responder(&self) -> Box<Responder> {
+        match &self.certificate {
+            Some(certificate) => {
+                Responder::from_certificate(certificate.static_key, certificate.message)
+            }
+            // an authority is only made with a certificate signed
+            None => unreachable!("authority without a certificate"),
+        }
+    }
+}
+
+/// A certificate by `signer` valid from `valid_from` until `not_valid_after`, over a static key
+/// generated for it. `noise_sv2` signs `SHA-256(version || valid_from || not_valid_after ||
+/// static key)`, the version 0 and the times in 2 and 4 bytes little endian, and hands the
+/// signature after the first 10 bytes.
+async fn certify(
+    signer: &dyn AuthoritySigner,
+    valid_from: u64,
+    not_valid_after: u64,
+) -> PoolResult<Certificate> {
+    let static_key = Responder::generate_static_key();
+    let mut message = [0; 74];
+    message[2..6].copy_from_slice(&(valid_from as u32).to_le_bytes());
+    message[6..10].copy_from_slice(&(not_valid_after as u32).to_le_bytes());
+    let digest: [u8; 32] = Sha256::new()
+        .chain_update(&message[..10])
+        .chain_update(static_key.x_only_public_key().0.serialize())
+        .finalize()
+        .into();
+    let signature = signer.sign(digest).await?;
+    // a signer answering for another key would have every miner refuse the handshake
+    Secp256k1::verification_only()
+        .verify_schnorr(
+            &signature,
+            &Message::from_digest(digest),
+            &signer.public_key().0,
+        )
+        .map_err(|e| {
+            PoolError::Custom(format!(
+                "certificate signature isn't one of {}: {}",
+                signer.public_key(),
+                e
+            ))
+        })?;
+    message[10..].copy_from_slice(signature.as_ref());
+    Ok(Certificate {
+        static_key,
+        message,
+    })
+}
+
+/// What `Authority::renew` did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Renewal {
+    None,
+    Renewed,
+    Rotated,
+}
+
+/// Renews the certificate of `pool` on schedule until `cancel_token` is cancelled. The
+/// certificate is signed out of the lock, on a copy of the authority put back once signed.
+pub async fn run(pool: Arc<Mutex<Pool>>, cancel_token: CancellationToken) {
+    let mut renews_in = None;
+    loop {
+        let mut authority = match pool.safe_lock(|p| p.authority.clone()) {
+            Ok(authority) => authority,
+            Err(e) => {
+                error!("Pool: can't schedule the certificate renewal: {}", e);
+                return;
+            }
+        };
+        let sleep = renews_in.unwrap_or_else(|| authority.renews_in(sim::unix_secs()));
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_secs(sleep)) => {}
+            _ = cancel_token.cancelled() => return,
+        }
+        renews_in = None;
+        let renewal = match authority.renew(sim::unix_secs()).await {
+            Ok(Renewal::None) => continue,
+            Ok(renewal) => renewal,
+            Err(e) => {
+                error!(
+                    "Pool: can't renew the certificate, valid until {}, retried in {}s: {}",
+                    authority.not_valid_after(),
+                    RETRY_RENEWAL_SECS,
+                    e
+                );
+                renews_in = Some(RETRY_RENEWAL_SECS);
+                continue;
+            }
+        };
+        // only renewed here, so the authority in the pool is still the one copied
+        if let Err(e) = pool.safe_lock(|p| p.authority = authority.clone()) {
+            error!("Pool: can't renew the certificate: {}", e);
+            return;
+        }
+        match renewal {
+            Renewal::Rotated => warn!(
+                "Pool: authority key rotated to {}, miners connecting have to trust it",
+                authority.public_key()
+            ),
+            _ => info!(
+                "Pool: noise certificate re-signed by {}, valid until {}",
+                authority.public_key(),
+                authority.not_valid_after()
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::configuration::create_default_pool_config;
+    use codec_sv2::Initiator;
+    use std::str::FromStr;
+
+    #[tokio::test]
+    async fn renews_before_expiry_and_rotates_on_schedule() {
+        let mut config = create_default_pool_config();
+        config.cert_validity_sec = 1000;
+        config.cert_renew_before_sec = Some(100);
+        let next_secret_key =
+            Secp256k1SecretKey(secp256k1::SecretKey::new(&mut rand::thread_rng()));
+        let next_public_key = Secp256k1PublicKey::from(next_secret_key);
+        config.authority_rotation = Some(AuthorityRotationConfig {
+            next_public_key,
+            next_secret_key: Some(next_secret_key),
+            rotate_at: 10_000,
+        });
+        let mut authority = Authority::new(&config, 0).await.unwrap();
+        assert_eq!(authority.not_valid_after(), 1000);
+        assert_eq!(authority.renews_in(0), 900);
+        assert_eq!(authority.renew(899).await.unwrap(), Renewal::None);
+        assert_eq!(authority.renew(900).await.unwrap(), Renewal::Renewed);
+        assert_eq!(authority.not_valid_after(), 1900);
+
+        // the rotation comes before the certificate expires
+        authority.renew(9500).await.unwrap();
+        assert_eq!(authority.renews_in(9500), 500);
+        assert_eq!(authority.renew(10_000).await.unwrap(), Renewal::Rotated);
+        assert_eq!(
+            authority.public_key().to_string(),
+            next_public_key.to_string()
+        );
+        assert_eq!(authority.not_valid_after(), 11_000);
+        assert_eq!(authority.renews_in(10_000), 900);
+    }
+
+    #[tokio::test]
+    async fn keeps_the_certificate_a_signer_fails_to_renew() {
+        let now = sim::unix_secs();
+        let mut config = create_default_pool_config();
+        config.cert_validity_sec = 1000;
+        // the secret key isn't the one of the next key
+        config.authority_rotation = Some(AuthorityRotationConfig {
+            next_public_key: Secp256k1PublicKey::from_str(
+                "9cYw69qALcFQBiJxivVeG8SyGhSN3wY7Eczw2M84TFpiqgD4kyZ",
+            )
+            .unwrap(),
+            next_secret_key: config.authority_secret_key,
+            rotate_at: now + 500,
+        });
+        let mut authority = Authority::new(&config, now).await.unwrap();
+        assert!(authority.renew(now + 500).await.is_err());
+        assert_eq!(
+            authority.public_key().to_string(),
+            config.authority_public_key.to_string()
+        );
+        assert_eq!(authority.not_valid_after(), now + 1000);
+        assert!(handshake(
+            &mut authority.responder(),
+            config.authority_public_key
+        ));
+    }
+
+    /// Whether a miner trusting `authority_key` completes a handshake with `responder`.
+    fn handshake(responder: &mut Responder, authority_key: Secp256k1PublicKey) -> bool {
+        let mut initiator = Initiator::from_raw_k(authority_key.into_bytes()).unwrap();
+        let first_message = initiator.step_0().unwrap();
+        let (second_message, _) = responder.step_1(first_message).unwrap();
+        initiator.step_2(second_message).is_ok()
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn signs_the_certificate_with_a_signing_daemon() {
+        let mut config = create_default_pool_config();
+        let secret_key = config.authority_secret_key.take().unwrap();
+        let dir = std::env::temp_dir().join(format!("potato-signer-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let socket_path = dir.join("signer.sock").to_str().unwrap().to_string();
+        let listener = tokio::net::UnixListener::bind(&socket_path).unwrap();
+        let public_key = config.authority_public_key;
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let (reader, mut writer) = stream.into_split();
+                let mut line = String::new();
+                BufReader::new(reader).read_line(&mut line).await.unwrap();
+                let request: serde_json::Value = serde_json::from_str(&line).unwrap();
+                assert_eq!(request["public_key"], public_key.to_string());
+                let digest = hex::decode(request["digest"].as_str().unwrap()).unwrap();
+                let signature = KeySigner::new(public_key, secret_key)
+                    .sign(digest.try_into().unwrap())
+                    .await
+                    .unwrap();
+                let answer = format!("{{\"signature\": \"{}\"}}\n", signature);
+                writer.write_all(answer.as_bytes()).await.unwrap();
+            }
+        });
+        // the key is in either the configuration or the daemon
+        assert!(Authority::new(&config, sim::unix_secs()).await.is_err());
+        config.authority_signer = Some(AuthoritySignerConfig {
+            socket_path,
+            retry: RetryPolicy::once(),
+        });
+        let authority = Authority::new(&config, sim::unix_secs()).await.unwrap();
+        assert!(handshake(&mut authority.responder(), public_key));
+        let other =
+            Secp256k1PublicKey::from_str("9cYw69qALcFQBiJxivVeG8SyGhSN3wY7Eczw2M84TFpiqgD4kyZ")
+                .unwrap();
+        assert!(!handshake(&mut authority.responder(), other));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
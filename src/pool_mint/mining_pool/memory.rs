@@ -0,0 +1,150 @@
+//! Memory budget of the pool. Memory taken by miners is estimated from what they hold rather than
+//! measured: the codec buffers and the frames queued of every connection, the jobs the channel
+//! factory keeps for every channel and the shares queued in the channels of the share pipeline
+//! (see `crate::status::diagnostics`). Past `raise_difficulty_at` of the budget the pool doubles
+//! the difficulty of every channel on every check, `MAX_DIFFICULTY_RAISES` times at most until
+//! the miner updates its channel, so fewer shares come in. Past `refuse_connections_at` it refuses
+//! new connections too, until the estimate falls back below.
+use crate::status::{diagnostics, events::Pressure};
+use serde::{Deserialize, Serialize};
+
+/// Bytes of the noise codec buffers of a connection, whatever it sends.
+pub const CONNECTION_BYTES: u64 = 64 * 1024;
+/// Bytes of a mining frame queued to or from a connection.
+pub const FRAME_BYTES: u64 = 512;
+/// Bytes of a job kept for a channel, with its coinbase and merkle path, and jobs kept: the
+/// current one and the future one.
+pub const JOB_BYTES: u64 = 2 * 1024;
+pub const JOBS_PER_CHANNEL: u64 = 2;
+/// Bytes of a share queued in the share pipeline.
+pub const SHARE_BYTES: u64 = 256;
+/// Times the difficulty of a channel is doubled before its miner updates the channel.
+pub const MAX_DIFFICULTY_RAISES: u32 = 4;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MemoryBudgetConfig {
+    /// Megabytes the connections, jobs and share queues of the pool may take.
+    pub limit_mb: u64,
+    /// Parts of the budget used past which the difficulty is raised, and new connections refused.
+    #[serde(default = "MemoryBudgetConfig::default_raise_difficulty_at")]
+    pub raise_difficulty_at: f64,
+    #[serde(default = "MemoryBudgetConfig::default_refuse_connections_at")]
+    pub refuse_connections_at: f64,
+    #[serde(default = "MemoryBudgetConfig::default_interval_secs")]
+    pub interval_secs: u64,
+}
+
+impl MemoryBudgetConfig {
+    fn default_raise_difficulty_at() -> f64 {
+        0.8
+    }
+
+    fn default_refuse_connections_at() -> f64 {
+        0.95
+    }
+
+    fn default_interval_secs() -> u64 {
+        5
+    }
+
+    pub fn limit_bytes(&self) -> u64 {
+        self.limit_mb.saturating_mul(1024 * 1024)
+    }
+
+    /// How close `usage` is to the budget.
+    pub fn pressure(&self, usage: &MemoryUsage) -> Pressure {
+        let used = usage.total() as f64 / self.limit_bytes().max(1) as f64;
+        if used >= self.refuse_connections_at {
+            Pressure::Critical
+        } else if used >= self.raise_difficulty_at {
+            Pressure::High
+        } else {
+            Pressure::Normal
+        }
+    }
+}
+
+/// Bytes the pool is estimated to take.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct MemoryUsage {
+    pub connections: u64,
+    pub jobs: u64,
+    pub share_queues: u64,
+}
+
+impl MemoryUsage {
+    /// The estimate for `connections` with `queued_frames` between them and the pool, mining on
+    /// `channels`, and `queued_shares` in the share pipeline.
+    pub fn estimate(
+        connections: u64,
+        queued_frames: u64,
+        channels: u64,
+        queued_shares: u64,
+    ) -> Self {
+        Self {
+            connections: connections * CONNECTION_BYTES + queued_frames * FRAME_BYTES,
+            jobs: channels * JOBS_PER_CHANNEL * JOB_BYTES,
+            share_queues: queued_shares * SHARE_BYTES,
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.connections + self.jobs + self.share_queues
+    }
+}
+
+/// Shares queued in every channel of the share pipeline.
+pub fn queued_shares() -> u64 {
+    diagnostics::channels()
+        .values()
+        .map(|stats| stats.len as u64)
+        .sum()
+}
+
+/// `target` (little endian) of twice the difficulty.
+pub fn harder_target(target: &[u8]) -> Vec<u8> {
+    let mut harder = target.to_vec();
+    let mut carry = 0;
+    // from the most significant byte down
+    for byte in harder.iter_mut().rev() {
+        let shifted = (*byte >> 1) | carry;
+        carry = (*byte & 1) << 7;
+        *byte = shifted;
+    }
+    harder
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pool_mint::mint;
+
+    #[test]
+    fn sheds_load_as_usage_nears_the_budget() {
+        let config = MemoryBudgetConfig {
+            limit_mb: 1,
+            raise_difficulty_at: 0.8,
+            refuse_connections_at: 0.95,
+            interval_secs: 5,
+        };
+        let usage = MemoryUsage::estimate(12, 100, 20, 0);
+        assert_eq!(usage.total(), 12 * 65_536 + 100 * 512 + 20 * 2 * 2048);
+        assert_eq!(config.pressure(&usage), Pressure::High);
+        assert_eq!(
+            config.pressure(&MemoryUsage::estimate(1, 0, 1, 0)),
+            Pressure::Normal
+        );
+        assert_eq!(
+            config.pressure(&MemoryUsage::estimate(16, 0, 0, 0)),
+            Pressure::Critical
+        );
+
+        // difficulty 1024
+        let mut target = vec![0xff; 32];
+        target[26..].copy_from_slice(&[0x3f, 0, 0, 0, 0, 0]);
+        assert_eq!(mint::share_weight(&target), 1024);
+        let harder = harder_target(&target);
+        assert_eq!(&harder[25..28], &[0xff, 0x1f, 0]);
+        assert_eq!(mint::share_weight(&harder), 2048);
+    }
+}
@@ -1,4 +1,8 @@
 use super::super::mining_pool::Downstream;
+use crate::{
+    logging,
+    status::events::{self, Event},
+};
 use roles_logic_sv2::{
     errors::Error,
     handlers::mining::{ParseDownstreamMiningMessages, SendTo, SupportedChannelTypes},
@@ -10,7 +14,7 @@ use roles_logic_sv2::{
     utils::Mutex,
 };
 use std::{convert::TryInto, sync::Arc};
-use tracing::error;
+use tracing::{error, info_span};
 
 impl ParseDownstreamMiningMessages<(), NullDownstreamMiningSelector, NoRouting> for Downstream {
     fn get_channel_type(&self) -> SupportedChannelTypes {
@@ -34,6 +38,10 @@ impl ParseDownstreamMiningMessages<(), NullDownstreamMiningSelector, NoRouting>
         incoming: OpenStandardMiningChannel,
         _m: Option<Arc<Mutex<()>>>,
     ) -> Result<SendTo<()>, Error> {
+        let account = String::from_utf8_lossy(incoming.user_identity.inner_as_ref()).to_string();
+        if !self.limiter.allow_identity(&account) {
+            return Ok(rate_limited(incoming.request_id.as_u32()));
+        }
         let header_only = self.downstream_data.header_only;
         let reposnses = self
             .channel_factory
@@ -57,6 +65,13 @@ impl ParseDownstreamMiningMessages<(), NullDownstreamMiningSelector, NoRouting>
             .map_err(|e| roles_logic_sv2::Error::PoisonLock(e.to_string()))??;
         let mut result = vec![];
         for response in reposnses {
+            if let Mining::OpenStandardMiningChannelSuccess(success) = &response {
+                self.set_channel_account(
+                    success.channel_id,
+                    account.clone(),
+                    success.target.inner_as_ref(),
+                );
+            }
             result.push(SendTo::Respond(response.into_static()))
         }
         Ok(SendTo::Multiple(result))
@@ -69,12 +84,25 @@ impl ParseDownstreamMiningMessages<(), NullDownstreamMiningSelector, NoRouting>
         let request_id = m.request_id;
         let hash_rate = m.nominal_hash_rate;
         let min_extranonce_size = m.min_extranonce_size;
+        let account = String::from_utf8_lossy(m.user_identity.inner_as_ref()).to_string();
+        if !self.limiter.allow_identity(&account) {
+            return Ok(rate_limited(request_id));
+        }
         let messages_res = self
             .channel_factory
             .safe_lock(|s| s.new_extended_channel(request_id, hash_rate, min_extranonce_size))
             .map_err(|e| roles_logic_sv2::Error::PoisonLock(e.to_string()))?;
         match messages_res {
             Ok(messages) => {
+                for message in &messages {
+                    if let Mining::OpenExtendedMiningChannelSuccess(success) = message {
+                        self.set_channel_account(
+                            success.channel_id,
+                            account.clone(),
+                            success.target.inner_as_ref(),
+                        );
+                    }
+                }
                 let messages = messages.into_iter().map(SendTo::Respond).collect();
                 Ok(SendTo::Multiple(messages))
             }
@@ -90,6 +118,7 @@ impl ParseDownstreamMiningMessages<(), NullDownstreamMiningSelector, NoRouting>
             .unwrap_or_else(|_| {
                 std::process::exit(1);
             });
+        self.set_channel_target(m.channel_id, maximum_target.inner_as_ref());
         let set_target = SetTarget {
             channel_id: m.channel_id,
             maximum_target,
@@ -108,11 +137,18 @@ impl ParseDownstreamMiningMessages<(), NullDownstreamMiningSelector, NoRouting>
         match res {
             Ok(res) => match res  {
                 roles_logic_sv2::channel_logic::channel_factory::OnNewShare::SendErrorDownstream(m) => {
+                    events::publish(Event::ShareRejected {
+                        channel_id: m.channel_id,
+                        reason: String::from_utf8_lossy(&m.error_code.to_vec()).into_owned(),
+                    });
                     Ok(SendTo::Respond(Mining::SubmitSharesError(m)))
                 }
                 roles_logic_sv2::channel_logic::channel_factory::OnNewShare::SendSubmitShareUpstream(_) => unreachable!(),
                 roles_logic_sv2::channel_logic::channel_factory::OnNewShare::RelaySubmitShareUpstream => unreachable!(),
                 roles_logic_sv2::channel_logic::channel_factory::OnNewShare::ShareMeetBitcoinTarget((share,t_id,coinbase,_)) => {
+                    // credit the share before the block closes its round
+                    let new_shares_sum = self.credit_share(m.channel_id);
+                    self.record_found_block(m.channel_id, &coinbase);
                     if let Some(template_id) = t_id {
                         let solution = SubmitSolution {
                             template_id,
@@ -128,7 +164,7 @@ impl ParseDownstreamMiningMessages<(), NullDownstreamMiningSelector, NoRouting>
                         channel_id: m.channel_id,
                         last_sequence_number: m.sequence_number,
                         new_submits_accepted_count: 1,
-                        new_shares_sum: 0,
+                        new_shares_sum,
                     };
 
                     Ok(SendTo::Respond(Mining::SubmitSharesSuccess(success)))
@@ -139,7 +175,7 @@ impl ParseDownstreamMiningMessages<(), NullDownstreamMiningSelector, NoRouting>
                         channel_id: m.channel_id,
                         last_sequence_number: m.sequence_number,
                         new_submits_accepted_count: 1,
-                        new_shares_sum: 0,
+                        new_shares_sum: self.credit_share(m.channel_id),
                     };
                     Ok(SendTo::Respond(Mining::SubmitSharesSuccess(success)))
                 },
@@ -152,6 +188,12 @@ impl ParseDownstreamMiningMessages<(), NullDownstreamMiningSelector, NoRouting>
         &mut self,
         m: SubmitSharesExtended,
     ) -> Result<SendTo<()>, Error> {
+        let _span = info_span!(
+            "validate_share",
+            channel_id = m.channel_id,
+            share_id = %logging::share_id(m.nonce, m.ntime)
+        )
+        .entered();
         let res = self
             .channel_factory
             .safe_lock(|cf| cf.on_submit_shares_extended(m.clone()))
@@ -159,11 +201,18 @@ impl ParseDownstreamMiningMessages<(), NullDownstreamMiningSelector, NoRouting>
         match res {
             Ok(res) => match res  {
                 roles_logic_sv2::channel_logic::channel_factory::OnNewShare::SendErrorDownstream(m) => {
+                    events::publish(Event::ShareRejected {
+                        channel_id: m.channel_id,
+                        reason: String::from_utf8_lossy(&m.error_code.to_vec()).into_owned(),
+                    });
                     Ok(SendTo::Respond(Mining::SubmitSharesError(m)))
                 }
                 roles_logic_sv2::channel_logic::channel_factory::OnNewShare::SendSubmitShareUpstream(_) => unreachable!(),
                 roles_logic_sv2::channel_logic::channel_factory::OnNewShare::RelaySubmitShareUpstream => unreachable!(),
                 roles_logic_sv2::channel_logic::channel_factory::OnNewShare::ShareMeetBitcoinTarget((share,t_id,coinbase,_)) => {
+                    // credit the share before the block closes its round
+                    let new_shares_sum = self.credit_share(m.channel_id);
+                    self.record_found_block(m.channel_id, &coinbase);
                     if let Some(template_id) = t_id {
                         let solution = SubmitSolution {
                             template_id,
@@ -179,7 +228,7 @@ impl ParseDownstreamMiningMessages<(), NullDownstreamMiningSelector, NoRouting>
                         channel_id: m.channel_id,
                         last_sequence_number: m.sequence_number,
                         new_submits_accepted_count: 1,
-                        new_shares_sum: 0,
+                        new_shares_sum,
                     };
 
                     Ok(SendTo::Respond(Mining::SubmitSharesSuccess(success)))
@@ -190,7 +239,7 @@ impl ParseDownstreamMiningMessages<(), NullDownstreamMiningSelector, NoRouting>
                         channel_id: m.channel_id,
                         last_sequence_number: m.sequence_number,
                         new_submits_accepted_count: 1,
-                        new_shares_sum: 0,
+                        new_shares_sum: self.credit_share(m.channel_id),
                     };
                     Ok(SendTo::Respond(Mining::SubmitSharesSuccess(success)))
                 },
@@ -214,3 +263,12 @@ impl ParseDownstreamMiningMessages<(), NullDownstreamMiningSelector, NoRouting>
         Ok(SendTo::Respond(Mining::SetCustomMiningJobSuccess(m)))
     }
 }
+
+/// Refuses the channel of `request_id`, its user identity past its rate limit.
+fn rate_limited(request_id: u32) -> SendTo<()> {
+    let error = OpenMiningChannelError {
+        request_id,
+        error_code: "rate-limited".to_string().into_bytes().try_into().unwrap(),
+    };
+    SendTo::Respond(Mining::OpenMiningChannelError(error))
+}
@@ -1,10 +1,38 @@
 use crate::{
     error::{PoolError, PoolResult},
-    status,
+    grpc::GrpcConfig,
+    handoff,
+    pool_mint::{
+        maturity::BitcoinRpcConfig,
+        mint::{
+            self,
+            backend::MintBackend,
+            payout::{self, PayoutConfig, PayoutMode, MESSAGE_TYPE_PAYOUT, PAYOUT_EXTENSION_TYPE},
+            MintConfig,
+        },
+    },
+    port_mapping::PortMappingConfig,
+    ratelimit::{RateLimiter, RateLimitsConfig},
+    retry::RetryPolicy,
+    sharded::ShardedMap,
+    snapshot::{self, SnapshotConfig},
+    status::{
+        self,
+        alerts::AlertConfig,
+        audit::{self, AuditConfig, AuditEvent},
+        diagnostics::ChannelProbe,
+        events::{self, Event, Listener, Pressure},
+        heartbeat::Heartbeat,
+        history::HistoryConfig,
+        hooks::CommandHookConfig,
+        nostr::NostrStatusConfig,
+        statsd::StatsdConfig,
+        tip::TipLagConfig,
+    },
 };
 use async_channel::{Receiver, Sender};
 use binary_sv2::U256;
-use codec_sv2::{HandshakeRole, Responder, StandardEitherFrame, StandardSv2Frame};
+use codec_sv2::{HandshakeRole, StandardEitherFrame, StandardSv2Frame};
 use error_handling::handle_result;
 use key_utils::{Secp256k1PublicKey, Secp256k1SecretKey, SignatureService};
 use network_helpers_sv2::noise_connection_tokio::Connection;
@@ -15,7 +43,7 @@ use roles_logic_sv2::{
     errors::Error,
     handlers::mining::{ParseDownstreamMiningMessages, SendTo},
     job_creator::JobsCreators,
-    mining_sv2::{ExtendedExtranonce, SetNewPrevHash as SetNPH},
+    mining_sv2::{ExtendedExtranonce, SetNewPrevHash as SetNPH, SetTarget},
     parsers::{Mining, PoolMessages},
     routing_logic::MiningRoutingLogic,
     template_distribution_sv2::{NewTemplate, SetNewPrevHash, SubmitSolution},
@@ -28,20 +56,34 @@ use std::{
     convert::{TryFrom, TryInto},
     net::SocketAddr,
     sync::Arc,
+    time::Duration,
 };
 use stratum_common::bitcoin::{Script, TxOut};
 use tokio::{net::TcpListener, task};
-use tracing::{debug, error, info, warn};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, field, info, info_span, warn, Instrument, Span};
 
 pub mod setup_connection;
 use setup_connection::SetupConnectionHandler;
 
 pub mod message_handler;
 
+pub mod memory;
+use memory::{MemoryBudgetConfig, MemoryUsage, MAX_DIFFICULTY_RAISES};
+
+pub mod accounts;
+use accounts::{Accounts, PoolAccountConfig};
+
+pub mod authority;
+use authority::{Authority, AuthorityRotationConfig, AuthoritySignerConfig};
+
 pub type Message = PoolMessages<'static>;
 pub type StdFrame = StandardSv2Frame<Message>;
 pub type EitherFrame = StandardEitherFrame<Message>;
 
+/// How long `Pool::shut_down` waits for the connections it closed to go.
+pub const SHUTDOWN_DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
 pub fn get_coinbase_output(config: &PoolConfiguration) -> Result<Vec<TxOut>, Error> {
     let mut result = Vec::new();
     for coinbase_output_pool in &config.coinbase_outputs {
@@ -89,20 +131,136 @@ impl TryFrom<&CoinbaseOutput> for CoinbaseOutput_ {
     }
 }
 
+/// Capacities of the channels between the template provider connection and the pool, each.
+#[derive(Debug, Deserialize, Clone)]
+pub struct TemplateChannels {
+    /// `NewTemplate` and `SetNewPrevHash` to the pool. Once full the template provider isn't read
+    /// from until the pool catches up.
+    #[serde(default = "TemplateChannels::default_capacity")]
+    pub templates: usize,
+    /// `SubmitSolution` of blocks found to the template provider. Once full the pool waits for
+    /// the template provider to take them.
+    #[serde(default = "TemplateChannels::default_capacity")]
+    pub solutions: usize,
+}
+
+impl TemplateChannels {
+    fn default_capacity() -> usize {
+        10
+    }
+}
+
+impl Default for TemplateChannels {
+    fn default() -> Self {
+        Self {
+            templates: Self::default_capacity(),
+            solutions: Self::default_capacity(),
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct PoolConfiguration {
     pub listen_address: String,
     pub tp_address: String,
     pub tp_authority_public_key: Option<Secp256k1PublicKey>,
+    /// How connecting to the template provider is retried, see `crate::retry`.
+    #[serde(default)]
+    pub tp_retry: RetryPolicy,
+    #[serde(default)]
+    pub template_channels: TemplateChannels,
     pub authority_public_key: Secp256k1PublicKey,
-    pub authority_secret_key: Secp256k1SecretKey,
+    /// Signs the noise certificate in memory. Unset with `authority_signer` configured instead.
+    #[serde(default)]
+    pub authority_secret_key: Option<Secp256k1SecretKey>,
+    /// Signing daemon holding the authority key, see `authority`.
+    #[serde(default)]
+    pub authority_signer: Option<AuthoritySignerConfig>,
     pub cert_validity_sec: u64,
+    /// How long before it expires the noise certificate is re-signed, see `authority`. A tenth
+    /// of `cert_validity_sec` if unset.
+    #[serde(default)]
+    pub cert_renew_before_sec: Option<u64>,
+    /// Rotation of the authority key, see `authority`. Not rotated if unset.
+    #[serde(default)]
+    pub authority_rotation: Option<AuthorityRotationConfig>,
     pub coinbase_outputs: Vec<CoinbaseOutput>,
     pub pool_signature: String,
+    #[serde(default)]
+    pub mint: MintConfig,
+    /// Loopback address of the local control API, see `crate::control`.
+    #[serde(default = "default_control_address")]
+    pub control_address: String,
+    /// File holding the token sent with every command of the control API, generated on first
+    /// start.
+    #[serde(default = "default_control_token_path")]
+    pub control_token_path: String,
+    /// Address of the HTTP status API and health endpoints, see `crate::status::server`. Not
+    /// served if unset.
+    #[serde(default)]
+    pub status_address: Option<String>,
+    /// Alerts of the operator, see `crate::status::alerts`. None raised if unset.
+    #[serde(default)]
+    pub alerts: Option<AlertConfig>,
+    /// StatsD server the metrics are pushed to, see `crate::status::statsd`. Not pushed if unset.
+    #[serde(default)]
+    pub statsd: Option<StatsdConfig>,
+    /// SQLite file the events of the bus are recorded in, see `crate::status::history`. Not
+    /// recorded if unset.
+    #[serde(default)]
+    pub event_history: Option<HistoryConfig>,
+    /// Nostr relays the status of the pool is posted to, see `crate::status::nostr`. Not posted
+    /// if unset.
+    #[serde(default)]
+    pub nostr_status: Option<NostrStatusConfig>,
+    /// Authenticated gRPC control plane API, see `crate::grpc`. Not served if unset.
+    #[serde(default)]
+    pub grpc: Option<GrpcConfig>,
+    /// Bitcoin Core RPC used to follow found blocks until their reward matures, see
+    /// `crate::pool_mint::maturity`. Rewards are never paid out without it.
+    #[serde(default)]
+    pub bitcoin_rpc: Option<BitcoinRpcConfig>,
+    /// Memory the pool may take before shedding load, see `memory`. Unbounded if unset.
+    #[serde(default)]
+    pub memory_budget: Option<MemoryBudgetConfig>,
+    /// File the connections of the pool and the translator are audited to, see
+    /// `crate::status::audit`. Not audited if unset.
+    #[serde(default)]
+    pub audit_log: Option<AuditConfig>,
+    /// References the tip of the node behind `bitcoin_rpc` is compared with, see
+    /// `crate::status::tip`. Not compared if unset.
+    #[serde(default)]
+    pub tip_lag: Option<TipLagConfig>,
+    /// Commands fed the events of the pool, see `crate::status::hooks`.
+    #[serde(default)]
+    pub hooks: Vec<CommandHookConfig>,
+    /// Accounts of the tenants the pool mines for, see `accounts`.
+    #[serde(default)]
+    pub accounts: Vec<PoolAccountConfig>,
+    /// File the state kept in memory is snapshot to, see `crate::snapshot`. Not snapshot if
+    /// unset.
+    #[serde(default)]
+    pub snapshot: Option<SnapshotConfig>,
+    /// Rate limits of the pool, the translator, the mint API and the control API, see
+    /// `crate::ratelimit`. None if unset.
+    #[serde(default)]
+    pub rate_limits: RateLimitsConfig,
+    /// Maps the ports of the pool and the translator on the router of the network, see
+    /// `crate::port_mapping`. None if unset.
+    #[serde(default)]
+    pub port_mapping: Option<PortMappingConfig>,
     #[cfg(feature = "test_only_allow_unencrypted")]
     pub test_only_listen_address_plain: String,
 }
 
+pub fn default_control_address() -> String {
+    "127.0.0.1:34260".to_string()
+}
+
+pub fn default_control_token_path() -> String {
+    "control_token".to_string()
+}
+
 pub struct TemplateProviderConfig {
     address: String,
     authority_public_key: Option<Secp256k1PublicKey>,
@@ -159,11 +317,34 @@ impl PoolConfiguration {
             listen_address: pool_connection.listen_address,
             tp_address: template_provider.address,
             tp_authority_public_key: template_provider.authority_public_key,
+            tp_retry: RetryPolicy::default(),
+            template_channels: TemplateChannels::default(),
             authority_public_key: authority_config.public_key,
-            authority_secret_key: authority_config.secret_key,
+            authority_secret_key: Some(authority_config.secret_key),
+            authority_signer: None,
             cert_validity_sec: pool_connection.cert_validity_sec,
+            cert_renew_before_sec: None,
+            authority_rotation: None,
             coinbase_outputs,
             pool_signature: pool_connection.signature,
+            mint: MintConfig::default(),
+            control_address: default_control_address(),
+            control_token_path: default_control_token_path(),
+            status_address: None,
+            alerts: None,
+            statsd: None,
+            event_history: None,
+            nostr_status: None,
+            grpc: None,
+            bitcoin_rpc: None,
+            memory_budget: None,
+            audit_log: None,
+            tip_lag: None,
+            hooks: vec![],
+            accounts: vec![],
+            snapshot: None,
+            rate_limits: RateLimitsConfig::default(),
+            port_mapping: None,
             #[cfg(feature = "test_only_allow_unencrypted")]
             test_only_listen_address_plain,
         }
@@ -179,16 +360,59 @@ pub struct Downstream {
     downstream_data: CommonDownstreamData,
     solution_sender: Sender<SubmitSolution<'static>>,
     channel_factory: Arc<Mutex<PoolChannelFactory>>,
+    mint: Arc<dyn MintBackend>,
+    /// Work of the channels mining for accounts paid in the coinbase, see `accounts`.
+    accounts: Arc<Mutex<Accounts>>,
+    /// Limits the channels opened per user identity, that of the pool.
+    limiter: Arc<RateLimiter>,
+    /// Account and share weight for every channel opened by this downstream, used to credit
+    /// accepted shares at the mint.
+    channel_accounts: HashMap<u32, ChannelAccount>,
+    /// Peer address, and why the pool closed the connection if it did, for the audit log.
+    address: SocketAddr,
+    close_reason: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ChannelAccount {
+    pub account: String,
+    /// Account of the pool paid in the coinbase the channel mines for, if any.
+    pub pool_account: Option<String>,
+    pub share_weight: u64,
+    /// Target of the channel (little endian), and times it was made harder to shed load.
+    pub target: Vec<u8>,
+    pub difficulty_raises: u32,
 }
 
 /// Accept downstream connection
 pub struct Pool {
-    downstreams: HashMap<u32, Arc<Mutex<Downstream>>, BuildNoHashHasher<u32>>,
+    /// Connections by id, out of the lock of the pool so they come and go without taking it.
+    downstreams: Arc<ShardedMap<u32, Arc<Mutex<Downstream>>, BuildNoHashHasher<u32>>>,
     solution_sender: Sender<SubmitSolution<'static>>,
+    /// Watches `solution_sender` as `pool_solutions`, see `crate::status::diagnostics`.
+    _solutions_probe: ChannelProbe,
     new_template_processed: bool,
     channel_factory: Arc<Mutex<PoolChannelFactory>>,
     last_prev_hash_template_id: u64,
     status_tx: status::Sender,
+    mint: Arc<dyn MintBackend>,
+    accounts: Arc<Mutex<Accounts>>,
+    /// Signs the noise certificate handed in handshakes, renewed by `authority::run`.
+    authority: Authority,
+    /// Limits connections per address and channels opened per user identity.
+    limiter: Arc<RateLimiter>,
+    /// Refuses new connections, those open mining on, see `crate::grpc`.
+    draining: bool,
+    /// How close the pool is to its memory budget, refusing new connections once critical.
+    memory_pressure: Pressure,
+}
+
+/// Connections disconnected by `Pool::kick`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KickTarget {
+    Connection(u32),
+    /// Every connection with a channel mining for the account.
+    Account(String),
 }
 
 impl Downstream {
@@ -199,6 +423,7 @@ impl Downstream {
         solution_sender: Sender<SubmitSolution<'static>>,
         pool: Arc<Mutex<Pool>>,
         channel_factory: Arc<Mutex<PoolChannelFactory>>,
+        mint: Arc<dyn MintBackend>,
         status_tx: status::Sender,
         address: SocketAddr,
     ) -> PoolResult<Arc<Mutex<Self>>> {
@@ -211,6 +436,12 @@ impl Downstream {
             false => channel_factory.safe_lock(|c| c.new_group_id())?,
             true => channel_factory.safe_lock(|c| c.new_standard_id_for_hom())?,
         };
+        let (downstreams, accounts, limiter) =
+            pool.safe_lock(|p| (p.downstreams.clone(), p.accounts.clone(), p.limiter.clone()))?;
+        events::publish(Event::MinerConnected {
+            listener: Listener::Pool,
+            connection_id: id,
+        });
 
         let self_ = Arc::new(Mutex::new(Downstream {
             id,
@@ -219,11 +450,17 @@ impl Downstream {
             downstream_data,
             solution_sender,
             channel_factory,
+            mint,
+            accounts,
+            limiter,
+            channel_accounts: HashMap::new(),
+            address,
+            close_reason: None,
         }));
 
         let cloned = self_.clone();
 
-        task::spawn(async move {
+        let receive = async move {
             debug!("Starting up downstream receiver");
             let receiver_res = cloned
                 .safe_lock(|d| d.receiver.clone())
@@ -246,6 +483,8 @@ impl Downstream {
                     return;
                 }
             };
+            // unless the peer closed it or the pool did
+            let mut reason = "dropped after an error".to_string();
             loop {
                 match receiver.recv().await {
                     Ok(received) => {
@@ -259,17 +498,35 @@ impl Downstream {
                         );
                     }
                     _ => {
-                        let res = pool
-                            .safe_lock(|p| p.downstreams.remove(&id))
-                            .map_err(|e| PoolError::PoisonLock(e.to_string()));
-                        handle_result!(status_tx, res);
                         error!("Downstream {} disconnected", id);
+                        events::publish(Event::MinerDisconnected {
+                            listener: Listener::Pool,
+                            connection_id: id,
+                        });
+                        reason = cloned
+                            .safe_lock(|d| d.close_reason.take())
+                            .ok()
+                            .flatten()
+                            .unwrap_or_else(|| "closed".to_string());
                         break;
                     }
                 }
             }
+            // also after an error, e.g. a lock a panic poisoned, which no later frame can recover
+            downstreams.remove(&id);
+            audit::record(
+                Listener::Pool,
+                address,
+                AuditEvent::Disconnected {
+                    connection_id: Some(id),
+                    reason,
+                },
+            );
             warn!("Downstream connection dropped");
-        });
+        };
+        // the worker is recorded once the channel's account is known
+        let span = info_span!("connection", connection_id = id, worker = field::Empty);
+        task::spawn(receive.instrument(span));
         Ok(self_)
     }
 
@@ -309,7 +566,7 @@ impl Downstream {
                         .map_err(|e| Error::PoisonLock(e.to_string()))?;
                     return Err(PoolError::Sv2ProtocolError((
                         downstream_id,
-                        message.clone(),
+                        (&message).into(),
                     )));
                 } else {
                     Self::send(self_, message.clone()).await?;
@@ -372,6 +629,190 @@ pub fn verify_token(
     is_verified
 }
 
+impl Downstream {
+    /// Remembers which account a channel mines for and how much each of its shares is worth.
+    fn set_channel_account(&mut self, channel_id: u32, account: String, target: &[u8]) {
+        Span::current().record("worker", account.as_str());
+        audit::record(
+            Listener::Pool,
+            self.address,
+            AuditEvent::Authorized {
+                connection_id: self.id,
+                channel_id: Some(channel_id),
+                identity: account.clone(),
+            },
+        );
+        events::publish(Event::ChannelOpened {
+            channel_id,
+            account: account.clone(),
+        });
+        let pool_account = self
+            .accounts
+            .safe_lock(|accounts| accounts.account_of(&account))
+            .ok()
+            .flatten();
+        if let Some(pool_account) = &pool_account {
+            info!("Channel {} mines for account {}", channel_id, pool_account);
+        }
+        self.channel_accounts.insert(
+            channel_id,
+            ChannelAccount {
+                account,
+                pool_account,
+                share_weight: mint::share_weight(target),
+                target: target.to_vec(),
+                difficulty_raises: 0,
+            },
+        );
+    }
+
+    /// Updates the share weight after the target of a channel changed.
+    fn set_channel_target(&mut self, channel_id: u32, target: &[u8]) {
+        if let Some(channel) = self.channel_accounts.get_mut(&channel_id) {
+            channel.share_weight = mint::share_weight(target);
+            channel.target = target.to_vec();
+            channel.difficulty_raises = 0;
+        }
+    }
+
+    /// Doubles the difficulty of every channel not raised `MAX_DIFFICULTY_RAISES` times yet,
+    /// returning the targets to send.
+    fn raise_difficulty(&mut self) -> PoolResult<Vec<Mining<'static>>> {
+        let mut messages = vec![];
+        for (channel_id, channel) in self.channel_accounts.iter_mut() {
+            if channel.difficulty_raises >= MAX_DIFFICULTY_RAISES {
+                continue;
+            }
+            let target = memory::harder_target(&channel.target);
+            let maximum_target: U256<'static> = target
+                .clone()
+                .try_into()
+                .map_err(|_| PoolError::Custom("invalid channel target".to_string()))?;
+            self.channel_factory.safe_lock(|f| {
+                f.update_target_for_channel(*channel_id, maximum_target.clone().into())
+            })?;
+            channel.share_weight = mint::share_weight(&target);
+            channel.target = target;
+            channel.difficulty_raises += 1;
+            messages.push(Mining::SetTarget(SetTarget {
+                channel_id: *channel_id,
+                maximum_target,
+            }));
+        }
+        Ok(messages)
+    }
+
+    /// Credits an accepted share on `channel_id` to the channel's account, returning its weight.
+    fn credit_share(&self, channel_id: u32) -> u64 {
+        let Some(channel) = self.channel_accounts.get(&channel_id) else {
+            warn!("Accepted share on channel {} with no account", channel_id);
+            return 0;
+        };
+        let _span = info_span!(
+            "credit_share",
+            channel_id,
+            account = %channel.account,
+            weight = channel.share_weight
+        )
+        .entered();
+        // paid in the coinbase rather than credited at the mint
+        let credited = match &channel.pool_account {
+            Some(_) => Ok(()),
+            None => self
+                .mint
+                .credit_share(&channel.account, channel.share_weight),
+        };
+        match credited {
+            Ok(()) => {
+                let accrued = self.accounts.safe_lock(|accounts| {
+                    accounts.accrue(channel.pool_account.as_deref(), channel.share_weight)
+                });
+                if let Err(e) = accrued {
+                    error!("Failed to accrue share: {}", e);
+                }
+                events::publish(Event::ShareAccepted {
+                    channel_id,
+                    account: channel.account.clone(),
+                    weight: channel.share_weight,
+                });
+                channel.share_weight
+            }
+            Err(e) => {
+                error!("Failed to credit share: {}", e);
+                0
+            }
+        }
+    }
+
+    /// Channel of this downstream mining for `account`, if any.
+    fn channel_for(&self, account: &str) -> Option<u32> {
+        self.channel_accounts
+            .iter()
+            .find(|(_, channel)| channel.account == account)
+            .map(|(channel_id, _)| *channel_id)
+    }
+
+    /// Sends `token` to `channel_id` with the payout extension message, framed by hand since the
+    /// roles_logic message types can't carry it.
+    async fn send_payout(
+        self_mutex: Arc<Mutex<Self>>,
+        channel_id: u32,
+        token: &str,
+    ) -> PoolResult<()> {
+        let payload = payout::encode_payout_message(channel_id, token);
+        if payload.len() >= 1 << 24 {
+            return Err(PoolError::Custom(format!(
+                "payout of {} bytes doesn't fit a frame",
+                payload.len()
+            )));
+        }
+        // extension_type (u16), msg_type (u8), msg_length (u24), all little endian
+        let mut bytes = Vec::with_capacity(6 + payload.len());
+        bytes.extend_from_slice(&PAYOUT_EXTENSION_TYPE.to_le_bytes());
+        bytes.push(MESSAGE_TYPE_PAYOUT);
+        bytes.extend_from_slice(&(payload.len() as u32).to_le_bytes()[..3]);
+        bytes.extend_from_slice(&payload);
+        let sv2_frame = StdFrame::from_bytes(bytes)
+            .map_err(|_| PoolError::Custom("invalid payout frame".to_string()))?;
+        let sender = self_mutex.safe_lock(|self_| self_.sender.clone())?;
+        sender.send(sv2_frame.into()).await?;
+        Ok(())
+    }
+
+    /// Closes the mint's open round with a block found by a share on `channel_id`. Its reward is
+    /// paid out once the coinbase matures.
+    fn record_found_block(&self, channel_id: u32, coinbase: &[u8]) {
+        let (txid, reward) = match mint::rounds::coinbase_reward(coinbase) {
+            Ok(coinbase) => coinbase,
+            Err(e) => {
+                error!("Failed to read the reward of a found block: {}", e);
+                return;
+            }
+        };
+        info!("Found block with coinbase {} paying {} sat", txid, reward);
+        events::publish(Event::BlockFound {
+            coinbase_txid: txid.clone(),
+            reward,
+        });
+        // the accounts paid in the coinbase have nothing to get from the mint
+        let paid = self
+            .accounts
+            .safe_lock(|accounts| accounts.on_block_found(coinbase))
+            .unwrap_or_else(|e| {
+                error!("Failed to settle the accounts of block {}: {}", txid, e);
+                0
+            });
+        let reward = reward.saturating_sub(paid);
+        let finder = self
+            .channel_accounts
+            .get(&channel_id)
+            .map(|channel| channel.account.as_str());
+        if let Err(e) = self.mint.found_block(&txid, reward, finder) {
+            error!("Failed to close the round of block {}: {}", txid, e);
+        }
+    }
+}
+
 impl IsDownstream for Downstream {
     fn get_downstream_mining_data(&self) -> CommonDownstreamData {
         self.downstream_data
@@ -414,8 +855,14 @@ impl Pool {
         self_: Arc<Mutex<Pool>>,
         config: PoolConfiguration,
     ) -> PoolResult<()> {
-        let status_tx = self_.safe_lock(|s| s.status_tx.clone())?;
-        let listener = TcpListener::bind(&config.listen_address).await?;
+        let (status_tx, limiter) = self_.safe_lock(|s| (s.status_tx.clone(), s.limiter.clone()))?;
+        let listener = handoff::bind(Listener::Pool, &config.listen_address)
+            .map_err(|e| PoolError::Bind(format!("{}: {}", config.listen_address, e)))?;
+        let listener = TcpListener::from_std(listener)?;
+        events::publish(Event::ListenerBound {
+            listener: Listener::Pool,
+            address: config.listen_address.clone(),
+        });
         info!("Starting mining pool server:");
         info!(
             "  - Listening for connections on: {}",
@@ -423,37 +870,56 @@ impl Pool {
         );
         info!("  - Template provider address: {}", config.tp_address);
 
-        while let Ok((stream, _)) = listener.accept().await {
+        let heartbeat = Heartbeat::start("pool_acceptor");
+        while let Some(Ok((stream, _))) =
+            handoff::accepting(heartbeat.beating(listener.accept())).await
+        {
             let address = stream.peer_addr().unwrap();
+            audit::record(Listener::Pool, address, AuditEvent::Accepted);
+            if self_.safe_lock(|p| p.draining)? {
+                debug!("Draining, refused connection from {}", address);
+                let reason = "draining".to_string();
+                audit::record(Listener::Pool, address, AuditEvent::Refused { reason });
+                continue;
+            }
+            if self_.safe_lock(|p| p.memory_pressure == Pressure::Critical)? {
+                warn!(
+                    "Memory budget nearly used, refused connection from {}",
+                    address
+                );
+                let reason = "memory budget".to_string();
+                audit::record(Listener::Pool, address, AuditEvent::Refused { reason });
+                continue;
+            }
+            if !limiter.allow_ip(address.ip()) {
+                debug!("Rate limited connection from {}", address);
+                let reason = "rate limit".to_string();
+                audit::record(Listener::Pool, address, AuditEvent::Refused { reason });
+                continue;
+            }
             debug!(
                 "New connection from {:?}",
                 stream.peer_addr().map_err(PoolError::Io)
             );
 
-            let responder = Responder::from_authority_kp(
-                &config.authority_public_key.into_bytes(),
-                &config.authority_secret_key.into_bytes(),
-                std::time::Duration::from_secs(config.cert_validity_sec),
-            );
-            match responder {
-                Ok(resp) => {
-                    if let Ok((receiver, sender, _, _)) =
-                        Connection::new(stream, HandshakeRole::Responder(resp)).await
-                    {
-                        handle_result!(
-                            status_tx,
-                            Self::accept_incoming_connection_(
-                                self_.clone(),
-                                receiver,
-                                sender,
-                                address
-                            )
+            let responder = self_.safe_lock(|p| p.authority.responder())?;
+            match Connection::new(stream, HandshakeRole::Responder(responder)).await {
+                Ok((receiver, sender, _, _)) => {
+                    let protocol = "noise_nx".to_string();
+                    audit::record(Listener::Pool, address, AuditEvent::Handshake { protocol });
+                    handle_result!(
+                        status_tx,
+                        Self::accept_incoming_connection_(self_.clone(), receiver, sender, address)
                             .await
-                        );
-                    }
+                    );
                 }
-                Err(_e) => {
-                    todo!()
+                Err(e) => {
+                    let reason = format!("{:?}", e);
+                    audit::record(
+                        Listener::Pool,
+                        address,
+                        AuditEvent::HandshakeFailed { reason },
+                    );
                 }
             }
         }
@@ -469,6 +935,7 @@ impl Pool {
         let solution_sender = self_.safe_lock(|p| p.solution_sender.clone())?;
         let status_tx = self_.safe_lock(|s| s.status_tx.clone())?;
         let channel_factory = self_.safe_lock(|s| s.channel_factory.clone())?;
+        let mint = self_.safe_lock(|s| s.mint.clone())?;
 
         let downstream = Downstream::new(
             receiver,
@@ -476,17 +943,28 @@ impl Pool {
             solution_sender,
             self_.clone(),
             channel_factory,
+            mint,
             // convert Listener variant to Downstream variant
             status_tx.listener_to_connection(),
             address,
         )
-        .await?;
+        .await
+        .inspect_err(|e| {
+            let reason = format!("setup failed: {}", e);
+            audit::record(
+                Listener::Pool,
+                address,
+                AuditEvent::Disconnected {
+                    connection_id: None,
+                    reason,
+                },
+            );
+        })?;
 
         let (_, channel_id) = downstream.safe_lock(|d| (d.downstream_data.header_only, d.id))?;
 
-        self_.safe_lock(|p| {
-            p.downstreams.insert(channel_id, downstream);
-        })?;
+        let downstreams = self_.safe_lock(|p| p.downstreams.clone())?;
+        downstreams.insert(channel_id, downstream);
         Ok(())
     }
 
@@ -495,22 +973,37 @@ impl Pool {
         rx: Receiver<SetNewPrevHash<'static>>,
         sender_message_received_signal: Sender<()>,
     ) -> PoolResult<()> {
-        let status_tx = self_
-            .safe_lock(|s| s.status_tx.clone())
+        let (status_tx, downstreams) = self_
+            .safe_lock(|s| (s.status_tx.clone(), s.downstreams.clone()))
             .map_err(|e| PoolError::PoisonLock(e.to_string()))?;
         while let Ok(new_prev_hash) = rx.recv().await {
             debug!("New prev hash received: {:?}", new_prev_hash);
+            // displayed byte reversed, like block explorers do
+            let mut prev_hash = new_prev_hash.prev_hash.inner_as_ref().to_vec();
+            prev_hash.reverse();
+            events::publish(Event::NewBlock {
+                prev_hash: hex::encode(prev_hash),
+            });
             let res = self_
                 .safe_lock(|s| {
                     s.last_prev_hash_template_id = new_prev_hash.template_id;
+                    s.accounts
+                        .safe_lock(|a| a.on_new_prev_hash(new_prev_hash.template_id))
+                        .map_err(|e| PoolError::PoisonLock(e.to_string()))
                 })
                 .map_err(|e| PoolError::PoisonLock(e.to_string()));
-            handle_result!(status_tx, res);
+            let outputs = handle_result!(status_tx, handle_result!(status_tx, res));
 
             let job_id_res = self_
                 .safe_lock(|s| {
                     s.channel_factory
-                        .safe_lock(|f| f.on_new_prev_hash_from_tp(&new_prev_hash))
+                        .safe_lock(|f| {
+                            // the jobs of channels opened from now on pay as the template
+                            if let Some(outputs) = outputs {
+                                f.update_pool_outputs(outputs);
+                            }
+                            f.on_new_prev_hash_from_tp(&new_prev_hash)
+                        })
                         .map_err(|e| PoolError::PoisonLock(e.to_string()))
                 })
                 .map_err(|e| PoolError::PoisonLock(e.to_string()));
@@ -518,12 +1011,7 @@ impl Pool {
 
             match job_id {
                 Ok(job_id) => {
-                    let downstreams = self_
-                        .safe_lock(|s| s.downstreams.clone())
-                        .map_err(|e| PoolError::PoisonLock(e.to_string()));
-                    let downstreams = handle_result!(status_tx, downstreams);
-
-                    for (channel_id, downstream) in downstreams {
+                    for (channel_id, downstream) in downstreams.entries() {
                         let message = Mining::SetNewPrevHash(SetNPH {
                             channel_id,
                             job_id,
@@ -553,30 +1041,42 @@ impl Pool {
     ) -> PoolResult<()> {
         let status_tx = self_.safe_lock(|s| s.status_tx.clone())?;
         let channel_factory = self_.safe_lock(|s| s.channel_factory.clone())?;
-        while let Ok(mut new_template) = rx.recv().await {
-            debug!(
-                "New template received, creating a new mining job(s): {:?}",
-                new_template
-            );
+        let downstreams = self_.safe_lock(|s| s.downstreams.clone())?;
+        let accounts = self_.safe_lock(|s| s.accounts.clone())?;
+        let heartbeat = Heartbeat::start("job_distributor");
+        while let Ok(mut new_template) = heartbeat.beating(rx.recv()).await {
+            // covers the jobs of the template until sent to every channel
+            let span = info_span!("distribute_job", template_id = new_template.template_id);
+            span.in_scope(|| {
+                debug!(
+                    "New template received, creating a new mining job(s): {:?}",
+                    new_template
+                )
+            });
 
+            let outputs = accounts
+                .safe_lock(|a| a.on_new_template(&mut new_template))
+                .map_err(|e| PoolError::PoisonLock(e.to_string()));
+            let outputs = handle_result!(status_tx, outputs);
             let messages = channel_factory
-                .safe_lock(|cf| cf.on_new_template(&mut new_template))
+                .safe_lock(|cf| {
+                    if let Some(outputs) = outputs {
+                        cf.update_pool_outputs(outputs);
+                    }
+                    cf.on_new_template(&mut new_template)
+                })
                 .map_err(|e| PoolError::PoisonLock(e.to_string()));
             let messages = handle_result!(status_tx, messages);
             let mut messages = handle_result!(status_tx, messages);
 
-            let downstreams = self_
-                .safe_lock(|s| s.downstreams.clone())
-                .map_err(|e| PoolError::PoisonLock(e.to_string()));
-            let downstreams = handle_result!(status_tx, downstreams);
-
-            for (channel_id, downstream) in downstreams {
+            for (channel_id, downstream) in downstreams.entries() {
                 if let Some(to_send) = messages.remove(&channel_id) {
                     if let Err(e) =
                         Downstream::match_send_to(downstream.clone(), Ok(SendTo::Respond(to_send)))
+                            .instrument(span.clone())
                             .await
                     {
-                        error!("Unknown template provider message: {:?}", e);
+                        span.in_scope(|| error!("Unknown template provider message: {:?}", e));
                     }
                 }
             }
@@ -590,13 +1090,20 @@ impl Pool {
         Ok(())
     }
 
+    /// Starts the pool, accepting miners and handing them the templates received until
+    /// `cancel_token` is cancelled. The connections open are drained by `shut_down`.
+    #[allow(clippy::too_many_arguments)]
     pub fn start(
         config: PoolConfiguration,
+        authority: Authority,
+        accounts: Accounts,
+        mint: Arc<dyn MintBackend>,
         new_template_rx: Receiver<NewTemplate<'static>>,
         new_prev_hash_rx: Receiver<SetNewPrevHash<'static>>,
         solution_sender: Sender<SubmitSolution<'static>>,
         sender_message_received_signal: Sender<()>,
         status_tx: status::Sender,
+        cancel_token: CancellationToken,
     ) -> Arc<Mutex<Self>> {
         let extranonce_len = 32;
         let range_0 = std::ops::Range { start: 0, end: 0 };
@@ -621,13 +1128,22 @@ impl Pool {
             pool_coinbase_outputs.expect("Invalid coinbase output in config"),
             config.pool_signature.clone(),
         )));
+        let accounts = Arc::new(Mutex::new(accounts));
+        snapshot::register("accounts", accounts.clone());
         let pool = Arc::new(Mutex::new(Pool {
-            downstreams: HashMap::with_hasher(BuildNoHashHasher::default()),
+            downstreams: Arc::new(ShardedMap::new()),
+            _solutions_probe: ChannelProbe::new("pool_solutions", &solution_sender),
             solution_sender,
             new_template_processed: false,
             channel_factory,
             last_prev_hash_template_id: 0,
             status_tx: status_tx.clone(),
+            mint,
+            accounts,
+            authority,
+            limiter: Arc::new(RateLimiter::new("pool", &config.rate_limits.pool)),
+            draining: false,
+            memory_pressure: Pressure::Normal,
         }));
 
         let cloned = pool.clone();
@@ -639,11 +1155,14 @@ impl Pool {
             let cloned4 = pool.clone();
             let status_tx_clone_unenc = status_tx.clone();
             let config_unenc = config.clone();
+            let cancel_token_unenc = cancel_token.clone();
 
             task::spawn(async move {
-                if let Err(e) = Self::accept_incoming_plain_connection(cloned4, config_unenc).await
-                {
-                    error!("{}", e);
+                let accepting = Self::accept_incoming_plain_connection(cloned4, config_unenc);
+                match cancel_token_unenc.run_until_cancelled(accepting).await {
+                    Some(Err(e)) => error!("{}", e),
+                    Some(Ok(())) => {}
+                    None => return,
                 }
                 if status_tx_clone_unenc
                     .send(status::Status {
@@ -661,9 +1180,18 @@ impl Pool {
 
         info!("Starting up pool listener");
         let status_tx_clone = status_tx.clone();
+        let accept_cancel_token = cancel_token.clone();
         task::spawn(async move {
-            if let Err(e) = Self::accept_incoming_connection(cloned, config).await {
-                error!("{}", e);
+            let accepting = Self::accept_incoming_connection(cloned, config);
+            match accept_cancel_token.run_until_cancelled(accepting).await {
+                Some(Err(e)) => error!("{}", e),
+                Some(Ok(())) => {}
+                // shutting down, the connections open are drained
+                None => return,
+            }
+            // the new process accepts from now on, this one shuts down
+            if handoff::is_handed_over() {
+                return;
             }
             if status_tx_clone
                 .send(status::Status {
@@ -680,9 +1208,13 @@ impl Pool {
 
         let cloned = sender_message_received_signal.clone();
         let status_tx_clone = status_tx.clone();
+        let prev_hash_cancel_token = cancel_token.clone();
         task::spawn(async move {
-            if let Err(e) = Self::on_new_prev_hash(cloned2, new_prev_hash_rx, cloned).await {
-                error!("{}", e);
+            let handling = Self::on_new_prev_hash(cloned2, new_prev_hash_rx, cloned);
+            match prev_hash_cancel_token.run_until_cancelled(handling).await {
+                Some(Err(e)) => error!("{}", e),
+                Some(Ok(())) => {}
+                None => return,
             }
             // on_new_prev_hash shutdown
             if status_tx_clone
@@ -700,10 +1232,12 @@ impl Pool {
 
         let status_tx_clone = status_tx;
         task::spawn(async move {
-            if let Err(e) =
-                Self::on_new_template(pool, new_template_rx, sender_message_received_signal).await
-            {
-                error!("{}", e);
+            let handling =
+                Self::on_new_template(pool, new_template_rx, sender_message_received_signal);
+            match cancel_token.run_until_cancelled(handling).await {
+                Some(Err(e)) => error!("{}", e),
+                Some(Ok(())) => {}
+                None => return,
             }
             // on_new_template shutdown
             if status_tx_clone
@@ -726,9 +1260,168 @@ impl Pool {
     /// this remove happens which will cause the cloning task to still attempt to communicate with
     /// the downstream. This is going to be rare and will won't cause any issues as the attempt
     /// to communicate will fail but continue with the next downstream.
-    pub fn remove_downstream(&mut self, downstream_id: u32) {
+    pub fn remove_downstream(&self, downstream_id: u32) {
         self.downstreams.remove(&downstream_id);
     }
+
+    /// Refuses new connections and closes those open, waiting `SHUTDOWN_DRAIN_TIMEOUT` at most
+    /// for their queued frames to be sent and handled, see `crate::shutdown`.
+    pub async fn shut_down(self_: &Arc<Mutex<Self>>) -> PoolResult<()> {
+        let downstreams = self_.safe_lock(|p| {
+            p.draining = true;
+            p.downstreams.clone()
+        })?;
+        let connections = downstreams.values();
+        for downstream in &connections {
+            downstream.safe_lock(|d| {
+                d.close_reason = Some("shutdown".to_string());
+                // queued frames are still taken once closed
+                d.receiver.close();
+                d.sender.close();
+            })?;
+        }
+        info!("Closing {} connections", connections.len());
+        let drained = tokio::time::timeout(SHUTDOWN_DRAIN_TIMEOUT, async {
+            while !downstreams.is_empty() {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        })
+        .await;
+        if drained.is_err() {
+            warn!(
+                "{} connections still open after draining",
+                downstreams.len()
+            );
+        }
+        Ok(())
+    }
+
+    pub fn set_draining(&mut self, draining: bool) {
+        self.draining = draining;
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining
+    }
+
+    /// Number of downstreams connected.
+    pub fn connections(&self) -> usize {
+        self.downstreams.len()
+    }
+
+    /// Memory the connections, jobs and share queues of the pool are estimated to take.
+    pub fn memory_usage(&self) -> PoolResult<MemoryUsage> {
+        let (mut queued_frames, mut channels) = (0, 0);
+        for downstream in self.downstreams.values() {
+            downstream.safe_lock(|d| {
+                queued_frames += (d.receiver.len() + d.sender.len()) as u64;
+                channels += d.channel_accounts.len() as u64;
+            })?;
+        }
+        Ok(MemoryUsage::estimate(
+            self.downstreams.len() as u64,
+            queued_frames,
+            channels,
+            memory::queued_shares(),
+        ))
+    }
+
+    /// Checks the memory the pool takes against `budget`, raising the difficulty of every
+    /// channel while it nears the budget. New connections are refused while it is critical.
+    pub async fn enforce_memory_budget(
+        self_: Arc<Mutex<Self>>,
+        budget: &MemoryBudgetConfig,
+    ) -> PoolResult<()> {
+        let usage = self_.safe_lock(|p| p.memory_usage())??;
+        let pressure = budget.pressure(&usage);
+        let previous = self_.safe_lock(|p| std::mem::replace(&mut p.memory_pressure, pressure))?;
+        if pressure != previous {
+            match pressure {
+                Pressure::Normal => info!("Memory back within budget: {:?}", usage),
+                _ => warn!(
+                    "Memory {:?} of budget, {} of {} bytes: {:?}",
+                    pressure,
+                    usage.total(),
+                    budget.limit_bytes(),
+                    usage
+                ),
+            }
+            events::publish(Event::MemoryPressure {
+                pressure,
+                used_bytes: usage.total(),
+                budget_bytes: budget.limit_bytes(),
+            });
+        }
+        if pressure == Pressure::Normal {
+            return Ok(());
+        }
+        let downstreams = self_.safe_lock(|p| p.downstreams.values())?;
+        for downstream in downstreams {
+            let messages = downstream.safe_lock(|d| d.raise_difficulty())??;
+            for message in messages {
+                if let Err(e) = Downstream::send(downstream.clone(), message).await {
+                    warn!("Failed to raise the difficulty of a channel: {}", e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Closes the connections of `target`, returning their ids. Each is dropped from the pool by
+    /// its receiving task once closed.
+    pub fn kick(&self, target: &KickTarget) -> PoolResult<Vec<u32>> {
+        let mut kicked = vec![];
+        for (id, downstream) in self.downstreams.entries() {
+            let closed = downstream.safe_lock(|d| {
+                let matches = match target {
+                    KickTarget::Connection(connection_id) => d.id == *connection_id,
+                    KickTarget::Account(account) => d.channel_for(account).is_some(),
+                };
+                if matches {
+                    d.close_reason = Some("kicked".to_string());
+                    d.receiver.close();
+                    d.sender.close();
+                }
+                matches
+            })?;
+            if closed {
+                kicked.push(id);
+            }
+        }
+        kicked.sort_unstable();
+        Ok(kicked)
+    }
+
+    /// Pushes the undelivered payouts of accounts paid over stratum to a connected channel of
+    /// the account. Payouts of accounts not connected wait for the next call.
+    pub async fn deliver_payouts(self_: Arc<Mutex<Self>>, config: &PayoutConfig) -> PoolResult<()> {
+        let (mint, downstreams) = self_.safe_lock(|p| (p.mint.clone(), p.downstreams.values()))?;
+        let payouts = mint.undelivered_payouts()?;
+        for payout in payouts
+            .into_iter()
+            .filter(|payout| config.mode(&payout.account) == PayoutMode::Stratum)
+        {
+            let target = downstreams.iter().find_map(|downstream| {
+                let channel_id = downstream
+                    .safe_lock(|d| d.channel_for(&payout.account))
+                    .ok()??;
+                Some((downstream.clone(), channel_id))
+            });
+            let Some((downstream, channel_id)) = target else {
+                continue;
+            };
+            if let Err(e) = Downstream::send_payout(downstream, channel_id, &payout.token).await {
+                warn!("Failed to deliver payout {}: {}", payout.id, e);
+                continue;
+            }
+            mint.mark_payout_delivered(&payout.id)?;
+            info!(
+                "Delivered payout of {} {} to {} on channel {}",
+                payout.amount, payout.unit, payout.account, channel_id
+            );
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -751,7 +1444,7 @@ mod test {
 
         // Load config
         let config: PoolConfiguration = match Config::builder()
-            .add_source(File::new(&config_path, FileFormat::Toml))
+            .add_source(File::new(config_path, FileFormat::Toml))
             .build()
         {
             Ok(settings) => match settings.try_deserialize::<PoolConfiguration>() {
@@ -783,7 +1476,7 @@ mod test {
         // build coinbase TX from 'job_creator::coinbase()'
 
         let mut bip34_bytes = get_bip_34_bytes(coinbase_prefix.try_into().unwrap());
-        let script_prefix_length = bip34_bytes.len() + config.pool_signature.as_bytes().len();
+        let script_prefix_length = bip34_bytes.len() + config.pool_signature.len();
         bip34_bytes.extend_from_slice(config.pool_signature.as_bytes());
         bip34_bytes.extend_from_slice(&vec![0; extranonce_len as usize]);
         let witness = match bip34_bytes.len() {
@@ -1,5 +1,11 @@
 use super::super::mining_pool::{EitherFrame, StdFrame};
-use crate::error::{PoolError, PoolResult};
+use crate::{
+    error::{PoolError, PoolResult},
+    status::{
+        audit::{self, AuditEvent},
+        events::Listener,
+    },
+};
 use async_channel::{Receiver, Sender};
 use roles_logic_sv2::{
     common_messages_sv2::{
@@ -18,6 +24,9 @@ use tracing::{debug, error};
 
 pub struct SetupConnectionHandler {
     header_only: Option<bool>,
+    /// Version used and device announced, for the audit log.
+    used_version: Option<u16>,
+    user_agent: String,
 }
 
 impl Default for SetupConnectionHandler {
@@ -28,7 +37,11 @@ impl Default for SetupConnectionHandler {
 
 impl SetupConnectionHandler {
     pub fn new() -> Self {
-        Self { header_only: None }
+        Self {
+            header_only: None,
+            used_version: None,
+            user_agent: String::new(),
+        }
     }
     pub async fn setup(
         self_: Arc<Mutex<Self>>,
@@ -75,7 +88,17 @@ impl SetupConnectionHandler {
         let sv2_frame: StdFrame = PoolMessages::Common(message.clone()).try_into()?;
         let sv2_frame = sv2_frame.into();
         sender.send(sv2_frame).await?;
-        self_.safe_lock(|s| s.header_only)?;
+        let (version, user_agent) = self_.safe_lock(|s| (s.used_version, s.user_agent.clone()))?;
+        audit::record(
+            Listener::Pool,
+            address,
+            AuditEvent::Setup {
+                connection_id: None,
+                protocol: "stratum_v2".to_string(),
+                version,
+                user_agent,
+            },
+        );
 
         match message {
             CommonMessages::SetupConnectionSuccess(m) => {
@@ -101,11 +124,23 @@ impl ParseDownstreamCommonMessages<NoRouting> for SetupConnectionHandler {
         let header_only = incoming.requires_standard_job();
         debug!("Handling setup connection: header_only: {}", header_only);
         self.header_only = Some(header_only);
+        let used_version = 2;
+        self.used_version = Some(used_version);
+        self.user_agent = [
+            incoming.vendor.inner_as_ref(),
+            incoming.hardware_version.inner_as_ref(),
+            incoming.firmware.inner_as_ref(),
+        ]
+        .iter()
+        .filter(|part| !part.is_empty())
+        .map(|part| String::from_utf8_lossy(part))
+        .collect::<Vec<_>>()
+        .join(" ");
         Ok(SendTo::RelayNewMessageToRemote(
             Arc::new(Mutex::new(())),
             CommonMessages::SetupConnectionSuccess(SetupConnectionSuccess {
                 flags: incoming.flags,
-                used_version: 2,
+                used_version,
             }),
         ))
     }
@@ -0,0 +1,54 @@
+//! Ledger of every account shares are mined under, bridging the share accounting and the mint.
+//! Per unit, an account is credited by its shares (ehash) and by the matured rounds it mined in
+//! (sat), is issued tokens out of its balance by mint quotes, queued outputs and payouts, and
+//! redeems the ehash the mint still holds for it into sat when its round matures. Its balance is
+//! what was credited less what was issued and redeemed.
+//!
+//! Shares of rounds whose block hasn't matured yet are pending: their ehash is credited, their sat
+//! isn't yet. Statements are served by `/v1/ehash/account`, to the holder of the credential of
+//! the account (see `credentials`), and by the control API `accounts`.
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Running totals of an account in one unit.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountLedger {
+    pub balance: u64,
+    pub credited: u64,
+    /// Signed as tokens out of the balance.
+    pub issued: u64,
+    /// Ehash settled into sat by matured rounds.
+    pub redeemed: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountStatement {
+    pub account: String,
+    /// Ledger per unit, of every unit of the mint.
+    pub ledgers: BTreeMap<String, AccountLedger>,
+    /// Weight of the shares in rounds not matured yet.
+    pub pending_shares: u64,
+}
+
+/// Whether the worker or user `name` matches `pattern`, `*` matching any number of characters.
+pub fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    // there is always at least one part, even for an empty pattern
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts: Vec<&str> = parts.collect();
+    let last = match parts.pop() {
+        Some(last) => last,
+        // no wildcard in the pattern, the name has to match exactly
+        None => return rest.is_empty(),
+    };
+    for part in parts {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
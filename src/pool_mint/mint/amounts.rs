@@ -0,0 +1,98 @@
+//! Amounts a keyset has keys for. By default a keyset signs every power of two up to
+//! `2^(max_order - 1)`; `amounts` configures another set per unit, either its own `max_order` or a
+//! custom list of powers of two. Without the small denominations, the mint refuses amounts that
+//! aren't a multiple of the smallest one, e.g. to keep ehash tokens free of proofs worth less
+//! than a share, and payouts and change leave the rest in the balance.
+//!
+//! The amounts of a keyset are recorded with it (see `lifecycle`) and never change. When the
+//! configured set of a unit no longer matches its active keyset, a keyset of the new set replaces
+//! it on startup, and tokens of the old one are still redeemed.
+use crate::error::{MintError, MintResult};
+use serde::Deserialize;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AmountStrategy {
+    /// Every power of two up to `2^(max_order - 1)`.
+    MaxOrder(u8),
+    /// These powers of two only.
+    Custom(Vec<u64>),
+}
+
+impl AmountStrategy {
+    /// The amounts of the strategy, ascending.
+    pub fn amounts(&self) -> MintResult<Vec<u64>> {
+        let mut amounts: Vec<u64> = match self {
+            Self::MaxOrder(max_order) => {
+                (0..(*max_order).min(64)).map(|order| 1 << order).collect()
+            }
+            Self::Custom(amounts) => amounts.clone(),
+        };
+        amounts.sort_unstable();
+        amounts.dedup();
+        if amounts.is_empty() {
+            return Err(MintError::InvalidAmounts("no amounts".into()));
+        }
+        if let Some(amount) = amounts.iter().find(|amount| !amount.is_power_of_two()) {
+            return Err(MintError::InvalidAmounts(format!(
+                "{} is not a power of two",
+                amount
+            )));
+        }
+        Ok(amounts)
+    }
+}
+
+/// Splits `amount` into `amounts`, powers of two in ascending order, largest first. Fails
+/// unless `amount` is a multiple of the smallest of them.
+pub fn split(amount: u64, amounts: &[u64]) -> MintResult<Vec<u64>> {
+    let mut parts = vec![];
+    let mut rest = amount;
+    for denomination in amounts.iter().rev() {
+        parts.extend(std::iter::repeat_n(
+            *denomination,
+            (rest / denomination) as usize,
+        ));
+        rest %= denomination;
+    }
+    match rest {
+        0 => Ok(parts),
+        _ => Err(MintError::UnsupportedAmount(amount)),
+    }
+}
+
+/// The largest part of `amount` that `split` splits into `amounts`.
+pub fn splittable(amount: u64, amounts: &[u64]) -> u64 {
+    let smallest = amounts.first().copied().unwrap_or(1);
+    amount - amount % smallest
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn splits_into_the_amounts_of_a_strategy() {
+        let powers = AmountStrategy::MaxOrder(32).amounts().unwrap();
+        assert_eq!(powers.len(), 32);
+        assert_eq!(split(13, &powers).unwrap(), vec![8, 4, 1]);
+        assert_eq!(split(21, &[1, 2, 4, 8]).unwrap(), vec![8, 8, 4, 1]);
+        assert_eq!(split(21, &[1, 2, 4]).unwrap(), vec![4, 4, 4, 4, 4, 1]);
+        assert!(split(0, &powers).unwrap().is_empty());
+
+        let custom = AmountStrategy::Custom(vec![4096, 256, 16, 256])
+            .amounts()
+            .unwrap();
+        assert_eq!(custom, vec![16, 256, 4096]);
+        assert_eq!(split(4384, &custom).unwrap(), vec![4096, 256, 16, 16]);
+        assert!(matches!(
+            split(4385, &custom),
+            Err(MintError::UnsupportedAmount(4385))
+        ));
+        assert_eq!(splittable(4385, &custom), 4384);
+
+        assert!(AmountStrategy::Custom(vec![1, 3]).amounts().is_err());
+        assert!(AmountStrategy::Custom(vec![]).amounts().is_err());
+        assert!(AmountStrategy::MaxOrder(0).amounts().is_err());
+    }
+}
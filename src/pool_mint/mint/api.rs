@@ -0,0 +1,717 @@
+//! Cashu REST API of the mint (NUT-01 to NUT-06), so existing Cashu wallets can mint, swap and
+//! melt potato issued tokens without custom tooling. Every signature comes with a NUT-12 DLEQ
+//! proof, so wallets can check it was made with the published keys.
+//!
+//! Mint quotes use the `ehash` payment method: the quote names the account a miner mines under
+//! and is paid from the ehash that account accrued. It carries the credential the operator issued
+//! the account (see `credentials`), or is answered with 401 Unauthorized. Melt quotes use
+//! `bolt11` and are paid by the configured Lightning node, see `melt`, or `onchain` and are paid
+//! to an address by the pool's batched payout transaction, see `onchain`.
+//!
+//! Miners can also queue blinded outputs under `/v1/ehash/outputs`, with the credential of the
+//! account. The mint signs them in the background as the account's balance grows, away from
+//! share validation, and wallets pick the signatures up from `/v1/ehash/signatures`.
+//! `/v1/restore` (NUT-09) answers the same, so wallets with deterministic secrets recover the
+//! signatures on outputs they lost, see `deterministic`.
+//!
+//! Ehash tokens of matured rounds convert into sat tokens under `/v1/ehash/convert`, at the rate
+//! published by `/v1/ehash/conversion`.
+//!
+//! The balances an account accrued are served by `/v1/ehash/balance`, and its running totals by
+//! `/v1/ehash/account` (see `accounts`), both to the holder of the credential of the account.
+//! Tokens minted by automatic payouts (see `payout`) are picked up from `/v1/ehash/payouts` with
+//! that credential, and are locked to the key an account registers under `/v1/ehash/pubkey`
+//! (see `p2pk`), with that credential too.
+//! Those of accounts paid out over Nostr are sent to the key registered the same way under
+//! `/v1/ehash/nostr` (see `nostr`).
+//!
+//! Wallets check whether proofs are unspent, reserved by a melt or spent under `/v1/checkstate`
+//! (NUT-07), e.g. before accepting a token from another miner, and subscribe to quote and proof
+//! states on the `/v1/ws` WebSocket (NUT-17, see `subscriptions`) instead of polling them.
+//!
+//! POST requests are rate limited per client address, and those naming an account per account
+//! too (see `crate::ratelimit`), answered with 429 Too Many Requests past the limit.
+//!
+//! Keys, keysets, mint quotes, swaps and proof states are served by the `MintBackend` issuing
+//! the tokens. When that is not this mint (see `cdk_mint`), the routes signing with its keysets
+//! are left out: queued outputs, restore, conversion and subscriptions.
+use super::{
+    accounts::AccountStatement,
+    backend::MintBackend,
+    info::MintInfoResponse,
+    melt::Melter,
+    metrics,
+    nuts::{
+        AccountRequest, BalanceRequest, BalanceResponse, CheckStateRequest, CheckStateResponse,
+        ErrorResponse, KeysResponse, KeysetsResponse, MeltQuoteRequest, MeltQuoteResponse,
+        MeltRequest, MintQuoteRequest, MintQuoteResponse, MintRequest, NostrKeyRequest,
+        OutputSignaturesResponse, OutputsRequest, PayoutsRequest, PayoutsResponse, PubkeyRequest,
+        QueueOutputsRequest, SignaturesResponse, SwapRequest,
+    },
+    onchain::OnchainConfig,
+    rounds::Conversion,
+    subscriptions::Subscriptions,
+    Mint,
+};
+use crate::{
+    error::{MintError, MintResult},
+    net,
+    ratelimit::{RateLimitConfig, RateLimiter},
+    status::events::{self, Event, Listener},
+};
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        ConnectInfo, Path, Request, State,
+    },
+    http::StatusCode,
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use roles_logic_sv2::utils::Mutex;
+use std::{net::SocketAddr, sync::Arc, time::Instant};
+use tokio::sync::broadcast::error::RecvError;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info};
+
+/// Payment method paying mint quotes from the balance accrued by accepted shares.
+pub const EHASH_METHOD: &str = "ehash";
+/// Payment method of melt quotes, paying Lightning invoices.
+pub const BOLT11_METHOD: &str = "bolt11";
+/// Payment method of melt quotes paid to a Bitcoin address, see `onchain`.
+pub const ONCHAIN_METHOD: &str = "onchain";
+
+type MintState = Arc<Mutex<Mint>>;
+
+#[derive(Debug, Clone)]
+pub struct ApiState {
+    /// Issues the tokens, `mint` unless set with `with_backend`.
+    backend: Arc<dyn MintBackend>,
+    /// Keeps the accounts, balances and payouts.
+    mint: MintState,
+    /// Whether `mint` signs the tokens too, serving the routes that sign with its keysets.
+    signs: bool,
+    /// `None` when no Lightning backend is configured, melting is disabled then.
+    melter: Option<Arc<Melter>>,
+    /// `None` when on-chain melts are disabled.
+    onchain: Option<Arc<OnchainConfig>>,
+    info: Arc<MintInfoResponse>,
+    limiter: Arc<RateLimiter>,
+}
+
+impl ApiState {
+    pub fn new(
+        mint: MintState,
+        melter: Option<Arc<Melter>>,
+        onchain: Option<OnchainConfig>,
+        info: MintInfoResponse,
+        limits: &RateLimitConfig,
+    ) -> Self {
+        Self {
+            backend: mint.clone(),
+            mint,
+            signs: true,
+            melter,
+            onchain: onchain.map(Arc::new),
+            info: Arc::new(info),
+            limiter: Arc::new(RateLimiter::new("mint", limits)),
+        }
+    }
+
+    /// Issues through `backend`, the mint of the state only keeping the accounts.
+    pub fn with_backend(self, backend: Arc<dyn MintBackend>) -> Self {
+        Self {
+            backend,
+            signs: false,
+            ..self
+        }
+    }
+
+    fn melter(&self, method: &str) -> MintResult<&Melter> {
+        match (method, &self.melter) {
+            (BOLT11_METHOD, Some(melter)) => Ok(melter),
+            _ => Err(MintError::UnsupportedMethod(method.to_string())),
+        }
+    }
+
+    fn onchain(&self) -> MintResult<&OnchainConfig> {
+        self.onchain
+            .as_deref()
+            .ok_or_else(|| MintError::UnsupportedMethod(ONCHAIN_METHOD.to_string()))
+    }
+
+    /// Refuses requests naming `account` past its rate limit.
+    fn check_account(&self, account: &str) -> MintResult<()> {
+        match self.limiter.allow_identity(account) {
+            true => Ok(()),
+            false => Err(MintError::RateLimited(account.to_string())),
+        }
+    }
+
+    /// Refuses requests issuing out of `account` past its rate limit, or without its
+    /// `credential`, see `credentials`.
+    fn check_owner(&self, account: &str, credential: Option<&str>) -> Result<(), ApiError> {
+        self.check_account(account)?;
+        with_mint(&self.mint, |mint| {
+            mint.check_credential(account, credential)
+        })
+    }
+
+    /// Checks melts of `method` are enabled.
+    fn check_melt_method(&self, method: &str) -> MintResult<()> {
+        match method {
+            ONCHAIN_METHOD => self.onchain().map(|_| ()),
+            method => self.melter(method).map(|_| ()),
+        }
+    }
+}
+
+pub fn router(state: ApiState) -> Router {
+    let mut limited = Router::new()
+        .route("/v1/mint/quote/:method", post(post_mint_quote))
+        .route("/v1/mint/:method", post(post_mint))
+        .route("/v1/melt/quote/:method", post(post_melt_quote))
+        .route("/v1/melt/:method", post(post_melt))
+        .route("/v1/swap", post(post_swap))
+        .route("/v1/checkstate", post(post_checkstate))
+        .route("/v1/ehash/balance", post(post_balance))
+        .route("/v1/ehash/account", post(post_account))
+        .route("/v1/ehash/payouts", post(post_payouts))
+        .route("/v1/ehash/pubkey", post(post_pubkey))
+        .route("/v1/ehash/nostr", post(post_nostr_key));
+    let mut open = Router::new()
+        .route("/v1/info", get(get_info))
+        .route("/v1/keys", get(get_keys))
+        .route("/v1/keys/:id", get(get_keyset_keys))
+        .route("/v1/keysets", get(get_keysets))
+        .route("/v1/mint/quote/:method/:quote", get(get_mint_quote))
+        .route("/v1/melt/quote/:method/:quote", get(get_melt_quote));
+    if state.signs {
+        limited = limited
+            .route("/v1/ehash/outputs", post(post_queue_outputs))
+            .route("/v1/ehash/signatures", post(post_output_signatures))
+            .route("/v1/restore", post(post_output_signatures))
+            .route("/v1/ehash/convert", post(post_convert));
+        open = open
+            .route("/v1/ehash/conversion", get(get_conversion))
+            .route("/v1/ws", get(get_ws));
+    }
+    let limited = limited.route_layer(middleware::from_fn_with_state(state.clone(), limit_clients));
+    open.merge(limited).with_state(state)
+}
+
+/// Serves the mint API on `address` until `cancel_token` is cancelled.
+pub async fn serve(
+    state: ApiState,
+    address: &str,
+    cancel_token: CancellationToken,
+) -> MintResult<()> {
+    let listener = net::listen(address)?;
+    let local_addr = listener.local_addr()?;
+    info!("Mint API listening on {}", local_addr);
+    events::publish(Event::ListenerBound {
+        listener: Listener::MintApi,
+        address: local_addr.to_string(),
+    });
+    let service = router(state).into_make_service_with_connect_info::<SocketAddr>();
+    axum::serve(listener, service)
+        .with_graceful_shutdown(cancel_token.cancelled_owned())
+        .await?;
+    Ok(())
+}
+
+/// A `MintError` answered the way NUT-00 specifies errors.
+#[derive(Debug)]
+pub struct ApiError(MintError);
+
+impl From<MintError> for ApiError {
+    fn from(e: MintError) -> Self {
+        ApiError(e)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        debug!("Mint API error: {}", self.0);
+        let body = ErrorResponse {
+            detail: self.0.to_string(),
+            code: error_code(&self.0),
+            error: self.0.code().to_string(),
+        };
+        let status = match self.0 {
+            MintError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            MintError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            _ => StatusCode::BAD_REQUEST,
+        };
+        (status, Json(body)).into_response()
+    }
+}
+
+/// NUT-00 error code of `e`, 0 when the spec has no code for it.
+pub fn error_code(e: &MintError) -> u16 {
+    match e {
+        MintError::OutputAlreadySigned => 10002,
+        MintError::InvalidProof | MintError::SpendingConditions(_) => 10003,
+        MintError::ProofAlreadySpent | MintError::ProofPending => 11001,
+        MintError::UnbalancedTransaction { .. } => 11002,
+        MintError::UnsupportedUnit(_) => 11005,
+        MintError::MixedUnits => 11009,
+        MintError::UnsupportedAmount(_) => 11006,
+        MintError::DuplicateInputs => 11007,
+        MintError::UnknownKeyset(_) => 12001,
+        MintError::InactiveKeyset(_) => 12002,
+        MintError::QuoteNotPaid(_) | MintError::InsufficientBalance { .. } => 20001,
+        MintError::QuoteAlreadyIssued(_) => 20002,
+        MintError::Lightning(_) => 20004,
+        MintError::QuotePending(_) => 20005,
+        MintError::QuoteExpired(_) => 20007,
+        _ => 0,
+    }
+}
+
+/// Refuses requests of a client address past its rate limit. Requests not coming through
+/// `serve` carry no address and aren't limited.
+async fn limit_clients(
+    State(state): State<ApiState>,
+    client: Option<ConnectInfo<SocketAddr>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if let Some(ConnectInfo(address)) = client {
+        if !state.limiter.allow_ip(address.ip()) {
+            return ApiError(MintError::RateLimited(address.ip().to_string())).into_response();
+        }
+    }
+    next.run(request).await
+}
+
+fn with_mint<T>(
+    mint: &MintState,
+    f: impl FnOnce(&mut Mint) -> MintResult<T>,
+) -> Result<T, ApiError> {
+    Ok(mint.safe_lock(f).map_err(MintError::from)??)
+}
+
+fn check_method(method: &str) -> MintResult<()> {
+    match method {
+        EHASH_METHOD => Ok(()),
+        _ => Err(MintError::UnsupportedMethod(method.to_string())),
+    }
+}
+
+async fn get_info(State(state): State<ApiState>) -> Json<MintInfoResponse> {
+    Json(state.info.as_ref().clone())
+}
+
+async fn get_keys(State(state): State<ApiState>) -> Result<Json<KeysResponse>, ApiError> {
+    let keysets = state.backend.keys(None).await?;
+    Ok(Json(KeysResponse { keysets }))
+}
+
+/// Keys of any published keyset, deprecated ones included so wallets can still check old proofs.
+async fn get_keyset_keys(
+    State(state): State<ApiState>,
+    Path(id): Path<String>,
+) -> Result<Json<KeysResponse>, ApiError> {
+    let keysets = state.backend.keys(Some(&id)).await?;
+    Ok(Json(KeysResponse { keysets }))
+}
+
+async fn get_keysets(State(state): State<ApiState>) -> Result<Json<KeysetsResponse>, ApiError> {
+    let keysets = state.backend.keysets().await?;
+    Ok(Json(KeysetsResponse { keysets }))
+}
+
+async fn post_mint_quote(
+    State(state): State<ApiState>,
+    Path(method): Path<String>,
+    Json(request): Json<MintQuoteRequest>,
+) -> Result<Json<MintQuoteResponse>, ApiError> {
+    check_method(&method)?;
+    state.check_owner(&request.account, request.credential.as_deref())?;
+    let started = Instant::now();
+    let quote = state
+        .backend
+        .create_mint_quote(&request.account, request.amount, &request.unit)
+        .await?;
+    metrics::observe_quote(&method, started.elapsed());
+    Ok(Json(quote))
+}
+
+async fn get_mint_quote(
+    State(state): State<ApiState>,
+    Path((method, quote)): Path<(String, String)>,
+) -> Result<Json<MintQuoteResponse>, ApiError> {
+    check_method(&method)?;
+    Ok(Json(state.backend.mint_quote(&quote).await?))
+}
+
+async fn post_mint(
+    State(state): State<ApiState>,
+    Path(method): Path<String>,
+    Json(request): Json<MintRequest>,
+) -> Result<Json<SignaturesResponse>, ApiError> {
+    check_method(&method)?;
+    let signatures = state.backend.mint(&request.quote, &request.outputs).await?;
+    Ok(Json(SignaturesResponse { signatures }))
+}
+
+async fn post_melt_quote(
+    State(state): State<ApiState>,
+    Path(method): Path<String>,
+    Json(request): Json<MeltQuoteRequest>,
+) -> Result<Json<MeltQuoteResponse>, ApiError> {
+    let started = Instant::now();
+    let quote = if method != ONCHAIN_METHOD {
+        state.melter(&method)?.quote(request).await?
+    } else {
+        let config = state.onchain()?;
+        let amount = request.amount.ok_or(MintError::UnsupportedAmount(0))?;
+        with_mint(&state.mint, |mint| {
+            mint.create_onchain_melt_quote(&request.request, amount, &request.unit, config)
+        })?
+    };
+    // only methods enabled get this far
+    metrics::observe_quote(&method, started.elapsed());
+    Ok(Json(quote))
+}
+
+async fn get_melt_quote(
+    State(state): State<ApiState>,
+    Path((method, quote)): Path<(String, String)>,
+) -> Result<Json<MeltQuoteResponse>, ApiError> {
+    state.check_melt_method(&method)?;
+    Ok(Json(with_mint(&state.mint, |mint| {
+        mint.melt_quote(&quote)
+    })?))
+}
+
+async fn post_melt(
+    State(state): State<ApiState>,
+    Path(method): Path<String>,
+    Json(request): Json<MeltRequest>,
+) -> Result<Json<MeltQuoteResponse>, ApiError> {
+    if method != ONCHAIN_METHOD {
+        return Ok(Json(state.melter(&method)?.melt(request).await?));
+    }
+    let config = state.onchain()?;
+    Ok(Json(with_mint(&state.mint, |mint| {
+        mint.melt_onchain(&request.quote, &request.inputs, &request.outputs, config)
+    })?))
+}
+
+async fn post_swap(
+    State(state): State<ApiState>,
+    Json(request): Json<SwapRequest>,
+) -> Result<Json<SignaturesResponse>, ApiError> {
+    let signatures = state
+        .backend
+        .swap(&request.inputs, &request.outputs)
+        .await?;
+    Ok(Json(SignaturesResponse { signatures }))
+}
+
+async fn post_checkstate(
+    State(state): State<ApiState>,
+    Json(request): Json<CheckStateRequest>,
+) -> Result<Json<CheckStateResponse>, ApiError> {
+    let states = state.backend.proof_states(&request.ys).await?;
+    Ok(Json(CheckStateResponse { states }))
+}
+
+async fn post_queue_outputs(
+    State(state): State<ApiState>,
+    Json(request): Json<QueueOutputsRequest>,
+) -> Result<Json<OutputsRequest>, ApiError> {
+    state.check_owner(&request.account, request.credential.as_deref())?;
+    with_mint(&state.mint, |mint| {
+        mint.queue_outputs(&request.account, &request.outputs)
+    })?;
+    Ok(Json(OutputsRequest {
+        outputs: request.outputs,
+    }))
+}
+
+/// Signatures issued for the requested outputs, leaving out those not signed.
+async fn post_output_signatures(
+    State(state): State<ApiState>,
+    Json(request): Json<OutputsRequest>,
+) -> Result<Json<OutputSignaturesResponse>, ApiError> {
+    let signatures = with_mint(&state.mint, |mint| mint.signatures(&request.outputs))?;
+    let (outputs, signatures) = request
+        .outputs
+        .into_iter()
+        .zip(signatures)
+        .filter_map(|(output, signature)| Some((output, signature?)))
+        .unzip();
+    Ok(Json(OutputSignaturesResponse {
+        outputs,
+        signatures,
+    }))
+}
+
+async fn get_conversion(State(state): State<ApiState>) -> Result<Json<Conversion>, ApiError> {
+    Ok(Json(with_mint(&state.mint, |mint| mint.conversion())?))
+}
+
+/// Same body as a swap, with ehash inputs and sat outputs.
+async fn post_convert(
+    State(state): State<ApiState>,
+    Json(request): Json<SwapRequest>,
+) -> Result<Json<SignaturesResponse>, ApiError> {
+    let signatures = with_mint(&state.mint, |mint| {
+        mint.convert(&request.inputs, &request.outputs)
+    })?;
+    Ok(Json(SignaturesResponse { signatures }))
+}
+
+/// Registers the key payouts are locked to, with the credential of the account. A key already
+/// registered is only replaced with its signature on the new one.
+async fn post_pubkey(
+    State(state): State<ApiState>,
+    Json(request): Json<PubkeyRequest>,
+) -> Result<Json<PubkeyRequest>, ApiError> {
+    state.check_owner(&request.account, request.credential.as_deref())?;
+    with_mint(&state.mint, |mint| {
+        mint.register_pubkey(
+            &request.account,
+            &request.pubkey,
+            request.signature.as_deref(),
+        )
+    })?;
+    Ok(Json(request))
+}
+
+/// Registers the Nostr key payouts in `nostr` mode are sent to, like `post_pubkey` the key
+/// payouts are locked to.
+async fn post_nostr_key(
+    State(state): State<ApiState>,
+    Json(request): Json<NostrKeyRequest>,
+) -> Result<Json<NostrKeyRequest>, ApiError> {
+    state.check_owner(&request.account, request.credential.as_deref())?;
+    with_mint(&state.mint, |mint| {
+        mint.register_nostr_key(
+            &request.account,
+            &request.pubkey,
+            request.signature.as_deref(),
+        )
+    })?;
+    Ok(Json(request))
+}
+
+async fn post_balance(
+    State(state): State<ApiState>,
+    Json(request): Json<BalanceRequest>,
+) -> Result<Json<BalanceResponse>, ApiError> {
+    state.check_owner(&request.account, request.credential.as_deref())?;
+    let balances = with_mint(&state.mint, |mint| {
+        mint.units()
+            .iter()
+            .map(|unit| Ok((unit.clone(), mint.balance(&request.account, unit)?)))
+            .collect()
+    })?;
+    Ok(Json(BalanceResponse { balances }))
+}
+
+async fn post_account(
+    State(state): State<ApiState>,
+    Json(request): Json<AccountRequest>,
+) -> Result<Json<AccountStatement>, ApiError> {
+    state.check_owner(&request.account, request.credential.as_deref())?;
+    Ok(Json(with_mint(&state.mint, |mint| {
+        mint.account_statement(&request.account)
+    })?))
+}
+
+async fn post_payouts(
+    State(state): State<ApiState>,
+    Json(request): Json<PayoutsRequest>,
+) -> Result<Json<PayoutsResponse>, ApiError> {
+    state.check_owner(&request.account, request.credential.as_deref())?;
+    let payouts = with_mint(&state.mint, |mint| mint.pick_up_payouts(&request.account))?;
+    Ok(Json(PayoutsResponse {
+        tokens: payouts.into_iter().map(|payout| payout.token).collect(),
+    }))
+}
+
+async fn get_ws(State(state): State<ApiState>, ws: WebSocketUpgrade) -> Response {
+    ws.on_upgrade(move |socket| serve_subscriptions(state.mint, socket))
+}
+
+/// Answers the requests of one WebSocket connection and pushes its notifications, until either
+/// side closes it.
+async fn serve_subscriptions(mint: MintState, mut socket: WebSocket) {
+    let Ok(mut events) = mint.safe_lock(|mint| mint.subscribe()) else {
+        return;
+    };
+    let mut subscriptions = Subscriptions::default();
+    loop {
+        let replies = tokio::select! {
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    with_mint(&mint, |mint| Ok(subscriptions.handle(mint, &text)))
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => continue,
+            },
+            event = events.recv() => match event {
+                Ok(event) => with_mint(&mint, |mint| Ok(subscriptions.on_event(mint, &event))),
+                Err(RecvError::Lagged(missed)) => {
+                    debug!("Mint API: subscriber missed {} events", missed);
+                    with_mint(&mint, |mint| Ok(subscriptions.refresh(mint)))
+                }
+                Err(RecvError::Closed) => break,
+            },
+        };
+        let Ok(replies) = replies else {
+            break;
+        };
+        for reply in replies {
+            if socket.send(Message::Text(reply)).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pool_mint::mint::{
+        client::{self, MintClient},
+        credentials,
+        info::MintInfoConfig,
+        payout::{PayoutConfig, PayoutMode},
+        MintConfig, EHASH_UNIT,
+    };
+
+    /// Serves the API of `mint`, returning its URL.
+    async fn serve_api(mint: MintState) -> String {
+        let info = mint
+            .safe_lock(|mint| {
+                MintInfoResponse::new(&MintInfoConfig::default(), "potato", mint, false, None)
+            })
+            .unwrap();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let state = ApiState::new(mint, None, None, info, &RateLimitConfig::default());
+        tokio::spawn(async move { axum::serve(listener, router(state)).await });
+        url
+    }
+
+    #[tokio::test]
+    async fn queues_outputs_for_the_owner_of_the_account_only() {
+        let mut mint = Mint::from_master_secret(&[4; 32], &MintConfig::default()).unwrap();
+        let credential = mint.issue_credential("alice").unwrap();
+        let client = MintClient::new(&serve_api(Arc::new(Mutex::new(mint))).await, None).unwrap();
+        let keyset = client.keys(EHASH_UNIT).await.unwrap();
+        let (outputs, _) = client::blank_outputs(&keyset, 3, None).unwrap();
+        let mut request = QueueOutputsRequest {
+            account: "alice".into(),
+            outputs,
+            credential: None,
+        };
+        for credential in [None, Some("0123".to_string())] {
+            request.credential = credential;
+            let refused = client.post::<OutputsRequest>("/v1/ehash/outputs", &request);
+            assert!(matches!(
+                refused.await,
+                Err(MintError::MintRequest(e)) if e.starts_with("401")
+            ));
+        }
+        request.credential = Some(credential);
+        let queued: OutputsRequest = client.post("/v1/ehash/outputs", &request).await.unwrap();
+        assert_eq!(queued.outputs, request.outputs);
+    }
+
+    #[tokio::test]
+    async fn hands_out_payouts_to_the_owner_of_the_account_only() {
+        let mut mint = Mint::from_master_secret(&[5; 32], &MintConfig::default()).unwrap();
+        let credential = mint.issue_credential("alice").unwrap();
+        mint.credit_share("alice", 8).unwrap();
+        let config = PayoutConfig {
+            mode: PayoutMode::Hold,
+            unit: EHASH_UNIT.into(),
+            min_amount: 1,
+            ..Default::default()
+        };
+        mint.pay_out_due(&config, "http://127.0.0.1").unwrap();
+        let mint = Arc::new(Mutex::new(mint));
+        let client = MintClient::new(&serve_api(mint.clone()).await, None).unwrap();
+        let mut request = PayoutsRequest {
+            account: "alice".into(),
+            credential: None,
+        };
+        let refused = client.post::<PayoutsResponse>("/v1/ehash/payouts", &request);
+        assert!(refused.await.is_err());
+        // still held for the owner
+        let held = mint.safe_lock(|mint| mint.undelivered_payouts().unwrap());
+        assert_eq!(held.unwrap().len(), 1);
+
+        request.credential = Some(credential);
+        let payouts: PayoutsResponse = client.post("/v1/ehash/payouts", &request).await.unwrap();
+        assert_eq!(payouts.tokens.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn binds_keys_for_the_owner_of_the_account_only() {
+        let mut mint = Mint::from_master_secret(&[6; 32], &MintConfig::default()).unwrap();
+        let credential = mint.issue_credential("alice").unwrap();
+        let client = MintClient::new(&serve_api(Arc::new(Mutex::new(mint))).await, None).unwrap();
+        let secp = secp256k1::Secp256k1::new();
+        let key = secp256k1::SecretKey::from_slice(&[7; 32]).unwrap();
+        let mut request = PubkeyRequest {
+            account: "alice".into(),
+            pubkey: key.public_key(&secp),
+            credential: None,
+            signature: None,
+        };
+        let refused = client.post::<PubkeyRequest>("/v1/ehash/pubkey", &request);
+        assert!(refused.await.is_err());
+        request.credential = Some(credential);
+        client
+            .post::<PubkeyRequest>("/v1/ehash/pubkey", &request)
+            .await
+            .unwrap();
+
+        // the credential alone doesn't replace the key
+        request.pubkey = secp256k1::SecretKey::from_slice(&[8; 32])
+            .unwrap()
+            .public_key(&secp);
+        let refused = client.post::<PubkeyRequest>("/v1/ehash/pubkey", &request);
+        assert!(refused.await.is_err());
+        request.signature = Some(credentials::sign_rebind(
+            credentials::PAYOUT_KEY,
+            "alice",
+            &request.pubkey.to_string(),
+            &key,
+        ));
+        client
+            .post::<PubkeyRequest>("/v1/ehash/pubkey", &request)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn answers_errors_with_stable_codes() {
+        let response = ApiError(MintError::ProofAlreadySpent).into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: ErrorResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body.code, 11001);
+        assert_eq!(body.error, "proof_already_spent");
+
+        // NUT-00 has no code for it, ours tells it apart from other errors
+        let response = ApiError(MintError::RateLimited("203.0.113.7".into())).into_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: ErrorResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!((body.code, body.error.as_str()), (0, "rate_limited"));
+    }
+}
@@ -0,0 +1,306 @@
+//! Solvency audit of the mint, see `potato mint audit`. Reconciles what the mint owes in sat,
+//! to holders of unspent tokens and to accounts not paid out yet, against the matured coinbase
+//! rewards backing it and the funds actually held by the Lightning node and on-chain wallet.
+//!
+//! Every sat the mint owes comes from the reward of a matured round and only leaves through paid
+//! melts, or stays with the mint as a fee. So the matured rewards, less the paid invoices and
+//! on-chain payouts and the fees, must cover the liabilities. What they cover beyond that is the
+//! rounding dust and the rewards of rounds without shares, kept by the pool, less the routing
+//! fees of melts.
+//!
+//! Ehash is a claim on future rewards rather than on sat held, so it is only reported.
+use super::{
+    db::{Ledger, MintDb},
+    lifecycle::{now_secs, KeysetInfo},
+    rounds::RoundState,
+    MintConfig, EHASH_UNIT, SAT_UNIT,
+};
+use crate::error::{MintError, MintResult};
+use serde::Serialize;
+use std::{collections::BTreeMap, fs};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Liabilities {
+    /// Unspent tokens, including those reserved by melts in flight.
+    pub tokens: u64,
+    /// Balances of accounts not withdrawn yet.
+    pub balances: u64,
+    /// Reserve of the ehash withdrawn before its round matured, see `rounds::Conversion`.
+    pub conversion_reserve: u64,
+    /// On-chain melts waiting for their batch transaction, see `onchain`.
+    pub onchain_queued: u64,
+    pub total: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct SatAudit {
+    pub liabilities: Liabilities,
+    /// Rewards of every matured round.
+    pub matured_rewards: u64,
+    /// Amounts of the invoices and on-chain payouts paid by melts.
+    pub melted: u64,
+    pub fees_collected: u64,
+    /// Tokens reserved by melts in flight, which may have left already.
+    pub pending_melts: u64,
+    /// Matured rewards neither owed, melted nor collected as fees.
+    pub unallocated: i64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct EhashAudit {
+    pub tokens: u64,
+    pub balances: u64,
+    /// Withdrawn ehash of matured rounds still converting into sat.
+    pub conversion_outstanding: u64,
+}
+
+/// Sat held outside the mint's books, `None` where not checked.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Reserves {
+    pub lightning: Option<u64>,
+    pub onchain: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct AuditReport {
+    pub created_at: u64,
+    /// `None` for a mint not issuing sat.
+    pub sat: Option<SatAudit>,
+    pub ehash: Option<EhashAudit>,
+    pub reserves: Reserves,
+    pub discrepancies: Vec<String>,
+}
+
+impl AuditReport {
+    pub fn is_solvent(&self) -> bool {
+        self.discrepancies.is_empty()
+    }
+
+    /// Checks the sat held by the Lightning node and on-chain against the liabilities. Funds not
+    /// checked count as nothing, so at least one of them must be given.
+    pub fn check_reserves(&mut self, lightning: Option<u64>, onchain: Option<u64>) {
+        self.reserves = Reserves { lightning, onchain };
+        let Some(sat) = &self.sat else {
+            return;
+        };
+        if lightning.is_none() && onchain.is_none() {
+            return;
+        }
+        let held = lightning.unwrap_or_default() + onchain.unwrap_or_default();
+        // melts in flight may have paid their invoice already
+        let owed = sat.liabilities.total.saturating_sub(sat.pending_melts);
+        if held < owed {
+            self.discrepancies.push(format!(
+                "held reserves of {} sat fall {} sat short of the liabilities",
+                held,
+                owed - held
+            ));
+        }
+    }
+}
+
+/// Audits the books of the mint configured by `config`, running or not. Reserves are left to
+/// `AuditReport::check_reserves`.
+pub fn books(config: &MintConfig) -> MintResult<AuditReport> {
+    let keysets: Vec<KeysetInfo> = serde_json::from_str(&fs::read_to_string(&config.keysets_path)?)
+        .map_err(|e| MintError::Storage(e.to_string()))?;
+    audit(&MintDb::open(&config.db_path)?, &keysets, &config.units)
+}
+
+pub(super) fn audit(
+    db: &MintDb,
+    keysets: &[KeysetInfo],
+    units: &[String],
+) -> MintResult<AuditReport> {
+    let mut discrepancies = vec![];
+    let unit_of = |id: &str| keysets.iter().find(|k| k.id == id).map(|k| k.unit.clone());
+    let mut issued = BTreeMap::<String, u64>::new();
+    let mut spent = BTreeMap::<String, u64>::new();
+    let mut pending = BTreeMap::<String, u64>::new();
+    for (sums, by_unit) in [
+        (db.issued_by_keyset()?, &mut issued),
+        (db.spent_by_keyset()?, &mut spent),
+        (db.pending_by_keyset()?, &mut pending),
+    ] {
+        for (id, amount) in sums {
+            match unit_of(&id) {
+                Some(unit) => *by_unit.entry(unit).or_default() += amount,
+                None => {
+                    discrepancies.push(format!("{} recorded under unknown keyset {}", amount, id))
+                }
+            }
+        }
+    }
+    let mut tokens = BTreeMap::new();
+    for unit in units {
+        let issued = issued.get(unit).copied().unwrap_or_default();
+        let spent = spent.get(unit).copied().unwrap_or_default();
+        if spent > issued {
+            discrepancies.push(format!(
+                "{} {} more spent than ever issued",
+                spent - issued,
+                unit
+            ));
+        }
+        tokens.insert(unit.as_str(), issued.saturating_sub(spent));
+    }
+    let total = |ledger| -> MintResult<u64> {
+        Ok(db
+            .balances(ledger, 0)?
+            .iter()
+            .map(|(_, amount)| amount)
+            .sum())
+    };
+    let conversion = db.conversion()?;
+
+    let sat = match tokens.get(SAT_UNIT) {
+        Some(tokens) => {
+            let mut liabilities = Liabilities {
+                tokens: *tokens,
+                balances: total(Ledger::Sat)?,
+                conversion_reserve: conversion.reserve,
+                onchain_queued: db.queued_onchain()?,
+                total: 0,
+            };
+            liabilities.total = liabilities.tokens
+                + liabilities.balances
+                + liabilities.conversion_reserve
+                + liabilities.onchain_queued;
+            let matured_rewards = db
+                .rounds(RoundState::Matured)?
+                .iter()
+                .filter_map(|round| round.reward)
+                .sum();
+            let melted = db.melted()?;
+            let fees_collected = db
+                .fees()?
+                .iter()
+                .filter(|fee| fee.unit == SAT_UNIT)
+                .map(|fee| fee.amount)
+                .sum();
+            let unallocated = matured_rewards as i64
+                - melted as i64
+                - fees_collected as i64
+                - liabilities.total as i64;
+            if unallocated < 0 {
+                discrepancies.push(format!(
+                    "liabilities exceed the matured rewards left by {} sat",
+                    -unallocated
+                ));
+            }
+            Some(SatAudit {
+                liabilities,
+                matured_rewards,
+                melted,
+                fees_collected,
+                pending_melts: pending.get(SAT_UNIT).copied().unwrap_or_default(),
+                unallocated,
+            })
+        }
+        None => None,
+    };
+    let ehash = match tokens.get(EHASH_UNIT) {
+        Some(tokens) => Some(EhashAudit {
+            tokens: *tokens,
+            balances: total(Ledger::Ehash)?,
+            conversion_outstanding: conversion.outstanding,
+        }),
+        None => None,
+    };
+    Ok(AuditReport {
+        created_at: now_secs(),
+        sat,
+        ehash,
+        reserves: Reserves::default(),
+        discrepancies,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pool_mint::mint::{
+        client,
+        lightning::{DecodedInvoice, LightningConfig, PaymentStatus},
+        nuts::KeySet,
+        Mint,
+    };
+
+    fn audit_of(mint: &Mint) -> AuditReport {
+        audit(&mint.db, &mint.keyset_infos(), mint.units()).unwrap()
+    }
+
+    #[test]
+    fn reconciles_liabilities_with_rewards_and_reserves() {
+        let mut mint = Mint::from_master_secret(&[4; 32], &MintConfig::default()).unwrap();
+        mint.credit_share("alice", 3).unwrap();
+        mint.credit_share("bob", 2).unwrap();
+        let round = mint.found_block("coinbase", 101, None).unwrap();
+        mint.mature_round(round).unwrap();
+        mint.credit_share("bob", 7).unwrap();
+
+        let active = mint.active_keyset(SAT_UNIT).unwrap();
+        let keyset = KeySet {
+            id: active.id.clone(),
+            unit: SAT_UNIT.to_string(),
+            keys: active
+                .public_keys()
+                .into_iter()
+                .map(|(amount, key)| (amount, key.to_string()))
+                .collect(),
+        };
+        let (outputs, secrets) = client::blank_outputs(&keyset, 40, None).unwrap();
+        let signatures = mint.withdraw("alice", &outputs).unwrap();
+        let proofs = client::unblind(&keyset, &outputs, &signatures, secrets).unwrap();
+        let report = audit_of(&mint);
+        let sat = report.sat.as_ref().unwrap();
+        // 60 to alice and 40 to bob, 1 sat of rounding dust
+        assert_eq!((sat.liabilities.tokens, sat.liabilities.balances), (40, 60));
+        assert_eq!(sat.unallocated, 1);
+        assert_eq!(report.ehash.as_ref().unwrap().balances, 7);
+        assert!(report.is_solvent());
+
+        let mut report = audit_of(&mint);
+        report.check_reserves(Some(60), Some(39));
+        assert_eq!(report.discrepancies.len(), 1);
+        let mut report = audit_of(&mint);
+        report.check_reserves(Some(60), Some(40));
+        assert!(report.is_solvent());
+
+        let config: LightningConfig =
+            serde_json::from_str(r#"{"backend": "cln", "rpc_path": ""}"#).unwrap();
+        let invoice = DecodedInvoice {
+            payment_hash: "00".repeat(32),
+            amount_msat: 30_000,
+        };
+        let quote = mint
+            .create_melt_quote("lnbc", SAT_UNIT, &invoice, &config)
+            .unwrap();
+        mint.begin_melt(&quote.quote, &proofs, &[]).unwrap();
+        // the invoice may be paid already, so the reserves only need to cover the balances
+        let mut report = audit_of(&mint);
+        assert_eq!(report.sat.as_ref().unwrap().pending_melts, 40);
+        report.check_reserves(Some(60), None);
+        assert!(report.is_solvent());
+
+        let paid = PaymentStatus::Paid {
+            preimage: "11".repeat(32),
+            fee_msat: 1_000,
+        };
+        mint.finish_melt(&quote.quote, &paid, &[]).unwrap();
+        let sat = audit_of(&mint).sat.unwrap();
+        // the 9 sat left over without change outputs stay with the mint
+        assert_eq!(
+            (sat.melted, sat.liabilities.total, sat.unallocated),
+            (30, 60, 11)
+        );
+
+        let keysets: Vec<_> = mint
+            .keyset_infos()
+            .into_iter()
+            .filter(|info| info.unit != SAT_UNIT)
+            .collect();
+        let report = audit(&mint.db, &keysets, mint.units()).unwrap();
+        assert!(!report.is_solvent());
+    }
+}
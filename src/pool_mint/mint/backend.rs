@@ -0,0 +1,289 @@
+//! What the pool and the Cashu API need from a mint: crediting accepted shares, closing rounds
+//! with found blocks and handing over the payouts it delivers over stratum, and the NUT-01 to
+//! NUT-07 operations of issuance. The pool and the API only hold a `MintBackend`, so the mint
+//! issuing the tokens can be swapped without touching pool code.
+//!
+//! The built-in implementation is the mint of this crate behind its lock. `cdk_mint` issues with
+//! a mint of the Cashu Dev Kit instead, this mint keeping the accounts. Tokens of a mint run
+//! elsewhere are paid out through `external`. The pool credits through the `writer` of the
+//! backend, off its share path.
+use super::{
+    journal::Credit,
+    keyset::Keyset,
+    lifecycle::{KeysetInfo, KeysetState},
+    nuts::{
+        BlindSignature, BlindedMessage, KeySet, KeySetSummary, MintQuoteResponse, Proof,
+        ProofState, Token,
+    },
+    payout::Payout,
+    Mint,
+};
+use crate::error::{MintError, MintResult};
+use async_trait::async_trait;
+use roles_logic_sv2::utils::Mutex;
+use secp256k1::PublicKey;
+use tracing::error;
+
+#[async_trait]
+pub trait MintBackend: std::fmt::Debug + Send + Sync {
+    /// Credits an accepted share of `weight` to `account`.
+    fn credit_share(&self, account: &str, weight: u64) -> MintResult<()>;
+
+    /// Credits accepted shares, `(account, weight)`, at once.
+    fn credit_shares(&self, shares: &[(String, u64)]) -> MintResult<()> {
+        shares
+            .iter()
+            .try_for_each(|(account, weight)| self.credit_share(account, *weight))
+    }
+
+    /// Closes the open round with a block found by the pool through a share of `finder`, paying
+    /// `reward` sat through `coinbase_txid`.
+    fn found_block(&self, coinbase_txid: &str, reward: u64, finder: Option<&str>)
+        -> MintResult<()>;
+
+    /// Payouts minted and not delivered yet.
+    fn undelivered_payouts(&self) -> MintResult<Vec<Payout>>;
+
+    fn mark_payout_delivered(&self, id: &str) -> MintResult<()>;
+
+    /// Keys of the active keysets, or of keyset `id` if given, deprecated ones included so
+    /// wallets can still check old proofs (NUT-01).
+    async fn keys(&self, id: Option<&str>) -> MintResult<Vec<KeySet>>;
+
+    /// Every published keyset (NUT-02).
+    async fn keysets(&self) -> MintResult<Vec<KeySetSummary>>;
+
+    /// Creates a NUT-04 quote to mint `amount` from the `unit` balance of `account`.
+    async fn create_mint_quote(
+        &self,
+        account: &str,
+        amount: u64,
+        unit: &str,
+    ) -> MintResult<MintQuoteResponse>;
+
+    async fn mint_quote(&self, id: &str) -> MintResult<MintQuoteResponse>;
+
+    /// Signs the outputs of a paid quote, which must add up to the quoted amount.
+    async fn mint(
+        &self,
+        quote: &str,
+        outputs: &[BlindedMessage],
+    ) -> MintResult<Vec<BlindSignature>>;
+
+    /// Redeems `inputs` for new tokens of the same total value less the fee (NUT-03).
+    async fn swap(
+        &self,
+        inputs: &[Proof],
+        outputs: &[BlindedMessage],
+    ) -> MintResult<Vec<BlindSignature>>;
+
+    /// Whether the proofs of `ys` are unspent, reserved by a melt or spent (NUT-07).
+    async fn proof_states(&self, ys: &[PublicKey]) -> MintResult<Vec<ProofState>>;
+}
+
+/// Mints the payouts of the accounts this mint keeps as tokens of another one, see `external` and
+/// `cdk_mint`.
+#[async_trait]
+pub trait PayoutIssuer: std::fmt::Debug + Send + Sync {
+    /// Has the mint issue a token of `amount` `unit` for `account`, locked to `pubkey` if given.
+    async fn issue(
+        &self,
+        account: &str,
+        unit: &str,
+        amount: u64,
+        pubkey: Option<&PublicKey>,
+    ) -> MintResult<Token>;
+
+    /// Pays `amount` of the `unit` balance of `account` out as a token of the mint, debiting the
+    /// balance once the token is issued.
+    async fn pay_out(
+        &self,
+        accounts: &Mutex<Mint>,
+        account: &str,
+        unit: &str,
+        amount: u64,
+        pubkey: Option<&PublicKey>,
+    ) -> MintResult<Payout> {
+        let token = self.issue(account, unit, amount, pubkey).await?;
+        record_payout(accounts, account, unit, amount, &token)
+    }
+}
+
+/// Debits the balance of `account` for `token` and keeps it until delivered. The token exists by
+/// then, so it is logged if that fails.
+pub fn record_payout(
+    accounts: &Mutex<Mint>,
+    account: &str,
+    unit: &str,
+    amount: u64,
+    token: &Token,
+) -> MintResult<Payout> {
+    accounts
+        .safe_lock(|m| m.record_payout(account, unit, amount, token))?
+        .inspect_err(|e| {
+            error!(
+                "Mint: recording payout of {} {} to {} failed: {}, token: {}",
+                amount,
+                unit,
+                account,
+                e,
+                token.encode().unwrap_or_default()
+            )
+        })
+}
+
+#[async_trait]
+impl MintBackend for Mutex<Mint> {
+    fn credit_share(&self, account: &str, weight: u64) -> MintResult<()> {
+        self.safe_lock(|m| m.credit_share(account, weight))?
+    }
+
+    fn credit_shares(&self, shares: &[(String, u64)]) -> MintResult<()> {
+        self.safe_lock(|m| m.credit_shares(shares))?
+    }
+
+    fn found_block(
+        &self,
+        coinbase_txid: &str,
+        reward: u64,
+        finder: Option<&str>,
+    ) -> MintResult<()> {
+        let credit = Credit::Block {
+            coinbase_txid: coinbase_txid.to_string(),
+            reward,
+            finder: finder.map(str::to_string),
+        };
+        // journaled like shares, so a block found during a database outage still closes its round
+        self.safe_lock(|m| m.credit(credit))?
+    }
+
+    fn undelivered_payouts(&self) -> MintResult<Vec<Payout>> {
+        self.safe_lock(|m| m.undelivered_payouts())?
+    }
+
+    fn mark_payout_delivered(&self, id: &str) -> MintResult<()> {
+        self.safe_lock(|m| m.mark_payout_delivered(id))?
+    }
+
+    async fn keys(&self, id: Option<&str>) -> MintResult<Vec<KeySet>> {
+        let keysets = self.safe_lock(|m| {
+            m.keysets()
+                .iter()
+                .filter(|(info, _)| match id {
+                    Some(id) => info.id == id && info.state != KeysetState::Pending,
+                    None => info.state == KeysetState::Active,
+                })
+                .map(|(info, keyset)| key_set(info, keyset))
+                .collect::<Vec<_>>()
+        })?;
+        match id {
+            Some(id) if keysets.is_empty() => Err(MintError::UnknownKeyset(id.to_string())),
+            _ => Ok(keysets),
+        }
+    }
+
+    async fn keysets(&self) -> MintResult<Vec<KeySetSummary>> {
+        Ok(self.safe_lock(|m| {
+            m.keyset_infos()
+                .into_iter()
+                .filter(|info| info.state != KeysetState::Pending)
+                .map(|info| KeySetSummary {
+                    active: info.state == KeysetState::Active,
+                    input_fee_ppk: m.input_fee_ppk(&info.id),
+                    epoch: info.epoch.map(|epoch| epoch.number),
+                    id: info.id,
+                    unit: info.unit,
+                })
+                .collect()
+        })?)
+    }
+
+    async fn create_mint_quote(
+        &self,
+        account: &str,
+        amount: u64,
+        unit: &str,
+    ) -> MintResult<MintQuoteResponse> {
+        self.safe_lock(|m| m.create_mint_quote(account, amount, unit))?
+    }
+
+    async fn mint_quote(&self, id: &str) -> MintResult<MintQuoteResponse> {
+        self.safe_lock(|m| m.mint_quote(id))?
+    }
+
+    async fn mint(
+        &self,
+        quote: &str,
+        outputs: &[BlindedMessage],
+    ) -> MintResult<Vec<BlindSignature>> {
+        self.safe_lock(|m| m.mint(quote, outputs))?
+    }
+
+    async fn swap(
+        &self,
+        inputs: &[Proof],
+        outputs: &[BlindedMessage],
+    ) -> MintResult<Vec<BlindSignature>> {
+        self.safe_lock(|m| m.swap(inputs, outputs))?
+    }
+
+    async fn proof_states(&self, ys: &[PublicKey]) -> MintResult<Vec<ProofState>> {
+        self.safe_lock(|m| m.proof_states(ys))?
+    }
+}
+
+fn key_set(info: &KeysetInfo, keyset: &Keyset) -> KeySet {
+    KeySet {
+        id: info.id.clone(),
+        unit: info.unit.clone(),
+        keys: keyset
+            .public_keys()
+            .into_iter()
+            .map(|(amount, key)| (amount, key.to_string()))
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pool_mint::mint::{MintConfig, EHASH_UNIT};
+    use std::sync::Arc;
+
+    #[test]
+    fn serves_the_pool_through_the_trait() {
+        let config = MintConfig::default();
+        let mint = Arc::new(Mutex::new(
+            Mint::from_master_secret(&[1; 32], &config).unwrap(),
+        ));
+        let backend: Arc<dyn MintBackend> = mint.clone();
+        backend.credit_share("alice", 3).unwrap();
+        backend.found_block("coinbase", 16, Some("alice")).unwrap();
+        assert!(backend.undelivered_payouts().unwrap().is_empty());
+        mint.safe_lock(|m| {
+            assert_eq!(m.balance("alice", EHASH_UNIT).unwrap(), 3);
+            let rounds = m.immature_rounds().unwrap();
+            assert_eq!(rounds.len(), 1);
+            assert_eq!(rounds[0].finder.as_deref(), Some("alice"));
+        })
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn publishes_the_keys_of_active_and_named_keysets() {
+        let config = MintConfig::default();
+        let mint = Mutex::new(Mint::from_master_secret(&[1; 32], &config).unwrap());
+        let active = mint.keys(None).await.unwrap();
+        assert_eq!(active.len(), config.units.len());
+        let id = active[0].id.clone();
+        assert_eq!(mint.keys(Some(&id)).await.unwrap(), active[..1]);
+        assert!(matches!(
+            mint.keys(Some("00ffffffffffffff")).await,
+            Err(MintError::UnknownKeyset(_))
+        ));
+        let keysets = mint.keysets().await.unwrap();
+        assert!(keysets
+            .iter()
+            .any(|keyset| keyset.id == id && keyset.active));
+    }
+}
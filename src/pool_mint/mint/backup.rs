@@ -0,0 +1,307 @@
+//! Encrypted backups of what the mint can't derive again: the master secret all keys derive
+//! from, the list of generated keysets, and a snapshot of the database with balances, issued
+//! signatures and spent proofs. Restoring one after a disk failure lets miners still redeem the
+//! tokens they hold, and keeps the tokens already redeemed from being redeemed again.
+//!
+//! Backups are sealed with ChaCha20-Poly1305 under a key stretched from a passphrase with
+//! Argon2id, so they can be kept off the pool host. A restore checks that every keyset derives
+//! from the master secret and that the database only references those keysets before writing
+//! anything.
+use super::{
+    db::MintDb,
+    keyset,
+    lifecycle::{now_secs, KeysetInfo},
+    MintConfig,
+};
+use crate::error::{MintError, MintResult};
+use argon2::Argon2;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, fs, path::Path};
+use tracing::info;
+
+/// Start of every backup file, authenticated along with the contents.
+const MAGIC: &[u8; 9] = b"POTATOBK1";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct BackupContents {
+    created_at: u64,
+    /// Hex encoded, as in the master secret file.
+    master_secret: String,
+    keysets: Vec<KeysetInfo>,
+    /// The SQLite database file, base64 encoded.
+    database: String,
+}
+
+/// What a backup holds, once checked against its keys.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackupSummary {
+    pub created_at: u64,
+    pub keysets: usize,
+    pub signatures: u64,
+    pub spent_proofs: u64,
+}
+
+/// Backs up the mint configured by `config`, running or not, encrypted under `passphrase`.
+pub fn export(config: &MintConfig, passphrase: &str) -> MintResult<(Vec<u8>, BackupSummary)> {
+    let master_secret = keyset::read_master_secret(&config.master_secret_path)?;
+    let keysets: Vec<KeysetInfo> = serde_json::from_str(&fs::read_to_string(&config.keysets_path)?)
+        .map_err(|e| MintError::Storage(e.to_string()))?;
+    let snapshot_path = format!("{}.backup-{}", config.db_path, std::process::id());
+    remove_database(&snapshot_path)?;
+    MintDb::open(&config.db_path)?.snapshot(&snapshot_path)?;
+    let database = fs::read(&snapshot_path);
+    remove_database(&snapshot_path)?;
+    let contents = BackupContents {
+        created_at: now_secs(),
+        master_secret: hex::encode(master_secret),
+        keysets,
+        database: STANDARD.encode(database?),
+    };
+    // the same check a restore makes, so an unusable backup is noticed right away
+    let summary = check(&contents, config.max_order, &snapshot_path);
+    remove_database(&snapshot_path)?;
+    let summary = summary?;
+    let plaintext = serde_json::to_vec(&contents).map_err(|e| MintError::Backup(e.to_string()))?;
+    Ok((encrypt(&plaintext, passphrase)?, summary))
+}
+
+/// Restores the backup `data` to the paths of `config`. The mint must not be running. Existing
+/// mint files are only replaced with `force`.
+pub fn restore(
+    config: &MintConfig,
+    data: &[u8],
+    passphrase: &str,
+    force: bool,
+) -> MintResult<BackupSummary> {
+    let contents: BackupContents = serde_json::from_slice(&decrypt(data, passphrase)?)
+        .map_err(|e| MintError::Backup(e.to_string()))?;
+    for path in [&config.db_path, &config.keysets_path] {
+        if let Some(parent) = Path::new(path).parent() {
+            fs::create_dir_all(parent)?;
+        }
+    }
+    let restored_path = format!("{}.restore", config.db_path);
+    remove_database(&restored_path)?;
+    let summary = match check(&contents, config.max_order, &restored_path) {
+        Ok(summary) => summary,
+        Err(e) => {
+            remove_database(&restored_path)?;
+            return Err(e);
+        }
+    };
+    let paths = [
+        &config.master_secret_path,
+        &config.keysets_path,
+        &config.db_path,
+    ];
+    if let Some(existing) = paths.iter().find(|path| Path::new(path).exists()) {
+        if !force {
+            remove_database(&restored_path)?;
+            return Err(MintError::Backup(format!(
+                "{} exists, refusing to overwrite it",
+                existing
+            )));
+        }
+    }
+    if Path::new(&config.master_secret_path).exists() {
+        fs::remove_file(&config.master_secret_path)?;
+    }
+    let master_secret = keyset::parse_master_secret(&contents.master_secret)
+        .ok_or_else(|| MintError::Backup("invalid master secret".into()))?;
+    keyset::write_master_secret(&config.master_secret_path, &master_secret)?;
+    let keysets = serde_json::to_string_pretty(&contents.keysets)
+        .map_err(|e| MintError::Storage(e.to_string()))?;
+    fs::write(&config.keysets_path, keysets)?;
+    // a journal left by the replaced database would be replayed into the restored one
+    remove_database(&config.db_path)?;
+    fs::rename(&restored_path, &config.db_path)?;
+    info!(
+        "Mint: restored backup from {} with {} keysets",
+        summary.created_at, summary.keysets
+    );
+    Ok(summary)
+}
+
+/// Checks that every keyset of the backup derives from its master secret and that the database,
+/// written to `db_path` for that, is intact and only references those keysets.
+fn check(contents: &BackupContents, max_order: u8, db_path: &str) -> MintResult<BackupSummary> {
+    let master_secret = keyset::parse_master_secret(&contents.master_secret)
+        .ok_or_else(|| MintError::Backup("invalid master secret".into()))?;
+    for info in &contents.keysets {
+        if info.derive(&master_secret, max_order).is_err() {
+            return Err(MintError::Backup(format!(
+                "keyset {} doesn't derive from the master secret",
+                info.id
+            )));
+        }
+    }
+    let database = STANDARD
+        .decode(&contents.database)
+        .map_err(|e| MintError::Backup(e.to_string()))?;
+    fs::write(db_path, database)?;
+    let db = MintDb::open(db_path)?;
+    db.check_integrity()?;
+    let ids: HashSet<&str> = contents
+        .keysets
+        .iter()
+        .map(|info| info.id.as_str())
+        .collect();
+    if let Some(unknown) = db
+        .referenced_keysets()?
+        .into_iter()
+        .find(|id| !ids.contains(id.as_str()))
+    {
+        return Err(MintError::Backup(format!(
+            "database references keyset {} missing from the backup",
+            unknown
+        )));
+    }
+    Ok(BackupSummary {
+        created_at: contents.created_at,
+        keysets: contents.keysets.len(),
+        signatures: db.signature_count()?,
+        spent_proofs: db.spent_count()?,
+    })
+}
+
+fn remove_database(path: &str) -> MintResult<()> {
+    for suffix in ["", "-wal", "-shm"] {
+        let path = format!("{}{}", path, suffix);
+        if Path::new(&path).exists() {
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+
+fn cipher(passphrase: &str, salt: &[u8]) -> MintResult<ChaCha20Poly1305> {
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| MintError::Backup(e.to_string()))?;
+    Ok(ChaCha20Poly1305::new(Key::from_slice(&key)))
+}
+
+/// `MAGIC || salt || nonce || ciphertext`
+fn encrypt(plaintext: &[u8], passphrase: &str) -> MintResult<Vec<u8>> {
+    let salt: [u8; SALT_LEN] = rand::random();
+    let nonce: [u8; NONCE_LEN] = rand::random();
+    let payload = Payload {
+        msg: plaintext,
+        aad: MAGIC,
+    };
+    let ciphertext = cipher(passphrase, &salt)?
+        .encrypt(Nonce::from_slice(&nonce), payload)
+        .map_err(|e| MintError::Backup(e.to_string()))?;
+    Ok([&MAGIC[..], &salt[..], &nonce[..], &ciphertext[..]].concat())
+}
+
+fn decrypt(data: &[u8], passphrase: &str) -> MintResult<Vec<u8>> {
+    let data = data
+        .strip_prefix(MAGIC.as_slice())
+        .filter(|data| data.len() > SALT_LEN + NONCE_LEN)
+        .ok_or_else(|| MintError::Backup("not a mint backup".into()))?;
+    let (salt, data) = data.split_at(SALT_LEN);
+    let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+    let payload = Payload {
+        msg: ciphertext,
+        aad: MAGIC,
+    };
+    cipher(passphrase, salt)?
+        .decrypt(Nonce::from_slice(nonce), payload)
+        .map_err(|_| MintError::Backup("wrong passphrase or corrupted backup".into()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pool_mint::mint::{
+        dhke, keyset::Keyset, lifecycle::KeysetState, nuts::BlindedMessage, Mint, EHASH_UNIT,
+    };
+
+    fn config(dir: &Path) -> MintConfig {
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        MintConfig::new(
+            vec![EHASH_UNIT.into()],
+            path("master_secret"),
+            path("mint.sqlite"),
+            path("keysets.json"),
+            8,
+            None,
+            "127.0.0.1:0".into(),
+            None,
+        )
+    }
+
+    #[test]
+    fn restores_balances_and_signatures() {
+        let dir = std::env::temp_dir().join(format!("potato-backup-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let config = config(&dir);
+        let (b, _) = dhke::blind_message(b"backed up", None).unwrap();
+        let outputs = {
+            let mut mint = Mint::new(&config, None).unwrap();
+            mint.credit_share("alice", 5).unwrap();
+            let outputs = vec![BlindedMessage {
+                amount: 2,
+                id: mint.active_keyset(EHASH_UNIT).unwrap().id.clone(),
+                blinded_secret: b,
+            }];
+            mint.withdraw("alice", &outputs).unwrap();
+            outputs
+        };
+        let (backup, summary) = export(&config, "hunter2").unwrap();
+        assert_eq!((summary.keysets, summary.signatures), (1, 1));
+
+        assert!(matches!(
+            restore(&config, &backup, "hunter2", false),
+            Err(MintError::Backup(_))
+        ));
+        assert!(matches!(
+            restore(&config, &backup, "hunter3", true),
+            Err(MintError::Backup(_))
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            restore(&config, &backup, "hunter2", false).unwrap(),
+            summary
+        );
+        let mint = Mint::new(&config, None).unwrap();
+        assert_eq!(mint.balance("alice", EHASH_UNIT).unwrap(), 3);
+        assert!(mint.signatures(&outputs).unwrap()[0].is_some());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_keysets_of_another_secret() {
+        let keyset = Keyset::derive(&[1; 32], EHASH_UNIT, 0, &[1, 2, 4, 8]).unwrap();
+        let contents = BackupContents {
+            created_at: 0,
+            master_secret: hex::encode([2; 32]),
+            keysets: vec![KeysetInfo {
+                id: keyset.id,
+                index: 0,
+                unit: EHASH_UNIT.into(),
+                state: KeysetState::Active,
+                created_at: 0,
+                activated_at: Some(0),
+                deprecated_at: None,
+                epoch: None,
+                amounts: Some(vec![1, 2, 4, 8]),
+            }],
+            database: String::new(),
+        };
+        assert!(matches!(
+            check(&contents, 8, "unused"),
+            Err(MintError::Backup(_))
+        ));
+    }
+}
@@ -0,0 +1,557 @@
+//! Issuance by a mint of the Cashu Dev Kit run in the process, for operators who want the NUT
+//! support of CDK behind the Cashu API of the pool. The mint of this crate keeps crediting shares,
+//! closing rounds and holding balances and payouts; CDK holds the keysets, signs the outputs and
+//! keeps the quotes, signatures and spent proofs in a redb database of its own.
+//!
+//! Mint quotes use the `ehash` payment method as before: a quote names an account and is paid
+//! from its balance. The balance is checked before CDK signs the outputs and debited once it has,
+//! one issuance at a time. Payouts are tokens of the CDK mint, see `PayoutIssuer`.
+//!
+//! Both sides speak the NUT JSON, so requests and answers cross over by serializing one side's
+//! type and deserializing the other's.
+use super::{
+    backend::{record_payout, MintBackend, PayoutIssuer},
+    client, keyset,
+    nuts::{
+        BlindSignature, BlindedMessage, CheckStateRequest, CheckStateResponse, KeySet,
+        KeySetSummary, KeysResponse, KeysetsResponse, MintQuoteResponse, MintQuoteState, Proof,
+        ProofState, SignaturesResponse, SwapRequest, Token,
+    },
+    p2pk::Conditions,
+    payout::Payout,
+    quote::{MELT_QUOTE_EXPIRY_SECS, MINT_QUOTE_EXPIRY_SECS},
+    Mint, MintConfig,
+};
+use crate::error::{MintError, MintResult};
+use async_trait::async_trait;
+use cdk::{
+    mint::MintQuote,
+    mint_url::MintUrl,
+    nuts::{self as cdk_nuts, CurrencyUnit, Id},
+    types::QuoteTTL,
+    Amount,
+};
+use cdk_redb::MintRedbDatabase;
+use roles_logic_sv2::utils::Mutex;
+use secp256k1::PublicKey;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{collections::HashMap, path::Path, str::FromStr, sync::Arc, time::SystemTime};
+use tracing::{error, info};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CdkMintConfig {
+    /// redb database of the CDK mint: its keysets, quotes, signatures and spent proofs.
+    #[serde(default = "CdkMintConfig::default_db_path")]
+    pub db_path: String,
+    /// File holding the hex encoded seed the keys of the CDK mint are derived from. Created on
+    /// first start.
+    #[serde(default = "CdkMintConfig::default_seed_path")]
+    pub seed_path: String,
+}
+
+impl CdkMintConfig {
+    fn default_db_path() -> String {
+        "cdk-mint.redb".to_string()
+    }
+
+    fn default_seed_path() -> String {
+        "cdk-mint-seed".to_string()
+    }
+}
+
+impl Default for CdkMintConfig {
+    fn default() -> Self {
+        Self {
+            db_path: Self::default_db_path(),
+            seed_path: Self::default_seed_path(),
+        }
+    }
+}
+
+pub struct CdkMint {
+    /// Keeps the accounts, as in `external` mode.
+    accounts: Arc<Mutex<Mint>>,
+    mint: cdk::Mint,
+    /// URL written into the tokens paid out, where the API serves the keys of `mint`.
+    url: String,
+    /// Held from the balance check of an issuance to its debit.
+    issuing: tokio::sync::Mutex<()>,
+}
+
+impl std::fmt::Debug for CdkMint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CdkMint")
+            .field("url", &self.url)
+            .finish_non_exhaustive()
+    }
+}
+
+impl CdkMint {
+    /// Opens (or creates) the CDK mint of `cdk`, with a keyset per unit of `config`, issuing
+    /// from the balances `accounts` keeps.
+    pub async fn new(
+        accounts: Arc<Mutex<Mint>>,
+        config: &MintConfig,
+        cdk: &CdkMintConfig,
+    ) -> MintResult<Self> {
+        let seed = keyset::load_or_create_master_secret(&cdk.seed_path)?;
+        let db = MintRedbDatabase::new(Path::new(&cdk.db_path))
+            .map_err(|e| MintError::Storage(e.to_string()))?;
+        let units = config
+            .units
+            .iter()
+            .map(|unit| {
+                let unit = currency_unit(unit)?;
+                Ok((unit, (config.fees.input_fee_ppk, config.max_order)))
+            })
+            .collect::<MintResult<HashMap<_, _>>>()?;
+        let mint = cdk::Mint::new(
+            &config.url(),
+            &seed,
+            cdk_nuts::MintInfo::default(),
+            QuoteTTL::new(MINT_QUOTE_EXPIRY_SECS, MELT_QUOTE_EXPIRY_SECS),
+            Arc::new(db),
+            HashMap::new(),
+            units,
+            HashMap::new(),
+        )
+        .await
+        .map_err(mint_error)?;
+        info!("Mint: issuing with the CDK mint of {}", cdk.db_path);
+        Ok(Self::with_mint(accounts, mint, config.url()))
+    }
+
+    fn with_mint(accounts: Arc<Mutex<Mint>>, mint: cdk::Mint, url: String) -> Self {
+        Self {
+            accounts,
+            mint,
+            url,
+            issuing: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// A token of `amount` `unit` for `account` from a quote CDK takes as paid, locked to
+    /// `pubkey` if given. The caller holds `issuing`.
+    async fn issue_token(
+        &self,
+        account: &str,
+        unit: &str,
+        amount: u64,
+        pubkey: Option<&PublicKey>,
+    ) -> MintResult<Token> {
+        let keyset = self
+            .keysets()
+            .await?
+            .into_iter()
+            .find(|keyset| keyset.active && keyset.unit == unit)
+            .ok_or_else(|| MintError::UnsupportedUnit(unit.to_string()))?;
+        let keyset = self.keys(Some(&keyset.id)).await?.remove(0);
+        let lock = pubkey.map(|pubkey| Conditions::p2pk(*pubkey));
+        let (outputs, secrets) = client::blank_outputs(&keyset, amount, lock.as_ref())?;
+        let quote = self.new_quote(account, unit, amount).await?;
+        let signatures = self.sign(&quote, &outputs).await?;
+        let proofs = client::unblind(&keyset, &outputs, &signatures, secrets)?;
+        Ok(Token::new(&self.url, unit, proofs))
+    }
+
+    /// Stores a quote of CDK for `amount` `unit` from the balance of `account`, naming the
+    /// account as its request.
+    async fn new_quote(&self, account: &str, unit: &str, amount: u64) -> MintResult<MintQuote> {
+        let url = MintUrl::from_str(&self.url).map_err(|e| MintError::Cdk(e.to_string()))?;
+        let quote = MintQuote::new(
+            url,
+            account.to_string(),
+            currency_unit(unit)?,
+            Amount::from(amount),
+            now_secs() + MINT_QUOTE_EXPIRY_SECS,
+            account.to_string(),
+        );
+        self.mint
+            .localstore
+            .add_mint_quote(quote.clone())
+            .await
+            .map_err(|e| mint_error(e.into()))?;
+        Ok(quote)
+    }
+
+    fn quote_response(&self, quote: &MintQuote) -> MintResult<MintQuoteResponse> {
+        let amount = u64::from(quote.amount);
+        let unit = quote.unit.to_string();
+        let state = match quote.state {
+            cdk_nuts::MintQuoteState::Issued => MintQuoteState::Issued,
+            _ if self.balance(&quote.request, &unit)? >= amount => MintQuoteState::Paid,
+            _ => MintQuoteState::Unpaid,
+        };
+        Ok(MintQuoteResponse {
+            quote: quote.id.to_string(),
+            request: quote.request.clone(),
+            amount,
+            unit,
+            state,
+            expiry: quote.expiry,
+        })
+    }
+
+    fn balance(&self, account: &str, unit: &str) -> MintResult<u64> {
+        self.accounts.safe_lock(|m| m.balance(account, unit))?
+    }
+
+    async fn quote(&self, id: &str) -> MintResult<MintQuote> {
+        let unknown = || MintError::UnknownQuote(id.to_string());
+        let uuid = Uuid::from_str(id).map_err(|_| unknown())?;
+        self.mint
+            .localstore
+            .get_mint_quote(&uuid)
+            .await
+            .map_err(|e| mint_error(e.into()))?
+            .ok_or_else(unknown)
+    }
+
+    /// Has CDK sign `outputs` for `quote`, which it takes as paid. The caller checked the
+    /// balance of the account and debits it.
+    async fn sign(
+        &self,
+        quote: &MintQuote,
+        outputs: &[BlindedMessage],
+    ) -> MintResult<Vec<BlindSignature>> {
+        let store = &self.mint.localstore;
+        store
+            .update_mint_quote_state(&quote.id, cdk_nuts::MintQuoteState::Paid)
+            .await
+            .map_err(|e| mint_error(e.into()))?;
+        let request = cdk_nuts::MintBolt11Request {
+            quote: quote.id,
+            outputs: convert(outputs)?,
+        };
+        let response = self
+            .mint
+            .process_mint_request(request)
+            .await
+            .map_err(mint_error)?;
+        convert(&response.signatures)
+    }
+
+    /// Debits the balance of `account` CDK issued tokens from. The tokens exist by then, a
+    /// failure is only logged.
+    fn record_issued(&self, account: &str, unit: &str, amount: u64) {
+        match self
+            .accounts
+            .safe_lock(|m| m.record_issued(account, unit, amount))
+        {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => error!(
+                "Mint: debiting {} {} CDK issued to {} failed: {}",
+                amount, unit, account, e
+            ),
+            Err(e) => error!("Mint: lock poisoned: {}", e),
+        }
+    }
+}
+
+#[async_trait]
+impl MintBackend for CdkMint {
+    fn credit_share(&self, account: &str, weight: u64) -> MintResult<()> {
+        self.accounts.credit_share(account, weight)
+    }
+
+    fn credit_shares(&self, shares: &[(String, u64)]) -> MintResult<()> {
+        self.accounts.credit_shares(shares)
+    }
+
+    fn found_block(
+        &self,
+        coinbase_txid: &str,
+        reward: u64,
+        finder: Option<&str>,
+    ) -> MintResult<()> {
+        self.accounts.found_block(coinbase_txid, reward, finder)
+    }
+
+    fn undelivered_payouts(&self) -> MintResult<Vec<Payout>> {
+        MintBackend::undelivered_payouts(self.accounts.as_ref())
+    }
+
+    fn mark_payout_delivered(&self, id: &str) -> MintResult<()> {
+        MintBackend::mark_payout_delivered(self.accounts.as_ref(), id)
+    }
+
+    async fn keys(&self, id: Option<&str>) -> MintResult<Vec<KeySet>> {
+        let response = match id {
+            Some(id) => {
+                let unknown = || MintError::UnknownKeyset(id.to_string());
+                let id = Id::from_str(id).map_err(|_| unknown())?;
+                self.mint.keyset_pubkeys(&id).await.map_err(|e| match e {
+                    cdk::Error::UnknownKeySet => unknown(),
+                    e => mint_error(e),
+                })?
+            }
+            None => self.mint.pubkeys().await.map_err(mint_error)?,
+        };
+        Ok(convert::<_, KeysResponse>(&response)?.keysets)
+    }
+
+    async fn keysets(&self) -> MintResult<Vec<KeySetSummary>> {
+        let response = self.mint.keysets().await.map_err(mint_error)?;
+        Ok(convert::<_, KeysetsResponse>(&response)?.keysets)
+    }
+
+    async fn create_mint_quote(
+        &self,
+        account: &str,
+        amount: u64,
+        unit: &str,
+    ) -> MintResult<MintQuoteResponse> {
+        if !self.accounts.safe_lock(|m| m.has_unit(unit))? {
+            return Err(MintError::UnsupportedUnit(unit.to_string()));
+        }
+        if amount == 0 {
+            return Err(MintError::UnsupportedAmount(amount));
+        }
+        let quote = self.new_quote(account, unit, amount).await?;
+        self.quote_response(&quote)
+    }
+
+    async fn mint_quote(&self, id: &str) -> MintResult<MintQuoteResponse> {
+        self.quote_response(&self.quote(id).await?)
+    }
+
+    async fn mint(
+        &self,
+        quote: &str,
+        outputs: &[BlindedMessage],
+    ) -> MintResult<Vec<BlindSignature>> {
+        let _issuing = self.issuing.lock().await;
+        let quote = self.quote(quote).await?;
+        let id = quote.id.to_string();
+        if quote.state == cdk_nuts::MintQuoteState::Issued {
+            return Err(MintError::QuoteAlreadyIssued(id));
+        }
+        if quote.expiry <= now_secs() {
+            return Err(MintError::QuoteExpired(id));
+        }
+        let (account, unit, amount) = (&quote.request, quote.unit.to_string(), quote.amount);
+        if self.balance(account, &unit)? < u64::from(amount) {
+            return Err(MintError::QuoteNotPaid(id));
+        }
+        let signatures = self.sign(&quote, outputs).await?;
+        self.record_issued(account, &unit, amount.into());
+        Ok(signatures)
+    }
+
+    async fn swap(
+        &self,
+        inputs: &[Proof],
+        outputs: &[BlindedMessage],
+    ) -> MintResult<Vec<BlindSignature>> {
+        let request = SwapRequest {
+            inputs: inputs.to_vec(),
+            outputs: outputs.to_vec(),
+        };
+        let response = self
+            .mint
+            .process_swap_request(convert(&request)?)
+            .await
+            .map_err(mint_error)?;
+        Ok(convert::<_, SignaturesResponse>(&response)?.signatures)
+    }
+
+    async fn proof_states(&self, ys: &[PublicKey]) -> MintResult<Vec<ProofState>> {
+        let request = CheckStateRequest { ys: ys.to_vec() };
+        let response = self
+            .mint
+            .check_state(&convert(&request)?)
+            .await
+            .map_err(mint_error)?;
+        Ok(convert::<_, CheckStateResponse>(&response)?.states)
+    }
+}
+
+#[async_trait]
+impl PayoutIssuer for CdkMint {
+    async fn issue(
+        &self,
+        account: &str,
+        unit: &str,
+        amount: u64,
+        pubkey: Option<&PublicKey>,
+    ) -> MintResult<Token> {
+        let _issuing = self.issuing.lock().await;
+        self.issue_token(account, unit, amount, pubkey).await
+    }
+
+    /// Holds issuance until the balance is debited, so a mint request of the account can't spend
+    /// it again meanwhile.
+    async fn pay_out(
+        &self,
+        accounts: &Mutex<Mint>,
+        account: &str,
+        unit: &str,
+        amount: u64,
+        pubkey: Option<&PublicKey>,
+    ) -> MintResult<Payout> {
+        let _issuing = self.issuing.lock().await;
+        let available = self.balance(account, unit)?;
+        if available < amount {
+            return Err(MintError::InsufficientBalance {
+                requested: amount,
+                available,
+            });
+        }
+        let token = self.issue_token(account, unit, amount, pubkey).await?;
+        record_payout(accounts, account, unit, amount, &token)
+    }
+}
+
+fn currency_unit(unit: &str) -> MintResult<CurrencyUnit> {
+    CurrencyUnit::from_str(unit).map_err(|_| MintError::UnsupportedUnit(unit.to_string()))
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+/// `value` as the type of the other side, both following the NUT JSON.
+fn convert<T: Serialize + ?Sized, U: DeserializeOwned>(value: &T) -> MintResult<U> {
+    serde_json::to_value(value)
+        .and_then(serde_json::from_value)
+        .map_err(|e| MintError::Cdk(e.to_string()))
+}
+
+/// The error of this mint CDK answered, keeping the NUT-00 code of those that have one.
+fn mint_error(e: cdk::Error) -> MintError {
+    use cdk::Error::*;
+    match e {
+        TokenAlreadySpent => MintError::ProofAlreadySpent,
+        TokenPending => MintError::ProofPending,
+        DuplicateProofs => MintError::DuplicateInputs,
+        BlindedMessageAlreadySigned => MintError::OutputAlreadySigned,
+        TransactionUnbalanced(inputs, outputs, fee) => MintError::UnbalancedTransaction {
+            inputs,
+            outputs: outputs.saturating_add(fee),
+        },
+        MultipleUnits => MintError::MixedUnits,
+        UnsupportedUnit => MintError::UnsupportedUnit(String::new()),
+        KeysetUnknown(id) => MintError::UnknownKeyset(id.to_string()),
+        UnknownKeySet => MintError::UnknownKeyset(String::new()),
+        InactiveKeyset => MintError::InactiveKeyset(String::new()),
+        UnknownQuote => MintError::UnknownQuote(String::new()),
+        UnpaidQuote => MintError::QuoteNotPaid(String::new()),
+        PendingQuote => MintError::QuotePending(String::new()),
+        IssuedQuote => MintError::QuoteAlreadyIssued(String::new()),
+        DHKE(_) => MintError::InvalidProof,
+        e @ (P2PKConditionsNotMet(_) | InvalidSpendConditions(_) | NUT11(_) | NUT14(_)) => {
+            MintError::SpendingConditions(e.to_string())
+        }
+        e => MintError::Cdk(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pool_mint::mint::{dhke, nuts::SpendState, EHASH_UNIT};
+    use cdk::cdk_database::mint_memory::MintMemoryDatabase;
+
+    async fn mint() -> (Arc<Mutex<Mint>>, CdkMint) {
+        let config = MintConfig::default();
+        let accounts = Arc::new(Mutex::new(
+            Mint::from_master_secret(&[3; 32], &config).unwrap(),
+        ));
+        let units = config
+            .units
+            .iter()
+            .map(|unit| (currency_unit(unit).unwrap(), (0, config.max_order)))
+            .collect();
+        let mint = cdk::Mint::new(
+            &config.url(),
+            &[3; 32],
+            cdk_nuts::MintInfo::default(),
+            QuoteTTL::new(MINT_QUOTE_EXPIRY_SECS, MELT_QUOTE_EXPIRY_SECS),
+            Arc::new(MintMemoryDatabase::default()),
+            HashMap::new(),
+            units,
+            HashMap::new(),
+        )
+        .await
+        .unwrap();
+        let backend = CdkMint::with_mint(accounts.clone(), mint, config.url());
+        (accounts, backend)
+    }
+
+    #[tokio::test]
+    async fn issues_from_balances_through_cdk() {
+        let (accounts, backend) = mint().await;
+        let backend: Arc<dyn MintBackend> = Arc::new(backend);
+        backend.credit_share("alice", 12).unwrap();
+
+        let quote = backend
+            .create_mint_quote("alice", 8, EHASH_UNIT)
+            .await
+            .unwrap();
+        assert_eq!(quote.state, MintQuoteState::Paid);
+        let keyset = backend.keys(None).await.unwrap();
+        let keyset = keyset.iter().find(|k| k.unit == EHASH_UNIT).unwrap();
+        let (outputs, secrets) = client::blank_outputs(keyset, 8, None).unwrap();
+        let signatures = backend.mint(&quote.quote, &outputs).await.unwrap();
+        let proofs = client::unblind(keyset, &outputs, &signatures, secrets).unwrap();
+        accounts
+            .safe_lock(|m| assert_eq!(m.balance("alice", EHASH_UNIT).unwrap(), 4))
+            .unwrap();
+        assert_eq!(
+            backend.mint_quote(&quote.quote).await.unwrap().state,
+            MintQuoteState::Issued
+        );
+        assert!(matches!(
+            backend.mint(&quote.quote, &outputs).await,
+            Err(MintError::QuoteAlreadyIssued(_))
+        ));
+
+        // a quote the balance no longer covers
+        let quote = backend
+            .create_mint_quote("alice", 8, EHASH_UNIT)
+            .await
+            .unwrap();
+        assert_eq!(quote.state, MintQuoteState::Unpaid);
+        let (more, _) = client::blank_outputs(keyset, 8, None).unwrap();
+        assert!(matches!(
+            backend.mint(&quote.quote, &more).await,
+            Err(MintError::QuoteNotPaid(_))
+        ));
+
+        let (swapped, _) = client::blank_outputs(keyset, 8, None).unwrap();
+        backend.swap(&proofs, &swapped).await.unwrap();
+        let ys: Vec<_> = proofs
+            .iter()
+            .map(|proof| dhke::hash_to_curve(proof.secret.as_bytes()).unwrap())
+            .collect();
+        let states = backend.proof_states(&ys).await.unwrap();
+        assert!(states.iter().all(|state| state.state == SpendState::Spent));
+        assert!(matches!(
+            backend.swap(&proofs, &swapped).await,
+            Err(MintError::ProofAlreadySpent)
+        ));
+    }
+
+    #[tokio::test]
+    async fn pays_out_tokens_of_the_cdk_mint() {
+        let (accounts, backend) = mint().await;
+        backend.credit_share("bob", 5).unwrap();
+        let payout = backend
+            .pay_out(&accounts, "bob", EHASH_UNIT, 5, None)
+            .await
+            .unwrap();
+        let token = Token::decode(&payout.token).unwrap();
+        assert_eq!(token.token[0].mint, backend.url);
+        assert_eq!(token.amount(), 5);
+        accounts
+            .safe_lock(|m| assert_eq!(m.balance("bob", EHASH_UNIT).unwrap(), 0))
+            .unwrap();
+        assert!(matches!(
+            backend.pay_out(&accounts, "bob", EHASH_UNIT, 5, None).await,
+            Err(MintError::InsufficientBalance { .. })
+        ));
+    }
+}
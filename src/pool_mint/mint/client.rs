@@ -0,0 +1,226 @@
+//! Client of the Cashu API of a mint, and the wallet side of issuance: blinding outputs and
+//! unblinding the signatures on them. Used to have an external mint issue payouts (see
+//! `external`) and by the miner wallet (see `wallet`).
+use super::{
+    amounts,
+    deterministic::Derivation,
+    dhke,
+    nuts::{BlindSignature, BlindedMessage, ErrorResponse, KeySet, KeysResponse, Proof},
+    p2pk::Conditions,
+};
+use crate::error::{MintError, MintResult};
+use secp256k1::{PublicKey, SecretKey};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{str::FromStr, time::Duration};
+
+const REQUEST_TIMEOUT_SECS: u64 = 30;
+
+/// Secrets of outputs along with the factors blinding them.
+pub type Secrets = Vec<(String, SecretKey)>;
+
+#[derive(Debug)]
+pub struct MintClient {
+    url: String,
+    /// Sent as bearer token with every request, if set.
+    api_key: Option<String>,
+    http: reqwest::Client,
+}
+
+impl MintClient {
+    pub fn new(url: &str, api_key: Option<String>) -> MintResult<Self> {
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(REQUEST_TIMEOUT_SECS))
+            .build()
+            .map_err(request_failed)?;
+        Ok(Self {
+            url: url.trim_end_matches('/').to_string(),
+            api_key,
+            http,
+        })
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Keys of the active keyset of `unit`.
+    pub async fn keys(&self, unit: &str) -> MintResult<KeySet> {
+        let keys: KeysResponse = self.get("/v1/keys").await?;
+        keys.keysets
+            .into_iter()
+            .find(|keyset| keyset.unit == unit)
+            .ok_or_else(|| MintError::UnsupportedUnit(unit.to_string()))
+    }
+
+    pub async fn get<T: DeserializeOwned>(&self, path: &str) -> MintResult<T> {
+        let request = self.http.get(format!("{}{}", self.url, path));
+        self.send(request).await
+    }
+
+    pub async fn post<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        body: &impl Serialize,
+    ) -> MintResult<T> {
+        let request = self.http.post(format!("{}{}", self.url, path)).json(body);
+        self.send(request).await
+    }
+
+    /// Sends `request`, reading the response or the NUT-00 error the mint answered.
+    async fn send<T: DeserializeOwned>(&self, request: reqwest::RequestBuilder) -> MintResult<T> {
+        let request = match &self.api_key {
+            Some(api_key) => request.bearer_auth(api_key),
+            None => request,
+        };
+        let response = request.send().await.map_err(request_failed)?;
+        let status = response.status();
+        let body = response.bytes().await.map_err(request_failed)?;
+        if !status.is_success() {
+            let detail = serde_json::from_slice::<ErrorResponse>(&body)
+                .map(|e| format!("{} (code {})", e.detail, e.code))
+                .unwrap_or_else(|_| String::from_utf8_lossy(&body).into_owned());
+            return Err(MintError::MintRequest(format!("{}: {}", status, detail)));
+        }
+        serde_json::from_slice(&body).map_err(request_failed)
+    }
+}
+
+/// Outputs of `keyset` adding up to `amount`, with the secrets and blinding factors they hide.
+/// The secrets carry the spending conditions `lock` if given.
+pub fn blank_outputs(
+    keyset: &KeySet,
+    amount: u64,
+    lock: Option<&Conditions>,
+) -> MintResult<(Vec<BlindedMessage>, Secrets)> {
+    outputs(keyset, amount, || {
+        let secret = match lock {
+            Some(lock) => lock.secret(),
+            None => hex::encode(rand::random::<[u8; 32]>()),
+        };
+        Ok((secret, None))
+    })
+}
+
+/// Outputs of `keyset` adding up to `amount` with the deterministic secrets of `derivation`
+/// (NUT-13), from output `counter` on. `counter` is left at the next unused output.
+pub fn deterministic_outputs(
+    keyset: &KeySet,
+    amount: u64,
+    derivation: &Derivation,
+    counter: &mut u32,
+) -> MintResult<(Vec<BlindedMessage>, Secrets)> {
+    outputs(keyset, amount, || {
+        let (secret, r) = derivation.secret(&keyset.id, *counter)?;
+        *counter += 1;
+        Ok((secret, Some(r)))
+    })
+}
+
+/// Outputs of `keyset` adding up to `amount`, each with the secret and, if given, the blinding
+/// factor `next` returns.
+fn outputs(
+    keyset: &KeySet,
+    amount: u64,
+    mut next: impl FnMut() -> MintResult<(String, Option<SecretKey>)>,
+) -> MintResult<(Vec<BlindedMessage>, Secrets)> {
+    let amounts: Vec<u64> = keyset.keys.keys().copied().collect();
+    let mut outputs = vec![];
+    let mut secrets = vec![];
+    for amount in amounts::split(amount, &amounts)? {
+        let (secret, r) = next()?;
+        let (blinded_secret, r) = dhke::blind_message(secret.as_bytes(), r)?;
+        outputs.push(BlindedMessage {
+            amount,
+            id: keyset.id.clone(),
+            blinded_secret,
+        });
+        secrets.push((secret, r));
+    }
+    Ok((outputs, secrets))
+}
+
+/// The proofs of `signatures` on `outputs`, checking their DLEQ proofs against the published
+/// keys.
+pub fn unblind(
+    keyset: &KeySet,
+    outputs: &[BlindedMessage],
+    signatures: &[BlindSignature],
+    secrets: Secrets,
+) -> MintResult<Vec<Proof>> {
+    if signatures.len() != outputs.len() {
+        return Err(MintError::MintRequest(format!(
+            "{} signatures for {} outputs",
+            signatures.len(),
+            outputs.len()
+        )));
+    }
+    signatures
+        .iter()
+        .zip(outputs)
+        .zip(secrets)
+        .map(|((signature, output), (secret, r))| {
+            let key = keyset
+                .keys
+                .get(&signature.amount)
+                .ok_or(MintError::UnsupportedAmount(signature.amount))?;
+            let key = PublicKey::from_str(key)?;
+            if let Some(dleq) = &signature.dleq {
+                let valid = dhke::verify_dleq(
+                    &dleq.e,
+                    &dleq.s,
+                    &key,
+                    &output.blinded_secret,
+                    &signature.blinded_signature,
+                )?;
+                if !valid {
+                    return Err(MintError::MintRequest("invalid DLEQ proof".to_string()));
+                }
+            }
+            Ok(Proof {
+                amount: signature.amount,
+                id: signature.id.clone(),
+                secret,
+                signature: dhke::unblind_signature(&signature.blinded_signature, &r, &key)?,
+                witness: None,
+            })
+        })
+        .collect()
+}
+
+fn request_failed(e: impl std::fmt::Display) -> MintError {
+    MintError::MintRequest(e.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pool_mint::mint::{Mint, MintConfig, EHASH_UNIT};
+
+    #[test]
+    fn unblinds_tokens_of_another_mint() {
+        let mut other = Mint::from_master_secret(&[9; 32], &MintConfig::default()).unwrap();
+        let active = other.active_keyset(EHASH_UNIT).unwrap();
+        let keyset = KeySet {
+            id: active.id.clone(),
+            unit: EHASH_UNIT.to_string(),
+            keys: active
+                .public_keys()
+                .into_iter()
+                .map(|(amount, key)| (amount, key.to_string()))
+                .collect(),
+        };
+        let (outputs, secrets) = blank_outputs(&keyset, 11, None).unwrap();
+        assert_eq!(
+            outputs
+                .iter()
+                .map(|output| output.amount)
+                .collect::<Vec<_>>(),
+            vec![8, 2, 1]
+        );
+        other.credit_share("alice", 11).unwrap();
+        let signatures = other.withdraw("alice", &outputs).unwrap();
+        let proofs = unblind(&keyset, &outputs, &signatures, secrets.clone()).unwrap();
+        assert!(proofs.iter().all(|proof| other.verify_proof(proof).is_ok()));
+        assert!(unblind(&keyset, &outputs, &signatures[1..], secrets).is_err());
+    }
+}
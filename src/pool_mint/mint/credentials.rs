@@ -0,0 +1,70 @@
+//! Credentials proving a caller owns an account. Accounts are named after the stratum users
+//! miners mine under, which the explorer lists, so naming one proves nothing. The operator issues
+//! an account its credential with the control API `issue_credential` (`potato mint credential`)
+//! and hands it to the miner, and the mint API asks for it before showing the account's balance
+//! and statement or issuing tokens out of the balance. Only the SHA-256 of a credential is kept,
+//! and issuing another one revokes it.
+//!
+//! Binding the key payouts of an account are locked to (see `p2pk`), or its Nostr key (see
+//! `nostr`), takes the credential too.
+//! Once bound, a key is only replaced with its Schnorr signature on the key replacing it (see
+//! `verify_rebind`), so neither a leaked credential nor the operator redirect payouts locked to
+//! the key of a miner.
+use crate::error::{MintError, MintResult};
+use secp256k1::{schnorr::Signature, Keypair, Message, Secp256k1, SecretKey, XOnlyPublicKey};
+use sha2::{Digest, Sha256};
+use std::str::FromStr;
+
+/// Kind of the key payouts are locked to, see `rebind_message`.
+pub const PAYOUT_KEY: &str = "pubkey";
+/// Kind of the Nostr key payouts are sent to, see `nostr`.
+pub const NOSTR_KEY: &str = "nostr";
+
+/// A new credential, 32 random bytes in hex.
+pub fn generate() -> String {
+    hex::encode(rand::random::<[u8; 32]>())
+}
+
+/// What is kept of `credential`.
+pub fn digest(credential: &str) -> String {
+    hex::encode(Sha256::digest(credential.as_bytes()))
+}
+
+/// Wallet side: the signature of `bound`, the key of `account` of `kind`, letting `key` replace
+/// it.
+pub fn sign_rebind(kind: &str, account: &str, key: &str, bound: &SecretKey) -> String {
+    let secp = Secp256k1::new();
+    let message = rebind_message(kind, account, key);
+    secp.sign_schnorr(&message, &Keypair::from_secret_key(&secp, bound))
+        .to_string()
+}
+
+/// Checks `signature` is the one of `bound`, the key of `account` of `kind`, letting `key`
+/// replace it.
+pub fn verify_rebind(
+    kind: &str,
+    account: &str,
+    key: &str,
+    bound: &XOnlyPublicKey,
+    signature: Option<&str>,
+) -> MintResult<()> {
+    let message = rebind_message(kind, account, key);
+    let signature = signature.and_then(|signature| Signature::from_str(signature).ok());
+    match signature {
+        Some(signature)
+            if Secp256k1::verification_only()
+                .verify_schnorr(&signature, &message, bound)
+                .is_ok() =>
+        {
+            Ok(())
+        }
+        _ => Err(MintError::PubkeyAlreadyRegistered(account.to_string())),
+    }
+}
+
+/// What the bound key signs: the SHA-256 of `potato/<kind>/<account>/<key>`, the kind keeping a
+/// signature for one key from replacing a key of another kind.
+fn rebind_message(kind: &str, account: &str, key: &str) -> Message {
+    let message = format!("potato/{}/{}/{}", kind, account, key);
+    Message::from_digest(Sha256::digest(message.as_bytes()).into())
+}
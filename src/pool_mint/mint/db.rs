@@ -0,0 +1,1751 @@
+//! SQLite storage of the mint: account balances, mint and melt quotes, issued blind signatures,
+//! outputs queued for signing, spent proofs, proofs reserved by a melt in progress, the rounds
+//! shares are paid out by, the reserve ehash tokens convert into sat from, tokens minted by
+//! automatic payouts until they are delivered, the keys payouts are locked to and sent to, the
+//! fees collected, the running totals of every account (see `accounts`), what every round
+//! credited (see `reconcile`) and the journaled credits replayed (see `journal`). Every operation
+//! moving value runs in one transaction, so a crash can't leave a quote issued without its
+//! balance debited, or proofs spent without their replacement recorded.
+//!
+//! Spent and pending proofs are looked up through an in memory index kept in step with every
+//! write, see `spent`.
+//!
+//! The schema is created and upgraded on open by the embedded `MIGRATIONS`.
+use super::{
+    accounts::AccountLedger,
+    fees::{FeeTotal, Operation},
+    journal::{Credit, Journaled},
+    lifecycle::now_secs,
+    migrations::{self, Migration},
+    nuts::{BlindSignature, BlindedMessage, MeltQuoteState, Proof, SpendState},
+    onchain::{OnchainBatch, OnchainPayout},
+    payout::{Payout, PayoutTotal},
+    quote::{MeltQuote, MintQuote},
+    reconcile::RoundBooks,
+    rounds::{split_reward, AccountRound, Conversion, Round, RoundState, Settlement},
+    spent::{BloomFilter, SpentIndex, SpentReport},
+    writer::Synchronous,
+    EHASH_UNIT, SAT_UNIT,
+};
+use crate::error::{MintError, MintResult};
+use rusqlite::{params, Connection, ErrorCode, OptionalExtension, TransactionBehavior};
+use secp256k1::{PublicKey, XOnlyPublicKey};
+use std::{
+    collections::{BTreeMap, HashMap},
+    str::FromStr,
+};
+
+/// Schema migrations of the database, see `migrations`.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial",
+        up: include_str!("migrations/0001_initial.up.sql"),
+        down: include_str!("migrations/0001_initial.down.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "onchain_melts",
+        up: include_str!("migrations/0002_onchain_melts.up.sql"),
+        down: include_str!("migrations/0002_onchain_melts.down.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "account_ledger",
+        up: include_str!("migrations/0003_account_ledger.up.sql"),
+        down: include_str!("migrations/0003_account_ledger.down.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "credit_journal",
+        up: include_str!("migrations/0004_credit_journal.up.sql"),
+        down: include_str!("migrations/0004_credit_journal.down.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "round_credits",
+        up: include_str!("migrations/0005_round_credits.up.sql"),
+        down: include_str!("migrations/0005_round_credits.down.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "nostr_keys",
+        up: include_str!("migrations/0006_nostr_keys.up.sql"),
+        down: include_str!("migrations/0006_nostr_keys.down.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "round_blocks",
+        up: include_str!("migrations/0007_round_blocks.up.sql"),
+        down: include_str!("migrations/0007_round_blocks.down.sql"),
+    },
+    Migration {
+        version: 8,
+        name: "account_credentials",
+        up: include_str!("migrations/0008_account_credentials.up.sql"),
+        down: include_str!("migrations/0008_account_credentials.down.sql"),
+    },
+];
+
+/// The balances tokens are issued from: ehash accrued per share, or sat paid out by matured
+/// rounds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ledger {
+    Ehash,
+    Sat,
+}
+
+impl Ledger {
+    fn table(&self) -> &'static str {
+        match self {
+            Ledger::Ehash => "balances",
+            Ledger::Sat => "sat_balances",
+        }
+    }
+
+    fn unit(&self) -> &'static str {
+        match self {
+            Ledger::Ehash => EHASH_UNIT,
+            Ledger::Sat => SAT_UNIT,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct MintDb {
+    conn: Connection,
+    spent: SpentIndex,
+}
+
+impl MintDb {
+    pub fn open(path: &str) -> MintResult<Self> {
+        let conn = Connection::open(path)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        // a committed issuance must survive a power loss, or tokens could be signed twice
+        conn.pragma_update(None, "synchronous", "FULL")?;
+        Self::init(conn)
+    }
+
+    /// Syncs the database to disk as `synchronous` tells, see `writer`.
+    pub fn set_synchronous(&self, synchronous: Synchronous) -> MintResult<()> {
+        self.conn
+            .pragma_update(None, "synchronous", synchronous.pragma())?;
+        Ok(())
+    }
+
+    pub fn open_in_memory() -> MintResult<Self> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(mut conn: Connection) -> MintResult<Self> {
+        migrations::migrate(&mut conn, MIGRATIONS)?;
+        let filter = spent_filter(&conn)?;
+        let pending = pending_proofs(&conn)?;
+        Ok(Self {
+            conn,
+            spent: SpentIndex::new(filter, pending),
+        })
+    }
+
+    /// Lookup counters and sizes of the spent proof index.
+    pub fn spent_report(&self) -> SpentReport {
+        self.spent.report()
+    }
+
+    /// Adds `ys` to the spent proof index, resizing its filter once it holds more proofs than it
+    /// was sized for.
+    fn index_spent<'a>(&mut self, ys: impl IntoIterator<Item = &'a PublicKey>) -> MintResult<()> {
+        self.spent.spend(ys);
+        if self.spent.needs_rebuild() {
+            self.spent.set_filter(spent_filter(&self.conn)?);
+        }
+        Ok(())
+    }
+
+    /// Checks the database answers queries.
+    pub fn ping(&self) -> MintResult<()> {
+        self.conn.query_row("SELECT 1", [], |_| Ok(()))?;
+        Ok(())
+    }
+
+    pub fn balance(&self, ledger: Ledger, account: &str) -> MintResult<u64> {
+        balance(&self.conn, ledger, account)
+    }
+
+    /// Running totals of `account` in `ledger`.
+    pub fn account_ledger(&self, ledger: Ledger, account: &str) -> MintResult<AccountLedger> {
+        let totals = self
+            .conn
+            .query_row(
+                "SELECT credited, issued, redeemed FROM account_ledger
+                 WHERE account = ?1 AND unit = ?2",
+                params![account, ledger.unit()],
+                |row| {
+                    Ok(AccountLedger {
+                        balance: 0,
+                        credited: row.get::<_, i64>(0)? as u64,
+                        issued: row.get::<_, i64>(1)? as u64,
+                        redeemed: row.get::<_, i64>(2)? as u64,
+                    })
+                },
+            )
+            .optional()?
+            .unwrap_or_default();
+        Ok(AccountLedger {
+            balance: balance(&self.conn, ledger, account)?,
+            ..totals
+        })
+    }
+
+    /// Every account ever credited, by name.
+    pub fn accounts(&self) -> MintResult<Vec<String>> {
+        let mut statement = self
+            .conn
+            .prepare("SELECT DISTINCT account FROM account_ledger ORDER BY account")?;
+        let accounts = statement
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(accounts)
+    }
+
+    /// Weight of the shares of `account` in the open round and the rounds not matured yet.
+    pub fn pending_shares(&self, account: &str) -> MintResult<u64> {
+        let weight: i64 = self.conn.query_row(
+            "SELECT COALESCE(SUM(weight), 0) FROM round_shares
+             JOIN rounds ON rounds.id = round_shares.round_id
+             WHERE account = ?1 AND rounds.state IN ('open', 'immature')",
+            [account],
+            |row| row.get(0),
+        )?;
+        Ok(weight as u64)
+    }
+
+    /// Credits a share of `weight` to the ehash balance of `account` and to the open round.
+    pub fn credit_share(&mut self, account: &str, weight: u64) -> MintResult<()> {
+        let tx = self
+            .conn
+            .transaction_with_behavior(TransactionBehavior::Immediate)?;
+        credit_share(&tx, account, weight)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Credits shares, `(account, weight)`, as `credit_share` does, in a single transaction.
+    pub fn credit_shares(&mut self, shares: &[(String, u64)]) -> MintResult<()> {
+        let tx = self
+            .conn
+            .transaction_with_behavior(TransactionBehavior::Immediate)?;
+        for (account, weight) in shares {
+            credit_share(&tx, account, *weight)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Closes the open round with the block `finder` found paying `reward` through
+    /// `coinbase_txid`, and opens the next one.
+    pub fn close_round(
+        &mut self,
+        coinbase_txid: &str,
+        reward: u64,
+        finder: Option<&str>,
+    ) -> MintResult<u64> {
+        let tx = self
+            .conn
+            .transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let round_id = close_round(&tx, coinbase_txid, reward, finder)?;
+        tx.commit()?;
+        Ok(round_id)
+    }
+
+    /// Applies the journaled `credit` (see `journal`) unless it was replayed before, returning
+    /// whether it was.
+    pub fn replay_credit(&mut self, journaled: &Journaled) -> MintResult<bool> {
+        let tx = self
+            .conn
+            .transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let replayed = tx.execute(
+            "INSERT INTO journal_replays (id) VALUES (?1) ON CONFLICT(id) DO NOTHING",
+            [&journaled.id],
+        )?;
+        if replayed == 0 {
+            return Ok(false);
+        }
+        match &journaled.credit {
+            Credit::Share { account, weight } => credit_share(&tx, account, *weight)?,
+            Credit::Block {
+                coinbase_txid,
+                reward,
+                finder,
+            } => {
+                close_round(&tx, coinbase_txid, *reward, finder.as_deref())?;
+            }
+        }
+        tx.commit()?;
+        Ok(true)
+    }
+
+    /// Forgets the replayed credits once none of them is journaled anymore.
+    pub fn clear_replays(&self) -> MintResult<()> {
+        self.conn.execute("DELETE FROM journal_replays", [])?;
+        Ok(())
+    }
+
+    pub fn rounds(&self, state: RoundState) -> MintResult<Vec<Round>> {
+        let mut statement = self
+            .conn
+            .prepare(&format!("{} WHERE state = ?1 ORDER BY id", SELECT_ROUND))?;
+        let rounds = statement
+            .query_map([round_state_to_str(state)], round_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(rounds)
+    }
+
+    /// Rounds closed by a block found by the pool, newest first, skipping the `offset` newest.
+    pub fn found_blocks(&self, offset: u64, limit: u64) -> MintResult<Vec<Round>> {
+        let mut statement = self.conn.prepare(&format!(
+            "{} WHERE state != 'open' ORDER BY id DESC LIMIT ?1 OFFSET ?2",
+            SELECT_ROUND
+        ))?;
+        let rounds = statement
+            .query_map(params![limit as i64, offset as i64], round_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(rounds)
+    }
+
+    /// Rounds `account` has shares in, with their weight, newest first, skipping the `offset`
+    /// newest.
+    pub fn account_rounds(
+        &self,
+        account: &str,
+        offset: u64,
+        limit: u64,
+    ) -> MintResult<Vec<AccountRound>> {
+        let mut statement = self.conn.prepare(
+            "SELECT r.id, r.state, r.started_at, r.ended_at, r.coinbase_txid, r.reward, r.height,
+                    r.block_hash, r.finder, s.weight,
+                    (SELECT SUM(weight) FROM round_shares WHERE round_id = r.id)
+             FROM round_shares s JOIN rounds r ON r.id = s.round_id
+             WHERE s.account = ?1 ORDER BY r.id DESC LIMIT ?2 OFFSET ?3",
+        )?;
+        let rounds = statement
+            .query_map(params![account, limit as i64, offset as i64], |row| {
+                Ok(AccountRound {
+                    round: round_from_row(row)?,
+                    weight: row.get::<_, i64>(9)? as u64,
+                    round_weight: row.get::<_, i64>(10)? as u64,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(rounds)
+    }
+
+    /// Records the block of a round seen in the best chain, at `height` with hash `block_hash`.
+    pub fn set_round_block(&self, round_id: u64, height: u64, block_hash: &str) -> MintResult<()> {
+        self.conn.execute(
+            "UPDATE rounds SET height = ?2, block_hash = ?3 WHERE id = ?1",
+            params![round_id as i64, height as i64, block_hash],
+        )?;
+        Ok(())
+    }
+
+    /// Settles the ehash of an immature round with its reward, see `rounds`. Without `settle` the
+    /// round is only marked matured, for mints not issuing sat.
+    pub fn mature_round(&mut self, round_id: u64, settle: bool) -> MintResult<Settlement> {
+        let tx = self
+            .conn
+            .transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let reward: Option<i64> = tx
+            .query_row(
+                "SELECT reward FROM rounds WHERE id = ?1 AND state = 'immature'",
+                [round_id as i64],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let Some(reward) = reward else {
+            // matured or orphaned already
+            return Ok(Settlement::default());
+        };
+        let shares = {
+            let mut statement =
+                tx.prepare("SELECT account, weight FROM round_shares WHERE round_id = ?1")?;
+            let shares = statement
+                .query_map([round_id as i64], |row| {
+                    Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)? as u64))
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+            shares
+        };
+        let mut settlement = Settlement::default();
+        let mut withdrawn_weight = 0;
+        let payouts = match settle {
+            true => split_reward(reward as u64, &shares),
+            false => vec![],
+        };
+        for (account, payout) in payouts {
+            let weight = shares
+                .iter()
+                .find(|(a, _)| *a == account)
+                .map(|(_, weight)| *weight)
+                .unwrap_or_default();
+            let held = weight.min(balance(&tx, Ledger::Ehash, &account)?);
+            let credited = (payout as u128 * held as u128 / weight as u128) as u64;
+            debit(&tx, Ledger::Ehash, &account, held)?;
+            credit(&tx, Ledger::Sat, &account, credited)?;
+            record_round_credit(&tx, round_id, &account, Ledger::Sat, credited)?;
+            settlement.reserved += payout - credited;
+            withdrawn_weight += weight - held;
+            settlement.credited.push((account, credited));
+        }
+        tx.execute(
+            "UPDATE conversion SET reserve = reserve + ?1, outstanding = outstanding + ?2",
+            params![settlement.reserved as i64, withdrawn_weight as i64],
+        )?;
+        tx.execute(
+            "UPDATE rounds SET state = 'matured' WHERE id = ?1",
+            [round_id as i64],
+        )?;
+        tx.commit()?;
+        Ok(settlement)
+    }
+
+    /// Shares and credits of every round, oldest first, see `reconcile`.
+    pub fn round_books(&self) -> MintResult<Vec<RoundBooks>> {
+        let mut books = BTreeMap::new();
+        for state in [
+            RoundState::Open,
+            RoundState::Immature,
+            RoundState::Matured,
+            RoundState::Orphaned,
+        ] {
+            for round in self.rounds(state)? {
+                books.insert(round.id, RoundBooks::new(round));
+            }
+        }
+        let mut statement = self
+            .conn
+            .prepare("SELECT round_id, account, weight FROM round_shares")?;
+        let shares = statement.query_map([], |row| {
+            Ok((
+                row.get::<_, i64>(0)? as u64,
+                row.get::<_, String>(1)?,
+                row.get::<_, i64>(2)? as u64,
+            ))
+        })?;
+        for share in shares {
+            let (round_id, account, weight) = share?;
+            if let Some(round) = books.get_mut(&round_id) {
+                round.shares.insert(account, weight);
+            }
+        }
+        let mut statement = self
+            .conn
+            .prepare("SELECT round_id, account, unit, amount FROM round_credits")?;
+        let credits = statement.query_map([], |row| {
+            Ok((
+                row.get::<_, i64>(0)? as u64,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, i64>(3)? as u64,
+            ))
+        })?;
+        for credit in credits {
+            let (round_id, account, unit, amount) = credit?;
+            let Some(round) = books.get_mut(&round_id) else {
+                continue;
+            };
+            match unit.as_str() {
+                SAT_UNIT => round.sat.insert(account, amount),
+                _ => round.ehash.insert(account, amount),
+            };
+        }
+        Ok(books.into_values().collect())
+    }
+
+    /// Credits `account` the `amount` of `ledger` round `round_id` owes it but never credited,
+    /// see `reconcile`. The ehash of a matured round was settled into sat already, so it is only
+    /// recorded as credited and redeemed.
+    pub fn repair_round_credit(
+        &mut self,
+        round_id: u64,
+        account: &str,
+        ledger: Ledger,
+        amount: u64,
+    ) -> MintResult<()> {
+        let tx = self
+            .conn
+            .transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let state: String = tx.query_row(
+            "SELECT state FROM rounds WHERE id = ?1",
+            [round_id as i64],
+            |row| row.get(0),
+        )?;
+        match (ledger, round_state_from_str(&state)) {
+            (Ledger::Ehash, RoundState::Matured) => {
+                record(&tx, ledger, account, "credited", amount)?;
+                record(&tx, ledger, account, "redeemed", amount)?;
+            }
+            _ => credit(&tx, ledger, account, amount)?,
+        }
+        record_round_credit(&tx, round_id, account, ledger, amount)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn conversion(&self) -> MintResult<Conversion> {
+        Ok(self.conn.query_row(
+            "SELECT reserve, outstanding FROM conversion WHERE id = 0",
+            [],
+            |row| {
+                Ok(Conversion {
+                    reserve: row.get::<_, i64>(0)? as u64,
+                    outstanding: row.get::<_, i64>(1)? as u64,
+                })
+            },
+        )?)
+    }
+
+    /// Spends ehash `inputs` worth `ehash` for sat outputs worth `sat` taken from the conversion
+    /// reserve, all or nothing.
+    pub fn convert(
+        &mut self,
+        inputs: &[(PublicKey, &Proof)],
+        ehash: u64,
+        sat: u64,
+        signed: &[(PublicKey, BlindSignature)],
+    ) -> MintResult<()> {
+        let tx = self
+            .conn
+            .transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let converted = tx.execute(
+            "UPDATE conversion SET reserve = reserve - ?1, outstanding = outstanding - ?2
+             WHERE reserve >= ?1 AND outstanding >= ?2",
+            params![sat as i64, ehash as i64],
+        )?;
+        if converted == 0 {
+            let conversion: i64 =
+                tx.query_row("SELECT outstanding FROM conversion", [], |row| row.get(0))?;
+            return Err(MintError::InsufficientBalance {
+                requested: ehash,
+                available: conversion as u64,
+            });
+        }
+        spend(&tx, inputs)?;
+        Self::insert_signatures(&tx, signed)?;
+        tx.commit()?;
+        self.index_spent(inputs.iter().map(|(y, _)| y))
+    }
+
+    pub fn orphan_round(&self, round_id: u64) -> MintResult<()> {
+        self.conn.execute(
+            "UPDATE rounds SET state = 'orphaned' WHERE id = ?1 AND state = 'immature'",
+            [round_id as i64],
+        )?;
+        Ok(())
+    }
+
+    /// Writes a consistent copy of the database to `path`, which must not exist yet. Safe while
+    /// the mint is running.
+    pub fn snapshot(&self, path: &str) -> MintResult<()> {
+        self.conn.execute("VACUUM INTO ?1", [path])?;
+        Ok(())
+    }
+
+    pub fn check_integrity(&self) -> MintResult<()> {
+        let result: String = self
+            .conn
+            .query_row("PRAGMA integrity_check", [], |row| row.get(0))?;
+        match result.as_str() {
+            "ok" => Ok(()),
+            _ => Err(MintError::Storage(result)),
+        }
+    }
+
+    /// Ids of every keyset that signed an output or a proof, spent or not, was issued under.
+    pub fn referenced_keysets(&self) -> MintResult<Vec<String>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT keyset_id FROM blind_signatures UNION SELECT keyset_id FROM spent_proofs
+             UNION SELECT keyset_id FROM pending_proofs UNION SELECT keyset_id FROM queued_outputs",
+        )?;
+        let ids = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        Ok(ids)
+    }
+
+    pub fn signature_count(&self) -> MintResult<u64> {
+        self.count("blind_signatures")
+    }
+
+    pub fn spent_count(&self) -> MintResult<u64> {
+        self.count("spent_proofs")
+    }
+
+    /// Total amount of the outputs signed under each keyset.
+    pub fn issued_by_keyset(&self) -> MintResult<HashMap<String, u64>> {
+        self.sum_by_keyset("blind_signatures")
+    }
+
+    /// Total amount of the proofs spent under each keyset.
+    pub fn spent_by_keyset(&self) -> MintResult<HashMap<String, u64>> {
+        self.sum_by_keyset("spent_proofs")
+    }
+
+    /// Total amount of the proofs reserved by melts in flight under each keyset.
+    pub fn pending_by_keyset(&self) -> MintResult<HashMap<String, u64>> {
+        self.sum_by_keyset("pending_proofs")
+    }
+
+    fn sum_by_keyset(&self, table: &str) -> MintResult<HashMap<String, u64>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT keyset_id, SUM(amount) FROM {} GROUP BY keyset_id",
+            table
+        ))?;
+        let sums = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get::<_, i64>(1)? as u64)))?
+            .collect::<Result<_, _>>()?;
+        Ok(sums)
+    }
+
+    /// Total amount of the invoices and on-chain payouts paid by melts.
+    pub fn melted(&self) -> MintResult<u64> {
+        let amount: i64 = self.conn.query_row(
+            "SELECT COALESCE(SUM(amount), 0) FROM melt_quotes WHERE state = 'PAID'",
+            [],
+            |row| row.get(0),
+        )?;
+        Ok(amount as u64)
+    }
+
+    fn count(&self, table: &str) -> MintResult<u64> {
+        let count: i64 =
+            self.conn
+                .query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| {
+                    row.get(0)
+                })?;
+        Ok(count as u64)
+    }
+
+    pub fn insert_quote(&self, quote: &MintQuote) -> MintResult<()> {
+        self.conn.execute(
+            "INSERT INTO mint_quotes (id, account, amount, unit, expiry, issued)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                quote.id,
+                quote.account,
+                quote.amount as i64,
+                quote.unit,
+                quote.expiry as i64,
+                quote.issued
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn quote(&self, id: &str) -> MintResult<Option<MintQuote>> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT id, account, amount, unit, expiry, issued FROM mint_quotes WHERE id = ?1",
+                [id],
+                |row| {
+                    Ok(MintQuote {
+                        id: row.get(0)?,
+                        account: row.get(1)?,
+                        amount: row.get::<_, i64>(2)? as u64,
+                        unit: row.get(3)?,
+                        expiry: row.get::<_, i64>(4)? as u64,
+                        issued: row.get(5)?,
+                    })
+                },
+            )
+            .optional()?)
+    }
+
+    /// Forgets quotes that expired before being issued. Issued ones are kept as a record.
+    pub fn delete_expired_quotes(&self, now: u64) -> MintResult<()> {
+        self.conn.execute(
+            "DELETE FROM mint_quotes WHERE expiry <= ?1 AND issued = 0",
+            [now as i64],
+        )?;
+        Ok(())
+    }
+
+    pub fn is_spent(&self, y: &PublicKey) -> MintResult<bool> {
+        if !self.spent.may_be_spent(y) {
+            return Ok(false);
+        }
+        let spent = self
+            .conn
+            .query_row(
+                "SELECT 1 FROM spent_proofs WHERE y = ?1",
+                [y.to_string()],
+                |_| Ok(()),
+            )
+            .optional()?
+            .is_some();
+        self.spent.record_lookup(spent);
+        Ok(spent)
+    }
+
+    pub fn is_signed(&self, blinded_secret: &PublicKey) -> MintResult<bool> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT 1 FROM blind_signatures WHERE blinded_secret = ?1",
+                [blinded_secret.to_string()],
+                |_| Ok(()),
+            )
+            .optional()?
+            .is_some())
+    }
+
+    /// Signature issued for `blinded_secret`, if any.
+    pub fn signature(&self, blinded_secret: &PublicKey) -> MintResult<Option<BlindSignature>> {
+        let row: Option<(i64, String, String)> = self
+            .conn
+            .query_row(
+                "SELECT amount, keyset_id, signature FROM blind_signatures
+                 WHERE blinded_secret = ?1",
+                [blinded_secret.to_string()],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .optional()?;
+        row.map(|(amount, id, signature)| {
+            Ok(BlindSignature {
+                amount: amount as u64,
+                id,
+                blinded_signature: PublicKey::from_str(&signature)?,
+                dleq: None,
+            })
+        })
+        .transpose()
+    }
+
+    /// Queues `outputs` to be signed for `account` once its balance covers them.
+    pub fn queue_outputs(&mut self, account: &str, outputs: &[BlindedMessage]) -> MintResult<()> {
+        let tx = self
+            .conn
+            .transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let now = now_secs() as i64;
+        for output in outputs {
+            tx.execute(
+                "INSERT INTO queued_outputs (blinded_secret, account, amount, keyset_id, queued_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    output.blinded_secret.to_string(),
+                    account,
+                    output.amount as i64,
+                    output.id,
+                    now
+                ],
+            )
+            .map_err(|e| match is_constraint_violation(&e) {
+                true => MintError::OutputAlreadySigned,
+                false => e.into(),
+            })?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn is_queued(&self, blinded_secret: &PublicKey) -> MintResult<bool> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT 1 FROM queued_outputs WHERE blinded_secret = ?1",
+                [blinded_secret.to_string()],
+                |_| Ok(()),
+            )
+            .optional()?
+            .is_some())
+    }
+
+    /// Queued outputs per account, oldest first.
+    pub fn queued_outputs(&self) -> MintResult<Vec<(String, BlindedMessage)>> {
+        let mut statement = self.conn.prepare(
+            "SELECT account, amount, keyset_id, blinded_secret FROM queued_outputs
+             ORDER BY queued_at, rowid",
+        )?;
+        let rows = statement
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, String>(3)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        rows.into_iter()
+            .map(|(account, amount, id, blinded_secret)| {
+                Ok((
+                    account,
+                    BlindedMessage {
+                        amount: amount as u64,
+                        id,
+                        blinded_secret: PublicKey::from_str(&blinded_secret)?,
+                    },
+                ))
+            })
+            .collect()
+    }
+
+    /// Drops queued outputs that can't be signed anymore.
+    pub fn unqueue_outputs(&self, blinded_secrets: &[PublicKey]) -> MintResult<()> {
+        for blinded_secret in blinded_secrets {
+            self.conn.execute(
+                "DELETE FROM queued_outputs WHERE blinded_secret = ?1",
+                [blinded_secret.to_string()],
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Debits `amount` from `account`, marks `quote_id` (if any) as issued and records the
+    /// signatures, all or nothing. Signed outputs leave the queue.
+    pub fn issue(
+        &mut self,
+        ledger: Ledger,
+        account: &str,
+        amount: u64,
+        quote_id: Option<&str>,
+        signed: &[(PublicKey, BlindSignature)],
+    ) -> MintResult<()> {
+        let tx = self
+            .conn
+            .transaction_with_behavior(TransactionBehavior::Immediate)?;
+        withdraw(&tx, ledger, account, amount)?;
+        if let Some(quote_id) = quote_id {
+            let marked = tx.execute(
+                "UPDATE mint_quotes SET issued = 1 WHERE id = ?1 AND issued = 0",
+                [quote_id],
+            )?;
+            if marked == 0 {
+                return Err(MintError::QuoteAlreadyIssued(quote_id.to_string()));
+            }
+        }
+        Self::insert_signatures(&tx, signed)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Debits the balance `payout` was minted from and keeps it for delivery, all or nothing.
+    pub fn issue_payout(
+        &mut self,
+        ledger: Ledger,
+        payout: &Payout,
+        signed: &[(PublicKey, BlindSignature)],
+    ) -> MintResult<()> {
+        let tx = self
+            .conn
+            .transaction_with_behavior(TransactionBehavior::Immediate)?;
+        withdraw(&tx, ledger, &payout.account, payout.amount)?;
+        Self::insert_signatures(&tx, signed)?;
+        tx.execute(
+            "INSERT INTO payouts (id, account, unit, amount, token, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                payout.id,
+                payout.account,
+                payout.unit,
+                payout.amount as i64,
+                payout.token,
+                payout.created_at as i64
+            ],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Payouts not delivered yet, of `account` or of every account, oldest first.
+    pub fn undelivered_payouts(&self, account: Option<&str>) -> MintResult<Vec<Payout>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, account, unit, amount, token, created_at FROM payouts
+             WHERE delivered_at IS NULL AND (?1 IS NULL OR account = ?1)
+             ORDER BY created_at, id",
+        )?;
+        let payouts = stmt
+            .query_map([account], |row| {
+                Ok(Payout {
+                    id: row.get(0)?,
+                    account: row.get(1)?,
+                    unit: row.get(2)?,
+                    amount: row.get::<_, i64>(3)? as u64,
+                    token: row.get(4)?,
+                    created_at: row.get::<_, i64>(5)? as u64,
+                    delivered_at: None,
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+        Ok(payouts)
+    }
+
+    /// Payouts created from `since` until `until`, unix times in seconds, totalled per unit.
+    pub fn payout_totals(&self, since: u64, until: u64) -> MintResult<Vec<PayoutTotal>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT unit, COUNT(*), SUM(amount), COUNT(delivered_at) FROM payouts
+             WHERE created_at >= ?1 AND created_at <= ?2 GROUP BY unit ORDER BY unit",
+        )?;
+        let totals = stmt
+            .query_map(params![since as i64, until as i64], |row| {
+                Ok(PayoutTotal {
+                    unit: row.get(0)?,
+                    count: row.get::<_, i64>(1)? as u64,
+                    amount: row.get::<_, i64>(2)? as u64,
+                    delivered: row.get::<_, i64>(3)? as u64,
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+        Ok(totals)
+    }
+
+    pub fn mark_payout_delivered(&self, id: &str) -> MintResult<()> {
+        self.conn.execute(
+            "UPDATE payouts SET delivered_at = ?2 WHERE id = ?1 AND delivered_at IS NULL",
+            params![id, now_secs() as i64],
+        )?;
+        Ok(())
+    }
+
+    /// Digest of the credential issued to `account`, see `credentials`.
+    pub fn account_credential(&self, account: &str) -> MintResult<Option<String>> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT digest FROM account_credentials WHERE account = ?1",
+                [account],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    /// Sets the digest of the credential of `account`, replacing the one it had.
+    pub fn set_account_credential(&self, account: &str, digest: &str) -> MintResult<()> {
+        self.conn.execute(
+            "INSERT INTO account_credentials (account, digest, issued_at) VALUES (?1, ?2, ?3)
+             ON CONFLICT(account) DO UPDATE SET
+                digest = excluded.digest, issued_at = excluded.issued_at",
+            params![account, digest, now_secs() as i64],
+        )?;
+        Ok(())
+    }
+
+    pub fn account_pubkey(&self, account: &str) -> MintResult<Option<PublicKey>> {
+        account_key(&self.conn, "account_pubkeys", account)
+    }
+
+    /// Sets the key of `account`, replacing the one it has.
+    pub fn set_account_pubkey(&self, account: &str, pubkey: &PublicKey) -> MintResult<()> {
+        set_account_key(&self.conn, "account_pubkeys", account, pubkey)
+    }
+
+    /// The Nostr key payouts of `account` are sent to, see `nostr`.
+    pub fn nostr_key(&self, account: &str) -> MintResult<Option<XOnlyPublicKey>> {
+        account_key(&self.conn, "nostr_keys", account)
+    }
+
+    /// Sets the Nostr key of `account` like `set_account_pubkey`.
+    pub fn set_nostr_key(&self, account: &str, pubkey: &XOnlyPublicKey) -> MintResult<()> {
+        set_account_key(&self.conn, "nostr_keys", account, pubkey)
+    }
+
+    /// Accounts holding at least `min` in `ledger`, with their balance.
+    pub fn balances(&self, ledger: Ledger, min: u64) -> MintResult<Vec<(String, u64)>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT account, amount FROM {} WHERE amount >= ?1 ORDER BY account",
+            ledger.table()
+        ))?;
+        let balances = stmt
+            .query_map([min as i64], |row| {
+                Ok((row.get(0)?, row.get::<_, i64>(1)? as u64))
+            })?
+            .collect::<Result<_, _>>()?;
+        Ok(balances)
+    }
+
+    /// Fees collected so far, per unit and operation.
+    pub fn fees(&self) -> MintResult<Vec<FeeTotal>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT unit, operation, amount FROM fees ORDER BY unit, operation")?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, i64>(2)? as u64,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(rows
+            .into_iter()
+            .filter_map(|(unit, operation, amount)| {
+                Some(FeeTotal {
+                    unit,
+                    operation: operation_from_str(&operation)?,
+                    amount,
+                })
+            })
+            .collect())
+    }
+
+    /// Marks `inputs` as spent, records the signatures of their replacement and collects the
+    /// `fee` in `unit` they paid, all or nothing.
+    pub fn swap(
+        &mut self,
+        inputs: &[(PublicKey, &Proof)],
+        signed: &[(PublicKey, BlindSignature)],
+        unit: &str,
+        fee: u64,
+    ) -> MintResult<()> {
+        let tx = self
+            .conn
+            .transaction_with_behavior(TransactionBehavior::Immediate)?;
+        spend(&tx, inputs)?;
+        Self::insert_signatures(&tx, signed)?;
+        collect_fee(&tx, unit, Operation::Swap, fee)?;
+        tx.commit()?;
+        self.index_spent(inputs.iter().map(|(y, _)| y))
+    }
+
+    pub fn is_pending(&self, y: &PublicKey) -> MintResult<bool> {
+        Ok(self.spent.is_pending(y))
+    }
+
+    /// State of the proof `y`, for wallets asking rather than redeeming it.
+    pub fn proof_state(&self, y: &PublicKey) -> MintResult<SpendState> {
+        if self.spent.is_reserved(y) {
+            return Ok(SpendState::Pending);
+        }
+        if !self.spent.may_contain(y) {
+            return Ok(SpendState::Unspent);
+        }
+        let spent = self
+            .conn
+            .query_row(
+                "SELECT 1 FROM spent_proofs WHERE y = ?1",
+                [y.to_string()],
+                |_| Ok(()),
+            )
+            .optional()?
+            .is_some();
+        Ok(match spent {
+            true => SpendState::Spent,
+            false => SpendState::Unspent,
+        })
+    }
+
+    /// Ys of the proofs reserved by the melt `quote_id`.
+    pub fn reserved(&self, quote_id: &str) -> Vec<PublicKey> {
+        self.spent.reserved(quote_id)
+    }
+
+    pub fn insert_melt_quote(&self, quote: &MeltQuote) -> MintResult<()> {
+        self.conn.execute(
+            "INSERT INTO melt_quotes
+             (id, request, payment_hash, amount, fee_reserve, state, expiry, method)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                quote.id,
+                quote.request,
+                quote.payment_hash,
+                quote.amount as i64,
+                quote.fee_reserve as i64,
+                melt_state_to_str(quote.state),
+                quote.expiry as i64,
+                quote.method
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn melt_quote(&self, id: &str) -> MintResult<Option<MeltQuote>> {
+        Ok(self
+            .conn
+            .query_row(
+                &format!("{} WHERE id = ?1", SELECT_MELT_QUOTE),
+                [id],
+                melt_quote_from_row,
+            )
+            .optional()?)
+    }
+
+    /// Lightning melts in progress. On-chain melts wait for their batch instead.
+    pub fn pending_melt_quotes(&self) -> MintResult<Vec<MeltQuote>> {
+        let mut statement = self.conn.prepare(&format!(
+            "{} WHERE state = 'PENDING' AND method = 'bolt11'",
+            SELECT_MELT_QUOTE
+        ))?;
+        let quotes = statement
+            .query_map([], melt_quote_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(quotes)
+    }
+
+    /// Total amount of the proofs reserved by a melt.
+    pub fn pending_total(&self, quote_id: &str) -> MintResult<u64> {
+        let total: i64 = self.conn.query_row(
+            "SELECT COALESCE(SUM(amount), 0) FROM pending_proofs WHERE quote_id = ?1",
+            [quote_id],
+            |row| row.get(0),
+        )?;
+        Ok(total as u64)
+    }
+
+    /// Reserves `inputs` for the melt `quote_id` and marks the quote pending with the `input_fee`
+    /// they pay, all or nothing.
+    pub fn begin_melt(
+        &mut self,
+        quote_id: &str,
+        inputs: &[(PublicKey, &Proof)],
+        input_fee: u64,
+    ) -> MintResult<()> {
+        let tx = self
+            .conn
+            .transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let marked = tx.execute(
+            "UPDATE melt_quotes SET state = 'PENDING', input_fee = ?2
+             WHERE id = ?1 AND state = 'UNPAID'",
+            params![quote_id, input_fee as i64],
+        )?;
+        if marked == 0 {
+            return Err(MintError::QuotePending(quote_id.to_string()));
+        }
+        for (y, proof) in inputs {
+            let spent = tx
+                .query_row(
+                    "SELECT 1 FROM spent_proofs WHERE y = ?1",
+                    [y.to_string()],
+                    |_| Ok(()),
+                )
+                .optional()?;
+            if spent.is_some() {
+                return Err(MintError::ProofAlreadySpent);
+            }
+            tx.execute(
+                "INSERT INTO pending_proofs (y, amount, keyset_id, quote_id) VALUES (?1, ?2, ?3, ?4)",
+                params![y.to_string(), proof.amount as i64, proof.id, quote_id],
+            )
+            .map_err(|e| match is_constraint_violation(&e) {
+                true => MintError::ProofPending,
+                false => e.into(),
+            })?;
+        }
+        tx.commit()?;
+        self.spent.reserve(inputs.iter().map(|(y, _)| y), quote_id);
+        Ok(())
+    }
+
+    /// Spends the proofs reserved by a paid melt, records its preimage and change, and collects
+    /// its input fee in `unit`.
+    pub fn complete_melt(
+        &mut self,
+        quote_id: &str,
+        payment_preimage: &str,
+        change: &[(PublicKey, BlindSignature)],
+        unit: &str,
+    ) -> MintResult<()> {
+        let tx = self
+            .conn
+            .transaction_with_behavior(TransactionBehavior::Immediate)?;
+        tx.execute(
+            "INSERT INTO spent_proofs (y, amount, keyset_id, spent_at)
+             SELECT y, amount, keyset_id, ?2 FROM pending_proofs WHERE quote_id = ?1",
+            params![quote_id, now_secs() as i64],
+        )?;
+        tx.execute("DELETE FROM pending_proofs WHERE quote_id = ?1", [quote_id])?;
+        tx.execute(
+            "UPDATE melt_quotes SET state = 'PAID', payment_preimage = ?2 WHERE id = ?1",
+            params![quote_id, payment_preimage],
+        )?;
+        let input_fee: i64 = tx.query_row(
+            "SELECT input_fee FROM melt_quotes WHERE id = ?1",
+            [quote_id],
+            |row| row.get(0),
+        )?;
+        collect_fee(&tx, unit, Operation::Melt, input_fee as u64)?;
+        Self::insert_signatures(&tx, change)?;
+        tx.commit()?;
+        let ys = self.spent.reserved(quote_id);
+        self.index_spent(&ys)
+    }
+
+    /// Marks the on-chain melt of `payout` pending, spends its `inputs`, records its `change`,
+    /// collects its `input_fee` and queues the payout for the next batch, all or nothing.
+    pub fn queue_onchain_melt(
+        &mut self,
+        payout: &OnchainPayout,
+        inputs: &[(PublicKey, &Proof)],
+        change: &[(PublicKey, BlindSignature)],
+        input_fee: u64,
+        unit: &str,
+    ) -> MintResult<()> {
+        let tx = self
+            .conn
+            .transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let marked = tx.execute(
+            "UPDATE melt_quotes SET state = 'PENDING', input_fee = ?2
+             WHERE id = ?1 AND state = 'UNPAID'",
+            params![payout.quote_id, input_fee as i64],
+        )?;
+        if marked == 0 {
+            return Err(MintError::QuotePending(payout.quote_id.clone()));
+        }
+        spend(&tx, inputs)?;
+        Self::insert_signatures(&tx, change)?;
+        collect_fee(&tx, unit, Operation::Melt, input_fee)?;
+        tx.execute(
+            "INSERT INTO onchain_payouts (quote_id, address, amount) VALUES (?1, ?2, ?3)",
+            params![payout.quote_id, payout.address, payout.amount as i64],
+        )?;
+        tx.commit()?;
+        self.index_spent(inputs.iter().map(|(y, _)| y))
+    }
+
+    /// On-chain payouts not in a batch yet, oldest first.
+    pub fn onchain_payouts(&self) -> MintResult<Vec<OnchainPayout>> {
+        let mut statement = self.conn.prepare(
+            "SELECT quote_id, address, amount FROM onchain_payouts WHERE txid IS NULL
+             ORDER BY rowid",
+        )?;
+        let payouts = statement
+            .query_map([], |row| {
+                Ok(OnchainPayout {
+                    quote_id: row.get(0)?,
+                    address: row.get(1)?,
+                    amount: row.get::<_, i64>(2)? as u64,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(payouts)
+    }
+
+    /// Records `batch` as paying the payouts of `quote_ids`, before it is broadcast.
+    pub fn record_onchain_batch(
+        &mut self,
+        batch: &OnchainBatch,
+        quote_ids: &[String],
+    ) -> MintResult<()> {
+        let tx = self
+            .conn
+            .transaction_with_behavior(TransactionBehavior::Immediate)?;
+        tx.execute(
+            "INSERT INTO onchain_batches (txid, tx, created_at) VALUES (?1, ?2, ?3)",
+            params![batch.txid, batch.tx, now_secs() as i64],
+        )?;
+        for quote_id in quote_ids {
+            let assigned = tx.execute(
+                "UPDATE onchain_payouts SET txid = ?2 WHERE quote_id = ?1 AND txid IS NULL",
+                params![quote_id, batch.txid],
+            )?;
+            if assigned == 0 {
+                return Err(MintError::Storage(format!(
+                    "payout of {} is not queued",
+                    quote_id
+                )));
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Batches recorded but not known to be broadcast.
+    pub fn unsent_onchain_batches(&self) -> MintResult<Vec<OnchainBatch>> {
+        let mut statement = self
+            .conn
+            .prepare("SELECT txid, tx FROM onchain_batches WHERE sent_at IS NULL")?;
+        let batches = statement
+            .query_map([], |row| {
+                Ok(OnchainBatch {
+                    txid: row.get(0)?,
+                    tx: row.get(1)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(batches)
+    }
+
+    /// Marks the batch `txid` as broadcast and the melts it pays as paid, with the txid as
+    /// preimage. Returns their quote ids.
+    pub fn settle_onchain_batch(&mut self, txid: &str) -> MintResult<Vec<String>> {
+        let tx = self
+            .conn
+            .transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let quote_ids = tx
+            .prepare("SELECT quote_id FROM onchain_payouts WHERE txid = ?1")?
+            .query_map([txid], |row| row.get(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+        tx.execute(
+            "UPDATE melt_quotes SET state = 'PAID', payment_preimage = ?1
+             WHERE id IN (SELECT quote_id FROM onchain_payouts WHERE txid = ?1)",
+            [txid],
+        )?;
+        tx.execute(
+            "UPDATE onchain_batches SET sent_at = ?2 WHERE txid = ?1",
+            params![txid, now_secs() as i64],
+        )?;
+        tx.commit()?;
+        Ok(quote_ids)
+    }
+
+    /// Total amount of the on-chain melts waiting for their batch to be sent.
+    pub fn queued_onchain(&self) -> MintResult<u64> {
+        let amount: i64 = self.conn.query_row(
+            "SELECT COALESCE(SUM(amount), 0) FROM melt_quotes
+             WHERE state = 'PENDING' AND method = 'onchain'",
+            [],
+            |row| row.get(0),
+        )?;
+        Ok(amount as u64)
+    }
+
+    /// Gives the proofs reserved by a failed melt back to the wallet.
+    pub fn release_melt(&mut self, quote_id: &str) -> MintResult<()> {
+        let tx = self
+            .conn
+            .transaction_with_behavior(TransactionBehavior::Immediate)?;
+        tx.execute("DELETE FROM pending_proofs WHERE quote_id = ?1", [quote_id])?;
+        tx.execute(
+            "UPDATE melt_quotes SET state = 'UNPAID', input_fee = 0 WHERE id = ?1",
+            [quote_id],
+        )?;
+        tx.commit()?;
+        self.spent.release(quote_id);
+        Ok(())
+    }
+
+    fn insert_signatures(
+        tx: &rusqlite::Transaction,
+        signed: &[(PublicKey, BlindSignature)],
+    ) -> MintResult<()> {
+        let now = now_secs() as i64;
+        for (blinded_secret, signature) in signed {
+            tx.execute(
+                "DELETE FROM queued_outputs WHERE blinded_secret = ?1",
+                [blinded_secret.to_string()],
+            )?;
+            tx.execute(
+                "INSERT INTO blind_signatures (blinded_secret, amount, keyset_id, signature, created_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    blinded_secret.to_string(),
+                    signature.amount as i64,
+                    signature.id,
+                    signature.blinded_signature.to_string(),
+                    now
+                ],
+            )
+            .map_err(|e| match is_constraint_violation(&e) {
+                true => MintError::OutputAlreadySigned,
+                false => e.into(),
+            })?;
+        }
+        Ok(())
+    }
+}
+
+fn balance(conn: &Connection, ledger: Ledger, account: &str) -> MintResult<u64> {
+    let amount: Option<i64> = conn
+        .query_row(
+            &format!("SELECT amount FROM {} WHERE account = ?1", ledger.table()),
+            [account],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(amount.unwrap_or_default() as u64)
+}
+
+fn credit(conn: &Connection, ledger: Ledger, account: &str, amount: u64) -> MintResult<()> {
+    conn.execute(
+        &format!(
+            "INSERT INTO {} (account, amount) VALUES (?1, ?2)
+             ON CONFLICT(account) DO UPDATE SET amount = amount + excluded.amount",
+            ledger.table()
+        ),
+        params![account, amount as i64],
+    )?;
+    record(conn, ledger, account, "credited", amount)
+}
+
+/// Debits `amount` from `account`, failing if its balance doesn't cover it.
+fn withdraw(conn: &Connection, ledger: Ledger, account: &str, amount: u64) -> MintResult<()> {
+    let debited = conn.execute(
+        &format!(
+            "UPDATE {} SET amount = amount - ?2 WHERE account = ?1 AND amount >= ?2",
+            ledger.table()
+        ),
+        params![account, amount as i64],
+    )?;
+    if debited == 0 && amount > 0 {
+        return Err(MintError::InsufficientBalance {
+            requested: amount,
+            available: balance(conn, ledger, account)?,
+        });
+    }
+    record(conn, ledger, account, "issued", amount)
+}
+
+/// Debits `amount` the balance of `account` covers, redeeming it for the other unit.
+fn debit(conn: &Connection, ledger: Ledger, account: &str, amount: u64) -> MintResult<()> {
+    conn.execute(
+        &format!(
+            "UPDATE {} SET amount = amount - ?2 WHERE account = ?1",
+            ledger.table()
+        ),
+        params![account, amount as i64],
+    )?;
+    record(conn, ledger, account, "redeemed", amount)
+}
+
+/// Adds `amount` to the `total` of `account` in the account ledger.
+fn record(
+    conn: &Connection,
+    ledger: Ledger,
+    account: &str,
+    total: &str,
+    amount: u64,
+) -> MintResult<()> {
+    if amount == 0 {
+        return Ok(());
+    }
+    conn.execute(
+        &format!(
+            "INSERT INTO account_ledger (account, unit, {0}) VALUES (?1, ?2, ?3)
+             ON CONFLICT(account, unit) DO UPDATE SET {0} = {0} + excluded.{0}",
+            total
+        ),
+        params![account, ledger.unit(), amount as i64],
+    )?;
+    Ok(())
+}
+
+/// The key of `account` in `table`, payout or Nostr keys.
+fn account_key<K: FromStr>(conn: &Connection, table: &str, account: &str) -> MintResult<Option<K>>
+where
+    K::Err: ToString,
+{
+    let pubkey: Option<String> = conn
+        .query_row(
+            &format!("SELECT pubkey FROM {} WHERE account = ?1", table),
+            [account],
+            |row| row.get(0),
+        )
+        .optional()?;
+    pubkey
+        .map(|pubkey| K::from_str(&pubkey).map_err(|e| MintError::Storage(e.to_string())))
+        .transpose()
+}
+
+fn set_account_key(
+    conn: &Connection,
+    table: &str,
+    account: &str,
+    pubkey: &impl ToString,
+) -> MintResult<()> {
+    conn.execute(
+        &format!(
+            "INSERT INTO {} (account, pubkey, registered_at) VALUES (?1, ?2, ?3)
+             ON CONFLICT(account) DO UPDATE SET
+                pubkey = excluded.pubkey, registered_at = excluded.registered_at",
+            table
+        ),
+        params![account, pubkey.to_string(), now_secs() as i64],
+    )?;
+    Ok(())
+}
+
+fn spend(conn: &Connection, inputs: &[(PublicKey, &Proof)]) -> MintResult<()> {
+    let now = now_secs() as i64;
+    for (y, proof) in inputs {
+        conn.execute(
+            "INSERT INTO spent_proofs (y, amount, keyset_id, spent_at) VALUES (?1, ?2, ?3, ?4)",
+            params![y.to_string(), proof.amount as i64, proof.id, now],
+        )
+        .map_err(|e| match is_constraint_violation(&e) {
+            true => MintError::ProofAlreadySpent,
+            false => e.into(),
+        })?;
+    }
+    Ok(())
+}
+
+/// Migrates the database at `path` to the schema version `target`, the latest of this release if
+/// `None`, and returns the version it is at. The pool must not be running.
+pub fn migrate_database(path: &str, target: Option<u32>) -> MintResult<u32> {
+    let mut conn = Connection::open(path)?;
+    match target {
+        Some(target) => migrations::migrate_to(&mut conn, MIGRATIONS, target),
+        None => migrations::migrate(&mut conn, MIGRATIONS),
+    }
+}
+
+fn credit_share(conn: &Connection, account: &str, weight: u64) -> MintResult<()> {
+    credit(conn, Ledger::Ehash, account, weight)?;
+    let round_id = open_round(conn)?;
+    conn.execute(
+        "INSERT INTO round_shares (round_id, account, weight) VALUES (?1, ?2, ?3)
+         ON CONFLICT(round_id, account) DO UPDATE SET weight = weight + excluded.weight",
+        params![round_id, account, weight as i64],
+    )?;
+    record_round_credit(conn, round_id as u64, account, Ledger::Ehash, weight)
+}
+
+/// Adds `amount` to what round `round_id` credited `account`, see `reconcile`.
+fn record_round_credit(
+    conn: &Connection,
+    round_id: u64,
+    account: &str,
+    ledger: Ledger,
+    amount: u64,
+) -> MintResult<()> {
+    conn.execute(
+        "INSERT INTO round_credits (round_id, account, unit, amount) VALUES (?1, ?2, ?3, ?4)
+         ON CONFLICT(round_id, account, unit) DO UPDATE SET amount = amount + excluded.amount",
+        params![round_id as i64, account, ledger.unit(), amount as i64],
+    )?;
+    Ok(())
+}
+
+fn close_round(
+    conn: &Connection,
+    coinbase_txid: &str,
+    reward: u64,
+    finder: Option<&str>,
+) -> MintResult<u64> {
+    let round_id = open_round(conn)?;
+    conn.execute(
+        "UPDATE rounds SET state = 'immature', ended_at = ?2, coinbase_txid = ?3, reward = ?4,
+         finder = ?5 WHERE id = ?1",
+        params![
+            round_id,
+            now_secs() as i64,
+            coinbase_txid,
+            reward as i64,
+            finder
+        ],
+    )?;
+    open_round(conn)?;
+    Ok(round_id as u64)
+}
+
+/// Id of the open round, opening one if there is none.
+fn open_round(conn: &Connection) -> MintResult<i64> {
+    let id: Option<i64> = conn
+        .query_row("SELECT id FROM rounds WHERE state = 'open'", [], |row| {
+            row.get(0)
+        })
+        .optional()?;
+    if let Some(id) = id {
+        return Ok(id);
+    }
+    conn.execute(
+        "INSERT INTO rounds (state, started_at) VALUES ('open', ?1)",
+        [now_secs() as i64],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+/// A filter of every spent proof, sized for twice as many.
+fn spent_filter(conn: &Connection) -> MintResult<BloomFilter> {
+    let count: i64 = conn.query_row("SELECT COUNT(*) FROM spent_proofs", [], |row| row.get(0))?;
+    let mut filter = BloomFilter::with_capacity(count as u64 * 2);
+    let mut stmt = conn.prepare("SELECT y FROM spent_proofs")?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        let y: String = row.get(0)?;
+        let mut bytes = [0; 33];
+        hex::decode_to_slice(&y, &mut bytes)
+            .map_err(|e| MintError::Storage(format!("invalid spent proof {}: {}", y, e)))?;
+        filter.insert(&bytes);
+    }
+    Ok(filter)
+}
+
+fn pending_proofs(conn: &Connection) -> MintResult<HashMap<PublicKey, String>> {
+    let mut stmt = conn.prepare("SELECT y, quote_id FROM pending_proofs")?;
+    let rows = stmt
+        .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get(1)?)))?
+        .collect::<Result<Vec<(String, String)>, _>>()?;
+    rows.into_iter()
+        .map(|(y, quote_id)| {
+            let y = PublicKey::from_str(&y).map_err(|e| MintError::Storage(e.to_string()))?;
+            Ok((y, quote_id))
+        })
+        .collect()
+}
+
+/// Adds `amount` to the fees collected in `unit` by `operation`.
+fn collect_fee(conn: &Connection, unit: &str, operation: Operation, amount: u64) -> MintResult<()> {
+    if amount == 0 {
+        return Ok(());
+    }
+    conn.execute(
+        "INSERT INTO fees (unit, operation, amount) VALUES (?1, ?2, ?3)
+         ON CONFLICT(unit, operation) DO UPDATE SET amount = amount + excluded.amount",
+        params![unit, operation_to_str(operation), amount as i64],
+    )?;
+    Ok(())
+}
+
+fn operation_to_str(operation: Operation) -> &'static str {
+    match operation {
+        Operation::Swap => "swap",
+        Operation::Melt => "melt",
+    }
+}
+
+fn operation_from_str(operation: &str) -> Option<Operation> {
+    match operation {
+        "swap" => Some(Operation::Swap),
+        "melt" => Some(Operation::Melt),
+        _ => None,
+    }
+}
+
+const SELECT_ROUND: &str =
+    "SELECT id, state, started_at, ended_at, coinbase_txid, reward, height, block_hash, finder
+     FROM rounds";
+
+fn round_from_row(row: &rusqlite::Row) -> rusqlite::Result<Round> {
+    Ok(Round {
+        id: row.get::<_, i64>(0)? as u64,
+        state: round_state_from_str(&row.get::<_, String>(1)?),
+        started_at: row.get::<_, i64>(2)? as u64,
+        ended_at: row.get::<_, Option<i64>>(3)?.map(|t| t as u64),
+        coinbase_txid: row.get(4)?,
+        reward: row.get::<_, Option<i64>>(5)?.map(|r| r as u64),
+        height: row.get::<_, Option<i64>>(6)?.map(|h| h as u64),
+        block_hash: row.get(7)?,
+        finder: row.get(8)?,
+    })
+}
+
+fn round_state_to_str(state: RoundState) -> &'static str {
+    match state {
+        RoundState::Open => "open",
+        RoundState::Immature => "immature",
+        RoundState::Matured => "matured",
+        RoundState::Orphaned => "orphaned",
+    }
+}
+
+fn round_state_from_str(state: &str) -> RoundState {
+    match state {
+        "immature" => RoundState::Immature,
+        "matured" => RoundState::Matured,
+        "orphaned" => RoundState::Orphaned,
+        _ => RoundState::Open,
+    }
+}
+
+const SELECT_MELT_QUOTE: &str =
+    "SELECT id, request, payment_hash, amount, fee_reserve, state, expiry,
+    payment_preimage, input_fee, method FROM melt_quotes";
+
+fn melt_quote_from_row(row: &rusqlite::Row) -> rusqlite::Result<MeltQuote> {
+    Ok(MeltQuote {
+        id: row.get(0)?,
+        request: row.get(1)?,
+        payment_hash: row.get(2)?,
+        amount: row.get::<_, i64>(3)? as u64,
+        fee_reserve: row.get::<_, i64>(4)? as u64,
+        state: melt_state_from_str(&row.get::<_, String>(5)?),
+        expiry: row.get::<_, i64>(6)? as u64,
+        payment_preimage: row.get(7)?,
+        input_fee: row.get::<_, i64>(8)? as u64,
+        method: row.get(9)?,
+    })
+}
+
+fn melt_state_to_str(state: MeltQuoteState) -> &'static str {
+    match state {
+        MeltQuoteState::Unpaid => "UNPAID",
+        MeltQuoteState::Pending => "PENDING",
+        MeltQuoteState::Paid => "PAID",
+    }
+}
+
+fn melt_state_from_str(state: &str) -> MeltQuoteState {
+    match state {
+        "PAID" => MeltQuoteState::Paid,
+        "PENDING" => MeltQuoteState::Pending,
+        _ => MeltQuoteState::Unpaid,
+    }
+}
+
+fn is_constraint_violation(e: &rusqlite::Error) -> bool {
+    matches!(e, rusqlite::Error::SqliteFailure(e, _) if e.code == ErrorCode::ConstraintViolation)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pool_mint::mint::dhke;
+
+    #[test]
+    fn survives_reopen_without_double_spend() {
+        let path = std::env::temp_dir().join(format!("potato-mint-{}.sqlite", std::process::id()));
+        let path = path.to_str().unwrap();
+        let (b, _) = dhke::blind_message(b"output", None).unwrap();
+        let signed = vec![(
+            b,
+            BlindSignature {
+                amount: 2,
+                id: "00ab".into(),
+                blinded_signature: b,
+                dleq: None,
+            },
+        )];
+        let y = dhke::hash_to_curve(b"input").unwrap();
+        let proof = Proof {
+            amount: 2,
+            id: "00ab".into(),
+            secret: "input".into(),
+            signature: b,
+            witness: None,
+        };
+        {
+            let mut db = MintDb::open(path).unwrap();
+            db.credit_share("dave", 5).unwrap();
+            db.issue(Ledger::Ehash, "dave", 2, None, &signed).unwrap();
+            db.swap(&[(y, &proof)], &[], "ehash", 0).unwrap();
+        }
+        let mut db = MintDb::open(path).unwrap();
+        assert_eq!(db.balance(Ledger::Ehash, "dave").unwrap(), 3);
+        assert!(db.is_spent(&y).unwrap());
+        assert!(db.is_signed(&b).unwrap());
+        assert!(matches!(
+            db.swap(&[(y, &proof)], &[], "ehash", 0),
+            Err(MintError::ProofAlreadySpent)
+        ));
+        assert!(matches!(
+            db.issue(Ledger::Ehash, "dave", 1, None, &signed),
+            Err(MintError::OutputAlreadySigned)
+        ));
+        // the failed issuance was rolled back
+        assert_eq!(db.balance(Ledger::Ehash, "dave").unwrap(), 3);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path, suffix));
+        }
+    }
+
+    #[test]
+    fn migrates_the_database_down_and_up() {
+        let path =
+            std::env::temp_dir().join(format!("potato-mint-migrate-{}.sqlite", std::process::id()));
+        let path = path.to_str().unwrap();
+        MintDb::open(path).unwrap().credit_share("dave", 5).unwrap();
+        let latest = MIGRATIONS.len() as u32;
+        let db = MintDb::open(path).unwrap();
+        assert_eq!(migrations::version(&db.conn).unwrap(), latest);
+        assert_eq!(db.balance(Ledger::Ehash, "dave").unwrap(), 5);
+        drop(db);
+        assert_eq!(migrate_database(path, Some(1)).unwrap(), 1);
+        assert_eq!(migrate_database(path, None).unwrap(), latest);
+        assert!(MintDb::open(path).is_ok());
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path, suffix));
+        }
+    }
+}
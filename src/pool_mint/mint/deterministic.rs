@@ -0,0 +1,120 @@
+//! Deterministic secrets of the miner wallet (NUT-13), so a wallet that lost its file recovers
+//! its proofs from the mnemonic alone, see `Wallet::restore`. The secret and blinding factor of
+//! output `counter` of keyset `id` are the private keys at the BIP32 paths
+//! `m/129372'/0'/{id mod 2^31 - 1}'/{counter}'/0` and `.../1` of the BIP39 seed, so the outputs
+//! match those of any NUT-13 wallet with the same mnemonic.
+//!
+//! The mint keeps every signature it issued, and hands them out again for the outputs they sign
+//! under `/v1/restore` (NUT-09).
+use crate::error::{MintError, MintResult};
+use bip39::Mnemonic;
+use secp256k1::SecretKey;
+use sha2::{Digest, Sha256};
+use stratum_common::bitcoin::{
+    secp256k1::{All, Secp256k1},
+    util::bip32::{ChildNumber, ExtendedPrivKey},
+    Network,
+};
+
+/// BIP32 purpose of Cashu secrets.
+const PURPOSE: u32 = 129372;
+/// Tag of the key payouts to the wallet are locked to, see `Derivation::payout_key`.
+const PAYOUT_KEY_TAG: &[u8] = b"potato/wallet/payout_key";
+
+pub struct Derivation {
+    seed: [u8; 64],
+    master: ExtendedPrivKey,
+    secp: Secp256k1<All>,
+}
+
+impl std::fmt::Debug for Derivation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Derivation").finish_non_exhaustive()
+    }
+}
+
+impl Derivation {
+    pub fn new(mnemonic: &Mnemonic) -> MintResult<Self> {
+        let seed = mnemonic.to_seed("");
+        let master = ExtendedPrivKey::new_master(Network::Bitcoin, &seed)
+            .map_err(|e| MintError::InvalidSeed(e.to_string()))?;
+        Ok(Self {
+            seed,
+            master,
+            secp: Secp256k1::new(),
+        })
+    }
+
+    /// Secret and blinding factor of output `counter` of keyset `keyset_id`.
+    pub fn secret(&self, keyset_id: &str, counter: u32) -> MintResult<(String, SecretKey)> {
+        let secret = self.derive(keyset_id, counter, 0)?;
+        let r = self.derive(keyset_id, counter, 1)?;
+        Ok((hex::encode(secret.secret_bytes()), r))
+    }
+
+    /// Key payouts of the wallet are locked to (NUT-11): `sha256(PAYOUT_KEY_TAG || bip39_seed)`.
+    pub fn payout_key(&self) -> MintResult<SecretKey> {
+        let key: [u8; 32] = Sha256::new()
+            .chain_update(PAYOUT_KEY_TAG)
+            .chain_update(self.seed)
+            .finalize()
+            .into();
+        Ok(SecretKey::from_slice(&key)?)
+    }
+
+    fn derive(&self, keyset_id: &str, counter: u32, leaf: u32) -> MintResult<SecretKey> {
+        let path = [
+            hardened(PURPOSE)?,
+            hardened(0)?,
+            hardened(keyset_index(keyset_id)?)?,
+            hardened(counter)?,
+            ChildNumber::from_normal_idx(leaf).map_err(derivation_failed)?,
+        ];
+        let key = self
+            .master
+            .derive_priv(&self.secp, &path)
+            .map_err(derivation_failed)?;
+        Ok(SecretKey::from_slice(&key.private_key.secret_bytes())?)
+    }
+}
+
+/// Keyset id as a BIP32 index: the big endian integer of its bytes, modulo `2^31 - 1`.
+fn keyset_index(keyset_id: &str) -> MintResult<u32> {
+    let index = hex::decode(keyset_id)?.iter().fold(0u64, |index, byte| {
+        (index * 256 + *byte as u64) % (u32::MAX as u64 >> 1)
+    });
+    Ok(index as u32)
+}
+
+fn hardened(index: u32) -> MintResult<ChildNumber> {
+    ChildNumber::from_hardened_idx(index).map_err(derivation_failed)
+}
+
+fn derivation_failed(e: impl std::fmt::Display) -> MintError {
+    MintError::InvalidSeed(e.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn derives_the_nut13_test_vectors() {
+        let mnemonic = Mnemonic::parse(
+            "half depart obvious quality work element tank gorilla view sugar picture humble",
+        )
+        .unwrap();
+        let derivation = Derivation::new(&mnemonic).unwrap();
+        assert_eq!(keyset_index("009a1f293253e41e").unwrap(), 864559728);
+        let (secret, r) = derivation.secret("009a1f293253e41e", 0).unwrap();
+        assert_eq!(
+            secret,
+            "485875df74771877439ac06339e284c3acfcd9be7abf3bc20b516faeadfe77ae"
+        );
+        assert_eq!(
+            hex::encode(r.secret_bytes()),
+            "ad00d431add9c673e843d4c2bf9a778a5f402b985b8da2d5550bf39cda41d679"
+        );
+        assert_ne!(derivation.secret("009a1f293253e41e", 1).unwrap().0, secret);
+    }
+}
@@ -0,0 +1,192 @@
+//! Blind Diffie-Hellman key exchange (BDHKE) as specified by Cashu NUT-00.
+//!
+//! The wallet blinds a secret `x` as `B_ = Y + rG` with `Y = hash_to_curve(x)`, the mint signs it
+//! with the key `k` for the requested amount as `C_ = kB_` and the wallet unblinds the signature
+//! with `C = C_ - rK`. Later the mint can check that `C == kY` without ever having seen `x` or `C`
+//! before, so it can't link the redeemed token to the miner it was issued to.
+use crate::error::MintError;
+use secp256k1::{PublicKey, Scalar, Secp256k1, SecretKey};
+use sha2::{Digest, Sha256};
+
+const DOMAIN_SEPARATOR: &[u8] = b"Secp256k1_HashToCurve_Cashu_";
+
+/// Maps a message to a point on the curve by hashing it with an increasing counter until the hash
+/// is a valid x coordinate.
+pub fn hash_to_curve(message: &[u8]) -> Result<PublicKey, MintError> {
+    let msg_hash = Sha256::new()
+        .chain_update(DOMAIN_SEPARATOR)
+        .chain_update(message)
+        .finalize();
+    for counter in 0..u16::MAX as u32 {
+        let hash = Sha256::new()
+            .chain_update(msg_hash)
+            .chain_update(counter.to_le_bytes())
+            .finalize();
+        let mut point = [0x02; 33];
+        point[1..].copy_from_slice(&hash);
+        if let Ok(point) = PublicKey::from_slice(&point) {
+            return Ok(point);
+        }
+    }
+    Err(MintError::HashToCurve)
+}
+
+/// Wallet side: blinds `secret` with `r`, or a random blinding factor if none is given.
+pub fn blind_message(
+    secret: &[u8],
+    r: Option<SecretKey>,
+) -> Result<(PublicKey, SecretKey), MintError> {
+    let secp = Secp256k1::new();
+    let y = hash_to_curve(secret)?;
+    let r = r.unwrap_or_else(|| SecretKey::new(&mut rand::thread_rng()));
+    Ok((y.combine(&r.public_key(&secp))?, r))
+}
+
+/// Mint side: signs a blinded message with the key for its amount.
+pub fn sign_message(k: &SecretKey, blinded_message: &PublicKey) -> Result<PublicKey, MintError> {
+    let secp = Secp256k1::new();
+    Ok(blinded_message.mul_tweak(&secp, &Scalar::from(*k))?)
+}
+
+/// Wallet side: removes the blinding factor from the mint's signature.
+pub fn unblind_signature(
+    blinded_signature: &PublicKey,
+    r: &SecretKey,
+    mint_pubkey: &PublicKey,
+) -> Result<PublicKey, MintError> {
+    let secp = Secp256k1::new();
+    let r_k = mint_pubkey.mul_tweak(&secp, &Scalar::from(*r))?;
+    Ok(blinded_signature.combine(&r_k.negate(&secp))?)
+}
+
+/// Mint side: checks an unblinded signature against the secret it is supposed to sign.
+pub fn verify_message(
+    k: &SecretKey,
+    signature: &PublicKey,
+    secret: &[u8],
+) -> Result<bool, MintError> {
+    let y = hash_to_curve(secret)?;
+    Ok(sign_message(k, &y)? == *signature)
+}
+
+/// Mint side: NUT-12 DLEQ proof `(e, s)` that `blinded_signature` was made with the same key `k`
+/// whose public key the mint advertises, without revealing `k`.
+pub fn sign_dleq(
+    k: &SecretKey,
+    blinded_message: &PublicKey,
+    blinded_signature: &PublicKey,
+) -> Result<(SecretKey, SecretKey), MintError> {
+    let secp = Secp256k1::new();
+    let nonce = SecretKey::new(&mut rand::thread_rng());
+    let r1 = nonce.public_key(&secp);
+    let r2 = blinded_message.mul_tweak(&secp, &Scalar::from(nonce))?;
+    let e = SecretKey::from_slice(&hash_e(&[r1, r2, k.public_key(&secp), *blinded_signature]))?;
+    let s = nonce.add_tweak(&Scalar::from(k.mul_tweak(&Scalar::from(e))?))?;
+    Ok((e, s))
+}
+
+/// Wallet side: checks a DLEQ proof of `blinded_signature` against the advertised `mint_pubkey`.
+pub fn verify_dleq(
+    e: &SecretKey,
+    s: &SecretKey,
+    mint_pubkey: &PublicKey,
+    blinded_message: &PublicKey,
+    blinded_signature: &PublicKey,
+) -> Result<bool, MintError> {
+    let secp = Secp256k1::new();
+    let e_scalar = Scalar::from(*e);
+    // R1 = sG - eA, R2 = sB_ - eC_
+    let r1 = s
+        .public_key(&secp)
+        .combine(&mint_pubkey.mul_tweak(&secp, &e_scalar)?.negate(&secp))?;
+    let r2 = blinded_message
+        .mul_tweak(&secp, &Scalar::from(*s))?
+        .combine(&blinded_signature.mul_tweak(&secp, &e_scalar)?.negate(&secp))?;
+    Ok(hash_e(&[r1, r2, *mint_pubkey, *blinded_signature]) == e.secret_bytes())
+}
+
+/// The challenge of a DLEQ proof: sha256 of the hex encoded uncompressed points.
+fn hash_e(points: &[PublicKey]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    for point in points {
+        hasher.update(hex::encode(point.serialize_uncompressed()));
+    }
+    hasher.finalize().into()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::str::FromStr;
+
+    // test vector from NUT-00
+    #[test]
+    fn hash_to_curve_matches_spec() {
+        let point = hash_to_curve(&[0; 32]).unwrap();
+        assert_eq!(
+            point.to_string(),
+            "024cce997d3b518f739663b757deaec95bcd9473c30a14ac2fd04023a739d1a725"
+        );
+    }
+
+    #[test]
+    fn blind_sign_unblind_verify() {
+        let secp = Secp256k1::new();
+        let k = SecretKey::new(&mut rand::thread_rng());
+        let secret = b"potato";
+        let (blinded, r) = blind_message(secret, None).unwrap();
+        let blinded_signature = sign_message(&k, &blinded).unwrap();
+        let signature = unblind_signature(&blinded_signature, &r, &k.public_key(&secp)).unwrap();
+        assert!(verify_message(&k, &signature, secret).unwrap());
+        assert!(!verify_message(&k, &signature, b"tomato").unwrap());
+    }
+
+    // test vectors from NUT-12
+    #[test]
+    fn hash_e_matches_spec() {
+        let one = PublicKey::from_str(
+            "020000000000000000000000000000000000000000000000000000000000000001",
+        )
+        .unwrap();
+        let c = PublicKey::from_str(
+            "02a9acc1e48c25eeeb9289b5031cc57da9fe72f3fe2861d264bdc074209b107ba2",
+        )
+        .unwrap();
+        assert_eq!(
+            hex::encode(hash_e(&[one, one, one, c])),
+            "a4dc034b74338c28c6bc3ea49731f2a24440fc7c4affc08b31a93fc9fbe6401e"
+        );
+    }
+
+    #[test]
+    fn verifies_spec_dleq() {
+        let secp = Secp256k1::new();
+        let a = SecretKey::from_slice(&[[0; 31].as_slice(), &[1]].concat()).unwrap();
+        let b = PublicKey::from_str(
+            "02a9acc1e48c25eeeb9289b5031cc57da9fe72f3fe2861d264bdc074209b107ba2",
+        )
+        .unwrap();
+        let e =
+            SecretKey::from_str("9818e061ee51d5c8edc3342369a554998ff7b4381c8652d724cdf46429be73d9")
+                .unwrap();
+        let s =
+            SecretKey::from_str("9818e061ee51d5c8edc3342369a554998ff7b4381c8652d724cdf46429be73da")
+                .unwrap();
+        let c = sign_message(&a, &b).unwrap();
+        assert!(verify_dleq(&e, &s, &a.public_key(&secp), &b, &c).unwrap());
+    }
+
+    #[test]
+    fn dleq_proves_the_signing_key() {
+        let secp = Secp256k1::new();
+        let k = SecretKey::new(&mut rand::thread_rng());
+        let (blinded, _) = blind_message(b"potato", None).unwrap();
+        let blinded_signature = sign_message(&k, &blinded).unwrap();
+        let (e, s) = sign_dleq(&k, &blinded, &blinded_signature).unwrap();
+        let pubkey = k.public_key(&secp);
+        assert!(verify_dleq(&e, &s, &pubkey, &blinded, &blinded_signature).unwrap());
+
+        let other = SecretKey::new(&mut rand::thread_rng()).public_key(&secp);
+        assert!(!verify_dleq(&e, &s, &other, &blinded, &blinded_signature).unwrap());
+    }
+}
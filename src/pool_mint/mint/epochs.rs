@@ -0,0 +1,86 @@
+//! Ehash keysets per Bitcoin difficulty epoch. Ehash counts difficulty 1 shares, and what a
+//! share is worth depends on the network difficulty it was mined at. With epochs enabled, the
+//! ehash keyset rolls over on every difficulty adjustment, so every ehash token tells the epoch,
+//! and the difficulty, its hash was done at.
+//!
+//! The chain is followed by the `MaturityWatcher`. Tokens of older epochs stay redeemable like
+//! those of any deprecated keyset, under the rule configured in `older`.
+use serde::{Deserialize, Serialize};
+
+/// Blocks between two difficulty adjustments.
+pub const EPOCH_BLOCKS: u64 = 2016;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct EpochConfig {
+    /// Rolls the ehash keyset on every difficulty epoch.
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub older: OlderEpochs,
+}
+
+/// What tokens of an epoch before the current one can be redeemed for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OlderEpochs {
+    /// Swapped one for one into ehash of the current epoch, or converted into sat.
+    #[default]
+    Swap,
+    /// Only converted into sat, at the rate of the rounds they were mined in, so ehash of
+    /// different difficulties never mixes.
+    Convert,
+}
+
+/// A difficulty epoch, as recorded with the ehash keyset of the epoch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Epoch {
+    pub number: u64,
+    /// Compact target (nBits) of the blocks of the epoch.
+    pub bits: u32,
+}
+
+impl Epoch {
+    /// Epoch of the block at `height`.
+    pub fn number_at(height: u64) -> u64 {
+        height / EPOCH_BLOCKS
+    }
+
+    /// Height of the first block of the epoch, which carries its target.
+    pub fn start_height(number: u64) -> u64 {
+        number * EPOCH_BLOCKS
+    }
+
+    /// Network difficulty of the epoch, relative to the difficulty 1 target.
+    pub fn difficulty(&self) -> f64 {
+        target(0x1d00ffff) / target(self.bits)
+    }
+}
+
+fn target(bits: u32) -> f64 {
+    let exponent = (bits >> 24) as i32;
+    let mantissa = (bits & 0x007f_ffff) as f64;
+    mantissa * 256f64.powi(exponent - 3)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn epochs_of_mainnet() {
+        assert_eq!(Epoch::number_at(2015), 0);
+        assert_eq!(Epoch::number_at(2016), 1);
+        assert_eq!(Epoch::start_height(420), 846_720);
+        let genesis = Epoch {
+            number: 0,
+            bits: 0x1d00ffff,
+        };
+        assert_eq!(genesis.difficulty(), 1.0);
+        // the example of the Bitcoin wiki
+        let epoch = Epoch {
+            number: 1,
+            bits: 0x1b0404cb,
+        };
+        assert!((epoch.difficulty() - 16307.420938523983).abs() < 1e-6);
+    }
+}
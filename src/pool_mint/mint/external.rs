@@ -0,0 +1,96 @@
+//! External mint mode: operators already running a Cashu mint (e.g. with CDK) have potato issue
+//! the tokens of its miners there rather than run a second mint. Potato keeps accounting shares,
+//! rounds and balances, but pays balances out as tokens of the external mint, which it requests
+//! on behalf of each miner.
+//!
+//! Issuance uses NUT-04 with the `method` of the configuration (`ehash` by default): potato asks
+//! for a mint quote naming the account and amount, authenticated with the API key as a bearer
+//! token, and mints it right away. The external mint must treat quotes authenticated that way as
+//! paid. The local Cashu API is not served in this mode, so miners only ever hold tokens of the
+//! external mint.
+use super::{
+    api::EHASH_METHOD,
+    backend::PayoutIssuer,
+    client::{self, MintClient},
+    nuts::{MintQuoteRequest, MintQuoteResponse, MintRequest, SignaturesResponse, Token},
+    p2pk::Conditions,
+};
+use crate::error::MintResult;
+use async_trait::async_trait;
+use secp256k1::PublicKey;
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ExternalMintConfig {
+    /// URL of the external mint, also written into the tokens paid out.
+    pub url: String,
+    /// Authorizes potato's mint quotes at the external mint.
+    pub api_key: String,
+    /// NUT-04 payment method of the quotes.
+    #[serde(default = "ExternalMintConfig::default_method")]
+    pub method: String,
+}
+
+impl ExternalMintConfig {
+    fn default_method() -> String {
+        EHASH_METHOD.to_string()
+    }
+}
+
+#[derive(Debug)]
+pub struct ExternalMint {
+    client: MintClient,
+    method: String,
+}
+
+impl ExternalMint {
+    pub fn new(config: &ExternalMintConfig) -> MintResult<Self> {
+        Ok(Self {
+            client: MintClient::new(&config.url, Some(config.api_key.clone()))?,
+            method: config.method.clone(),
+        })
+    }
+
+    pub fn url(&self) -> &str {
+        self.client.url()
+    }
+}
+
+#[async_trait]
+impl PayoutIssuer for ExternalMint {
+    async fn issue(
+        &self,
+        account: &str,
+        unit: &str,
+        amount: u64,
+        pubkey: Option<&PublicKey>,
+    ) -> MintResult<Token> {
+        let keyset = self.client.keys(unit).await?;
+        let lock = pubkey.map(|pubkey| Conditions::p2pk(*pubkey));
+        let (outputs, secrets) = client::blank_outputs(&keyset, amount, lock.as_ref())?;
+        let quote: MintQuoteResponse = self
+            .client
+            .post(
+                &format!("/v1/mint/quote/{}", self.method),
+                &MintQuoteRequest {
+                    amount,
+                    unit: unit.to_string(),
+                    account: account.to_string(),
+                    credential: None,
+                },
+            )
+            .await?;
+        let signatures: SignaturesResponse = self
+            .client
+            .post(
+                &format!("/v1/mint/{}", self.method),
+                &MintRequest {
+                    quote: quote.quote,
+                    outputs: outputs.clone(),
+                },
+            )
+            .await?;
+        let proofs = client::unblind(&keyset, &outputs, &signatures.signatures, secrets)?;
+        Ok(Token::new(self.url(), unit, proofs))
+    }
+}
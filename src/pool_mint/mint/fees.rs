@@ -0,0 +1,103 @@
+//! Fees the mint charges for redeeming tokens. Every input carries the NUT-02 fee of its keyset
+//! (`input_fee_ppk`, published with the keysets), and each operation can charge more per input
+//! and per output on top. Fees are in parts per thousand of a unit, and the total of a
+//! transaction is rounded up to a whole unit.
+//!
+//! What wallets pay in fees is kept by the mint and accounted for per unit and operation in the
+//! fee ledger, see `MintDb::fees`.
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Operations that redeem tokens and may charge fees.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Operation {
+    Swap,
+    Melt,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub struct OperationFees {
+    /// Charged per input, on top of the input fee of its keyset.
+    #[serde(default)]
+    pub input_fee_ppk: u64,
+    /// Charged per output signed. The outputs of a melt are its blank change outputs.
+    #[serde(default)]
+    pub output_fee_ppk: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct FeeConfig {
+    /// Input fee of the keysets not listed in `keysets`.
+    #[serde(default)]
+    pub input_fee_ppk: u64,
+    /// Input fee per keyset id.
+    #[serde(default)]
+    pub keysets: HashMap<String, u64>,
+    #[serde(default)]
+    pub swap: OperationFees,
+    #[serde(default)]
+    pub melt: OperationFees,
+}
+
+impl FeeConfig {
+    /// NUT-02 input fee of keyset `id`.
+    pub fn input_fee_ppk(&self, id: &str) -> u64 {
+        self.keysets.get(id).copied().unwrap_or(self.input_fee_ppk)
+    }
+
+    pub fn operation(&self, operation: Operation) -> OperationFees {
+        match operation {
+            Operation::Swap => self.swap,
+            Operation::Melt => self.melt,
+        }
+    }
+
+    /// Fee of `operation` redeeming inputs of the keysets `input_ids` for `outputs` outputs.
+    pub fn fee<'a>(
+        &self,
+        operation: Operation,
+        input_ids: impl IntoIterator<Item = &'a str>,
+        outputs: usize,
+    ) -> u64 {
+        let fees = self.operation(operation);
+        let inputs_ppk: u64 = input_ids
+            .into_iter()
+            .map(|id| self.input_fee_ppk(id).saturating_add(fees.input_fee_ppk))
+            .fold(0, u64::saturating_add);
+        let outputs_ppk = fees.output_fee_ppk.saturating_mul(outputs as u64);
+        inputs_ppk.saturating_add(outputs_ppk).div_ceil(1000)
+    }
+}
+
+/// Fees collected in `unit` by `operation`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FeeTotal {
+    pub unit: String,
+    pub operation: Operation,
+    pub amount: u64,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rounds_up_the_total_fee() {
+        let config = FeeConfig {
+            input_fee_ppk: 100,
+            keysets: HashMap::from([("free".to_string(), 0)]),
+            swap: OperationFees {
+                input_fee_ppk: 0,
+                output_fee_ppk: 50,
+            },
+            melt: OperationFees::default(),
+        };
+        assert_eq!(config.fee(Operation::Swap, [], 0), 0);
+        // 3 * 100 + 2 * 50 ppk
+        assert_eq!(config.fee(Operation::Swap, ["a", "a", "a"], 2), 1);
+        assert_eq!(config.fee(Operation::Swap, ["a"; 10], 1), 2);
+        assert_eq!(config.fee(Operation::Melt, ["a"; 10], 4), 1);
+        assert_eq!(config.fee(Operation::Melt, ["free"; 10], 4), 0);
+    }
+}
@@ -0,0 +1,198 @@
+//! NUT-06 mint info, telling wallets and explorers who runs this pool-mint and what it supports.
+use super::{
+    api::{BOLT11_METHOD, EHASH_METHOD, ONCHAIN_METHOD},
+    onchain::OnchainConfig,
+    Mint, SAT_UNIT,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+/// Operator provided metadata, every field optional.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct MintInfoConfig {
+    /// Defaults to the pool signature.
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub description_long: Option<String>,
+    #[serde(default)]
+    pub contact: Vec<ContactInfo>,
+    #[serde(default)]
+    pub motd: Option<String>,
+    #[serde(default)]
+    pub icon_url: Option<String>,
+    /// How the pool is paid, e.g. "2% of every block reward". Purely informative.
+    #[serde(default)]
+    pub fee_policy: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct ContactInfo {
+    /// e.g. "email" or "nostr"
+    pub method: String,
+    pub info: String,
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct MintInfoResponse {
+    pub name: String,
+    pub version: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description_long: Option<String>,
+    pub contact: Vec<ContactInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub motd: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icon_url: Option<String>,
+    /// Supported NUTs and their settings, keyed by NUT number.
+    pub nuts: Map<String, Value>,
+    /// Units tokens are issued in.
+    pub units: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fee_policy: Option<String>,
+}
+
+impl MintInfoResponse {
+    /// Info of `mint` as configured in `info`, named after the pool unless configured otherwise.
+    /// `melting` tells whether a Lightning backend is configured, `onchain` how on-chain melts
+    /// are paid if they are.
+    pub fn new(
+        info: &MintInfoConfig,
+        pool_signature: &str,
+        mint: &Mint,
+        melting: bool,
+        onchain: Option<&OnchainConfig>,
+    ) -> Self {
+        let units = mint.units().to_vec();
+        let mut nuts = Map::new();
+        let mint_methods: Vec<_> = units
+            .iter()
+            .map(|unit| json!({ "method": EHASH_METHOD, "unit": unit }))
+            .collect();
+        nuts.insert(
+            "4".into(),
+            json!({ "methods": mint_methods, "disabled": false }),
+        );
+        let mut melt_methods = vec![];
+        if mint.has_unit(SAT_UNIT) {
+            if melting {
+                melt_methods.push(json!({ "method": BOLT11_METHOD, "unit": SAT_UNIT }));
+            }
+            if let Some(onchain) = onchain {
+                melt_methods.push(json!({
+                    "method": ONCHAIN_METHOD,
+                    "unit": SAT_UNIT,
+                    "min_amount": onchain.min_amount,
+                }));
+            }
+        }
+        nuts.insert(
+            "5".into(),
+            json!({ "methods": melt_methods, "disabled": melt_methods.is_empty() }),
+        );
+        // proof states under /v1/checkstate
+        nuts.insert("7".into(), json!({ "supported": true }));
+        // signatures handed out again under /v1/restore
+        nuts.insert("9".into(), json!({ "supported": true }));
+        // P2PK spending conditions, see `p2pk`
+        nuts.insert("10".into(), json!({ "supported": true }));
+        nuts.insert("11".into(), json!({ "supported": true }));
+        // every signature carries a DLEQ proof
+        nuts.insert("12".into(), json!({ "supported": true }));
+        // HTLC spending conditions, see `p2pk`
+        nuts.insert("14".into(), json!({ "supported": true }));
+        // WebSocket subscriptions, see `subscriptions`
+        let mut subscriptions: Vec<_> = units
+            .iter()
+            .map(|unit| {
+                json!({
+                    "method": EHASH_METHOD,
+                    "unit": unit,
+                    "commands": ["ehash_mint_quote", "proof_state"],
+                })
+            })
+            .collect();
+        if melting && mint.has_unit(SAT_UNIT) {
+            subscriptions.push(json!({
+                "method": BOLT11_METHOD,
+                "unit": SAT_UNIT,
+                "commands": ["bolt11_melt_quote", "proof_state"],
+            }));
+        }
+        nuts.insert("17".into(), json!({ "supported": subscriptions }));
+        Self {
+            name: info
+                .name
+                .clone()
+                .unwrap_or_else(|| pool_signature.to_string()),
+            version: format!("potato/{}", env!("CARGO_PKG_VERSION")),
+            description: info.description.clone(),
+            description_long: info.description_long.clone(),
+            contact: info.contact.clone(),
+            motd: info.motd.clone(),
+            icon_url: info.icon_url.clone(),
+            nuts,
+            units,
+            fee_policy: info.fee_policy.clone(),
+        }
+    }
+
+    /// The info of a mint issuing through another backend, which doesn't serve restores or
+    /// subscriptions, see `api`.
+    pub fn issued_elsewhere(mut self) -> Self {
+        self.nuts.remove("9");
+        self.nuts.remove("17");
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pool_mint::mint::MintConfig;
+
+    #[test]
+    fn describes_pool_mint() {
+        let mut config = MintConfig::default();
+        let mint = Mint::from_master_secret(&[1; 32], &config).unwrap();
+        let info = MintInfoResponse::new(&config.info, "potato", &mint, true, None);
+        assert_eq!(info.name, "potato");
+        assert_eq!(info.units, vec!["ehash".to_string(), "sat".to_string()]);
+        assert_eq!(info.nuts["4"]["methods"][1]["unit"], SAT_UNIT);
+        assert_eq!(info.nuts["5"]["disabled"], false);
+        assert_eq!(info.nuts["7"]["supported"], true);
+        assert_eq!(info.nuts["9"]["supported"], true);
+        assert_eq!(info.nuts["11"]["supported"], true);
+        assert_eq!(info.nuts["12"]["supported"], true);
+        assert_eq!(info.nuts["14"]["supported"], true);
+        assert_eq!(
+            info.nuts["17"]["supported"][2]["commands"][0],
+            "bolt11_melt_quote"
+        );
+
+        // only sat tokens melt
+        config.units = vec!["ehash".to_string()];
+        let mint = Mint::from_master_secret(&[1; 32], &config).unwrap();
+        let info = MintInfoResponse::new(&config.info, "potato", &mint, true, None);
+        assert_eq!(info.nuts["4"]["methods"][0]["method"], EHASH_METHOD);
+        assert_eq!(info.nuts["5"]["disabled"], true);
+        assert_eq!(info.nuts["17"]["supported"].as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn describes_onchain_melts() {
+        let config = MintConfig::default();
+        let mint = Mint::from_master_secret(&[1; 32], &config).unwrap();
+        let onchain = OnchainConfig::default();
+        let info = MintInfoResponse::new(&config.info, "potato", &mint, false, Some(&onchain));
+        assert_eq!(info.nuts["5"]["disabled"], false);
+        assert_eq!(info.nuts["5"]["methods"][0]["method"], ONCHAIN_METHOD);
+        assert_eq!(info.nuts["5"]["methods"][0]["min_amount"], 10_000);
+        // bolt11 melt quote subscriptions need a Lightning backend
+        assert_eq!(info.nuts["17"]["supported"].as_array().unwrap().len(), 2);
+    }
+}
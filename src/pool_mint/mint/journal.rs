@@ -0,0 +1,173 @@
+//! Credits the database couldn't take. When crediting a share or closing a round fails on the
+//! database (locked, out of disk, gone), the credit is appended to the journal at `journal_path`
+//! instead, and the share is accepted as usual. Journaled credits are replayed in order before
+//! any new one and every few seconds, so a transient outage delays credits without losing them.
+//! Crediting never waits on Lightning, only melts do, and those stay pending until it answers
+//! (see `melt`).
+//!
+//! Every journaled credit has an id the database records in the transaction replaying it, so a
+//! crash between a replay and the journal being rewritten never credits it twice.
+use crate::error::{MintError, MintResult};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::Path,
+};
+use tracing::warn;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Credit {
+    /// An accepted share, see `Mint::credit_share`.
+    Share { account: String, weight: u64 },
+    /// A block found by the pool, see `Mint::found_block`.
+    Block {
+        coinbase_txid: String,
+        reward: u64,
+        /// Absent from credits journaled before finders were recorded.
+        #[serde(default)]
+        finder: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Journaled {
+    pub id: String,
+    #[serde(flatten)]
+    pub credit: Credit,
+}
+
+/// The journaled credits, oldest first, kept in memory only without a path.
+#[derive(Debug, Default)]
+pub struct CreditJournal {
+    path: Option<String>,
+    credits: VecDeque<Journaled>,
+}
+
+impl CreditJournal {
+    /// Loads the credits journaled at `path`, dropping a line cut short by a crash.
+    pub fn open(path: Option<String>) -> MintResult<Self> {
+        let mut credits = VecDeque::new();
+        let mut unreadable = false;
+        if let Some(path) = path.as_deref().filter(|path| Path::new(path).exists()) {
+            for line in fs::read_to_string(path)?.lines() {
+                match serde_json::from_str(line) {
+                    Ok(credit) => credits.push_back(credit),
+                    Err(e) => {
+                        warn!("Mint: dropping unreadable journaled credit: {}", e);
+                        unreadable = true;
+                    }
+                }
+            }
+        }
+        let journal = Self { path, credits };
+        if unreadable {
+            // or the next credit would be appended to the broken line
+            journal.save()?;
+        }
+        Ok(journal)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.credits.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.credits.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Journaled> {
+        self.credits.iter()
+    }
+
+    /// Appends `credit`, synced to disk before returning.
+    pub fn push(&mut self, credit: Credit) -> MintResult<()> {
+        let journaled = Journaled {
+            id: super::quote::random_id(),
+            credit,
+        };
+        if let Some(path) = &self.path {
+            let mut line = serde_json::to_string(&journaled).map_err(storage)?;
+            line.push('\n');
+            let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+            file.write_all(line.as_bytes())?;
+            file.sync_data()?;
+        }
+        self.credits.push_back(journaled);
+        Ok(())
+    }
+
+    /// Drops the `count` oldest credits once replayed.
+    pub fn remove_replayed(&mut self, count: usize) -> MintResult<()> {
+        self.credits.drain(..count.min(self.credits.len()));
+        self.save()
+    }
+
+    fn save(&self) -> MintResult<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if self.credits.is_empty() {
+            if Path::new(path).exists() {
+                fs::remove_file(path)?;
+            }
+            return Ok(());
+        }
+        let mut lines = String::new();
+        for credit in &self.credits {
+            lines.push_str(&serde_json::to_string(credit).map_err(storage)?);
+            lines.push('\n');
+        }
+        // write then rename so a crash never leaves a truncated journal behind
+        let tmp = format!("{}.tmp", path);
+        fs::write(&tmp, lines)?;
+        fs::rename(tmp, path)?;
+        Ok(())
+    }
+}
+
+fn storage(e: serde_json::Error) -> MintError {
+    MintError::Storage(e.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn keeps_credits_across_restarts() {
+        let path =
+            std::env::temp_dir().join(format!("potato-journal-{}.jsonl", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let share = |weight| Credit::Share {
+            account: "alice".into(),
+            weight,
+        };
+        let mut journal = CreditJournal::open(Some(path.clone())).unwrap();
+        journal.push(share(1)).unwrap();
+        journal
+            .push(Credit::Block {
+                coinbase_txid: "coinbase".into(),
+                reward: 100,
+                finder: Some("alice".into()),
+            })
+            .unwrap();
+        journal.push(share(2)).unwrap();
+        // a line cut short by a crash
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"{\"id\":\"ab\",\"ty").unwrap();
+
+        let mut journal = CreditJournal::open(Some(path.clone())).unwrap();
+        assert_eq!(journal.len(), 3);
+        journal.remove_replayed(2).unwrap();
+        let journal = CreditJournal::open(Some(path.clone())).unwrap();
+        let credits: Vec<_> = journal.iter().map(|j| j.credit.clone()).collect();
+        assert_eq!(credits, vec![share(2)]);
+
+        let mut journal = journal;
+        journal.remove_replayed(1).unwrap();
+        assert!(!Path::new(&path).exists());
+    }
+}
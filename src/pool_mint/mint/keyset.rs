@@ -0,0 +1,150 @@
+use super::amounts;
+use crate::error::MintError;
+use secp256k1::{PublicKey, Secp256k1, SecretKey};
+use sha2::{Digest, Sha256};
+use std::{collections::BTreeMap, fs, io::Write, path::Path};
+use tracing::info;
+
+/// Version prefix of NUT-02 keyset ids.
+const KEYSET_ID_VERSION: &str = "00";
+
+/// A set of mint keys, one per amount, sharing an id and a unit.
+#[derive(Debug, Clone)]
+pub struct Keyset {
+    pub id: String,
+    pub unit: String,
+    keys: BTreeMap<u64, SecretKey>,
+}
+
+impl Keyset {
+    /// Derives the keys for `amounts` (see `amounts`) from the mint master secret. The key for
+    /// amount `a` of keyset `index` is `sha256(master || unit || index || a)`, so the same master
+    /// secret always gives back the same keyset.
+    pub fn derive(
+        master_secret: &[u8; 32],
+        unit: &str,
+        index: u32,
+        amounts: &[u64],
+    ) -> Result<Self, MintError> {
+        let mut keys = BTreeMap::new();
+        for amount in amounts {
+            let hash = Sha256::new()
+                .chain_update(master_secret)
+                .chain_update(unit.as_bytes())
+                .chain_update(index.to_be_bytes())
+                .chain_update(amount.to_be_bytes())
+                .finalize();
+            keys.insert(*amount, SecretKey::from_slice(&hash)?);
+        }
+        let secp = Secp256k1::new();
+        let public_keys = keys
+            .iter()
+            .map(|(amount, key)| (*amount, key.public_key(&secp)))
+            .collect();
+        Ok(Self {
+            id: Self::compute_id(&public_keys),
+            unit: unit.to_string(),
+            keys,
+        })
+    }
+
+    /// Keyset id as defined in NUT-02: the version byte followed by the first 7 bytes of the
+    /// sha256 of all public keys concatenated in ascending amount order.
+    pub fn compute_id(public_keys: &BTreeMap<u64, PublicKey>) -> String {
+        let mut hasher = Sha256::new();
+        for key in public_keys.values() {
+            hasher.update(key.serialize());
+        }
+        let hash = hasher.finalize();
+        format!("{}{}", KEYSET_ID_VERSION, hex::encode(&hash[..7]))
+    }
+
+    pub fn public_keys(&self) -> BTreeMap<u64, PublicKey> {
+        let secp = Secp256k1::new();
+        self.keys
+            .iter()
+            .map(|(amount, key)| (*amount, key.public_key(&secp)))
+            .collect()
+    }
+
+    pub fn secret_key(&self, amount: u64) -> Result<&SecretKey, MintError> {
+        self.keys
+            .get(&amount)
+            .ok_or(MintError::UnsupportedAmount(amount))
+    }
+
+    /// Amounts this keyset can sign, ascending.
+    pub fn amounts(&self) -> Vec<u64> {
+        self.keys.keys().copied().collect()
+    }
+
+    /// Splits `amount` into amounts this keyset signs, largest first.
+    pub fn split(&self, amount: u64) -> Result<Vec<u64>, MintError> {
+        amounts::split(amount, &self.amounts())
+    }
+}
+
+/// Loads the hex encoded mint master secret at `path`, generating and saving a new one on first
+/// start. Losing this file means losing the ability to honor every token issued so far.
+pub fn load_or_create_master_secret(path: &str) -> Result<[u8; 32], MintError> {
+    if Path::new(path).exists() {
+        return read_master_secret(path);
+    }
+    let secret: [u8; 32] = rand::random();
+    write_master_secret(path, &secret)?;
+    info!("Generated new mint master secret at {}", path);
+    Ok(secret)
+}
+
+pub fn read_master_secret(path: &str) -> Result<[u8; 32], MintError> {
+    parse_master_secret(&fs::read_to_string(path)?)
+        .ok_or_else(|| MintError::InvalidMasterSecret(path.to_string()))
+}
+
+/// The master secret hex encoded, as stored in its file.
+pub fn parse_master_secret(hex: &str) -> Option<[u8; 32]> {
+    hex::decode(hex.trim()).ok()?.try_into().ok()
+}
+
+/// Writes the secret readable by the owner only, never replacing an existing one.
+pub fn write_master_secret(path: &str, secret: &[u8; 32]) -> Result<(), MintError> {
+    let path = Path::new(path);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+        .open(path)?
+        .write_all(hex::encode(secret).as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pool_mint::mint::amounts::AmountStrategy;
+
+    #[test]
+    fn derivation_is_deterministic() {
+        let amounts = AmountStrategy::MaxOrder(8).amounts().unwrap();
+        let a = Keyset::derive(&[7; 32], "ehash", 0, &amounts).unwrap();
+        let b = Keyset::derive(&[7; 32], "ehash", 0, &amounts).unwrap();
+        let c = Keyset::derive(&[7; 32], "ehash", 1, &amounts).unwrap();
+        assert_eq!(a.id, b.id);
+        assert_ne!(a.id, c.id);
+        assert_eq!(a.id.len(), 16);
+        assert_eq!(a.public_keys().len(), 8);
+        assert_eq!(a.amounts().last(), Some(&128));
+        assert!(a.secret_key(3).is_err());
+
+        // the same keys, but another set of them
+        let d = Keyset::derive(&[7; 32], "ehash", 0, &[16, 128]).unwrap();
+        assert_ne!(a.id, d.id);
+        assert_eq!(d.public_keys()[&16], a.public_keys()[&16]);
+        assert_eq!(d.split(160).unwrap(), vec![128, 16, 16]);
+        assert!(d.split(161).is_err());
+    }
+}
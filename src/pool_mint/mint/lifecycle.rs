@@ -0,0 +1,391 @@
+//! Keyset lifecycle: a keyset is generated as `Pending`, becomes `Active` and signs new outputs,
+//! and is `Deprecated` once another keyset is activated in its place. Deprecated keysets never
+//! sign again but stay around so proofs issued under them can still be verified and redeemed.
+use super::{amounts::AmountStrategy, epochs::Epoch, keyset::Keyset};
+use crate::error::{MintError, MintResult};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fs, path::Path};
+use tracing::info;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeysetState {
+    Pending,
+    Active,
+    Deprecated,
+}
+
+/// What is persisted about a keyset. The keys themselves are derived again from the master
+/// secret, the index and the amounts on startup.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeysetInfo {
+    pub id: String,
+    pub index: u32,
+    pub unit: String,
+    pub state: KeysetState,
+    pub created_at: u64,
+    pub activated_at: Option<u64>,
+    pub deprecated_at: Option<u64>,
+    /// Difficulty epoch of an ehash keyset, when keysets roll over with epochs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub epoch: Option<Epoch>,
+    /// Amounts the keyset has keys for. Keysets generated before they were recorded have every
+    /// power of two up to `max_order`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub amounts: Option<Vec<u64>>,
+}
+
+impl KeysetInfo {
+    /// Derives the keyset again, failing if it doesn't have the id recorded.
+    pub fn derive(&self, master_secret: &[u8; 32], max_order: u8) -> MintResult<Keyset> {
+        let amounts = match &self.amounts {
+            Some(amounts) => amounts.clone(),
+            None => AmountStrategy::MaxOrder(max_order).amounts()?,
+        };
+        let keyset = Keyset::derive(master_secret, &self.unit, self.index, &amounts)?;
+        if keyset.id != self.id {
+            // the master secret or the amounts changed under us, refuse to guess
+            return Err(MintError::UnknownKeyset(self.id.clone()));
+        }
+        Ok(keyset)
+    }
+}
+
+/// All keysets the mint ever generated, saved to `path` on every change.
+#[derive(Debug)]
+pub struct Keysets {
+    master_secret: [u8; 32],
+    max_order: u8,
+    /// Amounts of new keysets per unit, every power of two up to `max_order` for others.
+    amounts: HashMap<String, AmountStrategy>,
+    path: Option<String>,
+    keysets: Vec<(KeysetInfo, Keyset)>,
+}
+
+impl Keysets {
+    /// Restores the keysets listed at `path`, generating and activating the first one of every
+    /// unit that has none, or a replacement for an active keyset of other amounts than
+    /// configured.
+    pub fn load_or_create(
+        master_secret: [u8; 32],
+        units: &[String],
+        max_order: u8,
+        amounts: &HashMap<String, AmountStrategy>,
+        path: Option<String>,
+    ) -> MintResult<Self> {
+        let infos: Vec<KeysetInfo> = match &path {
+            Some(p) if Path::new(p).exists() => serde_json::from_str(&fs::read_to_string(p)?)
+                .map_err(|e| MintError::Storage(e.to_string()))?,
+            _ => vec![],
+        };
+        let mut keysets = Self {
+            master_secret,
+            max_order,
+            amounts: amounts.clone(),
+            path,
+            keysets: Vec::with_capacity(infos.len()),
+        };
+        let recorded = infos.iter().all(|info| info.amounts.is_some());
+        for mut info in infos {
+            let keyset = info.derive(&master_secret, max_order)?;
+            info.amounts = Some(keyset.amounts());
+            keysets.keysets.push((info, keyset));
+        }
+        if !recorded {
+            keysets.save()?;
+        }
+        for unit in units {
+            let amounts = keysets.amounts(unit)?;
+            match keysets.active(unit) {
+                Some(active) if active.amounts() == amounts => continue,
+                Some(active) => info!(
+                    "Mint: amounts of {} changed, replacing keyset {}",
+                    unit, active.id
+                ),
+                None => {}
+            }
+            keysets.rotate(unit)?;
+        }
+        Ok(keysets)
+    }
+
+    /// Amounts of the keysets generated for `unit`, ascending.
+    pub fn amounts(&self, unit: &str) -> MintResult<Vec<u64>> {
+        self.amounts
+            .get(unit)
+            .cloned()
+            .unwrap_or(AmountStrategy::MaxOrder(self.max_order))
+            .amounts()
+    }
+
+    /// Derives the next keyset for `unit` and adds it as `Pending`, in the epoch of the active
+    /// keyset.
+    pub fn generate(&mut self, unit: &str) -> MintResult<String> {
+        let index = self
+            .keysets
+            .iter()
+            .map(|(info, _)| info.index + 1)
+            .max()
+            .unwrap_or_default();
+        let keyset = Keyset::derive(&self.master_secret, unit, index, &self.amounts(unit)?)?;
+        let info = KeysetInfo {
+            id: keyset.id.clone(),
+            index,
+            unit: unit.to_string(),
+            state: KeysetState::Pending,
+            created_at: now_secs(),
+            activated_at: None,
+            deprecated_at: None,
+            epoch: self.active_info(unit).and_then(|info| info.epoch),
+            amounts: Some(keyset.amounts()),
+        };
+        info!(
+            "Mint: generated keyset {} ({}, index {})",
+            info.id, unit, index
+        );
+        let id = info.id.clone();
+        self.keysets.push((info, keyset));
+        self.save()?;
+        Ok(id)
+    }
+
+    /// Makes `id` the keyset signing new outputs of its unit, deprecating the one it replaces.
+    pub fn activate(&mut self, id: &str) -> MintResult<()> {
+        let now = now_secs();
+        let unit = match self.info(id) {
+            Some(info) if info.state == KeysetState::Pending => info.unit.clone(),
+            Some(info) if info.state == KeysetState::Active => return Ok(()),
+            Some(_) => return Err(MintError::InactiveKeyset(id.to_string())),
+            None => return Err(MintError::UnknownKeyset(id.to_string())),
+        };
+        for (info, _) in self.keysets.iter_mut() {
+            if info.id == id {
+                info.state = KeysetState::Active;
+                info.activated_at = Some(now);
+            } else if info.unit == unit && info.state == KeysetState::Active {
+                info.state = KeysetState::Deprecated;
+                info.deprecated_at = Some(now);
+                info!("Mint: deprecated keyset {}", info.id);
+            }
+        }
+        info!("Mint: activated keyset {}", id);
+        self.save()
+    }
+
+    /// Retires `id` without a replacement. The active keyset can only be replaced, not
+    /// deprecated, or the mint would have nothing left to sign with.
+    pub fn deprecate(&mut self, id: &str) -> MintResult<()> {
+        let info = self
+            .keysets
+            .iter_mut()
+            .map(|(info, _)| info)
+            .find(|info| info.id == id)
+            .ok_or_else(|| MintError::UnknownKeyset(id.to_string()))?;
+        match info.state {
+            KeysetState::Active => return Err(MintError::InactiveKeyset(id.to_string())),
+            KeysetState::Deprecated => return Ok(()),
+            KeysetState::Pending => {
+                info.state = KeysetState::Deprecated;
+                info.deprecated_at = Some(now_secs());
+            }
+        }
+        self.save()
+    }
+
+    /// Generates a new keyset for `unit` and activates it right away.
+    pub fn rotate(&mut self, unit: &str) -> MintResult<String> {
+        let id = self.generate(unit)?;
+        self.activate(&id)?;
+        Ok(id)
+    }
+
+    /// Rotates `unit` if its active keyset has been active for at least `interval_secs`.
+    pub fn rotate_if_due(&mut self, unit: &str, interval_secs: u64) -> MintResult<Option<String>> {
+        let activated_at = self
+            .active_info(unit)
+            .and_then(|info| info.activated_at)
+            .unwrap_or_default();
+        if now_secs().saturating_sub(activated_at) < interval_secs {
+            return Ok(None);
+        }
+        self.rotate(unit).map(Some)
+    }
+
+    /// Rotates `unit` into a keyset of `epoch`, unless the active keyset already is of it.
+    pub fn rotate_to_epoch(&mut self, unit: &str, epoch: Epoch) -> MintResult<Option<String>> {
+        let current = self.active_info(unit).and_then(|info| info.epoch);
+        if current.map(|current| current.number) == Some(epoch.number) {
+            return Ok(None);
+        }
+        let id = self.generate(unit)?;
+        if let Some((info, _)) = self.keysets.iter_mut().find(|(info, _)| info.id == id) {
+            info.epoch = Some(epoch);
+        }
+        self.activate(&id)?;
+        info!(
+            "Mint: keyset {} starts difficulty epoch {} ({})",
+            id, epoch.number, unit
+        );
+        Ok(Some(id))
+    }
+
+    pub fn active(&self, unit: &str) -> Option<&Keyset> {
+        self.keysets
+            .iter()
+            .find(|(info, _)| info.unit == unit && info.state == KeysetState::Active)
+            .map(|(_, keyset)| keyset)
+    }
+
+    pub fn active_info(&self, unit: &str) -> Option<&KeysetInfo> {
+        self.keysets
+            .iter()
+            .map(|(info, _)| info)
+            .find(|info| info.unit == unit && info.state == KeysetState::Active)
+    }
+
+    /// Keyset usable to verify proofs: active or deprecated, never pending.
+    pub fn for_verification(&self, id: &str) -> MintResult<&Keyset> {
+        match self.keysets.iter().find(|(info, _)| info.id == id) {
+            Some((info, _)) if info.state == KeysetState::Pending => {
+                Err(MintError::InactiveKeyset(id.to_string()))
+            }
+            Some((_, keyset)) => Ok(keyset),
+            None => Err(MintError::UnknownKeyset(id.to_string())),
+        }
+    }
+
+    pub fn info(&self, id: &str) -> Option<&KeysetInfo> {
+        self.keysets
+            .iter()
+            .map(|(info, _)| info)
+            .find(|info| info.id == id)
+    }
+
+    pub fn infos(&self) -> impl Iterator<Item = &KeysetInfo> {
+        self.keysets.iter().map(|(info, _)| info)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&KeysetInfo, &Keyset)> {
+        self.keysets.iter().map(|(info, keyset)| (info, keyset))
+    }
+
+    fn save(&self) -> MintResult<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let infos: Vec<&KeysetInfo> = self.infos().collect();
+        let json =
+            serde_json::to_string_pretty(&infos).map_err(|e| MintError::Storage(e.to_string()))?;
+        // write then rename so a crash never leaves a truncated file behind
+        let tmp = format!("{}.tmp", path);
+        fs::write(&tmp, json)?;
+        fs::rename(tmp, path)?;
+        Ok(())
+    }
+}
+
+pub fn now_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .expect("time went backwards")
+        .as_secs()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rotation_keeps_old_keysets_for_verification() {
+        let mut keysets =
+            Keysets::load_or_create([3; 32], &["ehash".to_string()], 4, &HashMap::new(), None)
+                .unwrap();
+        let first = keysets.active("ehash").unwrap().id.clone();
+
+        let pending = keysets.generate("ehash").unwrap();
+        assert!(keysets.for_verification(&pending).is_err());
+        assert_eq!(keysets.active("ehash").unwrap().id, first);
+
+        keysets.activate(&pending).unwrap();
+        assert_eq!(keysets.active("ehash").unwrap().id, pending);
+        assert_eq!(keysets.info(&first).unwrap().state, KeysetState::Deprecated);
+        assert!(keysets.for_verification(&first).is_ok());
+        assert!(keysets.activate(&first).is_err());
+        assert!(keysets.deprecate(&pending).is_err());
+
+        assert!(keysets.rotate_if_due("ehash", u64::MAX).unwrap().is_none());
+        let third = keysets.rotate_if_due("ehash", 0).unwrap().unwrap();
+        assert_eq!(keysets.active("ehash").unwrap().id, third);
+        assert_eq!(keysets.infos().count(), 3);
+    }
+
+    #[test]
+    fn rolls_over_with_epochs() {
+        let mut keysets =
+            Keysets::load_or_create([3; 32], &["ehash".to_string()], 4, &HashMap::new(), None)
+                .unwrap();
+        let epoch = |number| Epoch {
+            number,
+            bits: 0x1d00ffff,
+        };
+        let first = keysets.rotate_to_epoch("ehash", epoch(7)).unwrap().unwrap();
+        assert!(keysets
+            .rotate_to_epoch("ehash", epoch(7))
+            .unwrap()
+            .is_none());
+        // rotating within an epoch stays in the epoch
+        let second = keysets.rotate("ehash").unwrap();
+        assert_eq!(keysets.info(&second).unwrap().epoch, Some(epoch(7)));
+        let third = keysets.rotate_to_epoch("ehash", epoch(8)).unwrap().unwrap();
+        assert_eq!(keysets.active("ehash").unwrap().id, third);
+        assert_eq!(keysets.info(&first).unwrap().state, KeysetState::Deprecated);
+        assert_eq!(keysets.info(&third).unwrap().epoch, Some(epoch(8)));
+    }
+
+    #[test]
+    fn replaces_keysets_of_other_amounts() {
+        let path = std::env::temp_dir().join(format!("potato-keysets-{}.json", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let units = ["ehash".to_string()];
+        let first =
+            Keysets::load_or_create([3; 32], &units, 4, &HashMap::new(), Some(path.clone()))
+                .unwrap()
+                .active("ehash")
+                .unwrap()
+                .id
+                .clone();
+        // keysets recorded before their amounts
+        let mut infos: Vec<KeysetInfo> =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(infos[0].amounts, Some(vec![1, 2, 4, 8]));
+        infos[0].amounts = None;
+        fs::write(&path, serde_json::to_string(&infos).unwrap()).unwrap();
+
+        let keysets =
+            Keysets::load_or_create([3; 32], &units, 4, &HashMap::new(), Some(path.clone()))
+                .unwrap();
+        assert_eq!(keysets.active("ehash").unwrap().id, first);
+        assert_eq!(
+            keysets.info(&first).unwrap().amounts,
+            Some(vec![1, 2, 4, 8])
+        );
+
+        let amounts =
+            HashMap::from([("ehash".to_string(), AmountStrategy::Custom(vec![4, 8, 16]))]);
+        let keysets =
+            Keysets::load_or_create([3; 32], &units, 4, &amounts, Some(path.clone())).unwrap();
+        let active = keysets.active("ehash").unwrap();
+        assert_ne!(active.id, first);
+        assert_eq!(active.amounts(), vec![4, 8, 16]);
+        // the old keyset still redeems, and keeps its amounts when max_order changes
+        assert_eq!(keysets.info(&first).unwrap().state, KeysetState::Deprecated);
+        let keysets =
+            Keysets::load_or_create([3; 32], &units, 8, &amounts, Some(path.clone())).unwrap();
+        assert_eq!(
+            keysets.for_verification(&first).unwrap().amounts(),
+            vec![1, 2, 4, 8]
+        );
+        assert_eq!(keysets.infos().count(), 2);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,162 @@
+//! Core Lightning over its JSON-RPC unix socket, connecting to it retried. There are no unix
+//! sockets on Windows, a mint there pays through LND.
+use super::{DecodedInvoice, PaymentStatus};
+use crate::{
+    error::{MintError, MintResult},
+    retry::Retry,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+#[cfg(unix)]
+use std::io;
+#[cfg(unix)]
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::UnixStream,
+};
+
+#[derive(Debug)]
+pub struct ClnClient {
+    rpc_path: String,
+    #[cfg_attr(not(unix), allow(dead_code))]
+    retry: Retry,
+}
+
+#[derive(Debug, Deserialize)]
+struct RpcResponse {
+    result: Option<Value>,
+    error: Option<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Pay {
+    status: String,
+    #[serde(default, alias = "payment_preimage")]
+    preimage: Option<String>,
+    #[serde(default)]
+    amount_msat: Option<u64>,
+    #[serde(default)]
+    amount_sent_msat: Option<u64>,
+}
+
+impl ClnClient {
+    pub fn new(rpc_path: String, retry: Retry) -> Self {
+        Self { rpc_path, retry }
+    }
+
+    pub async fn decode(&self, invoice: &str) -> MintResult<DecodedInvoice> {
+        let decoded = self.call("decodepay", json!({ "bolt11": invoice })).await?;
+        Ok(DecodedInvoice {
+            payment_hash: decoded["payment_hash"]
+                .as_str()
+                .ok_or_else(|| MintError::Lightning("invoice without payment hash".into()))?
+                .to_string(),
+            amount_msat: decoded["amount_msat"].as_u64().unwrap_or_default(),
+        })
+    }
+
+    pub async fn pay(&self, invoice: &str, max_fee_msat: u64) -> MintResult<PaymentStatus> {
+        match self
+            .call("pay", json!({ "bolt11": invoice, "maxfee": max_fee_msat }))
+            .await
+        {
+            Ok(result) => Ok(pay_status(
+                serde_json::from_value(result).map_err(|e| MintError::Lightning(e.to_string()))?,
+            )),
+            // the RPC errors when the payment fails, `listpays` tells whether it is final
+            Err(MintError::Lightning(e)) => {
+                let hash = self.decode(invoice).await?.payment_hash;
+                match self.status(&hash).await? {
+                    PaymentStatus::Failed(_) => Ok(PaymentStatus::Failed(e)),
+                    status => Ok(status),
+                }
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    pub async fn status(&self, payment_hash: &str) -> MintResult<PaymentStatus> {
+        let pays = self
+            .call("listpays", json!({ "payment_hash": payment_hash }))
+            .await?;
+        let pays: Vec<Pay> = serde_json::from_value(pays["pays"].clone())
+            .map_err(|e| MintError::Lightning(e.to_string()))?;
+        Ok(match pays.into_iter().last() {
+            Some(pay) => pay_status(pay),
+            None => PaymentStatus::Failed("payment not found".into()),
+        })
+    }
+
+    pub async fn balance(&self) -> MintResult<u64> {
+        let funds = self.call("listfunds", json!({})).await?;
+        let msat = |value: &Value, field: &str| value[field].as_u64().unwrap_or_default();
+        let outputs: u64 = funds["outputs"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter(|output| output["status"] == "confirmed")
+            .map(|output| msat(output, "amount_msat"))
+            .sum();
+        let channels: u64 = funds["channels"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter(|channel| channel["state"] == "CHANNELD_NORMAL")
+            .map(|channel| msat(channel, "our_amount_msat"))
+            .sum();
+        Ok((outputs + channels) / 1000)
+    }
+
+    #[cfg(unix)]
+    async fn call(&self, method: &str, params: Value) -> MintResult<Value> {
+        let mut stream = self
+            .retry
+            .run(|| UnixStream::connect(&self.rpc_path))
+            .await
+            .map_err(|e| e.into_error(io::Error::other))?;
+        let request = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+        stream.write_all(request.to_string().as_bytes()).await?;
+        // lightningd answers with a single JSON object, read until it parses
+        let mut buffer = Vec::new();
+        let mut chunk = [0; 4096];
+        loop {
+            let n = stream.read(&mut chunk).await?;
+            if n == 0 {
+                return Err(MintError::Lightning(format!(
+                    "lightningd closed the connection during `{}`",
+                    method
+                )));
+            }
+            buffer.extend_from_slice(&chunk[..n]);
+            if let Ok(response) = serde_json::from_slice::<RpcResponse>(&buffer) {
+                return match (response.result, response.error) {
+                    (Some(result), _) => Ok(result),
+                    (None, Some(error)) => Err(MintError::Lightning(error.to_string())),
+                    (None, None) => Err(MintError::Lightning("empty response".into())),
+                };
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    async fn call(&self, method: &str, _params: Value) -> MintResult<Value> {
+        Err(MintError::Lightning(format!(
+            "can't call `{}` on {}, unix sockets are not supported on this platform",
+            method, self.rpc_path
+        )))
+    }
+}
+
+fn pay_status(pay: Pay) -> PaymentStatus {
+    match pay.status.as_str() {
+        "complete" => PaymentStatus::Paid {
+            preimage: pay.preimage.unwrap_or_default(),
+            fee_msat: pay
+                .amount_sent_msat
+                .unwrap_or_default()
+                .saturating_sub(pay.amount_msat.unwrap_or_default()),
+        },
+        "failed" => PaymentStatus::Failed("payment failed".into()),
+        _ => PaymentStatus::Pending,
+    }
+}
@@ -0,0 +1,307 @@
+//! LND over its gRPC interface, authenticated with a macaroon and pinned to the node's TLS
+//! certificate. Calls that can't reach it are retried, nothing having reached it.
+use super::{DecodedInvoice, PaymentStatus};
+use crate::{
+    error::{MintError, MintResult},
+    retry::Retry,
+};
+use hyper_util::rt::TokioIo;
+use rustls::{
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::{ring, WebPkiSupportedAlgorithms},
+    pki_types::{CertificateDer, ServerName, UnixTime},
+    CertificateError, ClientConfig, DigitallySignedStruct, SignatureScheme,
+};
+use std::{fs, future::Future, sync::Arc, time::Duration};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+use tonic::{
+    metadata::{Ascii, MetadataValue},
+    transport::{Channel, Endpoint, Uri},
+    Code, Request, Status, Streaming,
+};
+
+// the names are LND's
+#[allow(clippy::enum_variant_names)]
+mod lnrpc {
+    tonic::include_proto!("lnrpc");
+}
+
+mod routerrpc {
+    tonic::include_proto!("routerrpc");
+}
+
+use lnrpc::{lightning_client::LightningClient, payment::PaymentStatus as LndStatus};
+use routerrpc::router_client::RouterClient;
+
+/// How long a payment may stay in flight before `pay` gives up waiting and reports it pending.
+const PAYMENT_TIMEOUT_SECS: u64 = 60;
+
+#[derive(Debug)]
+pub struct LndClient {
+    channel: Channel,
+    macaroon: MetadataValue<Ascii>,
+    retry: Retry,
+}
+
+impl LndClient {
+    /// `url` is the gRPC endpoint, e.g. `https://127.0.0.1:10009`. Connects on the first call.
+    pub fn new(
+        url: &str,
+        macaroon_path: &str,
+        tls_cert_path: &str,
+        retry: Retry,
+    ) -> MintResult<Self> {
+        let macaroon = hex::encode(fs::read(macaroon_path)?)
+            .parse()
+            .map_err(lightning)?;
+        let cert = rustls_pemfile::certs(&mut fs::read(tls_cert_path)?.as_slice())
+            .next()
+            .ok_or_else(|| {
+                MintError::Lightning(format!("no certificate in {}", tls_cert_path))
+            })??;
+        let uri: Uri = url.parse().map_err(lightning)?;
+        if uri.scheme_str() != Some("https") {
+            return Err(MintError::Lightning(format!("{} isn't an https URL", url)));
+        }
+        let host = uri
+            .host()
+            .ok_or_else(|| MintError::Lightning(format!("no host in {}", url)))?
+            .trim_matches(['[', ']'])
+            .to_string();
+        let port = uri.port_u16().unwrap_or(10009);
+        let provider = Arc::new(ring::default_provider());
+        let algorithms = provider.signature_verification_algorithms;
+        let mut tls = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(lightning)?
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(PinnedCert { cert, algorithms }))
+            .with_no_client_auth();
+        tls.alpn_protocols = vec![b"h2".to_vec()];
+        let tls = TlsConnector::from(Arc::new(tls));
+        // The connector speaks TLS itself, tonic would insist on a CA verifying the certificate,
+        // and LND's is self-signed, so tonic is handed a plain http endpoint.
+        let endpoint = Endpoint::from_shared(format!(
+            "http://{}:{}",
+            uri.host().unwrap_or_default(),
+            port
+        ))
+        .map_err(lightning)?;
+        let channel = endpoint.connect_with_connector_lazy(tower::service_fn(move |_: Uri| {
+            let (tls, host) = (tls.clone(), host.clone());
+            async move {
+                let name = ServerName::try_from(host.clone())
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+                let tcp = TcpStream::connect((host.as_str(), port)).await?;
+                Ok::<_, std::io::Error>(TokioIo::new(tls.connect(name, tcp).await?))
+            }
+        }));
+        Ok(Self {
+            channel,
+            macaroon,
+            retry,
+        })
+    }
+
+    pub async fn decode(&self, invoice: &str) -> MintResult<DecodedInvoice> {
+        let pay_req = self
+            .call(|| async {
+                self.lightning()
+                    .decode_pay_req(self.request(lnrpc::PayReqString {
+                        pay_req: invoice.to_string(),
+                    }))
+                    .await
+            })
+            .await?;
+        Ok(DecodedInvoice {
+            payment_hash: pay_req.payment_hash,
+            amount_msat: pay_req.num_msat.try_into().unwrap_or_default(),
+        })
+    }
+
+    pub async fn pay(&self, invoice: &str, max_fee_msat: u64) -> MintResult<PaymentStatus> {
+        let request = routerrpc::SendPaymentRequest {
+            payment_request: invoice.to_string(),
+            timeout_seconds: PAYMENT_TIMEOUT_SECS as i32,
+            fee_limit_msat: max_fee_msat.try_into().unwrap_or(i64::MAX),
+            no_inflight_updates: true,
+        };
+        let updates = self
+            .call(|| async {
+                self.router()
+                    .send_payment_v2(self.request(request.clone()))
+                    .await
+            })
+            .await;
+        read_updates(updates, Duration::from_secs(PAYMENT_TIMEOUT_SECS + 10)).await
+    }
+
+    pub async fn status(&self, payment_hash: &str) -> MintResult<PaymentStatus> {
+        let request = routerrpc::TrackPaymentRequest {
+            payment_hash: hex::decode(payment_hash)?,
+            no_inflight_updates: true,
+        };
+        let updates = self
+            .call(|| async {
+                self.router()
+                    .track_payment_v2(self.request(request.clone()))
+                    .await
+            })
+            .await;
+        match read_updates(updates, Duration::from_secs(10)).await {
+            // LND never heard of the payment, so it can't go out anymore
+            Err(MintError::Lightning(e)) if e.contains("isn't initiated") => {
+                Ok(PaymentStatus::Failed(e))
+            }
+            status => status,
+        }
+    }
+
+    pub async fn balance(&self) -> MintResult<u64> {
+        let channels = self
+            .call(|| async {
+                self.lightning()
+                    .channel_balance(self.request(lnrpc::ChannelBalanceRequest {}))
+                    .await
+            })
+            .await?;
+        let wallet = self
+            .call(|| async {
+                self.lightning()
+                    .wallet_balance(self.request(lnrpc::WalletBalanceRequest {}))
+                    .await
+            })
+            .await?;
+        let channels = channels
+            .local_balance
+            .map(|balance| balance.sat)
+            .unwrap_or_default();
+        Ok(channels + u64::try_from(wallet.confirmed_balance).unwrap_or_default())
+    }
+
+    fn lightning(&self) -> LightningClient<Channel> {
+        LightningClient::new(self.channel.clone())
+    }
+
+    fn router(&self) -> RouterClient<Channel> {
+        RouterClient::new(self.channel.clone())
+    }
+
+    fn request<T>(&self, message: T) -> Request<T> {
+        let mut request = Request::new(message);
+        request
+            .metadata_mut()
+            .insert("macaroon", self.macaroon.clone());
+        request
+    }
+
+    /// Makes the call `call` makes, again for every attempt while the node can't be reached.
+    async fn call<T, F, Fut>(&self, call: F) -> MintResult<T>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<tonic::Response<T>, Status>>,
+    {
+        let response = self
+            .retry
+            .run(|| {
+                let call = call();
+                async move {
+                    match call.await {
+                        Err(status) if status.code() == Code::Unavailable => Err(status),
+                        response => Ok(response),
+                    }
+                }
+            })
+            .await
+            .map_err(|e| lightning(e.into_error(Status::unavailable)))?;
+        Ok(response.map_err(lightning)?.into_inner())
+    }
+}
+
+/// Reads a payment stream to its last update. A stream not ended within `timeout` leaves the
+/// payment pending.
+async fn read_updates(
+    updates: MintResult<Streaming<lnrpc::Payment>>,
+    timeout: Duration,
+) -> MintResult<PaymentStatus> {
+    let mut updates = updates?;
+    let mut last = None;
+    let read = async {
+        while let Some(payment) = updates.message().await.map_err(lightning)? {
+            last = Some(payment);
+        }
+        Ok::<_, MintError>(())
+    };
+    match tokio::time::timeout(timeout, read).await {
+        Ok(Ok(())) => Ok(last.map(payment_status).unwrap_or(PaymentStatus::Pending)),
+        Ok(Err(e)) => Err(e),
+        Err(_) => Ok(PaymentStatus::Pending),
+    }
+}
+
+fn payment_status(payment: lnrpc::Payment) -> PaymentStatus {
+    match payment.status() {
+        LndStatus::Succeeded => PaymentStatus::Paid {
+            preimage: payment.payment_preimage,
+            fee_msat: payment.fee_msat.try_into().unwrap_or_default(),
+        },
+        LndStatus::Failed => {
+            PaymentStatus::Failed(payment.failure_reason().as_str_name().to_string())
+        }
+        _ => PaymentStatus::Pending,
+    }
+}
+
+fn lightning(e: impl std::fmt::Display) -> MintError {
+    MintError::Lightning(e.to_string())
+}
+
+/// Accepts the one certificate LND was configured with. LND's is its own CA, which WebPKI
+/// refuses as the certificate of a server, so it is compared rather than verified.
+#[derive(Debug)]
+struct PinnedCert {
+    cert: CertificateDer<'static>,
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl ServerCertVerifier for PinnedCert {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if end_entity.as_ref() == self.cert.as_ref() {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::InvalidCertificate(
+                CertificateError::UnknownIssuer,
+            ))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
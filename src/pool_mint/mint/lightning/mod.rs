@@ -0,0 +1,177 @@
+//! Lightning backends the mint pays melt invoices with. Both speak to a node the pool operator
+//! runs: LND through its gRPC interface and Core Lightning through its JSON-RPC socket.
+pub mod cln;
+pub mod lnd;
+
+use crate::{
+    error::MintResult,
+    retry::{Retry, RetryPolicy},
+};
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct LightningConfig {
+    #[serde(flatten)]
+    pub backend: BackendConfig,
+    /// Fee reserve asked on top of the invoice amount, in parts per million of the amount.
+    #[serde(default = "LightningConfig::default_fee_reserve_ppm")]
+    pub fee_reserve_ppm: u64,
+    /// Lower bound of the fee reserve, in sat.
+    #[serde(default = "LightningConfig::default_fee_reserve_min_sat")]
+    pub fee_reserve_min_sat: u64,
+    /// How many times a payment failing for good is tried before the melt is given up.
+    #[serde(default = "LightningConfig::default_payment_attempts")]
+    pub payment_attempts: u32,
+    #[serde(default = "LightningConfig::default_retry_delay_secs")]
+    pub retry_delay_secs: u64,
+    /// How calls the node can't be reached for are retried, see `crate::retry`.
+    #[serde(default)]
+    pub retry: RetryPolicy,
+}
+
+impl LightningConfig {
+    pub fn new(
+        backend: BackendConfig,
+        fee_reserve_ppm: u64,
+        fee_reserve_min_sat: u64,
+        payment_attempts: u32,
+        retry_delay_secs: u64,
+    ) -> Self {
+        Self {
+            backend,
+            fee_reserve_ppm,
+            fee_reserve_min_sat,
+            payment_attempts,
+            retry_delay_secs,
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    fn default_fee_reserve_ppm() -> u64 {
+        10_000
+    }
+
+    fn default_fee_reserve_min_sat() -> u64 {
+        2
+    }
+
+    fn default_payment_attempts() -> u32 {
+        3
+    }
+
+    fn default_retry_delay_secs() -> u64 {
+        5
+    }
+
+    /// Fee reserve in sat for paying `amount` sat.
+    pub fn fee_reserve(&self, amount: u64) -> u64 {
+        (amount.saturating_mul(self.fee_reserve_ppm) / 1_000_000).max(self.fee_reserve_min_sat)
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(tag = "backend", rename_all = "snake_case")]
+pub enum BackendConfig {
+    Lnd {
+        /// gRPC endpoint, e.g. `https://127.0.0.1:10009`.
+        url: String,
+        macaroon_path: String,
+        tls_cert_path: String,
+    },
+    Cln {
+        /// Path of the `lightning-rpc` socket.
+        rpc_path: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedInvoice {
+    pub payment_hash: String,
+    pub amount_msat: u64,
+}
+
+/// Where a payment stands according to the node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PaymentStatus {
+    Paid {
+        preimage: String,
+        fee_msat: u64,
+    },
+    /// The payment failed for good, nothing was or will be sent.
+    Failed(String),
+    /// Still in flight, or the node could not tell. The funds may still go out.
+    Pending,
+}
+
+#[derive(Debug)]
+pub enum LightningBackend {
+    Lnd(lnd::LndClient),
+    Cln(cln::ClnClient),
+}
+
+impl LightningBackend {
+    pub fn new(config: &BackendConfig, retry: &RetryPolicy) -> MintResult<Self> {
+        let retry = Retry::new("lightning", retry);
+        Ok(match config {
+            BackendConfig::Lnd {
+                url,
+                macaroon_path,
+                tls_cert_path,
+            } => LightningBackend::Lnd(lnd::LndClient::new(
+                url,
+                macaroon_path,
+                tls_cert_path,
+                retry,
+            )?),
+            BackendConfig::Cln { rpc_path } => {
+                LightningBackend::Cln(cln::ClnClient::new(rpc_path.clone(), retry))
+            }
+        })
+    }
+
+    pub async fn decode(&self, invoice: &str) -> MintResult<DecodedInvoice> {
+        match self {
+            LightningBackend::Lnd(client) => client.decode(invoice).await,
+            LightningBackend::Cln(client) => client.decode(invoice).await,
+        }
+    }
+
+    pub async fn pay(&self, invoice: &str, max_fee_msat: u64) -> MintResult<PaymentStatus> {
+        match self {
+            LightningBackend::Lnd(client) => client.pay(invoice, max_fee_msat).await,
+            LightningBackend::Cln(client) => client.pay(invoice, max_fee_msat).await,
+        }
+    }
+
+    pub async fn status(&self, payment_hash: &str) -> MintResult<PaymentStatus> {
+        match self {
+            LightningBackend::Lnd(client) => client.status(payment_hash).await,
+            LightningBackend::Cln(client) => client.status(payment_hash).await,
+        }
+    }
+
+    /// Sat the node holds: its side of the channels and its confirmed on-chain funds.
+    pub async fn balance(&self) -> MintResult<u64> {
+        match self {
+            LightningBackend::Lnd(client) => client.balance().await,
+            LightningBackend::Cln(client) => client.balance().await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_backend_config() {
+        let config: LightningConfig = serde_json::from_str(
+            r#"{"backend": "cln", "rpc_path": "/tmp/lightning-rpc", "fee_reserve_ppm": 5000}"#,
+        )
+        .unwrap();
+        assert!(matches!(config.backend, BackendConfig::Cln { .. }));
+        assert_eq!(config.fee_reserve(100_000), 500);
+        assert_eq!(config.fee_reserve(10), 2);
+        assert_eq!(config.payment_attempts, 3);
+    }
+}
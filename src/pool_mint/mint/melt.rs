@@ -0,0 +1,143 @@
+//! Pays melt quotes through the Lightning backend. The mint is never locked while a payment is in
+//! flight: the inputs are reserved first, the invoice is paid (retrying payments that failed for
+//! good), then the melt is settled according to what the node reports.
+use super::{
+    lightning::{LightningBackend, LightningConfig, PaymentStatus},
+    nuts::{MeltQuoteRequest, MeltQuoteResponse, MeltRequest},
+    quote::MeltQuote,
+    Mint,
+};
+use crate::error::{MintError, MintResult};
+use roles_logic_sv2::utils::Mutex;
+use std::{collections::HashSet, sync::Arc, time::Duration};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+/// How often melts left pending are checked with the node.
+const SETTLEMENT_INTERVAL_SECS: u64 = 60;
+
+#[derive(Debug)]
+pub struct Melter {
+    mint: Arc<Mutex<Mint>>,
+    backend: LightningBackend,
+    config: LightningConfig,
+    /// Quotes being paid by a `melt` call, left alone by the settlement.
+    in_flight: Mutex<HashSet<String>>,
+}
+
+impl Melter {
+    pub fn new(mint: Arc<Mutex<Mint>>, config: &LightningConfig) -> MintResult<Self> {
+        Ok(Self {
+            mint,
+            backend: LightningBackend::new(&config.backend, &config.retry)?,
+            config: config.clone(),
+            in_flight: Mutex::new(HashSet::new()),
+        })
+    }
+
+    fn with_mint<T>(&self, f: impl FnOnce(&mut Mint) -> MintResult<T>) -> MintResult<T> {
+        self.mint.safe_lock(f).map_err(MintError::from)?
+    }
+
+    pub async fn quote(&self, request: MeltQuoteRequest) -> MintResult<MeltQuoteResponse> {
+        let invoice = self.backend.decode(&request.request).await?;
+        self.with_mint(|mint| {
+            mint.create_melt_quote(&request.request, &request.unit, &invoice, &self.config)
+        })
+    }
+
+    pub async fn melt(&self, request: MeltRequest) -> MintResult<MeltQuoteResponse> {
+        let quote = self
+            .with_mint(|mint| mint.begin_melt(&request.quote, &request.inputs, &request.outputs))?;
+        self.in_flight
+            .safe_lock(|in_flight| in_flight.insert(quote.id.clone()))
+            .map_err(MintError::from)?;
+        let status = self.pay(&quote).await;
+        let response =
+            self.with_mint(|mint| mint.finish_melt(&quote.id, &status, &request.outputs));
+        self.in_flight
+            .safe_lock(|in_flight| in_flight.remove(&quote.id))
+            .map_err(MintError::from)?;
+        let response = response?;
+        match status {
+            PaymentStatus::Failed(reason) => Err(MintError::Lightning(reason)),
+            _ => Ok(response),
+        }
+    }
+
+    /// Pays `quote`, trying again after a payment failed for good. When the node can't be
+    /// reached its view of the payment is asked for instead, the payment may have gone out.
+    async fn pay(&self, quote: &MeltQuote) -> PaymentStatus {
+        let max_fee_msat = quote.fee_reserve.saturating_mul(1000);
+        let attempts = self.config.payment_attempts.max(1);
+        let mut attempt = 1;
+        loop {
+            let status = match self.backend.pay(&quote.request, max_fee_msat).await {
+                Ok(status) => status,
+                Err(e) => {
+                    warn!("Mint: paying melt quote {} errored: {}", quote.id, e);
+                    self.backend
+                        .status(&quote.payment_hash)
+                        .await
+                        .unwrap_or(PaymentStatus::Pending)
+                }
+            };
+            match status {
+                PaymentStatus::Failed(reason) if attempt < attempts => {
+                    warn!(
+                        "Mint: attempt {}/{} to pay melt quote {} failed: {}",
+                        attempt, attempts, quote.id, reason
+                    );
+                    attempt += 1;
+                    tokio::time::sleep(Duration::from_secs(self.config.retry_delay_secs)).await;
+                }
+                status => return status,
+            }
+        }
+    }
+
+    /// Settles melts still pending, e.g. after a restart or a payment that took too long. Their
+    /// change outputs are gone by then, so they settle without change.
+    pub async fn settle_pending(&self) -> MintResult<()> {
+        for quote in self.with_mint(|mint| mint.pending_melt_quotes())? {
+            let in_flight = self
+                .in_flight
+                .safe_lock(|in_flight| in_flight.contains(&quote.id))
+                .map_err(MintError::from)?;
+            if in_flight {
+                continue;
+            }
+            let status = match self.backend.status(&quote.payment_hash).await {
+                Ok(PaymentStatus::Pending) => continue,
+                Ok(status) => status,
+                Err(e) => {
+                    warn!("Mint: can't check melt quote {}: {}", quote.id, e);
+                    continue;
+                }
+            };
+            info!("Mint: settling melt quote {} as {:?}", quote.id, status);
+            self.with_mint(|mint| mint.finish_melt(&quote.id, &status, &[]))?;
+        }
+        Ok(())
+    }
+
+    pub async fn run_settlement(self: Arc<Self>, cancel_token: CancellationToken) {
+        let mut ticker = tokio::time::interval(Duration::from_secs(SETTLEMENT_INTERVAL_SECS));
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {
+                    let settling = async {
+                        if let Err(e) = self.settle_pending().await {
+                            warn!("Mint: settling pending melts failed: {}", e);
+                        }
+                    };
+                    // the melts left pending are settled on the next start
+                    if cancel_token.run_until_cancelled(settling).await.is_none() {
+                        break;
+                    }
+                }
+                _ = cancel_token.cancelled() => break,
+            }
+        }
+    }
+}
@@ -0,0 +1,197 @@
+//! Prometheus metrics of the mint, served at `/metrics` after those of the pool (see
+//! `status::metrics`) once the mint started:
+//!
+//! - `potato_mint_tokens_issued_total` and `potato_mint_tokens_redeemed_total`, the amounts
+//!   signed and spent under each keyset, swaps included;
+//! - `potato_mint_outstanding`, the tokens of each keyset issued and not redeemed yet;
+//! - `potato_mint_melted_total`, the sat paid out by melts;
+//! - `potato_mint_liabilities`, what the mint owes per unit, see `audit`;
+//! - `potato_mint_reserve_ratio`, the matured rewards per sat of liabilities, see `reserves`;
+//! - `potato_mint_double_spends_total`, the attempts to redeem a spent proof of each keyset;
+//! - `potato_mint_quote_seconds`, the time taken to create a quote of each method.
+//!
+//! Issuance, redemption and melts are read from the database, so they count since the mint was
+//! created. Double spends and quote latency count since the process started.
+use super::{audit, Mint, EHASH_UNIT, SAT_UNIT};
+use crate::{
+    error::MintResult,
+    status::metrics::{self, Histogram, Kind, Metric},
+};
+use once_cell::sync::Lazy;
+use roles_logic_sv2::utils::Mutex;
+use std::{collections::BTreeMap, time::Duration};
+
+/// Upper bounds in milliseconds of the buckets of the time taken to create a quote.
+pub const QUOTE_LATENCY_BUCKETS_MS: [u64; 11] = [
+    10, 25, 50, 100, 250, 500, 1_000, 2_500, 5_000, 10_000, 30_000,
+];
+
+static STATS: Lazy<Mutex<MintStats>> = Lazy::new(|| Mutex::new(MintStats::default()));
+
+/// What the mint counts in memory only.
+#[derive(Debug, Default)]
+struct MintStats {
+    /// Double spends attempted per keyset.
+    double_spends: BTreeMap<String, u64>,
+    /// Milliseconds taken to create a quote, per payment method.
+    quote_latency: BTreeMap<String, Histogram>,
+}
+
+/// Counts an attempt to redeem a spent proof of `keyset`.
+pub fn record_double_spend(keyset: &str) {
+    let _ = STATS.safe_lock(|stats| {
+        *stats.double_spends.entry(keyset.to_string()).or_default() += 1;
+    });
+}
+
+/// Records a quote of `method` created in `elapsed`.
+pub fn observe_quote(method: &str, elapsed: Duration) {
+    let _ = STATS.safe_lock(|stats| {
+        stats
+            .quote_latency
+            .entry(method.to_string())
+            .or_insert_with(|| Histogram::new(&QUOTE_LATENCY_BUCKETS_MS))
+            .observe(elapsed.as_millis() as u64);
+    });
+}
+
+/// The metrics of `mint` in the Prometheus text format.
+pub fn render(mint: &Mint) -> MintResult<String> {
+    let keysets = mint.keyset_infos();
+    let unit_of = |id: &str| {
+        keysets
+            .iter()
+            .find(|keyset| keyset.id == id)
+            .map_or_else(String::new, |keyset| keyset.unit.clone())
+    };
+    let labels = |id: &str| vec![("unit", unit_of(id)), ("keyset", id.to_string())];
+    let issued = mint.db.issued_by_keyset()?;
+    let redeemed = mint.db.spent_by_keyset()?;
+    let (double_spends, quote_latency) = STATS
+        .safe_lock(|stats| (stats.double_spends.clone(), stats.quote_latency.clone()))
+        .unwrap_or_default();
+
+    let mut issued_metric = Metric::new(
+        "mint_tokens_issued",
+        Kind::Counter,
+        "Amount of the tokens signed under each keyset",
+    );
+    let mut redeemed_metric = Metric::new(
+        "mint_tokens_redeemed",
+        Kind::Counter,
+        "Amount of the tokens spent under each keyset, by swaps and melts",
+    );
+    let mut outstanding = Metric::new(
+        "mint_outstanding",
+        Kind::Gauge,
+        "Amount of the tokens of each keyset issued and not redeemed yet",
+    );
+    for keyset in &keysets {
+        let issued = issued.get(&keyset.id).copied().unwrap_or_default();
+        let redeemed = redeemed.get(&keyset.id).copied().unwrap_or_default();
+        issued_metric = issued_metric.with(labels(&keyset.id), issued as f64);
+        redeemed_metric = redeemed_metric.with(labels(&keyset.id), redeemed as f64);
+        outstanding = outstanding.with(labels(&keyset.id), issued.saturating_sub(redeemed) as f64);
+    }
+    let mut double_spend_metric = Metric::new(
+        "mint_double_spends",
+        Kind::Counter,
+        "Attempts to redeem a spent proof of each keyset",
+    );
+    for (keyset, count) in &double_spends {
+        double_spend_metric = double_spend_metric.with(labels(keyset), *count as f64);
+    }
+
+    let books = audit::audit(&mint.db, &keysets, &mint.units)?;
+    let mut liabilities = Metric::new(
+        "mint_liabilities",
+        Kind::Gauge,
+        "What the mint owes per unit, in tokens, balances and reserves",
+    );
+    if let Some(sat) = &books.sat {
+        liabilities = liabilities.with(
+            vec![("unit", SAT_UNIT.to_string())],
+            sat.liabilities.total as f64,
+        );
+    }
+    if let Some(ehash) = &books.ehash {
+        liabilities = liabilities.with(
+            vec![("unit", EHASH_UNIT.to_string())],
+            (ehash.tokens + ehash.balances) as f64,
+        );
+    }
+    let mut reserve_ratio = Metric::new(
+        "mint_reserve_ratio",
+        Kind::Gauge,
+        "Matured rewards per sat of liabilities",
+    );
+    if let Some(ratio_ppk) = mint
+        .reserve_report()?
+        .and_then(|reserves| reserves.ratio_ppk)
+    {
+        reserve_ratio = reserve_ratio.with(vec![], ratio_ppk as f64 / 1000.0);
+    }
+    let melted = Metric::new("mint_melted", Kind::Counter, "Sat paid out by melts").with(
+        vec![("unit", SAT_UNIT.to_string())],
+        mint.db.melted()? as f64,
+    );
+
+    let mut out = String::new();
+    metrics::write(
+        &mut out,
+        vec![
+            issued_metric,
+            redeemed_metric,
+            outstanding,
+            melted,
+            liabilities,
+            reserve_ratio,
+            double_spend_metric,
+        ],
+    );
+    metrics::family(
+        &mut out,
+        "potato_mint_quote_seconds",
+        "histogram",
+        "Time taken to create a quote",
+    );
+    for (method, latency) in &quote_latency {
+        metrics::histogram(
+            &mut out,
+            "potato_mint_quote_seconds",
+            &[("method", method.as_str())],
+            latency,
+            1000,
+        );
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pool_mint::mint::MintConfig;
+
+    #[test]
+    fn labels_the_mint_metrics_by_keyset() {
+        let mint = Mint::from_master_secret(&[7; 32], &MintConfig::default()).unwrap();
+        let keyset = mint.active_keyset(SAT_UNIT).unwrap().id.clone();
+        record_double_spend(&keyset);
+        observe_quote("bolt11", Duration::from_millis(40));
+
+        let metrics = render(&mint).unwrap();
+        let labels = format!("unit=\"sat\",keyset=\"{}\"", keyset);
+        assert!(metrics.contains(&format!(
+            "potato_mint_tokens_issued_total{{{}}} 0\n",
+            labels
+        )));
+        assert!(metrics.contains(&format!("potato_mint_outstanding{{{}}} 0\n", labels)));
+        assert!(metrics.contains(&format!("potato_mint_double_spends_total{{{}}} ", labels)));
+        assert!(metrics.contains("potato_mint_liabilities{unit=\"sat\"} 0\n"));
+        assert!(
+            metrics.contains("potato_mint_quote_seconds_bucket{method=\"bolt11\",le=\"0.05\"} ")
+        );
+        // no liabilities, no ratio
+        assert!(!metrics.contains("\npotato_mint_reserve_ratio "));
+    }
+}
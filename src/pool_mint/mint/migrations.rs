@@ -0,0 +1,175 @@
+//! Versioned schema migrations of SQLite databases, so upgrading across releases updates the
+//! schema on open rather than by hand. Each migration is a pair of embedded SQL scripts: `up`
+//! applies it, `down` reverts it. The share accounting and the mint live in one database, whose
+//! migrations are `db::MIGRATIONS`.
+//!
+//! Applied migrations are recorded in `schema_migrations` along with their `down` script, so a
+//! release can revert migrations of a later one it doesn't know, see `potato mint migrate`.
+//! Every migration is applied or reverted in one transaction with its record.
+use crate::error::{MintError, MintResult};
+use rusqlite::{params, Connection};
+
+use super::lifecycle::now_secs;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Migration {
+    /// Position of the migration, counting from 1 without gaps.
+    pub version: u32,
+    pub name: &'static str,
+    pub up: &'static str,
+    pub down: &'static str,
+}
+
+const CREATE_TABLE: &str = "
+CREATE TABLE IF NOT EXISTS schema_migrations (
+    version INTEGER PRIMARY KEY,
+    name TEXT NOT NULL,
+    down TEXT NOT NULL,
+    applied_at INTEGER NOT NULL
+);
+";
+
+/// Version of the last migration applied, 0 for none.
+pub fn version(conn: &Connection) -> MintResult<u32> {
+    conn.execute_batch(CREATE_TABLE)?;
+    let version: Option<i64> =
+        conn.query_row("SELECT MAX(version) FROM schema_migrations", [], |row| {
+            row.get(0)
+        })?;
+    Ok(version.unwrap_or_default() as u32)
+}
+
+/// Applies the `migrations` not applied yet. A database migrated by a later release is refused,
+/// as this release would misread it.
+pub fn migrate(conn: &mut Connection, migrations: &[Migration]) -> MintResult<u32> {
+    let latest = latest(migrations);
+    let version = version(conn)?;
+    if version > latest {
+        return Err(MintError::Storage(format!(
+            "database schema version {} is newer than the {} of this release, revert it with \
+             `potato mint migrate --to {}`",
+            version, latest, latest
+        )));
+    }
+    migrate_to(conn, migrations, latest)
+}
+
+/// Applies or reverts migrations until the database is at `target`. Reverting uses the `down`
+/// scripts recorded with the migrations, so it works for migrations this release doesn't know.
+pub fn migrate_to(conn: &mut Connection, migrations: &[Migration], target: u32) -> MintResult<u32> {
+    if target > latest(migrations) {
+        return Err(MintError::Storage(format!(
+            "no migration to version {} in this release",
+            target
+        )));
+    }
+    let mut version = version(conn)?;
+    while version < target {
+        let migration = migrations
+            .iter()
+            .find(|migration| migration.version == version + 1)
+            .ok_or_else(|| MintError::Storage(format!("migration {} missing", version + 1)))?;
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration.up)?;
+        record(&tx, migration)?;
+        tx.commit()?;
+        version = migration.version;
+    }
+    while version > target {
+        let tx = conn.transaction()?;
+        let down: String = tx.query_row(
+            "SELECT down FROM schema_migrations WHERE version = ?1",
+            [version as i64],
+            |row| row.get(0),
+        )?;
+        tx.execute_batch(&down)?;
+        tx.execute(
+            "DELETE FROM schema_migrations WHERE version = ?1",
+            [version as i64],
+        )?;
+        tx.commit()?;
+        version = self::version(conn)?;
+    }
+    Ok(version)
+}
+
+fn record(conn: &Connection, migration: &Migration) -> MintResult<()> {
+    conn.execute(
+        "INSERT INTO schema_migrations (version, name, down, applied_at) VALUES (?1, ?2, ?3, ?4)",
+        params![
+            migration.version as i64,
+            migration.name,
+            migration.down,
+            now_secs() as i64
+        ],
+    )?;
+    Ok(())
+}
+
+fn latest(migrations: &[Migration]) -> u32 {
+    migrations
+        .iter()
+        .map(|migration| migration.version)
+        .max()
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const MIGRATIONS: &[Migration] = &[
+        Migration {
+            version: 1,
+            name: "accounts",
+            up: "CREATE TABLE accounts (name TEXT PRIMARY KEY);",
+            down: "DROP TABLE accounts;",
+        },
+        Migration {
+            version: 2,
+            name: "account_weight",
+            up: "ALTER TABLE accounts ADD COLUMN weight INTEGER NOT NULL DEFAULT 0;",
+            down: "ALTER TABLE accounts DROP COLUMN weight;",
+        },
+    ];
+
+    fn columns(conn: &Connection) -> Vec<String> {
+        conn.prepare("PRAGMA table_info(accounts)")
+            .unwrap()
+            .query_map([], |row| row.get(1))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn migrates_up_and_down() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        assert_eq!(version(&conn).unwrap(), 0);
+        assert_eq!(migrate(&mut conn, MIGRATIONS).unwrap(), 2);
+        assert_eq!(columns(&conn), vec!["name", "weight"]);
+        // applied migrations aren't applied again
+        assert_eq!(migrate(&mut conn, MIGRATIONS).unwrap(), 2);
+
+        assert_eq!(migrate_to(&mut conn, MIGRATIONS, 1).unwrap(), 1);
+        assert_eq!(columns(&conn), vec!["name"]);
+        assert!(migrate_to(&mut conn, MIGRATIONS, 3).is_err());
+        assert_eq!(migrate_to(&mut conn, MIGRATIONS, 0).unwrap(), 0);
+        assert!(columns(&conn).is_empty());
+    }
+
+    #[test]
+    fn reverts_migrations_of_later_releases() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn, MIGRATIONS).unwrap();
+        // an earlier release only knows the first migration
+        let earlier = &MIGRATIONS[..1];
+        assert!(matches!(
+            migrate(&mut conn, earlier),
+            Err(MintError::Storage(_))
+        ));
+        assert_eq!(migrate_to(&mut conn, earlier, 1).unwrap(), 1);
+        assert_eq!(columns(&conn), vec!["name"]);
+        assert_eq!(migrate(&mut conn, earlier).unwrap(), 1);
+    }
+}
@@ -0,0 +1,14 @@
+DROP TABLE fees;
+DROP TABLE account_pubkeys;
+DROP TABLE payouts;
+DROP TABLE pending_proofs;
+DROP TABLE queued_outputs;
+DROP TABLE conversion;
+DROP TABLE round_shares;
+DROP TABLE rounds;
+DROP TABLE sat_balances;
+DROP TABLE melt_quotes;
+DROP TABLE spent_proofs;
+DROP TABLE blind_signatures;
+DROP TABLE mint_quotes;
+DROP TABLE balances;
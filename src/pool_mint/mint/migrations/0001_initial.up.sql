@@ -0,0 +1,96 @@
+-- balances, rounds and shares of the share accounting, and quotes, signatures and proofs of
+-- the mint
+CREATE TABLE balances (
+    account TEXT PRIMARY KEY,
+    amount INTEGER NOT NULL
+);
+CREATE TABLE mint_quotes (
+    id TEXT PRIMARY KEY,
+    account TEXT NOT NULL,
+    amount INTEGER NOT NULL,
+    expiry INTEGER NOT NULL,
+    issued INTEGER NOT NULL DEFAULT 0,
+    unit TEXT NOT NULL DEFAULT 'ehash'
+);
+CREATE TABLE blind_signatures (
+    blinded_secret TEXT PRIMARY KEY,
+    amount INTEGER NOT NULL,
+    keyset_id TEXT NOT NULL,
+    signature TEXT NOT NULL,
+    created_at INTEGER NOT NULL
+);
+CREATE TABLE spent_proofs (
+    y TEXT PRIMARY KEY,
+    amount INTEGER NOT NULL,
+    keyset_id TEXT NOT NULL,
+    spent_at INTEGER NOT NULL
+);
+CREATE TABLE melt_quotes (
+    id TEXT PRIMARY KEY,
+    request TEXT NOT NULL,
+    payment_hash TEXT NOT NULL,
+    amount INTEGER NOT NULL,
+    fee_reserve INTEGER NOT NULL,
+    state TEXT NOT NULL,
+    expiry INTEGER NOT NULL,
+    payment_preimage TEXT,
+    input_fee INTEGER NOT NULL DEFAULT 0
+);
+CREATE TABLE sat_balances (
+    account TEXT PRIMARY KEY,
+    amount INTEGER NOT NULL
+);
+CREATE TABLE rounds (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    state TEXT NOT NULL,
+    started_at INTEGER NOT NULL,
+    ended_at INTEGER,
+    coinbase_txid TEXT,
+    reward INTEGER,
+    height INTEGER
+);
+CREATE TABLE round_shares (
+    round_id INTEGER NOT NULL,
+    account TEXT NOT NULL,
+    weight INTEGER NOT NULL,
+    PRIMARY KEY (round_id, account)
+);
+CREATE TABLE conversion (
+    id INTEGER PRIMARY KEY CHECK (id = 0),
+    reserve INTEGER NOT NULL,
+    outstanding INTEGER NOT NULL
+);
+INSERT INTO conversion (id, reserve, outstanding) VALUES (0, 0, 0);
+CREATE TABLE queued_outputs (
+    blinded_secret TEXT PRIMARY KEY,
+    account TEXT NOT NULL,
+    amount INTEGER NOT NULL,
+    keyset_id TEXT NOT NULL,
+    queued_at INTEGER NOT NULL
+);
+CREATE TABLE pending_proofs (
+    y TEXT PRIMARY KEY,
+    amount INTEGER NOT NULL,
+    keyset_id TEXT NOT NULL,
+    quote_id TEXT NOT NULL
+);
+CREATE TABLE payouts (
+    id TEXT PRIMARY KEY,
+    account TEXT NOT NULL,
+    unit TEXT NOT NULL,
+    amount INTEGER NOT NULL,
+    token TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    delivered_at INTEGER
+);
+CREATE TABLE account_pubkeys (
+    account TEXT PRIMARY KEY,
+    pubkey TEXT NOT NULL,
+    registered_at INTEGER NOT NULL
+);
+CREATE TABLE fees (
+    unit TEXT NOT NULL,
+    operation TEXT NOT NULL,
+    amount INTEGER NOT NULL,
+    PRIMARY KEY (unit, operation)
+);
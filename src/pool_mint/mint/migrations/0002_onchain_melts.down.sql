@@ -0,0 +1,3 @@
+DROP TABLE onchain_batches;
+DROP TABLE onchain_payouts;
+ALTER TABLE melt_quotes DROP COLUMN method;
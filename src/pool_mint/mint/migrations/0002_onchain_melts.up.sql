@@ -0,0 +1,14 @@
+-- melts paying out to an address, sent in batch transactions, see `onchain`
+ALTER TABLE melt_quotes ADD COLUMN method TEXT NOT NULL DEFAULT 'bolt11';
+CREATE TABLE onchain_payouts (
+    quote_id TEXT PRIMARY KEY,
+    address TEXT NOT NULL,
+    amount INTEGER NOT NULL,
+    txid TEXT
+);
+CREATE TABLE onchain_batches (
+    txid TEXT PRIMARY KEY,
+    tx TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    sent_at INTEGER
+);
@@ -0,0 +1,12 @@
+-- running totals of what every account was credited, issued and had settled per unit, see
+-- `accounts`. Balances accrued before count as credited
+CREATE TABLE account_ledger (
+    account TEXT NOT NULL,
+    unit TEXT NOT NULL,
+    credited INTEGER NOT NULL DEFAULT 0,
+    issued INTEGER NOT NULL DEFAULT 0,
+    redeemed INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (account, unit)
+);
+INSERT INTO account_ledger (account, unit, credited) SELECT account, 'ehash', amount FROM balances;
+INSERT INTO account_ledger (account, unit, credited) SELECT account, 'sat', amount FROM sat_balances;
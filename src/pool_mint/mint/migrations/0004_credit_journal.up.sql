@@ -0,0 +1,3 @@
+-- credits of the journal kept while the database was unavailable, recorded as they are replayed
+-- so none is credited twice, see `journal`
+CREATE TABLE journal_replays (id TEXT PRIMARY KEY);
@@ -0,0 +1,19 @@
+-- what every round credited each of its accounts per unit, ehash as shares come in and sat once
+-- the round matured, cross-checked against round_shares by `reconcile`. Rounds credited before
+-- count as credited as their shares say, matured ones with their full payout
+CREATE TABLE round_credits (
+    round_id INTEGER NOT NULL,
+    account TEXT NOT NULL,
+    unit TEXT NOT NULL,
+    amount INTEGER NOT NULL,
+    PRIMARY KEY (round_id, account, unit)
+);
+INSERT INTO round_credits (round_id, account, unit, amount)
+SELECT round_id, account, 'ehash', weight FROM round_shares;
+INSERT INTO round_credits (round_id, account, unit, amount)
+SELECT s.round_id, s.account, 'sat', r.reward * s.weight / t.weight
+FROM round_shares s
+JOIN rounds r ON r.id = s.round_id
+JOIN (SELECT round_id, SUM(weight) AS weight FROM round_shares GROUP BY round_id) t
+    ON t.round_id = s.round_id
+WHERE r.state = 'matured' AND t.weight > 0 AND r.reward * s.weight / t.weight > 0;
@@ -0,0 +1,6 @@
+-- Nostr keys payouts of accounts paid out in `nostr` mode are sent to, see `nostr`
+CREATE TABLE nostr_keys (
+    account TEXT PRIMARY KEY,
+    pubkey TEXT NOT NULL,
+    registered_at INTEGER NOT NULL
+);
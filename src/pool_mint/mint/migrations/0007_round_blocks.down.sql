@@ -0,0 +1,2 @@
+ALTER TABLE rounds DROP COLUMN finder;
+ALTER TABLE rounds DROP COLUMN block_hash;
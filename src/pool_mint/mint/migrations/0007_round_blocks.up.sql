@@ -0,0 +1,4 @@
+-- the block closing each round, its hash once confirmed in the best chain and the account whose
+-- share found it, for the explorer pages of the status API
+ALTER TABLE rounds ADD COLUMN block_hash TEXT;
+ALTER TABLE rounds ADD COLUMN finder TEXT;
@@ -0,0 +1,7 @@
+-- SHA-256 of the credential the operator issued each account, asked for before issuing from its
+-- balance, see `credentials`
+CREATE TABLE account_credentials (
+    account TEXT PRIMARY KEY,
+    digest TEXT NOT NULL,
+    issued_at INTEGER NOT NULL
+);
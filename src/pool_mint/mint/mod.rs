@@ -0,0 +1,2874 @@
+//! The ecash side of potato. Every share a miner gets accepted accrues "ehash" to the account it
+//! mines under, weighted by the share difficulty. The balance can be withdrawn as blinded Cashu
+//! tokens signed with keys derived from a master secret the mint keeps on disk.
+//!
+//! The mint issues two units, each with its own keysets and balances. Ehash is credited right away
+//! for every share. Sat is credited from block rewards: shares count toward the current round, and
+//! once the block closing the round matures the round's ehash is settled in sat (see `rounds`).
+//! Rewards of blocks that could still be orphaned can't be redeemed.
+//!
+//! Without the `mint` feature, only the tokens and the payout messages carrying them are built
+//! (`nuts`, `payout`), for a translator to keep what its pool pays it.
+pub mod accounts;
+pub mod amounts;
+#[cfg(feature = "mint")]
+pub mod api;
+#[cfg(feature = "mint")]
+pub mod audit;
+#[cfg(feature = "mint")]
+pub mod backend;
+#[cfg(feature = "mint")]
+pub mod backup;
+#[cfg(feature = "cdk")]
+pub mod cdk_mint;
+#[cfg(feature = "mint")]
+pub mod client;
+#[cfg(feature = "mint")]
+pub mod credentials;
+#[cfg(feature = "mint")]
+pub mod db;
+#[cfg(feature = "mint")]
+pub mod deterministic;
+#[cfg(feature = "mint")]
+pub mod dhke;
+#[cfg(feature = "mint")]
+pub mod epochs;
+#[cfg(feature = "mint")]
+pub mod external;
+#[cfg(feature = "mint")]
+pub mod fees;
+#[cfg(feature = "mint")]
+pub mod info;
+#[cfg(feature = "mint")]
+pub mod journal;
+#[cfg(feature = "mint")]
+pub mod keyset;
+#[cfg(feature = "mint")]
+pub mod lifecycle;
+#[cfg(feature = "mint")]
+pub mod lightning;
+#[cfg(feature = "mint")]
+pub mod melt;
+#[cfg(feature = "mint")]
+pub mod metrics;
+#[cfg(feature = "mint")]
+pub mod migrations;
+#[cfg(feature = "mint")]
+pub mod nostr;
+pub mod nuts;
+#[cfg(feature = "mint")]
+pub mod onchain;
+#[cfg(feature = "mint")]
+pub mod p2pk;
+pub mod payout;
+#[cfg(feature = "mint")]
+pub mod quote;
+#[cfg(feature = "mint")]
+pub mod reconcile;
+#[cfg(feature = "mint")]
+pub mod reserves;
+#[cfg(feature = "mint")]
+pub mod rounds;
+#[cfg(feature = "mint")]
+pub mod seed;
+#[cfg(feature = "mint")]
+pub mod spent;
+#[cfg(feature = "mint")]
+pub mod subscriptions;
+#[cfg(feature = "mint")]
+pub mod wallet;
+#[cfg(feature = "mint")]
+pub mod writer;
+
+#[cfg(feature = "mint")]
+use {
+    crate::{
+        error::{MintError, MintResult},
+        ratelimit::RateLimitConfig,
+        status::events::{self, Event},
+    },
+    accounts::AccountStatement,
+    amounts::AmountStrategy,
+    api::{BOLT11_METHOD, ONCHAIN_METHOD},
+    db::{Ledger, MintDb},
+    epochs::{Epoch, EpochConfig, OlderEpochs},
+    external::ExternalMintConfig,
+    fees::{FeeConfig, FeeTotal, Operation},
+    info::MintInfoConfig,
+    journal::{Credit, CreditJournal},
+    keyset::Keyset,
+    lifecycle::{now_secs, KeysetInfo, KeysetState, Keysets},
+    lightning::{DecodedInvoice, LightningConfig, PaymentStatus},
+    nostr::NostrConfig,
+    nuts::{
+        BlindSignature, BlindedMessage, DleqProof, MeltQuoteResponse, MeltQuoteState,
+        MintQuoteResponse, MintQuoteState, Proof, ProofState, SpendState, Token,
+    },
+    onchain::{OnchainBatch, OnchainConfig, OnchainPayout},
+    payout::{Denominations, Payout, PayoutConfig, PayoutMode},
+    quote::{MeltQuote, MintQuote},
+    reconcile::{DiscrepancyKind, ReconcileConfig, ReconcileReport},
+    reserves::{ReserveConfig, ReserveReport},
+    rounds::{AccountRound, Conversion, Round, RoundState},
+    secp256k1::{PublicKey, Secp256k1, XOnlyPublicKey},
+    seed::SeedConfig,
+    serde::{Deserialize, Serialize},
+    spent::SpentReport,
+    std::collections::{BTreeMap, HashMap, HashSet},
+    stratum_common::bitcoin::util::uint::Uint256,
+    subscriptions::{MintEvent, EVENTS_CAPACITY},
+    tokio::sync::broadcast,
+    tracing::{debug, info, info_span, warn},
+    writer::AccountingConfig,
+};
+
+#[cfg(feature = "mint")]
+#[derive(Debug, Deserialize, Clone)]
+pub struct MintConfig {
+    /// Units of the issued tokens, `EHASH_UNIT` and/or `SAT_UNIT`.
+    #[serde(default = "MintConfig::default_units")]
+    pub units: Vec<String>,
+    /// File holding the hex encoded master secret all mint keys are derived from. Created on first
+    /// start.
+    #[serde(default = "MintConfig::default_master_secret_path")]
+    pub master_secret_path: String,
+    /// If set, the master secret is derived from this seed instead of generated, see `seed`.
+    #[serde(default)]
+    pub seed: Option<SeedConfig>,
+    /// SQLite database of balances, quotes, issued signatures and spent proofs.
+    #[serde(default = "MintConfig::default_db_path")]
+    pub db_path: String,
+    /// File listing the generated keysets and their lifecycle state.
+    #[serde(default = "MintConfig::default_keysets_path")]
+    pub keysets_path: String,
+    /// File credits are journaled to while the database is unavailable, see `journal`.
+    #[serde(default = "MintConfig::default_journal_path")]
+    pub journal_path: String,
+    /// Number of power of two denominations in a keyset, the largest one being
+    /// `2^(max_order - 1)`.
+    #[serde(default = "MintConfig::default_max_order")]
+    pub max_order: u8,
+    /// Amounts of the keysets of a unit, if not every power of two up to `max_order`, see
+    /// `amounts`.
+    #[serde(default)]
+    pub amounts: HashMap<String, AmountStrategy>,
+    /// Lightning node paying melt invoices. Melting is disabled without one.
+    #[serde(default)]
+    pub lightning: Option<LightningConfig>,
+    /// If set, the active keyset is rotated once it has been active for this many seconds.
+    #[serde(default)]
+    pub keyset_rotation_interval_secs: Option<u64>,
+    /// Address the Cashu HTTP API (see `api`) listens on.
+    #[serde(default = "MintConfig::default_api_address")]
+    pub api_address: String,
+    /// URL wallets reach the API at, `http://<api_address>` if unset.
+    #[serde(default)]
+    pub url: Option<String>,
+    /// Automatic payouts per account, see `payout`.
+    #[serde(default)]
+    pub payout: PayoutConfig,
+    /// Fees charged by swaps and melts, see `fees`.
+    #[serde(default)]
+    pub fees: FeeConfig,
+    /// Ehash keysets per difficulty epoch, see `epochs`.
+    #[serde(default)]
+    pub epochs: EpochConfig,
+    /// Relays payouts of accounts in `nostr` mode are sent through, see `nostr`.
+    #[serde(default)]
+    pub nostr: Option<NostrConfig>,
+    /// If set, payouts are tokens of this existing mint instead of this one, see `external`.
+    #[serde(default)]
+    pub external: Option<ExternalMintConfig>,
+    /// If set, a mint of the Cashu Dev Kit run in the process issues the tokens, this one only
+    /// keeping the accounts, see `cdk_mint`.
+    #[cfg(feature = "cdk")]
+    #[serde(default)]
+    pub cdk: Option<cdk_mint::CdkMintConfig>,
+    /// Metadata published by the NUT-06 info endpoint.
+    #[serde(default)]
+    pub info: MintInfoConfig,
+    /// Rate limits of the API, superseded by `rate_limits.mint` of the pool, see
+    /// `crate::ratelimit`.
+    #[serde(default)]
+    pub rate_limits: RateLimitConfig,
+    /// On-chain melts paid by batch transactions, disabled if unset, see `onchain`.
+    #[serde(default)]
+    pub onchain: Option<OnchainConfig>,
+    /// Reserve ratio sat tokens are issued at, see `reserves`.
+    #[serde(default)]
+    pub reserves: ReserveConfig,
+    /// Scheduled reconciliation of shares and credits, see `reconcile`.
+    #[serde(default)]
+    pub reconcile: ReconcileConfig,
+    /// How accepted shares are written to the database, see `writer`.
+    #[serde(default)]
+    pub accounting: AccountingConfig,
+}
+
+#[cfg(feature = "mint")]
+impl MintConfig {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        units: Vec<String>,
+        master_secret_path: String,
+        db_path: String,
+        keysets_path: String,
+        max_order: u8,
+        keyset_rotation_interval_secs: Option<u64>,
+        api_address: String,
+        lightning: Option<LightningConfig>,
+    ) -> Self {
+        Self {
+            units,
+            master_secret_path,
+            seed: None,
+            db_path,
+            keysets_path,
+            journal_path: Self::default_journal_path(),
+            max_order,
+            amounts: HashMap::new(),
+            keyset_rotation_interval_secs,
+            api_address,
+            lightning,
+            url: None,
+            payout: PayoutConfig::default(),
+            fees: FeeConfig::default(),
+            epochs: EpochConfig::default(),
+            nostr: None,
+            external: None,
+            #[cfg(feature = "cdk")]
+            cdk: None,
+            info: MintInfoConfig::default(),
+            rate_limits: RateLimitConfig::default(),
+            onchain: None,
+            reserves: ReserveConfig::default(),
+            reconcile: ReconcileConfig::default(),
+            accounting: AccountingConfig::default(),
+        }
+    }
+
+    /// URL tokens issued by the mint name it by.
+    pub fn url(&self) -> String {
+        self.url
+            .clone()
+            .unwrap_or_else(|| format!("http://{}", self.api_address))
+    }
+
+    fn default_units() -> Vec<String> {
+        vec![EHASH_UNIT.to_string(), SAT_UNIT.to_string()]
+    }
+
+    fn default_master_secret_path() -> String {
+        "mint_master_secret".to_string()
+    }
+
+    fn default_db_path() -> String {
+        "mint.sqlite".to_string()
+    }
+
+    fn default_keysets_path() -> String {
+        "mint_keysets.json".to_string()
+    }
+
+    fn default_journal_path() -> String {
+        "mint_credit_journal.jsonl".to_string()
+    }
+
+    fn default_max_order() -> u8 {
+        32
+    }
+
+    fn default_api_address() -> String {
+        "127.0.0.1:3338".to_string()
+    }
+}
+
+#[cfg(feature = "mint")]
+impl Default for MintConfig {
+    fn default() -> Self {
+        Self::new(
+            Self::default_units(),
+            Self::default_master_secret_path(),
+            Self::default_db_path(),
+            Self::default_keysets_path(),
+            Self::default_max_order(),
+            None,
+            Self::default_api_address(),
+            None,
+        )
+    }
+}
+
+/// Unit of the difficulty weighted credits issued for every accepted share.
+pub const EHASH_UNIT: &str = "ehash";
+/// Unit of the tokens backed by matured block rewards, the only one invoices can be paid in.
+pub const SAT_UNIT: &str = "sat";
+/// Weight of the shares before the last one in the moving average of share weights.
+#[cfg(feature = "mint")]
+const SHARE_WEIGHT_SMOOTHING: u64 = 8;
+
+/// Summary of the mint for its operator, served by the control API.
+#[cfg(feature = "mint")]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MintReport {
+    pub keysets: Vec<KeysetInfo>,
+    pub signatures: u64,
+    pub spent_proofs: u64,
+    /// The fee ledger.
+    pub fees: Vec<FeeTotal>,
+    /// Spent proof lookups and double spend attempts.
+    pub spent: SpentReport,
+    /// How the sat owed is backed, `None` for a mint not issuing sat.
+    pub reserves: Option<ReserveReport>,
+    /// Credits journaled while the database was unavailable and not replayed yet.
+    pub journaled_credits: usize,
+}
+
+#[cfg(feature = "mint")]
+#[derive(Debug)]
+pub struct Mint {
+    units: Vec<String>,
+    keysets: Keysets,
+    fees: FeeConfig,
+    epochs: EpochConfig,
+    reserves: ReserveConfig,
+    /// Credits waiting for the database, see `journal`.
+    journal: CreditJournal,
+    /// Unwithdrawn ehash and matured sat per account (an account being the user identity a
+    /// channel was opened with), rounds, quotes, issued signatures and spent proofs.
+    db: MintDb,
+    /// State changes, for the subscriptions of wallets.
+    events: broadcast::Sender<MintEvent>,
+    /// Moving average of the share weight of each account since the mint started, see
+    /// `payout::share_weighted_amount`.
+    share_weights: HashMap<String, u64>,
+}
+
+#[cfg(feature = "mint")]
+impl Mint {
+    /// Loads (or creates) the master secret and keysets from the config. The pool authority key
+    /// is only needed by a mint whose seed is the authority key.
+    pub fn new(config: &MintConfig, authority_secret_key: Option<&[u8; 32]>) -> MintResult<Self> {
+        check_units(&config.units)?;
+        let master_secret = match &config.seed {
+            Some(seed) => {
+                seed::load_master_secret(seed, authority_secret_key, &config.master_secret_path)?
+            }
+            None => keyset::load_or_create_master_secret(&config.master_secret_path)?,
+        };
+        let keysets = Keysets::load_or_create(
+            master_secret,
+            &config.units,
+            config.max_order,
+            &config.amounts,
+            Some(config.keysets_path.clone()),
+        )?;
+        let db = MintDb::open(&config.db_path)?;
+        db.set_synchronous(config.accounting.synchronous)?;
+        let mut mint = Self::with_keysets(keysets, db, config);
+        mint.journal = CreditJournal::open(Some(config.journal_path.clone()))?;
+        if !mint.journal.is_empty() {
+            info!(
+                "Mint: replaying {} credits journaled before the restart",
+                mint.journal.len()
+            );
+            mint.replay_journal()?;
+        }
+        for unit in mint.units() {
+            info!(
+                "Mint started with keyset {} ({})",
+                mint.active_keyset(unit)?.id,
+                unit
+            );
+        }
+        Ok(mint)
+    }
+
+    /// In memory mint that persists nothing.
+    pub fn from_master_secret(master_secret: &[u8; 32], config: &MintConfig) -> MintResult<Self> {
+        check_units(&config.units)?;
+        let keysets = Keysets::load_or_create(
+            *master_secret,
+            &config.units,
+            config.max_order,
+            &config.amounts,
+            None,
+        )?;
+        Ok(Self::with_keysets(
+            keysets,
+            MintDb::open_in_memory()?,
+            config,
+        ))
+    }
+
+    fn with_keysets(keysets: Keysets, db: MintDb, config: &MintConfig) -> Self {
+        Self {
+            units: config.units.clone(),
+            keysets,
+            fees: config.fees.clone(),
+            epochs: config.epochs.clone(),
+            reserves: config.reserves.clone(),
+            journal: CreditJournal::default(),
+            db,
+            events: broadcast::channel(EVENTS_CAPACITY).0,
+            share_weights: HashMap::new(),
+        }
+    }
+
+    /// Receives the state changes of quotes, proofs and balances from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<MintEvent> {
+        self.events.subscribe()
+    }
+
+    /// Publishes the event made by `event`, only made when someone subscribed.
+    fn notify(&self, event: impl FnOnce() -> MintEvent) {
+        if self.events.receiver_count() > 0 {
+            let _ = self.events.send(event());
+        }
+    }
+
+    pub fn units(&self) -> &[String] {
+        &self.units
+    }
+
+    pub fn has_unit(&self, unit: &str) -> bool {
+        self.units.iter().any(|u| u == unit)
+    }
+
+    /// The keyset new outputs of `unit` are signed with.
+    pub fn active_keyset(&self, unit: &str) -> MintResult<&Keyset> {
+        match self.has_unit(unit) {
+            true => Ok(self
+                .keysets
+                .active(unit)
+                .expect("the mint always keeps an active keyset per unit")),
+            false => Err(MintError::UnsupportedUnit(unit.to_string())),
+        }
+    }
+
+    pub fn keysets(&self) -> &Keysets {
+        &self.keysets
+    }
+
+    pub fn keyset_infos(&self) -> Vec<KeysetInfo> {
+        self.keysets.infos().cloned().collect()
+    }
+
+    /// Generates a keyset of `unit` that stays pending until activated.
+    pub fn generate_keyset(&mut self, unit: &str) -> MintResult<String> {
+        self.active_keyset(unit)?;
+        self.keysets.generate(unit)
+    }
+
+    pub fn activate_keyset(&mut self, id: &str) -> MintResult<()> {
+        self.keysets.activate(id)
+    }
+
+    pub fn deprecate_keyset(&mut self, id: &str) -> MintResult<()> {
+        self.keysets.deprecate(id)
+    }
+
+    /// Replaces the active keyset of `unit` with a freshly generated one.
+    pub fn rotate_keyset(&mut self, unit: &str) -> MintResult<String> {
+        self.active_keyset(unit)?;
+        self.keysets.rotate(unit)
+    }
+
+    /// Rotates the keyset of every unit active for `interval_secs`, returning the new ones.
+    pub fn rotate_keysets_if_due(&mut self, interval_secs: u64) -> MintResult<Vec<String>> {
+        let mut rotated = vec![];
+        for unit in &self.units {
+            rotated.extend(self.keysets.rotate_if_due(unit, interval_secs)?);
+        }
+        Ok(rotated)
+    }
+
+    pub fn epochs_enabled(&self) -> bool {
+        self.epochs.enabled && self.has_unit(EHASH_UNIT)
+    }
+
+    /// Rolls the ehash keyset over into `epoch` if it is a new one and epochs are enabled.
+    pub fn enter_epoch(&mut self, epoch: Epoch) -> MintResult<Option<String>> {
+        if !self.epochs_enabled() {
+            return Ok(None);
+        }
+        self.keysets.rotate_to_epoch(EHASH_UNIT, epoch)
+    }
+
+    /// Amount of `unit` tokens `account` can withdraw.
+    pub fn balance(&self, account: &str, unit: &str) -> MintResult<u64> {
+        self.db.balance(ledger(unit)?, account)
+    }
+
+    /// Ledgers of `account` in every unit and the weight of its shares not matured yet.
+    pub fn account_statement(&self, account: &str) -> MintResult<AccountStatement> {
+        let mut ledgers = BTreeMap::new();
+        for unit in &self.units {
+            let totals = self.db.account_ledger(ledger(unit)?, account)?;
+            ledgers.insert(unit.clone(), totals);
+        }
+        Ok(AccountStatement {
+            account: account.to_string(),
+            ledgers,
+            pending_shares: self.db.pending_shares(account)?,
+        })
+    }
+
+    /// Every account ever credited, by name.
+    pub fn accounts(&self) -> MintResult<Vec<String>> {
+        self.db.accounts()
+    }
+
+    /// Statements of every account ever credited.
+    pub fn account_statements(&self) -> MintResult<Vec<AccountStatement>> {
+        self.db
+            .accounts()?
+            .iter()
+            .map(|account| self.account_statement(account))
+            .collect()
+    }
+
+    /// Credits an accepted share of the given weight to `account` and the open round, or
+    /// journals it while the database is unavailable.
+    pub fn credit_share(&mut self, account: &str, weight: u64) -> MintResult<()> {
+        let _span = info_span!("mint_credit", account, weight).entered();
+        self.credit(Credit::Share {
+            account: account.to_string(),
+            weight,
+        })
+    }
+
+    /// Credits accepted shares, `(account, weight)`, in a single transaction, or journals them
+    /// while the database is unavailable.
+    pub fn credit_shares(&mut self, shares: &[(String, u64)]) -> MintResult<()> {
+        if !self.journal.is_empty() {
+            self.replay_journal()?;
+        }
+        if self.journal.is_empty() {
+            match self.db.credit_shares(shares) {
+                Ok(()) => {
+                    for (account, weight) in shares {
+                        self.credited_share(account, *weight);
+                    }
+                    return Ok(());
+                }
+                Err(MintError::Database(e)) => {
+                    warn!("Mint: journaling credits, the database failed: {}", e)
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        for (account, weight) in shares {
+            self.journal.push(Credit::Share {
+                account: account.clone(),
+                weight: *weight,
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Applies `credit`, after the credits journaled before it. Journals it instead if the
+    /// database fails, see `journal`.
+    pub fn credit(&mut self, credit: Credit) -> MintResult<()> {
+        if !self.journal.is_empty() {
+            self.replay_journal()?;
+        }
+        if self.journal.is_empty() {
+            match self.apply_credit(&credit) {
+                Err(MintError::Database(e)) => {
+                    warn!("Mint: journaling credits, the database failed: {}", e)
+                }
+                result => return result,
+            }
+        }
+        self.journal.push(credit)
+    }
+
+    /// Pings the database, returning how many credits wait in the journal for it.
+    pub fn database_health(&self) -> MintResult<usize> {
+        self.db.ping()?;
+        Ok(self.journal.len())
+    }
+
+    /// Replays the journaled credits in order until the database fails again, returning how
+    /// many were replayed.
+    pub fn replay_journal(&mut self) -> MintResult<usize> {
+        let mut replayed = 0;
+        for journaled in self.journal.iter() {
+            match self.db.replay_credit(journaled) {
+                Ok(_) => replayed += 1,
+                Err(MintError::Database(e)) => {
+                    debug!("Mint: journaled credits wait for the database: {}", e);
+                    break;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        let credits: Vec<_> = self
+            .journal
+            .iter()
+            .take(replayed)
+            .map(|journaled| journaled.credit.clone())
+            .collect();
+        self.journal.remove_replayed(replayed)?;
+        if self.journal.is_empty() && replayed > 0 {
+            self.db.clear_replays()?;
+            info!("Mint: replayed {} journaled credits", replayed);
+        }
+        for credit in credits {
+            match credit {
+                Credit::Share { account, weight } => self.credited_share(&account, weight),
+                Credit::Block {
+                    coinbase_txid,
+                    reward,
+                    ..
+                } => info!(
+                    "Mint: round closed by coinbase {} paying {} sat",
+                    coinbase_txid, reward
+                ),
+            }
+        }
+        Ok(replayed)
+    }
+
+    fn apply_credit(&mut self, credit: &Credit) -> MintResult<()> {
+        match credit {
+            Credit::Share { account, weight } => {
+                self.db.credit_share(account, *weight)?;
+                self.credited_share(account, *weight);
+                Ok(())
+            }
+            Credit::Block {
+                coinbase_txid,
+                reward,
+                finder,
+            } => self
+                .found_block(coinbase_txid, *reward, finder.as_deref())
+                .map(|_| ()),
+        }
+    }
+
+    fn credited_share(&mut self, account: &str, weight: u64) {
+        self.share_weights
+            .entry(account.to_string())
+            .and_modify(|average| {
+                *average =
+                    (*average * (SHARE_WEIGHT_SMOOTHING - 1) + weight) / SHARE_WEIGHT_SMOOTHING
+            })
+            .or_insert(weight);
+        debug!("Mint: credited {} to {}", weight, account);
+        self.notify(|| MintEvent::Balance(account.to_string()));
+    }
+
+    /// Closes the open round with a block found by the pool through a share of `finder`, its
+    /// reward becomes redeemable once `coinbase_txid` matures.
+    pub fn found_block(
+        &mut self,
+        coinbase_txid: &str,
+        reward: u64,
+        finder: Option<&str>,
+    ) -> MintResult<u64> {
+        let round_id = self.db.close_round(coinbase_txid, reward, finder)?;
+        info!(
+            "Mint: round {} closed by coinbase {} paying {} sat",
+            round_id, coinbase_txid, reward
+        );
+        Ok(round_id)
+    }
+
+    /// Rounds closed by a block whose reward isn't spendable yet.
+    pub fn immature_rounds(&self) -> MintResult<Vec<Round>> {
+        self.db.rounds(RoundState::Immature)
+    }
+
+    /// Rounds closed by a block found by the pool, newest first, see `MintDb::found_blocks`.
+    pub fn found_blocks(&self, offset: u64, limit: u64) -> MintResult<Vec<Round>> {
+        self.db.found_blocks(offset, limit)
+    }
+
+    /// Rounds `account` has shares in, newest first, see `MintDb::account_rounds`.
+    pub fn account_rounds(
+        &self,
+        account: &str,
+        offset: u64,
+        limit: u64,
+    ) -> MintResult<Vec<AccountRound>> {
+        self.db.account_rounds(account, offset, limit)
+    }
+
+    pub fn set_round_block(&self, round_id: u64, height: u64, block_hash: &str) -> MintResult<()> {
+        self.db.set_round_block(round_id, height, block_hash)
+    }
+
+    /// Settles the ehash of a matured round in sat. A mint not issuing sat has nothing to settle.
+    pub fn mature_round(&mut self, round_id: u64) -> MintResult<()> {
+        let settlement = self.db.mature_round(round_id, self.has_unit(SAT_UNIT))?;
+        let total: u64 = settlement.credited.iter().map(|(_, amount)| amount).sum();
+        info!(
+            "Mint: round {} matured, credited {} sat to {} accounts and reserved {} sat",
+            round_id,
+            total,
+            settlement.credited.len(),
+            settlement.reserved
+        );
+        for (account, _) in settlement.credited {
+            self.notify(|| MintEvent::Balance(account));
+        }
+        Ok(())
+    }
+
+    /// Cross-checks the shares of every round against what it credited, crediting the shares
+    /// never credited with `repair`, see `reconcile`.
+    pub fn reconcile(&mut self, repair: bool) -> MintResult<ReconcileReport> {
+        let sat = self.has_unit(SAT_UNIT);
+        let books = self.db.round_books()?;
+        let mut report = ReconcileReport {
+            rounds: books.len(),
+            ..Default::default()
+        };
+        for round in &books {
+            report.discrepancies.extend(reconcile::check(round, sat));
+        }
+        for discrepancy in &report.discrepancies {
+            warn!(
+                "Mint: round {} credited {} {} to {} for shares worth {}",
+                discrepancy.round_id,
+                discrepancy.credited,
+                discrepancy.unit,
+                discrepancy.account,
+                discrepancy.expected
+            );
+            if !repair || discrepancy.kind != DiscrepancyKind::UncreditedShares {
+                continue;
+            }
+            self.db.repair_round_credit(
+                discrepancy.round_id,
+                &discrepancy.account,
+                ledger(&discrepancy.unit)?,
+                discrepancy.missing(),
+            )?;
+            report.repaired += 1;
+            let account = discrepancy.account.clone();
+            self.notify(|| MintEvent::Balance(account));
+        }
+        if report.repaired > 0 {
+            info!(
+                "Mint: credited the shares of {} discrepancies",
+                report.repaired
+            );
+        }
+        Ok(report)
+    }
+
+    /// Reserve ehash tokens of matured rounds convert into sat from.
+    pub fn conversion(&self) -> MintResult<Conversion> {
+        self.db.conversion()
+    }
+
+    /// Redeems ehash `inputs` for sat `outputs` worth at most their share of the conversion
+    /// reserve. Whatever the outputs leave out stays in the reserve.
+    pub fn convert(
+        &mut self,
+        inputs: &[Proof],
+        outputs: &[BlindedMessage],
+    ) -> MintResult<Vec<BlindSignature>> {
+        let (ys, inputs_unit, ehash) = self.check_inputs(inputs)?;
+        let (outputs_unit, sat) = self.outputs_total(outputs)?;
+        if inputs_unit.as_deref().unwrap_or(EHASH_UNIT) != EHASH_UNIT {
+            return Err(MintError::UnsupportedUnit(inputs_unit.unwrap_or_default()));
+        }
+        if outputs_unit.as_deref().unwrap_or(SAT_UNIT) != SAT_UNIT {
+            return Err(MintError::UnsupportedUnit(outputs_unit.unwrap_or_default()));
+        }
+        let conversion = self.db.conversion()?;
+        let value = conversion
+            .value(ehash)
+            .ok_or(MintError::InsufficientBalance {
+                requested: ehash,
+                available: conversion.outstanding,
+            })?;
+        if sat > value {
+            return Err(MintError::UnbalancedTransaction {
+                inputs: value,
+                outputs: sat,
+            });
+        }
+        self.check_reserves(SAT_UNIT)?;
+        let signatures = self.sign_outputs(outputs)?;
+        let inputs: Vec<_> = ys.into_iter().zip(inputs).collect();
+        self.db
+            .convert(&inputs, ehash, sat, &signed(outputs, &signatures))?;
+        self.notify(|| spent_event(&inputs));
+        info!(
+            "Mint: converted {} {} into {} {}",
+            ehash, EHASH_UNIT, sat, SAT_UNIT
+        );
+        Ok(signatures)
+    }
+
+    /// Drops the reward of a round whose block left the best chain.
+    pub fn orphan_round(&mut self, round_id: u64) -> MintResult<()> {
+        self.db.orphan_round(round_id)?;
+        warn!("Mint: round {} orphaned, its reward is void", round_id);
+        Ok(())
+    }
+
+    /// Signs `outputs` and debits their total from `account`. Either every output is signed or
+    /// none is and the balance is left untouched.
+    pub fn withdraw(
+        &mut self,
+        account: &str,
+        outputs: &[BlindedMessage],
+    ) -> MintResult<Vec<BlindSignature>> {
+        self.issue(account, None, outputs)
+    }
+
+    fn issue(
+        &mut self,
+        account: &str,
+        quote_id: Option<&str>,
+        outputs: &[BlindedMessage],
+    ) -> MintResult<Vec<BlindSignature>> {
+        let (unit, total) = self.outputs_total(outputs)?;
+        let Some(unit) = unit else {
+            return Ok(vec![]);
+        };
+        let available = self.balance(account, &unit)?;
+        if total > available {
+            return Err(MintError::InsufficientBalance {
+                requested: total,
+                available,
+            });
+        }
+        self.check_reserves(&unit)?;
+        let signatures = self.sign_outputs(outputs)?;
+        self.db.issue(
+            ledger(&unit)?,
+            account,
+            total,
+            quote_id,
+            &signed(outputs, &signatures),
+        )?;
+        info!("Mint: {} withdrew {} {}", account, total, unit);
+        events::publish(Event::MintIssued {
+            account: account.to_string(),
+            unit: unit.clone(),
+            amount: total,
+        });
+        self.notify(|| MintEvent::Balance(account.to_string()));
+        Ok(signatures)
+    }
+
+    /// Debits `amount` of the `unit` balance of `account` for tokens another backend issued,
+    /// see `cdk_mint`.
+    pub fn record_issued(&mut self, account: &str, unit: &str, amount: u64) -> MintResult<()> {
+        self.db.issue(ledger(unit)?, account, amount, None, &[])?;
+        info!("Mint: {} withdrew {} {}", account, amount, unit);
+        events::publish(Event::MintIssued {
+            account: account.to_string(),
+            unit: unit.to_string(),
+            amount,
+        });
+        self.notify(|| MintEvent::Balance(account.to_string()));
+        Ok(())
+    }
+
+    /// Mints the whole balance of every account paid out automatically into a token held for
+    /// delivery, see `payout`.
+    pub fn pay_out_due(
+        &mut self,
+        config: &PayoutConfig,
+        mint_url: &str,
+    ) -> MintResult<Vec<Payout>> {
+        let mut payouts = vec![];
+        for (account, amount) in self.due_payouts(config)? {
+            match self.pay_out(&account, &config.unit, amount, mint_url) {
+                Ok(payout) => payouts.push(payout),
+                Err(e) => warn!("Mint: paying out {} failed: {}", account, e),
+            }
+        }
+        Ok(payouts)
+    }
+
+    /// Accounts paid out automatically with a balance due, and the amount to pay out.
+    pub fn due_payouts(&self, config: &PayoutConfig) -> MintResult<Vec<(String, u64)>> {
+        let amounts = self.active_keyset(&config.unit)?.amounts();
+        Ok(self
+            .db
+            .balances(ledger(&config.unit)?, config.min_amount.max(1))?
+            .into_iter()
+            .filter(|(account, _)| config.mode(account) != PayoutMode::Manual)
+            .map(|(account, balance)| {
+                let amount = match config.denominations {
+                    Denominations::ShareWeighted if config.unit == EHASH_UNIT => {
+                        let weight = self.share_weights.get(&account).copied().unwrap_or(1);
+                        payout::share_weighted_amount(balance, weight)
+                    }
+                    _ => balance,
+                };
+                (account, amounts::splittable(amount, &amounts))
+            })
+            .filter(|(_, amount)| *amount > 0)
+            .collect())
+    }
+
+    /// Debits `amount` of the `unit` balance of `account` for `token`, issued by another mint
+    /// (see `backend::PayoutIssuer`), and keeps the token until delivered.
+    pub fn record_payout(
+        &mut self,
+        account: &str,
+        unit: &str,
+        amount: u64,
+        token: &Token,
+    ) -> MintResult<Payout> {
+        let payout = Payout {
+            id: quote::random_id(),
+            account: account.to_string(),
+            unit: unit.to_string(),
+            amount,
+            token: token.encode()?,
+            created_at: now_secs(),
+            delivered_at: None,
+        };
+        self.db.issue_payout(ledger(unit)?, &payout, &[])?;
+        info!(
+            "Mint: paid out {} {} of another mint to {}",
+            amount, unit, account
+        );
+        events::publish(Event::PayoutComputed {
+            payout_id: payout.id.clone(),
+            account: payout.account.clone(),
+            unit: payout.unit.clone(),
+            amount,
+        });
+        Ok(payout)
+    }
+
+    /// Mints `amount` of the `unit` balance of `account` into a token, choosing the secrets
+    /// itself, and keeps it until delivered. The token is locked to the key of the account if it
+    /// registered one.
+    pub fn pay_out(
+        &mut self,
+        account: &str,
+        unit: &str,
+        amount: u64,
+        mint_url: &str,
+    ) -> MintResult<Payout> {
+        self.check_reserves(unit)?;
+        let secp = Secp256k1::new();
+        let keyset = self.active_keyset(unit)?;
+        let pubkey = self.db.account_pubkey(account)?;
+        let mut outputs = vec![];
+        let mut secrets = vec![];
+        for amount in keyset.split(amount)? {
+            let secret = match &pubkey {
+                Some(pubkey) => p2pk::lock_to(pubkey),
+                None => hex::encode(rand::random::<[u8; 32]>()),
+            };
+            let (blinded_secret, r) = dhke::blind_message(secret.as_bytes(), None)?;
+            outputs.push(BlindedMessage {
+                amount,
+                id: keyset.id.clone(),
+                blinded_secret,
+            });
+            secrets.push((secret, r));
+        }
+        let signatures = self.sign_outputs(&outputs)?;
+        let proofs = signatures
+            .iter()
+            .zip(secrets)
+            .map(|(signature, (secret, r))| {
+                let key = keyset.secret_key(signature.amount)?.public_key(&secp);
+                Ok(Proof {
+                    amount: signature.amount,
+                    id: signature.id.clone(),
+                    secret,
+                    signature: dhke::unblind_signature(&signature.blinded_signature, &r, &key)?,
+                    witness: None,
+                })
+            })
+            .collect::<MintResult<Vec<_>>>()?;
+        let payout = Payout {
+            id: quote::random_id(),
+            account: account.to_string(),
+            unit: unit.to_string(),
+            amount,
+            token: Token::new(mint_url, unit, proofs).encode()?,
+            created_at: now_secs(),
+            delivered_at: None,
+        };
+        self.db
+            .issue_payout(ledger(unit)?, &payout, &signed(&outputs, &signatures))?;
+        info!("Mint: paid out {} {} to {}", amount, unit, account);
+        events::publish(Event::MintIssued {
+            account: account.to_string(),
+            unit: unit.to_string(),
+            amount,
+        });
+        events::publish(Event::PayoutComputed {
+            payout_id: payout.id.clone(),
+            account: payout.account.clone(),
+            unit: payout.unit.clone(),
+            amount,
+        });
+        Ok(payout)
+    }
+
+    /// Issues `account` a new credential, revoking the one it had, see `credentials`.
+    pub fn issue_credential(&mut self, account: &str) -> MintResult<String> {
+        let credential = credentials::generate();
+        self.db
+            .set_account_credential(account, &credentials::digest(&credential))?;
+        info!("Mint: issued a credential to {}", account);
+        Ok(credential)
+    }
+
+    /// Refuses `credential` unless it is the one issued to `account`.
+    pub fn check_credential(&self, account: &str, credential: Option<&str>) -> MintResult<()> {
+        match (self.db.account_credential(account)?, credential) {
+            // digests of 32 random bytes, comparing them reveals nothing of the credential
+            (Some(digest), Some(credential)) if digest == credentials::digest(credential) => Ok(()),
+            _ => Err(MintError::Unauthorized(account.to_string())),
+        }
+    }
+
+    /// Binds the key payouts of `account` are locked to. A key already bound is only replaced
+    /// with its `signature` on the new one, see `credentials::verify_rebind`.
+    pub fn register_pubkey(
+        &mut self,
+        account: &str,
+        pubkey: &PublicKey,
+        signature: Option<&str>,
+    ) -> MintResult<()> {
+        match self.db.account_pubkey(account)? {
+            Some(bound) if bound == *pubkey => return Ok(()),
+            Some(bound) => credentials::verify_rebind(
+                credentials::PAYOUT_KEY,
+                account,
+                &pubkey.to_string(),
+                &bound.x_only_public_key().0,
+                signature,
+            )?,
+            None => (),
+        }
+        self.db.set_account_pubkey(account, pubkey)?;
+        info!("Mint: {} registered key {}", account, pubkey);
+        Ok(())
+    }
+
+    pub fn account_pubkey(&self, account: &str) -> MintResult<Option<PublicKey>> {
+        self.db.account_pubkey(account)
+    }
+
+    /// Binds the Nostr key payouts of `account` are sent to, see `nostr`. Like the key payouts
+    /// are locked to, a key already bound is only replaced with its `signature` on the new one.
+    pub fn register_nostr_key(
+        &mut self,
+        account: &str,
+        pubkey: &XOnlyPublicKey,
+        signature: Option<&str>,
+    ) -> MintResult<()> {
+        match self.db.nostr_key(account)? {
+            Some(bound) if bound == *pubkey => return Ok(()),
+            Some(bound) => credentials::verify_rebind(
+                credentials::NOSTR_KEY,
+                account,
+                &pubkey.to_string(),
+                &bound,
+                signature,
+            )?,
+            None => (),
+        }
+        self.db.set_nostr_key(account, pubkey)?;
+        info!("Mint: {} registered Nostr key {}", account, pubkey);
+        Ok(())
+    }
+
+    pub fn nostr_key(&self, account: &str) -> MintResult<Option<XOnlyPublicKey>> {
+        self.db.nostr_key(account)
+    }
+
+    /// Payouts waiting for delivery, of every account.
+    pub fn undelivered_payouts(&self) -> MintResult<Vec<Payout>> {
+        self.db.undelivered_payouts(None)
+    }
+
+    pub fn mark_payout_delivered(&self, id: &str) -> MintResult<()> {
+        self.db.mark_payout_delivered(id)
+    }
+
+    /// Hands out the payouts held for `account`, each one only once.
+    pub fn pick_up_payouts(&mut self, account: &str) -> MintResult<Vec<Payout>> {
+        let payouts = self.db.undelivered_payouts(Some(account))?;
+        for payout in &payouts {
+            self.db.mark_payout_delivered(&payout.id)?;
+        }
+        Ok(payouts)
+    }
+
+    /// Queues `outputs` to be signed for `account` as soon as its balance covers them, so miners
+    /// get tokens for their shares without asking for each withdrawal.
+    pub fn queue_outputs(&mut self, account: &str, outputs: &[BlindedMessage]) -> MintResult<()> {
+        self.outputs_total(outputs)?;
+        let mut seen = HashSet::with_capacity(outputs.len());
+        for output in outputs {
+            if !seen.insert(output.blinded_secret) {
+                return Err(MintError::DuplicateInputs);
+            }
+        }
+        self.db.queue_outputs(account, outputs)?;
+        debug!("Mint: queued {} outputs for {}", outputs.len(), account);
+        Ok(())
+    }
+
+    /// Signs queued outputs, oldest first, for every account whose balance covers them. An
+    /// account's outputs are signed in order, so one too large holds back the ones after it.
+    /// Signs at most `limit` outputs per call to keep the mint lock short. Returns how many were
+    /// signed.
+    pub fn issue_queued(&mut self, limit: usize) -> MintResult<usize> {
+        let mut by_account: Vec<(String, String, Vec<BlindedMessage>)> = vec![];
+        let mut stale = vec![];
+        for (account, output) in self.db.queued_outputs()? {
+            let unit = match self.keysets.info(&output.id) {
+                Some(info) if info.state == KeysetState::Active => info.unit.clone(),
+                _ => {
+                    // queued before a keyset rotation, the wallet has to queue it again
+                    stale.push(output.blinded_secret);
+                    continue;
+                }
+            };
+            match by_account
+                .iter_mut()
+                .find(|(a, u, _)| *a == account && *u == unit)
+            {
+                Some((_, _, outputs)) => outputs.push(output),
+                None => by_account.push((account, unit, vec![output])),
+            }
+        }
+        if !stale.is_empty() {
+            warn!("Mint: dropping {} outputs of inactive keysets", stale.len());
+            self.db.unqueue_outputs(&stale)?;
+        }
+        let mut issued = 0;
+        for (account, unit, outputs) in by_account {
+            let mut available = self.balance(&account, &unit)?;
+            let mut batch = vec![];
+            for output in outputs {
+                if issued + batch.len() >= limit || output.amount > available {
+                    break;
+                }
+                available -= output.amount;
+                batch.push(output);
+            }
+            if batch.is_empty() {
+                continue;
+            }
+            match self.issue(&account, None, &batch) {
+                Ok(_) => issued += batch.len(),
+                Err(e) => warn!("Mint: issuing queued outputs of {} failed: {}", account, e),
+            }
+        }
+        Ok(issued)
+    }
+
+    /// Signatures issued for `outputs`, in the same order, `None` where an output isn't signed
+    /// yet.
+    pub fn signatures(
+        &self,
+        outputs: &[BlindedMessage],
+    ) -> MintResult<Vec<Option<BlindSignature>>> {
+        outputs
+            .iter()
+            .map(|output| {
+                let Some(mut signature) = self.db.signature(&output.blinded_secret)? else {
+                    return Ok(None);
+                };
+                // proofs aren't stored, a new one is as good
+                signature.dleq = Some(self.dleq(&signature, &output.blinded_secret)?);
+                Ok(Some(signature))
+            })
+            .collect()
+    }
+
+    /// Creates a NUT-04 quote to mint `amount` from the `unit` balance of `account`.
+    pub fn create_mint_quote(
+        &mut self,
+        account: &str,
+        amount: u64,
+        unit: &str,
+    ) -> MintResult<MintQuoteResponse> {
+        if !self.has_unit(unit) {
+            return Err(MintError::UnsupportedUnit(unit.to_string()));
+        }
+        if amount == 0 {
+            return Err(MintError::UnsupportedAmount(amount));
+        }
+        // an amount the active keyset can't make could never be issued
+        self.active_keyset(unit)?.split(amount)?;
+        let now = now_secs();
+        self.db.delete_expired_quotes(now)?;
+        let quote = MintQuote::new(account, amount, unit, now);
+        self.db.insert_quote(&quote)?;
+        self.quote_response(&quote)
+    }
+
+    pub fn mint_quote(&self, id: &str) -> MintResult<MintQuoteResponse> {
+        match self.db.quote(id)? {
+            Some(quote) => self.quote_response(&quote),
+            None => Err(MintError::UnknownQuote(id.to_string())),
+        }
+    }
+
+    fn quote_response(&self, quote: &MintQuote) -> MintResult<MintQuoteResponse> {
+        let state = if quote.issued {
+            MintQuoteState::Issued
+        } else if self.balance(&quote.account, &quote.unit)? >= quote.amount {
+            MintQuoteState::Paid
+        } else {
+            MintQuoteState::Unpaid
+        };
+        Ok(MintQuoteResponse {
+            quote: quote.id.clone(),
+            request: quote.account.clone(),
+            amount: quote.amount,
+            unit: quote.unit.clone(),
+            state,
+            expiry: quote.expiry,
+        })
+    }
+
+    /// Signs the outputs of a paid quote, which must add up to the quoted amount.
+    pub fn mint(
+        &mut self,
+        quote_id: &str,
+        outputs: &[BlindedMessage],
+    ) -> MintResult<Vec<BlindSignature>> {
+        let quote = self
+            .db
+            .quote(quote_id)?
+            .ok_or_else(|| MintError::UnknownQuote(quote_id.to_string()))?;
+        if quote.issued {
+            return Err(MintError::QuoteAlreadyIssued(quote.id));
+        }
+        if quote.is_expired(now_secs()) {
+            return Err(MintError::QuoteExpired(quote.id));
+        }
+        let (unit, total) = self.outputs_total(outputs)?;
+        if total != quote.amount {
+            return Err(MintError::UnbalancedTransaction {
+                inputs: quote.amount,
+                outputs: total,
+            });
+        }
+        if unit.as_deref() != Some(quote.unit.as_str()) {
+            return Err(MintError::MixedUnits);
+        }
+        if self.balance(&quote.account, &quote.unit)? < quote.amount {
+            return Err(MintError::QuoteNotPaid(quote.id));
+        }
+        let signatures = self.issue(&quote.account, Some(&quote.id), outputs)?;
+        let response = self.quote_response(&MintQuote {
+            issued: true,
+            ..quote
+        })?;
+        self.notify(|| MintEvent::MintQuote(response));
+        Ok(signatures)
+    }
+
+    /// Redeems `inputs` for new tokens of the same total value less the fee (NUT-03).
+    pub fn swap(
+        &mut self,
+        inputs: &[Proof],
+        outputs: &[BlindedMessage],
+    ) -> MintResult<Vec<BlindSignature>> {
+        let (ys, inputs_unit, inputs_total) = self.check_inputs(inputs)?;
+        let (outputs_unit, outputs_total) = self.outputs_total(outputs)?;
+        let fee = self.fee(Operation::Swap, inputs, outputs.len());
+        if inputs_total != outputs_total.saturating_add(fee) {
+            return Err(MintError::UnbalancedTransaction {
+                inputs: inputs_total,
+                outputs: outputs_total.saturating_add(fee),
+            });
+        }
+        if inputs_unit != outputs_unit {
+            return Err(MintError::MixedUnits);
+        }
+        if self.epochs.older == OlderEpochs::Convert {
+            self.check_current_epoch(inputs)?;
+        }
+        let unit = inputs_unit.unwrap_or_default();
+        let signatures = self.sign_outputs(outputs)?;
+        let inputs: Vec<_> = ys.into_iter().zip(inputs).collect();
+        self.db
+            .swap(&inputs, &signed(outputs, &signatures), &unit, fee)?;
+        self.notify(|| spent_event(&inputs));
+        debug!(
+            "Mint: swapped {} {} for a fee of {}",
+            inputs_total, unit, fee
+        );
+        Ok(signatures)
+    }
+
+    /// Creates a NUT-05 quote to pay `invoice`. Invoices are paid in sat, so only sat tokens can
+    /// melt.
+    pub fn create_melt_quote(
+        &mut self,
+        request: &str,
+        unit: &str,
+        invoice: &DecodedInvoice,
+        config: &LightningConfig,
+    ) -> MintResult<MeltQuoteResponse> {
+        if unit != SAT_UNIT || !self.has_unit(SAT_UNIT) {
+            return Err(MintError::UnsupportedUnit(unit.to_string()));
+        }
+        // amountless invoices are not supported
+        let amount = invoice.amount_msat.div_ceil(1000);
+        if amount == 0 {
+            return Err(MintError::UnsupportedAmount(amount));
+        }
+        let quote = MeltQuote::new(
+            request,
+            &invoice.payment_hash,
+            amount,
+            config.fee_reserve(amount),
+            now_secs(),
+        );
+        self.db.insert_melt_quote(&quote)?;
+        Ok(melt_quote_response(&quote, None))
+    }
+
+    pub fn melt_quote(&self, id: &str) -> MintResult<MeltQuoteResponse> {
+        match self.db.melt_quote(id)? {
+            Some(quote) => Ok(melt_quote_response(&quote, None)),
+            None => Err(MintError::UnknownQuote(id.to_string())),
+        }
+    }
+
+    pub fn pending_melt_quotes(&self) -> MintResult<Vec<MeltQuote>> {
+        self.db.pending_melt_quotes()
+    }
+
+    /// Reserves `inputs` for paying the quote, they must cover its amount, fee reserve and the
+    /// melt fee. `outputs` are the blank outputs the change will be signed on.
+    pub fn begin_melt(
+        &mut self,
+        quote_id: &str,
+        inputs: &[Proof],
+        outputs: &[BlindedMessage],
+    ) -> MintResult<MeltQuote> {
+        let quote = self.unpaid_melt_quote(quote_id, BOLT11_METHOD)?;
+        let (ys, _, input_fee) = self.check_melt(&quote, inputs, outputs)?;
+        let inputs: Vec<_> = ys.into_iter().zip(inputs).collect();
+        self.db.begin_melt(&quote.id, &inputs, input_fee)?;
+        let quote = MeltQuote {
+            state: MeltQuoteState::Pending,
+            input_fee,
+            ..quote
+        };
+        self.notify(|| proofs_event(inputs.iter().map(|(y, _)| *y), SpendState::Pending));
+        self.notify(|| MintEvent::MeltQuote(melt_quote_response(&quote, None)));
+        Ok(quote)
+    }
+
+    /// Creates a quote to pay `amount` to `address` in the next on-chain batch. The payout is the
+    /// amount less the fee of `config`.
+    pub fn create_onchain_melt_quote(
+        &mut self,
+        address: &str,
+        amount: u64,
+        unit: &str,
+        config: &OnchainConfig,
+    ) -> MintResult<MeltQuoteResponse> {
+        if unit != SAT_UNIT || !self.has_unit(SAT_UNIT) {
+            return Err(MintError::UnsupportedUnit(unit.to_string()));
+        }
+        config.check_address(address)?;
+        config.payout_amount(amount)?;
+        let quote = MeltQuote::onchain(address, amount, now_secs());
+        self.db.insert_melt_quote(&quote)?;
+        Ok(melt_quote_response(&quote, None))
+    }
+
+    /// Spends `inputs` for the on-chain melt `quote_id` and queues its payout. The change is
+    /// signed on `outputs` right away, the quote stays pending until its batch is sent.
+    pub fn melt_onchain(
+        &mut self,
+        quote_id: &str,
+        inputs: &[Proof],
+        outputs: &[BlindedMessage],
+        config: &OnchainConfig,
+    ) -> MintResult<MeltQuoteResponse> {
+        let quote = self.unpaid_melt_quote(quote_id, ONCHAIN_METHOD)?;
+        let (ys, inputs_total, input_fee) = self.check_melt(&quote, inputs, outputs)?;
+        let payout = OnchainPayout {
+            quote_id: quote.id.clone(),
+            address: quote.request.clone(),
+            amount: config.payout_amount(quote.amount)?,
+        };
+        let change = inputs_total - quote.amount - input_fee;
+        let outputs = self.change_outputs(change, outputs)?;
+        let change = self.sign_outputs(&outputs)?;
+        let inputs: Vec<_> = ys.into_iter().zip(inputs).collect();
+        self.db.queue_onchain_melt(
+            &payout,
+            &inputs,
+            &signed(&outputs, &change),
+            input_fee,
+            SAT_UNIT,
+        )?;
+        self.notify(|| spent_event(&inputs));
+        info!(
+            "Mint: queued on-chain payout of {} {} to {} for quote {}",
+            payout.amount, SAT_UNIT, payout.address, quote.id
+        );
+        let quote = MeltQuote {
+            state: MeltQuoteState::Pending,
+            input_fee,
+            ..quote
+        };
+        let response = melt_quote_response(&quote, Some(change));
+        self.notify(|| MintEvent::MeltQuote(response.clone()));
+        Ok(response)
+    }
+
+    /// On-chain payouts waiting for a batch.
+    pub fn onchain_payouts(&self) -> MintResult<Vec<OnchainPayout>> {
+        self.db.onchain_payouts()
+    }
+
+    /// Records `batch` as paying the payouts of `quote_ids`, before it is broadcast.
+    pub fn record_onchain_batch(
+        &mut self,
+        batch: &OnchainBatch,
+        quote_ids: &[String],
+    ) -> MintResult<()> {
+        self.db.record_onchain_batch(batch, quote_ids)
+    }
+
+    /// Batches recorded but maybe not broadcast, e.g. after a crash.
+    pub fn unsent_onchain_batches(&self) -> MintResult<Vec<OnchainBatch>> {
+        self.db.unsent_onchain_batches()
+    }
+
+    /// Settles the melts paid by the broadcast batch `txid`.
+    pub fn settle_onchain_batch(&mut self, txid: &str) -> MintResult<()> {
+        for quote_id in self.db.settle_onchain_batch(txid)? {
+            if let Some(quote) = self.db.melt_quote(&quote_id)? {
+                self.notify(|| MintEvent::MeltQuote(melt_quote_response(&quote, None)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Settles a melt once the node reported on its payment: a paid melt spends the reserved
+    /// inputs and returns what is left of the fee reserve on `outputs`, a failed one releases
+    /// them, and a pending one stays as it is.
+    pub fn finish_melt(
+        &mut self,
+        quote_id: &str,
+        status: &PaymentStatus,
+        outputs: &[BlindedMessage],
+    ) -> MintResult<MeltQuoteResponse> {
+        let mut quote = self
+            .db
+            .melt_quote(quote_id)?
+            .ok_or_else(|| MintError::UnknownQuote(quote_id.to_string()))?;
+        if quote.state != MeltQuoteState::Pending {
+            return Ok(melt_quote_response(&quote, None));
+        }
+        match status {
+            PaymentStatus::Paid { preimage, fee_msat } => {
+                let inputs_total = self.db.pending_total(&quote.id)?;
+                let change = inputs_total
+                    .saturating_sub(quote.amount)
+                    .saturating_sub(fee_msat.div_ceil(1000))
+                    .saturating_sub(quote.input_fee);
+                let outputs = self.change_outputs(change, outputs)?;
+                let change = self.sign_outputs(&outputs)?;
+                let ys = self.db.reserved(&quote.id);
+                self.db
+                    .complete_melt(&quote.id, preimage, &signed(&outputs, &change), SAT_UNIT)?;
+                self.notify(|| proofs_event(ys, SpendState::Spent));
+                info!(
+                    "Mint: melted {} {} for quote {}",
+                    quote.amount, SAT_UNIT, quote.id
+                );
+                quote.state = MeltQuoteState::Paid;
+                quote.payment_preimage = Some(preimage.clone());
+                let response = melt_quote_response(&quote, Some(change));
+                self.notify(|| MintEvent::MeltQuote(response.clone()));
+                Ok(response)
+            }
+            PaymentStatus::Failed(reason) => {
+                warn!(
+                    "Mint: payment of melt quote {} failed: {}",
+                    quote.id, reason
+                );
+                let ys = self.db.reserved(&quote.id);
+                self.db.release_melt(&quote.id)?;
+                quote.state = MeltQuoteState::Unpaid;
+                self.notify(|| proofs_event(ys, SpendState::Unspent));
+                let response = melt_quote_response(&quote, None);
+                self.notify(|| MintEvent::MeltQuote(response.clone()));
+                Ok(response)
+            }
+            PaymentStatus::Pending => Ok(melt_quote_response(&quote, None)),
+        }
+    }
+
+    /// NUT-07 states of the proofs `ys`.
+    pub fn proof_states(&self, ys: &[PublicKey]) -> MintResult<Vec<ProofState>> {
+        ys.iter()
+            .map(|y| {
+                Ok(ProofState {
+                    y: *y,
+                    state: self.db.proof_state(y)?,
+                    witness: None,
+                })
+            })
+            .collect()
+    }
+
+    /// NUT-02 input fee of keyset `id`, in parts per thousand.
+    pub fn input_fee_ppk(&self, id: &str) -> u64 {
+        self.fees.input_fee_ppk(id)
+    }
+
+    /// Fees collected so far, per unit and operation.
+    pub fn fees_collected(&self) -> MintResult<Vec<FeeTotal>> {
+        self.db.fees()
+    }
+
+    pub fn report(&self) -> MintResult<MintReport> {
+        Ok(MintReport {
+            keysets: self.keyset_infos(),
+            signatures: self.db.signature_count()?,
+            spent_proofs: self.db.spent_count()?,
+            fees: self.db.fees()?,
+            spent: self.db.spent_report(),
+            reserves: self.reserve_report()?,
+            journaled_credits: self.journal.len(),
+        })
+    }
+
+    /// How the sat owed is backed by matured and immature rewards, `None` for a mint not issuing
+    /// sat.
+    pub fn reserve_report(&self) -> MintResult<Option<ReserveReport>> {
+        let books = audit::audit(&self.db, &self.keyset_infos(), &self.units)?;
+        let immature = self
+            .db
+            .rounds(RoundState::Immature)?
+            .iter()
+            .filter_map(|round| round.reward)
+            .sum();
+        Ok(books.sat.map(|sat| ReserveReport::new(&sat, immature)))
+    }
+
+    /// Checks the reserves allow issuing tokens of `unit`, only sat ones being limited.
+    fn check_reserves(&self, unit: &str) -> MintResult<()> {
+        if unit != SAT_UNIT || self.reserves.min_ratio_ppk.is_none() {
+            return Ok(());
+        }
+        match self.reserve_report()? {
+            Some(reserves) => self.reserves.check(&reserves),
+            None => Ok(()),
+        }
+    }
+
+    /// Melt quote `quote_id` of `method`, if it can still be melted.
+    fn unpaid_melt_quote(&self, quote_id: &str, method: &str) -> MintResult<MeltQuote> {
+        let quote = self
+            .db
+            .melt_quote(quote_id)?
+            .filter(|quote| quote.method == method)
+            .ok_or_else(|| MintError::UnknownQuote(quote_id.to_string()))?;
+        match quote.state {
+            MeltQuoteState::Paid => Err(MintError::QuoteAlreadyIssued(quote.id)),
+            MeltQuoteState::Pending => Err(MintError::QuotePending(quote.id)),
+            MeltQuoteState::Unpaid if quote.is_expired(now_secs()) => {
+                Err(MintError::QuoteExpired(quote.id))
+            }
+            MeltQuoteState::Unpaid => Ok(quote),
+        }
+    }
+
+    /// Checks `inputs` cover the amount, fee reserve and melt fee of `quote`, and that the blank
+    /// change `outputs` are sat. Returns the `Y`s and total of the inputs, and the melt fee.
+    fn check_melt(
+        &self,
+        quote: &MeltQuote,
+        inputs: &[Proof],
+        outputs: &[BlindedMessage],
+    ) -> MintResult<(Vec<PublicKey>, u64, u64)> {
+        let (ys, inputs_unit, inputs_total) = self.check_inputs(inputs)?;
+        // blank outputs carry no meaningful amount, only their keyset is checked
+        let (outputs_unit, _) = self.outputs_total(&blank(outputs))?;
+        if inputs_unit.as_deref() != Some(SAT_UNIT) {
+            return Err(MintError::UnsupportedUnit(inputs_unit.unwrap_or_default()));
+        }
+        if outputs_unit.as_deref().unwrap_or(SAT_UNIT) != SAT_UNIT {
+            return Err(MintError::MixedUnits);
+        }
+        let input_fee = self.fee(Operation::Melt, inputs, outputs.len());
+        let needed = quote
+            .amount
+            .saturating_add(quote.fee_reserve)
+            .saturating_add(input_fee);
+        if inputs_total < needed {
+            return Err(MintError::UnbalancedTransaction {
+                inputs: inputs_total,
+                outputs: needed,
+            });
+        }
+        Ok((ys, inputs_total, input_fee))
+    }
+
+    fn fee(&self, operation: Operation, inputs: &[Proof], outputs: usize) -> u64 {
+        self.fees.fee(
+            operation,
+            inputs.iter().map(|proof| proof.id.as_str()),
+            outputs,
+        )
+    }
+
+    /// Verifies `inputs` can be redeemed, returning their `Y`s (in order), unit and total amount.
+    /// All inputs must be of the same unit.
+    fn check_inputs(&self, inputs: &[Proof]) -> MintResult<(Vec<PublicKey>, Option<String>, u64)> {
+        let mut ys = Vec::with_capacity(inputs.len());
+        let mut seen = HashSet::with_capacity(inputs.len());
+        let mut unit: Option<String> = None;
+        let mut total: u64 = 0;
+        for proof in inputs {
+            self.verify_proof(proof)?;
+            p2pk::verify(&proof.secret, proof.witness.as_deref(), now_secs())?;
+            self.check_unit(&mut unit, &proof.id)?;
+            let y = dhke::hash_to_curve(proof.secret.as_bytes())?;
+            if self.db.is_spent(&y)? {
+                warn!("Mint: attempted double spend of proof {}", y);
+                metrics::record_double_spend(&proof.id);
+                return Err(MintError::ProofAlreadySpent);
+            }
+            if self.db.is_pending(&y)? {
+                return Err(MintError::ProofPending);
+            }
+            if !seen.insert(y) {
+                return Err(MintError::DuplicateInputs);
+            }
+            ys.push(y);
+            total = total
+                .checked_add(proof.amount)
+                .ok_or(MintError::UnsupportedAmount(proof.amount))?;
+        }
+        Ok((ys, unit, total))
+    }
+
+    /// Checks no input is of an older epoch than the active keyset of its unit.
+    fn check_current_epoch(&self, inputs: &[Proof]) -> MintResult<()> {
+        for proof in inputs {
+            let Some(info) = self.keysets.info(&proof.id) else {
+                return Err(MintError::UnknownKeyset(proof.id.clone()));
+            };
+            let current = self
+                .keysets
+                .active_info(&info.unit)
+                .and_then(|active| active.epoch);
+            let epoch = |epoch: Option<Epoch>| epoch.map(|epoch| epoch.number);
+            if epoch(current) > epoch(info.epoch) {
+                return Err(MintError::InactiveKeyset(proof.id.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Unit and total amount of `outputs`, all of which must be for the active keyset of the same
+    /// unit.
+    fn outputs_total(&self, outputs: &[BlindedMessage]) -> MintResult<(Option<String>, u64)> {
+        let mut unit: Option<String> = None;
+        let mut total: u64 = 0;
+        for output in outputs {
+            match self.keysets.info(&output.id) {
+                Some(info) if info.state == KeysetState::Active => {}
+                // only active keysets sign, outputs for any other are stale
+                Some(_) => return Err(MintError::InactiveKeyset(output.id.clone())),
+                None => return Err(MintError::UnknownKeyset(output.id.clone())),
+            }
+            self.check_unit(&mut unit, &output.id)?;
+            // blank outputs (see `blank`) only get their amount once signed
+            if output.amount > 0 {
+                self.keysets
+                    .for_verification(&output.id)?
+                    .secret_key(output.amount)?;
+            }
+            if self.db.is_signed(&output.blinded_secret)? {
+                return Err(MintError::OutputAlreadySigned);
+            }
+            total = total
+                .checked_add(output.amount)
+                .ok_or(MintError::UnsupportedAmount(output.amount))?;
+        }
+        Ok((unit, total))
+    }
+
+    /// Checks keyset `id` is of `unit`, setting it on the first call.
+    fn check_unit(&self, unit: &mut Option<String>, id: &str) -> MintResult<()> {
+        let keyset_unit = match self.keysets.info(id) {
+            Some(info) => &info.unit,
+            None => return Err(MintError::UnknownKeyset(id.to_string())),
+        };
+        match unit {
+            Some(unit) if unit != keyset_unit => Err(MintError::MixedUnits),
+            Some(_) => Ok(()),
+            None => {
+                *unit = Some(keyset_unit.clone());
+                Ok(())
+            }
+        }
+    }
+
+    /// Blank `outputs` (NUT-08) taking the `change` of a melt, in amounts their keyset signs,
+    /// smallest first.
+    /// Change the keyset can't make, or that doesn't fit on the outputs, stays with the mint.
+    fn change_outputs(
+        &self,
+        change: u64,
+        outputs: &[BlindedMessage],
+    ) -> MintResult<Vec<BlindedMessage>> {
+        let Some(first) = outputs.first() else {
+            return Ok(vec![]);
+        };
+        let amounts = self.keysets.for_verification(&first.id)?.amounts();
+        Ok(
+            amounts::split(amounts::splittable(change, &amounts), &amounts)?
+                .into_iter()
+                .rev()
+                .zip(outputs)
+                .map(|(amount, output)| BlindedMessage {
+                    amount,
+                    ..output.clone()
+                })
+                .collect(),
+        )
+    }
+
+    /// Signs `outputs` with the keysets they name, which `outputs_total` checked.
+    fn sign_outputs(&self, outputs: &[BlindedMessage]) -> MintResult<Vec<BlindSignature>> {
+        outputs
+            .iter()
+            .map(|output| {
+                let keyset = self.keysets.for_verification(&output.id)?;
+                let key = keyset.secret_key(output.amount)?;
+                let mut signature = BlindSignature {
+                    amount: output.amount,
+                    id: keyset.id.clone(),
+                    blinded_signature: dhke::sign_message(key, &output.blinded_secret)?,
+                    dleq: None,
+                };
+                signature.dleq = Some(self.dleq(&signature, &output.blinded_secret)?);
+                Ok(signature)
+            })
+            .collect()
+    }
+
+    /// NUT-12 proof that `signature` of `blinded_message` was made with the published key.
+    fn dleq(
+        &self,
+        signature: &BlindSignature,
+        blinded_message: &PublicKey,
+    ) -> MintResult<DleqProof> {
+        let key = self
+            .keysets
+            .for_verification(&signature.id)?
+            .secret_key(signature.amount)?;
+        let (e, s) = dhke::sign_dleq(key, blinded_message, &signature.blinded_signature)?;
+        Ok(DleqProof { e, s })
+    }
+
+    /// Checks that `proof` carries a valid signature from this mint, under the active keyset or
+    /// any deprecated one.
+    pub fn verify_proof(&self, proof: &Proof) -> MintResult<()> {
+        let key = self
+            .keysets
+            .for_verification(&proof.id)?
+            .secret_key(proof.amount)?;
+        match dhke::verify_message(key, &proof.signature, proof.secret.as_bytes())? {
+            true => Ok(()),
+            false => Err(MintError::InvalidProof),
+        }
+    }
+}
+
+/// Balances tokens of `unit` are issued from.
+#[cfg(feature = "mint")]
+fn ledger(unit: &str) -> MintResult<Ledger> {
+    match unit {
+        EHASH_UNIT => Ok(Ledger::Ehash),
+        SAT_UNIT => Ok(Ledger::Sat),
+        _ => Err(MintError::UnsupportedUnit(unit.to_string())),
+    }
+}
+
+#[cfg(feature = "mint")]
+fn check_units(units: &[String]) -> MintResult<()> {
+    for unit in units {
+        ledger(unit)?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "mint")]
+fn melt_quote_response(
+    quote: &MeltQuote,
+    change: Option<Vec<BlindSignature>>,
+) -> MeltQuoteResponse {
+    MeltQuoteResponse {
+        quote: quote.id.clone(),
+        amount: quote.amount,
+        fee_reserve: quote.fee_reserve,
+        state: quote.state,
+        expiry: quote.expiry,
+        payment_preimage: quote.payment_preimage.clone(),
+        change,
+    }
+}
+
+#[cfg(feature = "mint")]
+fn blank(outputs: &[BlindedMessage]) -> Vec<BlindedMessage> {
+    outputs
+        .iter()
+        .map(|output| BlindedMessage {
+            amount: 0,
+            ..output.clone()
+        })
+        .collect()
+}
+
+/// Pairs every signature with the blinded secret it signs, as recorded in the database.
+#[cfg(feature = "mint")]
+fn signed(
+    outputs: &[BlindedMessage],
+    signatures: &[BlindSignature],
+) -> Vec<(PublicKey, BlindSignature)> {
+    outputs
+        .iter()
+        .map(|output| output.blinded_secret)
+        .zip(signatures.iter().cloned())
+        .collect()
+}
+
+/// The event of redeemed `inputs` being spent, with the witnesses they came with.
+#[cfg(feature = "mint")]
+fn spent_event(inputs: &[(PublicKey, &Proof)]) -> MintEvent {
+    MintEvent::Proofs(
+        inputs
+            .iter()
+            .map(|(y, proof)| ProofState {
+                y: *y,
+                state: SpendState::Spent,
+                witness: proof.witness.clone(),
+            })
+            .collect(),
+    )
+}
+
+#[cfg(feature = "mint")]
+fn proofs_event(ys: impl IntoIterator<Item = PublicKey>, state: SpendState) -> MintEvent {
+    MintEvent::Proofs(
+        ys.into_iter()
+            .map(|y| ProofState {
+                y,
+                state,
+                witness: None,
+            })
+            .collect(),
+    )
+}
+
+/// Weight of a share found at `target` (little endian), i.e. its difficulty relative to the
+/// difficulty 1 target. Harder shares are worth proportionally more ehash.
+#[cfg(feature = "mint")]
+pub fn share_weight(target: &[u8]) -> u64 {
+    if target.iter().all(|b| *b == 0) {
+        return 0;
+    }
+    let mut target = target.to_vec();
+    target.reverse();
+    let target = match Uint256::from_be_slice(&target) {
+        Ok(target) => target,
+        Err(_) => return 0,
+    };
+    let pdiff: [u8; 32] = [
+        0, 0, 0, 0, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255,
+        255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255,
+    ];
+    let pdiff = Uint256::from_be_bytes(pdiff);
+    if pdiff > target {
+        (pdiff / target).low_u64()
+    } else {
+        // shares easier than difficulty 1 still count for something
+        1
+    }
+}
+
+#[cfg(all(test, feature = "mint"))]
+mod test {
+    use super::*;
+    use accounts::AccountLedger;
+    use secp256k1::SecretKey;
+
+    fn mint() -> Mint {
+        mint_with_units(&[EHASH_UNIT, SAT_UNIT])
+    }
+
+    fn mint_with_units(units: &[&str]) -> Mint {
+        let config = MintConfig::new(
+            units.iter().map(|unit| unit.to_string()).collect(),
+            "".into(),
+            "".into(),
+            "".into(),
+            8,
+            None,
+            "".into(),
+            None,
+        );
+        Mint::from_master_secret(&[1; 32], &config).unwrap()
+    }
+
+    #[test]
+    fn withdraws_accrued_balance() {
+        let mut mint = mint();
+        mint.credit_share("alice", 10).unwrap();
+        let id = mint.active_keyset(EHASH_UNIT).unwrap().id.clone();
+        let secrets = ["a", "b"];
+        let blinded: Vec<_> = secrets
+            .iter()
+            .map(|s| dhke::blind_message(s.as_bytes(), None).unwrap())
+            .collect();
+        let outputs: Vec<_> = [8, 2]
+            .iter()
+            .zip(&blinded)
+            .map(|(amount, (b, _))| BlindedMessage {
+                amount: *amount,
+                id: id.clone(),
+                blinded_secret: *b,
+            })
+            .collect();
+
+        let signatures = mint.withdraw("alice", &outputs).unwrap();
+        assert_eq!(mint.balance("alice", EHASH_UNIT).unwrap(), 0);
+
+        let secp = Secp256k1::new();
+        let mut proofs = vec![];
+        for ((signature, (_, r)), secret) in signatures.iter().zip(&blinded).zip(secrets) {
+            let mint_key = mint
+                .active_keyset(EHASH_UNIT)
+                .unwrap()
+                .secret_key(signature.amount)
+                .unwrap()
+                .public_key(&secp);
+            let proof = Proof {
+                amount: signature.amount,
+                id: id.clone(),
+                secret: secret.to_string(),
+                signature: dhke::unblind_signature(&signature.blinded_signature, r, &mint_key)
+                    .unwrap(),
+                witness: None,
+            };
+            mint.verify_proof(&proof).unwrap();
+            proofs.push(proof);
+        }
+
+        // proofs of a deprecated keyset stay valid, but it no longer signs
+        mint.rotate_keyset(EHASH_UNIT).unwrap();
+        assert_ne!(mint.active_keyset(EHASH_UNIT).unwrap().id, id);
+        for proof in &proofs {
+            mint.verify_proof(proof).unwrap();
+        }
+        mint.credit_share("alice", 10).unwrap();
+        assert!(mint.withdraw("alice", &outputs).is_err());
+    }
+
+    fn outputs(mint: &Mint, unit: &str, amounts: &[u64]) -> (Vec<BlindedMessage>, Vec<SecretKey>) {
+        amounts
+            .iter()
+            .enumerate()
+            .map(|(i, amount)| {
+                let (b, r) = dhke::blind_message(format!("secret {}", i).as_bytes(), None).unwrap();
+                let output = BlindedMessage {
+                    amount: *amount,
+                    id: mint.active_keyset(unit).unwrap().id.clone(),
+                    blinded_secret: b,
+                };
+                (output, r)
+            })
+            .unzip()
+    }
+
+    fn unblind(mint: &Mint, signatures: &[BlindSignature], rs: Vec<SecretKey>) -> Vec<Proof> {
+        let secp = Secp256k1::new();
+        signatures
+            .iter()
+            .zip(rs)
+            .enumerate()
+            .map(|(i, (signature, r))| {
+                let key = mint
+                    .keysets()
+                    .for_verification(&signature.id)
+                    .unwrap()
+                    .secret_key(signature.amount)
+                    .unwrap()
+                    .public_key(&secp);
+                let secret = format!("secret {}", i);
+                // what a wallet checks before trusting the signature
+                let (blinded, _) = dhke::blind_message(secret.as_bytes(), Some(r)).unwrap();
+                let dleq = signature.dleq.as_ref().unwrap();
+                assert!(dhke::verify_dleq(
+                    &dleq.e,
+                    &dleq.s,
+                    &key,
+                    &blinded,
+                    &signature.blinded_signature
+                )
+                .unwrap());
+                Proof {
+                    amount: signature.amount,
+                    id: signature.id.clone(),
+                    secret,
+                    signature: dhke::unblind_signature(&signature.blinded_signature, &r, &key)
+                        .unwrap(),
+                    witness: None,
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn mints_quotes_and_swaps() {
+        let mut mint = mint();
+        let quote = mint.create_mint_quote("carol", 5, EHASH_UNIT).unwrap();
+        assert_eq!(quote.state, MintQuoteState::Unpaid);
+        mint.credit_share("carol", 6).unwrap();
+        assert_eq!(
+            mint.mint_quote(&quote.quote).unwrap().state,
+            MintQuoteState::Paid
+        );
+
+        let (too_much, _) = outputs(&mint, EHASH_UNIT, &[4, 2]);
+        assert!(mint.mint(&quote.quote, &too_much).is_err());
+        let (wrong_unit, _) = outputs(&mint, SAT_UNIT, &[4, 1]);
+        assert!(matches!(
+            mint.mint(&quote.quote, &wrong_unit),
+            Err(MintError::MixedUnits)
+        ));
+        let (minted, rs) = outputs(&mint, EHASH_UNIT, &[4, 1]);
+        let signatures = mint.mint(&quote.quote, &minted).unwrap();
+        assert_eq!(mint.balance("carol", EHASH_UNIT).unwrap(), 1);
+        assert_eq!(
+            mint.mint_quote(&quote.quote).unwrap().state,
+            MintQuoteState::Issued
+        );
+        assert!(mint.mint(&quote.quote, &minted).is_err());
+
+        let proofs = unblind(&mint, &signatures, rs);
+
+        let (unbalanced, _) = outputs(&mint, EHASH_UNIT, &[4]);
+        assert!(mint.swap(&proofs, &unbalanced).is_err());
+        let (other_unit, _) = outputs(&mint, SAT_UNIT, &[4, 1]);
+        assert!(matches!(
+            mint.swap(&proofs, &other_unit),
+            Err(MintError::MixedUnits)
+        ));
+        let (swapped, _) = outputs(&mint, EHASH_UNIT, &[2, 2, 1]);
+        assert_eq!(mint.swap(&proofs, &swapped).unwrap().len(), 3);
+        assert!(matches!(
+            mint.swap(&proofs, &swapped),
+            Err(MintError::ProofAlreadySpent)
+        ));
+    }
+
+    #[test]
+    fn settles_older_epochs_as_configured() {
+        let mut mint = mint();
+        let epoch = |number| Epoch {
+            number,
+            bits: 0x1d00ffff,
+        };
+        assert!(mint.enter_epoch(epoch(7)).unwrap().is_none());
+        mint.epochs = EpochConfig {
+            enabled: true,
+            older: OlderEpochs::Convert,
+        };
+        let first = mint.enter_epoch(epoch(7)).unwrap().unwrap();
+        assert!(mint.enter_epoch(epoch(7)).unwrap().is_none());
+        mint.credit_share("erin", 4).unwrap();
+        let (withdrawn, rs) = outputs(&mint, EHASH_UNIT, &[4]);
+        let signatures = mint.withdraw("erin", &withdrawn).unwrap();
+        assert_eq!(signatures[0].id, first);
+        let proofs = unblind(&mint, &signatures, rs);
+
+        mint.enter_epoch(epoch(8)).unwrap().unwrap();
+        let (swapped, _) = outputs(&mint, EHASH_UNIT, &[4]);
+        assert!(matches!(
+            mint.swap(&proofs, &swapped),
+            Err(MintError::InactiveKeyset(id)) if id == first
+        ));
+        mint.epochs.older = OlderEpochs::Swap;
+        assert_eq!(mint.swap(&proofs, &swapped).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn melts_with_change_and_releases_failed_payments() {
+        let config: LightningConfig =
+            serde_json::from_str(r#"{"backend": "cln", "rpc_path": "", "fee_reserve_ppm": 0}"#)
+                .unwrap();
+        let invoice = DecodedInvoice {
+            payment_hash: "00".repeat(32),
+            amount_msat: 10_000,
+        };
+        assert!(mint()
+            .create_melt_quote("lnbc", "ehash", &invoice, &config)
+            .is_err());
+        assert!(mint_with_units(&[EHASH_UNIT])
+            .create_melt_quote("lnbc", "sat", &invoice, &config)
+            .is_err());
+
+        let mut mint = mint();
+        mint.credit_share("erin", 1).unwrap();
+        let round = mint.found_block("coinbase", 16, None).unwrap();
+        mint.mature_round(round).unwrap();
+        let (minted, rs) = outputs(&mint, SAT_UNIT, &[16]);
+        let signatures = mint.withdraw("erin", &minted).unwrap();
+        let proofs = unblind(&mint, &signatures, rs);
+
+        // 10 sat and a 2 sat fee reserve
+        let quote = mint
+            .create_melt_quote("lnbc", "sat", &invoice, &config)
+            .unwrap();
+        assert_eq!((quote.amount, quote.fee_reserve), (10, 2));
+
+        mint.begin_melt(&quote.quote, &proofs, &[]).unwrap();
+        assert!(matches!(
+            mint.swap(&proofs, &[]),
+            Err(MintError::ProofPending)
+        ));
+        let failed = PaymentStatus::Failed("no route".into());
+        let released = mint.finish_melt(&quote.quote, &failed, &[]).unwrap();
+        assert_eq!(released.state, MeltQuoteState::Unpaid);
+
+        let (blank, _) = outputs(&mint, SAT_UNIT, &[0, 0, 0]);
+        mint.begin_melt(&quote.quote, &proofs, &blank).unwrap();
+        let paid = PaymentStatus::Paid {
+            preimage: "11".repeat(32),
+            fee_msat: 1_000,
+        };
+        let melted = mint.finish_melt(&quote.quote, &paid, &blank).unwrap();
+        assert_eq!(melted.state, MeltQuoteState::Paid);
+        // 16 in, 10 paid, 1 fee: 5 back as 1 + 4
+        let change: Vec<_> = melted.change.unwrap().iter().map(|c| c.amount).collect();
+        assert_eq!(change, vec![1, 4]);
+        assert!(matches!(
+            mint.swap(&proofs, &[]),
+            Err(MintError::ProofAlreadySpent)
+        ));
+    }
+
+    #[test]
+    fn queues_onchain_melts_into_batches() {
+        let config = OnchainConfig {
+            network: "regtest".into(),
+            min_amount: 10,
+            fee_sat: 2,
+            ..Default::default()
+        };
+        let address = "bcrt1qs758ursh4q9z627kt3pp5yysm78ddny6txaqgw";
+        let mut mint = mint();
+        assert!(mint
+            .create_onchain_melt_quote(address, 9, "sat", &config)
+            .is_err());
+        assert!(mint
+            .create_onchain_melt_quote(
+                "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq",
+                10,
+                "sat",
+                &config
+            )
+            .is_err());
+
+        mint.credit_share("erin", 1).unwrap();
+        let round = mint.found_block("coinbase", 16, None).unwrap();
+        mint.mature_round(round).unwrap();
+        let (minted, rs) = outputs(&mint, SAT_UNIT, &[16]);
+        let signatures = mint.withdraw("erin", &minted).unwrap();
+        let proofs = unblind(&mint, &signatures, rs);
+
+        let quote = mint
+            .create_onchain_melt_quote(address, 10, "sat", &config)
+            .unwrap();
+        // Lightning melts don't take on-chain quotes
+        assert!(matches!(
+            mint.begin_melt(&quote.quote, &proofs, &[]),
+            Err(MintError::UnknownQuote(_))
+        ));
+        let (blank, _) = outputs(&mint, SAT_UNIT, &[0, 0, 0]);
+        let queued = mint
+            .melt_onchain(&quote.quote, &proofs, &blank, &config)
+            .unwrap();
+        assert_eq!(queued.state, MeltQuoteState::Pending);
+        // 16 in, 10 melted: 6 back as 2 + 4 right away
+        let change: Vec<_> = queued.change.unwrap().iter().map(|c| c.amount).collect();
+        assert_eq!(change, vec![2, 4]);
+        assert!(matches!(
+            mint.swap(&proofs, &[]),
+            Err(MintError::ProofAlreadySpent)
+        ));
+        assert!(mint.pending_melt_quotes().unwrap().is_empty());
+        let payouts = mint.onchain_payouts().unwrap();
+        assert_eq!(payouts.len(), 1);
+        assert_eq!(
+            (payouts[0].address.as_str(), payouts[0].amount),
+            (address, 8)
+        );
+
+        let batch = OnchainBatch {
+            txid: "22".repeat(32),
+            tx: "00".into(),
+        };
+        mint.record_onchain_batch(&batch, std::slice::from_ref(&quote.quote))
+            .unwrap();
+        assert!(mint.onchain_payouts().unwrap().is_empty());
+        assert_eq!(mint.unsent_onchain_batches().unwrap(), vec![batch.clone()]);
+        mint.settle_onchain_batch(&batch.txid).unwrap();
+        assert!(mint.unsent_onchain_batches().unwrap().is_empty());
+        let paid = mint.melt_quote(&quote.quote).unwrap();
+        assert_eq!(paid.state, MeltQuoteState::Paid);
+        assert_eq!(paid.payment_preimage, Some(batch.txid));
+    }
+
+    #[test]
+    fn keeps_running_totals_per_account() {
+        let mut mint = mint();
+        mint.credit_share("alice", 4).unwrap();
+        mint.credit_share("bob", 4).unwrap();
+        let (withdrawn, _) = outputs(&mint, EHASH_UNIT, &[2]);
+        mint.withdraw("alice", &withdrawn).unwrap();
+        let round = mint.found_block("coinbase", 16, None).unwrap();
+        assert_eq!(mint.account_statement("alice").unwrap().pending_shares, 4);
+
+        // alice still holds half her ehash, so half her 8 sat are credited
+        mint.mature_round(round).unwrap();
+        let alice = mint.account_statement("alice").unwrap();
+        assert_eq!(alice.pending_shares, 0);
+        assert_eq!(
+            alice.ledgers[EHASH_UNIT],
+            AccountLedger {
+                balance: 0,
+                credited: 4,
+                issued: 2,
+                redeemed: 2,
+            }
+        );
+        assert_eq!(
+            alice.ledgers[SAT_UNIT],
+            AccountLedger {
+                balance: 4,
+                credited: 4,
+                ..Default::default()
+            }
+        );
+        let accounts: Vec<_> = mint
+            .account_statements()
+            .unwrap()
+            .into_iter()
+            .map(|statement| statement.account)
+            .collect();
+        assert_eq!(accounts, vec!["alice", "bob"]);
+        assert_eq!(mint.account_statement("carol").unwrap().ledgers.len(), 2);
+    }
+
+    #[test]
+    fn halts_sat_issuance_below_the_reserve_ratio() {
+        let mut mint = mint();
+        mint.credit_share("alice", 1).unwrap();
+        let round = mint.found_block("coinbase", 16, None).unwrap();
+        mint.mature_round(round).unwrap();
+        mint.credit_share("alice", 1).unwrap();
+        mint.found_block("coinbase", 8, None).unwrap();
+        let reserves = mint.reserve_report().unwrap().unwrap();
+        assert_eq!(
+            (reserves.liabilities, reserves.matured, reserves.immature),
+            (16, 16, 8)
+        );
+        assert_eq!(reserves.ratio_ppk, Some(1000));
+
+        // asking for twice the liabilities in matured rewards halts sat, not ehash
+        mint.reserves = ReserveConfig {
+            min_ratio_ppk: Some(2000),
+            ..Default::default()
+        };
+        let (sat, _) = outputs(&mint, SAT_UNIT, &[16]);
+        assert!(matches!(
+            mint.withdraw("alice", &sat),
+            Err(MintError::InsufficientReserves {
+                ratio_ppk: 1000,
+                min_ratio_ppk: 2000
+            })
+        ));
+        let (ehash, _) = outputs(&mint, EHASH_UNIT, &[1]);
+        assert!(mint.withdraw("alice", &ehash).is_ok());
+        // the immature reward doesn't cover that either
+        mint.reserves.backing = reserves::Backing::Immature;
+        assert!(mint.withdraw("alice", &sat).is_err());
+        mint.reserves.min_ratio_ppk = Some(1500);
+        assert_eq!(mint.withdraw("alice", &sat).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn charges_and_collects_fees() {
+        let mut config = MintConfig::new(
+            vec![EHASH_UNIT.into(), SAT_UNIT.into()],
+            "".into(),
+            "".into(),
+            "".into(),
+            8,
+            None,
+            "".into(),
+            None,
+        );
+        config.fees.input_fee_ppk = 500;
+        config.fees.melt.input_fee_ppk = 500;
+        let mut mint = Mint::from_master_secret(&[1; 32], &config).unwrap();
+        let id = mint.active_keyset(EHASH_UNIT).unwrap().id.clone();
+        assert_eq!(mint.input_fee_ppk(&id), 500);
+
+        mint.credit_share("dave", 4).unwrap();
+        let (minted, rs) = outputs(&mint, EHASH_UNIT, &[2, 2]);
+        let signatures = mint.withdraw("dave", &minted).unwrap();
+        let proofs = unblind(&mint, &signatures, rs);
+        // two inputs at 500 ppk each
+        let (without_fee, _) = outputs(&mint, EHASH_UNIT, &[4]);
+        assert!(matches!(
+            mint.swap(&proofs, &without_fee),
+            Err(MintError::UnbalancedTransaction {
+                inputs: 4,
+                outputs: 5
+            })
+        ));
+        let (swapped, _) = outputs(&mint, EHASH_UNIT, &[2, 1]);
+        mint.swap(&proofs, &swapped).unwrap();
+        let report = mint.report().unwrap();
+        assert_eq!(report.spent_proofs, 2);
+        assert_eq!(
+            report.fees,
+            vec![FeeTotal {
+                unit: EHASH_UNIT.into(),
+                operation: Operation::Swap,
+                amount: 1,
+            }]
+        );
+
+        let mut mint = Mint::from_master_secret(&[1; 32], &config).unwrap();
+        let config: LightningConfig =
+            serde_json::from_str(r#"{"backend": "cln", "rpc_path": "", "fee_reserve_ppm": 0}"#)
+                .unwrap();
+        let invoice = DecodedInvoice {
+            payment_hash: "00".repeat(32),
+            amount_msat: 10_000,
+        };
+        mint.credit_share("erin", 1).unwrap();
+        let round = mint.found_block("coinbase", 16, None).unwrap();
+        mint.mature_round(round).unwrap();
+        let (minted, rs) = outputs(&mint, SAT_UNIT, &[16]);
+        let signatures = mint.withdraw("erin", &minted).unwrap();
+        let proofs = unblind(&mint, &signatures, rs);
+        let quote = mint
+            .create_melt_quote("lnbc", "sat", &invoice, &config)
+            .unwrap();
+        let (blank, _) = outputs(&mint, SAT_UNIT, &[0, 0, 0]);
+        mint.begin_melt(&quote.quote, &proofs, &blank).unwrap();
+        let paid = PaymentStatus::Paid {
+            preimage: "11".repeat(32),
+            fee_msat: 1_000,
+        };
+        let melted = mint.finish_melt(&quote.quote, &paid, &blank).unwrap();
+        // 16 in, 10 paid, 1 lightning fee, 1 melt fee
+        let change: Vec<_> = melted.change.unwrap().iter().map(|c| c.amount).collect();
+        assert_eq!(change, vec![4]);
+        assert_eq!(
+            mint.fees_collected().unwrap(),
+            vec![FeeTotal {
+                unit: SAT_UNIT.into(),
+                operation: Operation::Melt,
+                amount: 1,
+            }]
+        );
+    }
+
+    #[test]
+    fn redeems_only_matured_rewards() {
+        let mut mint = mint();
+        mint.credit_share("alice", 3).unwrap();
+        mint.credit_share("bob", 1).unwrap();
+        let round = mint.found_block("coinbase", 1000, None).unwrap();
+        // shares after the block count toward the next round
+        mint.credit_share("bob", 5).unwrap();
+        assert_eq!(mint.balance("alice", SAT_UNIT).unwrap(), 0);
+        let (minted, _) = outputs(&mint, SAT_UNIT, &[1]);
+        assert!(matches!(
+            mint.withdraw("alice", &minted),
+            Err(MintError::InsufficientBalance { .. })
+        ));
+        assert_eq!(mint.immature_rounds().unwrap()[0].id, round);
+
+        mint.mature_round(round).unwrap();
+        // maturing twice pays nothing more
+        mint.mature_round(round).unwrap();
+        assert_eq!(mint.balance("alice", SAT_UNIT).unwrap(), 750);
+        assert_eq!(mint.balance("bob", SAT_UNIT).unwrap(), 250);
+        // the round's ehash was settled, the next round's is left
+        assert_eq!(mint.balance("alice", EHASH_UNIT).unwrap(), 0);
+        assert_eq!(mint.balance("bob", EHASH_UNIT).unwrap(), 5);
+        assert!(mint.immature_rounds().unwrap().is_empty());
+
+        let orphan = mint.found_block("stale", 1000, None).unwrap();
+        mint.orphan_round(orphan).unwrap();
+        mint.mature_round(orphan).unwrap();
+        assert_eq!(mint.balance("bob", SAT_UNIT).unwrap(), 250);
+        assert_eq!(mint.balance("bob", EHASH_UNIT).unwrap(), 5);
+    }
+
+    #[test]
+    fn converts_withdrawn_ehash_from_the_reserve() {
+        let mut mint = mint();
+        mint.credit_share("alice", 4).unwrap();
+        let (minted, rs) = outputs(&mint, EHASH_UNIT, &[2, 2]);
+        let signatures = mint.withdraw("alice", &minted).unwrap();
+        let proofs = unblind(&mint, &signatures, rs);
+
+        // nothing to convert before the round matures
+        let (sat, _) = outputs(&mint, SAT_UNIT, &[1]);
+        assert!(mint.convert(&proofs[..1], &sat).is_err());
+
+        let round = mint.found_block("coinbase", 100, None).unwrap();
+        mint.mature_round(round).unwrap();
+        assert_eq!(mint.balance("alice", SAT_UNIT).unwrap(), 0);
+        let conversion = mint.conversion().unwrap();
+        assert_eq!((conversion.reserve, conversion.outstanding), (100, 4));
+
+        let (too_much, _) = outputs(&mint, SAT_UNIT, &[64]);
+        assert!(matches!(
+            mint.convert(&proofs[..1], &too_much),
+            Err(MintError::UnbalancedTransaction { .. })
+        ));
+        let (ehash, _) = outputs(&mint, EHASH_UNIT, &[32, 16, 2]);
+        assert!(mint.convert(&proofs[..1], &ehash).is_err());
+        let (sat, _) = outputs(&mint, SAT_UNIT, &[32, 16, 2]);
+        assert_eq!(mint.convert(&proofs[..1], &sat).unwrap().len(), 3);
+        assert!(matches!(
+            mint.convert(&proofs[..1], &[]),
+            Err(MintError::ProofAlreadySpent)
+        ));
+        let conversion = mint.conversion().unwrap();
+        assert_eq!((conversion.reserve, conversion.outstanding), (50, 2));
+    }
+
+    #[test]
+    fn pays_out_held_tokens() {
+        let mut mint = mint();
+        mint.credit_share("alice", 5).unwrap();
+        mint.credit_share("bob", 9).unwrap();
+        let mut config = PayoutConfig {
+            mode: PayoutMode::Hold,
+            unit: EHASH_UNIT.into(),
+            min_amount: 5,
+            ..Default::default()
+        };
+        config.accounts.insert("bob".into(), PayoutMode::Manual);
+        let payouts = mint.pay_out_due(&config, "http://mint").unwrap();
+        assert_eq!(payouts.len(), 1);
+        assert_eq!(mint.balance("alice", EHASH_UNIT).unwrap(), 0);
+        assert_eq!(mint.balance("bob", EHASH_UNIT).unwrap(), 9);
+        // below min_amount now
+        assert!(mint.pay_out_due(&config, "http://mint").unwrap().is_empty());
+
+        let token = Token::decode(&payouts[0].token).unwrap();
+        assert_eq!(token.amount(), 5);
+        assert_eq!(token.token[0].mint, "http://mint");
+        let proofs = &token.token[0].proofs;
+        let (swapped, _) = outputs(&mint, EHASH_UNIT, &[4, 1]);
+        mint.swap(proofs, &swapped).unwrap();
+
+        assert_eq!(mint.pick_up_payouts("alice").unwrap(), payouts);
+        assert!(mint.pick_up_payouts("alice").unwrap().is_empty());
+        assert!(mint.undelivered_payouts().unwrap().is_empty());
+    }
+
+    #[test]
+    fn issues_only_the_configured_amounts() {
+        let mut config = MintConfig::new(
+            vec![EHASH_UNIT.into()],
+            "".into(),
+            "".into(),
+            "".into(),
+            8,
+            None,
+            "".into(),
+            None,
+        );
+        config
+            .amounts
+            .insert(EHASH_UNIT.into(), AmountStrategy::Custom(vec![4, 16]));
+        let mut mint = Mint::from_master_secret(&[1; 32], &config).unwrap();
+        mint.credit_share("alice", 27).unwrap();
+        assert!(matches!(
+            mint.create_mint_quote("alice", 10, EHASH_UNIT),
+            Err(MintError::UnsupportedAmount(10))
+        ));
+        let (unsupported, _) = outputs(&mint, EHASH_UNIT, &[8]);
+        assert!(matches!(
+            mint.withdraw("alice", &unsupported),
+            Err(MintError::UnsupportedAmount(8))
+        ));
+
+        // payouts leave what the keyset can't make in the balance
+        let payout = PayoutConfig {
+            mode: PayoutMode::Hold,
+            unit: EHASH_UNIT.into(),
+            min_amount: 1,
+            ..Default::default()
+        };
+        let payouts = mint.pay_out_due(&payout, "http://mint").unwrap();
+        assert_eq!(payouts[0].amount, 24);
+        let token = Token::decode(&payouts[0].token).unwrap();
+        let amounts: Vec<_> = token.token[0].proofs.iter().map(|p| p.amount).collect();
+        assert_eq!(amounts, vec![16, 4, 4]);
+        assert_eq!(mint.balance("alice", EHASH_UNIT).unwrap(), 3);
+    }
+
+    #[test]
+    fn journals_credits_while_the_database_is_unavailable() {
+        let dir = std::env::temp_dir().join(format!("potato-mint-journal-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        let mut config = MintConfig::new(
+            vec![EHASH_UNIT.into()],
+            path("master_secret"),
+            path("mint.sqlite"),
+            path("keysets.json"),
+            8,
+            None,
+            "".into(),
+            None,
+        );
+        config.journal_path = path("journal.jsonl");
+        let mut mint = Mint::new(&config, None).unwrap();
+        mint.credit_share("alice", 3).unwrap();
+
+        // shares can't be recorded without their table
+        let conn = rusqlite::Connection::open(path("mint.sqlite")).unwrap();
+        conn.execute_batch("ALTER TABLE round_shares RENAME TO round_shares_away")
+            .unwrap();
+        mint.credit_share("alice", 4).unwrap();
+        // closing the round would work, but waits for the shares before it
+        mint.credit(Credit::Block {
+            coinbase_txid: "coinbase".into(),
+            reward: 16,
+            finder: None,
+        })
+        .unwrap();
+        mint.credit_share("alice", 5).unwrap();
+        assert_eq!(mint.balance("alice", EHASH_UNIT).unwrap(), 3);
+        assert_eq!(mint.replay_journal().unwrap(), 0);
+        assert!(mint.immature_rounds().unwrap().is_empty());
+
+        // the journal survives a restart, and the database takes it back in order
+        drop(mint);
+        conn.execute_batch("ALTER TABLE round_shares_away RENAME TO round_shares")
+            .unwrap();
+        let mint = Mint::new(&config, None).unwrap();
+        assert_eq!(mint.balance("alice", EHASH_UNIT).unwrap(), 12);
+        assert_eq!(mint.report().unwrap().journaled_credits, 0);
+        let rounds = mint.immature_rounds().unwrap();
+        assert_eq!(rounds[0].reward, Some(16));
+        let weight: i64 = conn
+            .query_row(
+                "SELECT weight FROM round_shares WHERE round_id = ?1",
+                [rounds[0].id as i64],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(weight, 7);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reconciles_shares_with_credits() {
+        let dir =
+            std::env::temp_dir().join(format!("potato-mint-reconcile-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        let mut config = MintConfig::new(
+            MintConfig::default_units(),
+            path("master_secret"),
+            path("mint.sqlite"),
+            path("keysets.json"),
+            8,
+            None,
+            "".into(),
+            None,
+        );
+        config.journal_path = path("journal.jsonl");
+        let mut mint = Mint::new(&config, None).unwrap();
+        mint.credit_share("alice", 3).unwrap();
+        mint.credit_share("bob", 1).unwrap();
+        mint.credit(Credit::Block {
+            coinbase_txid: "coinbase".into(),
+            reward: 100,
+            finder: None,
+        })
+        .unwrap();
+        let round_id = mint.immature_rounds().unwrap()[0].id;
+        mint.mature_round(round_id).unwrap();
+        mint.credit_share("carol", 2).unwrap();
+        assert!(mint.reconcile(false).unwrap().discrepancies.is_empty());
+
+        let conn = rusqlite::Connection::open(path("mint.sqlite")).unwrap();
+        conn.execute_batch(
+            "DELETE FROM round_credits WHERE account = 'bob' AND unit = 'sat';
+             UPDATE round_credits SET amount = 1 WHERE account = 'alice' AND unit = 'ehash';
+             UPDATE round_credits SET amount = 5 WHERE account = 'carol';",
+        )
+        .unwrap();
+        let report = mint.reconcile(false).unwrap();
+        assert_eq!(report.rounds, 2);
+        assert_eq!(report.discrepancies.len(), 3);
+        assert_eq!(report.repaired, 0);
+        assert_eq!(mint.balance("bob", SAT_UNIT).unwrap(), 25);
+
+        assert_eq!(mint.reconcile(true).unwrap().repaired, 2);
+        assert_eq!(mint.balance("bob", SAT_UNIT).unwrap(), 50);
+        // the ehash of the matured round was settled already
+        assert_eq!(mint.balance("alice", EHASH_UNIT).unwrap(), 0);
+        let discrepancies = mint.reconcile(true).unwrap().discrepancies;
+        assert_eq!(discrepancies.len(), 1);
+        assert_eq!(discrepancies[0].account, "carol");
+        assert_eq!(discrepancies[0].kind, DiscrepancyKind::CreditWithoutShares);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn records_payouts_of_an_external_mint() {
+        let mut mint = mint();
+        mint.credit_share("alice", 5).unwrap();
+        let config = PayoutConfig {
+            mode: PayoutMode::Hold,
+            unit: EHASH_UNIT.into(),
+            min_amount: 5,
+            ..Default::default()
+        };
+        assert_eq!(
+            mint.due_payouts(&config).unwrap(),
+            vec![("alice".to_string(), 5)]
+        );
+        let token = Token::new("http://external", EHASH_UNIT, vec![]);
+        let payout = mint.record_payout("alice", EHASH_UNIT, 5, &token).unwrap();
+        assert_eq!(mint.balance("alice", EHASH_UNIT).unwrap(), 0);
+        assert!(mint.due_payouts(&config).unwrap().is_empty());
+        assert_eq!(mint.pick_up_payouts("alice").unwrap(), vec![payout]);
+        assert!(matches!(
+            mint.record_payout("alice", EHASH_UNIT, 5, &token),
+            Err(MintError::InsufficientBalance { .. })
+        ));
+    }
+
+    #[test]
+    fn pays_out_in_share_weighted_denominations() {
+        let mut mint = mint();
+        for _ in 0..3 {
+            mint.credit_share("alice", 20).unwrap();
+        }
+        mint.credit_share("bob", 7).unwrap();
+        let config = PayoutConfig {
+            mode: PayoutMode::Hold,
+            unit: EHASH_UNIT.into(),
+            min_amount: 1,
+            denominations: Denominations::ShareWeighted,
+            ..Default::default()
+        };
+        assert_eq!(
+            mint.due_payouts(&config).unwrap(),
+            vec![("alice".to_string(), 48), ("bob".to_string(), 4)]
+        );
+        let payouts = mint.pay_out_due(&config, "http://mint").unwrap();
+        assert_eq!(
+            Token::decode(&payouts[0].token).unwrap().token[0]
+                .proofs
+                .len(),
+            2
+        );
+        assert_eq!(mint.balance("alice", EHASH_UNIT).unwrap(), 12);
+        // less than a share left
+        assert!(mint.due_payouts(&config).unwrap().is_empty());
+    }
+
+    #[test]
+    fn locks_payouts_to_registered_keys() {
+        let secp = Secp256k1::new();
+        let key = SecretKey::from_slice(&[7; 32]).unwrap();
+        let other = SecretKey::from_slice(&[8; 32]).unwrap().public_key(&secp);
+        let mut mint = mint();
+        mint.register_pubkey("alice", &key.public_key(&secp), None)
+            .unwrap();
+        mint.register_pubkey("alice", &key.public_key(&secp), None)
+            .unwrap();
+        assert!(matches!(
+            mint.register_pubkey("alice", &other, None),
+            Err(MintError::PubkeyAlreadyRegistered(_))
+        ));
+
+        mint.credit_share("alice", 5).unwrap();
+        let config = PayoutConfig {
+            mode: PayoutMode::Hold,
+            unit: EHASH_UNIT.into(),
+            min_amount: 1,
+            ..Default::default()
+        };
+        let payouts = mint.pay_out_due(&config, "http://mint").unwrap();
+        let mut proofs = Token::decode(&payouts[0].token).unwrap().token[0]
+            .proofs
+            .clone();
+        let (swapped, _) = outputs(&mint, EHASH_UNIT, &[4, 1]);
+        assert!(matches!(
+            mint.swap(&proofs, &swapped),
+            Err(MintError::SpendingConditions(_))
+        ));
+        for proof in &mut proofs {
+            proof.witness = Some(p2pk::sign(&proof.secret, &key));
+        }
+        mint.swap(&proofs, &swapped).unwrap();
+
+        // replaced with a signature of the bound key, only on the new key of the same account
+        let other_key = other.to_string();
+        let rebind = credentials::sign_rebind(credentials::PAYOUT_KEY, "alice", &other_key, &key);
+        let forged = credentials::sign_rebind(
+            credentials::PAYOUT_KEY,
+            "alice",
+            &other_key,
+            &SecretKey::from_slice(&[8; 32]).unwrap(),
+        );
+        let of_bob = credentials::sign_rebind(credentials::PAYOUT_KEY, "bob", &other_key, &key);
+        for signature in [&forged, &of_bob] {
+            assert!(mint
+                .register_pubkey("alice", &other, Some(signature))
+                .is_err());
+        }
+        mint.register_pubkey("alice", &other, Some(&rebind))
+            .unwrap();
+        assert_eq!(mint.account_pubkey("alice").unwrap(), Some(other));
+    }
+
+    #[test]
+    fn checks_the_credential_issued_to_an_account() {
+        let mut mint = mint();
+        assert!(matches!(
+            mint.check_credential("alice", None),
+            Err(MintError::Unauthorized(_))
+        ));
+        let credential = mint.issue_credential("alice").unwrap();
+        mint.check_credential("alice", Some(&credential)).unwrap();
+        assert!(mint.check_credential("bob", Some(&credential)).is_err());
+        assert!(mint.check_credential("alice", None).is_err());
+
+        // a new credential revokes the previous one
+        let renewed = mint.issue_credential("alice").unwrap();
+        assert!(mint.check_credential("alice", Some(&credential)).is_err());
+        mint.check_credential("alice", Some(&renewed)).unwrap();
+    }
+
+    #[test]
+    fn keeps_nostr_keys_apart_from_payout_keys() {
+        let secp = Secp256k1::new();
+        let key = SecretKey::from_slice(&[7; 32]).unwrap();
+        let (nostr_key, _) = key.x_only_public_key(&secp);
+        let (other, _) = SecretKey::from_slice(&[8; 32])
+            .unwrap()
+            .x_only_public_key(&secp);
+        let mut mint = mint();
+        mint.register_nostr_key("alice", &nostr_key, None).unwrap();
+        assert!(matches!(
+            mint.register_nostr_key("alice", &other, None),
+            Err(MintError::PubkeyAlreadyRegistered(_))
+        ));
+        assert_eq!(mint.nostr_key("alice").unwrap(), Some(nostr_key));
+        assert_eq!(mint.account_pubkey("alice").unwrap(), None);
+        mint.register_pubkey("alice", &key.public_key(&secp), None)
+            .unwrap();
+
+        // a signature replacing the payout key doesn't replace the Nostr key
+        let other_key = other.to_string();
+        let of_payout_key =
+            credentials::sign_rebind(credentials::PAYOUT_KEY, "alice", &other_key, &key);
+        assert!(mint
+            .register_nostr_key("alice", &other, Some(&of_payout_key))
+            .is_err());
+        let rebind = credentials::sign_rebind(credentials::NOSTR_KEY, "alice", &other_key, &key);
+        mint.register_nostr_key("alice", &other, Some(&rebind))
+            .unwrap();
+        assert_eq!(mint.nostr_key("alice").unwrap(), Some(other));
+    }
+
+    #[test]
+    fn signs_queued_outputs_as_balance_grows() {
+        let mut mint = mint();
+        let (queued, rs) = outputs(&mint, EHASH_UNIT, &[2, 4, 1]);
+        mint.queue_outputs("frank", &queued).unwrap();
+        assert!(matches!(
+            mint.queue_outputs("frank", &queued[..1]),
+            Err(MintError::OutputAlreadySigned)
+        ));
+        assert_eq!(mint.issue_queued(10).unwrap(), 0);
+
+        // 2 fits, 4 doesn't and holds back the 1 queued after it
+        mint.credit_share("frank", 3).unwrap();
+        assert_eq!(mint.issue_queued(10).unwrap(), 1);
+        assert_eq!(mint.balance("frank", EHASH_UNIT).unwrap(), 1);
+        let signatures = mint.signatures(&queued).unwrap();
+        assert!(signatures[0].is_some() && signatures[1].is_none());
+
+        mint.credit_share("frank", 4).unwrap();
+        assert_eq!(mint.issue_queued(10).unwrap(), 2);
+        assert_eq!(mint.balance("frank", EHASH_UNIT).unwrap(), 0);
+        let signatures: Vec<_> = mint
+            .signatures(&queued)
+            .unwrap()
+            .into_iter()
+            .map(Option::unwrap)
+            .collect();
+        for proof in unblind(&mint, &signatures, rs) {
+            mint.verify_proof(&proof).unwrap();
+        }
+    }
+
+    #[test]
+    fn refuses_overdraft() {
+        let mut mint = mint();
+        mint.credit_share("bob", 3).unwrap();
+        let (b, _) = dhke::blind_message(b"x", None).unwrap();
+        let outputs = vec![BlindedMessage {
+            amount: 4,
+            id: mint.active_keyset(EHASH_UNIT).unwrap().id.clone(),
+            blinded_secret: b,
+        }];
+        assert!(mint.withdraw("bob", &outputs).is_err());
+        assert_eq!(mint.balance("bob", EHASH_UNIT).unwrap(), 3);
+    }
+
+    #[test]
+    fn weights_shares_by_difficulty() {
+        // difficulty 512 target, see `gets_difficulty_from_target` in the translator
+        let target = vec![
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 128, 255, 127,
+            0, 0, 0, 0, 0,
+        ];
+        assert_eq!(share_weight(&target), 512);
+        assert_eq!(share_weight(&[0; 32]), 0);
+    }
+}
@@ -0,0 +1,282 @@
+//! Payout delivery over Nostr, for miners without a wallet online to pick tokens up. An account
+//! paid out in `nostr` mode registers a Nostr public key with its credential (`/v1/ehash/nostr`,
+//! see `credentials`), and every token paid out to it is sent as a NIP-04 encrypted direct
+//! message to that key, published to the configured relays. The payout counts as delivered once
+//! a relay accepted the message, and is held like any other until then.
+//!
+//! Messages are signed with a key of the mint kept at `secret_key_path`, so miners can tell them
+//! from anyone else's. The message is the encoded token alone, which Nostr wallets pick up.
+//!
+//! The same delivery posts the public status notes of the pool, see `status::nostr`.
+use super::{keyset, lifecycle::now_secs};
+use crate::error::{MintError, MintResult};
+use aes::cipher::{block_padding::Pkcs7, BlockEncryptMut, KeyIvInit};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use futures::{future, SinkExt, StreamExt};
+use secp256k1::{ecdh, Keypair, Message, Parity, PublicKey, Secp256k1, SecretKey, XOnlyPublicKey};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+use tracing::debug;
+
+/// Kind of public text notes, see NIP-01.
+const TEXT_NOTE: u16 = 1;
+/// Kind of NIP-04 encrypted direct messages.
+const ENCRYPTED_DIRECT_MESSAGE: u16 = 4;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct NostrConfig {
+    /// Relays messages are published to, e.g. `wss://relay.damus.io`.
+    pub relays: Vec<String>,
+    /// File holding the hex encoded key messages are signed with. Created on first start.
+    #[serde(default = "NostrConfig::default_secret_key_path")]
+    pub secret_key_path: String,
+    /// Seconds a relay has to accept a message.
+    #[serde(default = "NostrConfig::default_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+impl NostrConfig {
+    fn default_secret_key_path() -> String {
+        "mint_nostr_key".to_string()
+    }
+
+    fn default_timeout_secs() -> u64 {
+        10
+    }
+}
+
+/// A signed Nostr event, see NIP-01.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Event {
+    pub id: String,
+    pub pubkey: String,
+    pub created_at: u64,
+    pub kind: u16,
+    pub tags: Vec<Vec<String>>,
+    pub content: String,
+    pub sig: String,
+}
+
+#[derive(Debug)]
+pub struct NostrDelivery {
+    keypair: Keypair,
+    relays: Vec<String>,
+    timeout: Duration,
+}
+
+impl NostrDelivery {
+    pub fn new(config: &NostrConfig) -> MintResult<Self> {
+        if config.relays.is_empty() {
+            return Err(MintError::Nostr("no relays configured".into()));
+        }
+        let secret = keyset::load_or_create_master_secret(&config.secret_key_path)?;
+        Ok(Self::from_secret_key(
+            &SecretKey::from_slice(&secret)?,
+            config,
+        ))
+    }
+
+    fn from_secret_key(secret_key: &SecretKey, config: &NostrConfig) -> Self {
+        Self {
+            keypair: Keypair::from_secret_key(&Secp256k1::new(), secret_key),
+            relays: config.relays.clone(),
+            timeout: Duration::from_secs(config.timeout_secs.max(1)),
+        }
+    }
+
+    /// The key messages are signed with.
+    pub fn pubkey(&self) -> XOnlyPublicKey {
+        self.keypair.x_only_public_key().0
+    }
+
+    /// Sends `text` to `recipient` as an encrypted direct message, done once a relay accepted it.
+    pub async fn send(&self, recipient: &XOnlyPublicKey, text: &str) -> MintResult<()> {
+        let event = self.direct_message(recipient, text, now_secs())?;
+        self.broadcast(&event).await
+    }
+
+    /// Posts `text` as a public note, done once a relay accepted it.
+    pub async fn post(&self, text: &str) -> MintResult<()> {
+        self.broadcast(&self.note(text, now_secs())).await
+    }
+
+    /// Publishes `event` to every relay, done once one of them accepted it.
+    async fn broadcast(&self, event: &Event) -> MintResult<()> {
+        let results = future::join_all(
+            self.relays
+                .iter()
+                .map(|relay| tokio::time::timeout(self.timeout, publish(relay, event))),
+        )
+        .await;
+        let mut errors = vec![];
+        for (relay, result) in self.relays.iter().zip(results) {
+            match result {
+                Ok(Ok(())) => return Ok(()),
+                Ok(Err(e)) => errors.push(format!("{}: {}", relay, e)),
+                Err(_) => errors.push(format!("{}: timed out", relay)),
+            }
+        }
+        Err(MintError::Nostr(errors.join(", ")))
+    }
+
+    /// The direct message of `text` to `recipient`, signed at `created_at`.
+    pub fn direct_message(
+        &self,
+        recipient: &XOnlyPublicKey,
+        text: &str,
+        created_at: u64,
+    ) -> MintResult<Event> {
+        let content = encrypt(&self.keypair.secret_key(), recipient, text)?;
+        let tags = vec![vec!["p".to_string(), recipient.to_string()]];
+        Ok(self.sign(ENCRYPTED_DIRECT_MESSAGE, tags, content, created_at))
+    }
+
+    /// The public note of `text`, signed at `created_at`.
+    pub fn note(&self, text: &str, created_at: u64) -> Event {
+        self.sign(TEXT_NOTE, vec![], text.to_string(), created_at)
+    }
+
+    fn sign(&self, kind: u16, tags: Vec<Vec<String>>, content: String, created_at: u64) -> Event {
+        let pubkey = self.pubkey().to_string();
+        let id = event_id(&pubkey, created_at, kind, &tags, &content);
+        let sig = Secp256k1::new().sign_schnorr(&Message::from_digest(id), &self.keypair);
+        Event {
+            id: hex::encode(id),
+            pubkey,
+            created_at,
+            kind,
+            tags,
+            content,
+            sig: sig.to_string(),
+        }
+    }
+}
+
+/// The NIP-01 id of an event: the hash of its fields serialized as a JSON array.
+fn event_id(
+    pubkey: &str,
+    created_at: u64,
+    kind: u16,
+    tags: &[Vec<String>],
+    content: &str,
+) -> [u8; 32] {
+    let serialized = json!([0, pubkey, created_at, kind, tags, content]).to_string();
+    Sha256::digest(serialized.as_bytes()).into()
+}
+
+/// Encrypts `text` to `recipient` as NIP-04 does: AES-256-CBC keyed with the x coordinate of the
+/// shared point, `<ciphertext>?iv=<iv>` in base64.
+fn encrypt(secret_key: &SecretKey, recipient: &XOnlyPublicKey, text: &str) -> MintResult<String> {
+    let key = shared_key(secret_key, recipient);
+    let iv: [u8; 16] = rand::random();
+    let ciphertext = cbc::Encryptor::<aes::Aes256>::new(&key.into(), &iv.into())
+        .encrypt_padded_vec_mut::<Pkcs7>(text.as_bytes());
+    Ok(format!(
+        "{}?iv={}",
+        STANDARD.encode(ciphertext),
+        STANDARD.encode(iv)
+    ))
+}
+
+fn shared_key(secret_key: &SecretKey, pubkey: &XOnlyPublicKey) -> [u8; 32] {
+    let pubkey = PublicKey::from_x_only_public_key(*pubkey, Parity::Even);
+    let point = ecdh::shared_secret_point(&pubkey, secret_key);
+    point[..32]
+        .try_into()
+        .expect("a point has a 32 byte x coordinate")
+}
+
+/// Publishes `event` to `relay`, done once the relay accepted it.
+async fn publish(relay: &str, event: &Event) -> MintResult<()> {
+    let (mut stream, _) = tokio_tungstenite::connect_async(relay)
+        .await
+        .map_err(|e| MintError::Nostr(e.to_string()))?;
+    stream
+        .send(WsMessage::Text(json!(["EVENT", event]).to_string()))
+        .await
+        .map_err(|e| MintError::Nostr(e.to_string()))?;
+    while let Some(message) = stream.next().await {
+        let message = message.map_err(|e| MintError::Nostr(e.to_string()))?;
+        let WsMessage::Text(text) = message else {
+            continue;
+        };
+        // ["OK", <event id>, <accepted>, <message>], see NIP-20
+        let Ok(reply) = serde_json::from_str::<Vec<Value>>(&text) else {
+            continue;
+        };
+        if reply.first().and_then(Value::as_str) != Some("OK")
+            || reply.get(1).and_then(Value::as_str) != Some(&event.id)
+        {
+            debug!("Nostr: {} says {}", relay, text);
+            continue;
+        }
+        let _ = stream.close(None).await;
+        return match reply.get(2).and_then(Value::as_bool) {
+            Some(true) => Ok(()),
+            _ => Err(MintError::Nostr(format!(
+                "rejected: {}",
+                reply.get(3).and_then(Value::as_str).unwrap_or_default()
+            ))),
+        };
+    }
+    Err(MintError::Nostr("relay closed the connection".into()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use aes::cipher::BlockDecryptMut;
+
+    fn decrypt(secret_key: &SecretKey, sender: &XOnlyPublicKey, content: &str) -> String {
+        let (ciphertext, iv) = content.split_once("?iv=").unwrap();
+        let iv: [u8; 16] = STANDARD.decode(iv).unwrap().try_into().unwrap();
+        let plaintext =
+            cbc::Decryptor::<aes::Aes256>::new(&shared_key(secret_key, sender).into(), &iv.into())
+                .decrypt_padded_vec_mut::<Pkcs7>(&STANDARD.decode(ciphertext).unwrap())
+                .unwrap();
+        String::from_utf8(plaintext).unwrap()
+    }
+
+    #[test]
+    fn signs_messages_only_the_recipient_reads() {
+        let secp = Secp256k1::new();
+        let config = NostrConfig {
+            relays: vec!["wss://relay.example".into()],
+            secret_key_path: "".into(),
+            timeout_secs: 10,
+        };
+        let mint =
+            NostrDelivery::from_secret_key(&SecretKey::from_slice(&[1; 32]).unwrap(), &config);
+        let miner = SecretKey::from_slice(&[2; 32]).unwrap();
+        let (miner_pubkey, _) = miner.x_only_public_key(&secp);
+
+        let event = mint
+            .direct_message(&miner_pubkey, "cashuAeyJ0b2tlbiI6W119", 1_700_000_000)
+            .unwrap();
+        assert_eq!(event.kind, ENCRYPTED_DIRECT_MESSAGE);
+        assert_eq!(
+            event.tags,
+            vec![vec!["p".to_string(), miner_pubkey.to_string()]]
+        );
+        let id = event_id(
+            &event.pubkey,
+            event.created_at,
+            event.kind,
+            &event.tags,
+            &event.content,
+        );
+        assert_eq!(event.id, hex::encode(id));
+        let sig = event.sig.parse().unwrap();
+        assert!(secp
+            .verify_schnorr(&sig, &Message::from_digest(id), &mint.pubkey())
+            .is_ok());
+        assert_eq!(
+            decrypt(&miner, &mint.pubkey(), &event.content),
+            "cashuAeyJ0b2tlbiI6W119"
+        );
+        assert!(!event.content.contains("cashuA"));
+    }
+}
@@ -0,0 +1,440 @@
+//! Cashu wire types shared by the mint and the wallet, serialized the way the NUTs specify.
+use crate::error::MintError;
+use base64::{
+    engine::general_purpose::{URL_SAFE, URL_SAFE_NO_PAD},
+    Engine,
+};
+use secp256k1::{PublicKey, SecretKey, XOnlyPublicKey};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// An output the wallet wants signed: a blinded secret for a given amount and keyset.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlindedMessage {
+    pub amount: u64,
+    pub id: String,
+    #[serde(rename = "B_", with = "hex_pubkey")]
+    pub blinded_secret: PublicKey,
+}
+
+/// The mint's signature on a `BlindedMessage`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlindSignature {
+    pub amount: u64,
+    pub id: String,
+    #[serde(rename = "C_", with = "hex_pubkey")]
+    pub blinded_signature: PublicKey,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dleq: Option<DleqProof>,
+}
+
+/// NUT-12 proof that a `BlindSignature` was made with the key the keyset publishes for its
+/// amount, see `dhke::sign_dleq`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DleqProof {
+    #[serde(with = "hex_secret")]
+    pub e: SecretKey,
+    #[serde(with = "hex_secret")]
+    pub s: SecretKey,
+}
+
+/// An unblinded token, redeemable at the mint that signed it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Proof {
+    pub amount: u64,
+    pub id: String,
+    pub secret: String,
+    #[serde(rename = "C", with = "hex_pubkey")]
+    pub signature: PublicKey,
+    /// Signatures meeting the NUT-10 spending conditions of the secret, see `p2pk`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub witness: Option<String>,
+}
+
+/// A NUT-00 V3 token: proofs along with the mint they are redeemable at, passed around as a
+/// `cashuA` prefixed string.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Token {
+    pub token: Vec<TokenEntry>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unit: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memo: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenEntry {
+    pub mint: String,
+    pub proofs: Vec<Proof>,
+}
+
+impl Token {
+    const PREFIX: &'static str = "cashuA";
+
+    pub fn new(mint_url: &str, unit: &str, proofs: Vec<Proof>) -> Self {
+        Self {
+            token: vec![TokenEntry {
+                mint: mint_url.to_string(),
+                proofs,
+            }],
+            unit: Some(unit.to_string()),
+            memo: None,
+        }
+    }
+
+    pub fn amount(&self) -> u64 {
+        self.token
+            .iter()
+            .flat_map(|entry| &entry.proofs)
+            .map(|proof| proof.amount)
+            .sum()
+    }
+
+    pub fn encode(&self) -> Result<String, MintError> {
+        let json = serde_json::to_vec(self).map_err(|e| MintError::InvalidToken(e.to_string()))?;
+        Ok(format!("{}{}", Self::PREFIX, URL_SAFE.encode(json)))
+    }
+
+    pub fn decode(token: &str) -> Result<Self, MintError> {
+        let encoded = token
+            .trim()
+            .strip_prefix(Self::PREFIX)
+            .ok_or_else(|| MintError::InvalidToken("not a cashuA token".into()))?;
+        // wallets disagree on padding
+        let json = URL_SAFE_NO_PAD
+            .decode(encoded.trim_end_matches('='))
+            .map_err(|e| MintError::InvalidToken(e.to_string()))?;
+        serde_json::from_slice(&json).map_err(|e| MintError::InvalidToken(e.to_string()))
+    }
+}
+
+/// Public keys of a keyset, by amount (NUT-01).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeySet {
+    pub id: String,
+    pub unit: String,
+    pub keys: BTreeMap<u64, String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeysResponse {
+    pub keysets: Vec<KeySet>,
+}
+
+/// Summary of a keyset (NUT-02). Only active keysets sign new outputs.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeySetSummary {
+    pub id: String,
+    pub unit: String,
+    pub active: bool,
+    /// Fee per input of the keyset, in parts per thousand of a unit.
+    #[serde(default)]
+    pub input_fee_ppk: u64,
+    /// Difficulty epoch of an ehash keyset, see `epochs`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub epoch: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeysetsResponse {
+    pub keysets: Vec<KeySetSummary>,
+}
+
+/// Request for a quote to mint `amount` from the accrued balance of `account`. NUT-04 leaves the
+/// request body to the payment method, for the `ehash` method the balance is the payment.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MintQuoteRequest {
+    pub amount: u64,
+    pub unit: String,
+    pub account: String,
+    /// Proves the caller owns `account`, see `credentials`. Quotes at an external mint are
+    /// authorized by its API key instead.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub credential: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum MintQuoteState {
+    /// The account balance does not cover the quote (yet).
+    Unpaid,
+    Paid,
+    Issued,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MintQuoteResponse {
+    pub quote: String,
+    /// The account the quote is paid from.
+    pub request: String,
+    pub amount: u64,
+    pub unit: String,
+    pub state: MintQuoteState,
+    pub expiry: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MintRequest {
+    pub quote: String,
+    pub outputs: Vec<BlindedMessage>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MeltQuoteRequest {
+    /// Invoice to pay, or the address of an on-chain melt.
+    pub request: String,
+    pub unit: String,
+    /// Amount of an on-chain melt, invoices carry theirs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub amount: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum MeltQuoteState {
+    Unpaid,
+    /// Inputs are reserved and the invoice is being paid.
+    Pending,
+    Paid,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MeltQuoteResponse {
+    pub quote: String,
+    pub amount: u64,
+    pub fee_reserve: u64,
+    pub state: MeltQuoteState,
+    pub expiry: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payment_preimage: Option<String>,
+    /// Signatures on the blank outputs returning the unspent fee reserve (NUT-08).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub change: Option<Vec<BlindSignature>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum SpendState {
+    Unspent,
+    /// Reserved by a melt in progress.
+    Pending,
+    Spent,
+}
+
+/// NUT-07 state of a proof, identified by its `Y`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProofState {
+    #[serde(rename = "Y", with = "hex_pubkey")]
+    pub y: PublicKey,
+    pub state: SpendState,
+    /// Witness of the proof when it was spent, if known.
+    pub witness: Option<String>,
+}
+
+/// Asks for the states of the proofs `ys` (NUT-07).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CheckStateRequest {
+    #[serde(rename = "Ys", with = "hex_pubkeys")]
+    pub ys: Vec<PublicKey>,
+}
+
+/// States of the requested proofs, in the order of their `Y`s.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CheckStateResponse {
+    pub states: Vec<ProofState>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MeltRequest {
+    pub quote: String,
+    pub inputs: Vec<Proof>,
+    /// Blank outputs for the change, their amounts are set by the mint.
+    #[serde(default)]
+    pub outputs: Vec<BlindedMessage>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SwapRequest {
+    pub inputs: Vec<Proof>,
+    pub outputs: Vec<BlindedMessage>,
+}
+
+/// Response to both mint and swap requests.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignaturesResponse {
+    pub signatures: Vec<BlindSignature>,
+}
+
+/// Outputs a miner wants signed for `account` as its shares come in.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueueOutputsRequest {
+    pub account: String,
+    pub outputs: Vec<BlindedMessage>,
+    /// Proves the caller owns `account`, see `credentials`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub credential: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutputsRequest {
+    pub outputs: Vec<BlindedMessage>,
+}
+
+/// Asks for the balances `account` accrued at the mint.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BalanceRequest {
+    pub account: String,
+    /// Proves the caller owns `account`, see `credentials`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub credential: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BalanceResponse {
+    /// Balance per unit, of every unit of the mint.
+    pub balances: BTreeMap<String, u64>,
+}
+
+/// Asks for the statement of `account`, see `accounts`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountRequest {
+    pub account: String,
+    /// Proves the caller owns `account`, see `credentials`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub credential: Option<String>,
+}
+
+/// Picks up the payouts the mint holds for `account`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PayoutsRequest {
+    pub account: String,
+    /// Proves the caller owns `account`, see `credentials`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub credential: Option<String>,
+}
+
+/// Registers the key payouts of `account` are locked to (NUT-11), see `p2pk`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PubkeyRequest {
+    pub account: String,
+    #[serde(with = "hex_pubkey")]
+    pub pubkey: PublicKey,
+    /// Proves the caller owns `account`, see `credentials`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub credential: Option<String>,
+    /// Schnorr signature of the key already registered letting `pubkey` replace it, see
+    /// `credentials::verify_rebind`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+/// Registers the Nostr key payouts of `account` are sent to, see `nostr`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NostrKeyRequest {
+    pub account: String,
+    /// Hex encoded x-only key, as Nostr uses.
+    #[serde(with = "hex_xonly_pubkey")]
+    pub pubkey: XOnlyPublicKey,
+    /// Proves the caller owns `account`, see `credentials`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub credential: Option<String>,
+    /// Schnorr signature of the key already registered letting `pubkey` replace it, see
+    /// `credentials::verify_rebind`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PayoutsResponse {
+    /// `cashuA` encoded tokens, oldest first.
+    pub tokens: Vec<String>,
+}
+
+/// The requested outputs signed so far, each with its signature at the same index.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutputSignaturesResponse {
+    pub outputs: Vec<BlindedMessage>,
+    pub signatures: Vec<BlindSignature>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErrorResponse {
+    pub detail: String,
+    pub code: u16,
+    /// Stable code of the error, see `MintError::code`. Not in NUT-00, other mints leave it out.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub error: String,
+}
+
+pub(crate) mod hex_pubkey {
+    use secp256k1::PublicKey;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+    use std::str::FromStr;
+
+    pub fn serialize<S: Serializer>(key: &PublicKey, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&key.to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PublicKey, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        PublicKey::from_str(&hex).map_err(D::Error::custom)
+    }
+}
+
+pub(crate) mod hex_xonly_pubkey {
+    use secp256k1::XOnlyPublicKey;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+    use std::str::FromStr;
+
+    pub fn serialize<S: Serializer>(
+        key: &XOnlyPublicKey,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&key.to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<XOnlyPublicKey, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        XOnlyPublicKey::from_str(&hex).map_err(D::Error::custom)
+    }
+}
+
+pub(crate) mod hex_pubkeys {
+    use secp256k1::PublicKey;
+    use serde::{de::Error, ser::SerializeSeq, Deserialize, Deserializer, Serializer};
+    use std::str::FromStr;
+
+    pub fn serialize<S: Serializer>(keys: &[PublicKey], serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(keys.len()))?;
+        for key in keys {
+            seq.serialize_element(&key.to_string())?;
+        }
+        seq.end()
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<PublicKey>, D::Error> {
+        Vec::<String>::deserialize(deserializer)?
+            .iter()
+            .map(|hex| PublicKey::from_str(hex).map_err(D::Error::custom))
+            .collect()
+    }
+}
+
+pub(crate) mod hex_secret {
+    use secp256k1::SecretKey;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+    use std::str::FromStr;
+
+    pub fn serialize<S: Serializer>(key: &SecretKey, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(key.secret_bytes()))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<SecretKey, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        SecretKey::from_str(&hex).map_err(D::Error::custom)
+    }
+}
@@ -0,0 +1,135 @@
+//! Melts paying out to a Bitcoin address rather than a Lightning invoice, with the `onchain`
+//! method. The quote names the address and the amount melted. Melting spends the inputs right
+//! away and queues the payout, less `fee_sat` for its share of the transaction fee. Queued
+//! payouts are sent in one batched transaction of the Bitcoin Core wallet every `interval_secs`
+//! (see `onchain_batch`), which settles their quotes as paid, with the txid as preimage.
+//!
+//! The batch is recorded before it is broadcast, so a crash in between broadcasts the same
+//! transaction again rather than paying twice.
+use crate::error::{MintError, MintResult};
+use serde::Deserialize;
+use std::str::FromStr;
+use stratum_common::bitcoin::{Address, Network};
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct OnchainConfig {
+    /// Network of the addresses paid out to: "bitcoin", "testnet", "signet" or "regtest".
+    #[serde(default = "OnchainConfig::default_network")]
+    pub network: String,
+    /// Smallest amount melted, in sat.
+    #[serde(default = "OnchainConfig::default_min_amount")]
+    pub min_amount: u64,
+    /// Deducted from every payout for its share of the batch transaction fee, in sat.
+    #[serde(default = "OnchainConfig::default_fee_sat")]
+    pub fee_sat: u64,
+    /// How often queued payouts are sent.
+    #[serde(default = "OnchainConfig::default_interval_secs")]
+    pub interval_secs: u64,
+}
+
+impl Default for OnchainConfig {
+    fn default() -> Self {
+        Self {
+            network: Self::default_network(),
+            min_amount: Self::default_min_amount(),
+            fee_sat: Self::default_fee_sat(),
+            interval_secs: Self::default_interval_secs(),
+        }
+    }
+}
+
+impl OnchainConfig {
+    fn default_network() -> String {
+        "bitcoin".to_string()
+    }
+
+    fn default_min_amount() -> u64 {
+        10_000
+    }
+
+    fn default_fee_sat() -> u64 {
+        500
+    }
+
+    fn default_interval_secs() -> u64 {
+        3600
+    }
+
+    /// Checks that `address` is one of the configured network.
+    pub fn check_address(&self, address: &str) -> MintResult<()> {
+        let network = Network::from_str(&self.network)
+            .map_err(|_| MintError::Onchain(format!("unknown network {}", self.network)))?;
+        let parsed = Address::from_str(address)
+            .map_err(|e| MintError::Onchain(format!("invalid address {}: {}", address, e)))?;
+        if !parsed.is_valid_for_network(network) {
+            return Err(MintError::Onchain(format!(
+                "{} is not a {} address",
+                address, self.network
+            )));
+        }
+        Ok(())
+    }
+
+    /// Amount sent to the address of a melt of `amount`, once the fee is deducted.
+    pub fn payout_amount(&self, amount: u64) -> MintResult<u64> {
+        if amount < self.min_amount {
+            return Err(MintError::Onchain(format!(
+                "{} sat is below the minimum of {} sat",
+                amount, self.min_amount
+            )));
+        }
+        match amount.checked_sub(self.fee_sat) {
+            Some(payout) if payout > 0 => Ok(payout),
+            _ => Err(MintError::Onchain(format!(
+                "{} sat don't cover the fee of {} sat",
+                amount, self.fee_sat
+            ))),
+        }
+    }
+}
+
+/// A payout waiting for its batch transaction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OnchainPayout {
+    pub quote_id: String,
+    pub address: String,
+    /// Sent to the address, in sat.
+    pub amount: u64,
+}
+
+/// A batch transaction recorded before its broadcast, see `Mint::record_onchain_batch`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OnchainBatch {
+    pub txid: String,
+    /// Hex of the signed transaction.
+    pub tx: String,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn checks_addresses_and_amounts() {
+        let config = OnchainConfig {
+            network: "regtest".into(),
+            min_amount: 1000,
+            fee_sat: 200,
+            ..Default::default()
+        };
+        assert!(config
+            .check_address("bcrt1qs758ursh4q9z627kt3pp5yysm78ddny6txaqgw")
+            .is_ok());
+        assert!(config
+            .check_address("bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq")
+            .is_err());
+        assert!(config.check_address("potato").is_err());
+        assert_eq!(config.payout_amount(1000).unwrap(), 800);
+        assert!(config.payout_amount(999).is_err());
+        let expensive = OnchainConfig {
+            fee_sat: 1000,
+            ..config
+        };
+        assert!(expensive.payout_amount(1000).is_err());
+    }
+}
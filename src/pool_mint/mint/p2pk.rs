@@ -0,0 +1,364 @@
+//! Spending conditions of NUT-10 secrets: pay to public key (NUT-11) and hashed timelock
+//! contracts (NUT-14). A P2PK secret names the keys whose Schnorr signatures on the secret must
+//! come with the proof, so a token locked to a miner can't be spent by whoever intercepts it.
+//! Automatic payouts are locked to the key registered for the account, if any (see
+//! `Mint::register_pubkey`).
+//!
+//! An HTLC secret locks a token to a hash instead, spent by revealing its preimage, along with
+//! the signatures of the keys of its `pubkeys` tag if it has one. Locked to the payment hash of a
+//! Lightning invoice, a token goes to whoever pays the invoice, and with a refund key back to its
+//! seller once the locktime passed, so hashrate buyers and miners trade ehash for sat without
+//! trusting each other (see `wallet`).
+//!
+//! Only `SIG_INPUTS` is supported: every input is signed on its own, outputs are not signed.
+use crate::error::{MintError, MintResult};
+use secp256k1::{schnorr::Signature, Keypair, Message, PublicKey, Secp256k1, SecretKey};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::str::FromStr;
+
+const P2PK: &str = "P2PK";
+const HTLC: &str = "HTLC";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SecretData {
+    nonce: String,
+    data: String,
+    #[serde(default)]
+    tags: Vec<Vec<String>>,
+}
+
+/// What unlocks a proof with spending conditions, sent JSON encoded as the proof `witness`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Witness {
+    /// Hex encoded preimage of the hash an HTLC secret is locked to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preimage: Option<String>,
+    #[serde(default)]
+    pub signatures: Vec<String>,
+}
+
+/// The conditions of a P2PK or HTLC secret.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conditions {
+    /// The hash an HTLC secret is locked to, `None` for P2PK.
+    pub hash: Option<[u8; 32]>,
+    /// The key of the secret data (P2PK only) followed by those of the `pubkeys` tag.
+    pub pubkeys: Vec<PublicKey>,
+    pub n_sigs: usize,
+    pub locktime: Option<u64>,
+    pub refund: Vec<PublicKey>,
+}
+
+impl Conditions {
+    /// Conditions of `secret`, `None` for a secret that isn't a NUT-10 secret.
+    pub fn parse(secret: &str) -> MintResult<Option<Self>> {
+        if !secret.starts_with('[') {
+            return Ok(None);
+        }
+        let Ok((kind, data)) = serde_json::from_str::<(String, SecretData)>(secret) else {
+            return Ok(None);
+        };
+        let mut conditions = match kind.as_str() {
+            P2PK => Self::p2pk(parse_pubkey(&data.data)?),
+            HTLC => {
+                let hash = hex::decode(&data.data)
+                    .ok()
+                    .and_then(|hash| <[u8; 32]>::try_from(hash).ok())
+                    .ok_or_else(|| invalid(format!("invalid hash lock {}", data.data)))?;
+                Self::htlc(hash)
+            }
+            _ => return Err(invalid(format!("unsupported secret kind {}", kind))),
+        };
+        for tag in &data.tags {
+            let Some((name, values)) = tag.split_first() else {
+                continue;
+            };
+            match name.as_str() {
+                "sigflag" if values.first().map(String::as_str) != Some("SIG_INPUTS") => {
+                    return Err(invalid(format!("unsupported sigflag {:?}", values)));
+                }
+                "n_sigs" => conditions.n_sigs = parse_number(values)? as usize,
+                "locktime" => conditions.locktime = Some(parse_number(values)?),
+                "pubkeys" => conditions.pubkeys.extend(
+                    values
+                        .iter()
+                        .map(|key| parse_pubkey(key))
+                        .collect::<MintResult<Vec<_>>>()?,
+                ),
+                "refund" => {
+                    conditions.refund = values
+                        .iter()
+                        .map(|key| parse_pubkey(key))
+                        .collect::<MintResult<_>>()?
+                }
+                _ => {}
+            }
+        }
+        Ok(Some(conditions))
+    }
+
+    /// Locked to `pubkey`.
+    pub fn p2pk(pubkey: PublicKey) -> Self {
+        Self {
+            hash: None,
+            pubkeys: vec![pubkey],
+            n_sigs: 1,
+            locktime: None,
+            refund: vec![],
+        }
+    }
+
+    /// Locked to the preimage of `hash`, e.g. the payment hash of a Lightning invoice.
+    pub fn htlc(hash: [u8; 32]) -> Self {
+        Self {
+            hash: Some(hash),
+            pubkeys: vec![],
+            n_sigs: 1,
+            locktime: None,
+            refund: vec![],
+        }
+    }
+
+    /// A new secret with these conditions.
+    pub fn secret(&self) -> String {
+        let (kind, data, pubkeys) = match self.hash {
+            Some(hash) => (HTLC, hex::encode(hash), &self.pubkeys[..]),
+            None => (P2PK, self.pubkeys[0].to_string(), &self.pubkeys[1..]),
+        };
+        let keys_tag = |name: &str, keys: &[PublicKey]| {
+            let mut tag = vec![name.to_string()];
+            tag.extend(keys.iter().map(PublicKey::to_string));
+            tag
+        };
+        let mut tags = vec![];
+        if !pubkeys.is_empty() {
+            tags.push(keys_tag("pubkeys", pubkeys));
+        }
+        if self.n_sigs != 1 {
+            tags.push(vec!["n_sigs".to_string(), self.n_sigs.to_string()]);
+        }
+        if let Some(locktime) = self.locktime {
+            tags.push(vec!["locktime".to_string(), locktime.to_string()]);
+        }
+        if !self.refund.is_empty() {
+            tags.push(keys_tag("refund", &self.refund));
+        }
+        json!([
+            kind,
+            {
+                "nonce": hex::encode(rand::random::<[u8; 32]>()),
+                "data": data,
+                "tags": tags,
+            }
+        ])
+        .to_string()
+    }
+}
+
+/// A NUT-10 secret locking a token to `pubkey`.
+pub fn lock_to(pubkey: &PublicKey) -> String {
+    Conditions::p2pk(*pubkey).secret()
+}
+
+/// Checks that `witness` meets the spending conditions of `secret`, if it has any. Once the
+/// locktime passed, a signature of a refund key is enough too, or nothing if there is no refund
+/// key.
+pub fn verify(secret: &str, witness: Option<&str>, now: u64) -> MintResult<()> {
+    let Some(conditions) = Conditions::parse(secret)? else {
+        return Ok(());
+    };
+    let witness: Witness = serde_json::from_str(witness.unwrap_or("{}"))
+        .map_err(|e| invalid(format!("invalid witness: {}", e)))?;
+    let signatures: Vec<Signature> = witness
+        .signatures
+        .iter()
+        .filter_map(|signature| Signature::from_str(signature).ok())
+        .collect();
+    let message = message(secret);
+    if conditions.locktime.is_some_and(|locktime| now >= locktime)
+        && (conditions.refund.is_empty() || signed(&conditions.refund, &signatures, &message) > 0)
+    {
+        return Ok(());
+    }
+    if let Some(hash) = conditions.hash {
+        let preimage = witness
+            .preimage
+            .as_deref()
+            .and_then(|preimage| hex::decode(preimage).ok())
+            .ok_or_else(|| invalid("missing preimage".to_string()))?;
+        if Sha256::digest(preimage).as_slice() != hash {
+            return Err(invalid("preimage doesn't match the hash lock".to_string()));
+        }
+    }
+    // an HTLC without keys only takes the preimage
+    let needed = match conditions.pubkeys.is_empty() {
+        true => 0,
+        false => conditions.n_sigs,
+    };
+    let signed = signed(&conditions.pubkeys, &signatures, &message);
+    match signed >= needed {
+        true => Ok(()),
+        false => Err(invalid(format!(
+            "{} of {} required signatures",
+            signed, needed
+        ))),
+    }
+}
+
+/// Wallet side: the witness of a proof with `secret` signed by `key`.
+pub fn sign(secret: &str, key: &SecretKey) -> String {
+    unlock(secret, key, None)
+}
+
+/// Wallet side: the witness of a proof with `secret` signed by `key`, revealing the `preimage`
+/// (hex) of an HTLC secret if given.
+pub fn unlock(secret: &str, key: &SecretKey, preimage: Option<&str>) -> String {
+    let secp = Secp256k1::new();
+    let signature = secp.sign_schnorr(&message(secret), &Keypair::from_secret_key(&secp, key));
+    let witness = Witness {
+        preimage: preimage.map(str::to_string),
+        signatures: vec![signature.to_string()],
+    };
+    serde_json::to_string(&witness).expect("witness serializes")
+}
+
+/// How many of `keys` signed `message` among `signatures`.
+fn signed(keys: &[PublicKey], signatures: &[Signature], message: &Message) -> usize {
+    let secp = Secp256k1::verification_only();
+    keys.iter()
+        .filter(|key| {
+            let (key, _) = key.x_only_public_key();
+            signatures
+                .iter()
+                .any(|signature| secp.verify_schnorr(signature, message, &key).is_ok())
+        })
+        .count()
+}
+
+/// What is signed: the sha256 of the secret.
+fn message(secret: &str) -> Message {
+    Message::from_digest(Sha256::digest(secret.as_bytes()).into())
+}
+
+fn parse_pubkey(key: &str) -> MintResult<PublicKey> {
+    PublicKey::from_str(key).map_err(|e| invalid(format!("invalid public key {}: {}", key, e)))
+}
+
+fn parse_number(values: &[String]) -> MintResult<u64> {
+    values
+        .first()
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| invalid(format!("invalid number {:?}", values)))
+}
+
+fn invalid(reason: String) -> MintError {
+    MintError::SpendingConditions(reason)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn key(byte: u8) -> (SecretKey, PublicKey) {
+        let secret = SecretKey::from_slice(&[byte; 32]).unwrap();
+        (secret, secret.public_key(&Secp256k1::new()))
+    }
+
+    #[test]
+    fn requires_the_locking_key() {
+        let (alice, alice_pub) = key(1);
+        let (mallory, _) = key(2);
+        let secret = lock_to(&alice_pub);
+        assert_eq!(
+            Conditions::parse(&secret).unwrap().unwrap().pubkeys,
+            vec![alice_pub]
+        );
+        assert!(verify("plain secret", None, 0).is_ok());
+        assert!(verify(&secret, None, 0).is_err());
+        assert!(verify(&secret, Some(&sign(&secret, &mallory)), 0).is_err());
+        // a signature on another secret doesn't unlock this one
+        let other = lock_to(&alice_pub);
+        assert!(verify(&secret, Some(&sign(&other, &alice)), 0).is_err());
+        assert!(verify(&secret, Some(&sign(&secret, &alice)), 0).is_ok());
+    }
+
+    #[test]
+    fn counts_signatures_and_honors_locktime() {
+        let (alice, alice_pub) = key(1);
+        let (bob, bob_pub) = key(2);
+        let (carol, carol_pub) = key(3);
+        let secret = json!([
+            "P2PK",
+            {
+                "nonce": "00",
+                "data": alice_pub.to_string(),
+                "tags": [
+                    ["pubkeys", bob_pub.to_string()],
+                    ["n_sigs", "2"],
+                    ["locktime", "100"],
+                    ["refund", carol_pub.to_string()],
+                ],
+            }
+        ])
+        .to_string();
+        let witness = |keys: &[&SecretKey]| {
+            let signatures: Vec<String> = keys
+                .iter()
+                .map(|key| {
+                    let witness: Witness = serde_json::from_str(&sign(&secret, key)).unwrap();
+                    witness.signatures[0].clone()
+                })
+                .collect();
+            serde_json::to_string(&Witness {
+                preimage: None,
+                signatures,
+            })
+            .unwrap()
+        };
+        assert!(verify(&secret, Some(&witness(&[&alice])), 0).is_err());
+        assert!(verify(&secret, Some(&witness(&[&alice, &alice])), 0).is_err());
+        assert!(verify(&secret, Some(&witness(&[&alice, &bob])), 0).is_ok());
+        assert!(verify(&secret, Some(&witness(&[&carol])), 0).is_err());
+        assert!(verify(&secret, Some(&witness(&[&carol])), 100).is_ok());
+
+        let unsupported = secret.replace("\"n_sigs\"", "\"sigflag\",\"SIG_ALL\"],[\"n_sigs\"");
+        assert!(matches!(
+            verify(&unsupported, None, 0),
+            Err(MintError::SpendingConditions(_))
+        ));
+    }
+
+    #[test]
+    fn unlocks_htlcs_with_the_preimage() {
+        let (buyer, buyer_pub) = key(1);
+        let (seller, seller_pub) = key(2);
+        let preimage = [7; 32];
+        let mut conditions = Conditions::htlc(Sha256::digest(preimage).into());
+        let anyone = conditions.secret();
+        assert_eq!(
+            Conditions::parse(&anyone).unwrap(),
+            Some(conditions.clone())
+        );
+        assert!(verify(&anyone, None, 0).is_err());
+        assert!(verify(&anyone, Some(&unlock(&anyone, &seller, Some("0707"))), 0).is_err());
+        let revealed = unlock(&anyone, &seller, Some(&hex::encode(preimage)));
+        assert!(verify(&anyone, Some(&revealed), 0).is_ok());
+
+        conditions.pubkeys = vec![buyer_pub];
+        conditions.locktime = Some(100);
+        conditions.refund = vec![seller_pub];
+        let secret = conditions.secret();
+        assert_eq!(Conditions::parse(&secret).unwrap(), Some(conditions));
+        // the preimage alone doesn't do, the buyer has to sign too
+        let revealed = unlock(&secret, &seller, Some(&hex::encode(preimage)));
+        assert!(verify(&secret, Some(&revealed), 0).is_err());
+        let revealed = unlock(&secret, &buyer, Some(&hex::encode(preimage)));
+        assert!(verify(&secret, Some(&revealed), 0).is_ok());
+        // the seller gets it back after the locktime, the buyer can still redeem it
+        assert!(verify(&secret, Some(&sign(&secret, &seller)), 99).is_err());
+        assert!(verify(&secret, Some(&sign(&secret, &seller)), 100).is_ok());
+        assert!(verify(&secret, Some(&revealed), 100).is_ok());
+    }
+}
@@ -0,0 +1,183 @@
+//! Automatic payouts: instead of waiting for a miner to withdraw, the mint periodically turns
+//! the balance of an account into a token it mints itself. The token is then either delivered
+//! over the miner's stratum connection with the `PAYOUT_EXTENSION_TYPE` extension message, sent
+//! as a Nostr direct message (see `nostr`), or held until picked up from the mint API.
+//!
+//! The mint knows the secrets of the tokens it minted this way, so miners should swap them for
+//! fresh ones once received.
+//!
+//! Tokens take a proof per power of two of their amount. With `share_weighted` denominations, an
+//! ehash payout leaves out the part of the balance below the typical share weight of the
+//! account, which only adds proofs for amounts worth less than a share; it stays in the balance
+//! for the next payout.
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// SV2 extension carrying payouts from the pool to the proxy.
+pub const PAYOUT_EXTENSION_TYPE: u16 = 0x4548;
+/// Message of `PAYOUT_EXTENSION_TYPE`, its payload being the channel id (u32 LE) followed by the
+/// UTF-8 encoded token.
+pub const MESSAGE_TYPE_PAYOUT: u8 = 0x01;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PayoutMode {
+    /// Tokens are only issued when the miner asks for them.
+    #[default]
+    Manual,
+    /// Tokens are minted and kept at the mint until picked up.
+    Hold,
+    /// Tokens are minted and pushed over the stratum connection of the account, or held while it
+    /// is not connected.
+    Stratum,
+    /// Tokens are minted and sent as encrypted Nostr messages to the key the account registered,
+    /// or held while it has none, see `nostr`.
+    Nostr,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PayoutConfig {
+    /// Mode of accounts not listed in `accounts`.
+    #[serde(default)]
+    pub mode: PayoutMode,
+    /// Mode per account.
+    #[serde(default)]
+    pub accounts: HashMap<String, PayoutMode>,
+    /// Unit paid out, a sat payout only happens once rewards matured.
+    #[serde(default = "PayoutConfig::default_unit")]
+    pub unit: String,
+    /// Smallest balance paid out, so miners don't get a token for every share.
+    #[serde(default = "PayoutConfig::default_min_amount")]
+    pub min_amount: u64,
+    #[serde(default = "PayoutConfig::default_interval_secs")]
+    pub interval_secs: u64,
+    #[serde(default)]
+    pub denominations: Denominations,
+}
+
+/// How much of a balance a payout takes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Denominations {
+    /// The whole balance.
+    #[default]
+    Exact,
+    /// Ehash balances in multiples of the typical share weight of the account, see
+    /// `share_weighted_amount`.
+    ShareWeighted,
+}
+
+impl PayoutConfig {
+    pub fn mode(&self, account: &str) -> PayoutMode {
+        self.accounts.get(account).copied().unwrap_or(self.mode)
+    }
+
+    /// Whether any account is paid out without asking.
+    pub fn is_enabled(&self) -> bool {
+        self.mode != PayoutMode::Manual
+            || self
+                .accounts
+                .values()
+                .any(|mode| *mode != PayoutMode::Manual)
+    }
+
+    /// Whether any account is paid out in `mode`.
+    pub fn has_mode(&self, mode: PayoutMode) -> bool {
+        self.mode == mode || self.accounts.values().any(|m| *m == mode)
+    }
+
+    fn default_unit() -> String {
+        super::SAT_UNIT.to_string()
+    }
+
+    fn default_min_amount() -> u64 {
+        1000
+    }
+
+    fn default_interval_secs() -> u64 {
+        600
+    }
+}
+
+impl Default for PayoutConfig {
+    fn default() -> Self {
+        Self {
+            mode: PayoutMode::default(),
+            accounts: HashMap::new(),
+            unit: Self::default_unit(),
+            min_amount: Self::default_min_amount(),
+            interval_secs: Self::default_interval_secs(),
+            denominations: Denominations::default(),
+        }
+    }
+}
+
+/// A token minted for an account, kept until it is delivered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Payout {
+    pub id: String,
+    pub account: String,
+    pub unit: String,
+    pub amount: u64,
+    /// `cashuA` encoded token.
+    pub token: String,
+    pub created_at: u64,
+    pub delivered_at: Option<u64>,
+}
+
+/// Payouts of `unit` over some period, see `MintDb::payout_totals`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PayoutTotal {
+    pub unit: String,
+    pub count: u64,
+    pub amount: u64,
+    /// Those of them delivered by now.
+    pub delivered: u64,
+}
+
+/// Part of `balance` paid out to an account whose shares typically weigh `share_weight`: a
+/// multiple of the largest power of two not above the weight, so the token has no proofs worth
+/// less than a share.
+pub fn share_weighted_amount(balance: u64, share_weight: u64) -> u64 {
+    let unit = 1u64 << (63 - share_weight.max(1).leading_zeros());
+    balance - balance % unit
+}
+
+/// The SV2 extension frame payload of a payout to `channel_id`.
+pub fn encode_payout_message(channel_id: u32, token: &str) -> Vec<u8> {
+    [&channel_id.to_le_bytes()[..], token.as_bytes()].concat()
+}
+
+/// Channel id and token of a payout extension message.
+pub fn decode_payout_message(payload: &[u8]) -> Option<(u32, String)> {
+    if payload.len() < 4 {
+        return None;
+    }
+    let (channel_id, token) = payload.split_at(4);
+    let channel_id = u32::from_le_bytes(channel_id.try_into().ok()?);
+    Some((channel_id, String::from_utf8(token.to_vec()).ok()?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pool_mint::mint::amounts::{self, AmountStrategy};
+
+    #[test]
+    fn splits_into_denominations() {
+        // shares of weight 300 make a 256 unit
+        assert_eq!(share_weighted_amount(1000, 300), 768);
+        let amounts = AmountStrategy::MaxOrder(32).amounts().unwrap();
+        assert_eq!(amounts::split(768, &amounts).unwrap(), vec![512, 256]);
+        assert_eq!(share_weighted_amount(200, 300), 0);
+        assert_eq!(share_weighted_amount(13, 1), 13);
+        assert_eq!(share_weighted_amount(13, 0), 13);
+
+        let payload = encode_payout_message(7, "cashuAabc");
+        assert_eq!(
+            decode_payout_message(&payload),
+            Some((7, "cashuAabc".to_string()))
+        );
+        assert_eq!(decode_payout_message(&payload[..3]), None);
+    }
+}
@@ -0,0 +1,98 @@
+//! Mint quotes of the `ehash` payment method. A quote names an account, an amount and a unit, it
+//! counts as paid as soon as the account's balance in that unit covers the amount and is issued
+//! once the tokens are signed.
+//!
+//! Melt quotes of the `bolt11` method go the other way: they are paid by the mint once the wallet
+//! handed in tokens covering the invoice and the fee reserve. Those of the `onchain` method are
+//! paid to an address by the next batch transaction, see `onchain`.
+use super::{
+    api::{BOLT11_METHOD, ONCHAIN_METHOD},
+    nuts::MeltQuoteState,
+};
+use rand::RngCore;
+
+/// How long a wallet has to mint the tokens of a quote.
+pub const MINT_QUOTE_EXPIRY_SECS: u64 = 3600;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MintQuote {
+    pub id: String,
+    pub account: String,
+    pub amount: u64,
+    pub unit: String,
+    pub expiry: u64,
+    pub issued: bool,
+}
+
+/// How long a wallet has to melt tokens for a quote.
+pub const MELT_QUOTE_EXPIRY_SECS: u64 = 600;
+
+pub(super) fn random_id() -> String {
+    let mut id = [0; 16];
+    rand::thread_rng().fill_bytes(&mut id);
+    hex::encode(id)
+}
+
+impl MintQuote {
+    pub fn new(account: &str, amount: u64, unit: &str, now: u64) -> Self {
+        Self {
+            id: random_id(),
+            account: account.to_string(),
+            amount,
+            unit: unit.to_string(),
+            expiry: now + MINT_QUOTE_EXPIRY_SECS,
+            issued: false,
+        }
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        now >= self.expiry
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MeltQuote {
+    pub id: String,
+    /// The bolt11 invoice to pay, or the address of an on-chain melt.
+    pub request: String,
+    pub payment_hash: String,
+    pub amount: u64,
+    pub fee_reserve: u64,
+    pub state: MeltQuoteState,
+    pub expiry: u64,
+    pub payment_preimage: Option<String>,
+    /// Fee paid by the inputs reserved for the melt, see `fees`.
+    pub input_fee: u64,
+    /// Payment method, "bolt11" or "onchain".
+    pub method: String,
+}
+
+impl MeltQuote {
+    pub fn new(request: &str, payment_hash: &str, amount: u64, fee_reserve: u64, now: u64) -> Self {
+        Self {
+            id: random_id(),
+            request: request.to_string(),
+            payment_hash: payment_hash.to_string(),
+            amount,
+            fee_reserve,
+            state: MeltQuoteState::Unpaid,
+            expiry: now + MELT_QUOTE_EXPIRY_SECS,
+            payment_preimage: None,
+            input_fee: 0,
+            method: BOLT11_METHOD.to_string(),
+        }
+    }
+
+    /// Quote of an on-chain melt of `amount` to `address`. Its fee is deducted from the payout
+    /// rather than reserved.
+    pub fn onchain(address: &str, amount: u64, now: u64) -> Self {
+        Self {
+            method: ONCHAIN_METHOD.to_string(),
+            ..Self::new(address, "", amount, 0, now)
+        }
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        now >= self.expiry
+    }
+}
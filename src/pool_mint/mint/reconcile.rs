@@ -0,0 +1,205 @@
+//! Reconciliation of the share accounting against what the mint credited. Every round records
+//! what it credited each of its accounts: ehash as shares come in, sat once it matured. Both
+//! are written in the transaction updating `round_shares`, so they only disagree after a bug or a
+//! hand edit of the database, which is what `check` looks for round by round:
+//!
+//! - shares never credited: ehash credited short of the round's shares, or a matured round that
+//!   never credited an account its sat payout;
+//! - credits without shares: ehash beyond the round's shares, or sat beyond the payout of the
+//!   account's shares, sat credited by a round not matured included.
+//!
+//! The sat of a matured round may fall short of the payout without it being a discrepancy, by
+//! the ehash withdrawn before it matured (see `rounds`).
+//!
+//! With `interval_secs` set the pool reconciles on schedule, also served by the control API
+//! `reconcile`. In repair mode the shares never credited are credited; credits without shares may
+//! have been withdrawn already, so they are only reported for the operator to look into.
+use super::{
+    rounds::{split_reward, Round, RoundState},
+    EHASH_UNIT, SAT_UNIT,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct ReconcileConfig {
+    /// Seconds between two scheduled reconciliations, none if unset.
+    #[serde(default)]
+    pub interval_secs: Option<u64>,
+    /// Credits the shares scheduled reconciliations find never credited.
+    #[serde(default)]
+    pub repair: bool,
+}
+
+/// Shares and credits of one round, see `MintDb::round_books`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoundBooks {
+    pub round: Round,
+    /// Share weight per account.
+    pub shares: BTreeMap<String, u64>,
+    /// Ehash credited per account.
+    pub ehash: BTreeMap<String, u64>,
+    /// Sat credited per account once the round matured.
+    pub sat: BTreeMap<String, u64>,
+}
+
+impl RoundBooks {
+    pub fn new(round: Round) -> Self {
+        Self {
+            round,
+            shares: BTreeMap::new(),
+            ehash: BTreeMap::new(),
+            sat: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiscrepancyKind {
+    UncreditedShares,
+    CreditWithoutShares,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Discrepancy {
+    pub round_id: u64,
+    pub account: String,
+    pub unit: String,
+    pub kind: DiscrepancyKind,
+    /// What the shares of the account in the round are worth, the payout for sat.
+    pub expected: u64,
+    pub credited: u64,
+}
+
+impl Discrepancy {
+    /// What crediting the shares would take, zero for credits without shares.
+    pub fn missing(&self) -> u64 {
+        match self.kind {
+            DiscrepancyKind::UncreditedShares => self.expected - self.credited,
+            DiscrepancyKind::CreditWithoutShares => 0,
+        }
+    }
+}
+
+/// Result of a reconciliation, served by the control API.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ReconcileReport {
+    pub rounds: usize,
+    pub discrepancies: Vec<Discrepancy>,
+    /// Discrepancies repaired by crediting the shares.
+    pub repaired: usize,
+}
+
+/// Discrepancies between the shares and credits of `books`, its sat only checked if `sat` is
+/// issued.
+pub fn check(books: &RoundBooks, sat: bool) -> Vec<Discrepancy> {
+    let mut discrepancies = compare(books, EHASH_UNIT, &books.shares, &books.ehash, false);
+    if !sat {
+        return discrepancies;
+    }
+    let payouts: BTreeMap<String, u64> = match (books.round.state, books.round.reward) {
+        (RoundState::Matured, Some(reward)) => {
+            let shares: Vec<_> = books.shares.clone().into_iter().collect();
+            split_reward(reward, &shares).into_iter().collect()
+        }
+        _ => BTreeMap::new(),
+    };
+    discrepancies.extend(compare(books, SAT_UNIT, &payouts, &books.sat, true));
+    discrepancies
+}
+
+/// Compares what every account is `due` with what it was `credited`. With `short_allowed`, a
+/// credit short of what is due only counts as missing if there is none at all.
+fn compare(
+    books: &RoundBooks,
+    unit: &str,
+    due: &BTreeMap<String, u64>,
+    credited: &BTreeMap<String, u64>,
+    short_allowed: bool,
+) -> Vec<Discrepancy> {
+    let accounts: BTreeSet<&String> = due.keys().chain(credited.keys()).collect();
+    accounts
+        .into_iter()
+        .filter_map(|account| {
+            let expected = due.get(account).copied().unwrap_or_default();
+            let credit = credited.get(account).copied();
+            let kind = match credit {
+                Some(credit) if credit > expected => DiscrepancyKind::CreditWithoutShares,
+                Some(credit) if credit < expected && !short_allowed => {
+                    DiscrepancyKind::UncreditedShares
+                }
+                None if expected > 0 => DiscrepancyKind::UncreditedShares,
+                _ => return None,
+            };
+            Some(Discrepancy {
+                round_id: books.round.id,
+                account: account.clone(),
+                unit: unit.to_string(),
+                kind,
+                expected,
+                credited: credit.unwrap_or_default(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn books(state: RoundState) -> RoundBooks {
+        let mut books = RoundBooks::new(Round {
+            id: 1,
+            state,
+            started_at: 0,
+            ended_at: None,
+            coinbase_txid: None,
+            reward: Some(100),
+            height: None,
+            block_hash: None,
+            finder: None,
+        });
+        books.shares.insert("alice".into(), 3);
+        books.shares.insert("bob".into(), 1);
+        books.ehash.insert("alice".into(), 3);
+        books.ehash.insert("bob".into(), 1);
+        books
+    }
+
+    #[test]
+    fn flags_shares_and_credits_that_disagree() {
+        let mut open = books(RoundState::Open);
+        assert!(check(&open, true).is_empty());
+        open.ehash.insert("alice".into(), 2);
+        open.ehash.insert("carol".into(), 5);
+        open.sat.insert("bob".into(), 1);
+        let discrepancies = check(&open, true);
+        let found: Vec<_> = discrepancies
+            .iter()
+            .map(|d| (d.account.as_str(), d.unit.as_str(), d.kind, d.missing()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("alice", EHASH_UNIT, DiscrepancyKind::UncreditedShares, 1),
+                ("carol", EHASH_UNIT, DiscrepancyKind::CreditWithoutShares, 0),
+                ("bob", SAT_UNIT, DiscrepancyKind::CreditWithoutShares, 0),
+            ]
+        );
+
+        let mut matured = books(RoundState::Matured);
+        // alice withdrew some of her ehash before the round matured
+        matured.sat.insert("alice".into(), 40);
+        let discrepancies = check(&matured, true);
+        assert_eq!(discrepancies.len(), 1);
+        assert_eq!(discrepancies[0].account, "bob");
+        assert_eq!(discrepancies[0].missing(), 25);
+        assert!(check(&matured, false).is_empty());
+        matured.sat.insert("bob".into(), 26);
+        assert_eq!(
+            check(&matured, true)[0].kind,
+            DiscrepancyKind::CreditWithoutShares
+        );
+    }
+}
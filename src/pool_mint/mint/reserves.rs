@@ -0,0 +1,140 @@
+//! Reserves backing the sat the mint owes. Sat is only credited once the block closing a round
+//! matured, so the liabilities should be covered by the matured rewards left after melts and fees.
+//! The rewards of immature blocks come next: they back the liabilities once matured, unless the
+//! block is orphaned first.
+//!
+//! With `min_ratio_ppk` set, no sat tokens are issued while the backing covers less than that
+//! share of the liabilities, so a shortfall (see `audit`) stops growing the tokens in circulation.
+//! Only matured rewards back issuance, unless `backing` is `immature`.
+use super::audit::SatAudit;
+use crate::error::{MintError, MintResult};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Backing {
+    /// Rewards of matured blocks only.
+    #[default]
+    Matured,
+    /// Rewards of blocks not matured yet too.
+    Immature,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct ReserveConfig {
+    /// Smallest reserve ratio sat tokens are issued at, in parts per thousand of the liabilities.
+    /// Unlimited if unset.
+    #[serde(default)]
+    pub min_ratio_ppk: Option<u64>,
+    #[serde(default)]
+    pub backing: Backing,
+}
+
+impl ReserveConfig {
+    /// Checks `reserves` allow issuing sat tokens.
+    pub fn check(&self, reserves: &ReserveReport) -> MintResult<()> {
+        let Some(min_ratio_ppk) = self.min_ratio_ppk else {
+            return Ok(());
+        };
+        let backing = match self.backing {
+            Backing::Matured => reserves.matured,
+            Backing::Immature => reserves.matured.saturating_add(reserves.immature),
+        };
+        match ratio_ppk(backing, reserves.liabilities) {
+            Some(ratio) if ratio < min_ratio_ppk => Err(MintError::InsufficientReserves {
+                ratio_ppk: ratio,
+                min_ratio_ppk,
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// How the sat liabilities are backed, served with the control API report.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ReserveReport {
+    /// Sat owed, see `audit::Liabilities`.
+    pub liabilities: u64,
+    /// Rewards of matured blocks, less the melts paid and the fees collected.
+    pub matured: u64,
+    /// Rewards of blocks found but not matured yet.
+    pub immature: u64,
+    /// Liabilities covered by matured rewards.
+    pub backed_by_matured: u64,
+    /// Liabilities covered by immature rewards only.
+    pub backed_by_immature: u64,
+    pub unbacked: u64,
+    /// Matured rewards per thousand sat of liabilities, `None` without liabilities.
+    pub ratio_ppk: Option<u64>,
+}
+
+impl ReserveReport {
+    /// Reserves of the books `sat`, with `immature` rewards not matured yet.
+    pub fn new(sat: &SatAudit, immature: u64) -> Self {
+        let liabilities = sat.liabilities.total;
+        let matured = sat
+            .matured_rewards
+            .saturating_sub(sat.melted)
+            .saturating_sub(sat.fees_collected);
+        let backed_by_matured = liabilities.min(matured);
+        let backed_by_immature = (liabilities - backed_by_matured).min(immature);
+        Self {
+            liabilities,
+            matured,
+            immature,
+            backed_by_matured,
+            backed_by_immature,
+            unbacked: liabilities - backed_by_matured - backed_by_immature,
+            ratio_ppk: ratio_ppk(matured, liabilities),
+        }
+    }
+}
+
+fn ratio_ppk(backing: u64, liabilities: u64) -> Option<u64> {
+    match liabilities {
+        0 => None,
+        _ => Some((backing as u128 * 1000 / liabilities as u128).min(u64::MAX as u128) as u64),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pool_mint::mint::audit::Liabilities;
+
+    #[test]
+    fn splits_liabilities_by_backing() {
+        let sat = SatAudit {
+            liabilities: Liabilities {
+                total: 100,
+                ..Default::default()
+            },
+            matured_rewards: 90,
+            melted: 10,
+            fees_collected: 5,
+            ..Default::default()
+        };
+        let reserves = ReserveReport::new(&sat, 20);
+        assert_eq!(
+            (
+                reserves.backed_by_matured,
+                reserves.backed_by_immature,
+                reserves.unbacked
+            ),
+            (75, 20, 5)
+        );
+        assert_eq!(reserves.ratio_ppk, Some(750));
+
+        let mut config = ReserveConfig {
+            min_ratio_ppk: Some(900),
+            backing: Backing::Matured,
+        };
+        assert!(matches!(
+            config.check(&reserves),
+            Err(MintError::InsufficientReserves { ratio_ppk: 750, .. })
+        ));
+        config.backing = Backing::Immature;
+        assert!(config.check(&reserves).is_ok());
+        assert!(ReserveConfig::default().check(&reserves).is_ok());
+    }
+}
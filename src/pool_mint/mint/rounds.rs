@@ -0,0 +1,164 @@
+//! Rounds of shares between two blocks found by the pool. Shares credit ehash right away and also
+//! count toward the open round. Once the block closing a round matures, its coinbase reward is
+//! split over the round's accounts by share weight and the round's ehash is settled in sat, so
+//! sat tokens are only ever backed by rewards that can no longer be orphaned.
+//!
+//! Ehash an account still holds is debited and its sat credited right away. Ehash already
+//! withdrawn as tokens can't be debited, so its sat goes to a reserve instead, and any ehash token
+//! can be converted into sat from that reserve (see `Conversion`).
+use crate::error::{MintError, MintResult};
+use serde::Serialize;
+use stratum_common::bitcoin::{consensus::deserialize, Transaction};
+
+/// Confirmations a coinbase output needs before it can be spent.
+pub const COINBASE_MATURITY: u32 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RoundState {
+    /// Collecting shares, no block found yet.
+    Open,
+    /// Closed by a found block whose coinbase is not spendable yet.
+    Immature,
+    /// The reward was paid out as sat.
+    Matured,
+    /// The block left the best chain, the round pays nothing.
+    Orphaned,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Round {
+    pub id: u64,
+    pub state: RoundState,
+    pub started_at: u64,
+    pub ended_at: Option<u64>,
+    pub coinbase_txid: Option<String>,
+    /// Total value of the coinbase outputs, in sat.
+    pub reward: Option<u64>,
+    /// Height and hash of the block, once it was seen in the best chain.
+    pub height: Option<u64>,
+    pub block_hash: Option<String>,
+    /// Account whose share found the block, unknown for blocks found before it was recorded.
+    pub finder: Option<String>,
+}
+
+/// Shares of an account in a round, see `MintDb::account_rounds`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AccountRound {
+    pub round: Round,
+    /// Weight of the shares of the account, and of all shares of the round.
+    pub weight: u64,
+    pub round_weight: u64,
+}
+
+/// How the reward of a matured round was settled.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Settlement {
+    /// Sat credited per account, in exchange for the ehash debited from it.
+    pub credited: Vec<(String, u64)>,
+    /// Sat added to the conversion reserve for ehash circulating as tokens.
+    pub reserved: u64,
+}
+
+/// The reserve backing ehash tokens of matured rounds. Converting ehash gets its share of the
+/// reserve, at the rate of all settled ehash still circulating.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Conversion {
+    /// Sat set aside by matured rounds.
+    pub reserve: u64,
+    /// Ehash of matured rounds that circulates as tokens.
+    pub outstanding: u64,
+}
+
+impl Conversion {
+    /// Sat `ehash` converts to, rounded down. Only settled ehash converts.
+    pub fn value(&self, ehash: u64) -> Option<u64> {
+        if ehash > self.outstanding || self.outstanding == 0 {
+            return None;
+        }
+        Some((ehash as u128 * self.reserve as u128 / self.outstanding as u128) as u64)
+    }
+}
+
+/// Splits `reward` over `shares` (account, weight) proportionally to their weight, rounding down.
+/// The dust left by rounding stays with the pool.
+pub fn split_reward(reward: u64, shares: &[(String, u64)]) -> Vec<(String, u64)> {
+    let total: u128 = shares.iter().map(|(_, weight)| *weight as u128).sum();
+    if total == 0 {
+        return vec![];
+    }
+    shares
+        .iter()
+        .map(|(account, weight)| {
+            let amount = reward as u128 * *weight as u128 / total;
+            (account.clone(), amount as u64)
+        })
+        .filter(|(_, amount)| *amount > 0)
+        .collect()
+}
+
+/// Txid and total output value of a serialized coinbase transaction.
+pub fn coinbase_reward(coinbase: &[u8]) -> MintResult<(String, u64)> {
+    let tx: Transaction =
+        deserialize(coinbase).map_err(|e| MintError::InvalidCoinbase(e.to_string()))?;
+    let reward = tx.output.iter().map(|output| output.value).sum();
+    Ok((tx.txid().to_string(), reward))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use stratum_common::bitcoin::{
+        consensus::serialize, PackedLockTime, Script, Sequence, TxIn, TxOut, Witness,
+    };
+
+    #[test]
+    fn converts_at_reserve_rate() {
+        let conversion = Conversion {
+            reserve: 1000,
+            outstanding: 3,
+        };
+        assert_eq!(conversion.value(1), Some(333));
+        assert_eq!(conversion.value(3), Some(1000));
+        assert_eq!(conversion.value(4), None);
+        assert_eq!(Conversion::default().value(0), None);
+    }
+
+    #[test]
+    fn reads_coinbase_reward() {
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: Default::default(),
+                script_sig: Script::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![
+                TxOut {
+                    value: 312_500_000,
+                    script_pubkey: Script::new(),
+                },
+                TxOut {
+                    value: 0,
+                    script_pubkey: Script::new(),
+                },
+            ],
+        };
+        let (txid, reward) = coinbase_reward(&serialize(&tx)).unwrap();
+        assert_eq!(txid, tx.txid().to_string());
+        assert_eq!(reward, 312_500_000);
+        assert!(coinbase_reward(&[1, 2, 3]).is_err());
+    }
+
+    #[test]
+    fn splits_reward_by_weight() {
+        let shares = vec![("alice".to_string(), 3), ("bob".to_string(), 1)];
+        assert_eq!(
+            split_reward(1001, &shares),
+            vec![("alice".to_string(), 750), ("bob".to_string(), 250)]
+        );
+        assert!(split_reward(1000, &[]).is_empty());
+    }
+}
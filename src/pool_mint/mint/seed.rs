@@ -0,0 +1,164 @@
+//! Deriving the mint master secret from a secret the operator backs up anyway, so that one secret
+//! recovers every keyset of the mint. The seed is either
+//!
+//! - a BIP39 mnemonic, kept in its own file and generated (24 words) on first start, with an
+//!   optional passphrase. The master secret is `sha256(MASTER_SECRET_TAG || bip39_seed)`, where
+//!   `bip39_seed` is the 64 byte BIP39 seed of the mnemonic and passphrase.
+//! - the pool authority secret key of the pool config. The master secret is
+//!   `sha256(MASTER_SECRET_TAG || authority_secret_key)`, the key being its 32 bytes.
+//!
+//! The keys of every keyset are then derived from the master secret as described in
+//! `Keyset::derive`. The derived master secret is still written to `master_secret_path`, so
+//! backups work the same, and a seed not deriving the master secret already there is refused
+//! rather than silently replacing the keys of every token issued so far.
+use super::keyset;
+use crate::error::{MintError, MintResult};
+use bip39::Mnemonic;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::{fs, io::Write, path::Path};
+use tracing::info;
+
+/// Domain separation of the master secret derivation.
+pub const MASTER_SECRET_TAG: &[u8] = b"potato/mint/master_secret";
+
+/// Where the master secret is derived from, see the module documentation.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "source", rename_all = "snake_case")]
+pub enum SeedConfig {
+    Mnemonic {
+        /// File holding the mnemonic words, created on first start.
+        path: String,
+        #[serde(default)]
+        passphrase: String,
+    },
+    AuthorityKey,
+}
+
+/// The master secret derived from `seed`, saved at `master_secret_path` if there is none yet.
+pub fn load_master_secret(
+    seed: &SeedConfig,
+    authority_secret_key: Option<&[u8; 32]>,
+    master_secret_path: &str,
+) -> MintResult<[u8; 32]> {
+    let master_secret = match seed {
+        SeedConfig::Mnemonic { path, passphrase } => {
+            master_secret_from_mnemonic(&load_or_create_mnemonic(path)?, passphrase)
+        }
+        SeedConfig::AuthorityKey => master_secret_from_authority_key(
+            authority_secret_key
+                .ok_or_else(|| MintError::InvalidSeed("no pool authority key".to_string()))?,
+        ),
+    };
+    if !Path::new(master_secret_path).exists() {
+        keyset::write_master_secret(master_secret_path, &master_secret)?;
+    } else if keyset::read_master_secret(master_secret_path)? != master_secret {
+        return Err(MintError::InvalidSeed(format!(
+            "it doesn't derive the master secret in {}",
+            master_secret_path
+        )));
+    }
+    Ok(master_secret)
+}
+
+pub fn master_secret_from_mnemonic(mnemonic: &Mnemonic, passphrase: &str) -> [u8; 32] {
+    derive(&mnemonic.to_seed(passphrase))
+}
+
+pub fn master_secret_from_authority_key(authority_secret_key: &[u8; 32]) -> [u8; 32] {
+    derive(authority_secret_key)
+}
+
+fn derive(seed: &[u8]) -> [u8; 32] {
+    Sha256::new()
+        .chain_update(MASTER_SECRET_TAG)
+        .chain_update(seed)
+        .finalize()
+        .into()
+}
+
+/// Loads the mnemonic at `path`, generating and saving a new one, readable by the owner only, on
+/// first start.
+pub fn load_or_create_mnemonic(path: &str) -> MintResult<Mnemonic> {
+    if Path::new(path).exists() {
+        return Mnemonic::parse(fs::read_to_string(path)?.trim())
+            .map_err(|e| MintError::InvalidSeed(format!("{}: {}", path, e)));
+    }
+    let mnemonic = Mnemonic::from_entropy(&rand::random::<[u8; 32]>())
+        .map_err(|e| MintError::InvalidSeed(e.to_string()))?;
+    if let Some(parent) = Path::new(path).parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    writeln!(options.open(path)?, "{}", mnemonic)?;
+    info!("Generated new mint mnemonic at {}", path);
+    Ok(mnemonic)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn recovers_the_master_secret_from_the_mnemonic() {
+        let dir = std::env::temp_dir().join(format!("potato-seed-{}", std::process::id()));
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        let seed = SeedConfig::Mnemonic {
+            path: path("mnemonic"),
+            passphrase: "potato".to_string(),
+        };
+        let master_secret = load_master_secret(&seed, None, &path("master_secret")).unwrap();
+        assert_eq!(
+            load_or_create_mnemonic(&path("mnemonic"))
+                .unwrap()
+                .word_count(),
+            24
+        );
+        assert_eq!(
+            keyset::read_master_secret(&path("master_secret")).unwrap(),
+            master_secret
+        );
+
+        fs::remove_file(path("master_secret")).unwrap();
+        assert_eq!(
+            load_master_secret(&seed, None, &path("master_secret")).unwrap(),
+            master_secret
+        );
+        let other_passphrase = SeedConfig::Mnemonic {
+            path: path("mnemonic"),
+            passphrase: String::new(),
+        };
+        assert!(matches!(
+            load_master_secret(&other_passphrase, None, &path("master_secret")),
+            Err(MintError::InvalidSeed(_))
+        ));
+        assert!(matches!(
+            load_master_secret(&SeedConfig::AuthorityKey, None, &path("master_secret")),
+            Err(MintError::InvalidSeed(_))
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn derivation_is_documented() {
+        // BIP39 test vector, seed of the all zero entropy mnemonic and passphrase "TREZOR"
+        let mnemonic = Mnemonic::from_entropy(&[0; 16]).unwrap();
+        let bip39_seed = hex::decode(
+            "c55257c360c07c72029aebc1b53c05ed0362ada38ead3e3e9efa3708e53495531f09a6987599d18264c1e1c92f2cf141630c7a3c4ab7c81b2f001698e7463b04",
+        )
+        .unwrap();
+        let expected: [u8; 32] = Sha256::new()
+            .chain_update(b"potato/mint/master_secret")
+            .chain_update(&bip39_seed)
+            .finalize()
+            .into();
+        assert_eq!(master_secret_from_mnemonic(&mnemonic, "TREZOR"), expected);
+        assert_ne!(
+            master_secret_from_authority_key(&[1; 32]),
+            master_secret_from_authority_key(&[2; 32])
+        );
+    }
+}
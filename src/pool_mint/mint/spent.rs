@@ -0,0 +1,225 @@
+//! In memory index in front of the spent and pending proofs of the database. Every input of a
+//! swap, melt or conversion is looked up among all the proofs ever spent, and almost every input
+//! is fresh. A bloom filter over the `Y`s of spent proofs answers those lookups from memory, and
+//! only possible hits go to the database, which stays the source of truth. Proofs reserved by
+//! melts in progress are few and kept in a hash map.
+//!
+//! The index also counts lookups and redemption attempts of spent or pending proofs, reported by
+//! `Mint::report`.
+use secp256k1::PublicKey;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// Bits of the filter per spent proof it is sized for, for about 1% false positives.
+const BITS_PER_ITEM: u64 = 10;
+const HASHES: u64 = 7;
+/// Spent proofs the filter is sized for at least.
+pub const MIN_CAPACITY: u64 = 1 << 16;
+
+#[derive(Debug, Clone)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    capacity: u64,
+    len: u64,
+}
+
+impl BloomFilter {
+    pub fn with_capacity(capacity: u64) -> Self {
+        let capacity = capacity.max(MIN_CAPACITY);
+        Self {
+            bits: vec![0; (capacity * BITS_PER_ITEM).div_ceil(64) as usize],
+            capacity,
+            len: 0,
+        }
+    }
+
+    pub fn insert(&mut self, y: &[u8; 33]) {
+        for index in self.indexes(y) {
+            self.bits[(index / 64) as usize] |= 1 << (index % 64);
+        }
+        self.len += 1;
+    }
+
+    /// False if `y` was never inserted, true if it probably was.
+    pub fn may_contain(&self, y: &[u8; 33]) -> bool {
+        self.indexes(y)
+            .all(|index| self.bits[(index / 64) as usize] & (1 << (index % 64)) != 0)
+    }
+
+    /// Whether more items were inserted than the filter is sized for, making false positives more
+    /// likely.
+    pub fn is_full(&self) -> bool {
+        self.len > self.capacity
+    }
+
+    /// Items inserted so far.
+    pub fn count(&self) -> u64 {
+        self.len
+    }
+
+    /// Double hashing over the x coordinate of `y`, itself the output of a hash function.
+    fn indexes(&self, y: &[u8; 33]) -> impl Iterator<Item = u64> {
+        let bits = self.bits.len() as u64 * 64;
+        let h1 = u64::from_le_bytes(y[1..9].try_into().expect("8 bytes"));
+        let h2 = u64::from_le_bytes(y[9..17].try_into().expect("8 bytes")) | 1;
+        (0..HASHES).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % bits)
+    }
+}
+
+/// Counters of the spent proof lookups.
+#[derive(Debug, Default)]
+pub struct SpentStats {
+    lookups: AtomicU64,
+    database_lookups: AtomicU64,
+    false_positives: AtomicU64,
+    double_spends: AtomicU64,
+    pending_spends: AtomicU64,
+}
+
+/// A snapshot of `SpentStats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct SpentReport {
+    pub spent_proofs: u64,
+    pub pending_proofs: u64,
+    pub lookups: u64,
+    /// Lookups the filter couldn't answer.
+    pub database_lookups: u64,
+    /// Database lookups of proofs not spent after all.
+    pub false_positives: u64,
+    /// Attempts to redeem a spent proof.
+    pub double_spends: u64,
+    /// Attempts to redeem a proof reserved by a melt in progress.
+    pub pending_spends: u64,
+}
+
+#[derive(Debug)]
+pub struct SpentIndex {
+    filter: BloomFilter,
+    /// Reserved proofs, with the melt quote they are reserved by.
+    pending: HashMap<PublicKey, String>,
+    stats: SpentStats,
+}
+
+impl SpentIndex {
+    pub fn new(filter: BloomFilter, pending: HashMap<PublicKey, String>) -> Self {
+        Self {
+            filter,
+            pending,
+            stats: SpentStats::default(),
+        }
+    }
+
+    /// Whether `y` needs to be looked up in the database to know if it is spent.
+    pub fn may_be_spent(&self, y: &PublicKey) -> bool {
+        self.stats.lookups.fetch_add(1, Ordering::Relaxed);
+        let may_be_spent = self.filter.may_contain(&y.serialize());
+        if may_be_spent {
+            self.stats.database_lookups.fetch_add(1, Ordering::Relaxed);
+        }
+        may_be_spent
+    }
+
+    /// Records the database answer to a lookup `may_be_spent` couldn't answer.
+    pub fn record_lookup(&self, spent: bool) {
+        let counter = match spent {
+            true => &self.stats.double_spends,
+            false => &self.stats.false_positives,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn is_pending(&self, y: &PublicKey) -> bool {
+        let pending = self.pending.contains_key(y);
+        if pending {
+            self.stats.pending_spends.fetch_add(1, Ordering::Relaxed);
+        }
+        pending
+    }
+
+    /// Like `may_be_spent` and `is_pending` for state queries, which aren't redemption attempts
+    /// and aren't counted.
+    pub fn may_contain(&self, y: &PublicKey) -> bool {
+        self.filter.may_contain(&y.serialize())
+    }
+
+    pub fn is_reserved(&self, y: &PublicKey) -> bool {
+        self.pending.contains_key(y)
+    }
+
+    pub fn spend<'a>(&mut self, ys: impl IntoIterator<Item = &'a PublicKey>) {
+        for y in ys {
+            self.pending.remove(y);
+            self.filter.insert(&y.serialize());
+        }
+    }
+
+    pub fn reserve<'a>(&mut self, ys: impl IntoIterator<Item = &'a PublicKey>, quote_id: &str) {
+        for y in ys {
+            self.pending.insert(*y, quote_id.to_string());
+        }
+    }
+
+    pub fn release(&mut self, quote_id: &str) {
+        self.pending.retain(|_, id| id != quote_id);
+    }
+
+    /// Ys reserved by the melt `quote_id`.
+    pub fn reserved(&self, quote_id: &str) -> Vec<PublicKey> {
+        self.pending
+            .iter()
+            .filter(|(_, id)| *id == quote_id)
+            .map(|(y, _)| *y)
+            .collect()
+    }
+
+    pub fn needs_rebuild(&self) -> bool {
+        self.filter.is_full()
+    }
+
+    /// Replaces the filter, keeping the counters.
+    pub fn set_filter(&mut self, filter: BloomFilter) {
+        self.filter = filter;
+    }
+
+    pub fn report(&self) -> SpentReport {
+        SpentReport {
+            spent_proofs: self.filter.count(),
+            pending_proofs: self.pending.len() as u64,
+            lookups: self.stats.lookups.load(Ordering::Relaxed),
+            database_lookups: self.stats.database_lookups.load(Ordering::Relaxed),
+            false_positives: self.stats.false_positives.load(Ordering::Relaxed),
+            double_spends: self.stats.double_spends.load(Ordering::Relaxed),
+            pending_spends: self.stats.pending_spends.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use sha2::{Digest, Sha256};
+
+    /// A compressed point stand in, only its x coordinate is hashed.
+    fn y(i: u64) -> [u8; 33] {
+        let mut y = [2; 33];
+        y[1..].copy_from_slice(&Sha256::digest(i.to_le_bytes()));
+        y
+    }
+
+    #[test]
+    fn filters_fresh_proofs() {
+        let mut filter = BloomFilter::with_capacity(0);
+        for i in 0..MIN_CAPACITY {
+            filter.insert(&y(i));
+        }
+        assert!((0..MIN_CAPACITY).all(|i| filter.may_contain(&y(i))));
+        assert!(!filter.is_full());
+        let false_positives = (MIN_CAPACITY..MIN_CAPACITY + 10_000)
+            .filter(|i| filter.may_contain(&y(*i)))
+            .count();
+        assert!(false_positives < 300, "{} false positives", false_positives);
+    }
+}
@@ -0,0 +1,335 @@
+//! WebSocket subscriptions (NUT-17), pushing quote states and proof states to wallets instead of
+//! having them poll. Wallets connect to `/v1/ws` and send JSON-RPC requests subscribing to the
+//! state of mint quotes, melt quotes or proofs (by `Y`). The mint answers each subscription with
+//! the current state of every filter, then notifies every change.
+//!
+//! The mint publishes a `MintEvent` whenever a state changes. Ehash mint quotes become paid as
+//! the account mines, so balance changes are published too and the quotes of that account
+//! checked again. Only notifications of an actual change are sent.
+use super::{
+    nuts::{MeltQuoteResponse, MintQuoteResponse, ProofState},
+    Mint,
+};
+use crate::error::MintError;
+use secp256k1::PublicKey;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{collections::HashMap, str::FromStr};
+use tracing::warn;
+
+/// Events kept for subscribers slower than the mint, past that they check every state again.
+pub const EVENTS_CAPACITY: usize = 1024;
+/// Subscriptions a connection can hold.
+pub const MAX_SUBSCRIPTIONS: usize = 100;
+
+const JSONRPC_VERSION: &str = "2.0";
+const INVALID_REQUEST: i64 = -32600;
+const INVALID_PARAMS: i64 = -32602;
+
+/// A state change published by the mint.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MintEvent {
+    MintQuote(MintQuoteResponse),
+    MeltQuote(MeltQuoteResponse),
+    Proofs(Vec<ProofState>),
+    /// The balance of an account changed, which may pay or unpay its mint quotes.
+    Balance(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SubscriptionKind {
+    /// Filters are mint quote ids.
+    EhashMintQuote,
+    /// Filters are melt quote ids.
+    Bolt11MeltQuote,
+    /// Filters are hex encoded proof `Y`s.
+    ProofState,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Request {
+    jsonrpc: String,
+    #[serde(flatten)]
+    method: Method,
+    id: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "method", content = "params", rename_all = "snake_case")]
+enum Method {
+    Subscribe {
+        kind: SubscriptionKind,
+        #[serde(rename = "subId")]
+        sub_id: String,
+        filters: Vec<String>,
+    },
+    Unsubscribe {
+        #[serde(rename = "subId")]
+        sub_id: String,
+    },
+}
+
+#[derive(Debug)]
+struct Subscription {
+    kind: SubscriptionKind,
+    /// Last payload sent per filter, `None` before the first one.
+    last: HashMap<String, Option<Value>>,
+}
+
+/// The subscriptions of one connection.
+#[derive(Debug, Default)]
+pub struct Subscriptions {
+    subscriptions: HashMap<String, Subscription>,
+}
+
+impl Subscriptions {
+    /// Answers a request of the wallet, followed by the current states of a new subscription.
+    pub fn handle(&mut self, mint: &Mint, text: &str) -> Vec<String> {
+        let request: Request = match serde_json::from_str(text) {
+            Ok(request) => request,
+            Err(e) => return vec![error(None, INVALID_REQUEST, &e.to_string())],
+        };
+        if request.jsonrpc != JSONRPC_VERSION {
+            return vec![error(
+                Some(request.id),
+                INVALID_REQUEST,
+                "jsonrpc must be 2.0",
+            )];
+        }
+        match request.method {
+            Method::Subscribe {
+                kind,
+                sub_id,
+                filters,
+            } => {
+                if self.subscriptions.len() >= MAX_SUBSCRIPTIONS {
+                    return vec![error(
+                        Some(request.id),
+                        INVALID_PARAMS,
+                        "too many subscriptions",
+                    )];
+                }
+                if kind == SubscriptionKind::ProofState {
+                    if let Some(filter) = filters.iter().find(|y| PublicKey::from_str(y).is_err()) {
+                        let message = format!("invalid Y {}", filter);
+                        return vec![error(Some(request.id), INVALID_PARAMS, &message)];
+                    }
+                }
+                let subscription = Subscription {
+                    kind,
+                    last: filters.into_iter().map(|filter| (filter, None)).collect(),
+                };
+                self.subscriptions.insert(sub_id.clone(), subscription);
+                let mut replies = vec![ok(request.id, &sub_id)];
+                replies.extend(self.refresh_where(mint, |id, _| *id == sub_id));
+                replies
+            }
+            Method::Unsubscribe { sub_id } => match self.subscriptions.remove(&sub_id) {
+                Some(_) => vec![ok(request.id, &sub_id)],
+                None => vec![error(Some(request.id), INVALID_PARAMS, "unknown subId")],
+            },
+        }
+    }
+
+    /// Notifications of `event` for the subscriptions concerned.
+    pub fn on_event(&mut self, mint: &Mint, event: &MintEvent) -> Vec<String> {
+        match event {
+            MintEvent::MintQuote(quote) => self.update(
+                SubscriptionKind::EhashMintQuote,
+                &quote.quote,
+                to_value(quote),
+            ),
+            MintEvent::MeltQuote(quote) => self.update(
+                SubscriptionKind::Bolt11MeltQuote,
+                &quote.quote,
+                to_value(quote),
+            ),
+            MintEvent::Proofs(states) => states
+                .iter()
+                .flat_map(|state| {
+                    self.update(
+                        SubscriptionKind::ProofState,
+                        &state.y.to_string(),
+                        to_value(state),
+                    )
+                })
+                .collect(),
+            MintEvent::Balance(account) => self.refresh_where(mint, |_, subscription| {
+                subscription.kind == SubscriptionKind::EhashMintQuote
+                    && subscription.last.values().any(|last| {
+                        last.as_ref()
+                            .and_then(|last| last.get("request"))
+                            .and_then(Value::as_str)
+                            == Some(account)
+                    })
+            }),
+        }
+    }
+
+    fn update(&mut self, kind: SubscriptionKind, filter: &str, payload: Value) -> Vec<String> {
+        self.subscriptions
+            .iter_mut()
+            .filter(|(_, subscription)| subscription.kind == kind)
+            .filter_map(|(sub_id, subscription)| {
+                let last = subscription.last.get_mut(filter)?;
+                notify(sub_id, last, payload.clone())
+            })
+            .collect()
+    }
+
+    /// Checks every state again, after missing events.
+    pub fn refresh(&mut self, mint: &Mint) -> Vec<String> {
+        self.refresh_where(mint, |_, _| true)
+    }
+
+    fn refresh_where(
+        &mut self,
+        mint: &Mint,
+        selected: impl Fn(&String, &Subscription) -> bool,
+    ) -> Vec<String> {
+        let mut notifications = vec![];
+        for (sub_id, subscription) in &mut self.subscriptions {
+            if !selected(sub_id, subscription) {
+                continue;
+            }
+            for (filter, last) in &mut subscription.last {
+                let payload = match current_state(mint, subscription.kind, filter) {
+                    Ok(payload) => payload,
+                    // quotes are deleted once expired, leaving nothing to notify
+                    Err(MintError::UnknownQuote(_)) => continue,
+                    Err(e) => {
+                        warn!("Mint: can't check state of {}: {}", filter, e);
+                        continue;
+                    }
+                };
+                notifications.extend(notify(sub_id, last, payload));
+            }
+        }
+        notifications
+    }
+}
+
+fn current_state(mint: &Mint, kind: SubscriptionKind, filter: &str) -> Result<Value, MintError> {
+    Ok(match kind {
+        SubscriptionKind::EhashMintQuote => to_value(&mint.mint_quote(filter)?),
+        SubscriptionKind::Bolt11MeltQuote => to_value(&mint.melt_quote(filter)?),
+        SubscriptionKind::ProofState => {
+            let y = PublicKey::from_str(filter)?;
+            to_value(&mint.proof_states(&[y])?[0])
+        }
+    })
+}
+
+/// The notification of `payload`, unless it was the last one sent.
+fn notify(sub_id: &str, last: &mut Option<Value>, payload: Value) -> Option<String> {
+    if last.as_ref() == Some(&payload) {
+        return None;
+    }
+    let notification = json!({
+        "jsonrpc": JSONRPC_VERSION,
+        "method": "subscribe",
+        "params": { "subId": sub_id, "payload": payload },
+    });
+    *last = Some(payload);
+    Some(notification.to_string())
+}
+
+fn to_value(payload: &impl Serialize) -> Value {
+    serde_json::to_value(payload).expect("payloads serialize")
+}
+
+fn ok(id: u64, sub_id: &str) -> String {
+    json!({
+        "jsonrpc": JSONRPC_VERSION,
+        "result": { "status": "OK", "subId": sub_id },
+        "id": id,
+    })
+    .to_string()
+}
+
+fn error(id: Option<u64>, code: i64, message: &str) -> String {
+    json!({
+        "jsonrpc": JSONRPC_VERSION,
+        "error": { "code": code, "message": message },
+        "id": id,
+    })
+    .to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pool_mint::mint::{dhke, nuts::SpendState, MintConfig};
+
+    fn subscribe(kind: &str, filter: &str) -> String {
+        json!({
+            "jsonrpc": "2.0",
+            "method": "subscribe",
+            "params": { "kind": kind, "subId": kind, "filters": [filter] },
+            "id": 1,
+        })
+        .to_string()
+    }
+
+    fn payload(notification: &str) -> Value {
+        serde_json::from_str::<Value>(notification).unwrap()["params"]["payload"].clone()
+    }
+
+    #[test]
+    fn notifies_state_changes() {
+        let mut mint = Mint::from_master_secret(&[1; 32], &MintConfig::default()).unwrap();
+        let mut events = mint.subscribe();
+        let quote = mint.create_mint_quote("alice", 5, "ehash").unwrap();
+        let mut subscriptions = Subscriptions::default();
+
+        let replies = subscriptions.handle(&mint, &subscribe("ehash_mint_quote", &quote.quote));
+        assert_eq!(replies.len(), 2);
+        assert_eq!(
+            serde_json::from_str::<Value>(&replies[0]).unwrap()["result"]["status"],
+            "OK"
+        );
+        assert_eq!(payload(&replies[1])["state"], "UNPAID");
+
+        mint.credit_share("alice", 3).unwrap();
+        let event = events.try_recv().unwrap();
+        assert_eq!(event, MintEvent::Balance("alice".to_string()));
+        // still unpaid, nothing changed
+        assert!(subscriptions.on_event(&mint, &event).is_empty());
+        mint.credit_share("alice", 3).unwrap();
+        let notifications = subscriptions.on_event(&mint, &events.try_recv().unwrap());
+        assert_eq!(notifications.len(), 1);
+        assert_eq!(payload(&notifications[0])["state"], "PAID");
+        // another account's balance doesn't concern the quote
+        mint.credit_share("bob", 3).unwrap();
+        assert!(subscriptions
+            .on_event(&mint, &events.try_recv().unwrap())
+            .is_empty());
+
+        let y = dhke::hash_to_curve(b"unspent").unwrap();
+        let replies = subscriptions.handle(&mint, &subscribe("proof_state", &y.to_string()));
+        assert_eq!(payload(&replies[1])["state"], "UNSPENT");
+        let spent = MintEvent::Proofs(vec![ProofState {
+            y,
+            state: SpendState::Spent,
+            witness: None,
+        }]);
+        let notifications = subscriptions.on_event(&mint, &spent);
+        assert_eq!(payload(&notifications[0])["Y"], y.to_string());
+        assert_eq!(payload(&notifications[0])["state"], "SPENT");
+
+        let unsubscribe = json!({
+            "jsonrpc": "2.0",
+            "method": "unsubscribe",
+            "params": { "subId": "proof_state" },
+            "id": 2,
+        });
+        subscriptions.handle(&mint, &unsubscribe.to_string());
+        assert!(subscriptions.on_event(&mint, &spent).is_empty());
+        let invalid = subscriptions.handle(&mint, &subscribe("proof_state", "not a key"));
+        assert_eq!(
+            serde_json::from_str::<Value>(&invalid[0]).unwrap()["error"]["code"],
+            INVALID_PARAMS
+        );
+    }
+}
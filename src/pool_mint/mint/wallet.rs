@@ -0,0 +1,915 @@
+//! Wallet for miners to claim and redeem their tokens without installing a Cashu wallet, behind
+//! the `potato wallet` commands. It keeps the proofs of one mint in a JSON file, along with the
+//! key payouts of its account are locked to, and talks to the mint over its Cashu API (see `api`).
+//!
+//! - `receive` swaps a token for proofs only this wallet knows the secrets of. `check` asks the
+//!   mint whether the proofs of a token are unspent (NUT-07) without redeeming them.
+//! - `claim` collects what the pool owes an account: it registers the wallet key (see `p2pk`),
+//!   receives the payouts held for the account and mints its balances, with the credential the
+//!   operator issued the account (see `credentials`).
+//! - `melt` pays a Lightning invoice with sat proofs, getting what is left of the fee reserve
+//!   back as change (NUT-08).
+//! - `export` swaps proofs for a token of an exact amount, to hand to another wallet. The token
+//!   can be locked to a hash (NUT-14, see `p2pk`) for selling ehash: the seller locks it to the
+//!   payment hash of a Lightning invoice and a buyer key, the buyer learns the preimage paying
+//!   the invoice and receives the token with it. Past the locktime the seller receives it back.
+//! - `consolidate` swaps the proofs of a unit for as few as their total needs, once there are
+//!   many more than that. Every claim consolidates, so the proof set stays small however many
+//!   payouts come in.
+//! - `restore` recreates a lost wallet from its mnemonic. Secrets of the outputs the wallet keeps
+//!   are derived from it (NUT-13, see `deterministic`), so the mint can hand their signatures
+//!   out again. Exported tokens aren't restored, and payouts held at the mint are claimed anew.
+//!
+//! Tokens are written to the wallet file before they are redeemed, so one the mint handed out
+//! isn't lost if redeeming it fails; it is tried again on the next claim. Fees are computed from
+//! the NUT-02 input fees of the keysets. Fees the operator charges per operation on top aren't
+//! published, so a mint charging them refuses the swaps of this wallet.
+use super::{
+    api::{BOLT11_METHOD, EHASH_METHOD, ONCHAIN_METHOD},
+    client::{self, MintClient, Secrets},
+    deterministic::Derivation,
+    dhke,
+    nuts::{
+        hex_secret, BalanceRequest, BalanceResponse, BlindedMessage, CheckStateRequest,
+        CheckStateResponse, KeySet, KeySetSummary, KeysResponse, KeysetsResponse, MeltQuoteRequest,
+        MeltQuoteResponse, MeltQuoteState, MeltRequest, MintQuoteRequest, MintQuoteResponse,
+        MintRequest, OutputSignaturesResponse, OutputsRequest, PayoutsRequest, PayoutsResponse,
+        Proof, PubkeyRequest, SignaturesResponse, SpendState, SwapRequest, Token,
+    },
+    p2pk::{self, Conditions},
+    SAT_UNIT,
+};
+use crate::error::{MintError, MintResult};
+use bip39::Mnemonic;
+use secp256k1::{PublicKey, Secp256k1, SecretKey};
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap},
+    fs,
+    io::Write,
+    path::Path,
+};
+use tracing::{info, warn};
+
+/// Proofs of a unit held before they are consolidated at the earliest.
+const MIN_CONSOLIDATION_PROOFS: usize = 8;
+/// Outputs asked for at once by a restore.
+const RESTORE_BATCH: u32 = 100;
+/// Batches in a row without a signed output after which a restore gives up on a keyset.
+const RESTORE_EMPTY_BATCHES: u32 = 3;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct WalletFile {
+    mint: String,
+    /// Key the payouts of the account are locked to, derived from the mnemonic unless the
+    /// wallet was created before it had one.
+    #[serde(with = "hex_secret")]
+    key: SecretKey,
+    /// BIP39 mnemonic the secrets of outputs are derived from (NUT-13), see `deterministic`.
+    #[serde(default)]
+    mnemonic: Option<String>,
+    /// Next unused output of each keyset, by keyset id.
+    #[serde(default)]
+    counters: BTreeMap<String, u32>,
+    /// Unspent proofs by unit.
+    #[serde(default)]
+    proofs: BTreeMap<String, Vec<Proof>>,
+    /// Tokens received but not redeemed yet.
+    #[serde(default)]
+    tokens: Vec<String>,
+}
+
+/// Amounts of the proofs of a token by unit, for each state the mint reports them in.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TokenCheck {
+    pub unspent: BTreeMap<String, u64>,
+    /// Reserved by a melt in progress.
+    pub pending: BTreeMap<String, u64>,
+    pub spent: BTreeMap<String, u64>,
+}
+
+#[derive(Debug)]
+pub struct Wallet {
+    path: String,
+    client: MintClient,
+    file: WalletFile,
+    derivation: Derivation,
+}
+
+impl Wallet {
+    /// Opens the wallet at `path`, or creates one for `mint_url` with a new mnemonic.
+    pub fn open(path: &str, mint_url: &str) -> MintResult<Self> {
+        let created = !Path::new(path).exists();
+        let mut file = if !created {
+            let file: WalletFile = serde_json::from_str(&fs::read_to_string(path)?)
+                .map_err(|e| MintError::Wallet(format!("{}: {}", path, e)))?;
+            if file.mint.trim_end_matches('/') != mint_url.trim_end_matches('/') {
+                return Err(MintError::Wallet(format!(
+                    "{} holds tokens of {}, not {}",
+                    path, file.mint, mint_url
+                )));
+            }
+            file
+        } else {
+            info!("Creating wallet {} for {}", path, mint_url);
+            WalletFile {
+                mint: mint_url.to_string(),
+                key: SecretKey::new(&mut rand::thread_rng()),
+                mnemonic: None,
+                counters: BTreeMap::new(),
+                proofs: BTreeMap::new(),
+                tokens: vec![],
+            }
+        };
+        let mnemonic = match &file.mnemonic {
+            Some(mnemonic) => parse_mnemonic(mnemonic)?,
+            None => {
+                let mnemonic = Mnemonic::from_entropy(&rand::random::<[u8; 32]>())
+                    .map_err(|e| MintError::InvalidSeed(e.to_string()))?;
+                if !created {
+                    info!(
+                        "Wallet {} derives its secrets from a mnemonic from now on, back it up",
+                        path
+                    );
+                }
+                file.mnemonic = Some(mnemonic.to_string());
+                mnemonic
+            }
+        };
+        let derivation = Derivation::new(&mnemonic)?;
+        if created {
+            file.key = derivation.payout_key()?;
+        }
+        let wallet = Self {
+            path: path.to_string(),
+            client: MintClient::new(&file.mint, None)?,
+            file,
+            derivation,
+        };
+        wallet.save()?;
+        Ok(wallet)
+    }
+
+    /// Creates the wallet at `path` for `mint_url` from `mnemonic`, recovering the unspent
+    /// proofs of every keyset of the mint: the mint hands out the signatures on the outputs of
+    /// the derived secrets again (NUT-09), until `RESTORE_EMPTY_BATCHES` batches in a row have
+    /// none signed. Returns the amount recovered by unit.
+    pub async fn restore(
+        path: &str,
+        mint_url: &str,
+        mnemonic: &str,
+    ) -> MintResult<(Self, BTreeMap<String, u64>)> {
+        if Path::new(path).exists() {
+            return Err(MintError::Wallet(format!("{} exists already", path)));
+        }
+        let mnemonic = parse_mnemonic(mnemonic)?;
+        let derivation = Derivation::new(&mnemonic)?;
+        let mut wallet = Self {
+            path: path.to_string(),
+            client: MintClient::new(mint_url, None)?,
+            file: WalletFile {
+                mint: mint_url.to_string(),
+                key: derivation.payout_key()?,
+                mnemonic: Some(mnemonic.to_string()),
+                counters: BTreeMap::new(),
+                proofs: BTreeMap::new(),
+                tokens: vec![],
+            },
+            derivation,
+        };
+        let mut restored = BTreeMap::new();
+        for summary in wallet.keysets().await? {
+            let keys: KeysResponse = wallet
+                .client
+                .get(&format!("/v1/keys/{}", summary.id))
+                .await?;
+            let Some(keyset) = keys.keysets.into_iter().next() else {
+                continue;
+            };
+            let proofs = wallet.restore_keyset(&keyset).await?;
+            let states = wallet.states(&proofs).await?;
+            let unspent: Vec<Proof> = proofs
+                .into_iter()
+                .zip(states)
+                .filter(|(_, state)| *state == SpendState::Unspent)
+                .map(|(proof, _)| proof)
+                .collect();
+            if unspent.is_empty() {
+                continue;
+            }
+            *restored.entry(summary.unit.clone()).or_default() += total(&unspent);
+            wallet.keep(&summary.unit, unspent)?;
+        }
+        wallet.save()?;
+        Ok((wallet, restored))
+    }
+
+    /// Proofs of every output of `keyset` the mint signed, moving its counter past the last.
+    async fn restore_keyset(&mut self, keyset: &KeySet) -> MintResult<Vec<Proof>> {
+        let mut proofs = vec![];
+        let mut start = 0;
+        let mut empty = 0;
+        while empty < RESTORE_EMPTY_BATCHES {
+            let mut outputs = vec![];
+            let mut secrets = HashMap::new();
+            for counter in start..start + RESTORE_BATCH {
+                let (secret, r) = self.derivation.secret(&keyset.id, counter)?;
+                let (blinded_secret, r) = dhke::blind_message(secret.as_bytes(), Some(r))?;
+                outputs.push(BlindedMessage {
+                    amount: 1,
+                    id: keyset.id.clone(),
+                    blinded_secret,
+                });
+                secrets.insert(blinded_secret, (counter, secret, r));
+            }
+            let response: OutputSignaturesResponse = self
+                .client
+                .post("/v1/restore", &OutputsRequest { outputs })
+                .await?;
+            start += RESTORE_BATCH;
+            if response.outputs.is_empty() {
+                empty += 1;
+                continue;
+            }
+            empty = 0;
+            let mut signed = vec![];
+            for output in &response.outputs {
+                let Some((counter, secret, r)) = secrets.remove(&output.blinded_secret) else {
+                    return Err(MintError::MintRequest(
+                        "signature on an output not asked for".to_string(),
+                    ));
+                };
+                self.file.counters.insert(keyset.id.clone(), counter + 1);
+                signed.push((secret, r));
+            }
+            proofs.extend(client::unblind(
+                keyset,
+                &response.outputs,
+                &response.signatures,
+                signed,
+            )?);
+        }
+        Ok(proofs)
+    }
+
+    /// The mnemonic the wallet derives its secrets from, and its key unless it was created
+    /// before it had one. Restores the wallet, see `restore`.
+    pub fn mnemonic(&self) -> &str {
+        self.file.mnemonic.as_deref().unwrap_or_default()
+    }
+
+    pub fn pubkey(&self) -> PublicKey {
+        self.file.key.public_key(&Secp256k1::new())
+    }
+
+    /// Amount of the proofs held, by unit.
+    pub fn balance(&self) -> BTreeMap<String, u64> {
+        self.file
+            .proofs
+            .iter()
+            .map(|(unit, proofs)| (unit.clone(), proofs.iter().map(|p| p.amount).sum()))
+            .collect()
+    }
+
+    /// Balances `account` accrued at the mint, by unit, shown to the owner of its `credential`.
+    pub async fn account_balance(
+        &self,
+        account: &str,
+        credential: &str,
+    ) -> MintResult<BTreeMap<String, u64>> {
+        let response: BalanceResponse = self
+            .client
+            .post(
+                "/v1/ehash/balance",
+                &BalanceRequest {
+                    account: account.to_string(),
+                    credential: Some(credential.to_string()),
+                },
+            )
+            .await?;
+        Ok(response.balances)
+    }
+
+    /// Redeems `token` into proofs of this wallet, revealing `preimage` (hex) for proofs locked
+    /// to a hash. Returns the amount received by unit, after fees.
+    pub async fn receive(
+        &mut self,
+        token: &str,
+        preimage: Option<&str>,
+    ) -> MintResult<BTreeMap<String, u64>> {
+        let decoded = Token::decode(token)?;
+        if !self.file.tokens.iter().any(|t| t == token) {
+            self.file.tokens.push(token.to_string());
+            self.save()?;
+        }
+        let keysets = self.keysets().await?;
+        let mut by_unit: BTreeMap<String, Vec<Proof>> = BTreeMap::new();
+        for proof in decoded.token.into_iter().flat_map(|entry| entry.proofs) {
+            let unit = unit_of(&keysets, &proof.id)?;
+            by_unit.entry(unit).or_default().push(proof);
+        }
+        let mut received = BTreeMap::new();
+        for (unit, inputs) in by_unit {
+            let amount = total(&inputs).saturating_sub(input_fee(&keysets, &inputs));
+            let inputs = self.signed(&inputs, preimage);
+            self.swap(&unit, &inputs, 0, None, &keysets).await?;
+            *received.entry(unit).or_default() += amount;
+        }
+        self.file.tokens.retain(|t| t != token);
+        self.save()?;
+        Ok(received)
+    }
+
+    /// Asks the mint which proofs of `token` are still unspent (NUT-07), so a token from another
+    /// miner can be checked before accepting it. Doesn't redeem it.
+    pub async fn check(&self, token: &str) -> MintResult<TokenCheck> {
+        let proofs: Vec<Proof> = Token::decode(token)?
+            .token
+            .into_iter()
+            .flat_map(|entry| entry.proofs)
+            .collect();
+        let states = self.states(&proofs).await?;
+        let keysets = self.keysets().await?;
+        let mut check = TokenCheck::default();
+        for (proof, state) in proofs.iter().zip(states) {
+            let amounts = match state {
+                SpendState::Unspent => &mut check.unspent,
+                SpendState::Pending => &mut check.pending,
+                SpendState::Spent => &mut check.spent,
+            };
+            *amounts.entry(unit_of(&keysets, &proof.id)?).or_default() += proof.amount;
+        }
+        Ok(check)
+    }
+
+    /// States of `proofs` at the mint (NUT-07), in the same order.
+    async fn states(&self, proofs: &[Proof]) -> MintResult<Vec<SpendState>> {
+        if proofs.is_empty() {
+            return Ok(vec![]);
+        }
+        let ys = proofs
+            .iter()
+            .map(|proof| dhke::hash_to_curve(proof.secret.as_bytes()))
+            .collect::<MintResult<Vec<_>>>()?;
+        let request = CheckStateRequest { ys: ys.clone() };
+        let response: CheckStateResponse = self.client.post("/v1/checkstate", &request).await?;
+        if response.states.iter().map(|state| state.y).ne(ys) {
+            return Err(MintError::MintRequest(
+                "states don't match the proofs asked for".to_string(),
+            ));
+        }
+        Ok(response
+            .states
+            .into_iter()
+            .map(|state| state.state)
+            .collect())
+    }
+
+    /// Collects what the pool owes `account`: the tokens not redeemed yet, the payouts held for
+    /// it and its balances, proving it owns the account with `credential` (see `credentials`).
+    /// Returns the amount received by unit.
+    pub async fn claim(
+        &mut self,
+        account: &str,
+        credential: &str,
+    ) -> MintResult<BTreeMap<String, u64>> {
+        let request = PubkeyRequest {
+            account: account.to_string(),
+            pubkey: self.pubkey(),
+            credential: Some(credential.to_string()),
+            signature: None,
+        };
+        if let Err(e) = self
+            .client
+            .post::<PubkeyRequest>("/v1/ehash/pubkey", &request)
+            .await
+        {
+            warn!("Payouts of {} stay locked to its key: {}", account, e);
+        }
+        let payouts: PayoutsResponse = self
+            .client
+            .post(
+                "/v1/ehash/payouts",
+                &PayoutsRequest {
+                    account: account.to_string(),
+                    credential: Some(credential.to_string()),
+                },
+            )
+            .await?;
+        self.file.tokens.extend(payouts.tokens);
+        self.save()?;
+
+        let mut received = BTreeMap::new();
+        for token in self.file.tokens.clone() {
+            match self.receive(&token, None).await {
+                Ok(amounts) => add(&mut received, amounts),
+                Err(e) => warn!("Keeping token, redeeming it failed: {}", e),
+            }
+        }
+        for (unit, amount) in self.account_balance(account, credential).await? {
+            if amount == 0 {
+                continue;
+            }
+            self.mint(account, credential, &unit, amount).await?;
+            *received.entry(unit).or_default() += amount;
+        }
+        if let Err(e) = self.consolidate().await {
+            warn!("Consolidating the wallet failed: {}", e);
+        }
+        Ok(received)
+    }
+
+    /// Swaps the proofs of every unit holding more than twice the proofs their total needs, and
+    /// at least `MIN_CONSOLIDATION_PROOFS`, for the fewest proofs adding up to it, less the fee.
+    /// Returns how many proofs were swapped.
+    pub async fn consolidate(&mut self) -> MintResult<usize> {
+        let due: Vec<String> = self
+            .file
+            .proofs
+            .iter()
+            .filter(|(_, proofs)| {
+                let needed = total(proofs).count_ones() as usize;
+                proofs.len() >= MIN_CONSOLIDATION_PROOFS.max(2 * needed + 1)
+            })
+            .map(|(unit, _)| unit.clone())
+            .collect();
+        if due.is_empty() {
+            return Ok(0);
+        }
+        let keysets = self.keysets().await?;
+        let mut swapped = 0;
+        for unit in due {
+            let inputs = self.file.proofs.remove(&unit).unwrap_or_default();
+            let signed = self.signed(&inputs, None);
+            if let Err(e) = self.swap(&unit, &signed, 0, None, &keysets).await {
+                self.keep(&unit, inputs)?;
+                return Err(e);
+            }
+            info!("Consolidated {} {} proofs", inputs.len(), unit);
+            swapped += inputs.len();
+        }
+        Ok(swapped)
+    }
+
+    /// Mints `amount` of the `unit` balance of `account`.
+    async fn mint(
+        &mut self,
+        account: &str,
+        credential: &str,
+        unit: &str,
+        amount: u64,
+    ) -> MintResult<()> {
+        let quote: MintQuoteResponse = self
+            .client
+            .post(
+                &format!("/v1/mint/quote/{}", EHASH_METHOD),
+                &MintQuoteRequest {
+                    amount,
+                    unit: unit.to_string(),
+                    account: account.to_string(),
+                    credential: Some(credential.to_string()),
+                },
+            )
+            .await?;
+        let keyset = self.client.keys(unit).await?;
+        let (outputs, secrets) = self.outputs(&keyset, amount)?;
+        let signatures: SignaturesResponse = self
+            .client
+            .post(
+                &format!("/v1/mint/{}", EHASH_METHOD),
+                &MintRequest {
+                    quote: quote.quote,
+                    outputs: outputs.clone(),
+                },
+            )
+            .await?;
+        let proofs = client::unblind(&keyset, &outputs, &signatures.signatures, secrets)?;
+        self.keep(unit, proofs)
+    }
+
+    /// Pays `invoice` with sat proofs.
+    pub async fn melt(&mut self, invoice: &str) -> MintResult<MeltQuoteResponse> {
+        let request = MeltQuoteRequest {
+            request: invoice.to_string(),
+            unit: SAT_UNIT.to_string(),
+            amount: None,
+        };
+        self.melt_with(BOLT11_METHOD, &request).await
+    }
+
+    /// Melts `amount` sat proofs into a payout to `address` by the next on-chain batch of the
+    /// mint, which deducts its fee from the payout. The quote stays pending until then.
+    pub async fn melt_onchain(
+        &mut self,
+        address: &str,
+        amount: u64,
+    ) -> MintResult<MeltQuoteResponse> {
+        let request = MeltQuoteRequest {
+            request: address.to_string(),
+            unit: SAT_UNIT.to_string(),
+            amount: Some(amount),
+        };
+        self.melt_with(ONCHAIN_METHOD, &request).await
+    }
+
+    async fn melt_with(
+        &mut self,
+        method: &str,
+        request: &MeltQuoteRequest,
+    ) -> MintResult<MeltQuoteResponse> {
+        let quote: MeltQuoteResponse = self
+            .client
+            .post(&format!("/v1/melt/quote/{}", method), request)
+            .await?;
+        let keysets = self.keysets().await?;
+        let inputs = self.take(SAT_UNIT, quote.amount + quote.fee_reserve, &keysets)?;
+        let keyset = self.client.keys(SAT_UNIT).await?;
+        // a blank output per bit of the largest possible change, the mint sets their amounts
+        let change = total(&inputs) - quote.amount;
+        let bits = (u64::BITS - change.leading_zeros()).max(1);
+        let (outputs, secrets) = self.outputs(&keyset, u64::MAX >> (64 - bits))?;
+        let request = MeltRequest {
+            quote: quote.quote,
+            inputs: self.signed(&inputs, None),
+            outputs: outputs.clone(),
+        };
+        let response = match self
+            .client
+            .post::<MeltQuoteResponse>(&format!("/v1/melt/{}", method), &request)
+            .await
+        {
+            Ok(response) => response,
+            Err(e) => {
+                self.keep(SAT_UNIT, inputs)?;
+                return Err(e);
+            }
+        };
+        match response.state {
+            // on-chain melts hand out their change while the payout waits for its batch
+            MeltQuoteState::Paid | MeltQuoteState::Pending if response.change.is_some() => {
+                let change = response.change.clone().unwrap_or_default();
+                let n = change.len();
+                let proofs = client::unblind(
+                    &keyset,
+                    &outputs[..n],
+                    &change,
+                    secrets.into_iter().take(n).collect(),
+                )?;
+                self.keep(SAT_UNIT, proofs)?;
+            }
+            MeltQuoteState::Paid => self.save()?,
+            MeltQuoteState::Unpaid => self.keep(SAT_UNIT, inputs)?,
+            // the inputs are reserved until the payment settles, the change is lost
+            MeltQuoteState::Pending => {
+                warn!("Payment of melt quote {} is still pending", response.quote);
+                self.save()?;
+            }
+        }
+        Ok(response)
+    }
+
+    /// Swaps proofs for a token of exactly `amount` `unit`, which leaves the wallet. Its proofs
+    /// carry the spending conditions `lock` if given.
+    pub async fn export(
+        &mut self,
+        unit: &str,
+        amount: u64,
+        lock: Option<&Conditions>,
+    ) -> MintResult<Token> {
+        let keysets = self.keysets().await?;
+        let inputs = self.take(unit, amount, &keysets)?;
+        let signed = self.signed(&inputs, None);
+        match self.swap(unit, &signed, amount, lock, &keysets).await {
+            Ok(proofs) => Ok(Token::new(self.client.url(), unit, proofs)),
+            Err(e) => {
+                self.keep(unit, inputs)?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Conditions locking a token to the preimage of `hash`, and to the signature of `buyer` if
+    /// given. Past `locktime` this wallet can take it back.
+    pub fn hash_lock(
+        &self,
+        hash: [u8; 32],
+        buyer: Option<PublicKey>,
+        locktime: Option<u64>,
+    ) -> Conditions {
+        let mut conditions = Conditions::htlc(hash);
+        conditions.pubkeys.extend(buyer);
+        if locktime.is_some() {
+            conditions.locktime = locktime;
+            conditions.refund = vec![self.pubkey()];
+        }
+        conditions
+    }
+
+    /// Swaps signed `inputs` for proofs of `send` with the spending conditions `lock`, returned,
+    /// and proofs of the rest less the fee, kept.
+    async fn swap(
+        &mut self,
+        unit: &str,
+        inputs: &[Proof],
+        send: u64,
+        lock: Option<&Conditions>,
+        keysets: &[KeySetSummary],
+    ) -> MintResult<Vec<Proof>> {
+        let fee = input_fee(keysets, inputs);
+        let available = total(inputs);
+        let Some(rest) = available.checked_sub(send + fee) else {
+            return Err(MintError::InsufficientBalance {
+                requested: send + fee,
+                available,
+            });
+        };
+        let keyset = self.client.keys(unit).await?;
+        let (mut outputs, mut secrets) = client::blank_outputs(&keyset, send, lock)?;
+        let sent = outputs.len();
+        let (kept_outputs, kept_secrets) = self.outputs(&keyset, rest)?;
+        outputs.extend(kept_outputs);
+        secrets.extend(kept_secrets);
+        let request = SwapRequest {
+            inputs: inputs.to_vec(),
+            outputs: outputs.clone(),
+        };
+        let response: SignaturesResponse = self.client.post("/v1/swap", &request).await?;
+        let mut proofs = client::unblind(&keyset, &outputs, &response.signatures, secrets)?;
+        self.keep(unit, proofs.split_off(sent))?;
+        Ok(proofs)
+    }
+
+    /// Takes proofs of `unit` covering `amount` and their input fee out of the wallet, largest
+    /// first.
+    fn take(
+        &mut self,
+        unit: &str,
+        amount: u64,
+        keysets: &[KeySetSummary],
+    ) -> MintResult<Vec<Proof>> {
+        let proofs = self.file.proofs.entry(unit.to_string()).or_default();
+        proofs.sort_by_key(|proof| Reverse(proof.amount));
+        let mut n = 0;
+        while total(&proofs[..n]) < amount + input_fee(keysets, &proofs[..n]) {
+            if n == proofs.len() {
+                return Err(MintError::InsufficientBalance {
+                    requested: amount + input_fee(keysets, proofs),
+                    available: total(proofs),
+                });
+            }
+            n += 1;
+        }
+        Ok(proofs.drain(..n).collect())
+    }
+
+    /// Outputs of `keyset` adding up to `amount` with the next deterministic secrets. The
+    /// counter is saved before they are sent, so no secret is used twice.
+    fn outputs(
+        &mut self,
+        keyset: &KeySet,
+        amount: u64,
+    ) -> MintResult<(Vec<BlindedMessage>, Secrets)> {
+        let counter = self.file.counters.entry(keyset.id.clone()).or_default();
+        let outputs = client::deterministic_outputs(keyset, amount, &self.derivation, counter)?;
+        self.save()?;
+        Ok(outputs)
+    }
+
+    fn keep(&mut self, unit: &str, proofs: Vec<Proof>) -> MintResult<()> {
+        self.file
+            .proofs
+            .entry(unit.to_string())
+            .or_default()
+            .extend(proofs);
+        self.save()
+    }
+
+    /// `proofs` with a witness for those with spending conditions, signed by the wallet key and
+    /// revealing `preimage` to those locked to a hash.
+    fn signed(&self, proofs: &[Proof], preimage: Option<&str>) -> Vec<Proof> {
+        proofs
+            .iter()
+            .map(|proof| match Conditions::parse(&proof.secret) {
+                Ok(Some(conditions)) => Proof {
+                    witness: Some(p2pk::unlock(
+                        &proof.secret,
+                        &self.file.key,
+                        conditions.hash.and(preimage),
+                    )),
+                    ..proof.clone()
+                },
+                _ => proof.clone(),
+            })
+            .collect()
+    }
+
+    async fn keysets(&self) -> MintResult<Vec<KeySetSummary>> {
+        let response: KeysetsResponse = self.client.get("/v1/keysets").await?;
+        Ok(response.keysets)
+    }
+
+    /// Writes the wallet file, replacing it only once fully written.
+    fn save(&self) -> MintResult<()> {
+        let json =
+            serde_json::to_vec_pretty(&self.file).map_err(|e| MintError::Wallet(e.to_string()))?;
+        let tmp = format!("{}.tmp", self.path);
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options.open(&tmp)?.write_all(&json)?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+fn parse_mnemonic(mnemonic: &str) -> MintResult<Mnemonic> {
+    Mnemonic::parse(mnemonic.trim()).map_err(|e| MintError::InvalidSeed(e.to_string()))
+}
+
+fn unit_of(keysets: &[KeySetSummary], id: &str) -> MintResult<String> {
+    keysets
+        .iter()
+        .find(|keyset| keyset.id == id)
+        .map(|keyset| keyset.unit.clone())
+        .ok_or_else(|| MintError::UnknownKeyset(id.to_string()))
+}
+
+/// NUT-02 fee of spending `proofs`.
+fn input_fee(keysets: &[KeySetSummary], proofs: &[Proof]) -> u64 {
+    proofs
+        .iter()
+        .map(|proof| {
+            keysets
+                .iter()
+                .find(|keyset| keyset.id == proof.id)
+                .map_or(0, |keyset| keyset.input_fee_ppk)
+        })
+        .sum::<u64>()
+        .div_ceil(1000)
+}
+
+fn total(proofs: &[Proof]) -> u64 {
+    proofs.iter().map(|proof| proof.amount).sum()
+}
+
+fn add(amounts: &mut BTreeMap<String, u64>, more: BTreeMap<String, u64>) {
+    for (unit, amount) in more {
+        *amounts.entry(unit).or_default() += amount;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pool_mint::mint::{
+        api::{self, ApiState},
+        info::{MintInfoConfig, MintInfoResponse},
+        lifecycle::now_secs,
+        payout::{PayoutConfig, PayoutMode},
+        Mint, MintConfig, EHASH_UNIT,
+    };
+    use roles_logic_sv2::utils::Mutex;
+    use sha2::{Digest, Sha256};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn claims_exports_and_receives_tokens() {
+        let mut mint = Mint::from_master_secret(&[3; 32], &MintConfig::default()).unwrap();
+        mint.credit_share("alice", 21).unwrap();
+        let credential = mint.issue_credential("alice").unwrap();
+        let info = MintInfoResponse::new(&MintInfoConfig::default(), "potato", &mint, false, None);
+        let mint = Arc::new(Mutex::new(mint));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let router = api::router(ApiState::new(
+            mint.clone(),
+            None,
+            None,
+            info,
+            &Default::default(),
+        ));
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        let dir = std::env::temp_dir().join(format!("potato-wallet-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+
+        let mut wallet = Wallet::open(&path("alice.json"), &url).unwrap();
+        assert_eq!(
+            wallet.account_balance("alice", &credential).await.unwrap()[EHASH_UNIT],
+            21
+        );
+        // only the owner of the account sees and mints its balance
+        assert!(matches!(
+            wallet.account_balance("alice", "0123").await,
+            Err(MintError::MintRequest(e)) if e.starts_with("401")
+        ));
+        assert!(matches!(
+            wallet.claim("alice", "0123").await,
+            Err(MintError::MintRequest(e)) if e.starts_with("401")
+        ));
+        assert_eq!(
+            wallet.claim("alice", &credential).await.unwrap()[EHASH_UNIT],
+            21
+        );
+        assert_eq!(wallet.balance()[EHASH_UNIT], 21);
+
+        // payouts are locked to the key the claim registered
+        let config = PayoutConfig {
+            mode: PayoutMode::Hold,
+            unit: EHASH_UNIT.into(),
+            min_amount: 1,
+            ..Default::default()
+        };
+        mint.safe_lock(|m| {
+            m.credit_share("alice", 8).unwrap();
+            m.pay_out_due(&config, &url).unwrap();
+        })
+        .unwrap();
+        assert_eq!(
+            wallet.claim("alice", &credential).await.unwrap()[EHASH_UNIT],
+            8
+        );
+        assert_eq!(wallet.balance()[EHASH_UNIT], 29);
+
+        // a claim consolidates many small payouts
+        for _ in 0..10 {
+            mint.safe_lock(|m| {
+                m.credit_share("alice", 1).unwrap();
+                m.pay_out_due(&config, &url).unwrap();
+            })
+            .unwrap();
+        }
+        assert_eq!(
+            wallet.claim("alice", &credential).await.unwrap()[EHASH_UNIT],
+            10
+        );
+        assert_eq!(wallet.balance()[EHASH_UNIT], 39);
+        assert_eq!(wallet.file.proofs[EHASH_UNIT].len(), 4);
+        assert_eq!(wallet.consolidate().await.unwrap(), 0);
+        mint.safe_lock(|m| m.credit_share("alice", 10).unwrap())
+            .unwrap();
+        wallet.claim("alice", &credential).await.unwrap();
+
+        let token = wallet.export(EHASH_UNIT, 5, None).await.unwrap();
+        assert_eq!(token.amount(), 5);
+        assert_eq!(wallet.balance()[EHASH_UNIT], 44);
+        assert!(wallet.export(EHASH_UNIT, 45, None).await.is_err());
+        assert_eq!(wallet.balance()[EHASH_UNIT], 44);
+
+        let token = token.encode().unwrap();
+        let mut bob = Wallet::open(&path("bob.json"), &url).unwrap();
+        assert_eq!(bob.check(&token).await.unwrap().unspent[EHASH_UNIT], 5);
+        assert_eq!(bob.receive(&token, None).await.unwrap()[EHASH_UNIT], 5);
+        assert!(bob.receive(&token, None).await.is_err());
+        let check = bob.check(&token).await.unwrap();
+        assert!(check.unspent.is_empty());
+        assert_eq!(check.spent[EHASH_UNIT], 5);
+
+        // sold to bob for the preimage of a payment hash, or back to alice after the locktime
+        let preimage = [5; 32];
+        let hash = Sha256::digest(preimage).into();
+        let lock = wallet.hash_lock(hash, Some(bob.pubkey()), Some(now_secs() + 3600));
+        let sold = wallet.export(EHASH_UNIT, 4, Some(&lock)).await.unwrap();
+        let sold = sold.encode().unwrap();
+        assert!(bob.receive(&sold, None).await.is_err());
+        assert!(wallet.receive(&sold, None).await.is_err());
+        let received = bob.receive(&sold, Some(&hex::encode(preimage))).await;
+        assert_eq!(received.unwrap()[EHASH_UNIT], 4);
+        let lock = wallet.hash_lock(hash, Some(bob.pubkey()), Some(now_secs()));
+        let expired = wallet.export(EHASH_UNIT, 2, Some(&lock)).await.unwrap();
+        let expired = expired.encode().unwrap();
+        assert!(bob
+            .receive(&expired, Some(&hex::encode(preimage)))
+            .await
+            .is_ok());
+        let lock = wallet.hash_lock(hash, None, Some(now_secs()));
+        let refunded = wallet.export(EHASH_UNIT, 2, Some(&lock)).await.unwrap();
+        let refunded = refunded.encode().unwrap();
+        assert_eq!(
+            wallet.receive(&refunded, None).await.unwrap()[EHASH_UNIT],
+            2
+        );
+        assert_eq!(wallet.balance()[EHASH_UNIT], 38);
+
+        let reopened = Wallet::open(&path("alice.json"), &url).unwrap();
+        assert_eq!(reopened.balance()[EHASH_UNIT], 38);
+        assert_eq!(reopened.pubkey(), wallet.pubkey());
+
+        // the mnemonic alone recovers the unspent proofs and the payout key
+        let (restored, amounts) = Wallet::restore(&path("restored.json"), &url, wallet.mnemonic())
+            .await
+            .unwrap();
+        assert_eq!(amounts[EHASH_UNIT], 38);
+        assert_eq!(restored.pubkey(), wallet.pubkey());
+        assert_eq!(restored.file.counters, wallet.file.counters);
+        assert!(
+            Wallet::restore(&path("restored.json"), &url, wallet.mnemonic())
+                .await
+                .is_err()
+        );
+        assert!(Wallet::open(&path("alice.json"), "http://elsewhere").is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
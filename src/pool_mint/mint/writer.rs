@@ -0,0 +1,404 @@
+//! Writes the share accounting off the share path of the pool. Crediting a share queues it for
+//! the `AccountingWriter` task and returns right away; the task commits what is queued in batched
+//! transactions, up to `max_batch` credits waiting at most `batch_interval_ms` for a batch to
+//! fill, so a busy pool syncs the database once per batch instead of once per share.
+//!
+//! Credits are committed in the order they were accepted, a found block closing its round after
+//! the shares before it. Once the queue is full, new credits wait in an overflow where the shares
+//! of an account between two blocks add up, until the task takes them with its next batch:
+//! a database slower than the shares coming in costs memory per account, never a share. A failing
+//! database journals credits as before, see `journal`.
+//!
+//! With `synchronous = "normal"`, SQLite only syncs at WAL checkpoints: the last commits before
+//! a power loss may be lost, never the database. It applies to every write of the mint,
+//! issuance included, so is only worth it when the disk is the bottleneck.
+use super::{
+    backend::MintBackend,
+    journal::Credit,
+    nuts::{
+        BlindSignature, BlindedMessage, KeySet, KeySetSummary, MintQuoteResponse, Proof, ProofState,
+    },
+    payout::Payout,
+};
+use crate::error::MintResult;
+use async_channel::{bounded, Receiver, Sender, TrySendError};
+use async_trait::async_trait;
+use roles_logic_sv2::utils::Mutex;
+use secp256k1::PublicKey;
+use serde::Deserialize;
+use std::{sync::Arc, time::Duration};
+use tokio::{task::JoinHandle, time::Instant};
+use tracing::{error, warn};
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct AccountingConfig {
+    /// Credits queued for the writer before they overflow.
+    #[serde(default = "AccountingConfig::default_queue_capacity")]
+    pub queue_capacity: usize,
+    /// Most credits committed in one transaction.
+    #[serde(default = "AccountingConfig::default_max_batch")]
+    pub max_batch: usize,
+    /// How long the first credit of a batch waits for others to join it.
+    #[serde(default = "AccountingConfig::default_batch_interval_ms")]
+    pub batch_interval_ms: u64,
+    #[serde(default)]
+    pub synchronous: Synchronous,
+}
+
+impl AccountingConfig {
+    fn default_queue_capacity() -> usize {
+        10_000
+    }
+
+    fn default_max_batch() -> usize {
+        500
+    }
+
+    fn default_batch_interval_ms() -> u64 {
+        50
+    }
+}
+
+impl Default for AccountingConfig {
+    fn default() -> Self {
+        Self {
+            queue_capacity: Self::default_queue_capacity(),
+            max_batch: Self::default_max_batch(),
+            batch_interval_ms: Self::default_batch_interval_ms(),
+            synchronous: Synchronous::default(),
+        }
+    }
+}
+
+/// When SQLite syncs the mint database to disk, see the module.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Synchronous {
+    /// On every commit.
+    #[default]
+    Full,
+    /// At WAL checkpoints.
+    Normal,
+}
+
+impl Synchronous {
+    pub fn pragma(self) -> &'static str {
+        match self {
+            Synchronous::Full => "FULL",
+            Synchronous::Normal => "NORMAL",
+        }
+    }
+}
+
+/// Credits shares and blocks through a task of its own, see the module.
+#[derive(Debug)]
+pub struct AccountingWriter {
+    mint: Arc<dyn MintBackend>,
+    queue: Sender<Credit>,
+    /// Credits that found the queue full, in order.
+    overflow: Arc<Mutex<Vec<Credit>>>,
+    task: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl AccountingWriter {
+    /// Starts the task committing the credits to `mint`.
+    pub fn spawn(mint: Arc<dyn MintBackend>, config: &AccountingConfig) -> Arc<Self> {
+        let (queue, credits) = bounded(config.queue_capacity.max(1));
+        let overflow = Arc::new(Mutex::new(vec![]));
+        let task = tokio::spawn(Self::run(
+            mint.clone(),
+            credits,
+            overflow.clone(),
+            config.max_batch.max(1),
+            Duration::from_millis(config.batch_interval_ms),
+        ));
+        Arc::new(Self {
+            mint,
+            queue,
+            overflow,
+            task: Mutex::new(Some(task)),
+        })
+    }
+
+    /// Stops taking credits and waits for those accepted to be committed. Credits after this are
+    /// committed right away.
+    pub async fn close(&self) {
+        self.queue.close();
+        let task = self.task.safe_lock(Option::take).ok().flatten();
+        if let Some(task) = task {
+            if let Err(e) = task.await {
+                error!("Mint: accounting writer failed: {}", e);
+            }
+        }
+        Self::commit(&self.mint, &self.overflow, vec![]).await;
+    }
+
+    async fn run(
+        mint: Arc<dyn MintBackend>,
+        credits: Receiver<Credit>,
+        overflow: Arc<Mutex<Vec<Credit>>>,
+        max_batch: usize,
+        batch_interval: Duration,
+    ) {
+        while let Ok(first) = credits.recv().await {
+            let mut batch = vec![first];
+            let deadline = Instant::now() + batch_interval;
+            while batch.len() < max_batch {
+                match tokio::time::timeout_at(deadline, credits.recv()).await {
+                    Ok(Ok(credit)) => batch.push(credit),
+                    _ => break,
+                }
+            }
+            Self::commit(&mint, &overflow, batch).await;
+        }
+    }
+
+    /// Commits `batch`, then what overflowed meanwhile, which came after it.
+    async fn commit(
+        mint: &Arc<dyn MintBackend>,
+        overflow: &Mutex<Vec<Credit>>,
+        batch: Vec<Credit>,
+    ) {
+        let overflowed = overflow.safe_lock(std::mem::take).unwrap_or_default();
+        if batch.is_empty() && overflowed.is_empty() {
+            return;
+        }
+        let mint = mint.clone();
+        let credits = batch.into_iter().chain(overflowed);
+        let committed = tokio::task::spawn_blocking(move || apply(mint.as_ref(), credits)).await;
+        if let Err(e) = committed {
+            error!("Mint: committing credits failed: {}", e);
+        }
+    }
+
+    /// Queues `credit`, or overflows it once the queue is full or something overflowed before,
+    /// to keep the order. Returns it if the writer is closed.
+    fn queue(&self, credit: Credit) -> Option<Credit> {
+        self.overflow
+            .safe_lock(|overflow| {
+                let credit = match overflow.is_empty() {
+                    true => match self.queue.try_send(credit) {
+                        Ok(()) => return None,
+                        Err(TrySendError::Closed(credit)) => return Some(credit),
+                        Err(TrySendError::Full(credit)) => {
+                            warn!("Mint: the accounting queue is full, the database lags behind");
+                            credit
+                        }
+                    },
+                    false => credit,
+                };
+                overflow_into(overflow, credit);
+                None
+            })
+            .unwrap_or_else(|e| {
+                error!("Mint: accounting overflow lock poisoned: {}", e);
+                None
+            })
+    }
+}
+
+/// Pushes `credit`, a share adding up to one of its account queued since the last block if any.
+fn overflow_into(overflow: &mut Vec<Credit>, credit: Credit) {
+    if let Credit::Share { account, weight } = &credit {
+        let since_block = overflow
+            .iter_mut()
+            .rev()
+            .take_while(|queued| matches!(queued, Credit::Share { .. }));
+        for queued in since_block {
+            if let Credit::Share {
+                account: queued_account,
+                weight: queued_weight,
+            } = queued
+            {
+                if queued_account == account {
+                    *queued_weight += weight;
+                    return;
+                }
+            }
+        }
+    }
+    overflow.push(credit);
+}
+
+/// Applies `credits` in order, the shares between two blocks in one transaction.
+fn apply(mint: &dyn MintBackend, credits: impl IntoIterator<Item = Credit>) {
+    let mut shares = vec![];
+    for credit in credits {
+        match credit {
+            Credit::Share { account, weight } => shares.push((account, weight)),
+            Credit::Block {
+                coinbase_txid,
+                reward,
+                finder,
+            } => {
+                credit_shares(mint, &mut shares);
+                if let Err(e) = mint.found_block(&coinbase_txid, reward, finder.as_deref()) {
+                    error!(
+                        "Mint: failed to close the round of {}: {}",
+                        coinbase_txid, e
+                    );
+                }
+            }
+        }
+    }
+    credit_shares(mint, &mut shares);
+}
+
+fn credit_shares(mint: &dyn MintBackend, shares: &mut Vec<(String, u64)>) {
+    if shares.is_empty() {
+        return;
+    }
+    if let Err(e) = mint.credit_shares(shares) {
+        error!("Mint: failed to credit {} shares: {}", shares.len(), e);
+    }
+    shares.clear();
+}
+
+/// Issuance goes straight to the backend, only credits are queued.
+#[async_trait]
+impl MintBackend for AccountingWriter {
+    fn credit_share(&self, account: &str, weight: u64) -> MintResult<()> {
+        let credit = Credit::Share {
+            account: account.to_string(),
+            weight,
+        };
+        match self.queue(credit) {
+            Some(_) => self.mint.credit_share(account, weight),
+            None => Ok(()),
+        }
+    }
+
+    fn found_block(
+        &self,
+        coinbase_txid: &str,
+        reward: u64,
+        finder: Option<&str>,
+    ) -> MintResult<()> {
+        let credit = Credit::Block {
+            coinbase_txid: coinbase_txid.to_string(),
+            reward,
+            finder: finder.map(str::to_string),
+        };
+        match self.queue(credit) {
+            Some(_) => self.mint.found_block(coinbase_txid, reward, finder),
+            None => Ok(()),
+        }
+    }
+
+    fn undelivered_payouts(&self) -> MintResult<Vec<Payout>> {
+        self.mint.undelivered_payouts()
+    }
+
+    fn mark_payout_delivered(&self, id: &str) -> MintResult<()> {
+        self.mint.mark_payout_delivered(id)
+    }
+
+    async fn keys(&self, id: Option<&str>) -> MintResult<Vec<KeySet>> {
+        self.mint.keys(id).await
+    }
+
+    async fn keysets(&self) -> MintResult<Vec<KeySetSummary>> {
+        self.mint.keysets().await
+    }
+
+    async fn create_mint_quote(
+        &self,
+        account: &str,
+        amount: u64,
+        unit: &str,
+    ) -> MintResult<MintQuoteResponse> {
+        self.mint.create_mint_quote(account, amount, unit).await
+    }
+
+    async fn mint_quote(&self, id: &str) -> MintResult<MintQuoteResponse> {
+        self.mint.mint_quote(id).await
+    }
+
+    async fn mint(
+        &self,
+        quote: &str,
+        outputs: &[BlindedMessage],
+    ) -> MintResult<Vec<BlindSignature>> {
+        self.mint.mint(quote, outputs).await
+    }
+
+    async fn swap(
+        &self,
+        inputs: &[Proof],
+        outputs: &[BlindedMessage],
+    ) -> MintResult<Vec<BlindSignature>> {
+        self.mint.swap(inputs, outputs).await
+    }
+
+    async fn proof_states(&self, ys: &[PublicKey]) -> MintResult<Vec<ProofState>> {
+        self.mint.proof_states(ys).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pool_mint::mint::{Mint, MintConfig, EHASH_UNIT};
+
+    #[tokio::test]
+    async fn commits_every_credit_in_order_past_a_full_queue() {
+        let config = MintConfig::default();
+        let mint = Arc::new(Mutex::new(
+            Mint::from_master_secret(&[5; 32], &config).unwrap(),
+        ));
+        let config = AccountingConfig {
+            queue_capacity: 2,
+            max_batch: 2,
+            ..AccountingConfig::default()
+        };
+        let writer = AccountingWriter::spawn(mint.clone(), &config);
+        // more than the queue takes before the task gets to run
+        for _ in 0..5 {
+            writer.credit_share("alice", 3).unwrap();
+            writer.credit_share("bob", 2).unwrap();
+        }
+        writer.found_block("coinbase", 100, Some("alice")).unwrap();
+        writer.credit_share("bob", 7).unwrap();
+        writer.close().await;
+        // credited right away once closed
+        writer.credit_share("carol", 1).unwrap();
+
+        mint.safe_lock(|m| {
+            assert_eq!(m.balance("alice", EHASH_UNIT).unwrap(), 15);
+            assert_eq!(m.balance("bob", EHASH_UNIT).unwrap(), 17);
+            assert_eq!(m.balance("carol", EHASH_UNIT).unwrap(), 1);
+            let rounds = m.account_rounds("bob", 0, 10).unwrap();
+            let weights: Vec<_> = rounds.iter().map(|round| round.weight).collect();
+            // the shares before the block in its round, the one after in the next
+            assert_eq!(weights, vec![7, 10]);
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn adds_overflowing_shares_up_between_blocks() {
+        let share = |account: &str, weight| Credit::Share {
+            account: account.to_string(),
+            weight,
+        };
+        let block = Credit::Block {
+            coinbase_txid: "coinbase".to_string(),
+            reward: 1,
+            finder: None,
+        };
+        let mut overflow = vec![];
+        for credit in [
+            share("alice", 1),
+            share("bob", 1),
+            share("alice", 2),
+            block.clone(),
+            share("alice", 4),
+        ] {
+            overflow_into(&mut overflow, credit);
+        }
+        assert_eq!(
+            overflow,
+            vec![share("alice", 3), share("bob", 1), block, share("alice", 4)]
+        );
+    }
+}
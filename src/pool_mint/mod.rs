@@ -1,43 +1,122 @@
+#[cfg(feature = "pool")]
+pub mod maturity;
+#[cfg(feature = "pool")]
 pub mod mining_pool;
+pub mod mint;
+#[cfg(feature = "pool")]
+pub mod onchain_batch;
+#[cfg(feature = "pool")]
 pub mod template_receiver;
 
-use core::panic;
+#[cfg(feature = "pool")]
+use {
+    crate::{
+        configuration::ReloadablePoolConfig,
+        control::{self, ControlServer},
+        error::{Error, PoolError},
+        grpc::GrpcServer,
+        sim,
+        status::{
+            self,
+            events::{self, Event, Upstream},
+            heartbeat,
+            server::StatusServer,
+        },
+    },
+    async_channel::{bounded, unbounded},
+    core::panic,
+    maturity::MaturityWatcher,
+    mining_pool::{
+        accounts::Accounts,
+        authority::{self, Authority},
+        get_coinbase_output,
+        memory::MemoryBudgetConfig,
+        Pool, PoolConfiguration,
+    },
+    mint::{
+        api::ApiState,
+        backend::{MintBackend, PayoutIssuer},
+        external::ExternalMint,
+        info::MintInfoResponse,
+        melt::Melter,
+        nostr::NostrDelivery,
+        payout::{PayoutConfig, PayoutMode},
+        writer::AccountingWriter,
+        Mint,
+    },
+    onchain_batch::OnchainBatcher,
+    roles_logic_sv2::utils::Mutex,
+    std::{sync::Arc, time::Duration},
+    template_receiver::TemplateRx,
+    tokio::sync::Notify,
+    tokio_util::sync::CancellationToken,
+    tracing::{debug, error, info, warn},
+};
 
-use async_channel::{bounded, unbounded};
-use tokio_util::sync::CancellationToken;
-
-use crate::{error::PoolError, status};
-use mining_pool::{get_coinbase_output, Pool, PoolConfiguration};
-use template_receiver::TemplateRx;
-use tracing::{debug, error, info, warn};
+/// How often outputs queued by miners are signed, see `Mint::issue_queued`.
+#[cfg(feature = "pool")]
+const QUEUED_ISSUANCE_INTERVAL: Duration = Duration::from_secs(1);
+/// Most outputs signed per round of queued issuance.
+#[cfg(feature = "pool")]
+const QUEUED_ISSUANCE_BATCH: usize = 1000;
+/// How often credits journaled while the mint database was unavailable are replayed.
+#[cfg(feature = "pool")]
+const JOURNAL_REPLAY_INTERVAL: Duration = Duration::from_secs(5);
 
+/// The pool: serves SV2 miners the templates of the template provider at `tp_address` and
+/// credits their shares to the mint, along with the APIs its configuration enables.
+#[cfg(feature = "pool")]
 #[derive(Debug, Clone)]
 pub struct PoolSv2 {
     config: PoolConfiguration,
+    /// Where `config` comes from, reloaded through the gRPC API.
+    reload: ReloadablePoolConfig,
     cancel_token: CancellationToken,
 }
 
+#[cfg(feature = "pool")]
 impl PoolSv2 {
-    pub fn new(config: PoolConfiguration, cancel_token: CancellationToken) -> PoolSv2 {
+    /// The pool of `config`, reloaded from `reload` on demand, stopping once `cancel_token` is
+    /// cancelled.
+    pub fn new(
+        config: PoolConfiguration,
+        reload: ReloadablePoolConfig,
+        cancel_token: CancellationToken,
+    ) -> PoolSv2 {
         PoolSv2 {
             config,
+            reload,
             cancel_token,
         }
     }
 
+    /// Runs the pool until it is cancelled or fails, with the failure.
     pub async fn start(&self) -> Result<(), PoolError> {
         debug!("starting pool");
         let config = self.config.clone();
         let (status_tx, status_rx) = unbounded();
-        let (s_new_t, r_new_t) = bounded(10);
-        let (s_prev_hash, r_prev_hash) = bounded(10);
-        let (s_solution, r_solution) = bounded(10);
+        let channels = &config.template_channels;
+        let (s_new_t, r_new_t) = bounded(channels.templates.max(1));
+        let (s_prev_hash, r_prev_hash) = bounded(channels.templates.max(1));
+        let (s_solution, r_solution) = bounded(channels.solutions.max(1));
         let (s_message_recv_signal, r_message_recv_signal) = bounded(10);
-        let coinbase_output_result = get_coinbase_output(&config);
-        let coinbase_output_len = coinbase_output_result?.len() as u32;
+        // checked before connecting, the outputs of the accounts taking room in the coinbase too
+        let accounts = Accounts::new(get_coinbase_output(&config)?, &config.accounts)?;
+        let coinbase_output_len = accounts.coinbase_size();
         let tp_authority_public_key = config.tp_authority_public_key;
-        TemplateRx::connect(
-            config.tp_address.parse().unwrap(),
+        // served while waiting for the template provider, so probes see the pool is not ready
+        let status_server = StatusServer::default();
+        if let Some(status_address) = config.status_address.clone() {
+            let server = status_server.clone();
+            let status_cancel_token = self.cancel_token.clone();
+            tokio::spawn(async move {
+                if let Err(e) = server.serve(&status_address, status_cancel_token).await {
+                    error!("Status API stopped: {}", e);
+                }
+            });
+        }
+        let connecting = TemplateRx::connect(
+            &config.tp_address,
             s_new_t,
             s_prev_hash,
             r_solution,
@@ -45,21 +124,174 @@ impl PoolSv2 {
             status::Sender::Upstream(status_tx.clone()),
             coinbase_output_len,
             tp_authority_public_key,
-        )
-        .await?;
+            &config.tp_retry,
+            self.cancel_token.clone(),
+        );
+        // cancelled while retrying the template provider, there is nothing to drain yet
+        let Some(connected) = self.cancel_token.run_until_cancelled(connecting).await else {
+            info!("Cancelled before the template provider connected");
+            return Ok(());
+        };
+        connected?;
         debug!("template receiver connected");
+        events::publish(Event::NodeReady {
+            address: config.tp_address.clone(),
+        });
+        let authority_secret_key = config.authority_secret_key.map(|key| key.into_bytes());
+        let mint = Arc::new(Mutex::new(Mint::new(
+            &config.mint,
+            authority_secret_key.as_ref(),
+        )?));
+        status_server.set_mint(mint.clone());
+        tokio::spawn(heartbeat::watch_lock(
+            "mint",
+            mint.clone(),
+            self.cancel_token.clone(),
+        ));
+        let external = match &config.mint.external {
+            Some(external) => {
+                info!(
+                    "Mint: paying out tokens of the external mint at {}",
+                    external.url
+                );
+                if !config.mint.payout.is_enabled() {
+                    warn!("External mint configured without payouts, miners won't get tokens");
+                }
+                Some(Arc::new(ExternalMint::new(external)?))
+            }
+            None => None,
+        };
+        // the mint issuing the tokens paid out if not this one, and serving the Cashu API in its
+        // place if run in the process
+        let cdk = Self::start_cdk_mint(&config, &mint, external.is_some()).await?;
+        let backend: Option<Arc<dyn MintBackend>> = cdk.clone().map(|(_, backend)| backend);
+        let issuer: Option<Arc<dyn PayoutIssuer>> = match cdk {
+            Some((issuer, _)) => Some(issuer),
+            None => external.clone().map(|external| external as _),
+        };
+        let nostr = match &config.mint.nostr {
+            Some(nostr) => {
+                let delivery = NostrDelivery::new(nostr)?;
+                info!(
+                    "Mint: sending payouts over Nostr as {} through {} relays",
+                    delivery.pubkey(),
+                    nostr.relays.len()
+                );
+                Some(Arc::new(delivery))
+            }
+            None => {
+                if config.mint.payout.has_mode(PayoutMode::Nostr) {
+                    warn!("Nostr payouts configured without relays, their tokens are held");
+                }
+                None
+            }
+        };
+        // keysets and queued outputs of this mint are only used if it issues the tokens
+        if issuer.is_none() {
+            if let Some(interval) = config.mint.keyset_rotation_interval_secs {
+                Self::schedule_keyset_rotation(mint.clone(), interval, self.cancel_token.clone());
+            }
+            Self::schedule_queued_issuance(mint.clone(), self.cancel_token.clone());
+        }
+        Self::schedule_journal_replay(mint.clone(), self.cancel_token.clone());
+        if let Some(interval) = config.mint.reconcile.interval_secs {
+            Self::schedule_reconciliation(
+                mint.clone(),
+                interval,
+                config.mint.reconcile.repair,
+                self.cancel_token.clone(),
+            );
+        }
+        match &config.bitcoin_rpc {
+            Some(bitcoin_rpc) => {
+                let watcher = MaturityWatcher::new(mint.clone(), bitcoin_rpc)?;
+                tokio::spawn(watcher.run(self.cancel_token.clone()));
+            }
+            None => {
+                warn!("No bitcoin_rpc configured, rewards of found blocks won't be paid out");
+                if config.mint.epochs.enabled {
+                    warn!(
+                        "No bitcoin_rpc configured, ehash keysets won't follow difficulty epochs"
+                    );
+                }
+            }
+        }
+        // wallets only deal with the external mint, which holds the tokens paid out
+        if external.is_none() {
+            self.serve_mint_api(&config, mint.clone(), backend.clone())?;
+        }
+        let control_token = control::load_or_create_token(&config.control_token_path)?;
+        let control = ControlServer::new(mint.clone(), &config.rate_limits.control, control_token);
+        let control_address = config.control_address.clone();
+        let control_cancel_token = self.cancel_token.clone();
+        tokio::spawn(async move {
+            if let Err(e) = control.serve(&control_address, control_cancel_token).await {
+                error!("Control API stopped: {}", e);
+            }
+        });
+        // shares are credited by a task of their own, off the share path
+        let accounting = AccountingWriter::spawn(
+            backend.unwrap_or_else(|| mint.clone()),
+            &config.mint.accounting,
+        );
+        let authority = Authority::new(&config, sim::unix_secs()).await?;
+        info!(
+            "Noise certificate signed by {}, valid until {}",
+            authority.public_key(),
+            authority.not_valid_after()
+        );
         let pool = Pool::start(
             config.clone(),
+            authority,
+            accounts,
+            accounting.clone(),
             r_new_t,
             r_prev_hash,
             s_solution,
             s_message_recv_signal,
             status::Sender::DownstreamListener(status_tx),
+            self.cancel_token.clone(),
         );
         debug!("pool started");
+        tokio::spawn(authority::run(pool.clone(), self.cancel_token.clone()));
+        if let Some(budget) = config.memory_budget.clone() {
+            Self::schedule_memory_budget(pool.clone(), budget, self.cancel_token.clone());
+        }
+        // notified by the gRPC API to pay out before the next interval
+        let payouts = config
+            .mint
+            .payout
+            .is_enabled()
+            .then(|| Arc::new(Notify::new()));
+        if let Some(grpc) = config.grpc.clone() {
+            let server = GrpcServer::new(
+                mint.clone(),
+                pool.clone(),
+                payouts.clone(),
+                self.reload.clone(),
+            );
+            let grpc_cancel_token = self.cancel_token.clone();
+            tokio::spawn(async move {
+                if let Err(e) = server.serve(&grpc, grpc_cancel_token).await {
+                    error!("gRPC API stopped: {}", e);
+                }
+            });
+        }
+        if let Some(trigger) = payouts {
+            Self::schedule_payouts(
+                mint.clone(),
+                pool.clone(),
+                config.mint.payout.clone(),
+                config.mint.url(),
+                issuer,
+                nostr,
+                trigger,
+                self.cancel_token.clone(),
+            );
+        }
         // Start the error handling loop
-        // See `./status.rs` and `utils/error_handling` for information on how this operates
-        loop {
+        // See `../status/mod.rs` and `utils/error_handling` for information on how this operates
+        let stopped = loop {
             tokio::select! {
                 task_status = status_rx.recv() => {
                     let task_status: status::Status = task_status.unwrap();
@@ -71,11 +303,18 @@ impl PoolSv2 {
                                 "SHUTDOWN from Downstream: {}\nTry to restart the downstream listener",
                                 err
                             );
-                            break Ok(());
+                            break Err(match err {
+                                Error::MiningPoolError(e) => e,
+                                err => PoolError::ComponentShutdown(err.to_string()),
+                            });
                         }
                         status::State::TemplateProviderShutdown(err) => {
+                            events::publish(Event::UpstreamDown {
+                                upstream: Upstream::TemplateProvider,
+                                reason: err.to_string(),
+                            });
                             error!("SHUTDOWN from Upstream: {}\nTry to reconnecting or connecting to a new upstream", err);
-                            break Ok(());
+                            break Err(err);
                         }
                         status::State::Healthy(msg) => {
                             info!("HEALTHY message: {}", msg);
@@ -96,9 +335,368 @@ impl PoolSv2 {
                 },
                 _ = self.cancel_token.cancelled() => {
                     info!("Cancellation token triggered, shutting down...");
+                    if let Err(e) = Pool::shut_down(&pool).await {
+                        break Err(e);
+                    }
                     break Ok(());
                 }
             }
+        };
+        // the credits of the shares accepted so far are committed before the pool goes away
+        accounting.close().await;
+        if self.cancel_token.is_cancelled() {
+            Self::flush_journal(&mint);
+        }
+        stopped
+    }
+
+    /// Replays the credits journaled one last time before the process exits, telling how many
+    /// are left for the next start.
+    fn flush_journal(mint: &Arc<Mutex<Mint>>) {
+        match mint.safe_lock(|m| m.replay_journal().and_then(|_| m.database_health())) {
+            Ok(Ok(0)) => {}
+            Ok(Ok(n)) => warn!("Mint: {} credits stay journaled for the next start", n),
+            Ok(Err(e)) => warn!("Mint: credits stay journaled for the next start: {}", e),
+            Err(e) => error!("Mint: lock poisoned: {}", e),
+        }
+    }
+
+    /// Opens the CDK mint issuing the tokens in place of this one if configured, unless an
+    /// external mint already does.
+    #[cfg(feature = "cdk")]
+    async fn start_cdk_mint(
+        config: &PoolConfiguration,
+        mint: &Arc<Mutex<Mint>>,
+        external: bool,
+    ) -> Result<Option<(Arc<dyn PayoutIssuer>, Arc<dyn MintBackend>)>, PoolError> {
+        match &config.mint.cdk {
+            Some(_) if external => {
+                warn!(
+                    "Both an external and a CDK mint configured, paying out with the external one"
+                );
+                Ok(None)
+            }
+            Some(cdk) => {
+                let cdk =
+                    Arc::new(mint::cdk_mint::CdkMint::new(mint.clone(), &config.mint, cdk).await?);
+                Ok(Some((cdk.clone(), cdk)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    #[cfg(not(feature = "cdk"))]
+    async fn start_cdk_mint(
+        _config: &PoolConfiguration,
+        _mint: &Arc<Mutex<Mint>>,
+        _external: bool,
+    ) -> Result<Option<(Arc<dyn PayoutIssuer>, Arc<dyn MintBackend>)>, PoolError> {
+        Ok(None)
+    }
+
+    /// Serves the Cashu API of the mint, melting through the configured Lightning node and the
+    /// on-chain batches sent through bitcoin_rpc. Tokens issued by `backend` instead, if given,
+    /// aren't melted.
+    fn serve_mint_api(
+        &self,
+        config: &PoolConfiguration,
+        mint: Arc<Mutex<Mint>>,
+        backend: Option<Arc<dyn MintBackend>>,
+    ) -> Result<(), PoolError> {
+        let melter = match &config.mint.lightning {
+            Some(_) if backend.is_some() => {
+                warn!("Mint: melting is disabled with a CDK mint");
+                None
+            }
+            Some(lightning) => {
+                let melter = Arc::new(Melter::new(mint.clone(), lightning)?);
+                tokio::spawn(melter.clone().run_settlement(self.cancel_token.clone()));
+                Some(melter)
+            }
+            None => None,
+        };
+        let onchain = match (&config.mint.onchain, &config.bitcoin_rpc) {
+            (Some(_), _) if backend.is_some() => None,
+            (Some(onchain), Some(bitcoin_rpc)) => {
+                let batcher = OnchainBatcher::new(mint.clone(), onchain, bitcoin_rpc)?;
+                tokio::spawn(batcher.run(self.cancel_token.clone()));
+                Some(onchain.clone())
+            }
+            (Some(_), None) => {
+                warn!("No bitcoin_rpc configured, on-chain melts are disabled");
+                None
+            }
+            (None, _) => None,
+        };
+        let info = mint.safe_lock(|m| {
+            MintInfoResponse::new(
+                &config.mint.info,
+                &config.pool_signature,
+                m,
+                melter.is_some(),
+                onchain.as_ref(),
+            )
+        })?;
+        let limits = match config.rate_limits.mint.is_unlimited() {
+            true if !config.mint.rate_limits.is_unlimited() => {
+                warn!("mint.rate_limits is deprecated, set rate_limits.mint instead");
+                &config.mint.rate_limits
+            }
+            _ => &config.rate_limits.mint,
+        };
+        let api_state = match backend {
+            Some(backend) => ApiState::new(mint, None, None, info.issued_elsewhere(), limits)
+                .with_backend(backend),
+            None => ApiState::new(mint, melter, onchain, info, limits),
+        };
+        let api_address = config.mint.api_address.clone();
+        let api_cancel_token = self.cancel_token.clone();
+        tokio::spawn(async move {
+            if let Err(e) = mint::api::serve(api_state, &api_address, api_cancel_token).await {
+                error!("Mint API stopped: {}", e);
+            }
+        });
+        Ok(())
+    }
+
+    /// Rotates the mint keyset once the active one is older than `interval_secs`. Checked every
+    /// minute at most, so a restart does not push the next rotation back.
+    fn schedule_keyset_rotation(
+        mint: Arc<Mutex<Mint>>,
+        interval_secs: u64,
+        cancel_token: CancellationToken,
+    ) {
+        let period = Duration::from_secs(interval_secs.clamp(1, 60));
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(period);
+            loop {
+                tokio::select! {
+                    _ = ticker.tick() => {
+                        match mint.safe_lock(|m| m.rotate_keysets_if_due(interval_secs)) {
+                            Ok(Ok(ids)) => {
+                                for id in ids {
+                                    info!("Mint: scheduled rotation to keyset {}", id);
+                                }
+                            }
+                            Ok(Err(e)) => error!("Mint: scheduled keyset rotation failed: {}", e),
+                            Err(e) => {
+                                error!("Mint: lock poisoned: {}", e);
+                                break;
+                            }
+                        }
+                    }
+                    _ = cancel_token.cancelled() => break,
+                }
+            }
+        });
+    }
+
+    /// Signs the outputs miners queued in the background, so share acceptance never waits on
+    /// signing.
+    fn schedule_queued_issuance(mint: Arc<Mutex<Mint>>, cancel_token: CancellationToken) {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(QUEUED_ISSUANCE_INTERVAL);
+            loop {
+                tokio::select! {
+                    _ = ticker.tick() => {
+                        match mint.safe_lock(|m| m.issue_queued(QUEUED_ISSUANCE_BATCH)) {
+                            Ok(Ok(0)) => {}
+                            Ok(Ok(n)) => debug!("Mint: signed {} queued outputs", n),
+                            Ok(Err(e)) => error!("Mint: queued issuance failed: {}", e),
+                            Err(e) => {
+                                error!("Mint: lock poisoned: {}", e);
+                                break;
+                            }
+                        }
+                    }
+                    _ = cancel_token.cancelled() => break,
+                }
+            }
+        });
+    }
+
+    /// Replays the credits journaled while the database was unavailable, even if no share comes
+    /// in to trigger it.
+    fn schedule_journal_replay(mint: Arc<Mutex<Mint>>, cancel_token: CancellationToken) {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(JOURNAL_REPLAY_INTERVAL);
+            loop {
+                tokio::select! {
+                    _ = ticker.tick() => {
+                        match mint.safe_lock(|m| m.replay_journal()) {
+                            Ok(Ok(_)) => {}
+                            Ok(Err(e)) => error!("Mint: replaying journaled credits failed: {}", e),
+                            Err(e) => {
+                                error!("Mint: lock poisoned: {}", e);
+                                break;
+                            }
+                        }
+                    }
+                    _ = cancel_token.cancelled() => break,
+                }
+            }
+        });
+    }
+
+    /// Checks the memory the pool takes against `budget` every `interval_secs`, shedding load
+    /// as it nears the budget.
+    fn schedule_memory_budget(
+        pool: Arc<Mutex<Pool>>,
+        budget: MemoryBudgetConfig,
+        cancel_token: CancellationToken,
+    ) {
+        tokio::spawn(async move {
+            let mut ticker =
+                tokio::time::interval(Duration::from_secs(budget.interval_secs.max(1)));
+            loop {
+                tokio::select! {
+                    _ = ticker.tick() => {
+                        if let Err(e) = Pool::enforce_memory_budget(pool.clone(), &budget).await {
+                            error!("Pool: memory budget check failed: {}", e);
+                        }
+                    }
+                    _ = cancel_token.cancelled() => break,
+                }
+            }
+        });
+    }
+
+    /// Cross-checks the shares of every round against what it credited every `interval_secs`,
+    /// crediting the shares never credited with `repair`.
+    fn schedule_reconciliation(
+        mint: Arc<Mutex<Mint>>,
+        interval_secs: u64,
+        repair: bool,
+        cancel_token: CancellationToken,
+    ) {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(Duration::from_secs(interval_secs.max(1)));
+            loop {
+                tokio::select! {
+                    _ = ticker.tick() => {
+                        match mint.safe_lock(|m| m.reconcile(repair)) {
+                            Ok(Ok(report)) if report.discrepancies.is_empty() => {
+                                debug!("Mint: reconciled {} rounds", report.rounds)
+                            }
+                            Ok(Ok(report)) => warn!(
+                                "Mint: reconciled {} rounds, {} discrepancies, {} repaired",
+                                report.rounds,
+                                report.discrepancies.len(),
+                                report.repaired
+                            ),
+                            Ok(Err(e)) => error!("Mint: reconciliation failed: {}", e),
+                            Err(e) => {
+                                error!("Mint: lock poisoned: {}", e);
+                                break;
+                            }
+                        }
+                    }
+                    _ = cancel_token.cancelled() => break,
+                }
+            }
+        });
+    }
+
+    /// Pays out the balances due every `interval_secs`, or when `trigger` is notified, as tokens
+    /// of `issuer` if this mint doesn't issue them, and pushes the payouts of accounts paid over
+    /// stratum to their connections and those of accounts paid over Nostr to their keys, retrying
+    /// those not delivered yet on every tick.
+    #[allow(clippy::too_many_arguments)]
+    fn schedule_payouts(
+        mint: Arc<Mutex<Mint>>,
+        pool: Arc<Mutex<Pool>>,
+        config: PayoutConfig,
+        mint_url: String,
+        issuer: Option<Arc<dyn PayoutIssuer>>,
+        nostr: Option<Arc<NostrDelivery>>,
+        trigger: Arc<Notify>,
+        cancel_token: CancellationToken,
+    ) {
+        tokio::spawn(async move {
+            let mut ticker =
+                tokio::time::interval(Duration::from_secs(config.interval_secs.max(1)));
+            loop {
+                tokio::select! {
+                    _ = ticker.tick() => {}
+                    _ = trigger.notified() => info!("Mint: payouts triggered"),
+                    _ = cancel_token.cancelled() => break,
+                }
+                // finished once begun, cut short a token issued by another mint is lost
+                if let Some(issuer) = &issuer {
+                    if let Err(e) = Self::pay_out_elsewhere(&mint, issuer.as_ref(), &config).await {
+                        error!("Mint: payout failed: {}", e);
+                    }
+                } else {
+                    match mint.safe_lock(|m| m.pay_out_due(&config, &mint_url)) {
+                        Ok(Ok(_)) => {}
+                        Ok(Err(e)) => error!("Mint: payout failed: {}", e),
+                        Err(e) => {
+                            error!("Mint: lock poisoned: {}", e);
+                            break;
+                        }
+                    }
+                }
+                if let Some(nostr) = &nostr {
+                    if let Err(e) = Self::deliver_nostr(&mint, nostr, &config).await {
+                        error!("Mint: Nostr payout delivery failed: {}", e);
+                    }
+                }
+                if let Err(e) = Pool::deliver_payouts(pool.clone(), &config).await {
+                    error!("Mint: payout delivery failed: {}", e);
+                }
+            }
+        });
+    }
+
+    /// Sends the payouts of accounts paid over Nostr to the keys they registered, holding those
+    /// of accounts without one. The mint isn't locked while relays answer.
+    async fn deliver_nostr(
+        mint: &Arc<Mutex<Mint>>,
+        nostr: &NostrDelivery,
+        config: &PayoutConfig,
+    ) -> Result<(), PoolError> {
+        let payouts = mint.safe_lock(|m| m.undelivered_payouts())??;
+        for payout in payouts
+            .into_iter()
+            .filter(|payout| config.mode(&payout.account) == PayoutMode::Nostr)
+        {
+            let Some(pubkey) = mint.safe_lock(|m| m.nostr_key(&payout.account))?? else {
+                continue;
+            };
+            if let Err(e) = nostr.send(&pubkey, &payout.token).await {
+                warn!(
+                    "Mint: sending payout {} over Nostr failed: {}",
+                    payout.id, e
+                );
+                continue;
+            }
+            mint.safe_lock(|m| m.mark_payout_delivered(&payout.id))??;
+            info!(
+                "Mint: sent payout of {} {} to {} over Nostr",
+                payout.amount, payout.unit, payout.account
+            );
+        }
+        Ok(())
+    }
+
+    /// Has `issuer` issue a token for every balance due, locked to the key of the account if it
+    /// registered one. The mint isn't locked during requests, a balance is only debited once its
+    /// token is issued.
+    async fn pay_out_elsewhere(
+        mint: &Arc<Mutex<Mint>>,
+        issuer: &dyn PayoutIssuer,
+        config: &PayoutConfig,
+    ) -> Result<(), PoolError> {
+        let due = mint.safe_lock(|m| m.due_payouts(config))??;
+        for (account, amount) in due {
+            let pubkey = mint.safe_lock(|m| m.account_pubkey(&account))??;
+            if let Err(e) = issuer
+                .pay_out(mint, &account, &config.unit, amount, pubkey.as_ref())
+                .await
+            {
+                warn!("Mint: paying out {} failed: {}", account, e);
+            }
         }
+        Ok(())
     }
 }
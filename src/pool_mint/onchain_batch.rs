@@ -0,0 +1,151 @@
+//! Sends the payouts of on-chain melts (see `onchain`) in one transaction of the Bitcoin Core
+//! wallet every `interval_secs`, paying each address the sum of its payouts. The wallet funds the
+//! transaction and pays its fee, covered by the fee deducted from every payout.
+//!
+//! A batch is recorded with the mint before it is broadcast, and batches not known to be
+//! broadcast are broadcast again first, so a crash in between never pays the same melts twice.
+use crate::{
+    error::{MintError, MintResult},
+    pool_mint::{
+        maturity::{call, client, rpc, BitcoinRpcConfig},
+        mint::{
+            onchain::{OnchainBatch, OnchainConfig, OnchainPayout},
+            Mint,
+        },
+    },
+    retry::Retry,
+};
+use bitcoincore_rpc::{bitcoin::Amount, jsonrpc, Client, RpcApi};
+use roles_logic_sv2::utils::Mutex;
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+/// RPC code of `sendrawtransaction` for a transaction already in the chain.
+const RPC_VERIFY_ALREADY_IN_CHAIN: i32 = -27;
+
+#[derive(Debug)]
+pub struct OnchainBatcher {
+    mint: Arc<Mutex<Mint>>,
+    client: Arc<Client>,
+    retry: Retry,
+    interval: Duration,
+}
+
+impl OnchainBatcher {
+    pub fn new(
+        mint: Arc<Mutex<Mint>>,
+        config: &OnchainConfig,
+        bitcoin_rpc: &BitcoinRpcConfig,
+    ) -> MintResult<Self> {
+        Ok(Self {
+            mint,
+            client: Arc::new(client(bitcoin_rpc)?),
+            retry: bitcoin_rpc.retry(),
+            interval: Duration::from_secs(config.interval_secs.max(1)),
+        })
+    }
+
+    pub async fn run(self, cancel_token: CancellationToken) {
+        let mut ticker = tokio::time::interval(self.interval);
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {
+                    let sending = async {
+                        if let Err(e) = self.send_unsent().await {
+                            warn!("Mint: broadcasting recorded on-chain batches failed: {}", e);
+                        }
+                        if let Err(e) = self.send_batch().await {
+                            warn!("Mint: sending the on-chain payout batch failed: {}", e);
+                        }
+                    };
+                    // a batch recorded but cut short is broadcast again on the next start
+                    if cancel_token.run_until_cancelled(sending).await.is_none() {
+                        break;
+                    }
+                }
+                _ = cancel_token.cancelled() => break,
+            }
+        }
+    }
+
+    fn with_mint<T>(&self, f: impl FnOnce(&mut Mint) -> MintResult<T>) -> MintResult<T> {
+        self.mint.safe_lock(f).map_err(MintError::from)?
+    }
+
+    /// Broadcasts the batches recorded before a crash or a failed broadcast.
+    async fn send_unsent(&self) -> MintResult<()> {
+        for batch in self.with_mint(|m| m.unsent_onchain_batches())? {
+            self.broadcast(&batch).await?;
+        }
+        Ok(())
+    }
+
+    /// Sends the payouts queued since the last batch.
+    async fn send_batch(&self) -> MintResult<()> {
+        let payouts = self.with_mint(|m| m.onchain_payouts())?;
+        if payouts.is_empty() {
+            return Ok(());
+        }
+        let batch = self.build(&payouts).await?;
+        let quote_ids: Vec<_> = payouts.iter().map(|p| p.quote_id.clone()).collect();
+        self.with_mint(|m| m.record_onchain_batch(&batch, &quote_ids))?;
+        info!(
+            "Mint: on-chain batch {} pays {} melts",
+            batch.txid,
+            quote_ids.len()
+        );
+        self.broadcast(&batch).await
+    }
+
+    /// Funds and signs a transaction paying `payouts` with the node's wallet.
+    async fn build(&self, payouts: &[OnchainPayout]) -> MintResult<OnchainBatch> {
+        let mut outputs = HashMap::<String, u64>::new();
+        for payout in payouts {
+            *outputs.entry(payout.address.clone()).or_default() += payout.amount;
+        }
+        let outputs: HashMap<_, _> = outputs
+            .into_iter()
+            .map(|(address, amount)| (address, Amount::from_sat(amount)))
+            .collect();
+        call(&self.client, &self.retry, move |client| {
+            let raw = client
+                .create_raw_transaction_hex(&[], &outputs, None, None)
+                .map_err(rpc)?;
+            let funded = client.fund_raw_transaction(raw, None, None).map_err(rpc)?;
+            let signed = client
+                .sign_raw_transaction_with_wallet(&funded.hex[..], None, None)
+                .map_err(rpc)?;
+            if !signed.complete {
+                return Err(MintError::BitcoinRpc(
+                    "the wallet could not sign the batch".to_string(),
+                ));
+            }
+            Ok(OnchainBatch {
+                txid: signed.transaction().map_err(rpc)?.txid().to_string(),
+                tx: hex::encode(&signed.hex),
+            })
+        })
+        .await
+    }
+
+    /// Broadcasts `batch`, then settles the melts it pays.
+    async fn broadcast(&self, batch: &OnchainBatch) -> MintResult<()> {
+        let tx = batch.tx.clone();
+        call(&self.client, &self.retry, move |client| {
+            match client.send_raw_transaction(tx.as_str()) {
+                Ok(_) => Ok(()),
+                Err(bitcoincore_rpc::Error::JsonRpc(jsonrpc::Error::Rpc(e)))
+                    if e.code == RPC_VERIFY_ALREADY_IN_CHAIN =>
+                {
+                    Ok(())
+                }
+                Err(e) => Err(rpc(e)),
+            }
+        })
+        .await?;
+        self.with_mint(|m| m.settle_onchain_batch(&batch.txid))?;
+        info!("Mint: on-chain batch {} sent", batch.txid);
+        Ok(())
+    }
+}
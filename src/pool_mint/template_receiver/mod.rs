@@ -1,6 +1,7 @@
 use super::mining_pool::{EitherFrame, StdFrame};
 use crate::error::{PoolError, PoolResult};
 use crate::pool_mint::template_receiver::setup_connection::SetupConnectionHandler;
+use crate::retry::{Retry, RetryPolicy};
 use crate::status;
 use async_channel::{Receiver, Sender};
 use codec_sv2::{HandshakeRole, Initiator};
@@ -15,8 +16,9 @@ use roles_logic_sv2::{
     },
     utils::Mutex,
 };
-use std::{convert::TryInto, net::SocketAddr, sync::Arc};
+use std::{convert::TryInto, sync::Arc};
 use tokio::{net::TcpStream, task};
+use tokio_util::sync::CancellationToken;
 use tracing::{debug, info};
 
 mod message_handler;
@@ -34,7 +36,7 @@ pub struct TemplateRx {
 impl TemplateRx {
     #[allow(clippy::too_many_arguments)]
     pub async fn connect(
-        address: SocketAddr,
+        address: &str,
         templ_sender: Sender<NewTemplate<'static>>,
         prev_h_sender: Sender<SetNewPrevHash<'static>>,
         solution_receiver: Receiver<SubmitSolution<'static>>,
@@ -42,12 +44,19 @@ impl TemplateRx {
         status_tx: status::Sender,
         coinbase_out_len: u32,
         expected_tp_authority_public_key: Option<Secp256k1PublicKey>,
+        retry: &RetryPolicy,
+        cancel_token: CancellationToken,
     ) -> PoolResult<()> {
         debug!("connecting to template provider");
-        let stream = TcpStream::connect(address).await?;
+        let stream = Retry::new("template_provider", retry)
+            .run(|| TcpStream::connect(address))
+            .await
+            .map_err(|e| PoolError::Unreachable(e.to_string()))?;
         debug!("connected to template provider");
+        // the address of the host connected to, of those `address` resolves to
+        let peer = stream.peer_addr()?;
         info!("Template provider connection:");
-        info!("  - Connected to server at: {}", address);
+        info!("  - Connected to server at: {} ({})", address, peer);
         info!(
             "  - Authority public key: {}",
             expected_tp_authority_public_key.map_or("None".to_string(), |k| k.to_string())
@@ -64,7 +73,7 @@ impl TemplateRx {
                 .await
                 .unwrap();
 
-        SetupConnectionHandler::setup(&mut receiver, &mut sender, address).await?;
+        SetupConnectionHandler::setup(&mut receiver, &mut sender, peer).await?;
 
         let self_ = Arc::new(Mutex::new(Self {
             receiver,
@@ -86,7 +95,8 @@ impl TemplateRx {
 
         Self::send(self_.clone(), frame).await?;
 
-        task::spawn(async { Self::start(cloned).await });
+        // the solutions of the shares handled while draining are still sent once cancelled
+        task::spawn(async move { cancel_token.run_until_cancelled(Self::start(cloned)).await });
         task::spawn(async { Self::on_new_solution(self_, solution_receiver).await });
 
         Ok(())
@@ -0,0 +1,356 @@
+//! Port mappings of the listeners on the router of a home network, so miners outside of it reach
+//! the pool and the translator without configuring the router. With `port_mapping` set, the
+//! process asks the router to forward the port of the pool and that of the SV1 miners of the
+//! translator, the same port outside, for `lifetime_secs`, and asks again half-way through so the
+//! mappings never lapse while it runs. They are removed on shutdown if the router answers in
+//! time, else lapse by themselves.
+//!
+//! Routers are asked over UPnP IGD, found by SSDP on the network, or over NAT-PMP (RFC 6886) at
+//! `gateway`, the default route of the host if unset (read from `/proc/net/route`, so Linux only).
+//! A router refusing or not answering is logged and asked again a minute later; the listeners
+//! serve the network either way. The address the router is reached at from outside is logged, for
+//! the operator to hand out to miners.
+use crate::status::events::Listener;
+use igd_next::{aio::tokio::search_gateway, PortMappingProtocol, SearchOptions};
+use serde::Deserialize;
+use std::{
+    fs, io,
+    net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
+    time::Duration,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+/// Port of the NAT-PMP server of the router.
+const NAT_PMP_PORT: u16 = 5351;
+/// Time a NAT-PMP request is first given, doubled on every attempt after.
+const NAT_PMP_TIMEOUT: Duration = Duration::from_millis(250);
+const NAT_PMP_ATTEMPTS: u32 = 4;
+/// Time before asking a router that refused or didn't answer again.
+const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+/// Time given to the router to remove the mappings on shutdown.
+const REMOVE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Name of the mappings on the router.
+const DESCRIPTION: &str = "potato";
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct PortMappingConfig {
+    #[serde(default)]
+    pub protocol: MappingProtocol,
+    /// Address of the NAT-PMP router, the default route of the host if unset.
+    #[serde(default)]
+    pub gateway: Option<Ipv4Addr>,
+    #[serde(default = "PortMappingConfig::default_lifetime_secs")]
+    pub lifetime_secs: u32,
+}
+
+impl PortMappingConfig {
+    fn default_lifetime_secs() -> u32 {
+        3600
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum MappingProtocol {
+    #[default]
+    Upnp,
+    NatPmp,
+}
+
+/// Maps `ports` of the listeners on the router, refreshed until `cancel_token` is cancelled, see
+/// the module.
+pub async fn run(
+    config: PortMappingConfig,
+    ports: Vec<(Listener, u16)>,
+    cancel_token: CancellationToken,
+) {
+    // refreshed every minute at most
+    let lifetime = config.lifetime_secs.max(120);
+    loop {
+        let refresh = match map_all(&config, &ports, lifetime).await {
+            Ok(granted) => Duration::from_secs(granted as u64 / 2),
+            Err(e) => {
+                warn!("Port mapping: the router mapped no port: {}", e);
+                RETRY_INTERVAL
+            }
+        };
+        tokio::select! {
+            _ = tokio::time::sleep(refresh) => {}
+            _ = cancel_token.cancelled() => break,
+        }
+    }
+    let removing = async {
+        for (listener, port) in &ports {
+            if let Err(e) = map(&config, *port, 0).await {
+                warn!("Port mapping: can't remove that of {:?}: {}", listener, e);
+            }
+        }
+    };
+    if tokio::time::timeout(REMOVE_TIMEOUT, removing)
+        .await
+        .is_err()
+    {
+        warn!("Port mapping: the router didn't remove the mappings in time");
+    }
+}
+
+/// Maps every port, the shortest lifetime the router granted.
+async fn map_all(
+    config: &PortMappingConfig,
+    ports: &[(Listener, u16)],
+    lifetime: u32,
+) -> io::Result<u32> {
+    let mut granted = None;
+    let mut last_error = None;
+    for (listener, port) in ports {
+        match map(config, *port, lifetime).await {
+            Ok(mapping) => {
+                info!(
+                    "Port mapping: {:?} reached at {} for {}s",
+                    listener, mapping.external, mapping.lifetime
+                );
+                granted = Some(granted.unwrap_or(u32::MAX).min(mapping.lifetime));
+            }
+            Err(e) => {
+                warn!(
+                    "Port mapping: can't map port {} of {:?}: {}",
+                    port, listener, e
+                );
+                last_error = Some(e);
+            }
+        }
+    }
+    match (granted, last_error) {
+        // the ports not mapped are asked for again within a minute
+        (Some(granted), Some(_)) => Ok(granted.min(2 * RETRY_INTERVAL.as_secs() as u32)),
+        (Some(granted), None) => Ok(granted),
+        (None, Some(e)) => Err(e),
+        (None, None) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "no port to map",
+        )),
+    }
+}
+
+/// A port mapped on the router.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Mapping {
+    external: SocketAddr,
+    lifetime: u32,
+}
+
+/// Maps `port` for `lifetime` seconds, removes its mapping if 0.
+async fn map(config: &PortMappingConfig, port: u16, lifetime: u32) -> io::Result<Mapping> {
+    match config.protocol {
+        MappingProtocol::Upnp => map_upnp(port, lifetime).await,
+        MappingProtocol::NatPmp => {
+            let gateway = match config.gateway {
+                Some(gateway) => gateway,
+                None => default_gateway(&fs::read_to_string("/proc/net/route")?)
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no default route"))?,
+            };
+            let gateway = SocketAddr::from((gateway, NAT_PMP_PORT));
+            let ip = nat_pmp_request(gateway, &external_address_request())
+                .await
+                .and_then(|response| parse_external_address(&response))?;
+            let response = nat_pmp_request(gateway, &map_request(port, lifetime)).await?;
+            let (external_port, lifetime) = parse_map_response(&response, port)?;
+            Ok(Mapping {
+                external: SocketAddr::from((ip, external_port)),
+                lifetime,
+            })
+        }
+    }
+}
+
+async fn map_upnp(port: u16, lifetime: u32) -> io::Result<Mapping> {
+    let gateway = search_gateway(SearchOptions::default())
+        .await
+        .map_err(io::Error::other)?;
+    if lifetime == 0 {
+        gateway
+            .remove_port(PortMappingProtocol::TCP, port)
+            .await
+            .map_err(io::Error::other)?;
+    } else {
+        let local = SocketAddr::new(local_ip(gateway.addr)?, port);
+        gateway
+            .add_port(PortMappingProtocol::TCP, port, local, lifetime, DESCRIPTION)
+            .await
+            .map_err(io::Error::other)?;
+    }
+    let ip = gateway.get_external_ip().await.map_err(io::Error::other)?;
+    Ok(Mapping {
+        external: SocketAddr::new(ip, port),
+        lifetime,
+    })
+}
+
+/// The address of the host on the network of `gateway`, that the router forwards to.
+fn local_ip(gateway: SocketAddr) -> io::Result<IpAddr> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    // nothing is sent, connecting only picks the route
+    socket.connect(gateway)?;
+    Ok(socket.local_addr()?.ip())
+}
+
+/// The gateway of the default route in `routes`, the content of `/proc/net/route`.
+fn default_gateway(routes: &str) -> Option<Ipv4Addr> {
+    // Iface Destination Gateway Flags ..., addresses in hex in the byte order of the host
+    const RTF_GATEWAY: u16 = 0x2;
+    routes.lines().skip(1).find_map(|line| {
+        let fields: Vec<_> = line.split_whitespace().collect();
+        let (destination, gateway, flags) = (fields.get(1)?, fields.get(2)?, fields.get(3)?);
+        let flags = u16::from_str_radix(flags, 16).ok()?;
+        if *destination != "00000000" || flags & RTF_GATEWAY == 0 {
+            return None;
+        }
+        let gateway = u32::from_str_radix(gateway, 16).ok()?;
+        Some(Ipv4Addr::from(gateway.to_ne_bytes()))
+    })
+}
+
+/// Sends `request` to the NAT-PMP server at `gateway` until it answers, its response.
+async fn nat_pmp_request(gateway: SocketAddr, request: &[u8]) -> io::Result<Vec<u8>> {
+    let socket = tokio::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+    socket.connect(gateway).await?;
+    let mut timeout = NAT_PMP_TIMEOUT;
+    let mut response = [0; 16];
+    for _ in 0..NAT_PMP_ATTEMPTS {
+        socket.send(request).await?;
+        if let Ok(received) = tokio::time::timeout(timeout, socket.recv(&mut response)).await {
+            return Ok(response[..received?].to_vec());
+        }
+        timeout *= 2;
+    }
+    Err(io::Error::new(
+        io::ErrorKind::TimedOut,
+        format!("no answer from {}", gateway),
+    ))
+}
+
+fn external_address_request() -> [u8; 2] {
+    // version 0, opcode 0
+    [0, 0]
+}
+
+/// Request mapping TCP `port` to the same port outside for `lifetime` seconds, removing the
+/// mapping if 0.
+fn map_request(port: u16, lifetime: u32) -> [u8; 12] {
+    let mut request = [0; 12];
+    // version 0, opcode 2 for TCP, then 2 reserved bytes
+    request[1] = 2;
+    request[4..6].copy_from_slice(&port.to_be_bytes());
+    // the port outside suggested, none when removing
+    let external = if lifetime == 0 { 0 } else { port };
+    request[6..8].copy_from_slice(&external.to_be_bytes());
+    request[8..12].copy_from_slice(&lifetime.to_be_bytes());
+    request
+}
+
+/// Checks `response` answers `opcode`, an error unless the router did as asked.
+fn check_response(response: &[u8], opcode: u8, len: usize) -> io::Result<()> {
+    if response.len() < len || response[0] != 0 || response[1] != 128 + opcode {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a NAT-PMP response",
+        ));
+    }
+    match u16::from_be_bytes([response[2], response[3]]) {
+        0 => Ok(()),
+        code => {
+            let reason = match code {
+                1 => "unsupported version",
+                2 => "refused",
+                3 => "network failure",
+                4 => "out of resources",
+                _ => "unsupported opcode",
+            };
+            Err(io::Error::other(format!("NAT-PMP {} ({})", reason, code)))
+        }
+    }
+}
+
+fn parse_external_address(response: &[u8]) -> io::Result<Ipv4Addr> {
+    check_response(response, 0, 12)?;
+    Ok(Ipv4Addr::new(
+        response[8],
+        response[9],
+        response[10],
+        response[11],
+    ))
+}
+
+/// The port outside and the lifetime of the mapping of `port`.
+fn parse_map_response(response: &[u8], port: u16) -> io::Result<(u16, u32)> {
+    check_response(response, 2, 16)?;
+    if u16::from_be_bytes([response[8], response[9]]) != port {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "response to another mapping",
+        ));
+    }
+    let external = u16::from_be_bytes([response[10], response[11]]);
+    let lifetime = u32::from_be_bytes([response[12], response[13], response[14], response[15]]);
+    Ok((external, lifetime))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reads_the_default_gateway() {
+        let routes = "\
+Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT
+eth0\t0001A8C0\t00000000\t0001\t0\t0\t100\t00FFFFFF\t0\t0\t0
+eth0\t00000000\t0101A8C0\t0003\t0\t0\t100\t00000000\t0\t0\t0
+";
+        let gateway = u32::from_str_radix("0101A8C0", 16).unwrap().to_ne_bytes();
+        assert_eq!(default_gateway(routes), Some(Ipv4Addr::from(gateway)));
+        // no route through a gateway
+        assert_eq!(default_gateway(&routes.replace("0003", "0001")), None);
+    }
+
+    #[tokio::test]
+    async fn maps_over_nat_pmp() {
+        let router = tokio::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap();
+        let gateway = router.local_addr().unwrap();
+        let answering = tokio::spawn(async move {
+            let mut request = [0; 12];
+            // the external address, then the mapping, of 7200s where 3600s were asked
+            let (_, from) = router.recv_from(&mut request).await.unwrap();
+            let response = [0, 128, 0, 0, 0, 0, 0, 1, 203, 0, 113, 7];
+            router.send_to(&response, from).await.unwrap();
+            let (len, from) = router.recv_from(&mut request).await.unwrap();
+            assert_eq!(request[..len], map_request(34254, 3600));
+            let mut response = [
+                0, 130, 0, 0, 0, 0, 0, 2, 0x85, 0xce, 0x85, 0xcf, 0, 0, 0x1c, 0x20,
+            ];
+            router.send_to(&response, from).await.unwrap();
+            // refused
+            let (_, from) = router.recv_from(&mut request).await.unwrap();
+            response[3] = 2;
+            router.send_to(&response, from).await.unwrap();
+        });
+        let response = nat_pmp_request(gateway, &external_address_request())
+            .await
+            .unwrap();
+        assert_eq!(
+            parse_external_address(&response).unwrap(),
+            Ipv4Addr::new(203, 0, 113, 7)
+        );
+        let response = nat_pmp_request(gateway, &map_request(34254, 3600))
+            .await
+            .unwrap();
+        assert_eq!(parse_map_response(&response, 34254).unwrap(), (34255, 7200));
+        let response = nat_pmp_request(gateway, &map_request(34254, 3600))
+            .await
+            .unwrap();
+        assert!(parse_map_response(&response, 34254).is_err());
+        answering.await.unwrap();
+    }
+}
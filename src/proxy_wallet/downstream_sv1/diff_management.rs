@@ -1,6 +1,10 @@
-use super::{Downstream, DownstreamMessages, SetDownstreamTarget};
+use super::{Downstream, DownstreamMessages, SetDownstreamTarget, OVERLOAD_RAISE_INTERVAL_SECS};
+use crate::proxy_wallet::proxy_config::OverloadPolicy;
 
-use crate::error::{Error, ProxyResult};
+use crate::{
+    error::{Error, ProxyResult},
+    sim,
+};
 use roles_logic_sv2::utils::Mutex;
 use std::{ops::Div, sync::Arc};
 use sv1_api::json_rpc;
@@ -14,28 +18,17 @@ impl Downstream {
     pub async fn init_difficulty_management(
         self_: Arc<Mutex<Self>>,
         init_target: &[u8],
-    ) -> ProxyResult<'static, ()> {
-        let (connection_id, upstream_difficulty_config, miner_hashrate) = self_
+    ) -> ProxyResult<()> {
+        let connection_id = self_
             .safe_lock(|d| {
-                let timestamp_secs = std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .expect("time went backwards")
-                    .as_secs();
+                let timestamp_secs = sim::unix_secs();
                 d.difficulty_mgmt.timestamp_of_last_update = timestamp_secs;
                 d.difficulty_mgmt.submits_since_last_update = 0;
-                (
-                    d.connection_id,
-                    d.upstream_difficulty_config.clone(),
-                    d.difficulty_mgmt.min_individual_miner_hashrate,
-                )
-            })
-            .map_err(|_e| Error::PoisonLock)?;
-        // add new connection hashrate to channel hashrate
-        upstream_difficulty_config
-            .safe_lock(|u| {
-                u.channel_nominal_hashrate += miner_hashrate;
+                // add new connection hashrate to channel hashrate, unless a resumed session did
+                d.set_channel_hashrate(d.nominal_hashrate())
+                    .map(|()| d.connection_id)
             })
-            .map_err(|_e| Error::PoisonLock)?;
+            .map_err(|_e| Error::PoisonLock)??;
         // update downstream target with bridge
         let init_target = binary_sv2::U256::try_from(init_target.to_vec())?;
         Self::send_message_upstream(
@@ -53,33 +46,124 @@ impl Downstream {
     /// Called before a miner disconnects so we can remove the miner's hashrate from the aggregated
     /// channel hashrate
     #[allow(clippy::result_large_err)]
-    pub fn remove_miner_hashrate_from_channel(self_: Arc<Mutex<Self>>) -> ProxyResult<'static, ()> {
+    pub fn remove_miner_hashrate_from_channel(self_: Arc<Mutex<Self>>) -> ProxyResult<()> {
         self_
-            .safe_lock(|d| {
-                d.upstream_difficulty_config
-                    .safe_lock(|u| {
-                        let hashrate_to_subtract = d.difficulty_mgmt.min_individual_miner_hashrate;
-                        if u.channel_nominal_hashrate >= hashrate_to_subtract {
-                            u.channel_nominal_hashrate -= hashrate_to_subtract;
-                        } else {
-                            u.channel_nominal_hashrate = 0.0;
-                        }
-                    })
-                    .map_err(|_e| Error::PoisonLock)
+            .safe_lock(|d| d.set_channel_hashrate(0.0))
+            .map_err(|_e| Error::PoisonLock)?
+    }
+
+    /// Sets what this miner adds to the nominal hashrate of the channel to `hashrate`, the channel
+    /// getting the difference with what it added before.
+    #[allow(clippy::result_large_err)]
+    pub(super) fn set_channel_hashrate(&mut self, hashrate: f32) -> ProxyResult<()> {
+        let delta = hashrate - self.channel_hashrate;
+        self.channel_hashrate = hashrate;
+        self.upstream_difficulty_config
+            .safe_lock(|u| {
+                u.channel_nominal_hashrate = (u.channel_nominal_hashrate + delta).max(0.0);
             })
-            .map_err(|_e| Error::PoisonLock)??;
-        Ok(())
+            .map_err(|_e| Error::PoisonLock)
+    }
+
+    /// Hashrate the miner adds to the channel: that its pinned difficulty implies at
+    /// `shares_per_minute` if it is pinned, its estimated hashrate otherwise.
+    pub(super) fn nominal_hashrate(&self) -> f32 {
+        let estimated = self.difficulty_mgmt.min_individual_miner_hashrate;
+        let Some(difficulty) = self.pinned_difficulty else {
+            return estimated;
+        };
+        Downstream::target_from_difficulty(difficulty)
+            .ok()
+            .and_then(|target| binary_sv2::U256::try_from(target).ok())
+            .and_then(|target| {
+                roles_logic_sv2::utils::hash_rate_from_target(
+                    target,
+                    self.difficulty_mgmt.shares_per_minute.into(),
+                )
+                .ok()
+            })
+            .map_or(estimated, |hashrate| hashrate as f32)
+    }
+
+    /// Difficulty of the first of the workers of the miner that is pinned, in the config or at
+    /// runtime.
+    pub(super) fn worker_pinned_difficulty(&self) -> Option<f64> {
+        self.authorized_names
+            .iter()
+            .find_map(|name| self.difficulty_mgmt.pinned_difficulty_for(name))
+    }
+
+    /// Settles the pinned difficulty of the miner on that of its workers, which a worker
+    /// authorizing or a pin changed at runtime changes. If it did, restarts vardiff from there and
+    /// returns the target to send the miner.
+    #[allow(clippy::result_large_err)]
+    pub(super) fn refresh_pinned_difficulty(
+        self_: Arc<Mutex<Self>>,
+    ) -> ProxyResult<Option<Vec<u8>>> {
+        self_
+            .safe_lock(|d| -> ProxyResult<Option<Vec<u8>>> {
+                let pinned = d.worker_pinned_difficulty();
+                if pinned == d.pinned_difficulty {
+                    return Ok(None);
+                }
+                match pinned {
+                    Some(difficulty) => tracing::info!(
+                        "Down: Channel {} pinned to difficulty {}",
+                        d.connection_id,
+                        difficulty
+                    ),
+                    None => tracing::info!("Down: Channel {} unpinned", d.connection_id),
+                }
+                // vardiff picks up from the hashrate the pin implied
+                if pinned.is_none() {
+                    d.difficulty_mgmt.min_individual_miner_hashrate = d.nominal_hashrate();
+                }
+                d.pinned_difficulty = pinned;
+                d.difficulty_mgmt.timestamp_of_last_update = sim::unix_secs();
+                d.difficulty_mgmt.submits_since_last_update = 0;
+                // the first job adds the miner to the channel
+                if d.first_job_received {
+                    d.set_channel_hashrate(d.nominal_hashrate())?;
+                }
+                d.target().map(Some)
+            })
+            .map_err(|_e| Error::PoisonLock)?
     }
 
     /// if enough shares have been submitted according to the config, this function updates the
     /// difficulty for the connection and sends the new difficulty to the miner
-    pub async fn try_update_difficulty_settings(
-        self_: Arc<Mutex<Self>>,
-    ) -> ProxyResult<'static, ()> {
-        let (diff_mgmt, channel_id) = self_
+    pub async fn try_update_difficulty_settings(self_: Arc<Mutex<Self>>) -> ProxyResult<()> {
+        // a miner pinned or unpinned since the last job goes to its new difficulty right away
+        if let Some(new_target) = Self::refresh_pinned_difficulty(self_.clone())? {
+            let channel_id = self_
+                .safe_lock(|d| d.connection_id)
+                .map_err(|_e| Error::PoisonLock)?;
+            let message = Self::get_set_difficulty(new_target.clone())?;
+            Downstream::send_message_downstream(self_.clone(), message).await?;
+            let update_target_msg = SetDownstreamTarget {
+                channel_id,
+                new_target: binary_sv2::U256::try_from(new_target)?.into(),
+            };
+            return Downstream::send_message_upstream(
+                self_,
+                DownstreamMessages::SetDownstreamTarget(update_target_msg),
+            )
+            .await;
+        }
+        let (diff_mgmt, channel_id, pinned_difficulty) = self_
             .clone()
-            .safe_lock(|d| (d.difficulty_mgmt.clone(), d.connection_id))
+            .safe_lock(|d| {
+                (
+                    d.difficulty_mgmt.clone(),
+                    d.connection_id,
+                    d.pinned_difficulty,
+                )
+            })
             .map_err(|_e| Error::PoisonLock)?;
+        // pinned workers are exempt from vardiff
+        if pinned_difficulty.is_some() {
+            return Ok(());
+        }
         tracing::debug!(
             "Time of last diff update: {:?}",
             diff_mgmt.timestamp_of_last_update
@@ -123,28 +207,102 @@ impl Downstream {
         Ok(())
     }
 
-    /// calculates the target according to the current stored hashrate of the miner
-    #[allow(clippy::result_large_err)]
-    pub fn hash_rate_to_target(self_: Arc<Mutex<Self>>) -> ProxyResult<'static, Vec<u8>> {
-        self_
-            .safe_lock(|d| {
+    /// Under `OverloadPolicy::RaiseDifficulty`, doubles the miner's difficulty when the submission
+    /// pipeline is full so it sends fewer shares. Raises at most once per
+    /// `OVERLOAD_RAISE_INTERVAL_SECS` so a queue that takes a while to drain doesn't send the
+    /// difficulty through the roof.
+    pub(super) async fn relieve_overload(self_: Arc<Mutex<Self>>) -> ProxyResult<()> {
+        let new_target = self_
+            .safe_lock(|d| -> ProxyResult<Option<_>> {
+                if d.submissions.policy() != OverloadPolicy::RaiseDifficulty
+                    || !d.submissions.is_full()
+                    || d.pinned_difficulty.is_some()
+                {
+                    return Ok(None);
+                }
+                let timestamp_secs = sim::unix_secs();
+                if timestamp_secs.saturating_sub(d.difficulty_mgmt.timestamp_of_last_update)
+                    < OVERLOAD_RAISE_INTERVAL_SECS
+                {
+                    return Ok(None);
+                }
+                d.difficulty_mgmt.min_individual_miner_hashrate *= 2.0;
+                d.difficulty_mgmt.timestamp_of_last_update = timestamp_secs;
+                d.difficulty_mgmt.submits_since_last_update = 0;
+                d.set_channel_hashrate(d.difficulty_mgmt.min_individual_miner_hashrate)?;
+                tracing::warn!(
+                    "Down: Submission pipeline overloaded, raising difficulty of channel {}",
+                    d.connection_id
+                );
                 match roles_logic_sv2::utils::hash_rate_to_target(
                     d.difficulty_mgmt.min_individual_miner_hashrate.into(),
                     d.difficulty_mgmt.shares_per_minute.into(),
                 ) {
-                    Ok(target) => Ok(target.to_vec()),
+                    Ok(target) => Ok(Some((target, d.connection_id))),
                     Err(e) => Err(Error::TargetError(e)),
                 }
             })
+            .map_err(|_e| Error::PoisonLock)??;
+        if let Some((new_target, channel_id)) = new_target {
+            let message = Self::get_set_difficulty(new_target.to_vec())?;
+            Downstream::send_message_downstream(self_.clone(), message).await?;
+            let update_target_msg = SetDownstreamTarget {
+                channel_id,
+                new_target: new_target.into(),
+            };
+            Downstream::send_message_upstream(
+                self_.clone(),
+                DownstreamMessages::SetDownstreamTarget(update_target_msg),
+            )
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Current difficulty of the miner, from the pinned difficulty or its estimated hashrate.
+    pub(super) fn current_difficulty(&self) -> f64 {
+        if let Some(difficulty) = self.pinned_difficulty {
+            return difficulty;
+        }
+        roles_logic_sv2::utils::hash_rate_to_target(
+            self.difficulty_mgmt.min_individual_miner_hashrate.into(),
+            self.difficulty_mgmt.shares_per_minute.into(),
+        )
+        .ok()
+        .and_then(|target| Downstream::difficulty_from_target(target.to_vec()).ok())
+        .unwrap_or(0.0)
+    }
+
+    /// calculates the target according to the current stored hashrate of the miner, or from the
+    /// pinned difficulty if the miner is pinned to one
+    #[allow(clippy::result_large_err)]
+    pub fn hash_rate_to_target(self_: Arc<Mutex<Self>>) -> ProxyResult<Vec<u8>> {
+        self_
+            .safe_lock(|d| d.target())
             .map_err(|_e| Error::PoisonLock)?
     }
 
+    #[allow(clippy::result_large_err)]
+    fn target(&self) -> ProxyResult<Vec<u8>> {
+        if let Some(difficulty) = self.pinned_difficulty {
+            return Downstream::target_from_difficulty(difficulty);
+        }
+        match roles_logic_sv2::utils::hash_rate_to_target(
+            self.difficulty_mgmt.min_individual_miner_hashrate.into(),
+            self.difficulty_mgmt.shares_per_minute.into(),
+        ) {
+            Ok(target) => Ok(target.to_vec()),
+            Err(e) => Err(Error::TargetError(e)),
+        }
+    }
+
     /// increments the number of shares since the last difficulty update
     #[allow(clippy::result_large_err)]
-    pub(super) fn save_share(self_: Arc<Mutex<Self>>) -> ProxyResult<'static, ()> {
+    pub(super) fn save_share(self_: Arc<Mutex<Self>>) -> ProxyResult<()> {
         self_
             .safe_lock(|d| {
                 d.difficulty_mgmt.submits_since_last_update += 1;
+                d.session_stats.shares_submitted += 1;
             })
             .map_err(|_e| Error::PoisonLock)?;
         Ok(())
@@ -154,7 +312,7 @@ impl Downstream {
     /// difficulty for the Downstream role and creates the SV1 `mining.set_difficulty` message to
     /// be sent to the Downstream role.
     #[allow(clippy::result_large_err)]
-    pub(super) fn get_set_difficulty(target: Vec<u8>) -> ProxyResult<'static, json_rpc::Message> {
+    pub(super) fn get_set_difficulty(target: Vec<u8>) -> ProxyResult<json_rpc::Message> {
         let value = Downstream::difficulty_from_target(target)?;
         tracing::debug!("Difficulty from target: {:?}", value);
         let set_target = sv1_api::methods::server_to_client::SetDifficulty { value };
@@ -165,7 +323,7 @@ impl Downstream {
     /// Converts target received by the `SetTarget` SV2 message from the Upstream role into the
     /// difficulty for the Downstream role sent via the SV1 `mining.set_difficulty` message.
     #[allow(clippy::result_large_err)]
-    pub(super) fn difficulty_from_target(mut target: Vec<u8>) -> ProxyResult<'static, f64> {
+    pub(super) fn difficulty_from_target(mut target: Vec<u8>) -> ProxyResult<f64> {
         // reverse because target is LE and this function relies on BE
         target.reverse();
         let target = target.as_slice();
@@ -184,6 +342,10 @@ impl Downstream {
 
         if pdiff > target {
             let diff = pdiff.div(target);
+            // saturated, the low bits alone of a larger quotient are any difficulty at all
+            if diff.bits() > 64 {
+                return Ok(u64::MAX as f64);
+            }
             Ok(diff.low_u64() as f64)
         } else {
             let diff = target.div(pdiff);
@@ -193,6 +355,33 @@ impl Downstream {
         }
     }
 
+    /// Inverse of `difficulty_from_target`, returns the little endian target for a difficulty.
+    #[allow(clippy::result_large_err)]
+    pub(super) fn target_from_difficulty(difficulty: f64) -> ProxyResult<Vec<u8>> {
+        let pdiff: [u8; 32] = [
+            0, 0, 0, 0, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255,
+            255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255,
+        ];
+        let pdiff = Uint256::from_be_bytes(pdiff);
+        let target = if difficulty >= 1.0 {
+            let divisor = Uint256::from_u64(difficulty.round() as u64)
+                .expect("a u64 always fits in a Uint256");
+            pdiff.div(divisor)
+        } else if difficulty > 0.0 {
+            // from 2^-32 down the product leaves 256 bits, all of them get the easiest target
+            match (1.0 / difficulty).round() {
+                multiplier if multiplier <= u32::MAX as f64 => pdiff.mul_u32(multiplier as u32),
+                _ => Uint256::from_be_bytes([255; 32]),
+            }
+        } else {
+            pdiff
+        };
+        let mut target = target.to_be_bytes().to_vec();
+        // back to LE as expected by the SV2 side
+        target.reverse();
+        Ok(target)
+    }
+
     /// This function updates the miner hashrate and resets difficulty management params. To
     /// calculate hashrate it calculates the realized shares per minute from the number of shares
     /// submitted and the delta time since last update. It then uses the realized shares per
@@ -204,13 +393,10 @@ impl Downstream {
     pub fn update_miner_hashrate(
         self_: Arc<Mutex<Self>>,
         miner_target: Vec<u8>,
-    ) -> ProxyResult<'static, Option<f32>> {
+    ) -> ProxyResult<Option<f32>> {
         self_
             .safe_lock(|d| {
-                let timestamp_secs = std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .expect("time went backwards")
-                    .as_secs();
+                let timestamp_secs = sim::unix_secs();
 
                 // reset if timestamp is at 0
                 if d.difficulty_mgmt.timestamp_of_last_update == 0 {
@@ -243,7 +429,7 @@ impl Downstream {
                     }
                 };
 
-                let mut hashrate_delta =
+                let hashrate_delta =
                     new_miner_hashrate - d.difficulty_mgmt.min_individual_miner_hashrate;
                 let hashrate_delta_percentage = (hashrate_delta.abs()
                     / d.difficulty_mgmt.min_individual_miner_hashrate)
@@ -265,8 +451,6 @@ impl Downstream {
                         dt if dt < 60 => d.difficulty_mgmt.min_individual_miner_hashrate / 2.0,
                         _ => d.difficulty_mgmt.min_individual_miner_hashrate / 3.0,
                     };
-                    hashrate_delta =
-                        new_miner_hashrate - d.difficulty_mgmt.min_individual_miner_hashrate;
                 }
                 if (realized_share_per_min > 0.0) && (hashrate_delta_percentage > 1000.0) {
                     new_miner_hashrate = match delta_time {
@@ -274,19 +458,11 @@ impl Downstream {
                         dt if dt < 60 => d.difficulty_mgmt.min_individual_miner_hashrate * 5.0,
                         _ => d.difficulty_mgmt.min_individual_miner_hashrate * 3.0,
                     };
-                    hashrate_delta =
-                        new_miner_hashrate - d.difficulty_mgmt.min_individual_miner_hashrate;
                 }
                 d.difficulty_mgmt.min_individual_miner_hashrate = new_miner_hashrate;
                 d.difficulty_mgmt.timestamp_of_last_update = timestamp_secs;
                 d.difficulty_mgmt.submits_since_last_update = 0;
-                d.upstream_difficulty_config.super_safe_lock(|c| {
-                    if c.channel_nominal_hashrate + hashrate_delta > 0.0 {
-                        c.channel_nominal_hashrate += hashrate_delta;
-                    } else {
-                        c.channel_nominal_hashrate = 0.0;
-                    }
-                });
+                d.set_channel_hashrate(new_miner_hashrate)?;
                 Ok(Some(new_miner_hashrate))
                 } else {
                     Ok(None)
@@ -309,9 +485,19 @@ impl Downstream {
 
 #[cfg(test)]
 mod test {
-    use crate::proxy_wallet::proxy_config::{DownstreamDifficultyConfig, UpstreamDifficultyConfig};
+    use crate::proxy_wallet::{
+        downstream_sv1::SubmissionPipeline,
+        proxy_config::{
+            pin_workers, DownstreamDifficultyConfig, PinnedDifficulty, SubmissionPipelineConfig,
+            UpstreamDifficultyConfig,
+        },
+    };
     use async_channel::unbounded;
     use binary_sv2::U256;
+    use proptest::{
+        prelude::{any, prop, Strategy},
+        prop_assert, prop_assert_eq, prop_assume, prop_oneof, proptest,
+    };
     use rand::{thread_rng, Rng};
     use roles_logic_sv2::{mining_sv2::Target, utils::Mutex};
     use sha2::{Digest, Sha256};
@@ -422,6 +608,7 @@ mod test {
             shares_per_minute: 1000.0,          // 1000 shares per minute
             submits_since_last_update: 0,
             timestamp_of_last_update: 0, // updated below
+            pinned_workers: vec![],
         };
         let upstream_config = UpstreamDifficultyConfig {
             channel_diff_update_interval: 60,
@@ -429,7 +616,8 @@ mod test {
             timestamp_of_last_update: 0,
             should_aggregate: false,
         };
-        let (tx_sv1_submit, _rx_sv1_submit) = unbounded();
+        let (submissions, _rx_submissions) =
+            SubmissionPipeline::new(&SubmissionPipelineConfig::default());
         let (tx_outgoing, _rx_outgoing) = unbounded();
         let mut downstream = Downstream::new(
             1,
@@ -437,7 +625,7 @@ mod test {
             vec![],
             None,
             None,
-            tx_sv1_submit,
+            submissions,
             tx_outgoing,
             false,
             0,
@@ -504,4 +692,179 @@ mod test {
         }
         ret
     }
+
+    #[test]
+    fn pinned_worker_patterns_match() {
+        let exact = PinnedDifficulty::new("rig.1".to_string(), 1.0).unwrap();
+        assert!(exact.matches("rig.1"));
+        assert!(!exact.matches("rig.10"));
+
+        let prefix = PinnedDifficulty::new("testrig.*".to_string(), 1.0).unwrap();
+        assert!(prefix.matches("testrig.s9"));
+        assert!(!prefix.matches("prod.s9"));
+
+        let infix = PinnedDifficulty::new("*.s9*".to_string(), 1.0).unwrap();
+        assert!(infix.matches("farm.s9-02"));
+        assert!(!infix.matches("farm.s19"));
+
+        let mut config = DownstreamDifficultyConfig::new(1_000.0, 6.0, 0, 0);
+        config.pinned_workers = vec![prefix, PinnedDifficulty::new("*".to_string(), 2.0).unwrap()];
+        assert_eq!(config.pinned_difficulty_for("testrig.a"), Some(1.0));
+        assert_eq!(config.pinned_difficulty_for("other"), Some(2.0));
+    }
+
+    #[test]
+    fn rejects_pinned_difficulties_that_are_not_positive() {
+        for difficulty in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert!(PinnedDifficulty::new("rig.*".to_string(), difficulty).is_err());
+        }
+        let config = r#"{"worker_pattern":"rig.*","difficulty":0.0}"#;
+        assert!(serde_json::from_str::<PinnedDifficulty>(config).is_err());
+        assert!(pin_workers("rig.*".to_string(), Some(-2.0)).is_err());
+    }
+
+    #[test]
+    fn pinned_miners_add_the_hashrate_of_their_difficulty_to_the_channel() {
+        let channel = Arc::new(Mutex::new(UpstreamDifficultyConfig::new(60, 0.0, 0, false)));
+        let (submissions, _rx_submissions) =
+            SubmissionPipeline::new(&SubmissionPipelineConfig::default());
+        let downstream = Arc::new(Mutex::new(Downstream::new(
+            1,
+            vec!["runtime-pin.1".to_string()],
+            vec![],
+            None,
+            None,
+            submissions,
+            unbounded().0,
+            true,
+            0,
+            DownstreamDifficultyConfig::new(10.0, 6.0, 0, 0),
+            channel.clone(),
+            "0".to_string(),
+        )));
+        let nominal = || channel.safe_lock(|c| c.channel_nominal_hashrate).unwrap();
+        downstream
+            .safe_lock(|d| d.set_channel_hashrate(d.nominal_hashrate()))
+            .unwrap()
+            .unwrap();
+        assert_eq!(nominal(), 10.0);
+
+        // 2^32 hashes a share of difficulty 1, 6 shares a minute
+        let pinned_hashrate = || (nominal() / (2f32.powi(32) * 6.0 / 60.0) - 1.0).abs() < 0.01;
+        pin_workers("runtime-pin.*".to_string(), Some(1.0)).unwrap();
+        let target = Downstream::refresh_pinned_difficulty(downstream.clone())
+            .unwrap()
+            .unwrap();
+        assert_eq!(Downstream::difficulty_from_target(target).unwrap(), 1.0);
+        assert!(pinned_hashrate(), "{}", nominal());
+        assert!(Downstream::refresh_pinned_difficulty(downstream.clone())
+            .unwrap()
+            .is_none());
+
+        // vardiff picks up from the hashrate of the pin
+        pin_workers("runtime-pin.*".to_string(), None).unwrap();
+        assert!(Downstream::refresh_pinned_difficulty(downstream.clone())
+            .unwrap()
+            .is_some());
+        assert_eq!(downstream.safe_lock(|d| d.pinned_difficulty).unwrap(), None);
+        assert!(pinned_hashrate(), "{}", nominal());
+    }
+
+    /// A little endian target, its top `zeros` bytes cleared to reach the small targets too.
+    fn target() -> impl Strategy<Value = Vec<u8>> {
+        (any::<[u8; 32]>(), 0..=32usize).prop_map(|(mut target, zeros)| {
+            target[32 - zeros..].fill(0);
+            target.to_vec()
+        })
+    }
+
+    /// A difficulty above 0, most of them in the range of miners.
+    fn difficulty() -> impl Strategy<Value = f64> {
+        prop_oneof![
+            prop::num::f64::POSITIVE.prop_filter("0 is no difficulty", |d| *d > 0.0),
+            (-12.0..24.0f64).prop_map(|exponent| 10f64.powf(exponent)),
+        ]
+    }
+
+    /// `target` as a big endian number, to compare targets with.
+    fn value(target: &[u8]) -> Vec<u8> {
+        target.iter().rev().copied().collect()
+    }
+
+    proptest! {
+        #[test]
+        fn difficulty_falls_as_the_target_rises(a in target(), b in target()) {
+            prop_assume!(a.iter().any(|byte| *byte != 0) && b.iter().any(|byte| *byte != 0));
+            let (low, high) = match value(&a) <= value(&b) {
+                true => (a, b),
+                false => (b, a),
+            };
+            let low = Downstream::difficulty_from_target(low).unwrap();
+            let high = Downstream::difficulty_from_target(high).unwrap();
+            prop_assert!(low >= high, "{} < {}", low, high);
+            prop_assert!(high > 0.0);
+        }
+
+        #[test]
+        fn target_falls_as_the_difficulty_rises(a in difficulty(), b in difficulty()) {
+            let (low, high) = (a.min(b), a.max(b));
+            let low = Downstream::target_from_difficulty(low).unwrap();
+            let high = Downstream::target_from_difficulty(high).unwrap();
+            prop_assert!(value(&low) >= value(&high));
+        }
+
+        #[test]
+        fn difficulties_below_the_easiest_target_get_it(exponent in 32.0..1000.0f64) {
+            let easiest = Downstream::target_from_difficulty(2f64.powf(-exponent)).unwrap();
+            prop_assert_eq!(&easiest, &vec![255; 32]);
+            let above = Downstream::target_from_difficulty(1.0 / u32::MAX as f64).unwrap();
+            prop_assert!(value(&above) < value(&easiest));
+        }
+
+        #[test]
+        fn difficulty_round_trips_through_its_target(
+            whole in 1..=(1u64 << 53),
+            fraction in 1..=u32::MAX,
+        ) {
+            for difficulty in [whole as f64, 1.0 / fraction as f64] {
+                let target = Downstream::target_from_difficulty(difficulty).unwrap();
+                prop_assert_eq!(Downstream::difficulty_from_target(target).unwrap(), difficulty);
+            }
+        }
+
+        #[test]
+        fn set_difficulty_carries_the_difficulty_of_the_target(target in target()) {
+            let difficulty = Downstream::difficulty_from_target(target.clone()).unwrap();
+            let message = Downstream::get_set_difficulty(target).unwrap();
+            let message = serde_json::to_value(&message).unwrap();
+            prop_assert_eq!(message["method"].as_str(), Some("mining.set_difficulty"));
+            prop_assert_eq!(message["params"][0].as_f64(), Some(difficulty));
+        }
+
+        #[test]
+        fn target_falls_as_the_hashrate_rises(
+            a in 0.0..1e24f64,
+            b in 0.0..1e24f64,
+            shares_per_minute in 0.1..120.0f64,
+        ) {
+            let (low, high) = (a.min(b), a.max(b));
+            let low = roles_logic_sv2::utils::hash_rate_to_target(low, shares_per_minute).unwrap();
+            let high =
+                roles_logic_sv2::utils::hash_rate_to_target(high, shares_per_minute).unwrap();
+            prop_assert!(value(&low.to_vec()) >= value(&high.to_vec()));
+        }
+
+        #[test]
+        fn hashrate_round_trips_through_its_target(
+            hashrate in 1e6..1e18f64,
+            shares_per_minute in 1.0..60.0f64,
+        ) {
+            let target =
+                roles_logic_sv2::utils::hash_rate_to_target(hashrate, shares_per_minute).unwrap();
+            let estimated =
+                roles_logic_sv2::utils::hash_rate_from_target(target, shares_per_minute).unwrap();
+            let error = (estimated - hashrate).abs() / hashrate;
+            prop_assert!(error < 0.01, "{} for {}", estimated, hashrate);
+        }
+    }
 }
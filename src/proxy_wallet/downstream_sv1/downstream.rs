@@ -1,10 +1,14 @@
 use crate::{
     error::ProxyResult,
-    proxy_wallet::{
-        downstream_sv1,
-        proxy_config::{DownstreamDifficultyConfig, UpstreamDifficultyConfig},
+    handoff, logging,
+    proxy_wallet::proxy_config::{DownstreamDifficultyConfig, UpstreamDifficultyConfig},
+    ratelimit::RateLimiter,
+    sim,
+    status::{
+        self,
+        audit::{self, AuditEvent},
+        events::{self, Event, Listener},
     },
-    status,
 };
 use async_channel::{bounded, Receiver, Sender};
 use async_std::{
@@ -17,7 +21,13 @@ use error_handling::handle_result;
 use futures::FutureExt;
 use tokio::{sync::broadcast, task::AbortHandle};
 
-use super::{kill, DownstreamMessages, SubmitShareWithChannelId, SUBSCRIBE_TIMEOUT_SECS};
+use super::{
+    error_response, kill,
+    pipeline::{SubmissionPipeline, SubmitOutcome},
+    session::{SessionStats, SessionStore},
+    DownstreamMessages, InvalidRequest, SubmitShareWithChannelId, Sv1Frame, INVALID_REQUEST,
+    SUBSCRIBE_TIMEOUT_SECS,
+};
 
 use roles_logic_sv2::{
     common_properties::{IsDownstream, IsMiningDownstream},
@@ -26,16 +36,20 @@ use roles_logic_sv2::{
 
 use crate::error::Error;
 use futures::select;
-use tokio_util::codec::{FramedRead, LinesCodec};
+use tokio_util::{
+    codec::{AnyDelimiterCodec, FramedRead},
+    sync::CancellationToken,
+};
 
-use std::{net::SocketAddr, sync::Arc};
+use std::sync::Arc;
 use sv1_api::{
-    client_to_server::{self, Submit},
-    json_rpc, server_to_client,
+    client_to_server, json_rpc,
+    methods::Client2Server,
+    server_to_client,
     utils::{Extranonce, HexU32Be},
     IsServer,
 };
-use tracing::{debug, info, warn};
+use tracing::{debug, field, info, info_span, warn, Instrument, Span};
 
 const MAX_LINE_LENGTH: usize = 2_usize.pow(16);
 
@@ -45,7 +59,7 @@ const MAX_LINE_LENGTH: usize = 2_usize.pow(16);
 pub struct Downstream {
     /// List of authorized Downstream Mining Devices.
     pub(super) connection_id: u32,
-    authorized_names: Vec<String>,
+    pub(super) authorized_names: Vec<String>,
     extranonce1: Vec<u8>,
     /// `extranonce1` to be sent to the Downstream in the SV1 `mining.subscribe` message response.
     //extranonce1: Vec<u8>,
@@ -56,27 +70,42 @@ pub struct Downstream {
     version_rolling_min_bit: Option<HexU32Be>,
     /// Sends a SV1 `mining.submit` message received from the Downstream role to the `Bridge` for
     /// translation into a SV2 `SubmitSharesExtended`.
-    tx_sv1_bridge: Sender<DownstreamMessages>,
+    pub(super) submissions: SubmissionPipeline,
     /// Sends message to the SV1 Downstream role.
-    tx_outgoing: Sender<json_rpc::Message>,
+    tx_outgoing: Sender<Sv1Frame>,
     /// True if this is the first job received from `Upstream`.
-    first_job_received: bool,
+    pub(super) first_job_received: bool,
     extranonce2_len: usize,
     pub(super) difficulty_mgmt: DownstreamDifficultyConfig,
     pub(super) upstream_difficulty_config: Arc<Mutex<UpstreamDifficultyConfig>>,
+    /// Hashrate this miner adds to the nominal hashrate of the channel, see
+    /// `set_channel_hashrate`.
+    pub(super) channel_hashrate: f32,
     last_job_id: String, // we usually receive a String on SV1 messages, no need to cast to u32
+    /// Opaque token handed out as the subscription id, used to resume this session on reconnect.
+    pub(super) session_token: String,
+    pub(super) session_stats: SessionStats,
+    pub(super) session_store: Arc<SessionStore>,
+    /// Fixed difficulty of the first worker pinned, in the `pinned_workers` of the config or at
+    /// runtime, settled on every job by `refresh_pinned_difficulty`.
+    pub(super) pinned_difficulty: Option<f64>,
+    /// Peer address of the mining device, for the audit log.
+    host: String,
+    /// Rate limits of the translator, `mining.authorize` being limited per worker name.
+    limiter: Arc<RateLimiter>,
 }
 
 impl Downstream {
-    #[cfg(test)]
+    #[cfg(any(test, feature = "simulation"))]
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         connection_id: u32,
         authorized_names: Vec<String>,
         extranonce1: Vec<u8>,
         version_rolling_mask: Option<HexU32Be>,
         version_rolling_min_bit: Option<HexU32Be>,
-        tx_sv1_bridge: Sender<DownstreamMessages>,
-        tx_outgoing: Sender<json_rpc::Message>,
+        submissions: SubmissionPipeline,
+        tx_outgoing: Sender<Sv1Frame>,
         first_job_received: bool,
         extranonce2_len: usize,
         difficulty_mgmt: DownstreamDifficultyConfig,
@@ -89,13 +118,20 @@ impl Downstream {
             extranonce1,
             version_rolling_mask,
             version_rolling_min_bit,
-            tx_sv1_bridge,
+            submissions,
             tx_outgoing,
             first_job_received,
             extranonce2_len,
             difficulty_mgmt,
             upstream_difficulty_config,
+            channel_hashrate: 0.0,
             last_job_id,
+            session_token: SessionStore::new_token(),
+            session_stats: SessionStats::default(),
+            session_store: Arc::new(SessionStore::new()),
+            pinned_difficulty: None,
+            host: String::new(),
+            limiter: Arc::new(RateLimiter::new("translator", &Default::default())),
         }
     }
     /// Instantiate a new `Downstream`.
@@ -103,7 +139,7 @@ impl Downstream {
     pub async fn new_downstream(
         stream: TcpStream,
         connection_id: u32,
-        tx_sv1_bridge: Sender<DownstreamMessages>,
+        submissions: SubmissionPipeline,
         mut rx_sv1_notify: broadcast::Receiver<server_to_client::Notify<'static>>,
         tx_status: status::Sender,
         extranonce1: Vec<u8>,
@@ -112,13 +148,16 @@ impl Downstream {
         host: String,
         difficulty_config: DownstreamDifficultyConfig,
         upstream_difficulty_config: Arc<Mutex<UpstreamDifficultyConfig>>,
+        session_store: Arc<SessionStore>,
+        outgoing_capacity: usize,
         task_collector: Arc<Mutex<Vec<(AbortHandle, String)>>>,
+        limiter: Arc<RateLimiter>,
     ) {
         let stream = std::sync::Arc::new(stream);
 
         // Reads and writes from Downstream SV1 Mining Device Client
         let (socket_reader, socket_writer) = (stream.clone(), stream);
-        let (tx_outgoing, receiver_outgoing) = bounded(10);
+        let (tx_outgoing, receiver_outgoing) = bounded(outgoing_capacity);
 
         let socket_writer_clone = socket_writer.clone();
         // Used to send SV1 `mining.notify` messages to the Downstreams
@@ -131,15 +170,29 @@ impl Downstream {
             //extranonce1: extranonce1.to_vec(),
             version_rolling_mask: None,
             version_rolling_min_bit: None,
-            tx_sv1_bridge,
+            submissions,
             tx_outgoing,
             first_job_received: false,
             extranonce2_len,
             difficulty_mgmt: difficulty_config,
             upstream_difficulty_config,
+            channel_hashrate: 0.0,
             last_job_id: "".to_string(),
+            session_token: SessionStore::new_token(),
+            session_stats: SessionStats::default(),
+            session_store: session_store.clone(),
+            pinned_difficulty: None,
+            host: host.clone(),
+            limiter,
         }));
+        session_store.track(connection_id, downstream.clone());
         let self_ = downstream.clone();
+        events::publish(Event::MinerConnected {
+            listener: Listener::Translator,
+            connection_id,
+        });
+        // the tasks of the connection log in its span, the worker recorded once authorized
+        let span = info_span!("connection", connection_id, worker = field::Empty);
 
         let host_ = host.clone();
         // The shutdown channel is used local to the `Downstream::new_downstream()` function.
@@ -161,11 +214,12 @@ impl Downstream {
         // SV1 message received, a message response is sent directly back to the SV1 Downstream
         // role, or the message is sent upwards to the Bridge for translation into a SV2 message
         // and then sent to the SV2 Upstream role.
-        let socket_reader_task = tokio::task::spawn(async move {
+        let read_socket = async move {
             let reader = BufReader::new(&*socket_reader);
+            // lines are split off the read buffer and parsed in place, not copied into a `String`
             let mut messages = FramedRead::new(
                 async_compat::Compat::new(reader),
-                LinesCodec::new_with_max_length(MAX_LINE_LENGTH),
+                AnyDelimiterCodec::new_with_max_length(b"\n".to_vec(), vec![], MAX_LINE_LENGTH),
             );
             loop {
                 // Read message from SV1 Mining Device Client socket
@@ -177,18 +231,9 @@ impl Downstream {
                         match res {
                             Some(Ok(incoming)) => {
                                 debug!("Receiving from Mining Device {}: {:?}", &host_, &incoming);
-                                let incoming: json_rpc::Message = handle_result!(tx_status_reader, serde_json::from_str(&incoming));
-                                // Handle what to do with message
-                                // if let json_rpc::Message
-
-                                // if message is Submit Shares update difficulty management
-                                if let sv1_api::Message::StandardRequest(standard_req) = incoming.clone() {
-                                    if let Ok(Submit{..}) = standard_req.try_into() {
-                                        handle_result!(tx_status_reader, Self::save_share(self_.clone()));
-                                    }
-                                }
-
-                                let res = Self::handle_incoming_sv1(self_.clone(), incoming).await;
+                                // a line is either a single message or a JSON-RPC batch array
+                                let incoming: Sv1Frame = handle_result!(tx_status_reader, serde_json::from_slice(&incoming));
+                                let res = Self::handle_incoming_frame(self_.clone(), incoming).await;
                                 handle_result!(tx_status_reader, res);
                             }
                             Some(Err(_)) => {
@@ -211,7 +256,8 @@ impl Downstream {
             }
             kill(&tx_shutdown_clone).await;
             warn!("Downstream: Shutting down sv1 downstream reader");
-        });
+        };
+        let socket_reader_task = tokio::task::spawn(read_socket.instrument(span.clone()));
         let _ = task_collector_mining_device.safe_lock(|a| {
             a.push((
                 socket_reader_task.abort_handle(),
@@ -227,21 +273,22 @@ impl Downstream {
         let task_collector_new_sv1_message_no_transl = task_collector.clone();
         // Task to receive SV1 message responses to SV1 messages that do NOT need translation.
         // These response messages are sent directly to the SV1 Downstream role.
-        let socket_writer_task = tokio::task::spawn(async move {
+        let write_socket = async move {
+            // every line is serialized in the same buffer
+            let mut line = Vec::with_capacity(1024);
             loop {
                 select! {
                     res = receiver_outgoing.recv().fuse() => {
                         let to_send = handle_result!(tx_status_writer, res);
-                        let to_send = match serde_json::to_string(&to_send) {
-                            Ok(string) => format!("{}\n", string),
-                            Err(_e) => {
-                                debug!("\nDownstream: Bad SV1 server message\n");
-                                break;
-                            }
-                        };
+                        line.clear();
+                        if serde_json::to_writer(&mut line, &to_send).is_err() {
+                            debug!("\nDownstream: Bad SV1 server message\n");
+                            break;
+                        }
+                        line.push(b'\n');
                         debug!("Sending to Mining Device: {} - {:?}", &host_, &to_send);
                         let res = (&*socket_writer_clone)
-                                    .write_all(to_send.as_bytes())
+                                    .write_all(&line)
                                     .await;
                         handle_result!(tx_status_writer, res);
                     },
@@ -255,7 +302,8 @@ impl Downstream {
                 "Downstream: Shutting down sv1 downstream writer: {}",
                 &host_
             );
-        });
+        };
+        let socket_writer_task = tokio::task::spawn(write_socket.instrument(span.clone()));
         let _ = task_collector_new_sv1_message_no_transl.safe_lock(|a| {
             a.push((
                 socket_writer_task.abort_handle(),
@@ -267,9 +315,10 @@ impl Downstream {
         let self_ = downstream.clone();
 
         let task_collector_notify_task = task_collector.clone();
-        let notify_task = tokio::task::spawn(async move {
-            let timeout_timer = std::time::Instant::now();
+        let notify = async move {
+            let timeout_timer = sim::now();
             let mut first_sent = false;
+            let mut reason = "closed";
             loop {
                 let is_a = match downstream.safe_lock(|d| !d.authorized_names.is_empty()) {
                     Ok(is_a) => is_a,
@@ -279,6 +328,10 @@ impl Downstream {
                     }
                 };
                 if is_a && !first_sent && last_notify.is_some() {
+                    handle_result!(
+                        tx_status_notify,
+                        Self::refresh_pinned_difficulty(downstream.clone())
+                    );
                     let target = handle_result!(
                         tx_status_notify,
                         Self::hash_rate_to_target(downstream.clone())
@@ -319,6 +372,11 @@ impl Downstream {
                     // mining.set_difficulty
                     select! {
                         res = rx_sv1_notify.recv().fuse() => {
+                            // too far behind, the jobs missed are stale: on to the newest
+                            if let Err(broadcast::error::RecvError::Lagged(missed)) = res {
+                                warn!("Downstream {}: skipping {} jobs it lagged", &host, missed);
+                                continue;
+                            }
                             // if hashrate has changed, update difficulty management, and send new mining.set_difficulty
                             handle_result!(tx_status_notify, Self::try_update_difficulty_settings(downstream.clone()).await);
 
@@ -336,62 +394,105 @@ impl Downstream {
                 } else {
                     // timeout connection if miner does not send the authorize message after sending
                     // a subscribe
-                    if timeout_timer.elapsed().as_secs() > SUBSCRIBE_TIMEOUT_SECS {
+                    if sim::now().duration_since(timeout_timer).as_secs() > SUBSCRIBE_TIMEOUT_SECS {
                         debug!(
                             "Downstream: miner.subscribe/miner.authorize TIMEOUT for {}",
                             &host
                         );
+                        reason = "no mining.authorize in time";
                         break;
                     }
                     task::sleep(std::time::Duration::from_secs(1)).await;
                 }
             }
+            let _ = Self::stash_session(self_.clone());
+            // untracked even if a panic poisoned its lock, its session then not stashed
+            session_store.untrack(connection_id);
             let _ = Self::remove_miner_hashrate_from_channel(self_);
             kill(&tx_shutdown).await;
+            events::publish(Event::MinerDisconnected {
+                listener: Listener::Translator,
+                connection_id,
+            });
+            audit::record(
+                Listener::Translator,
+                &host,
+                AuditEvent::Disconnected {
+                    connection_id: Some(connection_id),
+                    reason: reason.to_string(),
+                },
+            );
             warn!(
                 "Downstream: Shutting down sv1 downstream job notifier for {}",
                 &host
             );
-        });
+        };
+        let notify_task = tokio::task::spawn(notify.instrument(span));
 
         let _ = task_collector_notify_task
             .safe_lock(|a| a.push((notify_task.abort_handle(), "notify_task".to_string())));
     }
 
     /// Accept connections from one or more SV1 Downstream roles (SV1 Mining Devices) and create a
-    /// new `Downstream` for each connection.
+    /// new `Downstream` for each connection, until `cancel_token` is cancelled. A listener that
+    /// can't bind its address shuts the translator down through `tx_status`.
     #[allow(clippy::too_many_arguments)]
     pub fn accept_connections(
-        downstream_addr: SocketAddr,
-        tx_sv1_submit: Sender<DownstreamMessages>,
+        downstream_addr: String,
+        submissions: SubmissionPipeline,
         tx_mining_notify: broadcast::Sender<server_to_client::Notify<'static>>,
         tx_status: status::Sender,
         bridge: Arc<Mutex<crate::proxy_wallet::proxy::Bridge>>,
         downstream_difficulty_config: DownstreamDifficultyConfig,
         upstream_difficulty_config: Arc<Mutex<UpstreamDifficultyConfig>>,
+        session_store: Arc<SessionStore>,
+        outgoing_capacity: usize,
         task_collector: Arc<Mutex<Vec<(AbortHandle, String)>>>,
+        limiter: Arc<RateLimiter>,
+        cancel_token: CancellationToken,
     ) {
         let task_collector_downstream = task_collector.clone();
-
-        let accept_connections = tokio::task::spawn(async move {
-            let downstream_listener = TcpListener::bind(downstream_addr).await.unwrap();
+        let tx_status_listener = tx_status.clone();
+
+        let accepting = async move {
+            let downstream_listener = handoff::bind(Listener::Translator, &downstream_addr)
+                .map_err(|e| Error::Bind(format!("{}: {}", downstream_addr, e)))?;
+            let downstream_listener = TcpListener::from(downstream_listener);
+            events::publish(Event::ListenerBound {
+                listener: Listener::Translator,
+                address: downstream_addr.clone(),
+            });
             let mut downstream_incoming = downstream_listener.incoming();
 
-            while let Some(stream) = downstream_incoming.next().await {
+            while let Some(Some(stream)) = handoff::accepting(downstream_incoming.next()).await {
                 let stream = stream.expect("Err on SV1 Downstream connection stream");
+                let peer = match stream.peer_addr() {
+                    Ok(peer) => peer,
+                    Err(e) => {
+                        warn!("Downstream: connection without a peer address: {}", e);
+                        continue;
+                    }
+                };
+                let host = peer.to_string();
+                if !limiter.allow_ip(peer.ip()) {
+                    debug!("Rate limited connection from {}", host);
+                    let reason = "rate limit".to_string();
+                    audit::record(Listener::Translator, &host, AuditEvent::Refused { reason });
+                    continue;
+                }
                 let expected_hash_rate = downstream_difficulty_config.min_individual_miner_hashrate;
                 let open_sv1_downstream = bridge
                     .safe_lock(|s| s.on_new_sv1_connection(expected_hash_rate))
                     .unwrap();
 
-                let host = stream.peer_addr().unwrap().to_string();
+                audit::record(Listener::Translator, &host, AuditEvent::Accepted);
                 match open_sv1_downstream {
                     Ok(opened) => {
                         info!("PROXY SERVER - ACCEPTING FROM DOWNSTREAM: {}", host);
                         Downstream::new_downstream(
                             stream,
                             opened.channel_id,
-                            tx_sv1_submit.clone(),
+                            submissions.clone(),
                             tx_mining_notify.subscribe(),
                             tx_status.listener_to_connection(),
                             opened.extranonce,
@@ -400,15 +501,26 @@ impl Downstream {
                             host,
                             downstream_difficulty_config.clone(),
                             upstream_difficulty_config.clone(),
+                            session_store.clone(),
+                            outgoing_capacity,
                             task_collector_downstream.clone(),
+                            limiter.clone(),
                         )
                         .await;
                     }
                     Err(e) => {
                         tracing::error!("Failed to create a new downstream connection: {:?}", e);
+                        let reason = format!("no channel: {:?}", e);
+                        audit::record(Listener::Translator, &host, AuditEvent::Refused { reason });
                     }
                 }
             }
+            Ok::<_, Error>(())
+        };
+        let accept_connections = tokio::task::spawn(async move {
+            if let Some(Err(e)) = cancel_token.run_until_cancelled(accepting).await {
+                status::handle_error(&tx_status_listener, e).await;
+            }
         });
         let _ = task_collector.safe_lock(|a| {
             a.push((
@@ -418,34 +530,117 @@ impl Downstream {
         });
     }
 
-    /// As SV1 messages come in, determines if the message response needs to be translated to SV2
-    /// and sent to the `Upstream`, or if a direct response can be sent back by the `Translator`
-    /// (SV1 and SV2 protocol messages are NOT 1-to-1).
+    /// Handles a line read from the SV1 Downstream. Every message of a batch is handled in order
+    /// and the responses are written back as a single batch, as JSON-RPC expects: a message that
+    /// fails is answered with an error in place of its response, and an empty batch with a single
+    /// error.
+    pub(crate) async fn handle_incoming_frame(
+        self_: Arc<Mutex<Self>>,
+        frame: Sv1Frame,
+    ) -> Result<(), crate::error::Error> {
+        match frame {
+            Sv1Frame::Single(message_sv1) => {
+                if let Some(response) =
+                    Self::handle_incoming_sv1(self_.clone(), message_sv1).await?
+                {
+                    Self::send_message_downstream(self_, response).await?;
+                }
+            }
+            Sv1Frame::Batch(messages) if messages.is_empty() => {
+                let error = InvalidRequest::new(INVALID_REQUEST, "Empty batch".to_string());
+                Self::send_frame_downstream(self_, Sv1Frame::Invalid(error)).await?;
+            }
+            Sv1Frame::Batch(messages) => {
+                debug!("Down: Handling batch of {} messages", messages.len());
+                let mut responses = Vec::with_capacity(messages.len());
+                for message_sv1 in messages {
+                    let id = match &message_sv1 {
+                        json_rpc::Message::StandardRequest(request) => Some(request.id),
+                        _ => None,
+                    };
+                    match Self::handle_incoming_sv1(self_.clone(), message_sv1).await {
+                        Ok(Some(response)) => responses.push(response),
+                        Ok(None) => {}
+                        Err(e) => {
+                            warn!("Down: Message {:?} of a batch failed: {}", id, e);
+                            // notifications get no answer, not even an error
+                            if let Some(id) = id {
+                                responses.push(error_response(id, &e));
+                            }
+                        }
+                    }
+                }
+                if !responses.is_empty() {
+                    Self::send_frame_downstream(self_, Sv1Frame::Batch(responses)).await?;
+                }
+            }
+            // only ever written to the Downstream, never read from it
+            Sv1Frame::Invalid(_) => {}
+        }
+        Ok(())
+    }
+
+    /// Handles a single SV1 message, returning the response to send back to the Downstream if the
+    /// message does not need translation. As SV1 messages come in, determines if the message
+    /// response needs to be translated to SV2 and sent to the `Upstream`, or if a direct response
+    /// can be sent back by the `Translator` (SV1 and SV2 protocol messages are NOT 1-to-1).
     async fn handle_incoming_sv1(
         self_: Arc<Mutex<Self>>,
         message_sv1: json_rpc::Message,
-    ) -> Result<(), crate::error::Error<'static>> {
-        // `handle_message` in `IsServer` trait + calls `handle_request`
-        // TODO: Map err from V1Error to Error::V1Error
-        let response = self_.safe_lock(|s| s.handle_message(message_sv1)).unwrap();
-        match response {
-            Ok(res) => {
-                if let Some(r) = res {
-                    // If some response is received, indicates no messages translation is needed
-                    // and response should be sent directly to the SV1 Downstream. Otherwise,
-                    // message will be sent to the upstream Translator to be translated to SV2 and
-                    // forwarded to the `Upstream`
-                    // let sender = self_.safe_lock(|s| s.connection.sender_upstream)
-                    if let Err(e) = Self::send_message_downstream(self_, r.into()).await {
-                        return Err(e.into());
-                    }
-                    Ok(())
-                } else {
-                    // If None response is received, indicates this SV1 message received from the
-                    // Downstream MD is passed to the `Translator` for translation into SV2
-                    Ok(())
+    ) -> Result<Option<json_rpc::Message>, crate::error::Error> {
+        // a server gets no responses, as `IsServer::handle_message` checks
+        if message_sv1.is_response() {
+            return Err(sv1_api::error::Error::InvalidJsonRpcMessageKind.into());
+        }
+        // parsed once, then told apart here and handled by `handle_request`
+        let request: Client2Server<'static> = message_sv1
+            .try_into()
+            .map_err(sv1_api::error::Error::from)?;
+        let mut answered_by_pipeline = false;
+        match &request {
+            // if message is Submit Shares update difficulty management
+            Client2Server::Submit(_) => {
+                Self::save_share(self_.clone())?;
+                Self::relieve_overload(self_.clone()).await?;
+                answered_by_pipeline = self_
+                    .safe_lock(|s| s.submissions.answers_shares())
+                    .map_err(|_e| Error::PoisonLock)?;
+            }
+            Client2Server::Subscribe(subscribe) => {
+                let (connection_id, host) = self_
+                    .safe_lock(|s| (s.connection_id, s.host.clone()))
+                    .unwrap();
+                audit::record(
+                    Listener::Translator,
+                    host,
+                    AuditEvent::Setup {
+                        connection_id: Some(connection_id),
+                        protocol: "stratum_v1".to_string(),
+                        version: None,
+                        user_agent: subscribe.agent_signature.clone(),
+                    },
+                );
+                // if the miner sent back a session token, try to resume it before the subscribe
+                // response goes out
+                if let Some(token) = &subscribe.extranonce1 {
+                    let token = hex::encode(token.0.inner_as_ref());
+                    Self::try_resume_session(self_.clone(), token)?;
                 }
             }
+            _ => {}
+        }
+
+        // TODO: Map err from V1Error to Error::V1Error
+        #[allow(clippy::result_large_err)]
+        let response = self_.safe_lock(|s| s.handle_request(request)).unwrap();
+        match response {
+            // If some response is received, indicates no messages translation is needed and
+            // response should be sent directly to the SV1 Downstream. If None response is
+            // received, indicates this SV1 message received from the Downstream MD is passed to
+            // the `Translator` for translation into SV2
+            // a share queued is answered once it leaves the queue, see `SubmitOutcome::Pending`
+            Ok(Some(res)) if answered_by_pipeline && res.result.as_bool() == Some(true) => Ok(None),
+            Ok(res) => Ok(res.map(|r| r.into())),
             Err(e) => Err(e.into()),
         }
     }
@@ -455,10 +650,17 @@ impl Downstream {
     pub(super) async fn send_message_downstream(
         self_: Arc<Mutex<Self>>,
         response: json_rpc::Message,
-    ) -> Result<(), async_channel::SendError<sv1_api::Message>> {
+    ) -> Result<(), async_channel::SendError<Sv1Frame>> {
+        Self::send_frame_downstream(self_, Sv1Frame::Single(response)).await
+    }
+
+    async fn send_frame_downstream(
+        self_: Arc<Mutex<Self>>,
+        frame: Sv1Frame,
+    ) -> Result<(), async_channel::SendError<Sv1Frame>> {
         let sender = self_.safe_lock(|s| s.tx_outgoing.clone()).unwrap();
-        debug!("To DOWN: {:?}", response);
-        sender.send(response).await
+        debug!("To DOWN: {:?}", frame);
+        sender.send(frame).await
     }
 
     /// Send SV1 response message that is generated by `Downstream` (as opposed to being received
@@ -466,10 +668,10 @@ impl Downstream {
     pub(super) async fn send_message_upstream(
         self_: Arc<Mutex<Self>>,
         msg: DownstreamMessages,
-    ) -> ProxyResult<'static, ()> {
-        let sender = self_.safe_lock(|s| s.tx_sv1_bridge.clone()).unwrap();
+    ) -> ProxyResult<()> {
+        let submissions = self_.safe_lock(|s| s.submissions.clone()).unwrap();
         debug!("To Bridge: {:?}", msg);
-        let _ = sender.send(msg).await;
+        let _ = submissions.send(msg).await;
         Ok(())
     }
 }
@@ -512,18 +714,17 @@ impl IsServer<'static> for Downstream {
     /// Handle the response to a `mining.subscribe` message received from the client.
     /// The subscription messages are erroneous and just used to conform the SV1 protocol spec.
     /// Because no one unsubscribed in practice, they just unplug their machine.
+    /// The subscription id is the session token, miners that support resumption send it back as
+    /// the second `mining.subscribe` parameter when reconnecting.
     fn handle_subscribe(&self, request: &client_to_server::Subscribe) -> Vec<(String, String)> {
         info!("Down: Subscribing");
         debug!("Down: Handling mining.subscribe: {:?}", &request);
 
         let set_difficulty_sub = (
             "mining.set_difficulty".to_string(),
-            downstream_sv1::new_subscription_id(),
-        );
-        let notify_sub = (
-            "mining.notify".to_string(),
-            "ae6812eb4cd7735a302a8a9dd95cf71f".to_string(),
+            self.session_token.clone(),
         );
+        let notify_sub = ("mining.notify".to_string(), self.session_token.clone());
 
         vec![set_difficulty_sub, notify_sub]
     }
@@ -534,12 +735,25 @@ impl IsServer<'static> for Downstream {
     fn handle_authorize(&self, request: &client_to_server::Authorize) -> bool {
         info!("Down: Authorizing");
         debug!("Down: Handling mining.authorize: {:?}", &request);
+        if !self.limiter.allow_identity(&request.name) {
+            warn!(
+                "Down: mining.authorize of {} refused: rate limited",
+                request.name
+            );
+            return false;
+        }
         true
     }
 
     /// When miner find the job which meets requested difficulty, it can submit share to the server.
     /// Only [Submit](client_to_server::Submit) requests for authorized user names can be submitted.
     fn handle_submit(&self, request: &client_to_server::Submit<'static>) -> bool {
+        let _span = info_span!(
+            "sv1_submit",
+            job_id = %request.job_id,
+            share_id = %logging::share_id(request.nonce.0, request.time.0)
+        )
+        .entered();
         info!("Down: Submitting Share {:?}", request);
         debug!("Down: Handling mining.submit: {:?}", &request);
 
@@ -549,16 +763,18 @@ impl IsServer<'static> for Downstream {
             let to_send = SubmitShareWithChannelId {
                 channel_id: self.connection_id,
                 share: request.clone(),
-                extranonce: self.extranonce1.clone(),
                 extranonce2_len: self.extranonce2_len,
                 version_rolling_mask: self.version_rolling_mask.clone(),
+                difficulty: self.current_difficulty(),
             };
 
-            self.tx_sv1_bridge
-                .try_send(DownstreamMessages::SubmitShares(to_send))
-                .unwrap();
-
-            true
+            match self
+                .submissions
+                .submit(DownstreamMessages::SubmitShares(to_send), &self.tx_outgoing)
+            {
+                SubmitOutcome::Queued | SubmitOutcome::Pending => true,
+                SubmitOutcome::Dropped | SubmitOutcome::Rejected => false,
+            }
         } else {
             false
         }
@@ -572,9 +788,21 @@ impl IsServer<'static> for Downstream {
         self.authorized_names.contains(&name.to_string())
     }
 
-    /// Authorizes a Downstream role.
+    /// Authorizes a Downstream role. A resumed session already carries its worker names.
     fn authorize(&mut self, name: &str) {
-        self.authorized_names.push(name.to_string());
+        if !self.is_authorized(name) {
+            self.authorized_names.push(name.to_string());
+            audit::record(
+                Listener::Translator,
+                &self.host,
+                AuditEvent::Authorized {
+                    connection_id: self.connection_id,
+                    channel_id: None,
+                    identity: name.to_string(),
+                },
+            );
+        }
+        Span::current().record("worker", name);
     }
 
     /// Sets the `extranonce1` field sent in the SV1 `mining.notify` message to the value specified
@@ -617,7 +845,7 @@ impl IsServer<'static> for Downstream {
         self.version_rolling_min_bit = mask
     }
 
-    fn notify(&mut self) -> Result<json_rpc::Message, sv1_api::error::Error> {
+    fn notify(&mut self) -> Result<json_rpc::Message, sv1_api::error::Error<'_>> {
         unreachable!()
     }
 }
@@ -646,4 +874,33 @@ mod tests {
         let expect = 512.0;
         assert_eq!(actual, expect);
     }
+
+    #[test]
+    fn target_from_difficulty_roundtrips() {
+        for difficulty in [1.0, 512.0, 65536.0] {
+            let target = Downstream::target_from_difficulty(difficulty).unwrap();
+            let actual = Downstream::difficulty_from_target(target).unwrap();
+            assert!((actual - difficulty).abs() / difficulty < 1e-6);
+        }
+    }
+
+    #[test]
+    fn parses_batch_frames() {
+        let single = r#"{"id":1,"method":"mining.subscribe","params":["cgminer/4.10.0"]}"#;
+        assert!(matches!(
+            serde_json::from_str::<Sv1Frame>(single).unwrap(),
+            Sv1Frame::Single(_)
+        ));
+
+        let batch = r#"[{"id":1,"method":"mining.subscribe","params":["cgminer/4.10.0"]},
+            {"id":2,"method":"mining.authorize","params":["worker.1","x"]}]"#;
+        match serde_json::from_str::<Sv1Frame>(batch).unwrap() {
+            Sv1Frame::Batch(messages) => {
+                assert_eq!(messages.len(), 2);
+                let response = serde_json::to_string(&Sv1Frame::Batch(messages)).unwrap();
+                assert!(response.starts_with('['));
+            }
+            _ => panic!("batch parsed as a single message"),
+        }
+    }
 }
@@ -1,8 +1,14 @@
+use crate::error::Error;
 use roles_logic_sv2::mining_sv2::Target;
-use sv1_api::{client_to_server::Submit, utils::HexU32Be};
+use serde::{Deserialize, Serialize};
+use sv1_api::{client_to_server::Submit, json_rpc, utils::HexU32Be};
 pub mod diff_management;
 pub mod downstream;
+pub mod pipeline;
+pub mod session;
 pub use downstream::Downstream;
+pub use pipeline::{SubmissionPipeline, SubmissionReceiver};
+pub use session::SessionStore;
 
 /// This constant is used as a check to ensure clients
 /// do not send a mining.subscribe and never a mining.authorize
@@ -11,6 +17,10 @@ pub use downstream::Downstream;
 /// `mining.subscribe` messages that init connections and take up compute
 const SUBSCRIBE_TIMEOUT_SECS: u64 = 10;
 
+/// Minimum time between two difficulty raises of the same Downstream caused by an overloaded
+/// submission pipeline.
+const OVERLOAD_RAISE_INTERVAL_SECS: u64 = 10;
+
 /// enum of messages sent to the Bridge
 #[derive(Debug)]
 pub enum DownstreamMessages {
@@ -24,9 +34,63 @@ pub enum DownstreamMessages {
 pub struct SubmitShareWithChannelId {
     pub channel_id: u32,
     pub share: Submit<'static>,
-    pub extranonce: Vec<u8>,
     pub extranonce2_len: usize,
     pub version_rolling_mask: Option<HexU32Be>,
+    /// Difficulty the Downstream was mining at, used to pick shares to drop under overload.
+    pub difficulty: f64,
+}
+
+/// A line exchanged with the SV1 Downstream. Some farm controllers send several requests at once
+/// as a JSON-RPC batch array, which gets answered with an array of responses.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Sv1Frame {
+    Single(json_rpc::Message),
+    Batch(Vec<json_rpc::Message>),
+    /// Answers a line that is no request at all, only ever written.
+    #[serde(skip_deserializing)]
+    Invalid(InvalidRequest),
+}
+
+/// JSON-RPC error code of a line that is no valid request, e.g. an empty batch.
+pub const INVALID_REQUEST: i32 = -32600;
+/// Stratum error code of a request that failed otherwise, "Other/Unknown".
+const OTHER_ERROR: i32 = 20;
+
+/// The error answering a line that is no request, its id null since it has none to answer to.
+#[derive(Debug, Serialize)]
+pub struct InvalidRequest {
+    id: Option<u64>,
+    error: json_rpc::JsonRpcError,
+    result: serde_json::Value,
+}
+
+impl InvalidRequest {
+    pub fn new(code: i32, message: String) -> Self {
+        Self {
+            id: None,
+            error: json_rpc::JsonRpcError {
+                code,
+                message,
+                data: None,
+            },
+            result: serde_json::Value::Null,
+        }
+    }
+}
+
+/// The error answering request `id` that failed with `error`, its stable code as data.
+pub fn error_response(id: u64, error: &Error) -> json_rpc::Message {
+    json_rpc::Response {
+        id,
+        error: Some(json_rpc::JsonRpcError {
+            code: OTHER_ERROR,
+            message: error.to_string(),
+            data: Some(error.code().into()),
+        }),
+        result: serde_json::Value::Null,
+    }
+    .into()
 }
 
 /// message for notifying the bridge that a downstream target has updated
@@ -45,7 +109,3 @@ pub async fn kill(sender: &async_channel::Sender<bool>) {
     // meaning all tasks have already dropped
     sender.send(true).await.unwrap();
 }
-
-pub fn new_subscription_id() -> String {
-    "ae6812eb4cd7735a302a8a9dd95cf71f".into()
-}
@@ -0,0 +1,499 @@
+use super::{DownstreamMessages, Sv1Frame};
+use crate::{
+    proxy_wallet::proxy_config::{OverloadPolicy, SubmissionPipelineConfig},
+    status::diagnostics::{ChannelProbe, ChannelStats},
+};
+use async_channel::{RecvError, SendError, Sender, TryRecvError};
+use roles_logic_sv2::utils::Mutex;
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+use sv1_api::json_rpc;
+use tokio::sync::Notify;
+use tracing::{debug, warn};
+
+/// What happened to a share handed to the `SubmissionPipeline`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubmitOutcome {
+    /// The share is queued for the Bridge.
+    Queued,
+    /// The share is queued for the Bridge and the pipeline answers the miner once it leaves the
+    /// queue: accepted when the Bridge takes it, rejected if a share of a higher difficulty
+    /// evicts it first, see `OverloadPolicy::DropLowestDifficulty`.
+    Pending,
+    /// The pipeline was full and the share was dropped, to be rejected back to the miner.
+    Dropped,
+    /// The pipeline was full (or closed) and the share should be rejected back to the miner.
+    Rejected,
+}
+
+/// Bounded queue carrying `DownstreamMessages` from every Downstream to the Bridge. Replaces an
+/// unbounded channel that could grow without limit when the upstream side falls behind. When the
+/// queue is full, shares are handled according to the configured `OverloadPolicy`.
+pub struct SubmissionPipeline {
+    shared: Arc<Shared>,
+    policy: OverloadPolicy,
+    dropped: Arc<AtomicU64>,
+    /// Watches the queue as `translator_submissions`, see `status::diagnostics`.
+    _probe: ChannelProbe,
+}
+
+/// The end of the `SubmissionPipeline` the Bridge takes the messages from, in the order they came.
+pub struct SubmissionReceiver {
+    shared: Arc<Shared>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    queue: Mutex<Queue>,
+    capacity: usize,
+    /// Woken when a message is queued or the last pipeline goes.
+    queued: Notify,
+    /// Woken when a message leaves the queue or the last receiver goes.
+    room: Notify,
+}
+
+#[derive(Debug, Default)]
+struct Queue {
+    next_seq: u64,
+    /// The messages queued by sequence number, so in the order they came.
+    messages: BTreeMap<u64, Entry>,
+    /// The shares queued by difficulty then sequence number, the lowest first, so the one to evict
+    /// is found without going through the queue.
+    shares: BTreeSet<(i64, u64)>,
+    senders: usize,
+    receivers: usize,
+}
+
+#[derive(Debug)]
+struct Entry {
+    msg: DownstreamMessages,
+    /// The key of the share in `Queue::shares`.
+    difficulty: Option<i64>,
+    answer: Option<Answer>,
+}
+
+/// A miner waiting for the answer to its share, see `SubmitOutcome::Pending`.
+#[derive(Debug)]
+struct Answer {
+    id: u64,
+    miner: Sender<Sv1Frame>,
+}
+
+impl Answer {
+    /// Answers the `mining.submit` of the share, without waiting on a miner that doesn't read.
+    fn send(self, accepted: bool) {
+        let response = json_rpc::Response {
+            id: self.id,
+            error: None,
+            result: Value::Bool(accepted),
+        };
+        if let Err(e) = self.miner.try_send(Sv1Frame::Single(response.into())) {
+            debug!("Down: Share {} left unanswered: {}", self.id, e);
+        }
+    }
+}
+
+/// `difficulty` as a key ordered as `f64::total_cmp` orders them.
+fn difficulty_key(difficulty: f64) -> i64 {
+    let bits = difficulty.to_bits() as i64;
+    bits ^ (((bits >> 63) as u64) >> 1) as i64
+}
+
+impl Queue {
+    fn push(&mut self, msg: DownstreamMessages, answer: Option<Answer>) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        let difficulty = match &msg {
+            DownstreamMessages::SubmitShares(share) => Some(difficulty_key(share.difficulty)),
+            DownstreamMessages::SetDownstreamTarget(_) => None,
+        };
+        if let Some(difficulty) = difficulty {
+            self.shares.insert((difficulty, seq));
+        }
+        self.messages.insert(
+            seq,
+            Entry {
+                msg,
+                difficulty,
+                answer,
+            },
+        );
+    }
+
+    fn remove(&mut self, seq: u64, entry: &Entry) {
+        if let Some(difficulty) = entry.difficulty {
+            self.shares.remove(&(difficulty, seq));
+        }
+    }
+}
+
+impl SubmissionPipeline {
+    /// Creates the pipeline, returning it along with the receiver for the Bridge.
+    pub fn new(config: &SubmissionPipelineConfig) -> (Self, SubmissionReceiver) {
+        let capacity = config.capacity.max(1);
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(Queue {
+                senders: 1,
+                receivers: 1,
+                ..Queue::default()
+            }),
+            capacity,
+            queued: Notify::new(),
+            room: Notify::new(),
+        });
+        let probed = shared.clone();
+        let pipeline = Self {
+            shared: shared.clone(),
+            policy: config.overload_policy,
+            dropped: Arc::new(AtomicU64::new(0)),
+            _probe: ChannelProbe::from_fn("translator_submissions", move || ChannelStats {
+                len: probed.len(),
+                capacity: Some(capacity),
+            }),
+        };
+        (pipeline, SubmissionReceiver { shared })
+    }
+
+    pub fn policy(&self) -> OverloadPolicy {
+        self.policy
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.shared.len() >= self.shared.capacity
+    }
+
+    /// Whether the miners of the shares queued are answered by the pipeline rather than right
+    /// away, see `SubmitOutcome::Pending`.
+    pub fn answers_shares(&self) -> bool {
+        self.policy == OverloadPolicy::DropLowestDifficulty
+    }
+
+    /// Number of shares dropped because the pipeline was full.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Sends a non-share message, waiting for room. Target updates must not be dropped.
+    pub async fn send(&self, msg: DownstreamMessages) -> Result<(), SendError<DownstreamMessages>> {
+        let mut msg = Some(msg);
+        loop {
+            // taken before looking so a message leaving in between still wakes it
+            let room = self.shared.room.notified();
+            // a poisoned queue is as closed as one without receivers
+            let sent = self
+                .shared
+                .queue
+                .safe_lock(|queue| {
+                    if queue.receivers == 0 {
+                        return Some(false);
+                    }
+                    if queue.messages.len() >= self.shared.capacity {
+                        return None;
+                    }
+                    queue.push(msg.take().expect("sent once"), None);
+                    Some(true)
+                })
+                .unwrap_or(Some(false));
+            match sent {
+                Some(true) => {
+                    self.shared.queued.notify_one();
+                    return Ok(());
+                }
+                Some(false) => return Err(SendError(msg.take().expect("not sent"))),
+                None => room.await,
+            }
+        }
+    }
+
+    /// Queues a share without blocking, applying the overload policy if the pipeline is full.
+    /// `miner` is where the answer goes if the pipeline answers it, see `answers_shares`.
+    pub fn submit(&self, msg: DownstreamMessages, miner: &Sender<Sv1Frame>) -> SubmitOutcome {
+        let answer = match &msg {
+            DownstreamMessages::SubmitShares(share) if self.answers_shares() => Some(Answer {
+                id: share.share.id,
+                miner: miner.clone(),
+            }),
+            _ => None,
+        };
+        let queued = if answer.is_some() {
+            SubmitOutcome::Pending
+        } else {
+            SubmitOutcome::Queued
+        };
+        let submitted = self.shared.queue.safe_lock(|queue| {
+            if queue.receivers == 0 {
+                return (SubmitOutcome::Rejected, None);
+            }
+            if queue.messages.len() < self.shared.capacity {
+                queue.push(msg, answer);
+                return (queued, None);
+            }
+            warn!(
+                "Down: Submission pipeline is full, applying {:?}",
+                self.policy
+            );
+            match self.policy {
+                OverloadPolicy::Reject => (SubmitOutcome::Rejected, None),
+                OverloadPolicy::RaiseDifficulty => (SubmitOutcome::Dropped, None),
+                OverloadPolicy::DropLowestDifficulty => Self::evict_lowest(queue, msg, answer),
+            }
+        });
+        let (outcome, evicted) = submitted.unwrap_or((SubmitOutcome::Rejected, None));
+        if let Some(evicted) = evicted {
+            evicted.send(false);
+        }
+        match outcome {
+            SubmitOutcome::Queued | SubmitOutcome::Pending => self.shared.queued.notify_one(),
+            SubmitOutcome::Dropped => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+            SubmitOutcome::Rejected => {}
+        }
+        outcome
+    }
+
+    /// Queues `incoming` in place of the queued share with the lowest difficulty if that is lower,
+    /// returning the miner to reject the evicted share to. Drops `incoming` otherwise.
+    fn evict_lowest(
+        queue: &mut Queue,
+        incoming: DownstreamMessages,
+        answer: Option<Answer>,
+    ) -> (SubmitOutcome, Option<Answer>) {
+        let difficulty = match &incoming {
+            DownstreamMessages::SubmitShares(share) => difficulty_key(share.difficulty),
+            DownstreamMessages::SetDownstreamTarget(_) => return (SubmitOutcome::Rejected, None),
+        };
+        let lowest = match queue.shares.first() {
+            Some(&(lowest, seq)) if lowest < difficulty => seq,
+            _ => return (SubmitOutcome::Dropped, None),
+        };
+        let evicted = queue
+            .messages
+            .remove(&lowest)
+            .expect("every share indexed is queued");
+        queue.remove(lowest, &evicted);
+        queue.push(incoming, answer);
+        // dropped in place of the incoming share
+        (SubmitOutcome::Pending, evicted.answer)
+    }
+}
+
+impl Clone for SubmissionPipeline {
+    fn clone(&self) -> Self {
+        let _ = self.shared.queue.safe_lock(|queue| queue.senders += 1);
+        Self {
+            shared: self.shared.clone(),
+            policy: self.policy,
+            dropped: self.dropped.clone(),
+            _probe: self._probe.clone(),
+        }
+    }
+}
+
+impl Drop for SubmissionPipeline {
+    fn drop(&mut self) {
+        let _ = self.shared.queue.safe_lock(|queue| queue.senders -= 1);
+        self.shared.queued.notify_waiters();
+    }
+}
+
+impl std::fmt::Debug for SubmissionPipeline {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SubmissionPipeline")
+            .field("policy", &self.policy)
+            .field("len", &self.shared.len())
+            .field("capacity", &self.shared.capacity)
+            .finish()
+    }
+}
+
+impl Shared {
+    fn len(&self) -> usize {
+        self.queue
+            .safe_lock(|queue| queue.messages.len())
+            .unwrap_or(0)
+    }
+}
+
+impl SubmissionReceiver {
+    /// The next message, waiting for one. Fails once every pipeline is gone and the queue empty.
+    pub async fn recv(&self) -> Result<DownstreamMessages, RecvError> {
+        loop {
+            // taken before looking so a message queued in between still wakes it
+            let queued = self.shared.queued.notified();
+            match self.try_recv() {
+                Ok(msg) => return Ok(msg),
+                Err(TryRecvError::Closed) => return Err(RecvError),
+                Err(TryRecvError::Empty) => queued.await,
+            }
+        }
+    }
+
+    /// The next message if there is one. A share the pipeline answers is accepted on the way out.
+    pub fn try_recv(&self) -> Result<DownstreamMessages, TryRecvError> {
+        let next = self
+            .shared
+            .queue
+            .safe_lock(|queue| match queue.messages.pop_first() {
+                Some((seq, entry)) => {
+                    queue.remove(seq, &entry);
+                    Ok(entry)
+                }
+                None if queue.senders == 0 => Err(TryRecvError::Closed),
+                None => Err(TryRecvError::Empty),
+            })
+            .map_err(|_| TryRecvError::Closed)??;
+        self.shared.room.notify_one();
+        if let Some(answer) = next.answer {
+            answer.send(true);
+        }
+        Ok(next.msg)
+    }
+}
+
+impl Clone for SubmissionReceiver {
+    fn clone(&self) -> Self {
+        let _ = self.shared.queue.safe_lock(|queue| queue.receivers += 1);
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl Drop for SubmissionReceiver {
+    fn drop(&mut self) {
+        let _ = self.shared.queue.safe_lock(|queue| queue.receivers -= 1);
+        self.shared.room.notify_waiters();
+    }
+}
+
+impl std::fmt::Debug for SubmissionReceiver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SubmissionReceiver")
+            .field("len", &self.shared.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::proxy_wallet::downstream_sv1::SubmitShareWithChannelId;
+    use async_channel::{unbounded, Receiver};
+    use sv1_api::{
+        client_to_server::Submit,
+        utils::{Extranonce, HexU32Be},
+    };
+
+    fn share(channel_id: u32, difficulty: f64) -> DownstreamMessages {
+        DownstreamMessages::SubmitShares(SubmitShareWithChannelId {
+            channel_id,
+            share: Submit {
+                user_name: "worker".to_string(),
+                job_id: "1".to_string(),
+                extra_nonce2: Extranonce::try_from([0; 8].to_vec()).unwrap(),
+                time: HexU32Be(0),
+                nonce: HexU32Be(0),
+                version_bits: None,
+                id: channel_id as u64,
+            },
+            extranonce2_len: 8,
+            version_rolling_mask: None,
+            difficulty,
+        })
+    }
+
+    fn channel_ids(rx: &SubmissionReceiver) -> Vec<u32> {
+        let mut ids = vec![];
+        while let Ok(DownstreamMessages::SubmitShares(share)) = rx.try_recv() {
+            ids.push(share.channel_id);
+        }
+        ids
+    }
+
+    /// The ids of the shares answered and whether they were accepted.
+    fn answers(miner: &Receiver<Sv1Frame>) -> Vec<(u64, bool)> {
+        std::iter::from_fn(|| miner.try_recv().ok())
+            .map(|frame| match frame {
+                Sv1Frame::Single(json_rpc::Message::OkResponse(response)) => {
+                    (response.id, response.result == Value::Bool(true))
+                }
+                frame => panic!("not an answer: {:?}", frame),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn rejects_when_full() {
+        let config = SubmissionPipelineConfig::new(1, OverloadPolicy::Reject);
+        let (pipeline, _rx) = SubmissionPipeline::new(&config);
+        let (miner, _) = unbounded();
+        let submit =
+            |channel_id, difficulty| pipeline.submit(share(channel_id, difficulty), &miner);
+        assert_eq!(submit(1, 1.0), SubmitOutcome::Queued);
+        assert_eq!(submit(2, 1.0), SubmitOutcome::Rejected);
+        assert_eq!(pipeline.dropped(), 0);
+    }
+
+    #[test]
+    fn drops_shares_when_full_raising_difficulty() {
+        let config = SubmissionPipelineConfig::new(1, OverloadPolicy::RaiseDifficulty);
+        let (pipeline, rx) = SubmissionPipeline::new(&config);
+        let (miner, _) = unbounded();
+        let submit =
+            |channel_id, difficulty| pipeline.submit(share(channel_id, difficulty), &miner);
+        assert_eq!(submit(1, 1.0), SubmitOutcome::Queued);
+        assert_eq!(submit(2, 1.0), SubmitOutcome::Dropped);
+        assert_eq!(pipeline.dropped(), 1);
+        assert_eq!(channel_ids(&rx), vec![1]);
+        drop(rx);
+        assert_eq!(submit(3, 1.0), SubmitOutcome::Rejected);
+    }
+
+    #[test]
+    fn drops_lowest_difficulty_share() {
+        let config = SubmissionPipelineConfig::new(2, OverloadPolicy::DropLowestDifficulty);
+        let (pipeline, rx) = SubmissionPipeline::new(&config);
+        let (miner, answered) = unbounded();
+        let submit =
+            |channel_id, difficulty| pipeline.submit(share(channel_id, difficulty), &miner);
+        assert_eq!(submit(1, 8.0), SubmitOutcome::Pending);
+        assert_eq!(submit(2, 2.0), SubmitOutcome::Pending);
+        // higher than what is queued, evicts channel 2
+        assert_eq!(submit(3, 4.0), SubmitOutcome::Pending);
+        assert_eq!(answers(&answered), vec![(2, false)]);
+        // lowest of all, dropped itself
+        assert_eq!(submit(4, 1.0), SubmitOutcome::Dropped);
+        assert_eq!(pipeline.dropped(), 1);
+        assert_eq!(channel_ids(&rx), vec![1, 3]);
+        // accepted once the bridge takes them
+        assert_eq!(answers(&answered), vec![(1, true), (3, true)]);
+    }
+
+    #[tokio::test]
+    async fn target_updates_wait_for_room() {
+        let config = SubmissionPipelineConfig::new(1, OverloadPolicy::Reject);
+        let (pipeline, rx) = SubmissionPipeline::new(&config);
+        let (miner, _) = unbounded();
+        assert_eq!(
+            pipeline.submit(share(1, 1.0), &miner),
+            SubmitOutcome::Queued
+        );
+        let sender = pipeline.clone();
+        let sent = tokio::spawn(async move { sender.send(share(2, 1.0)).await.is_ok() });
+        assert!(matches!(
+            rx.recv().await,
+            Ok(DownstreamMessages::SubmitShares(share)) if share.channel_id == 1
+        ));
+        assert!(sent.await.unwrap());
+        assert_eq!(channel_ids(&rx), vec![2]);
+        drop(pipeline);
+        assert!(rx.recv().await.is_err());
+    }
+}
@@ -0,0 +1,302 @@
+use super::Downstream;
+use crate::{
+    error::{Error, ProxyResult},
+    proxy_wallet::proxy_config::DownstreamDifficultyConfig,
+    sharded::ShardedMap,
+    sim,
+    snapshot::Snapshot,
+};
+use roles_logic_sv2::utils::Mutex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::BTreeMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tracing::info;
+
+/// How long a disconnected miner's session is kept around waiting for the miner to reconnect
+/// with its token. Long enough to ride out a network blip or a firmware restart, short enough
+/// that the stored hashrate estimate is still meaningful.
+pub const SESSION_RESUME_TTL_SECS: u64 = 300;
+
+/// Number of random bytes in a session token.
+const SESSION_TOKEN_LEN: usize = 16;
+
+/// Per-connection counters carried over when a session is resumed.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SessionStats {
+    pub shares_submitted: u64,
+    pub resumed_count: u32,
+}
+
+/// Everything about a Downstream that should survive a reconnect.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionState {
+    pub difficulty_mgmt: DownstreamDifficultyConfig,
+    pub authorized_names: Vec<String>,
+    pub stats: SessionStats,
+}
+
+/// Stores the state of recently disconnected Downstreams keyed by the opaque token handed out as
+/// the subscription id in the `mining.subscribe` response. Miners that support session resumption
+/// send the token back as the second `mining.subscribe` parameter when they reconnect.
+///
+/// The sessions, and those of the Downstreams still connected, are snapshot (see
+/// `crate::snapshot`): the miners of a translator that crashed resume them as if disconnected.
+#[derive(Debug)]
+pub struct SessionStore {
+    sessions: ShardedMap<String, (SessionState, Instant)>,
+    /// Downstreams connected, by connection id.
+    live: ShardedMap<u32, Arc<Mutex<Downstream>>>,
+    ttl: Duration,
+}
+
+impl Default for SessionStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SessionStore {
+    pub fn new() -> Self {
+        Self::with_ttl(Duration::from_secs(SESSION_RESUME_TTL_SECS))
+    }
+
+    pub fn with_ttl(ttl: Duration) -> Self {
+        Self {
+            sessions: ShardedMap::new(),
+            live: ShardedMap::new(),
+            ttl,
+        }
+    }
+
+    /// Snapshots the session of `downstream` until `untrack`.
+    pub fn track(&self, connection_id: u32, downstream: Arc<Mutex<Downstream>>) {
+        self.live.insert(connection_id, downstream);
+    }
+
+    pub fn untrack(&self, connection_id: u32) {
+        self.live.remove(&connection_id);
+    }
+
+    /// Generates a new random hex encoded session token.
+    pub fn new_token() -> String {
+        let mut bytes = [0; SESSION_TOKEN_LEN];
+        sim::fill_bytes(&mut bytes);
+        hex::encode(bytes)
+    }
+
+    /// Saves the state of a Downstream that is shutting down so it can be picked up again.
+    pub fn stash(&self, token: String, state: SessionState) {
+        self.prune();
+        self.sessions.insert(token, (state, sim::now() + self.ttl));
+    }
+
+    /// Removes and returns the state for `token` if it exists and has not expired.
+    pub fn take(&self, token: &str) -> Option<SessionState> {
+        self.sessions
+            .remove(&token.to_string())
+            .filter(|(_, expires_at)| *expires_at > sim::now())
+            .map(|(state, _)| state)
+    }
+
+    fn prune(&self) {
+        let now = sim::now();
+        self.sessions.retain(|_, (_, expires_at)| *expires_at > now);
+    }
+}
+
+impl Snapshot for SessionStore {
+    fn save(&self) -> Value {
+        let stashed = self
+            .sessions
+            .entries()
+            .into_iter()
+            .map(|(token, (state, _))| (token, state));
+        let live = self.live.values().into_iter().filter_map(|downstream| {
+            downstream
+                .safe_lock(|d| Some((d.session_token.clone(), d.session_state()?)))
+                .ok()?
+        });
+        let sessions: BTreeMap<_, _> = stashed.chain(live).collect();
+        serde_json::to_value(sessions).unwrap_or_default()
+    }
+
+    /// Stashes the sessions snapshot, for their miners to resume once reconnected.
+    fn restore(&self, state: Value) -> Result<(), String> {
+        let sessions: BTreeMap<String, SessionState> =
+            serde_json::from_value(state).map_err(|e| e.to_string())?;
+        for (token, state) in sessions {
+            self.stash(token, state);
+        }
+        Ok(())
+    }
+}
+
+impl Downstream {
+    /// What a reconnect picks up, once authorized.
+    fn session_state(&self) -> Option<SessionState> {
+        if self.authorized_names.is_empty() {
+            return None;
+        }
+        Some(SessionState {
+            difficulty_mgmt: self.difficulty_mgmt.clone(),
+            authorized_names: self.authorized_names.clone(),
+            stats: self.session_stats.clone(),
+        })
+    }
+
+    /// Restores difficulty, worker names and stats from a previous connection if the miner sent
+    /// back a session token we handed out and it has not expired yet, the restored hashrate added
+    /// to the channel in place of what the connection added. Must be called before the first
+    /// `mining.set_difficulty` is sent so the restored hashrate is used.
+    #[allow(clippy::result_large_err)]
+    pub(super) fn try_resume_session(self_: Arc<Mutex<Self>>, token: String) -> ProxyResult<bool> {
+        let store = self_
+            .safe_lock(|d| d.session_store.clone())
+            .map_err(|_e| Error::PoisonLock)?;
+        let resumed = store.take(&token);
+        match resumed {
+            Some(mut state) => {
+                state.stats.resumed_count += 1;
+                info!(
+                    "Down: Resuming session {} for {:?} ({} shares so far)",
+                    token, state.authorized_names, state.stats.shares_submitted
+                );
+                self_
+                    .safe_lock(|d| {
+                        d.difficulty_mgmt = state.difficulty_mgmt;
+                        d.authorized_names = state.authorized_names;
+                        d.pinned_difficulty = d.worker_pinned_difficulty();
+                        d.session_stats = state.stats;
+                        d.session_token = token;
+                        d.set_channel_hashrate(d.nominal_hashrate())
+                    })
+                    .map_err(|_e| Error::PoisonLock)??;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Saves the state of an authorized Downstream that is going away so a quick reconnect with
+    /// the same session token can pick up where it left off.
+    #[allow(clippy::result_large_err)]
+    pub(super) fn stash_session(self_: Arc<Mutex<Self>>) -> ProxyResult<()> {
+        self_
+            .safe_lock(|d| {
+                if let Some(state) = d.session_state() {
+                    d.session_store.stash(d.session_token.clone(), state);
+                }
+            })
+            .map_err(|_e| Error::PoisonLock)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::proxy_wallet::{
+        downstream_sv1::SubmissionPipeline,
+        proxy_config::{SubmissionPipelineConfig, UpstreamDifficultyConfig},
+    };
+
+    fn state() -> SessionState {
+        SessionState {
+            difficulty_mgmt: DownstreamDifficultyConfig::new(1_000.0, 6.0, 0, 0),
+            authorized_names: vec!["worker.1".to_string()],
+            stats: SessionStats::default(),
+        }
+    }
+
+    #[test]
+    fn resumes_stashed_session_once() {
+        let store = SessionStore::new();
+        let token = SessionStore::new_token();
+        assert_eq!(token.len(), SESSION_TOKEN_LEN * 2);
+        store.stash(token.clone(), state());
+        let resumed = store.take(&token).unwrap();
+        assert_eq!(resumed.authorized_names, vec!["worker.1".to_string()]);
+        assert!(store.take(&token).is_none());
+    }
+
+    #[test]
+    fn expired_sessions_are_dropped() {
+        let store = SessionStore::with_ttl(Duration::from_secs(0));
+        store.stash("abcd".to_string(), state());
+        assert!(store.take("abcd").is_none());
+    }
+
+    #[test]
+    fn resumed_sessions_add_their_hashrate_to_the_channel() {
+        let channel = Arc::new(Mutex::new(UpstreamDifficultyConfig::new(
+            60, 5_000.0, 0, false,
+        )));
+        let (submissions, _rx_submissions) =
+            SubmissionPipeline::new(&SubmissionPipelineConfig::default());
+        let downstream = Downstream::new(
+            1,
+            vec![],
+            vec![],
+            None,
+            None,
+            submissions,
+            async_channel::unbounded().0,
+            false,
+            0,
+            DownstreamDifficultyConfig::new(10.0, 6.0, 0, 0),
+            channel.clone(),
+            "0".to_string(),
+        );
+        let store = downstream.session_store.clone();
+        let downstream = Arc::new(Mutex::new(downstream));
+        store.stash("abcd".to_string(), state());
+
+        assert!(Downstream::try_resume_session(downstream.clone(), "abcd".to_string()).unwrap());
+        let nominal = || channel.safe_lock(|c| c.channel_nominal_hashrate).unwrap();
+        assert_eq!(nominal(), 6_000.0);
+        // the session resumed already added its hashrate
+        Downstream::remove_miner_hashrate_from_channel(downstream).unwrap();
+        assert_eq!(nominal(), 5_000.0);
+    }
+
+    #[test]
+    fn leaves_sessions_a_panic_poisoned_out_of_the_snapshot() {
+        let (submissions, _rx_submissions) =
+            SubmissionPipeline::new(&SubmissionPipelineConfig::default());
+        let downstream = Downstream::new(
+            1,
+            vec!["worker.1".to_string()],
+            vec![],
+            None,
+            None,
+            submissions,
+            async_channel::unbounded().0,
+            false,
+            0,
+            DownstreamDifficultyConfig::new(10.0, 6.0, 0, 0),
+            Arc::new(Mutex::new(UpstreamDifficultyConfig::new(
+                60, 5_000.0, 0, false,
+            ))),
+            "0".to_string(),
+        );
+        let store = downstream.session_store.clone();
+        let downstream = Arc::new(Mutex::new(downstream));
+        store.track(1, downstream.clone());
+        assert_eq!(store.save().as_object().unwrap().len(), 1);
+
+        let poisoning = downstream.clone();
+        let panicked = std::thread::spawn(move || {
+            let _ = poisoning.safe_lock(|d| {
+                d.authorized_names.clear();
+                panic!("a connection handler panicked")
+            });
+        })
+        .join();
+        assert!(panicked.is_err());
+        assert!(Downstream::stash_session(downstream).is_err());
+        assert!(store.save().as_object().unwrap().is_empty());
+    }
+}
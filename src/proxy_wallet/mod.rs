@@ -1,12 +1,7 @@
 use async_channel::{bounded, unbounded};
 use futures::FutureExt;
-use rand::Rng;
 pub use roles_logic_sv2::utils::Mutex;
-use std::{
-    net::{IpAddr, SocketAddr},
-    str::FromStr,
-    sync::Arc,
-};
+use std::{sync::Arc, time::Duration};
 
 pub use sv1_api::server_to_client;
 use tokio::{
@@ -17,8 +12,19 @@ use tokio_util::sync::CancellationToken;
 use tracing::{debug, error, info, warn};
 
 use proxy_config::ProxyConfig;
+use upstream_sv2::discovery::Endpoint;
 
-use crate::status::{self, State, Status};
+use crate::{
+    error::{Error, ProxyResult},
+    net,
+    ratelimit::RateLimiter,
+    sim, snapshot,
+    status::{
+        self, diagnostics,
+        events::{self, Event, Upstream},
+        State, Status,
+    },
+};
 
 pub mod downstream_sv1;
 pub mod proxy;
@@ -26,6 +32,13 @@ pub mod proxy_config;
 pub mod upstream_sv2;
 pub mod utils;
 
+/// How long the translator waits for the shares queued to be sent to the pool on shutdown.
+pub const SHUTDOWN_FLUSH_TIMEOUT: Duration = Duration::from_secs(10);
+/// Channels shares are queued in on their way to the pool, see `crate::status::diagnostics`.
+const SHARE_QUEUES: [&str; 2] = ["translator_submissions", "translator_upstream"];
+
+/// The translator: serves SV1 miners, translating their work to and from the SV2 pool it mines
+/// on.
 #[derive(Clone, Debug)]
 pub struct TranslatorSv2 {
     config: ProxyConfig,
@@ -34,17 +47,17 @@ pub struct TranslatorSv2 {
 }
 
 impl TranslatorSv2 {
+    /// The translator of `config`, stopping once `cancel_token` is cancelled.
     pub fn new(config: ProxyConfig, cancel_token: CancellationToken) -> Self {
-        let mut rng = rand::thread_rng();
-        let wait_time = rng.gen_range(0..=3000);
         Self {
             config,
-            reconnect_wait_time: wait_time,
+            reconnect_wait_time: sim::random_up_to(3000),
             cancel_token,
         }
     }
 
-    pub async fn start(self) {
+    /// Runs the translator, reconnecting to the pool, until it is cancelled or fails.
+    pub async fn start(self) -> ProxyResult<()> {
         let (tx_status, rx_status) = unbounded();
 
         let target = Arc::new(Mutex::new(vec![0; 32]));
@@ -53,18 +66,29 @@ impl TranslatorSv2 {
         let (tx_sv1_notify, _rx_sv1_notify): (
             broadcast::Sender<server_to_client::Notify>,
             broadcast::Receiver<server_to_client::Notify>,
-        ) = broadcast::channel(10);
+        ) = broadcast::channel(self.config.channels.notify.max(1));
 
         let task_collector: Arc<Mutex<Vec<(AbortHandle, String)>>> =
             Arc::new(Mutex::new(Vec::new()));
 
-        self.internal_start(
+        // Outlives upstream reconnects so miners can resume their sessions across them
+        let session_store = Arc::new(downstream_sv1::SessionStore::new());
+        snapshot::register("sessions", session_store.clone());
+
+        let starting = self.internal_start(
             tx_sv1_notify.clone(),
             target.clone(),
             tx_status.clone(),
+            session_store.clone(),
             task_collector.clone(),
-        )
-        .await;
+        );
+        // cancelled while retrying the pool, no miner is connected yet
+        let Some(started) = self.cancel_token.run_until_cancelled(starting).await else {
+            info!("Cancelled before the pool connected");
+            kill_tasks(task_collector);
+            return Ok(());
+        };
+        started?;
 
         debug!("Starting up signal listener");
         let task_collector_ = task_collector.clone();
@@ -81,36 +105,54 @@ impl TranslatorSv2 {
                         // Should only be sent by the downstream listener
                         State::DownstreamShutdown(err) => {
                             error!("SHUTDOWN from: {}", err);
-                            break;
+                            return Err(err);
                         }
                         State::BridgeShutdown(err) => {
                             error!("SHUTDOWN from: {}", err);
-                            break;
+                            return Err(err);
                         }
                         State::UpstreamShutdown(err) => {
                             error!("SHUTDOWN from: {}", err);
-                            break;
+                            events::publish(Event::UpstreamDown {
+                                upstream: Upstream::Pool,
+                                reason: err.to_string(),
+                            });
+                            return Err(err);
                         }
                         State::UpstreamTryReconnect(err) => {
                             error!("Trying to reconnect the Upstream because of: {}", err);
+                            events::publish(Event::UpstreamDown {
+                                upstream: Upstream::Pool,
+                                reason: err.to_string(),
+                            });
 
-                            // wait a random amount of time between 0 and 3000ms
-                            // if all the downstreams try to reconnect at the same time, the upstream may
-                            // fail
-                            tokio::time::sleep(std::time::Duration::from_millis(wait_time)).await;
-
-                            // kill al the tasks
-                            let task_collector_aborting = task_collector_.clone();
-                            kill_tasks(task_collector_aborting.clone());
-
-                            warn!("Trying reconnecting to upstream");
-                            self.internal_start(
-                                tx_sv1_notify.clone(),
-                                target.clone(),
-                                tx_status.clone(),
-                                task_collector_.clone(),
-                            )
-                            .await;
+                            let reconnecting = async {
+                                // wait a random amount of time between 0 and 3000ms
+                                // if all the downstreams try to reconnect at the same time, the
+                                // upstream may fail
+                                tokio::time::sleep(Duration::from_millis(wait_time)).await;
+
+                                // kill al the tasks
+                                kill_tasks(task_collector_.clone());
+
+                                warn!("Trying reconnecting to upstream");
+                                self.internal_start(
+                                    tx_sv1_notify.clone(),
+                                    target.clone(),
+                                    tx_status.clone(),
+                                    session_store.clone(),
+                                    task_collector_.clone(),
+                                )
+                                .await
+                            };
+                            match cancel_token.run_until_cancelled(reconnecting).await {
+                                Some(reconnected) => reconnected?,
+                                None => {
+                                    info!("Cancelled while reconnecting, shutting down...");
+                                    shut_down(task_collector_.clone()).await;
+                                    return Ok(());
+                                }
+                            }
                         }
                         State::Healthy(msg) => {
                             info!("HEALTHY message: {}", msg);
@@ -122,7 +164,8 @@ impl TranslatorSv2 {
                 },
                 _ = cancel_token.cancelled() => {
                     info!("Cancellation token triggered, shutting down...");
-                    break;
+                    shut_down(task_collector_.clone()).await;
+                    return Ok(());
                 }
             }
         }
@@ -132,24 +175,25 @@ impl TranslatorSv2 {
         &self,
         tx_sv1_notify: broadcast::Sender<server_to_client::Notify<'static>>,
         target: Arc<Mutex<Vec<u8>>>,
-        tx_status: async_channel::Sender<Status<'static>>,
+        tx_status: async_channel::Sender<Status>,
+        session_store: Arc<downstream_sv1::SessionStore>,
         task_collector: Arc<Mutex<Vec<(AbortHandle, String)>>>,
-    ) {
+    ) -> ProxyResult<()> {
         let proxy_config = self.config.clone();
         // Sender/Receiver to send a SV2 `SubmitSharesExtended` from the `Bridge` to the `Upstream`
         // (Sender<SubmitSharesExtended<'static>>, Receiver<SubmitSharesExtended<'static>>)
-        let (tx_sv2_submit_shares_ext, rx_sv2_submit_shares_ext) = bounded(10);
+        let channels = &proxy_config.channels;
+        let (tx_sv2_submit_shares_ext, rx_sv2_submit_shares_ext) = bounded(channels.shares.max(1));
 
-        // `tx_sv1_bridge` sender is used by `Downstream` to send a `DownstreamMessages` message to
-        // `Bridge` via the `rx_sv1_downstream` receiver
-        // (Sender<downstream_sv1::DownstreamMessages>,
-        // Receiver<downstream_sv1::DownstreamMessages>)
-        let (tx_sv1_bridge, rx_sv1_downstream) = unbounded();
+        // `submissions` is the bounded pipeline used by `Downstream` to send a `DownstreamMessages`
+        // message to `Bridge` via the `rx_sv1_downstream` receiver
+        let (submissions, rx_sv1_downstream) =
+            downstream_sv1::SubmissionPipeline::new(&proxy_config.submission_pipeline);
 
         // Sender/Receiver to send a SV2 `NewExtendedMiningJob` message from the `Upstream` to the
         // `Bridge`
         // (Sender<NewExtendedMiningJob<'static>>, Receiver<NewExtendedMiningJob<'static>>)
-        let (tx_sv2_new_ext_mining_job, rx_sv2_new_ext_mining_job) = bounded(10);
+        let (tx_sv2_new_ext_mining_job, rx_sv2_new_ext_mining_job) = bounded(channels.jobs.max(1));
 
         // Sender/Receiver to send a new extranonce from the `Upstream` to this `main` function to
         // be passed to the `Downstream` upon a Downstream role connection
@@ -158,22 +202,20 @@ impl TranslatorSv2 {
 
         // Sender/Receiver to send a SV2 `SetNewPrevHash` message from the `Upstream` to the
         // `Bridge` (Sender<SetNewPrevHash<'static>>, Receiver<SetNewPrevHash<'static>>)
-        let (tx_sv2_set_new_prev_hash, rx_sv2_set_new_prev_hash) = bounded(10);
+        let (tx_sv2_set_new_prev_hash, rx_sv2_set_new_prev_hash) = bounded(channels.jobs.max(1));
+        let outgoing_capacity = channels.downstream_outgoing.max(1);
 
-        // Format `Upstream` connection address
-        let upstream_addr = SocketAddr::new(
-            IpAddr::from_str(&proxy_config.upstream_address)
-                .expect("Failed to parse upstream address!"),
-            proxy_config.upstream_port,
-        );
+        // `Upstream` connection address, a hostname or an IP address, or an SRV record
+        let upstream_addr = Endpoint::new(&proxy_config);
 
         let diff_config = Arc::new(Mutex::new(proxy_config.upstream_difficulty_config.clone()));
         let task_collector_upstream = task_collector.clone();
         // Instantiate a new `Upstream` (SV2 Pool)
         debug!("creating upstream");
         let upstream = match upstream_sv2::Upstream::new(
-            upstream_addr,
+            &upstream_addr,
             proxy_config.upstream_authority_pubkey,
+            proxy_config.upstream_next_authority_pubkey,
             rx_sv2_submit_shares_ext,
             tx_sv2_set_new_prev_hash,
             tx_sv2_new_ext_mining_job,
@@ -183,22 +225,26 @@ impl TranslatorSv2 {
             target.clone(),
             diff_config.clone(),
             task_collector_upstream,
+            proxy_config.payout_tokens_path.clone(),
+            &proxy_config.upstream_retry,
         )
         .await
         {
             Ok(upstream) => upstream,
             Err(e) => {
                 error!("Failed to create upstream: {}", e);
-                return;
+                return Err(e);
             }
         };
         debug!("upstream created");
         let task_collector_init_task = task_collector.clone();
+        let cancel_token = self.cancel_token.clone();
+        let init_cancel_token = cancel_token.clone();
         // Spawn a task to do all of this init work so that the main thread
         // can listen for signals and failures on the status channel. This
         // allows for the tproxy to fail gracefully if any of these init tasks
         //fail
-        let task = task::spawn(async move {
+        let init = async move {
             // Connect to the SV2 Upstream role
             match upstream_sv2::Upstream::connect(
                 upstream.clone(),
@@ -207,24 +253,30 @@ impl TranslatorSv2 {
             )
             .await
             {
-                Ok(_) => info!("Connected to Upstream!"),
+                Ok(_) => {
+                    info!("Connected to Upstream!");
+                    events::publish(Event::UpstreamConnected {
+                        upstream: Upstream::Pool,
+                        address: upstream_addr.to_string(),
+                    });
+                }
                 Err(e) => {
                     error!("Failed to connect to Upstream EXITING! : {}", e);
-                    return;
+                    return init_failed(&tx_status, e).await;
                 }
             }
 
             // Start receiving messages from the SV2 Upstream role
             if let Err(e) = upstream_sv2::Upstream::parse_incoming(upstream.clone()) {
                 error!("failed to create sv2 parser: {}", e);
-                return;
+                return init_failed(&tx_status, e).await;
             }
 
             debug!("Finished starting upstream listener");
             // Start task handler to receive submits from the SV1 Downstream role once it connects
             if let Err(e) = upstream_sv2::Upstream::handle_submit(upstream.clone()) {
                 error!("Failed to create submit handler: {}", e);
-                return;
+                return init_failed(&tx_status, e).await;
             }
 
             // Receive the extranonce information from the Upstream role to send to the Downstream
@@ -252,39 +304,81 @@ impl TranslatorSv2 {
                 up_id,
                 task_collector_bridge,
             );
-            proxy::Bridge::start(b.clone());
+            proxy::Bridge::start(b.clone(), cancel_token.clone());
 
             // Format `Downstream` connection address
-            let downstream_addr = SocketAddr::new(
-                IpAddr::from_str(&proxy_config.downstream_address).unwrap(),
+            let downstream_addr = net::host_port(
+                &proxy_config.downstream_address,
                 proxy_config.downstream_port,
             );
 
             info!("Starting proxy server:");
             info!("  - Downstream (miners) listening on: {}", downstream_addr);
-            info!(
-                "  - Upstream (pool) connecting to: {}:{}",
-                proxy_config.upstream_address, proxy_config.upstream_port
-            );
+            info!("  - Upstream (pool) connecting to: {}", upstream_addr);
 
             let task_collector_downstream = task_collector_init_task.clone();
+            let limiter = Arc::new(RateLimiter::new("translator", &proxy_config.rate_limits));
             // Accept connections from one or more SV1 Downstream roles (SV1 Mining Devices)
             downstream_sv1::Downstream::accept_connections(
                 downstream_addr,
-                tx_sv1_bridge,
+                submissions,
                 tx_sv1_notify,
                 status::Sender::DownstreamListener(tx_status.clone()),
                 b,
                 proxy_config.downstream_difficulty_config,
                 diff_config,
+                session_store,
+                outgoing_capacity,
                 task_collector_downstream,
+                limiter,
+                cancel_token,
             );
-        }); // End of init task
+        }; // End of init task
+        let task = task::spawn(async move { init_cancel_token.run_until_cancelled(init).await });
         let _ =
             task_collector.safe_lock(|t| t.push((task.abort_handle(), "init task".to_string())));
+        Ok(())
     }
 }
 
+/// Hands an error of the init task to `start`, which fails with it.
+async fn init_failed(tx_status: &async_channel::Sender<Status>, e: Error) {
+    let status = Status {
+        state: State::UpstreamShutdown(e),
+    };
+    let _ = tx_status.send(status).await;
+}
+
+/// Stops accepting miners, then waits `SHUTDOWN_FLUSH_TIMEOUT` at most for the shares they
+/// queued to be sent to the pool before closing their connections, see `crate::shutdown`.
+async fn shut_down(task_collector: Arc<Mutex<Vec<(AbortHandle, String)>>>) {
+    let _ = task_collector.safe_lock(|t| {
+        for (handle, name) in t.iter() {
+            if name == "accept_connections" {
+                handle.abort();
+            }
+        }
+    });
+    let queued = || {
+        diagnostics::channels()
+            .iter()
+            .filter(|(name, _)| SHARE_QUEUES.contains(&name.as_str()))
+            .map(|(_, stats)| stats.len)
+            .sum::<usize>()
+    };
+    info!("Sending the {} shares queued to the pool", queued());
+    let flushed = tokio::time::timeout(SHUTDOWN_FLUSH_TIMEOUT, async {
+        while queued() > 0 {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await;
+    if flushed.is_err() {
+        warn!("{} shares still queued after draining", queued());
+    }
+    kill_tasks(task_collector);
+}
+
 fn kill_tasks(task_collector: Arc<Mutex<Vec<(AbortHandle, String)>>>) {
     let _ = task_collector.safe_lock(|t| {
         while let Some(handle) = t.pop() {
@@ -293,3 +387,25 @@ fn kill_tasks(task_collector: Arc<Mutex<Vec<(AbortHandle, String)>>>) {
         }
     });
 }
+
+#[cfg(all(test, feature = "pool"))]
+mod test {
+    use super::*;
+    use crate::configuration::{create_default_pool_config, create_default_proxy_config};
+
+    #[tokio::test]
+    async fn stops_when_cancelled_while_retrying_the_pool() {
+        let mut config = create_default_proxy_config(&create_default_pool_config());
+        // nothing listens there, the pool is retried forever
+        let unused = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        config.upstream_port = unused.local_addr().unwrap().port();
+        drop(unused);
+        let cancel_token = CancellationToken::new();
+        let running = tokio::spawn(TranslatorSv2::new(config, cancel_token.clone()).start());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!running.is_finished());
+        cancel_token.cancel();
+        let stopped = tokio::time::timeout(Duration::from_millis(500), running).await;
+        assert!(matches!(stopped, Ok(Ok(Ok(())))));
+    }
+}
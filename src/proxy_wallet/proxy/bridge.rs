@@ -10,18 +10,25 @@ use roles_logic_sv2::{
 use std::sync::Arc;
 use sv1_api::{client_to_server::Submit, server_to_client, utils::HexU32Be};
 use tokio::{sync::broadcast, task::AbortHandle};
+use tokio_util::sync::CancellationToken;
 
 use super::super::{
-    downstream_sv1::{DownstreamMessages, SetDownstreamTarget, SubmitShareWithChannelId},
-    status,
+    downstream_sv1::{
+        DownstreamMessages, SetDownstreamTarget, SubmissionReceiver, SubmitShareWithChannelId,
+    },
+    status::{self, heartbeat::Heartbeat},
 };
-use crate::error::{
-    Error::{self, PoisonLock},
-    ProxyResult,
+use crate::{
+    error::{
+        Error::{self, PoisonLock},
+        ProxyResult,
+    },
+    logging,
+    status::diagnostics::ChannelProbe,
 };
 use error_handling::handle_result;
 use roles_logic_sv2::{channel_logic::channel_factory::OnNewShare, Error as RolesLogicError};
-use tracing::{debug, error, info, warn};
+use tracing::{debug, error, info, info_span, warn, Instrument};
 
 /// Bridge between the SV2 `Upstream` and SV1 `Downstream` responsible for the following messaging
 /// translation:
@@ -30,10 +37,12 @@ use tracing::{debug, error, info, warn};
 #[derive(Debug)]
 pub struct Bridge {
     /// Receives a SV1 `mining.submit` message from the Downstream role.
-    rx_sv1_downstream: Receiver<DownstreamMessages>,
+    rx_sv1_downstream: SubmissionReceiver,
     /// Sends SV2 `SubmitSharesExtended` messages translated from SV1 `mining.submit` messages to
     /// the `Upstream`.
     tx_sv2_submit_shares_ext: Sender<SubmitSharesExtended<'static>>,
+    /// Watches `tx_sv2_submit_shares_ext` as `translator_upstream`, see `status::diagnostics`.
+    _submits_probe: ChannelProbe,
     /// Receives a SV2 `SetNewPrevHash` message from the `Upstream` to be translated (along with a
     /// SV2 `NewExtendedMiningJob` message) to a SV1 `mining.submit` for the `Downstream`.
     rx_sv2_set_new_prev_hash: Receiver<SetNewPrevHash<'static>>,
@@ -70,7 +79,7 @@ impl Bridge {
     #[allow(clippy::too_many_arguments)]
     /// Instantiate a new `Bridge`.
     pub fn new(
-        rx_sv1_downstream: Receiver<DownstreamMessages>,
+        rx_sv1_downstream: SubmissionReceiver,
         tx_sv2_submit_shares_ext: Sender<SubmitSharesExtended<'static>>,
         rx_sv2_set_new_prev_hash: Receiver<SetNewPrevHash<'static>>,
         rx_sv2_new_ext_mining_job: Receiver<NewExtendedMiningJob<'static>>,
@@ -88,6 +97,7 @@ impl Bridge {
         let upstream_target: Target = upstream_target.into();
         Arc::new(Mutex::new(Self {
             rx_sv1_downstream,
+            _submits_probe: ChannelProbe::new("translator_upstream", &tx_sv2_submit_shares_ext),
             tx_sv2_submit_shares_ext,
             rx_sv2_set_new_prev_hash,
             rx_sv2_new_ext_mining_job,
@@ -113,10 +123,7 @@ impl Bridge {
     }
 
     #[allow(clippy::result_large_err)]
-    pub fn on_new_sv1_connection(
-        &mut self,
-        hash_rate: f32,
-    ) -> ProxyResult<'static, OpenSv1Downstream> {
+    pub fn on_new_sv1_connection(&mut self, hash_rate: f32) -> ProxyResult<OpenSv1Downstream> {
         match self.channel_factory.new_extended_channel(0, hash_rate, 0) {
             Ok(messages) => {
                 for message in messages {
@@ -154,10 +161,11 @@ impl Bridge {
     }
 
     /// Starts the tasks that receive SV1 and SV2 messages to be translated and sent to their
-    /// respective roles.
-    pub fn start(self_: Arc<Mutex<Self>>) {
-        Self::handle_new_prev_hash(self_.clone());
-        Self::handle_new_extended_mining_job(self_.clone());
+    /// respective roles. Jobs stop being sent to the miners once `cancel_token` is cancelled, their
+    /// shares are still forwarded until the translator is done draining.
+    pub fn start(self_: Arc<Mutex<Self>>, cancel_token: CancellationToken) {
+        Self::handle_new_prev_hash(self_.clone(), cancel_token.clone());
+        Self::handle_new_extended_mining_job(self_.clone(), cancel_token);
         Self::handle_downstream_messages(self_);
     }
 
@@ -170,14 +178,23 @@ impl Bridge {
             .safe_lock(|s| (s.rx_sv1_downstream.clone(), s.tx_status.clone()))
             .unwrap();
         let handle_downstream = tokio::task::spawn(async move {
+            let heartbeat = Heartbeat::start("translator_bridge");
             loop {
-                let msg = handle_result!(tx_status, rx_sv1_downstream.clone().recv().await);
+                let msg =
+                    handle_result!(tx_status, heartbeat.beating(rx_sv1_downstream.recv()).await);
 
                 match msg {
                     DownstreamMessages::SubmitShares(share) => {
+                        let span = info_span!(
+                            "submit_share",
+                            channel_id = share.channel_id,
+                            share_id = %logging::share_id(share.share.nonce.0, share.share.time.0)
+                        );
                         handle_result!(
                             tx_status,
-                            Self::handle_submit_shares(self_.clone(), share).await
+                            Self::handle_submit_shares(self_.clone(), share)
+                                .instrument(span)
+                                .await
                         );
                     }
                     DownstreamMessages::SetDownstreamTarget(new_target) => {
@@ -201,7 +218,7 @@ impl Bridge {
     fn handle_update_downstream_target(
         self_: Arc<Mutex<Self>>,
         new_target: SetDownstreamTarget,
-    ) -> ProxyResult<'static, ()> {
+    ) -> ProxyResult<()> {
         self_
             .safe_lock(|b| {
                 b.channel_factory
@@ -211,11 +228,11 @@ impl Bridge {
         Ok(())
     }
     /// receives a `SubmitShareWithChannelId` and validates the shares and sends to `Upstream` if
-    /// the share meets the upstream target
+    /// the share meets the upstream target, within the `submit_share` span of the caller
     async fn handle_submit_shares(
         self_: Arc<Mutex<Self>>,
         share: SubmitShareWithChannelId,
-    ) -> ProxyResult<'static, ()> {
+    ) -> ProxyResult<()> {
         let (tx_sv2_submit_shares_ext, target_mutex, tx_status) = self_
             .safe_lock(|s| {
                 (
@@ -226,22 +243,22 @@ impl Bridge {
             })
             .map_err(|_| PoisonLock)?;
         let upstream_target: [u8; 32] = target_mutex
-            .safe_lock(|t| t.clone())
-            .map_err(|_| PoisonLock)?
-            .try_into()?;
+            .safe_lock(|t| <[u8; 32]>::try_from(&t[..]).map_err(|_| t.clone()))
+            .map_err(|_| PoisonLock)??;
         let mut upstream_target: Target = upstream_target.into();
-        self_
-            .safe_lock(|s| s.channel_factory.set_target(&mut upstream_target))
-            .map_err(|_| PoisonLock)?;
 
-        let sv2_submit = self_
+        // translated and checked under a single lock, the share is moved all the way through
+        let checked = self_
             .safe_lock(|s| {
+                s.channel_factory.set_target(&mut upstream_target);
                 s.translate_submit(share.channel_id, share.share, share.version_rolling_mask)
+                    .map(|sv2_submit| s.channel_factory.on_submit_shares_extended(sv2_submit))
             })
-            .map_err(|_| PoisonLock)??;
-        let res = self_
-            .safe_lock(|s| s.channel_factory.on_submit_shares_extended(sv2_submit))
             .map_err(|_| PoisonLock);
+        let res = match checked {
+            Ok(checked) => Ok(checked?),
+            Err(e) => Err(e),
+        };
 
         match res {
             Ok(Ok(OnNewShare::SendErrorDownstream(e))) => {
@@ -286,36 +303,19 @@ impl Bridge {
         channel_id: u32,
         sv1_submit: Submit,
         version_rolling_mask: Option<HexU32Be>,
-    ) -> ProxyResult<'static, SubmitSharesExtended<'static>> {
+    ) -> ProxyResult<SubmitSharesExtended<'static>> {
         let last_version = self
             .channel_factory
             .last_valid_job_version()
             .ok_or(Error::RolesSv2Logic(RolesLogicError::NoValidJob))?;
-        let version = match (sv1_submit.version_bits, version_rolling_mask) {
-            // regarding version masking see https://github.com/slushpool/stratumprotocol/blob/master/stratum-extensions.mediawiki#changes-in-request-miningsubmit
-            (Some(vb), Some(mask)) => (last_version & !mask.0) | (vb.0 & mask.0),
-            (None, None) => last_version,
-            _ => return Err(Error::V1Protocol(sv1_api::error::Error::InvalidSubmission)),
-        };
-        let mining_device_extranonce: Vec<u8> = sv1_submit.extra_nonce2.into();
-        let extranonce2 = mining_device_extranonce;
-        Ok(SubmitSharesExtended {
-            channel_id,
-            // I put 0 below cause sequence_number is not what should be TODO
-            sequence_number: 0,
-            job_id: sv1_submit.job_id.parse::<u32>()?,
-            nonce: sv1_submit.nonce.0,
-            ntime: sv1_submit.time.0,
-            version,
-            extranonce: extranonce2.try_into()?,
-        })
+        translate_submit(channel_id, sv1_submit, version_rolling_mask, last_version)
     }
 
     async fn handle_new_prev_hash_(
         self_: Arc<Mutex<Self>>,
         sv2_set_new_prev_hash: SetNewPrevHash<'static>,
         tx_sv1_notify: broadcast::Sender<server_to_client::Notify<'static>>,
-    ) -> Result<(), Error<'static>> {
+    ) -> Result<(), Error> {
         while !crate::proxy_wallet::upstream_sv2::upstream::IS_NEW_JOB_HANDLED
             .load(std::sync::atomic::Ordering::SeqCst)
         {
@@ -376,7 +376,7 @@ impl Bridge {
     /// that before every received `SetNewPrevHash`, a `NewExtendedMiningJob` with a
     /// corresponding `job_id` has already been received. If this is not the case, an error has
     /// occurred on the Upstream pool role and the connection will close.
-    fn handle_new_prev_hash(self_: Arc<Mutex<Self>>) {
+    fn handle_new_prev_hash(self_: Arc<Mutex<Self>>, cancel_token: CancellationToken) {
         let task_collector_handle_new_prev_hash =
             self_.safe_lock(|b| b.task_collector.clone()).unwrap();
         let (tx_sv1_notify, rx_sv2_set_new_prev_hash, tx_status) = self_
@@ -389,11 +389,11 @@ impl Bridge {
             })
             .unwrap();
         debug!("Starting handle_new_prev_hash task");
-        let handle_new_prev_hash = tokio::task::spawn(async move {
+        let handling = async move {
             loop {
                 // Receive `SetNewPrevHash` from `Upstream`
                 let sv2_set_new_prev_hash: SetNewPrevHash =
-                    handle_result!(tx_status, rx_sv2_set_new_prev_hash.clone().recv().await);
+                    handle_result!(tx_status, rx_sv2_set_new_prev_hash.recv().await);
                 debug!(
                     "handle_new_prev_hash job_id: {:?}",
                     &sv2_set_new_prev_hash.job_id
@@ -408,7 +408,9 @@ impl Bridge {
                     .await
                 )
             }
-        });
+        };
+        let handle_new_prev_hash =
+            tokio::task::spawn(async move { cancel_token.run_until_cancelled(handling).await });
         let _ = task_collector_handle_new_prev_hash.safe_lock(|a| {
             a.push((
                 handle_new_prev_hash.abort_handle(),
@@ -421,12 +423,12 @@ impl Bridge {
         self_: Arc<Mutex<Self>>,
         sv2_new_extended_mining_job: NewExtendedMiningJob<'static>,
         tx_sv1_notify: broadcast::Sender<server_to_client::Notify<'static>>,
-    ) -> Result<(), Error<'static>> {
+    ) -> Result<(), Error> {
         // convert to non segwit jobs so we don't have to depend if miner's support segwit or not
         self_
             .safe_lock(|s| {
                 s.channel_factory
-                    .on_new_extended_mining_job(sv2_new_extended_mining_job.as_static().clone())
+                    .on_new_extended_mining_job(sv2_new_extended_mining_job.clone())
             })
             .map_err(|_| PoisonLock)??;
 
@@ -478,7 +480,7 @@ impl Bridge {
     /// `Downstream`. If `future_job=false` but this job's `job_id` does not match the current SV2
     /// `SetNewPrevHash` `job_id`, an error has occurred on the Upstream pool role and the
     /// connection will close.
-    fn handle_new_extended_mining_job(self_: Arc<Mutex<Self>>) {
+    fn handle_new_extended_mining_job(self_: Arc<Mutex<Self>>, cancel_token: CancellationToken) {
         let task_collector_new_extended_mining_job =
             self_.safe_lock(|b| b.task_collector.clone()).unwrap();
         let (tx_sv1_notify, rx_sv2_new_ext_mining_job, tx_status) = self_
@@ -491,13 +493,11 @@ impl Bridge {
             })
             .unwrap();
         debug!("Starting handle_new_extended_mining_job task");
-        let handle_new_extended_mining_job = tokio::task::spawn(async move {
+        let handling = async move {
             loop {
                 // Receive `NewExtendedMiningJob` from `Upstream`
-                let sv2_new_extended_mining_job: NewExtendedMiningJob = handle_result!(
-                    tx_status.clone(),
-                    rx_sv2_new_ext_mining_job.clone().recv().await
-                );
+                let sv2_new_extended_mining_job: NewExtendedMiningJob =
+                    handle_result!(tx_status.clone(), rx_sv2_new_ext_mining_job.recv().await);
                 debug!(
                     "handle_new_extended_mining_job job_id: {:?}",
                     &sv2_new_extended_mining_job.job_id
@@ -514,7 +514,9 @@ impl Bridge {
                 crate::proxy_wallet::upstream_sv2::upstream::IS_NEW_JOB_HANDLED
                     .store(true, std::sync::atomic::Ordering::SeqCst);
             }
-        });
+        };
+        let handle_new_extended_mining_job =
+            tokio::task::spawn(async move { cancel_token.run_until_cancelled(handling).await });
         let _ = task_collector_new_extended_mining_job.safe_lock(|a| {
             a.push((
                 handle_new_extended_mining_job.abort_handle(),
@@ -531,6 +533,34 @@ pub struct OpenSv1Downstream {
     pub extranonce2_len: u16,
 }
 
+/// Translates a SV1 `mining.submit` message for a job of version `last_version` to a SV2
+/// `SubmitSharesExtended` message.
+#[allow(clippy::result_large_err)]
+pub fn translate_submit(
+    channel_id: u32,
+    sv1_submit: Submit,
+    version_rolling_mask: Option<HexU32Be>,
+    last_version: u32,
+) -> ProxyResult<SubmitSharesExtended<'static>> {
+    let version = match (sv1_submit.version_bits, version_rolling_mask) {
+        // regarding version masking see https://github.com/slushpool/stratumprotocol/blob/master/stratum-extensions.mediawiki#changes-in-request-miningsubmit
+        (Some(vb), Some(mask)) => (last_version & !mask.0) | (vb.0 & mask.0),
+        (None, None) => last_version,
+        _ => return Err(sv1_api::error::Error::InvalidSubmission.into()),
+    };
+    Ok(SubmitSharesExtended {
+        channel_id,
+        // I put 0 below cause sequence_number is not what should be TODO
+        sequence_number: 0,
+        job_id: sv1_submit.job_id.parse::<u32>()?,
+        nonce: sv1_submit.nonce.0,
+        ntime: sv1_submit.time.0,
+        version,
+        // the bytes of the SV1 line, moved rather than copied
+        extranonce: sv1_submit.extra_nonce2.0.into_static(),
+    })
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -540,10 +570,14 @@ mod test {
 
     pub mod test_utils {
         use super::*;
+        use crate::proxy_wallet::{
+            downstream_sv1::SubmissionPipeline,
+            proxy_config::{OverloadPolicy, SubmissionPipelineConfig},
+        };
 
         #[allow(dead_code)]
         pub struct BridgeInterface {
-            pub tx_sv1_submit: Sender<DownstreamMessages>,
+            pub submissions: SubmissionPipeline,
             pub rx_sv2_submit_shares_ext: Receiver<SubmitSharesExtended<'static>>,
             pub tx_sv2_set_new_prev_hash: Sender<SetNewPrevHash<'static>>,
             pub tx_sv2_new_ext_mining_job: Sender<NewExtendedMiningJob<'static>>,
@@ -553,7 +587,8 @@ mod test {
         pub fn create_bridge(
             extranonces: ExtendedExtranonce,
         ) -> (Arc<Mutex<Bridge>>, BridgeInterface) {
-            let (tx_sv1_submit, rx_sv1_submit) = bounded(1);
+            let (submissions, rx_sv1_submit) =
+                SubmissionPipeline::new(&SubmissionPipelineConfig::new(1, OverloadPolicy::Reject));
             let (tx_sv2_submit_shares_ext, rx_sv2_submit_shares_ext) = bounded(1);
             let (tx_sv2_set_new_prev_hash, rx_sv2_set_new_prev_hash) = bounded(1);
             let (tx_sv2_new_ext_mining_job, rx_sv2_new_ext_mining_job) = bounded(1);
@@ -564,7 +599,7 @@ mod test {
                 0, 0, 0, 0, 0, 0, 0,
             ];
             let interface = BridgeInterface {
-                tx_sv1_submit,
+                submissions,
                 rx_sv2_submit_shares_ext,
                 tx_sv2_set_new_prev_hash,
                 tx_sv2_new_ext_mining_job,
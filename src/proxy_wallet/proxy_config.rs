@@ -1,11 +1,24 @@
+use crate::{
+    pool_mint::mint::accounts::matches_pattern, ratelimit::RateLimitConfig, retry::RetryPolicy,
+};
 use key_utils::Secp256k1PublicKey;
-use serde::Deserialize;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::sync::Mutex;
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct ProxyConfig {
     pub upstream_address: String,
     pub upstream_port: u16,
+    /// SRV record listing the pools to mine on in place of `upstream_address`, resolved on every
+    /// attempt to connect, see `upstream_sv2::discovery`.
+    #[serde(default)]
+    pub upstream_srv: Option<String>,
     pub upstream_authority_pubkey: Secp256k1PublicKey,
+    /// Authority key the pool rotates to, trusted along with `upstream_authority_pubkey` so the
+    /// handshake succeeds on either side of the rotation.
+    #[serde(default)]
+    pub upstream_next_authority_pubkey: Option<Secp256k1PublicKey>,
     pub downstream_address: String,
     pub downstream_port: u16,
     pub max_supported_version: u16,
@@ -13,6 +26,20 @@ pub struct ProxyConfig {
     pub min_extranonce2_size: u16,
     pub downstream_difficulty_config: DownstreamDifficultyConfig,
     pub upstream_difficulty_config: UpstreamDifficultyConfig,
+    #[serde(default)]
+    pub submission_pipeline: SubmissionPipelineConfig,
+    #[serde(default)]
+    pub channels: ChannelCapacities,
+    /// File the tokens the pool pays out over stratum are appended to.
+    #[serde(default = "ProxyConfig::default_payout_tokens_path")]
+    pub payout_tokens_path: String,
+    /// How connecting to the pool is retried, see `crate::retry`. Forever by default.
+    #[serde(default = "RetryPolicy::forever")]
+    pub upstream_retry: RetryPolicy,
+    /// Rate limits of the SV1 miners, `rate_limits.translator` of the pool when running with it,
+    /// see `crate::ratelimit`.
+    #[serde(default)]
+    pub rate_limits: RateLimitConfig,
 }
 
 pub struct UpstreamConfig {
@@ -65,7 +92,9 @@ impl ProxyConfig {
         Self {
             upstream_address: upstream.address,
             upstream_port: upstream.port,
+            upstream_srv: None,
             upstream_authority_pubkey: upstream.authority_pubkey,
+            upstream_next_authority_pubkey: None,
             downstream_address: downstream.address,
             downstream_port: downstream.port,
             max_supported_version,
@@ -73,11 +102,102 @@ impl ProxyConfig {
             min_extranonce2_size,
             downstream_difficulty_config: downstream.difficulty_config,
             upstream_difficulty_config: upstream.difficulty_config,
+            submission_pipeline: SubmissionPipelineConfig::default(),
+            channels: ChannelCapacities::default(),
+            payout_tokens_path: Self::default_payout_tokens_path(),
+            upstream_retry: RetryPolicy::forever(),
+            rate_limits: RateLimitConfig::default(),
         }
     }
+
+    pub fn default_payout_tokens_path() -> String {
+        "payout_tokens.txt".to_string()
+    }
+}
+
+/// What the proxy does with a share when the downstream to upstream pipeline is full.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum OverloadPolicy {
+    /// Reject the share and raise the submitting miner's difficulty so it sends fewer shares.
+    #[default]
+    RaiseDifficulty,
+    /// Make room by dropping the queued share with the lowest difficulty, or the incoming share
+    /// if nothing queued is lower, rejecting the share dropped. Shares are answered once they
+    /// leave the queue, so only those the Bridge takes are accepted.
+    DropLowestDifficulty,
+    /// Reject the share back to the miner.
+    Reject,
+}
+
+/// Sizing and overload behavior of the queue carrying shares from the Downstreams to the Bridge.
+#[derive(Debug, Deserialize, Clone)]
+pub struct SubmissionPipelineConfig {
+    #[serde(default = "SubmissionPipelineConfig::default_capacity")]
+    pub capacity: usize,
+    #[serde(default)]
+    pub overload_policy: OverloadPolicy,
+}
+
+impl SubmissionPipelineConfig {
+    pub fn new(capacity: usize, overload_policy: OverloadPolicy) -> Self {
+        Self {
+            capacity,
+            overload_policy,
+        }
+    }
+
+    fn default_capacity() -> usize {
+        1024
+    }
+}
+
+impl Default for SubmissionPipelineConfig {
+    fn default() -> Self {
+        Self::new(Self::default_capacity(), OverloadPolicy::default())
+    }
 }
 
+/// Capacities of the channels between the tasks of the translator, other than the submission
+/// pipeline. Each tells what happens once it is full.
 #[derive(Debug, Deserialize, Clone)]
+pub struct ChannelCapacities {
+    /// `SubmitSharesExtended` from the Bridge to the Upstream. Once full the Bridge waits, so
+    /// shares back up into the submission pipeline and its overload policy applies.
+    #[serde(default = "ChannelCapacities::default_capacity")]
+    pub shares: usize,
+    /// `NewExtendedMiningJob` and `SetNewPrevHash` from the Upstream to the Bridge, each. Once full
+    /// the Upstream stops reading from the pool until the Bridge catches up.
+    #[serde(default = "ChannelCapacities::default_capacity")]
+    pub jobs: usize,
+    /// `mining.notify` broadcast to every Downstream. A Downstream more than this many jobs behind
+    /// skips to the newest one.
+    #[serde(default = "ChannelCapacities::default_capacity")]
+    pub notify: usize,
+    /// Lines queued for the socket of each Downstream. Once full its handler waits for the miner
+    /// to read them.
+    #[serde(default = "ChannelCapacities::default_capacity")]
+    pub downstream_outgoing: usize,
+}
+
+impl ChannelCapacities {
+    fn default_capacity() -> usize {
+        10
+    }
+}
+
+impl Default for ChannelCapacities {
+    fn default() -> Self {
+        Self {
+            shares: Self::default_capacity(),
+            jobs: Self::default_capacity(),
+            notify: Self::default_capacity(),
+            downstream_outgoing: Self::default_capacity(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct DownstreamDifficultyConfig {
     pub min_individual_miner_hashrate: f32,
     pub shares_per_minute: f32,
@@ -85,6 +205,9 @@ pub struct DownstreamDifficultyConfig {
     pub submits_since_last_update: u32,
     #[serde(default = "u64::default")]
     pub timestamp_of_last_update: u64,
+    /// Workers pinned to a fixed difficulty, exempt from vardiff. The first matching rule wins.
+    #[serde(default)]
+    pub pinned_workers: Vec<PinnedDifficulty>,
 }
 
 impl DownstreamDifficultyConfig {
@@ -99,9 +222,93 @@ impl DownstreamDifficultyConfig {
             shares_per_minute,
             submits_since_last_update,
             timestamp_of_last_update,
+            pinned_workers: vec![],
+        }
+    }
+
+    /// Returns the pinned difficulty for `worker_name` if any rule matches it, those pinned at
+    /// runtime by `pin_workers` first.
+    pub fn pinned_difficulty_for(&self, worker_name: &str) -> Option<f64> {
+        let pinned_at_runtime = PINNED_AT_RUNTIME
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .find(|p| p.matches(worker_name))
+            .map(|p| p.difficulty);
+        pinned_at_runtime.or_else(|| {
+            self.pinned_workers
+                .iter()
+                .find(|p| p.matches(worker_name))
+                .map(|p| p.difficulty)
+        })
+    }
+}
+
+/// Workers pinned through the control API, ahead of the `pinned_workers` of the config.
+static PINNED_AT_RUNTIME: Lazy<Mutex<Vec<PinnedDifficulty>>> = Lazy::new(|| Mutex::new(vec![]));
+
+/// Pins the workers matching `worker_pattern` to `difficulty`, replacing an earlier runtime pin of
+/// the same pattern, or drops that pin without `difficulty`. Connected workers pick the change up
+/// on their next job. Pins of the config can't be dropped at runtime.
+pub fn pin_workers(worker_pattern: String, difficulty: Option<f64>) -> Result<(), String> {
+    let pin = difficulty
+        .map(|difficulty| PinnedDifficulty::new(worker_pattern.clone(), difficulty))
+        .transpose()?;
+    let mut pins = PINNED_AT_RUNTIME.lock().unwrap_or_else(|e| e.into_inner());
+    pins.retain(|p| p.worker_pattern != worker_pattern);
+    // the latest first, so it wins over broader patterns pinned before
+    if let Some(pin) = pin {
+        pins.insert(0, pin);
+    }
+    Ok(())
+}
+
+/// The workers pinned at runtime by `pin_workers`, the first matching one winning.
+pub fn pinned_at_runtime() -> Vec<PinnedDifficulty> {
+    PINNED_AT_RUNTIME
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+}
+
+/// Pins every worker whose name matches `worker_pattern` to `difficulty`, a positive number. The
+/// pattern supports `*` as a wildcard for any number of characters, e.g. `testrig.*` or `*.s9`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PinnedDifficulty {
+    pub worker_pattern: String,
+    #[serde(deserialize_with = "PinnedDifficulty::deserialize_difficulty")]
+    pub difficulty: f64,
+}
+
+impl PinnedDifficulty {
+    pub fn new(worker_pattern: String, difficulty: f64) -> Result<Self, String> {
+        Ok(Self {
+            worker_pattern,
+            difficulty: Self::validate_difficulty(difficulty)?,
+        })
+    }
+
+    /// `difficulty` if it is a difficulty a miner can be set to: finite and above 0.
+    fn validate_difficulty(difficulty: f64) -> Result<f64, String> {
+        if difficulty.is_finite() && difficulty > 0.0 {
+            Ok(difficulty)
+        } else {
+            Err(format!(
+                "pinned difficulty must be a positive number, not {}",
+                difficulty
+            ))
         }
     }
+
+    fn deserialize_difficulty<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+        Self::validate_difficulty(f64::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+    }
+
+    pub fn matches(&self, worker_name: &str) -> bool {
+        matches_pattern(&self.worker_pattern, worker_name)
+    }
 }
+
 impl PartialEq for DownstreamDifficultyConfig {
     fn eq(&self, other: &Self) -> bool {
         other.min_individual_miner_hashrate.round() as u32
@@ -14,7 +14,7 @@ use std::{sync::Arc, time::Duration};
 
 impl Upstream {
     /// this function checks if the elapsed time since the last update has surpassed the config
-    pub(super) async fn try_update_hashrate(self_: Arc<Mutex<Self>>) -> ProxyResult<'static, ()> {
+    pub(super) async fn try_update_hashrate(self_: Arc<Mutex<Self>>) -> ProxyResult<()> {
         let (channel_id_option, diff_mgmt, tx_frame) = self_
             .safe_lock(|u| {
                 (
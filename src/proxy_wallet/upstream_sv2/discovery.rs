@@ -0,0 +1,158 @@
+//! Discovery of the pool through a DNS SRV record (RFC 2782). With `upstream_srv` set, e.g. to
+//! `_sv2._tcp.pool.example.com`, the translator mines on the pools the record lists rather than on
+//! `upstream_address`, so the operator of the pool steers the translators from its DNS zone.
+//!
+//! The pools of the lowest priority are tried first, in a random order favouring those of the
+//! heaviest weight, then those of the next priority. The record is resolved again on every
+//! attempt to connect: every retry, and every reconnection once the pool is lost, follows the zone
+//! as it is then. A record whose only target is `.` says no pool serves there.
+use crate::{net, proxy_wallet::proxy_config::ProxyConfig, sim};
+use async_std::net::TcpStream;
+use hickory_resolver::TokioAsyncResolver;
+use std::{fmt, io};
+use tracing::{debug, info};
+
+/// Where the translator connects to the pool.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+    /// A `host:port`, see `crate::net`.
+    Address(String),
+    /// The name of an SRV record listing the pools, see the module.
+    Srv(String),
+}
+
+impl Endpoint {
+    pub fn new(config: &ProxyConfig) -> Self {
+        match &config.upstream_srv {
+            Some(name) => Self::Srv(name.clone()),
+            None => Self::Address(net::host_port(
+                &config.upstream_address,
+                config.upstream_port,
+            )),
+        }
+    }
+
+    /// A connection to the pool, to the first target of the SRV record accepting it.
+    pub async fn connect(&self) -> io::Result<TcpStream> {
+        let name = match self {
+            Self::Address(address) => return TcpStream::connect(address.as_str()).await,
+            Self::Srv(name) => name,
+        };
+        let targets = order(resolve(name).await?);
+        let mut last_error = None;
+        for target in targets {
+            let address = net::host_port(&target.host, target.port);
+            debug!("Connecting to {} of {}", address, name);
+            match TcpStream::connect(address.as_str()).await {
+                Ok(stream) => {
+                    info!("Connected to {} listed by {}", address, name);
+                    return Ok(stream);
+                }
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("{} lists no pool", name))
+        }))
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Address(address) => write!(f, "{}", address),
+            Self::Srv(name) => write!(f, "{} (SRV)", name),
+        }
+    }
+}
+
+/// A pool listed by an SRV record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+    pub priority: u16,
+    pub weight: u16,
+    pub host: String,
+    pub port: u16,
+}
+
+/// The targets of the SRV record `name`.
+async fn resolve(name: &str) -> io::Result<Vec<Target>> {
+    let resolver = TokioAsyncResolver::tokio_from_system_conf().map_err(io::Error::other)?;
+    let lookup = resolver.srv_lookup(name).await.map_err(io::Error::other)?;
+    let targets = lookup
+        .iter()
+        .map(|srv| Target {
+            priority: srv.priority(),
+            weight: srv.weight(),
+            host: srv.target().to_utf8().trim_end_matches('.').to_string(),
+            port: srv.port(),
+        })
+        // the target `.` is no pool
+        .filter(|target| !target.host.is_empty())
+        .collect();
+    Ok(targets)
+}
+
+/// `targets` in the order to try them: by priority, then at random by weight as RFC 2782 says.
+pub fn order(mut targets: Vec<Target>) -> Vec<Target> {
+    // those of no weight first, so they have a small chance to be picked first
+    targets.sort_by_key(|target| (target.priority, target.weight));
+    let mut ordered = Vec::with_capacity(targets.len());
+    for group in targets.chunk_by(|a, b| a.priority == b.priority) {
+        let mut group = group.to_vec();
+        while !group.is_empty() {
+            let total = group.iter().map(|target| target.weight as u64).sum();
+            let pick = sim::random_up_to(total);
+            let mut sum = 0;
+            let index = group
+                .iter()
+                .position(|target| {
+                    sum += target.weight as u64;
+                    sum >= pick
+                })
+                .unwrap_or(0);
+            ordered.push(group.remove(index));
+        }
+    }
+    ordered
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn target(priority: u16, weight: u16, host: &str) -> Target {
+        Target {
+            priority,
+            weight,
+            host: host.to_string(),
+            port: 34254,
+        }
+    }
+
+    #[test]
+    fn orders_by_priority_then_at_random_by_weight() {
+        let targets = vec![
+            target(20, 0, "backup"),
+            target(10, 0, "spare"),
+            target(10, 100, "main"),
+            target(10, 50, "second"),
+        ];
+        let mut main_first = 0;
+        let mut spare_first = 0;
+        for _ in 0..1000 {
+            let ordered = order(targets.clone());
+            let hosts: Vec<_> = ordered.iter().map(|t| t.host.as_str()).collect();
+            assert_eq!(ordered.len(), 4);
+            assert_eq!(hosts[3], "backup");
+            assert!(["spare", "main", "second"]
+                .iter()
+                .all(|h| hosts.contains(h)));
+            main_first += (hosts[0] == "main") as u32;
+            spare_first += (hosts[0] == "spare") as u32;
+        }
+        // two thirds of the time, the spare one time in 151
+        assert!((550..800).contains(&main_first), "{}", main_first);
+        assert!(spare_first < 50, "{}", spare_first);
+    }
+}
@@ -2,6 +2,7 @@ use codec_sv2::{StandardEitherFrame, StandardSv2Frame};
 use roles_logic_sv2::parsers::PoolMessages;
 
 pub mod diff_management;
+pub mod discovery;
 pub mod upstream;
 pub mod upstream_connection;
 pub use upstream::Upstream;
@@ -1,15 +1,20 @@
 use crate::error::{
-    Error::{CodecNoise, InvalidExtranonce, PoisonLock, UpstreamIncoming},
+    Error::{CodecNoise, Handshake, InvalidExtranonce, PoisonLock, Unreachable, UpstreamIncoming},
     ProxyResult,
 };
+use crate::logging;
+use crate::pool_mint::mint::{
+    nuts::Token,
+    payout::{self, MESSAGE_TYPE_PAYOUT, PAYOUT_EXTENSION_TYPE},
+};
 use crate::proxy_wallet::{
     downstream_sv1::Downstream,
     proxy_config::UpstreamDifficultyConfig,
     status,
-    upstream_sv2::{EitherFrame, Message, StdFrame, UpstreamConnection},
+    upstream_sv2::{discovery::Endpoint, EitherFrame, Message, StdFrame, UpstreamConnection},
 };
+use crate::retry::{Retry, RetryPolicy};
 use async_channel::{Receiver, Sender};
-use async_std::net::TcpStream;
 use binary_sv2::u256_from_int;
 use codec_sv2::{HandshakeRole, Initiator};
 use error_handling::handle_result;
@@ -34,14 +39,15 @@ use roles_logic_sv2::{
     Error::NoUpstreamsConnected,
 };
 use std::{
-    net::SocketAddr,
+    fs,
+    io::Write,
     sync::{atomic::AtomicBool, Arc},
 };
 use tokio::{
     task::AbortHandle,
     time::{sleep, Duration},
 };
-use tracing::{error, info, warn};
+use tracing::{error, info, info_span, warn, Instrument};
 
 use stratum_common::bitcoin::BlockHash;
 
@@ -102,6 +108,8 @@ pub struct Upstream {
     // than the configured percentage
     pub(super) difficulty_config: Arc<Mutex<UpstreamDifficultyConfig>>,
     task_collector: Arc<Mutex<Vec<(AbortHandle, String)>>>,
+    /// File the tokens paid out by the pool over the payout extension are appended to.
+    payout_tokens_path: String,
 }
 
 impl PartialEq for Upstream {
@@ -118,8 +126,9 @@ impl Upstream {
     /// from the `Downstream`.
     #[allow(clippy::too_many_arguments)]
     pub async fn new(
-        address: SocketAddr,
+        endpoint: &Endpoint,
         authority_public_key: Secp256k1PublicKey,
+        next_authority_public_key: Option<Secp256k1PublicKey>,
         rx_sv2_submit_shares_ext: Receiver<SubmitSharesExtended<'static>>,
         tx_sv2_set_new_prev_hash: Sender<SetNewPrevHash<'static>>,
         tx_sv2_new_ext_mining_job: Sender<NewExtendedMiningJob<'static>>,
@@ -129,34 +138,41 @@ impl Upstream {
         target: Arc<Mutex<Vec<u8>>>,
         difficulty_config: Arc<Mutex<UpstreamDifficultyConfig>>,
         task_collector: Arc<Mutex<Vec<(AbortHandle, String)>>>,
-    ) -> ProxyResult<'static, Arc<Mutex<Self>>> {
-        // Connect to the SV2 Upstream role retry connection every 5 seconds.
-        let socket = loop {
-            match TcpStream::connect(address).await {
-                Ok(socket) => break socket,
-                Err(e) => {
-                    error!(
-                        "Failed to connect to Upstream role at {}, retrying in 5s: {}",
-                        address, e
-                    );
-
-                    sleep(Duration::from_secs(5)).await;
+        payout_tokens_path: String,
+        retry: &RetryPolicy,
+    ) -> ProxyResult<Arc<Mutex<Self>>> {
+        // Connect to the SV2 Upstream role, retried as `retry` says, handshaking trusting the
+        // next authority key of the pool too if it rotates its key.
+        let mut channels = None;
+        for pub_key in std::iter::once(authority_public_key).chain(next_authority_public_key) {
+            let socket = Retry::new("pool", retry)
+                .run(|| endpoint.connect())
+                .await
+                .map_err(|e| Unreachable(e.to_string()))?;
+            let initiator = Initiator::from_raw_k(pub_key.into_bytes())?;
+
+            info!(
+                "PROXY SERVER - ACCEPTING FROM UPSTREAM: {}",
+                socket.peer_addr()?
+            );
+
+            // Channel to send and receive messages to the SV2 Upstream role
+            match Connection::new(socket, HandshakeRole::Initiator(initiator), 10).await {
+                Ok(connected) => {
+                    channels = Some(connected);
+                    break;
                 }
+                Err(e) => warn!(
+                    "Handshake with the pool trusting {} failed: {:?}",
+                    pub_key, e
+                ),
             }
+        }
+        let Some((receiver, sender)) = channels else {
+            return Err(Handshake(
+                "the pool certificate isn't signed by an authority key trusted".to_string(),
+            ));
         };
-
-        let pub_key: Secp256k1PublicKey = authority_public_key;
-        let initiator = Initiator::from_raw_k(pub_key.into_bytes())?;
-
-        info!(
-            "PROXY SERVER - ACCEPTING FROM UPSTREAM: {}",
-            socket.peer_addr()?
-        );
-
-        // Channel to send and receive messages to the SV2 Upstream role
-        let (receiver, sender) = Connection::new(socket, HandshakeRole::Initiator(initiator), 10)
-            .await
-            .unwrap();
         // Initialize `UpstreamConnection` with channel for SV2 Upstream role communication and
         // channel for downstream Translator Proxy communication
         let connection = UpstreamConnection { receiver, sender };
@@ -178,6 +194,7 @@ impl Upstream {
             target,
             difficulty_config,
             task_collector,
+            payout_tokens_path,
         })))
     }
 
@@ -186,7 +203,7 @@ impl Upstream {
         self_: Arc<Mutex<Self>>,
         min_version: u16,
         max_version: u16,
-    ) -> ProxyResult<'static, ()> {
+    ) -> ProxyResult<()> {
         // Get the `SetupConnection` message with Mining Device information (currently hard coded)
         let setup_connection = Self::get_setup_connection_message(min_version, max_version, false)?;
         let mut connection = self_
@@ -265,7 +282,7 @@ impl Upstream {
     /// Parses the incoming SV2 message from the Upstream role and routes the message to the
     /// appropriate handler.
     #[allow(clippy::result_large_err)]
-    pub fn parse_incoming(self_: Arc<Mutex<Self>>) -> ProxyResult<'static, ()> {
+    pub fn parse_incoming(self_: Arc<Mutex<Self>>) -> ProxyResult<()> {
         let clone = self_.clone();
         let task_collector = self_.safe_lock(|s| s.task_collector.clone()).unwrap();
         let collector1 = task_collector.clone();
@@ -277,6 +294,7 @@ impl Upstream {
             tx_sv2_set_new_prev_hash,
             recv,
             tx_status,
+            payout_tokens_path,
         ) = clone
             .safe_lock(|s| {
                 (
@@ -286,6 +304,7 @@ impl Upstream {
                     s.tx_sv2_set_new_prev_hash.clone(),
                     s.connection.receiver.clone(),
                     s.tx_status.clone(),
+                    s.payout_tokens_path.clone(),
                 )
             })
             .map_err(|_| PoisonLock)?;
@@ -314,14 +333,20 @@ impl Upstream {
                 let mut incoming: StdFrame = handle_result!(tx_status, incoming.try_into());
                 // On message receive, get the message type from the message header and get the
                 // message payload
-                let message_type = incoming.get_header().ok_or(crate::error::Error::FramingSv2(
+                let header = incoming.get_header().ok_or(crate::error::Error::FramingSv2(
                     framing_sv2::Error::ExpectedSv2Frame,
                 ));
-
-                let message_type = handle_result!(tx_status, message_type).msg_type();
+                let header = handle_result!(tx_status, header);
+                let message_type = header.msg_type();
 
                 let payload = incoming.payload();
 
+                // Payouts come with an extension message the mining handlers don't know
+                if header.ext_type() == PAYOUT_EXTENSION_TYPE {
+                    Self::receive_payout(&payout_tokens_path, message_type, payload);
+                    continue;
+                }
+
                 // Since this is not communicating with an SV2 proxy, but instead a custom SV1
                 // proxy where the routing logic is handled via the `Upstream`'s communication
                 // channels, we do not use the mining routing logic in the SV2 library and specify
@@ -452,10 +477,45 @@ impl Upstream {
 
         Ok(())
     }
+
+    /// Appends a token paid out by the pool to `path`, one per line, for the miner to redeem
+    /// with a Cashu wallet. Messages of the payout extension this proxy can't read are dropped.
+    fn receive_payout(path: &str, message_type: u8, payload: &[u8]) {
+        if message_type != MESSAGE_TYPE_PAYOUT {
+            warn!("Ignoring unknown payout message type {}", message_type);
+            return;
+        }
+        let Some((channel_id, token)) = payout::decode_payout_message(payload) else {
+            warn!("Ignoring malformed payout message");
+            return;
+        };
+        match Token::decode(&token) {
+            Ok(decoded) => info!(
+                "Received payout of {} {} on channel {}",
+                decoded.amount(),
+                decoded.unit.as_deref().unwrap_or_default(),
+                channel_id
+            ),
+            Err(e) => {
+                warn!("Ignoring payout with an invalid token: {}", e);
+                return;
+            }
+        }
+        let mut options = fs::OpenOptions::new();
+        options.append(true).create(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let written = options
+            .open(path)
+            .and_then(|mut file| writeln!(file, "{}", token));
+        if let Err(e) = written {
+            error!("Failed to save payout token to {}: {}", path, e);
+        }
+    }
     #[allow(clippy::result_large_err)]
     fn get_job_id(
         self_: &Arc<Mutex<Self>>,
-    ) -> Result<Result<u32, crate::error::Error<'static>>, crate::error::Error<'static>> {
+    ) -> Result<Result<u32, crate::error::Error>, crate::error::Error> {
         self_
             .safe_lock(|s| {
                 if s.is_work_selection_enabled() {
@@ -472,7 +532,7 @@ impl Upstream {
     }
 
     #[allow(clippy::result_large_err)]
-    pub fn handle_submit(self_: Arc<Mutex<Self>>) -> ProxyResult<'static, ()> {
+    pub fn handle_submit(self_: Arc<Mutex<Self>>) -> ProxyResult<()> {
         let task_collector = self_.safe_lock(|s| s.task_collector.clone()).unwrap();
         let clone = self_.clone();
         let (tx_frame, receiver, tx_status) = clone
@@ -489,6 +549,10 @@ impl Upstream {
             loop {
                 let mut sv2_submit: SubmitSharesExtended =
                     handle_result!(tx_status, receiver.recv().await);
+                let span = info_span!(
+                    "sv2_submit",
+                    share_id = %logging::share_id(sv2_submit.nonce, sv2_submit.ntime)
+                );
 
                 let channel_id = self_
                     .safe_lock(|s| {
@@ -512,7 +576,7 @@ impl Upstream {
                 let frame: EitherFrame = frame.into();
                 handle_result!(
                     tx_status,
-                    tx_frame.send(frame).await.map_err(|e| {
+                    tx_frame.send(frame).instrument(span).await.map_err(|e| {
                         crate::error::Error::ChannelErrorSender(
                             crate::error::ChannelSendError::General(e.to_string()),
                         )
@@ -538,7 +602,7 @@ impl Upstream {
         min_version: u16,
         max_version: u16,
         is_work_selection_enabled: bool,
-    ) -> ProxyResult<'static, SetupConnection<'static>> {
+    ) -> ProxyResult<SetupConnection<'static>> {
         let endpoint_host = "0.0.0.0".to_string().into_bytes().try_into()?;
         let vendor = String::new().try_into()?;
         let hardware_version = String::new().try_into()?;
@@ -7,3 +7,31 @@ pub fn proxy_extranonce1_len(
     // full_extranonce_len - pool_extranonce1_len - miner_extranonce2 = tproxy_extranonce1_len
     channel_extranonce2_size - downstream_extranonce2_len
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use proptest::{
+        prelude::{any, Just, Strategy},
+        prop_assert_eq, proptest,
+    };
+
+    proptest! {
+        #[test]
+        fn splits_the_extranonce_of_the_channel_between_the_proxy_and_the_miner(
+            (channel, miner) in any::<u16>().prop_flat_map(|channel| (Just(channel), 0..=channel)),
+            prefix_len in 0..=32usize,
+        ) {
+            let (channel, miner) = (channel as usize, miner as usize);
+            let proxy = proxy_extranonce1_len(channel, miner);
+            // the ranges `Upstream` builds the extended extranonce from, back to back
+            let range_0 = 0..prefix_len;
+            let range_1 = prefix_len..prefix_len + proxy;
+            let range_2 = prefix_len + proxy..prefix_len + channel;
+            prop_assert_eq!(range_0.end, range_1.start);
+            prop_assert_eq!(range_1.end, range_2.start);
+            prop_assert_eq!(range_2.len(), miner);
+            prop_assert_eq!(range_0.len() + range_1.len() + range_2.len(), prefix_len + channel);
+        }
+    }
+}
@@ -0,0 +1,237 @@
+//! Rate limits of the listeners of the process, with token buckets: every client may make
+//! `burst` requests at once and then `requests_per_minute`. Clients are limited per address they
+//! connect from, and per identity they give where the listener knows one:
+//!
+//! - `pool`: connections per address, and channels opened per user identity;
+//! - `translator`: SV1 connections per address, and `mining.authorize` per worker name;
+//! - `mint`: requests per address, and those changing state per account they name;
+//! - `control`: requests per address, there is no identity.
+//!
+//! Limits are set in the `rate_limits` section of the pool configuration, none by default.
+//! Requests refused by every limiter are counted in the metrics, see `refused`.
+//!
+//! Clients are told apart by the address they connect from, so behind a reverse proxy every
+//! client shares the proxy's limit. Limit per identity only, or at the proxy, then.
+use crate::{net, sim};
+use once_cell::sync::Lazy;
+use roles_logic_sv2::utils::Mutex;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::Hash,
+    net::IpAddr,
+    time::Instant,
+};
+
+/// Clients tracked at most by a limiter before those not limited anymore are forgotten.
+const MAX_BUCKETS: usize = 10_000;
+
+static REFUSED: Lazy<Mutex<BTreeMap<(&'static str, Scope), u64>>> =
+    Lazy::new(|| Mutex::new(BTreeMap::new()));
+
+/// Limits of every limiter, see the module.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct RateLimitsConfig {
+    #[serde(default)]
+    pub pool: RateLimitConfig,
+    #[serde(default)]
+    pub translator: RateLimitConfig,
+    #[serde(default)]
+    pub mint: RateLimitConfig,
+    #[serde(default)]
+    pub control: RateLimitConfig,
+}
+
+#[derive(Debug, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct RateLimitConfig {
+    /// Limit of each client address, none if unset.
+    #[serde(default)]
+    pub per_ip: Option<Limit>,
+    /// Limit of each identity, none if unset. `per_account` is the name of the mint limits.
+    #[serde(default, alias = "per_account")]
+    pub per_identity: Option<Limit>,
+}
+
+impl RateLimitConfig {
+    pub fn is_unlimited(&self) -> bool {
+        self.per_ip.is_none() && self.per_identity.is_none()
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct Limit {
+    pub requests_per_minute: u32,
+    /// Requests allowed at once after a quiet period.
+    pub burst: u32,
+}
+
+/// What a refused request was limited by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    Ip,
+    Identity,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, limit: Limit, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens =
+            (self.tokens + elapsed * limit.requests_per_minute as f64 / 60.0).min(capacity(limit));
+        self.updated = now;
+    }
+}
+
+#[derive(Debug)]
+struct Buckets<K> {
+    limit: Limit,
+    buckets: Mutex<HashMap<K, Bucket>>,
+}
+
+impl<K: Hash + Eq> Buckets<K> {
+    fn new(limit: Limit) -> Self {
+        Self {
+            limit,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes a token from the bucket of `key`, `false` if it is empty.
+    fn take(&self, key: K, now: Instant) -> bool {
+        let limit = self.limit;
+        self.buckets
+            .safe_lock(|buckets| {
+                if buckets.len() >= MAX_BUCKETS {
+                    buckets.retain(|_, bucket| {
+                        bucket.refill(limit, now);
+                        bucket.tokens < capacity(limit)
+                    });
+                }
+                let bucket = buckets.entry(key).or_insert(Bucket {
+                    tokens: capacity(limit),
+                    updated: now,
+                });
+                bucket.refill(limit, now);
+                if bucket.tokens < 1.0 {
+                    return false;
+                }
+                bucket.tokens -= 1.0;
+                true
+            })
+            // buckets left poisoned by a panic are only ever off by a token, let clients through
+            .unwrap_or(true)
+    }
+}
+
+/// Rate limits of a listener, named after it in the metrics.
+#[derive(Debug)]
+pub struct RateLimiter {
+    name: &'static str,
+    per_ip: Option<Buckets<IpAddr>>,
+    per_identity: Option<Buckets<String>>,
+}
+
+impl RateLimiter {
+    pub fn new(name: &'static str, config: &RateLimitConfig) -> Self {
+        Self {
+            name,
+            per_ip: config.per_ip.map(Buckets::new),
+            per_identity: config.per_identity.map(Buckets::new),
+        }
+    }
+
+    /// Whether a request of `ip` is allowed, counting it.
+    pub fn allow_ip(&self, ip: IpAddr) -> bool {
+        match &self.per_ip {
+            Some(buckets) => {
+                let allowed = buckets.take(net::client_ip(ip), sim::now());
+                self.count(Scope::Ip, allowed)
+            }
+            None => true,
+        }
+    }
+
+    /// Whether a request of `identity` is allowed, counting it.
+    pub fn allow_identity(&self, identity: &str) -> bool {
+        match &self.per_identity {
+            Some(buckets) => {
+                let allowed = buckets.take(identity.to_string(), sim::now());
+                self.count(Scope::Identity, allowed)
+            }
+            None => true,
+        }
+    }
+
+    fn count(&self, scope: Scope, allowed: bool) -> bool {
+        if !allowed {
+            let _ =
+                REFUSED.safe_lock(|refused| *refused.entry((self.name, scope)).or_default() += 1);
+        }
+        allowed
+    }
+}
+
+/// Requests refused so far, by limiter and by what they were limited by.
+pub fn refused() -> BTreeMap<(&'static str, Scope), u64> {
+    REFUSED
+        .safe_lock(|refused| refused.clone())
+        .unwrap_or_default()
+}
+
+fn capacity(limit: Limit) -> f64 {
+    limit.burst.max(1) as f64
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn allows_bursts_then_the_sustained_rate() {
+        let buckets = Buckets::new(Limit {
+            requests_per_minute: 60,
+            burst: 3,
+        });
+        let start = Instant::now();
+        for _ in 0..3 {
+            assert!(buckets.take("alice", start));
+        }
+        assert!(!buckets.take("alice", start));
+        // other clients have buckets of their own
+        assert!(buckets.take("bob", start));
+        // one request per second refills
+        let later = start + Duration::from_millis(1500);
+        assert!(buckets.take("alice", later));
+        assert!(!buckets.take("alice", later));
+        // never more than the burst
+        let much_later = start + Duration::from_secs(3600);
+        for _ in 0..3 {
+            assert!(buckets.take("alice", much_later));
+        }
+        assert!(!buckets.take("alice", much_later));
+    }
+
+    #[test]
+    fn limits_only_what_is_configured_and_counts_the_refused() {
+        let config: RateLimitConfig = serde_json::from_value(serde_json::json!({
+            "per_account": { "requests_per_minute": 1, "burst": 1 }
+        }))
+        .unwrap();
+        let limiter = RateLimiter::new("test", &config);
+        let ip = IpAddr::from([127, 0, 0, 1]);
+        for _ in 0..10 {
+            assert!(limiter.allow_ip(ip));
+        }
+        assert!(limiter.allow_identity("alice"));
+        assert!(!limiter.allow_identity("alice"));
+        assert_eq!(refused().get(&("test", Scope::Identity)), Some(&1));
+        assert_eq!(refused().get(&("test", Scope::Ip)), None);
+    }
+}
@@ -0,0 +1,421 @@
+//! Retries and circuit breakers of the external dependencies of the process: bitcoind RPC, the
+//! template provider, the pool the translator mines on and the Lightning node of the mint. Each
+//! is called through a [`Retry`] with the [`RetryPolicy`] of its configuration: a failed call is
+//! tried again after a backoff doubling from `initial_backoff_ms` up to `max_backoff_ms`, with
+//! a fifth of jitter so miners and tasks don't all retry at once, up to `max_attempts` times.
+//!
+//! Past `breaker_threshold` failures in a row of a dependency its circuit breaker opens: calls
+//! fail right away for `breaker_cooldown_secs` rather than pile up on a dependency that is down,
+//! or wait for it if retried forever. Once it is over the breaker is half open: the first call
+//! goes through as a probe while the others keep failing or waiting, the probe closing the
+//! breaker if it succeeds and opening it for another cooldown if it fails. A probe that never
+//! ends holds the breaker for a cooldown. Breakers are shared by every call of a dependency, of
+//! any task, and outlive the restarts of the pool and the translator.
+use once_cell::sync::Lazy;
+use rand::Rng;
+use roles_logic_sv2::utils::Mutex;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tracing::{info, warn};
+
+static BREAKERS: Lazy<Mutex<HashMap<&'static str, Arc<CircuitBreaker>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct RetryPolicy {
+    /// Attempts of a call before it fails, 0 retrying it forever.
+    #[serde(default = "RetryPolicy::default_max_attempts")]
+    pub max_attempts: u32,
+    #[serde(default = "RetryPolicy::default_initial_backoff_ms")]
+    pub initial_backoff_ms: u64,
+    #[serde(default = "RetryPolicy::default_max_backoff_ms")]
+    pub max_backoff_ms: u64,
+    /// Failures in a row opening the circuit breaker of the dependency, 0 never opening it.
+    #[serde(default = "RetryPolicy::default_breaker_threshold")]
+    pub breaker_threshold: u32,
+    #[serde(default = "RetryPolicy::default_breaker_cooldown_secs")]
+    pub breaker_cooldown_secs: u64,
+}
+
+impl RetryPolicy {
+    fn default_max_attempts() -> u32 {
+        3
+    }
+
+    fn default_initial_backoff_ms() -> u64 {
+        1000
+    }
+
+    fn default_max_backoff_ms() -> u64 {
+        30_000
+    }
+
+    fn default_breaker_threshold() -> u32 {
+        5
+    }
+
+    fn default_breaker_cooldown_secs() -> u64 {
+        30
+    }
+
+    /// The default policy, retrying forever, for connections the process can't do without.
+    pub fn forever() -> Self {
+        Self {
+            max_attempts: 0,
+            ..Self::default()
+        }
+    }
+
+    /// A single attempt, no circuit breaker, for calls that are fine failing.
+    pub fn once() -> Self {
+        Self {
+            max_attempts: 1,
+            breaker_threshold: 0,
+            ..Self::default()
+        }
+    }
+
+    /// Wait after the `attempt`th failure of a call, jittered.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let doublings = attempt.saturating_sub(1).min(16);
+        let backoff = self
+            .initial_backoff_ms
+            .saturating_mul(1 << doublings)
+            .min(self.max_backoff_ms);
+        let jitter = rand::thread_rng().gen_range(0.8..=1.2);
+        Duration::from_millis(backoff).mul_f64(jitter)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: Self::default_max_attempts(),
+            initial_backoff_ms: Self::default_initial_backoff_ms(),
+            max_backoff_ms: Self::default_max_backoff_ms(),
+            breaker_threshold: Self::default_breaker_threshold(),
+            breaker_cooldown_secs: Self::default_breaker_cooldown_secs(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct BreakerState {
+    failures: u32,
+    open_until: Option<Instant>,
+    /// Half open, a call probing the dependency until `open_until`.
+    probing: bool,
+}
+
+/// Circuit breaker of a dependency, see the module.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    dependency: &'static str,
+    threshold: u32,
+    cooldown: Duration,
+    state: Mutex<BreakerState>,
+}
+
+impl CircuitBreaker {
+    /// The breaker of `dependency`, set up with `policy` by its first caller.
+    pub fn of(dependency: &'static str, policy: &RetryPolicy) -> Arc<Self> {
+        let breaker = || {
+            Arc::new(Self {
+                dependency,
+                threshold: policy.breaker_threshold,
+                cooldown: Duration::from_secs(policy.breaker_cooldown_secs),
+                state: Mutex::new(BreakerState::default()),
+            })
+        };
+        BREAKERS
+            .safe_lock(|breakers| breakers.entry(dependency).or_insert_with(breaker).clone())
+            .unwrap_or_else(|_| breaker())
+    }
+
+    /// How long calls keep failing right away, none if one may go through.
+    pub fn open_for(&self) -> Option<Duration> {
+        self.state
+            .safe_lock(|state| {
+                state
+                    .open_until
+                    .and_then(|until| until.checked_duration_since(Instant::now()))
+            })
+            .ok()
+            .flatten()
+    }
+
+    /// How long a call has to wait, none if it may go through. The first call past the cooldown
+    /// is the probe, the others wait for it.
+    fn admit(&self) -> Option<Duration> {
+        let (open_for, stuck) = self
+            .state
+            .safe_lock(|state| {
+                let Some(until) = state.open_until else {
+                    return (None, false);
+                };
+                let now = Instant::now();
+                match until.checked_duration_since(now) {
+                    Some(open_for) => (Some(open_for), false),
+                    None => {
+                        state.open_until = Some(now + self.cooldown);
+                        (None, std::mem::replace(&mut state.probing, true))
+                    }
+                }
+            })
+            .unwrap_or_default();
+        if stuck {
+            warn!(
+                "{} probe unfinished after {}s, probing again",
+                self.dependency,
+                self.cooldown.as_secs()
+            );
+        }
+        open_for
+    }
+
+    fn succeeded(&self) {
+        let was_open = self
+            .state
+            .safe_lock(|state| {
+                let was_open = state.open_until.take().is_some();
+                state.failures = 0;
+                state.probing = false;
+                was_open
+            })
+            .unwrap_or_default();
+        if was_open {
+            info!("{} is back, circuit breaker closed", self.dependency);
+        }
+    }
+
+    fn failed(&self) {
+        let opened = self
+            .state
+            .safe_lock(|state| {
+                state.failures = state.failures.saturating_add(1);
+                let open = self.threshold > 0 && state.failures >= self.threshold;
+                if open {
+                    state.open_until = Some(Instant::now() + self.cooldown);
+                }
+                state.probing = false;
+                open.then_some(state.failures)
+            })
+            .ok()
+            .flatten();
+        if let Some(failures) = opened {
+            warn!(
+                "{} failed {} times in a row, circuit breaker open for {}s",
+                self.dependency,
+                failures,
+                self.cooldown.as_secs()
+            );
+        }
+    }
+}
+
+/// Why a call retried failed.
+#[derive(Debug)]
+pub enum RetryError<E> {
+    /// The breaker of `dependency` is open for `open_for` still.
+    Open {
+        dependency: &'static str,
+        open_for: Duration,
+    },
+    /// Every attempt failed, the last with `error`.
+    Failed { attempts: u32, error: E },
+}
+
+impl<E> RetryError<E> {
+    /// The error of the last attempt, or the one `open` makes of why none was made.
+    pub fn into_error(self, open: impl FnOnce(String) -> E) -> E
+    where
+        E: fmt::Display,
+    {
+        match self {
+            RetryError::Failed { error, .. } => error,
+            open_error => open(open_error.to_string()),
+        }
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for RetryError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RetryError::Open {
+                dependency,
+                open_for,
+            } => write!(
+                f,
+                "{} unavailable, circuit breaker open for {}s",
+                dependency,
+                open_for.as_secs()
+            ),
+            RetryError::Failed { attempts, error } => {
+                write!(f, "{} (after {} attempts)", error, attempts)
+            }
+        }
+    }
+}
+
+/// Calls a dependency as its policy says, see the module.
+#[derive(Debug, Clone)]
+pub struct Retry {
+    dependency: &'static str,
+    policy: RetryPolicy,
+    breaker: Arc<CircuitBreaker>,
+}
+
+impl Retry {
+    pub fn new(dependency: &'static str, policy: &RetryPolicy) -> Self {
+        Self {
+            dependency,
+            policy: policy.clone(),
+            breaker: CircuitBreaker::of(dependency, policy),
+        }
+    }
+
+    /// Calls `call` until it succeeds or the attempts of the policy are used up.
+    pub async fn run<T, E, F, Fut>(&self, mut call: F) -> Result<T, RetryError<E>>
+    where
+        E: fmt::Display,
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let mut attempt = 0;
+        loop {
+            if let Some(open_for) = self.breaker.admit() {
+                if self.policy.max_attempts != 0 {
+                    return Err(RetryError::Open {
+                        dependency: self.dependency,
+                        open_for,
+                    });
+                }
+                tokio::time::sleep(open_for).await;
+                continue;
+            }
+            attempt += 1;
+            let error = match call().await {
+                Ok(value) => {
+                    self.breaker.succeeded();
+                    return Ok(value);
+                }
+                Err(error) => error,
+            };
+            self.breaker.failed();
+            if attempt == self.policy.max_attempts {
+                return Err(RetryError::Failed {
+                    attempts: attempt,
+                    error,
+                });
+            }
+            let backoff = self.policy.backoff(attempt);
+            warn!(
+                "{} failed, retrying in {}ms: {}",
+                self.dependency,
+                backoff.as_millis(),
+                error
+            );
+            tokio::time::sleep(backoff).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn retries_then_opens_the_breaker_until_its_cooldown_is_over() {
+        let policy = RetryPolicy {
+            max_attempts: 3,
+            initial_backoff_ms: 1,
+            max_backoff_ms: 4,
+            breaker_threshold: 5,
+            breaker_cooldown_secs: 1,
+        };
+        let backoff = policy.backoff(10);
+        assert!(backoff >= Duration::from_micros(3200) && backoff <= Duration::from_micros(4800));
+
+        let retry = Retry::new("test_dependency", &policy);
+        let mut calls = 0;
+        let result: Result<(), _> = retry
+            .run(|| {
+                calls += 1;
+                async { Err("refused") }
+            })
+            .await;
+        assert!(matches!(
+            result,
+            Err(RetryError::Failed { attempts: 3, .. })
+        ));
+        assert_eq!(calls, 3);
+        assert!(retry.breaker.open_for().is_none());
+
+        // succeeding on the second attempt
+        let mut calls = 0;
+        let result = retry
+            .run(|| {
+                calls += 1;
+                let result = match calls {
+                    1 => Err("refused"),
+                    _ => Ok(calls),
+                };
+                async move { result }
+            })
+            .await;
+        assert_eq!(result.unwrap(), 2);
+
+        // the fifth failure in a row opens the breaker of every caller, failing right away
+        for _ in 0..2 {
+            let _ = retry.run(|| async { Err::<(), _>("refused") }).await;
+        }
+        let other = Retry::new("test_dependency", &RetryPolicy::default());
+        assert!(other.breaker.open_for().is_some());
+        let mut calls = 0;
+        let result: Result<(), _> = other
+            .run(|| {
+                calls += 1;
+                async { Err("refused") }
+            })
+            .await;
+        assert_eq!(calls, 0);
+        let error = result.unwrap_err().into_error(|_| "open");
+        assert_eq!(error, "open");
+
+        // retried forever, waiting for the cooldown, then closed by a success
+        let forever = Retry::new("test_dependency", &RetryPolicy::forever());
+        assert_eq!(forever.run(|| async { Ok::<_, &str>(1) }).await.unwrap(), 1);
+        assert!(forever.breaker.open_for().is_none());
+    }
+
+    #[tokio::test]
+    async fn lets_one_call_probe_a_half_open_breaker() {
+        let policy = RetryPolicy {
+            breaker_threshold: 1,
+            breaker_cooldown_secs: 1,
+            ..RetryPolicy::default()
+        };
+        let breaker = CircuitBreaker::of("test_probed_dependency", &policy);
+        breaker.failed();
+        assert!(breaker.admit().is_some());
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        // the probe goes through, the calls after it wait for it
+        assert!(breaker.admit().is_none());
+        assert!(breaker.admit().is_some());
+        assert!(breaker.state.safe_lock(|state| state.probing).unwrap());
+
+        // a failed probe opens the breaker for another cooldown, a succeeded one closes it
+        breaker.failed();
+        assert!(breaker.admit().unwrap() > Duration::from_millis(500));
+        breaker.succeeded();
+        assert!(breaker.admit().is_none());
+        assert!(breaker.admit().is_none());
+    }
+}
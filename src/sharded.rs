@@ -0,0 +1,191 @@
+//! Per-connection state in maps split in shards, each behind a lock of its own. A shard poisoned by
+//! a panic is taken over, inserts and removes leaving it consistent. The state of a connection
+//! keeps a lock of its own that a panic does poison: its handler fails on the next lock and the
+//! connection is removed, see `mining_pool` and `downstream_sv1`.
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::{BuildHasher, Hash},
+    sync::{PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+
+/// Shards of a map, enough for a few thousand connections to rarely share one.
+pub const SHARDS: usize = 64;
+
+/// A map of per-connection state, see the module. Entries are spread over the shards by the hash
+/// of their key, `S` builds the hasher of both.
+#[derive(Debug)]
+pub struct ShardedMap<K, V, S = RandomState> {
+    shards: Box<[RwLock<HashMap<K, V, S>>]>,
+    hasher: S,
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher + Clone + Default> Default for ShardedMap<K, V, S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher + Clone + Default> ShardedMap<K, V, S> {
+    pub fn new() -> Self {
+        Self::with_shards(SHARDS)
+    }
+
+    pub fn with_shards(shards: usize) -> Self {
+        let hasher = S::default();
+        Self {
+            shards: (0..shards.max(1))
+                .map(|_| RwLock::new(HashMap::with_hasher(hasher.clone())))
+                .collect(),
+            hasher,
+        }
+    }
+
+    /// The shard of `key`, picked from the high bits of its hash mixed (Fibonacci hashing): the
+    /// hash of an id is the id itself with `BuildNoHashHasher`, and ids a multiple of the shards
+    /// apart would otherwise all land in one shard.
+    fn shard(&self, key: &K) -> &RwLock<HashMap<K, V, S>> {
+        let mixed = self
+            .hasher
+            .hash_one(key)
+            .wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        &self.shards[((mixed as u128 * self.shards.len() as u128) >> 64) as usize]
+    }
+
+    fn read(shard: &RwLock<HashMap<K, V, S>>) -> RwLockReadGuard<'_, HashMap<K, V, S>> {
+        shard.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write(shard: &RwLock<HashMap<K, V, S>>) -> RwLockWriteGuard<'_, HashMap<K, V, S>> {
+        shard.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn insert(&self, key: K, value: V) -> Option<V> {
+        Self::write(self.shard(&key)).insert(key, value)
+    }
+
+    pub fn remove(&self, key: &K) -> Option<V> {
+        Self::write(self.shard(key)).remove(key)
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        Self::read(self.shard(key)).contains_key(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| Self::read(shard).len())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(|shard| Self::read(shard).is_empty())
+    }
+
+    /// Keeps the entries `keep` returns true for, one shard locked at a time.
+    pub fn retain(&self, mut keep: impl FnMut(&K, &mut V) -> bool) {
+        for shard in self.shards.iter() {
+            Self::write(shard).retain(&mut keep);
+        }
+    }
+}
+
+impl<K: Hash + Eq, V: Clone, S: BuildHasher + Clone + Default> ShardedMap<K, V, S> {
+    pub fn get(&self, key: &K) -> Option<V> {
+        Self::read(self.shard(key)).get(key).cloned()
+    }
+
+    /// The values at the time each shard is read, connections of a shard already read may come
+    /// and go meanwhile.
+    pub fn values(&self) -> Vec<V> {
+        self.shards
+            .iter()
+            .flat_map(|shard| Self::read(shard).values().cloned().collect::<Vec<_>>())
+            .collect()
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone, S: BuildHasher + Clone + Default> ShardedMap<K, V, S> {
+    /// The entries at the time each shard is read, as `values`.
+    pub fn entries(&self) -> Vec<(K, V)> {
+        self.shards
+            .iter()
+            .flat_map(|shard| {
+                Self::read(shard)
+                    .iter()
+                    .map(|(key, value)| (key.clone(), value.clone()))
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{
+        hash::{BuildHasherDefault, Hasher},
+        sync::Arc,
+        thread,
+    };
+
+    /// The hash of an id is the id, as with `BuildNoHashHasher`.
+    #[derive(Default)]
+    struct IdHasher(u64);
+
+    impl Hasher for IdHasher {
+        fn finish(&self) -> u64 {
+            self.0
+        }
+
+        fn write(&mut self, _: &[u8]) {
+            unreachable!("ids only")
+        }
+
+        fn write_u32(&mut self, id: u32) {
+            self.0 = id as u64;
+        }
+    }
+
+    #[test]
+    fn spreads_ids_a_stride_apart_over_the_shards() {
+        let map = ShardedMap::<u32, (), BuildHasherDefault<IdHasher>>::new();
+        for id in 0..SHARDS as u32 * 16 {
+            map.insert(id * SHARDS as u32, ());
+        }
+        for shard in map.shards.iter() {
+            let len = ShardedMap::read(shard).len();
+            assert!((8..=24).contains(&len), "{} ids in a shard", len);
+        }
+    }
+
+    #[test]
+    fn keeps_serving_connections_after_a_panic_in_a_shard() {
+        let map = Arc::new(ShardedMap::<u32, u32>::with_shards(4));
+        for id in 0..100u32 {
+            map.insert(id, id * 2);
+        }
+        assert_eq!(map.len(), 100);
+        assert_eq!(map.get(&21), Some(42));
+
+        let poisoning = map.clone();
+        let panicked = thread::spawn(move || {
+            poisoning.retain(|id, _| match *id {
+                50 => panic!("a connection handler panicked"),
+                _ => true,
+            })
+        })
+        .join();
+        assert!(panicked.is_err());
+
+        // the shards retained before the panic and the one it poisoned are still served
+        assert!(map.remove(&50).is_some());
+        map.insert(100, 200);
+        assert_eq!(map.len(), 100);
+        let mut entries = map.entries();
+        entries.sort_unstable();
+        assert_eq!(entries.first(), Some(&(0, 0)));
+        assert_eq!(entries.last(), Some(&(100, 200)));
+        assert!(!map.contains_key(&50));
+    }
+}
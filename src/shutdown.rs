@@ -0,0 +1,107 @@
+//! Graceful shutdown on SIGTERM or SIGINT (Ctrl-C). The first signal cancels the token the pool,
+//! the translator and every task of the process stop on, and they drain rather than stop
+//! mid-write:
+//!
+//! - the pool refuses new connections and closes those open once their queued frames are sent,
+//!   so their shares are handled, then replays the credits journaled (see
+//!   `crate::pool_mint::mint::journal`);
+//! - the translator stops accepting miners and forwards the shares they queued to the pool
+//!   before closing their connections.
+//!
+//! Everything else stops where it waits: the accept loops, the handling of templates and jobs,
+//! connecting or reconnecting to the template provider or the pool, and the calls to bitcoind
+//! and Lightning, which the next start makes again. Payouts are the exception, a round begun is
+//! finished so no token of the external mint is lost.
+//!
+//! The process exits once both are done, or `--shutdown-timeout` seconds after the signal
+//! whatever they are doing. A second signal exits right away. A new process taking the
+//! listeners over (see `crate::handoff`) shuts this one down the same way.
+use std::{future::Future, io, time::Duration};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+/// Waits for the next SIGTERM or SIGINT, returning its name.
+#[cfg(unix)]
+async fn signal() -> io::Result<&'static str> {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        _ = terminate.recv() => Ok("SIGTERM"),
+        result = tokio::signal::ctrl_c() => result.map(|_| "SIGINT"),
+    }
+}
+
+/// Waits for the next Ctrl-C, the only signal there is off unix.
+#[cfg(not(unix))]
+async fn signal() -> io::Result<&'static str> {
+    tokio::signal::ctrl_c().await.map(|_| "Ctrl-C")
+}
+
+/// Cancels `cancel_token` on the first signal, exits the process on the second.
+pub async fn on_signal(cancel_token: CancellationToken) {
+    tokio::select! {
+        signal = signal() => match signal {
+            Ok(signal) => info!("{} received, shutting down", signal),
+            Err(e) => {
+                warn!("Can't listen for signals: {}", e);
+                return;
+            }
+        },
+        // shutting down on its own
+        _ = cancel_token.cancelled() => {}
+    }
+    cancel_token.cancel();
+    if let Ok(signal) = signal().await {
+        warn!("{} received again, exiting now", signal);
+        std::process::exit(1);
+    }
+}
+
+/// Runs `running` to completion, or until `timeout` after `cancel_token` is cancelled, returning
+/// its output if it completed.
+pub async fn with_deadline<F: Future>(
+    running: F,
+    cancel_token: &CancellationToken,
+    timeout: Duration,
+) -> Option<F::Output> {
+    tokio::select! {
+        output = running => Some(output),
+        _ = async {
+            cancel_token.cancelled().await;
+            tokio::time::sleep(timeout).await;
+        } => {
+            warn!("Shutdown took over {}s, exiting", timeout.as_secs());
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn gives_up_on_a_stuck_shutdown_once_past_its_deadline() {
+        let cancel_token = CancellationToken::new();
+        let timeout = Duration::from_millis(50);
+        let stuck = std::future::pending::<()>();
+        let running = tokio::spawn({
+            let cancel_token = cancel_token.clone();
+            async move { with_deadline(stuck, &cancel_token, timeout).await }
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        // no deadline before the shutdown
+        assert!(!running.is_finished());
+        cancel_token.cancel();
+        assert_eq!(running.await.unwrap(), None);
+
+        let draining = async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            "drained"
+        };
+        assert_eq!(
+            with_deadline(draining, &cancel_token, timeout).await,
+            Some("drained")
+        );
+    }
+}
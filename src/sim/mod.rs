@@ -0,0 +1,248 @@
+//! Time and randomness of the protocol handling of the pool and the translator, behind traits so
+//! it can run deterministically. In the process they are the system's: its clock and its random
+//! number generator. Handlers ask this module for them, never `SystemTime`, `Instant` or `rand`:
+//! the vardiff timestamps, session tokens and reconnection jitter of the translator.
+//!
+//! With the `simulation` feature, a `Simulation` replaces them on the thread it runs on with a
+//! `SimClock` only moving when advanced and a `SeededEntropy` drawing from a seed, and `script`
+//! plays scripted messages to the handlers in place of the sockets of their connections. A
+//! regression test of a protocol edge case then plays out the same on every run.
+use rand::Rng;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+#[cfg(feature = "simulation")]
+use {
+    rand::{rngs::StdRng, RngCore, SeedableRng},
+    roles_logic_sv2::utils::Mutex,
+    std::{cell::RefCell, future::Future, sync::Arc, time::Duration},
+};
+
+#[cfg(all(feature = "simulation", feature = "proxy"))]
+pub mod script;
+
+pub trait Clock: Send + Sync {
+    /// Seconds since the unix epoch.
+    fn unix_secs(&self) -> u64;
+
+    /// An instant to measure durations from, as `Instant::now`.
+    fn now(&self) -> Instant;
+}
+
+pub trait Entropy: Send + Sync {
+    fn fill_bytes(&self, bytes: &mut [u8]);
+}
+
+/// The clock of the system.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn unix_secs(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.as_secs())
+            .unwrap_or_default()
+    }
+
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// The random number generator of the system.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OsEntropy;
+
+impl Entropy for OsEntropy {
+    fn fill_bytes(&self, bytes: &mut [u8]) {
+        rand::thread_rng().fill(bytes);
+    }
+}
+
+#[cfg(feature = "simulation")]
+thread_local! {
+    static CURRENT: RefCell<Option<Simulation>> = const { RefCell::new(None) };
+}
+
+/// The clock of this thread, the one of the simulation entered if any.
+fn with_clock<T>(f: impl FnOnce(&dyn Clock) -> T) -> T {
+    #[cfg(feature = "simulation")]
+    if let Some(simulation) = CURRENT.with(|current| current.borrow().clone()) {
+        return f(simulation.clock.as_ref());
+    }
+    f(&SystemClock)
+}
+
+/// The randomness of this thread, the one of the simulation entered if any.
+fn with_entropy<T>(f: impl FnOnce(&dyn Entropy) -> T) -> T {
+    #[cfg(feature = "simulation")]
+    if let Some(simulation) = CURRENT.with(|current| current.borrow().clone()) {
+        return f(simulation.entropy.as_ref());
+    }
+    f(&OsEntropy)
+}
+
+pub fn unix_secs() -> u64 {
+    with_clock(|clock| clock.unix_secs())
+}
+
+pub fn now() -> Instant {
+    with_clock(|clock| clock.now())
+}
+
+pub fn fill_bytes(bytes: &mut [u8]) {
+    with_entropy(|entropy| entropy.fill_bytes(bytes))
+}
+
+/// A random number up to `max`, included.
+pub fn random_up_to(max: u64) -> u64 {
+    let mut bytes = [0; 8];
+    fill_bytes(&mut bytes);
+    let random = u64::from_le_bytes(bytes);
+    match max.checked_add(1) {
+        Some(bound) => random % bound,
+        None => random,
+    }
+}
+
+/// A clock only moving when advanced, from the unix time it starts at.
+#[cfg(feature = "simulation")]
+#[derive(Debug)]
+pub struct SimClock {
+    unix_start: u64,
+    start: Instant,
+    elapsed: Mutex<Duration>,
+}
+
+#[cfg(feature = "simulation")]
+impl SimClock {
+    pub fn new(unix_start: u64) -> Self {
+        Self {
+            unix_start,
+            start: Instant::now(),
+            elapsed: Mutex::new(Duration::ZERO),
+        }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        let _ = self.elapsed.safe_lock(|elapsed| *elapsed += by);
+    }
+
+    fn elapsed(&self) -> Duration {
+        self.elapsed
+            .safe_lock(|elapsed| *elapsed)
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(feature = "simulation")]
+impl Clock for SimClock {
+    fn unix_secs(&self) -> u64 {
+        self.unix_start + self.elapsed().as_secs()
+    }
+
+    fn now(&self) -> Instant {
+        self.start + self.elapsed()
+    }
+}
+
+/// Randomness drawn from a seed, the same for the same seed.
+#[cfg(feature = "simulation")]
+#[derive(Debug)]
+pub struct SeededEntropy(Mutex<StdRng>);
+
+#[cfg(feature = "simulation")]
+impl SeededEntropy {
+    pub fn new(seed: u64) -> Self {
+        Self(Mutex::new(StdRng::seed_from_u64(seed)))
+    }
+}
+
+#[cfg(feature = "simulation")]
+impl Entropy for SeededEntropy {
+    fn fill_bytes(&self, bytes: &mut [u8]) {
+        let _ = self.0.safe_lock(|rng| rng.fill_bytes(bytes));
+    }
+}
+
+/// A simulated clock and randomness, see the module.
+#[cfg(feature = "simulation")]
+#[derive(Debug, Clone)]
+pub struct Simulation {
+    clock: Arc<SimClock>,
+    entropy: Arc<SeededEntropy>,
+}
+
+#[cfg(feature = "simulation")]
+impl Simulation {
+    /// A simulation starting at `unix_start`, its randomness drawn from `seed`.
+    pub fn new(seed: u64, unix_start: u64) -> Self {
+        Self {
+            clock: Arc::new(SimClock::new(unix_start)),
+            entropy: Arc::new(SeededEntropy::new(seed)),
+        }
+    }
+
+    pub fn clock(&self) -> &SimClock {
+        &self.clock
+    }
+
+    /// Makes the clock and the randomness of this thread the simulation's, until the guard is
+    /// dropped.
+    pub fn enter(&self) -> Entered {
+        let previous = CURRENT.with(|current| current.replace(Some(self.clone())));
+        Entered { previous }
+    }
+
+    /// Runs `run` to completion in the simulation, on a runtime of its own on this thread so
+    /// every task it spawns runs in the simulation too.
+    pub fn run<F: Future>(&self, run: F) -> F::Output {
+        let _entered = self.enter();
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("a current thread runtime always builds")
+            .block_on(run)
+    }
+}
+
+/// The simulation entered on a thread, see `Simulation::enter`.
+#[cfg(feature = "simulation")]
+#[must_use]
+pub struct Entered {
+    previous: Option<Simulation>,
+}
+
+#[cfg(feature = "simulation")]
+impl Drop for Entered {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        CURRENT.with(|current| *current.borrow_mut() = previous);
+    }
+}
+
+#[cfg(all(test, feature = "simulation"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn draws_the_same_time_and_randomness_in_the_same_simulation() {
+        let draw = |seed| {
+            let simulation = Simulation::new(seed, 1_700_000_000);
+            let _entered = simulation.enter();
+            let start = now();
+            simulation.clock().advance(Duration::from_secs(90));
+            let mut bytes = [0; 16];
+            fill_bytes(&mut bytes);
+            (unix_secs(), now() - start, bytes, random_up_to(3000))
+        };
+        let (unix_secs, elapsed, bytes, jitter) = draw(7);
+        assert_eq!(unix_secs, 1_700_000_090);
+        assert_eq!(elapsed, Duration::from_secs(90));
+        assert!(jitter <= 3000);
+        assert_eq!(draw(7), (unix_secs, elapsed, bytes, jitter));
+        assert_ne!(draw(8).2, bytes);
+
+        // the system's once left
+        assert!(super::unix_secs() > 1_700_000_090);
+    }
+}
@@ -0,0 +1,164 @@
+//! Scripted connections: the lines a miner sends, played to the message handling of the translator
+//! with no socket in between, and what it answers and passes on to the bridge kept for a test to
+//! check. The handling reads and writes its connection through channels, to the tasks of the
+//! socket in the process, to the script here.
+use crate::{
+    error::Error,
+    proxy_wallet::{
+        downstream_sv1::{
+            Downstream, DownstreamMessages, SubmissionPipeline, SubmissionReceiver, Sv1Frame,
+        },
+        proxy_config::{
+            DownstreamDifficultyConfig, SubmissionPipelineConfig, UpstreamDifficultyConfig,
+        },
+    },
+};
+use async_channel::{unbounded, Receiver};
+use roles_logic_sv2::utils::Mutex;
+use std::sync::Arc;
+
+/// A miner connected to the translator, its lines scripted.
+pub struct Sv1Script {
+    downstream: Arc<Mutex<Downstream>>,
+    outgoing: Receiver<Sv1Frame>,
+    upstream: SubmissionReceiver,
+}
+
+impl Sv1Script {
+    /// Connection `connection_id` of the translator, handed `extranonce1` and mining job `job_id`.
+    pub fn new(
+        connection_id: u32,
+        extranonce1: Vec<u8>,
+        extranonce2_len: usize,
+        job_id: &str,
+        difficulty_config: DownstreamDifficultyConfig,
+        upstream_difficulty_config: UpstreamDifficultyConfig,
+        pipeline: &SubmissionPipelineConfig,
+    ) -> Self {
+        let (submissions, upstream) = SubmissionPipeline::new(pipeline);
+        let (tx_outgoing, outgoing) = unbounded();
+        let downstream = Downstream::new(
+            connection_id,
+            vec![],
+            extranonce1,
+            None,
+            None,
+            submissions,
+            tx_outgoing,
+            true,
+            extranonce2_len,
+            difficulty_config,
+            Arc::new(Mutex::new(upstream_difficulty_config)),
+            job_id.to_string(),
+        );
+        Self {
+            downstream: Arc::new(Mutex::new(downstream)),
+            outgoing,
+            upstream,
+        }
+    }
+
+    /// Plays `line` as sent by the miner, a single message or a batch.
+    pub async fn send(&self, line: &str) -> Result<(), Error> {
+        let frame: Sv1Frame = serde_json::from_str(line)?;
+        Downstream::handle_incoming_frame(self.downstream.clone(), frame).await
+    }
+
+    /// The lines written to the miner since the last call.
+    pub fn answers(&self) -> Vec<String> {
+        std::iter::from_fn(|| self.outgoing.try_recv().ok())
+            .filter_map(|frame| serde_json::to_string(&frame).ok())
+            .collect()
+    }
+
+    /// The messages passed on to the bridge since the last call.
+    pub fn passed_on(&self) -> Vec<DownstreamMessages> {
+        std::iter::from_fn(|| self.upstream.try_recv().ok()).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sim::Simulation;
+    use std::time::Duration;
+
+    const SUBSCRIBE: &str = r#"{"id":1,"method":"mining.subscribe","params":["cgminer/4.10.0"]}"#;
+    const AUTHORIZE: &str = r#"{"id":2,"method":"mining.authorize","params":["rig.1","x"]}"#;
+
+    fn submit(id: u64, job_id: &str) -> String {
+        let params = format!(r#"["rig.1","{}","00000001","5f5e1000","0000abcd"]"#, job_id);
+        format!(
+            r#"{{"id":{},"method":"mining.submit","params":{}}}"#,
+            id, params
+        )
+    }
+
+    fn script() -> Sv1Script {
+        Sv1Script::new(
+            1,
+            vec![0, 0, 0, 1],
+            4,
+            "7",
+            DownstreamDifficultyConfig::new(10_000_000.0, 6.0, 0, 0),
+            UpstreamDifficultyConfig::new(60, 0.0, 0, false),
+            &SubmissionPipelineConfig::default(),
+        )
+    }
+
+    /// Subscribes, authorizes and submits a share of the current job then one of a stale job,
+    /// a minute apart.
+    fn play(seed: u64) -> (Vec<String>, usize) {
+        let simulation = Simulation::new(seed, 1_700_000_000);
+        simulation.run(async {
+            let script = script();
+            script.send(SUBSCRIBE).await.unwrap();
+            script.send(AUTHORIZE).await.unwrap();
+            script.send(&submit(3, "7")).await.unwrap();
+            simulation.clock().advance(Duration::from_secs(60));
+            script.send(&submit(4, "6")).await.unwrap();
+            (script.answers(), script.passed_on().len())
+        })
+    }
+
+    #[test]
+    fn plays_the_same_session_for_the_same_seed() {
+        let (answers, passed_on) = play(42);
+        assert_eq!(answers.len(), 4);
+        // the subscription id is the session token, drawn from the seed
+        assert!(answers[0].contains("mining.set_difficulty"));
+        assert!(answers[1].contains(r#""result":true"#));
+        assert!(answers[2].contains(r#""result":true"#));
+        // the job moved on
+        assert!(answers[3].contains(r#""result":false"#));
+        assert_eq!(passed_on, 1);
+
+        assert_eq!(play(42), (answers.clone(), passed_on));
+        assert_ne!(play(43).0[0], answers[0]);
+    }
+
+    #[test]
+    fn answers_every_message_of_a_batch() {
+        let simulation = Simulation::new(42, 1_700_000_000);
+        simulation.run(async {
+            let script = script();
+            script.send("[]").await.unwrap();
+            let answers = script.answers();
+            assert_eq!(answers.len(), 1);
+            assert!(answers[0].contains(r#""id":null"#));
+            assert!(answers[0].contains(r#""code":-32600"#));
+
+            // the submit of a worker not authorized fails, the subscribe is still answered
+            let batch = format!("[{},{}]", SUBSCRIBE, submit(2, "7"));
+            script.send(&batch).await.unwrap();
+            let answers = script.answers();
+            assert_eq!(answers.len(), 1);
+            let responses: Vec<serde_json::Value> = serde_json::from_str(&answers[0]).unwrap();
+            assert_eq!(responses.len(), 2);
+            assert_eq!(responses[0]["id"], 1);
+            assert!(responses[0]["error"].is_null());
+            assert_eq!(responses[1]["id"], 2);
+            assert_eq!(responses[1]["error"]["code"], 20);
+        });
+    }
+}
@@ -0,0 +1,191 @@
+//! Snapshots of the state of the process only kept in memory, written to `path` every
+//! `interval_secs` and once more on shutdown, and restored on the next start. A crash then loses
+//! seconds of it rather than all of it:
+//!
+//! - `accounts`, the work the accounts paid in the coinbase accrued since their last block (see
+//!   `crate::pool_mint::mining_pool::accounts`), the round of the coinbase;
+//! - `sessions`, the vardiff state and workers of the SV1 miners of the translator, open or
+//!   waiting to be resumed, so those reconnecting with their session token resume at the
+//!   difficulty they had (see `crate::proxy_wallet::downstream_sv1::SessionStore`).
+//!
+//! Shares credited at the mint are not part of it: they are committed within
+//! `batch_interval_ms` of being accepted (see `crate::pool_mint::mint::writer`), or journaled.
+//! Neither are the channels of the pool, which its miners open anew once reconnected.
+//!
+//! Parts `register` on start. A part registered again, the pool or the translator restarted in
+//! process, takes the state of the one it replaces rather than the snapshot.
+use crate::sim;
+use once_cell::sync::Lazy;
+use roles_logic_sv2::utils::Mutex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{collections::BTreeMap, fs, io, path::Path, sync::Arc, time::Duration};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+static PARTS: Lazy<Mutex<Parts>> = Lazy::new(|| Mutex::new(Parts::default()));
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct SnapshotConfig {
+    #[serde(default = "SnapshotConfig::default_path")]
+    pub path: String,
+    #[serde(default = "SnapshotConfig::default_interval_secs")]
+    pub interval_secs: u64,
+}
+
+impl SnapshotConfig {
+    fn default_path() -> String {
+        "snapshot.json".to_string()
+    }
+
+    fn default_interval_secs() -> u64 {
+        5
+    }
+}
+
+/// State of the process a snapshot saves, see the module.
+pub trait Snapshot: Send + Sync {
+    fn save(&self) -> Value;
+
+    fn restore(&self, state: Value) -> Result<(), String>;
+}
+
+#[derive(Default)]
+struct Parts {
+    registered: BTreeMap<&'static str, Arc<dyn Snapshot>>,
+    /// Read from the last snapshot, until their part registers.
+    recovered: BTreeMap<String, Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SnapshotFile {
+    taken_at: u64,
+    /// Taken on shutdown rather than while running.
+    clean: bool,
+    parts: BTreeMap<String, Value>,
+}
+
+/// Registers `part` as `name`, restoring its state if a snapshot or a part it replaces has one.
+pub fn register(name: &'static str, part: Arc<dyn Snapshot>) {
+    let replaced = PARTS.safe_lock(|parts| match parts.registered.insert(name, part.clone()) {
+        Some(previous) => Some(Ok(previous)),
+        None => parts.recovered.remove(name).map(Err),
+    });
+    let state = match replaced {
+        Ok(Some(Ok(previous))) => previous.save(),
+        Ok(Some(Err(recovered))) => recovered,
+        Ok(None) => return,
+        Err(e) => {
+            warn!("Snapshot: can't register {}: {}", name, e);
+            return;
+        }
+    };
+    match part.restore(state) {
+        Ok(()) => info!("Snapshot: restored {}", name),
+        Err(e) => warn!("Snapshot: can't restore {}: {}", name, e),
+    }
+}
+
+/// Reads the snapshot at `path` if any, for the parts to restore their state from as they
+/// register.
+pub fn recover(path: &str) -> io::Result<()> {
+    if !Path::new(path).exists() {
+        return Ok(());
+    }
+    let snapshot: SnapshotFile = serde_json::from_str(&fs::read_to_string(path)?)?;
+    let age = sim::unix_secs().saturating_sub(snapshot.taken_at);
+    match snapshot.clean {
+        true => info!(
+            "Snapshot: recovering the state saved on shutdown {}s ago",
+            age
+        ),
+        false => warn!(
+            "Snapshot: unclean exit, recovering the state of {}s ago, what came after is lost",
+            age
+        ),
+    }
+    PARTS
+        .safe_lock(|parts| parts.recovered = snapshot.parts)
+        .map_err(|e| io::Error::other(e.to_string()))
+}
+
+/// Writes a snapshot every `interval_secs` until `cancel_token` is cancelled.
+pub async fn run(config: SnapshotConfig, cancel_token: CancellationToken) {
+    let mut interval = tokio::time::interval(Duration::from_secs(config.interval_secs.max(1)));
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                if let Err(e) = write(&config.path, false) {
+                    warn!("Snapshot: can't write {}: {}", config.path, e);
+                }
+            }
+            _ = cancel_token.cancelled() => return,
+        }
+    }
+}
+
+/// Writes the snapshot of the parts registered to `path`, and the state recovered of those not
+/// registered yet, through a temporary file so a crash mid-write leaves the previous one.
+pub fn write(path: &str, clean: bool) -> io::Result<()> {
+    let (registered, mut parts) = PARTS
+        .safe_lock(|parts| (parts.registered.clone(), parts.recovered.clone()))
+        .map_err(|e| io::Error::other(e.to_string()))?;
+    for (name, part) in registered {
+        parts.insert(name.to_string(), part.save());
+    }
+    let snapshot = SnapshotFile {
+        taken_at: sim::unix_secs(),
+        clean,
+        parts,
+    };
+    let temporary = format!("{}.tmp", path);
+    fs::write(&temporary, serde_json::to_vec(&snapshot)?)?;
+    fs::rename(temporary, path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct Counter(Mutex<u64>);
+
+    fn counter() -> Arc<Counter> {
+        Arc::new(Counter(Mutex::new(0)))
+    }
+
+    impl Snapshot for Counter {
+        fn save(&self) -> Value {
+            self.0.safe_lock(|count| (*count).into()).unwrap()
+        }
+
+        fn restore(&self, state: Value) -> Result<(), String> {
+            let count = state.as_u64().ok_or("not a count")?;
+            self.0.safe_lock(|c| *c = count).map_err(|e| e.to_string())
+        }
+    }
+
+    #[test]
+    fn restores_a_crashed_process_then_a_restarted_part() {
+        let path = std::env::temp_dir().join(format!("potato-snapshot-{}", std::process::id()));
+        let path = path.to_string_lossy().to_string();
+        let crashed = counter();
+        register("counter", crashed.clone());
+        crashed.0.safe_lock(|c| *c = 7).unwrap();
+        write(&path, false).unwrap();
+        crashed.0.safe_lock(|c| *c = 8).unwrap();
+
+        // the next process recovers what was written
+        PARTS.safe_lock(|parts| parts.registered.clear()).unwrap();
+        recover(&path).unwrap();
+        let recovered = counter();
+        register("counter", recovered.clone());
+        assert_eq!(recovered.save(), Value::from(7));
+
+        // a restart in process carries the state over as it is
+        recovered.0.safe_lock(|c| *c = 9).unwrap();
+        let restarted = counter();
+        register("counter", restarted.clone());
+        assert_eq!(restarted.save(), Value::from(9));
+        fs::remove_file(path).unwrap();
+    }
+}
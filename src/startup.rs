@@ -0,0 +1,133 @@
+//! Ordered startup of the pool and the translator. The pool serves miners only once its template
+//! provider is connected, so a translator started along with it would retry the pool until then,
+//! logging every attempt that failed. The translator mining on the pool of the process rather
+//! waits for the pool to report on the event bus both its listener bound and its template
+//! provider connected (see `crate::status::events`) before connecting to it.
+//!
+//! Every run of the translator waits the same way: restarted while the pool waits for its
+//! template provider again, it waits with it. A translator mining on another pool doesn't wait.
+use crate::{
+    net,
+    pool_mint::mining_pool::PoolConfiguration,
+    proxy_wallet::proxy_config::ProxyConfig,
+    status::events::{self, Listener, Snapshot},
+};
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use tokio::sync::broadcast::error::RecvError;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+/// Whether the translator of `proxy` mines on the pool of `pool`, the one of the process.
+pub fn mines_on(proxy: &ProxyConfig, pool: &PoolConfiguration) -> bool {
+    // the pools an SRV record lists are others
+    if proxy.upstream_srv.is_some() {
+        return false;
+    }
+    let upstream = net::host_port(&proxy.upstream_address, proxy.upstream_port);
+    let (Ok(upstreams), Ok(listens)) = (
+        upstream.to_socket_addrs(),
+        pool.listen_address.to_socket_addrs(),
+    ) else {
+        return false;
+    };
+    let listens: Vec<_> = listens.collect();
+    upstreams
+        .into_iter()
+        .any(|upstream| listens.iter().any(|listen| reaches(upstream, *listen)))
+}
+
+/// Whether connecting to `upstream` reaches a listener on `listen`.
+fn reaches(upstream: SocketAddr, listen: SocketAddr) -> bool {
+    let ip = net::client_ip(upstream.ip());
+    let local = ip.is_loopback() || ip.is_unspecified();
+    let reaches = match listen.ip() {
+        // `[::]` accepts IPv4 clients too, see `crate::net`
+        IpAddr::V6(listen) if listen.is_unspecified() => local,
+        IpAddr::V4(listen) if listen.is_unspecified() => local && ip.is_ipv4(),
+        listen => ip == listen,
+    };
+    reaches && upstream.port() == listen.port()
+}
+
+/// Whether the pool of `snapshot` serves miners: its listener bound and its template provider
+/// connected.
+pub fn pool_ready(snapshot: &Snapshot) -> bool {
+    snapshot.node_ready && snapshot.listeners.contains_key(&Listener::Pool)
+}
+
+/// Waits for the pool of the process to serve miners, false if `cancel_token` is cancelled
+/// first.
+pub async fn wait_for_pool(cancel_token: &CancellationToken) -> bool {
+    // subscribed before reading the snapshot, so no event is missed in between
+    let mut events = events::subscribe();
+    if !pool_ready(&events::snapshot()) {
+        info!("Waiting for the pool to serve miners before connecting the translator");
+    }
+    loop {
+        if pool_ready(&events::snapshot()) {
+            return true;
+        }
+        tokio::select! {
+            event = events.recv() => {
+                if let Err(RecvError::Closed) = event {
+                    return false;
+                }
+            }
+            _ = cancel_token.cancelled() => return false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::configuration::{create_default_pool_config, create_default_proxy_config};
+    use events::{Event, EventBus};
+
+    #[test]
+    fn waits_for_the_pool_of_the_process_only() {
+        let mut pool = create_default_pool_config();
+        let mut proxy = create_default_proxy_config(&pool);
+        assert!(mines_on(&proxy, &pool));
+        proxy.upstream_port += 1;
+        assert!(!mines_on(&proxy, &pool));
+        proxy.upstream_port -= 1;
+        pool.listen_address = "10.0.0.1:34254".to_string();
+        assert!(!mines_on(&proxy, &pool));
+        proxy.upstream_address = "10.0.0.1".to_string();
+        assert!(mines_on(&proxy, &pool));
+
+        // over IPv6, the dual-stack listener reached over IPv4 too
+        pool.listen_address = "[::]:34254".to_string();
+        proxy.upstream_address = "::1".to_string();
+        assert!(mines_on(&proxy, &pool));
+        proxy.upstream_address = "127.0.0.1".to_string();
+        assert!(mines_on(&proxy, &pool));
+        pool.listen_address = "0.0.0.0:34254".to_string();
+        proxy.upstream_address = "::1".to_string();
+        assert!(!mines_on(&proxy, &pool));
+        pool.listen_address = "[::1]:34254".to_string();
+        assert!(mines_on(&proxy, &pool));
+        proxy.upstream_srv = Some("_sv2._tcp.pool.example.com".to_string());
+        assert!(!mines_on(&proxy, &pool));
+    }
+
+    #[test]
+    fn is_ready_once_bound_and_connected_to_the_template_provider() {
+        let bus = EventBus::new(16);
+        bus.publish(Event::NodeReady {
+            address: "127.0.0.1:8442".to_string(),
+        });
+        assert!(!pool_ready(&bus.snapshot()));
+        bus.publish(Event::ListenerBound {
+            listener: Listener::Pool,
+            address: "0.0.0.0:34254".to_string(),
+        });
+        assert!(pool_ready(&bus.snapshot()));
+        bus.publish(Event::UpstreamDown {
+            upstream: events::Upstream::TemplateProvider,
+            reason: "gone".to_string(),
+        });
+        assert!(!pool_ready(&bus.snapshot()));
+    }
+}
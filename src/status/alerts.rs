@@ -0,0 +1,588 @@
+//! Alerts of the operator, raised by rules watching the event bus and sent to every configured
+//! sink. The rules, each off unless configured but `block_found` and `component_stalled`:
+//!
+//! - `block_found`, a share of the pool met the network target;
+//! - `component_stalled`, a task of the process stopped beating, likely deadlocked, and again
+//!   once it beats (see `heartbeat`);
+//! - `node_unreachable_mins`, the template provider has been disconnected for that long, and
+//!   again once it is back;
+//! - `node_behind`, the node fell behind the chain tip of the references of `tip_lag`, and again
+//!   once it caught up (see `tip`);
+//! - `hashrate_drop_percent`, the hashrate fell that far below its mean of the last hour, and
+//!   again once it is back;
+//! - `worker_hashrate`, the hashrate of a worker strays from its baseline, see `anomaly`;
+//! - `mint_insolvency`, the books of the mint audited every `audit_interval_secs` show a
+//!   discrepancy, see `potato mint audit`.
+//!
+//! The sinks: webhooks posted the alert as JSON, a Telegram bot, email through an SMTP relay and
+//! Nostr direct messages to the operator's key.
+use super::{
+    anomaly::{WorkerHashrateConfig, WorkerHashrates},
+    events::{self, Event, Snapshot, Upstream},
+};
+use crate::pool_mint::mint::{
+    audit,
+    nostr::{NostrConfig, NostrDelivery},
+    MintConfig,
+};
+use lettre::{
+    message::Mailbox, transport::smtp::authentication::Credentials, AsyncSmtpTransport,
+    AsyncTransport, Message, Tokio1Executor,
+};
+use secp256k1::XOnlyPublicKey;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{collections::VecDeque, str::FromStr, sync::Arc, time::Duration};
+use tokio::sync::broadcast::error::RecvError;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+/// How often the node and the hashrate are checked.
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+/// Checks of the hashrate its mean is taken over, an hour of them.
+const HASHRATE_SAMPLES: usize = 60;
+/// Checks of the hashrate needed before a drop is told from a start.
+const MIN_HASHRATE_SAMPLES: usize = 10;
+/// Seconds a sink has to take an alert.
+const SEND_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AlertConfig {
+    pub block_found: bool,
+    pub component_stalled: bool,
+    pub node_unreachable_mins: Option<u64>,
+    pub node_behind: bool,
+    pub hashrate_drop_percent: Option<f64>,
+    pub worker_hashrate: Option<WorkerHashrateConfig>,
+    pub mint_insolvency: bool,
+    pub audit_interval_secs: u64,
+    /// URLs every alert is posted to as JSON.
+    pub webhooks: Vec<String>,
+    pub telegram: Option<TelegramConfig>,
+    pub smtp: Option<SmtpConfig>,
+    pub nostr: Option<NostrAlertConfig>,
+}
+
+impl Default for AlertConfig {
+    fn default() -> Self {
+        Self {
+            block_found: true,
+            component_stalled: true,
+            node_unreachable_mins: None,
+            node_behind: true,
+            hashrate_drop_percent: None,
+            worker_hashrate: None,
+            mint_insolvency: false,
+            audit_interval_secs: 3600,
+            webhooks: vec![],
+            telegram: None,
+            smtp: None,
+            nostr: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TelegramConfig {
+    pub bot_token: String,
+    pub chat_id: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SmtpConfig {
+    /// Relay alerts are sent through with STARTTLS, e.g. `smtp.example.com`.
+    pub server: String,
+    #[serde(default = "SmtpConfig::default_port")]
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub from: String,
+    pub to: Vec<String>,
+}
+
+impl SmtpConfig {
+    fn default_port() -> u16 {
+        587
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct NostrAlertConfig {
+    /// Hex public key of the operator alerts are sent to.
+    pub recipient: String,
+    #[serde(flatten)]
+    pub relays: NostrConfig,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertKind {
+    BlockFound,
+    ComponentStalled,
+    ComponentRecovered,
+    NodeUnreachable,
+    NodeRecovered,
+    NodeBehind,
+    NodeCaughtUp,
+    HashrateDrop,
+    HashrateRecovered,
+    MintInsolvent,
+    MintSolvent,
+    WorkerHashrateDrop,
+    WorkerHashrateRecovered,
+    WorkerIdle,
+    WorkerMining,
+    WorkerHashrateSpike,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Alert {
+    pub kind: AlertKind,
+    pub message: String,
+}
+
+impl Alert {
+    pub(super) fn new(kind: AlertKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+        }
+    }
+}
+
+/// State of the rules between events and checks. Every alert but `block_found` is raised once,
+/// until its condition clears.
+#[derive(Debug)]
+struct Rules {
+    config: AlertConfig,
+    /// Since when the template provider is disconnected, from the start until it connects.
+    node_down_since: Option<u64>,
+    node_alerted: bool,
+    hashrate_samples: VecDeque<f64>,
+    hashrate_alerted: bool,
+    workers: Option<WorkerHashrates>,
+    insolvency_alerted: bool,
+}
+
+impl Rules {
+    fn new(config: AlertConfig, now: u64) -> Self {
+        Self {
+            workers: config.worker_hashrate.clone().map(WorkerHashrates::new),
+            config,
+            node_down_since: Some(now),
+            node_alerted: false,
+            hashrate_samples: VecDeque::new(),
+            hashrate_alerted: false,
+            insolvency_alerted: false,
+        }
+    }
+
+    fn on_event(&mut self, event: &Event, now: u64) -> Option<Alert> {
+        match event {
+            Event::BlockFound {
+                coinbase_txid,
+                reward,
+            } if self.config.block_found => Some(Alert::new(
+                AlertKind::BlockFound,
+                format!(
+                    "Block found, {} sat reward in coinbase {}",
+                    reward, coinbase_txid
+                ),
+            )),
+            Event::ComponentStalled {
+                component,
+                silent_secs,
+            } if self.config.component_stalled => Some(Alert::new(
+                AlertKind::ComponentStalled,
+                format!(
+                    "Critical: {} stuck, no heartbeat for {} seconds",
+                    component, silent_secs
+                ),
+            )),
+            Event::ComponentRecovered { component } if self.config.component_stalled => {
+                Some(Alert::new(
+                    AlertKind::ComponentRecovered,
+                    format!("{} no longer stuck", component),
+                ))
+            }
+            Event::NodeReady { address } => {
+                self.node_down_since = None;
+                std::mem::take(&mut self.node_alerted).then(|| {
+                    Alert::new(
+                        AlertKind::NodeRecovered,
+                        format!("Template provider {} reconnected", address),
+                    )
+                })
+            }
+            Event::NodeBehind {
+                height,
+                reference,
+                reference_height,
+                stale_secs,
+            } if self.config.node_behind => Some(Alert::new(
+                AlertKind::NodeBehind,
+                format!(
+                    "Node at height {} is {} blocks behind {}, its tip {} minutes stale: \
+                     hashrate is wasted",
+                    height,
+                    reference_height.saturating_sub(*height),
+                    reference,
+                    stale_secs / 60
+                ),
+            )),
+            Event::NodeCaughtUp { height } if self.config.node_behind => Some(Alert::new(
+                AlertKind::NodeCaughtUp,
+                format!("Node caught up at height {}", height),
+            )),
+            Event::UpstreamDown {
+                upstream: Upstream::TemplateProvider,
+                ..
+            } => {
+                self.node_down_since.get_or_insert(now);
+                None
+            }
+            _ => None,
+        }
+    }
+
+    fn on_check(&mut self, snapshot: &Snapshot, now: u64) -> Vec<Alert> {
+        let mut alerts = vec![];
+        if let (Some(mins), Some(since)) = (self.config.node_unreachable_mins, self.node_down_since)
+        {
+            let down = now.saturating_sub(since);
+            if down >= mins * 60 && !self.node_alerted {
+                self.node_alerted = true;
+                let reason = snapshot
+                    .upstreams_down
+                    .get(&Upstream::TemplateProvider)
+                    .map_or("never connected".to_string(), |outage| {
+                        outage.reason.clone()
+                    });
+                alerts.push(Alert::new(
+                    AlertKind::NodeUnreachable,
+                    format!(
+                        "Template provider unreachable for {} minutes: {}",
+                        down / 60,
+                        reason
+                    ),
+                ));
+            }
+        }
+        if let Some(percent) = self.config.hashrate_drop_percent {
+            let hashrate = snapshot.hashrate(now);
+            if self.hashrate_samples.len() >= MIN_HASHRATE_SAMPLES {
+                let mean =
+                    self.hashrate_samples.iter().sum::<f64>() / self.hashrate_samples.len() as f64;
+                let dropped = hashrate < mean * (1.0 - percent / 100.0);
+                if dropped && !self.hashrate_alerted {
+                    self.hashrate_alerted = true;
+                    alerts.push(Alert::new(
+                        AlertKind::HashrateDrop,
+                        format!(
+                            "Hashrate dropped to {:.0} H/s, {:.0}% below its mean of {:.0} H/s",
+                            hashrate,
+                            (1.0 - hashrate / mean) * 100.0,
+                            mean
+                        ),
+                    ));
+                } else if !dropped && self.hashrate_alerted {
+                    self.hashrate_alerted = false;
+                    alerts.push(Alert::new(
+                        AlertKind::HashrateRecovered,
+                        format!("Hashrate back to {:.0} H/s", hashrate),
+                    ));
+                }
+            }
+            self.hashrate_samples.push_back(hashrate);
+            while self.hashrate_samples.len() > HASHRATE_SAMPLES {
+                self.hashrate_samples.pop_front();
+            }
+        }
+        if let Some(workers) = &mut self.workers {
+            alerts.extend(workers.check(&snapshot.workers, now));
+        }
+        alerts
+    }
+
+    fn on_audit(&mut self, discrepancies: &[String]) -> Option<Alert> {
+        match (discrepancies.is_empty(), self.insolvency_alerted) {
+            (false, false) => {
+                self.insolvency_alerted = true;
+                Some(Alert::new(
+                    AlertKind::MintInsolvent,
+                    format!("Mint audit found: {}", discrepancies.join("; ")),
+                ))
+            }
+            (true, true) => {
+                self.insolvency_alerted = false;
+                Some(Alert::new(
+                    AlertKind::MintSolvent,
+                    "Mint audit found no discrepancies anymore",
+                ))
+            }
+            _ => None,
+        }
+    }
+}
+
+enum Sink {
+    Webhook(String),
+    Telegram(TelegramConfig),
+    Smtp {
+        transport: AsyncSmtpTransport<Tokio1Executor>,
+        from: Mailbox,
+        to: Vec<Mailbox>,
+    },
+    Nostr {
+        delivery: NostrDelivery,
+        recipient: XOnlyPublicKey,
+    },
+}
+
+impl Sink {
+    /// The sinks of `config`, those misconfigured left out.
+    fn all(config: &AlertConfig) -> Vec<Sink> {
+        let mut sinks: Vec<_> = config.webhooks.iter().cloned().map(Sink::Webhook).collect();
+        if let Some(telegram) = &config.telegram {
+            sinks.push(Sink::Telegram(telegram.clone()));
+        }
+        if let Some(smtp) = &config.smtp {
+            match Self::smtp(smtp) {
+                Ok(sink) => sinks.push(sink),
+                Err(e) => error!("Alerts: SMTP sink disabled: {}", e),
+            }
+        }
+        if let Some(nostr) = &config.nostr {
+            let sink = XOnlyPublicKey::from_str(&nostr.recipient)
+                .map_err(|e| e.to_string())
+                .and_then(|recipient| {
+                    let delivery = NostrDelivery::new(&nostr.relays).map_err(|e| e.to_string())?;
+                    Ok(Sink::Nostr {
+                        delivery,
+                        recipient,
+                    })
+                });
+            match sink {
+                Ok(sink) => sinks.push(sink),
+                Err(e) => error!("Alerts: Nostr sink disabled: {}", e),
+            }
+        }
+        sinks
+    }
+
+    fn smtp(config: &SmtpConfig) -> Result<Sink, String> {
+        let mut transport = AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.server)
+            .map_err(|e| e.to_string())?
+            .port(config.port);
+        if let (Some(username), Some(password)) = (&config.username, &config.password) {
+            transport = transport.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+        let to = config
+            .to
+            .iter()
+            .map(|to| to.parse::<Mailbox>().map_err(|e| e.to_string()))
+            .collect::<Result<_, _>>()?;
+        Ok(Sink::Smtp {
+            transport: transport.build(),
+            from: config
+                .from
+                .parse()
+                .map_err(|e: lettre::address::AddressError| e.to_string())?,
+            to,
+        })
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Sink::Webhook(_) => "webhook",
+            Sink::Telegram(_) => "Telegram",
+            Sink::Smtp { .. } => "SMTP",
+            Sink::Nostr { .. } => "Nostr",
+        }
+    }
+
+    async fn send(&self, client: &reqwest::Client, alert: &Alert) -> Result<(), String> {
+        match self {
+            Sink::Webhook(url) => {
+                client
+                    .post(url)
+                    .json(alert)
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status())
+                    .map_err(|e| e.to_string())?;
+            }
+            Sink::Telegram(telegram) => {
+                let url = format!(
+                    "https://api.telegram.org/bot{}/sendMessage",
+                    telegram.bot_token
+                );
+                let body = json!({ "chat_id": telegram.chat_id, "text": alert.message });
+                client
+                    .post(url)
+                    .json(&body)
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status())
+                    // the URL holds the token
+                    .map_err(|e| e.without_url().to_string())?;
+            }
+            Sink::Smtp {
+                transport,
+                from,
+                to,
+            } => {
+                let mut message = Message::builder()
+                    .from(from.clone())
+                    .subject(format!("potato: {}", alert.message));
+                for to in to {
+                    message = message.to(to.clone());
+                }
+                let message = message
+                    .body(alert.message.clone())
+                    .map_err(|e| e.to_string())?;
+                transport.send(message).await.map_err(|e| e.to_string())?;
+            }
+            Sink::Nostr {
+                delivery,
+                recipient,
+            } => {
+                delivery
+                    .send(recipient, &alert.message)
+                    .await
+                    .map_err(|e| e.to_string())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Raises the alerts of `config` until `cancel_token` is cancelled, auditing the mint of `mint`.
+pub async fn run(config: AlertConfig, mint: MintConfig, cancel_token: CancellationToken) {
+    let sinks = Arc::new(Sink::all(&config));
+    if sinks.is_empty() {
+        warn!("Alerts: no sink configured, alerts are only logged");
+    }
+    let client = match reqwest::Client::builder().timeout(SEND_TIMEOUT).build() {
+        Ok(client) => client,
+        Err(e) => {
+            error!("Alerts: {}", e);
+            return;
+        }
+    };
+    let mut events = events::subscribe();
+    let mut checks = tokio::time::interval(CHECK_INTERVAL);
+    let mut audits = tokio::time::interval(Duration::from_secs(config.audit_interval_secs.max(60)));
+    let mint_insolvency = config.mint_insolvency;
+    let mut rules = Rules::new(config, events::now());
+    loop {
+        let alerts = tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) => rules.on_event(&event, events::now()).into_iter().collect(),
+                Err(RecvError::Lagged(_)) => vec![],
+                Err(RecvError::Closed) => break,
+            },
+            _ = checks.tick() => rules.on_check(&events::snapshot(), events::now()),
+            _ = audits.tick(), if mint_insolvency => {
+                let mint = mint.clone();
+                match tokio::task::spawn_blocking(move || audit::books(&mint)).await {
+                    Ok(Ok(report)) => rules.on_audit(&report.discrepancies).into_iter().collect(),
+                    Ok(Err(e)) => {
+                        warn!("Alerts: mint audit failed: {}", e);
+                        vec![]
+                    }
+                    Err(_) => vec![],
+                }
+            },
+            _ = cancel_token.cancelled() => break,
+        };
+        for alert in alerts {
+            info!("Alert: {}", alert.message);
+            let (sinks, client) = (sinks.clone(), client.clone());
+            tokio::spawn(async move {
+                for sink in sinks.iter() {
+                    if let Err(e) = sink.send(&client, &alert).await {
+                        warn!("Alerts: {} sink failed: {}", sink.name(), e);
+                    }
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::status::events::EventBus;
+
+    #[test]
+    fn raises_each_alert_once_until_it_clears() {
+        let config = AlertConfig {
+            node_unreachable_mins: Some(10),
+            hashrate_drop_percent: Some(50.0),
+            ..Default::default()
+        };
+        let mut rules = Rules::new(config, 0);
+        let block = Event::BlockFound {
+            coinbase_txid: "ab".repeat(32),
+            reward: 312_500_000,
+        };
+        assert_eq!(
+            rules.on_event(&block, 0).map(|alert| alert.kind),
+            Some(AlertKind::BlockFound)
+        );
+        let stalled = Event::ComponentStalled {
+            component: "mint".into(),
+            silent_secs: 30,
+        };
+        assert_eq!(
+            rules.on_event(&stalled, 0).map(|alert| alert.kind),
+            Some(AlertKind::ComponentStalled)
+        );
+
+        // the node never connected since the start
+        let snapshot = Snapshot::default();
+        assert!(rules.on_check(&snapshot, 599).is_empty());
+        let alerts = rules.on_check(&snapshot, 600);
+        assert_eq!(alerts[0].kind, AlertKind::NodeUnreachable);
+        assert!(rules.on_check(&snapshot, 660).is_empty());
+        let ready = Event::NodeReady {
+            address: "127.0.0.1:8442".into(),
+        };
+        assert_eq!(
+            rules.on_event(&ready, 700).map(|alert| alert.kind),
+            Some(AlertKind::NodeRecovered)
+        );
+
+        // a share over the window, then none
+        let bus = EventBus::new(16);
+        bus.publish(Event::ShareAccepted {
+            channel_id: 1,
+            account: "alice".into(),
+            weight: 600,
+        });
+        let mut mining = bus.snapshot();
+        mining.started_at = Some(0);
+        let now = mining.share_window[0].0;
+        rules.hashrate_samples = vec![mining.hashrate(now); MIN_HASHRATE_SAMPLES].into();
+        assert!(rules.on_check(&mining, now).is_empty());
+        let idle = Snapshot {
+            started_at: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(rules.on_check(&idle, now)[0].kind, AlertKind::HashrateDrop);
+        assert!(rules.on_check(&idle, now).is_empty());
+
+        let discrepancies = vec!["held reserves fall short".to_string()];
+        assert_eq!(
+            rules.on_audit(&discrepancies).map(|alert| alert.kind),
+            Some(AlertKind::MintInsolvent)
+        );
+        assert_eq!(rules.on_audit(&discrepancies), None);
+        assert_eq!(
+            rules.on_audit(&[]).map(|alert| alert.kind),
+            Some(AlertKind::MintSolvent)
+        );
+    }
+}
@@ -0,0 +1,325 @@
+//! Hashrate anomalies of every worker, raised through the alerts (see `alerts`). Every check the
+//! hashrate of a worker is taken from the weight of the shares it had accepted since the last
+//! one, and its baseline learned as their mean over the last `baseline_mins`. Once learned over
+//! `MIN_SAMPLES` checks, alerts are raised once until their condition clears:
+//!
+//! - a drop, the mean of the last `window_mins` checks falls `drop_percent` below the baseline;
+//! - an idle worker, no share accepted for `idle_mins`;
+//! - a spike, a check `spike_factor` times over the baseline, or over `max_hashrate` whether the
+//!   baseline is learned or not. A worker doesn't gain that much hashrate from a minute to the
+//!   next, it is misconfigured or forging shares.
+//!
+//! The baseline is not learned from while an alert is raised, so a worker that drops or spikes
+//! has to get back to what it was. Workers idle for `FORGET_AFTER_SECS` are forgotten.
+use super::{
+    alerts::{Alert, AlertKind},
+    events::Worker,
+    server::hashrate,
+};
+use serde::Deserialize;
+use std::collections::{BTreeMap, VecDeque};
+
+/// Checks the baseline of a worker is learned over before alerting.
+pub const MIN_SAMPLES: u32 = 10;
+/// Seconds without a share after which a worker is forgotten, a day.
+pub const FORGET_AFTER_SECS: u64 = 86_400;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct WorkerHashrateConfig {
+    #[serde(default = "WorkerHashrateConfig::default_baseline_mins")]
+    pub baseline_mins: u64,
+    #[serde(default = "WorkerHashrateConfig::default_drop_percent")]
+    pub drop_percent: f64,
+    #[serde(default = "WorkerHashrateConfig::default_window_mins")]
+    pub window_mins: u64,
+    #[serde(default = "WorkerHashrateConfig::default_idle_mins")]
+    pub idle_mins: u64,
+    #[serde(default = "WorkerHashrateConfig::default_spike_factor")]
+    pub spike_factor: f64,
+    /// Hashes per second no worker can reach, e.g. `1e18`.
+    #[serde(default)]
+    pub max_hashrate: Option<f64>,
+}
+
+impl WorkerHashrateConfig {
+    fn default_baseline_mins() -> u64 {
+        60
+    }
+
+    fn default_drop_percent() -> f64 {
+        50.0
+    }
+
+    fn default_window_mins() -> u64 {
+        10
+    }
+
+    fn default_idle_mins() -> u64 {
+        15
+    }
+
+    fn default_spike_factor() -> f64 {
+        10.0
+    }
+}
+
+/// What is known of a worker as of the last check.
+#[derive(Debug, Clone, Default)]
+struct WorkerState {
+    weight: u64,
+    baseline: f64,
+    samples: u32,
+    /// Hashrate of the last `window_mins` checks, spikes left out.
+    recent: VecDeque<f64>,
+    dropped: bool,
+    idle: bool,
+    spiked: bool,
+}
+
+/// Learns the baseline of every worker and tells when one strays from it.
+#[derive(Debug)]
+pub struct WorkerHashrates {
+    config: WorkerHashrateConfig,
+    workers: BTreeMap<String, WorkerState>,
+    checked_at: Option<u64>,
+}
+
+impl WorkerHashrates {
+    pub fn new(config: WorkerHashrateConfig) -> Self {
+        Self {
+            config,
+            workers: BTreeMap::new(),
+            checked_at: None,
+        }
+    }
+
+    /// The alerts of `workers` at `now`, the shares accepted per account of the snapshot.
+    pub fn check(&mut self, workers: &BTreeMap<String, Worker>, now: u64) -> Vec<Alert> {
+        let elapsed = self
+            .checked_at
+            .replace(now)
+            .map(|at| now.saturating_sub(at))
+            .filter(|elapsed| *elapsed > 0);
+        let mut alerts = vec![];
+        for (account, worker) in workers {
+            if now.saturating_sub(worker.last_share_at) >= FORGET_AFTER_SECS {
+                self.workers.remove(account);
+                continue;
+            }
+            let state = match self.workers.get_mut(account) {
+                Some(state) => state,
+                // known from the next check on
+                None => {
+                    let state = WorkerState {
+                        weight: worker.weight,
+                        ..Default::default()
+                    };
+                    self.workers.insert(account.clone(), state);
+                    continue;
+                }
+            };
+            let Some(elapsed) = elapsed else {
+                state.weight = worker.weight;
+                continue;
+            };
+            let sample =
+                worker.weight.saturating_sub(state.weight) as f64 * 2f64.powi(32) / elapsed as f64;
+            state.weight = worker.weight;
+            alerts.extend(Self::observe(
+                &self.config,
+                account,
+                state,
+                sample,
+                now.saturating_sub(worker.last_share_at),
+                elapsed,
+            ));
+        }
+        alerts
+    }
+
+    /// The alerts of `account` hashing at `sample` over the last `elapsed` seconds, its last
+    /// share `silent_secs` ago.
+    fn observe(
+        config: &WorkerHashrateConfig,
+        account: &str,
+        state: &mut WorkerState,
+        sample: f64,
+        silent_secs: u64,
+        elapsed: u64,
+    ) -> Vec<Alert> {
+        let mut alerts = vec![];
+        let learned = state.samples >= MIN_SAMPLES;
+
+        let mut limit = config.max_hashrate.unwrap_or(f64::INFINITY);
+        if learned {
+            limit = limit.min(state.baseline * config.spike_factor);
+        }
+        let spiked = sample > limit;
+        if spiked && !state.spiked {
+            alerts.push(Alert::new(
+                AlertKind::WorkerHashrateSpike,
+                format!(
+                    "Hashrate of {} jumped to {} against a baseline of {}, misconfigured or \
+                     forging shares",
+                    account,
+                    hashrate(sample),
+                    hashrate(state.baseline)
+                ),
+            ));
+        }
+        state.spiked = spiked;
+
+        let idle = learned && silent_secs >= config.idle_mins * 60;
+        if idle && !state.idle {
+            alerts.push(Alert::new(
+                AlertKind::WorkerIdle,
+                format!("No share of {} for {} minutes", account, silent_secs / 60),
+            ));
+        } else if !idle && state.idle {
+            alerts.push(Alert::new(
+                AlertKind::WorkerMining,
+                format!("{} mines again", account),
+            ));
+        }
+        state.idle = idle;
+
+        if !spiked {
+            state.recent.push_back(sample);
+        }
+        let window = config.window_mins.max(1) as usize;
+        while state.recent.len() > window {
+            state.recent.pop_front();
+        }
+        let mean = state.recent.iter().sum::<f64>() / state.recent.len().max(1) as f64;
+        let dropped = learned
+            && state.recent.len() >= window
+            && mean < state.baseline * (1.0 - config.drop_percent / 100.0);
+        if dropped && !state.dropped {
+            alerts.push(Alert::new(
+                AlertKind::WorkerHashrateDrop,
+                format!(
+                    "Hashrate of {} dropped to {}, {:.0}% below its baseline of {}",
+                    account,
+                    hashrate(mean),
+                    (1.0 - mean / state.baseline) * 100.0,
+                    hashrate(state.baseline)
+                ),
+            ));
+        } else if !dropped && state.dropped {
+            alerts.push(Alert::new(
+                AlertKind::WorkerHashrateRecovered,
+                format!("Hashrate of {} back to {}", account, hashrate(mean)),
+            ));
+        }
+        state.dropped = dropped;
+
+        if !spiked && !idle && !dropped {
+            // the mean of about the last `baseline_mins`
+            let weight = (elapsed as f64 / (config.baseline_mins.max(1) * 60) as f64).min(1.0);
+            state.baseline = match state.samples {
+                0 => sample,
+                _ => state.baseline + (sample - state.baseline) * weight,
+            };
+            state.samples = state.samples.saturating_add(1);
+        }
+        alerts
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn mine(workers: &mut BTreeMap<String, Worker>, account: &str, weight: u64, now: u64) {
+        let worker = workers.entry(account.to_string()).or_default();
+        worker.weight += weight;
+        worker.last_share_at = now;
+    }
+
+    fn check(
+        detector: &mut WorkerHashrates,
+        workers: &BTreeMap<String, Worker>,
+        now: u64,
+    ) -> Vec<AlertKind> {
+        let alerts = detector.check(workers, now);
+        alerts.into_iter().map(|alert| alert.kind).collect()
+    }
+
+    #[test]
+    fn alerts_on_drops_idle_workers_and_spikes() {
+        let config = WorkerHashrateConfig {
+            baseline_mins: 60,
+            drop_percent: 50.0,
+            window_mins: 5,
+            idle_mins: 15,
+            spike_factor: 10.0,
+            max_hashrate: Some(1e18),
+        };
+        let mut detector = WorkerHashrates::new(config);
+        let mut workers = BTreeMap::new();
+        // a weight of 60 a minute, 2^32 hashes a second
+        let mut now = 0;
+        for _ in 0..=MIN_SAMPLES + 1 {
+            now += 60;
+            mine(&mut workers, "alice", 60, now);
+            assert!(check(&mut detector, &workers, now).is_empty());
+        }
+
+        // a quarter of the baseline, below half of it on average from the fourth minute
+        for minute in 1..=5 {
+            now += 60;
+            mine(&mut workers, "alice", 15, now);
+            let alerts = check(&mut detector, &workers, now);
+            match minute {
+                4 => assert_eq!(alerts, vec![AlertKind::WorkerHashrateDrop]),
+                _ => assert!(alerts.is_empty()),
+            }
+        }
+        let mut alerts = vec![];
+        for _ in 0..5 {
+            now += 60;
+            mine(&mut workers, "alice", 60, now);
+            alerts.extend(check(&mut detector, &workers, now));
+        }
+        assert_eq!(alerts, vec![AlertKind::WorkerHashrateRecovered]);
+
+        // no share since
+        let mut alerts = vec![];
+        for _ in 0..15 {
+            now += 60;
+            alerts.extend(check(&mut detector, &workers, now));
+        }
+        assert_eq!(
+            alerts,
+            vec![AlertKind::WorkerHashrateDrop, AlertKind::WorkerIdle]
+        );
+        now += 60;
+        mine(&mut workers, "alice", 60, now);
+        assert_eq!(
+            check(&mut detector, &workers, now),
+            vec![AlertKind::WorkerMining]
+        );
+
+        // a hundred times the baseline, alerted once
+        for _ in 0..2 {
+            now += 60;
+            mine(&mut workers, "alice", 6000, now);
+            alerts.extend(check(&mut detector, &workers, now));
+        }
+        assert_eq!(alerts.last(), Some(&AlertKind::WorkerHashrateSpike));
+        assert_eq!(alerts.len(), 3);
+
+        // impossible from the first check of a worker on
+        mine(&mut workers, "bob", 1, now);
+        now += 60;
+        check(&mut detector, &workers, now);
+        now += 60;
+        mine(&mut workers, "bob", 1 << 40, now);
+        assert!(check(&mut detector, &workers, now).contains(&AlertKind::WorkerHashrateSpike));
+
+        // forgotten a day later
+        now += FORGET_AFTER_SECS;
+        check(&mut detector, &workers, now);
+        assert!(detector.workers.is_empty());
+    }
+}
@@ -0,0 +1,230 @@
+//! Audit log of the connections of the listeners miners reach, the pool and the translator, for
+//! security review of those exposed to the internet. Every connection accepted or refused, its
+//! handshake, the protocol and the device it set up with, the identities it authorized and why
+//! it disconnected are appended to `path` as JSON lines with the peer address, e.g.
+//!
+//! `{"at_ms":1760000000000,"listener":"pool","peer":"203.0.113.7:51000","event":"authorized",
+//! "connection_id":1,"channel_id":1,"identity":"alice"}`
+//!
+//! Unlike the event history (see `history`) the file is never trimmed, it is meant to be rotated
+//! and shipped by the operator. Records are written by a task of their own so connections never
+//! wait on the disk, at most `QUEUE_CAPACITY` of them queued.
+use super::events::Listener;
+use once_cell::sync::Lazy;
+use roles_logic_sv2::utils::Mutex;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufWriter, Write},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+/// Records queued for the writer before new ones are dropped.
+pub const QUEUE_CAPACITY: usize = 10_000;
+
+static AUDIT: Lazy<Mutex<Option<mpsc::Sender<AuditRecord>>>> = Lazy::new(|| Mutex::new(None));
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AuditConfig {
+    #[serde(default = "AuditConfig::default_path")]
+    pub path: String,
+}
+
+impl AuditConfig {
+    fn default_path() -> String {
+        "audit.jsonl".to_string()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AuditRecord {
+    pub at_ms: u64,
+    pub listener: Listener,
+    pub peer: String,
+    #[serde(flatten)]
+    pub event: AuditEvent,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AuditEvent {
+    /// The listener accepted a TCP connection.
+    Accepted,
+    /// The listener closed a connection right away, e.g. while draining.
+    Refused {
+        reason: String,
+    },
+    /// The connection completed its handshake of `protocol`, e.g. `noise_nx`.
+    Handshake {
+        protocol: String,
+    },
+    HandshakeFailed {
+        reason: String,
+    },
+    /// The connection set up `protocol` at `version` with the device `user_agent` announced.
+    Setup {
+        connection_id: Option<u32>,
+        protocol: String,
+        version: Option<u16>,
+        user_agent: String,
+    },
+    /// The connection opened a channel, or authorized a worker, as `identity`.
+    Authorized {
+        connection_id: u32,
+        channel_id: Option<u32>,
+        identity: String,
+    },
+    Disconnected {
+        connection_id: Option<u32>,
+        reason: String,
+    },
+}
+
+/// Appends `event` of `peer` on `listener` to the audit log, if one is kept.
+pub fn record(listener: Listener, peer: impl ToString, event: AuditEvent) {
+    let record = AuditRecord {
+        at_ms: now_ms(),
+        listener,
+        peer: peer.to_string(),
+        event,
+    };
+    let _ = AUDIT.safe_lock(|audit| {
+        if let Some(sender) = audit {
+            if sender.try_send(record).is_err() {
+                warn!("Audit log queue full, dropped a record");
+            }
+        }
+    });
+}
+
+/// The audit log, written once run.
+#[derive(Debug)]
+pub struct AuditLog {
+    path: String,
+    file: BufWriter<File>,
+    records: mpsc::Receiver<AuditRecord>,
+}
+
+impl AuditLog {
+    /// Opens the audit log of `config`, appending to it, and records to it from now on.
+    pub fn open(config: &AuditConfig) -> io::Result<Self> {
+        let mut options = OpenOptions::new();
+        options.append(true).create(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let file = BufWriter::new(options.open(&config.path)?);
+        let (sender, records) = mpsc::channel(QUEUE_CAPACITY);
+        AUDIT
+            .safe_lock(|audit| *audit = Some(sender))
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        Ok(Self {
+            path: config.path.clone(),
+            file,
+            records,
+        })
+    }
+
+    /// Writes the records until `cancel_token` is cancelled, then those still queued.
+    pub async fn run(mut self, cancel_token: CancellationToken) {
+        info!("Recording the audit log to {}", self.path);
+        loop {
+            let record = tokio::select! {
+                record = self.records.recv() => record,
+                _ = cancel_token.cancelled() => None,
+            };
+            let Some(record) = record else {
+                break;
+            };
+            self.write(&record);
+            while let Ok(record) = self.records.try_recv() {
+                self.write(&record);
+            }
+            if let Err(e) = self.file.flush() {
+                warn!("Failed to write the audit log {}: {}", self.path, e);
+            }
+        }
+        let _ = AUDIT.safe_lock(|audit| *audit = None);
+        while let Ok(record) = self.records.try_recv() {
+            self.write(&record);
+        }
+        if let Err(e) = self.file.flush() {
+            warn!("Failed to write the audit log {}: {}", self.path, e);
+        }
+    }
+
+    fn write(&mut self, record: &AuditRecord) {
+        let Ok(line) = serde_json::to_string(record) else {
+            return;
+        };
+        if let Err(e) = writeln!(self.file, "{}", line) {
+            warn!("Failed to write the audit log {}: {}", self.path, e);
+        }
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::Value;
+
+    #[tokio::test]
+    async fn appends_a_line_per_connection_event() {
+        let dir = std::env::temp_dir().join(format!("potato-audit-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("audit.jsonl");
+        let _ = std::fs::remove_file(&path);
+        let config = AuditConfig {
+            path: path.to_str().unwrap().to_string(),
+        };
+
+        let cancel_token = CancellationToken::new();
+        let log = AuditLog::open(&config).unwrap();
+        let peer = "203.0.113.7:51000";
+        record(Listener::Pool, peer, AuditEvent::Accepted);
+        record(
+            Listener::Pool,
+            peer,
+            AuditEvent::Authorized {
+                connection_id: 1,
+                channel_id: Some(1),
+                identity: "alice".to_string(),
+            },
+        );
+        record(
+            Listener::Pool,
+            peer,
+            AuditEvent::Disconnected {
+                connection_id: Some(1),
+                reason: "kicked".to_string(),
+            },
+        );
+        cancel_token.cancel();
+        log.run(cancel_token).await;
+        // not recorded once the log stopped
+        record(Listener::Translator, peer, AuditEvent::Accepted);
+
+        let lines: Vec<Value> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0]["event"], "accepted");
+        assert_eq!(lines[1]["listener"], "pool");
+        assert_eq!(lines[1]["peer"], peer);
+        assert_eq!(lines[1]["identity"], "alice");
+        assert_eq!(lines[2]["event"], "disconnected");
+        assert_eq!(lines[2]["reason"], "kicked");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,224 @@
+//! Runtime diagnostics, to tell where async work stalls in the share pipeline in production:
+//!
+//! - `/v1/diagnostics` of the status API dumps the tasks of the tokio runtime, the heartbeats of
+//!   the long running tasks (see `heartbeat`) and how full the channels of the share pipeline
+//!   are: `translator_submissions` from the miners to the bridge of the translator,
+//!   `translator_upstream` from the bridge to the pool and `pool_solutions` from the pool to the
+//!   template provider;
+//! - `--tokio-console <address>` serves tokio-console, showing every task with its polls, wakes
+//!   and the resources it waits on. It is opt in at build time too, instrumenting tokio costs: it
+//!   needs a build with `--features tokio-console` and `RUSTFLAGS="--cfg tokio_unstable"`.
+//!
+//! A channel is watched for as long as the `ChannelProbe` created with one of its senders is held
+//! by what owns the sender, so a channel closes as it would without the probe.
+use super::heartbeat::{self, TaskBeat};
+use async_channel::Sender;
+use once_cell::sync::Lazy;
+use roles_logic_sv2::utils::Mutex;
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    fmt, io,
+    net::SocketAddr,
+    sync::{Arc, Weak},
+};
+use tracing::Subscriber;
+use tracing_subscriber::{registry::LookupSpan, Layer};
+
+type Probe = dyn Fn() -> ChannelStats + Send + Sync;
+/// Channels watched by name, until their probe is dropped.
+type Probes = Vec<(&'static str, Weak<Probe>)>;
+
+static CHANNELS: Lazy<Mutex<Probes>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct ConsoleArgs {
+    /// Serves tokio-console on this address, e.g. `127.0.0.1:6669`. Needs a build with
+    /// `--features tokio-console` and `RUSTFLAGS="--cfg tokio_unstable"`
+    #[arg(long = "tokio-console")]
+    pub tokio_console: Option<SocketAddr>,
+}
+
+/// The layer instrumenting tasks for tokio-console, if `args` asks for it.
+#[cfg(all(feature = "tokio-console", tokio_unstable))]
+pub fn console_layer<S>(args: &ConsoleArgs) -> io::Result<Option<Box<dyn Layer<S> + Send + Sync>>>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    Ok(args.tokio_console.map(|address| {
+        console_subscriber::ConsoleLayer::builder()
+            .server_addr(address)
+            .spawn()
+            .boxed()
+    }))
+}
+
+/// The layer instrumenting tasks for tokio-console, which this build can't serve.
+#[cfg(not(all(feature = "tokio-console", tokio_unstable)))]
+pub fn console_layer<S>(args: &ConsoleArgs) -> io::Result<Option<Box<dyn Layer<S> + Send + Sync>>>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    match args.tokio_console {
+        Some(_) => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "--tokio-console needs a build with `--features tokio-console` and \
+             `RUSTFLAGS=\"--cfg tokio_unstable\"`",
+        )),
+        None => Ok(None),
+    }
+}
+
+/// How full a channel is.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct ChannelStats {
+    /// Messages queued.
+    pub len: usize,
+    /// Messages it holds at most, none if unbounded.
+    pub capacity: Option<usize>,
+}
+
+/// Watches the channel of a sender for `/v1/diagnostics` until dropped.
+#[derive(Clone)]
+pub struct ChannelProbe {
+    _probe: Arc<Probe>,
+}
+
+impl ChannelProbe {
+    pub fn new<T: Send + 'static>(name: &'static str, sender: &Sender<T>) -> Self {
+        let sender = sender.clone();
+        Self::from_fn(name, move || ChannelStats {
+            len: sender.len(),
+            capacity: sender.capacity(),
+        })
+    }
+
+    /// Watches a queue other than a channel, `stats` telling how full it is.
+    pub fn from_fn(
+        name: &'static str,
+        stats: impl Fn() -> ChannelStats + Send + Sync + 'static,
+    ) -> Self {
+        let probe: Arc<Probe> = Arc::new(stats);
+        let _ = CHANNELS.safe_lock(|channels| {
+            channels.retain(|(_, probe)| probe.strong_count() > 0);
+            channels.push((name, Arc::downgrade(&probe)));
+        });
+        Self { _probe: probe }
+    }
+}
+
+impl fmt::Debug for ChannelProbe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ChannelProbe")
+    }
+}
+
+/// The channels watched, those of one name added up.
+pub fn channels() -> BTreeMap<String, ChannelStats> {
+    let probes: Vec<_> = CHANNELS
+        .safe_lock(|channels| {
+            channels
+                .iter()
+                .filter_map(|(name, probe)| Some((*name, probe.upgrade()?)))
+                .collect()
+        })
+        .unwrap_or_default();
+    let mut channels = BTreeMap::new();
+    for (name, probe) in probes {
+        let stats = probe();
+        let total = channels.entry(name.to_string()).or_insert(ChannelStats {
+            len: 0,
+            capacity: Some(0),
+        });
+        total.len += stats.len;
+        // unbounded if one of them is
+        total.capacity = total.capacity.zip(stats.capacity).map(|(a, b)| a + b);
+    }
+    channels
+}
+
+/// Tasks of the tokio runtime.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct RuntimeStats {
+    pub workers: usize,
+    pub alive_tasks: usize,
+    /// Tasks scheduled from outside the runtime, waiting for a worker.
+    pub global_queue_depth: usize,
+    /// Only known with `--cfg tokio_unstable`.
+    pub blocking_threads: Option<usize>,
+    /// Milliseconds every worker spent busy, only known with `--cfg tokio_unstable`.
+    pub worker_busy_ms: Vec<u64>,
+}
+
+impl RuntimeStats {
+    /// Those of the runtime the caller runs on, none outside of one.
+    pub fn current() -> Option<Self> {
+        let metrics = tokio::runtime::Handle::try_current().ok()?.metrics();
+        #[allow(unused_mut)]
+        let mut stats = Self {
+            workers: metrics.num_workers(),
+            alive_tasks: metrics.num_alive_tasks(),
+            global_queue_depth: metrics.global_queue_depth(),
+            ..Self::default()
+        };
+        #[cfg(tokio_unstable)]
+        {
+            stats.blocking_threads = Some(metrics.num_blocking_threads());
+            stats.worker_busy_ms = (0..stats.workers)
+                .map(|worker| metrics.worker_total_busy_duration(worker).as_millis() as u64)
+                .collect();
+        }
+        Some(stats)
+    }
+}
+
+/// Answer of `/v1/diagnostics`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Diagnostics {
+    pub runtime: Option<RuntimeStats>,
+    pub tasks: Vec<TaskBeat>,
+    pub channels: BTreeMap<String, ChannelStats>,
+}
+
+impl Diagnostics {
+    pub fn current() -> Self {
+        Self {
+            runtime: RuntimeStats::current(),
+            tasks: heartbeat::tasks(),
+            channels: channels(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn dumps_tasks_and_channels() {
+        let (sender, _receiver) = async_channel::bounded::<u32>(4);
+        let probe = ChannelProbe::new("test_queue", &sender);
+        let other = ChannelProbe::new("test_queue", &async_channel::bounded::<u32>(2).0);
+        sender.try_send(1).unwrap();
+        sender.try_send(2).unwrap();
+        let _heartbeat = heartbeat::Heartbeat::start("test_task");
+
+        let diagnostics = Diagnostics::current();
+        let expected = ChannelStats {
+            len: 2,
+            capacity: Some(6),
+        };
+        assert_eq!(diagnostics.channels["test_queue"], expected);
+        assert_eq!(diagnostics.runtime.unwrap().workers, 1);
+        assert!(diagnostics
+            .tasks
+            .iter()
+            .any(|task| task.component == "test_task" && !task.stalled));
+
+        // dropped with what owns the sender
+        drop(other);
+        assert_eq!(channels()["test_queue"].capacity, Some(4));
+        drop(probe);
+        assert!(!channels().contains_key("test_queue"));
+    }
+}
@@ -0,0 +1,581 @@
+//! Event bus of the process: subsystems publish typed `Event`s, consumers subscribe to them or
+//! read the `Snapshot` kept of them. A subscriber `EVENTS_CAPACITY` events behind misses some.
+use super::metrics::{ChannelShares, CHANNEL_IDLE_SECS};
+use once_cell::sync::Lazy;
+use roles_logic_sv2::utils::Mutex;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::broadcast;
+use tokio_util::sync::CancellationToken;
+use tracing::{
+    debug,
+    field::{Field, Visit},
+    Level, Subscriber,
+};
+use tracing_subscriber::{layer::Context, Layer};
+
+/// Events a subscriber can lag behind before missing some.
+pub const EVENTS_CAPACITY: usize = 1024;
+/// Blocks found and errors logged the snapshot keeps.
+pub const RECENT_BLOCKS: usize = 10;
+pub const RECENT_ERRORS: usize = 20;
+/// Seconds of accepted shares the hashrate is estimated over.
+pub const HASHRATE_WINDOW_SECS: u64 = 600;
+
+static BUS: Lazy<EventBus> = Lazy::new(|| EventBus::new(EVENTS_CAPACITY));
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    /// The process started, mining on `network`.
+    Started { version: String, network: String },
+    /// The template provider of the pool connected.
+    NodeReady { address: String },
+    /// The template provider announced a new chain tip.
+    NewBlock { prev_hash: String },
+    /// A channel of the pool opened, mining for `account`.
+    ChannelOpened { channel_id: u32, account: String },
+    /// A share accepted by the pool, credited to `account`.
+    ShareAccepted {
+        channel_id: u32,
+        account: String,
+        weight: u64,
+    },
+    /// A share refused by the pool, answered with the error code `reason`.
+    ShareRejected { channel_id: u32, reason: String },
+    /// A miner connected to the pool or the translator.
+    MinerConnected {
+        listener: Listener,
+        connection_id: u32,
+    },
+    MinerDisconnected {
+        listener: Listener,
+        connection_id: u32,
+    },
+    /// A share of the pool met the network target.
+    BlockFound { coinbase_txid: String, reward: u64 },
+    /// The translator connected to the pool.
+    UpstreamConnected { upstream: Upstream, address: String },
+    /// The upstream of the pool or the translator dropped.
+    UpstreamDown { upstream: Upstream, reason: String },
+    /// A server of the process listens on `address`.
+    ListenerBound { listener: Listener, address: String },
+    /// Tokens signed out of the balance of `account`, withdrawn or paid out.
+    MintIssued {
+        account: String,
+        unit: String,
+        amount: u64,
+    },
+    /// A payout of `amount` of the balance of `account` computed and held for delivery, minted
+    /// by the pool or by an external mint, see `crate::pool_mint::mint::payout`.
+    PayoutComputed {
+        payout_id: String,
+        account: String,
+        unit: String,
+        amount: u64,
+    },
+    /// An error logged by the module at `target`, with the stable code of the error if it was
+    /// logged with one (see `crate::error::Error::code`).
+    Error {
+        target: String,
+        code: Option<String>,
+        message: String,
+    },
+    /// The pool or the translator failed with the error of stable `code`, restarted unless it
+    /// failed too often in a row.
+    SubsystemFailed {
+        subsystem: String,
+        code: String,
+        reason: String,
+        restarting: bool,
+    },
+    /// A task of `component` has not beaten for `silent_secs`, see `heartbeat`.
+    ComponentStalled { component: String, silent_secs: u64 },
+    /// The stalled `component` beats again, or its task ended.
+    ComponentRecovered { component: String },
+    /// The memory the pool is estimated to take moved to `pressure` of its budget, see
+    /// `crate::pool_mint::mining_pool::memory`.
+    MemoryPressure {
+        pressure: Pressure,
+        used_bytes: u64,
+        budget_bytes: u64,
+    },
+    /// The best chain of the node is at `height`, behind `reference` at `reference_height` with
+    /// a tip `stale_secs` newer, see `tip`.
+    NodeBehind {
+        height: u64,
+        reference: String,
+        reference_height: u64,
+        stale_secs: u64,
+    },
+    /// The node behind caught up with the references.
+    NodeCaughtUp { height: u64 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Upstream {
+    /// The template provider of the pool.
+    TemplateProvider,
+    /// The pool the translator mines on.
+    Pool,
+}
+
+/// How close the pool is to its memory budget, and the load it sheds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Pressure {
+    #[default]
+    Normal,
+    /// Raises the difficulty of every channel.
+    High,
+    /// Refuses new connections too.
+    Critical,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Listener {
+    /// The pool, for SV2 miners and the translator.
+    Pool,
+    /// The translator, for SV1 miners.
+    Translator,
+    MintApi,
+    Control,
+    Status,
+    /// The gRPC control plane API, see `crate::grpc`.
+    Grpc,
+}
+
+/// What the events published so far add up to.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Snapshot {
+    /// When the process started, its version and the network it mines on.
+    pub started_at: Option<u64>,
+    pub version: Option<String>,
+    pub network: Option<String>,
+    /// Whether the template provider is connected.
+    pub node_ready: bool,
+    /// Previous block hash of the last block announced, and when.
+    pub last_block: Option<String>,
+    pub last_block_at: Option<u64>,
+    pub channels_opened: u64,
+    /// Miners connected to each listener.
+    pub miners: BTreeMap<Listener, u64>,
+    pub shares_accepted: u64,
+    /// Total weight of the accepted shares.
+    pub share_weight: u64,
+    pub shares_rejected: u64,
+    /// Shares accepted per account.
+    pub workers: BTreeMap<String, Worker>,
+    /// Shares accepted per channel of the pool, for the metrics histograms.
+    pub channels: BTreeMap<u32, ChannelShares>,
+    /// Upstreams connected, the template provider once the node is ready.
+    pub upstreams_connected: BTreeSet<Upstream>,
+    /// Last time every upstream went down.
+    pub upstreams_down: BTreeMap<Upstream, Outage>,
+    /// Address of every server listening.
+    pub listeners: BTreeMap<Listener, String>,
+    /// Tokens issued per unit.
+    pub issued: BTreeMap<String, u64>,
+    pub blocks_found: u64,
+    /// Last `RECENT_BLOCKS` blocks found, oldest first.
+    pub recent_blocks: VecDeque<FoundBlock>,
+    /// Last `RECENT_ERRORS` errors logged, oldest first.
+    pub recent_errors: VecDeque<LoggedError>,
+    /// Times the supervisor restarted every subsystem.
+    pub restarts: BTreeMap<String, u64>,
+    /// Components whose tasks stopped beating.
+    pub stalled: BTreeSet<String>,
+    /// How close the pool is to its memory budget.
+    pub memory_pressure: Pressure,
+    /// Whether the node is behind the chain tip of the references.
+    pub node_behind: bool,
+    /// Weight of the shares accepted every second of the last `HASHRATE_WINDOW_SECS`.
+    #[serde(skip)]
+    pub share_window: VecDeque<(u64, u64)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Outage {
+    pub at: u64,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Worker {
+    pub shares: u64,
+    pub weight: u64,
+    pub last_share_at: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FoundBlock {
+    pub at: u64,
+    pub coinbase_txid: String,
+    pub reward: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LoggedError {
+    pub at: u64,
+    pub target: String,
+    #[serde(default)]
+    pub code: Option<String>,
+    pub message: String,
+}
+
+/// Appends `item` to `items`, dropping the oldest past `capacity`.
+fn push_recent<T>(items: &mut VecDeque<T>, item: T, capacity: usize) {
+    items.push_back(item);
+    while items.len() > capacity {
+        items.pop_front();
+    }
+}
+
+impl Snapshot {
+    /// Hashes per second the shares accepted over the last `HASHRATE_WINDOW_SECS` before `now`
+    /// took, a share of weight 1 taking 2^32 hashes on average.
+    pub fn hashrate(&self, now: u64) -> f64 {
+        let since = now.saturating_sub(HASHRATE_WINDOW_SECS);
+        let weight: u64 = self
+            .share_window
+            .iter()
+            .filter(|(at, _)| *at > since)
+            .map(|(_, weight)| weight)
+            .sum();
+        // over the time the process ran if shorter than the window
+        let window = match self.started_at {
+            Some(started_at) => now
+                .saturating_sub(started_at)
+                .clamp(1, HASHRATE_WINDOW_SECS),
+            None => HASHRATE_WINDOW_SECS,
+        };
+        weight as f64 * 2f64.powi(32) / window as f64
+    }
+
+    fn apply(&mut self, event: &Event, now_ms: u64) {
+        let now = now_ms / 1000;
+        match event {
+            Event::Started { version, network } => {
+                self.started_at = Some(now);
+                self.version = Some(version.clone());
+                self.network = Some(network.clone());
+            }
+            Event::NodeReady { .. } => {
+                self.node_ready = true;
+                self.upstreams_connected.insert(Upstream::TemplateProvider);
+            }
+            Event::NewBlock { prev_hash } => {
+                self.last_block = Some(prev_hash.clone());
+                self.last_block_at = Some(now);
+            }
+            Event::ChannelOpened {
+                channel_id,
+                account,
+            } => {
+                self.channels_opened += 1;
+                self.channels
+                    .insert(*channel_id, ChannelShares::new(account.clone(), now_ms));
+            }
+            Event::ShareAccepted {
+                channel_id,
+                account,
+                weight,
+            } => {
+                self.channels
+                    .entry(*channel_id)
+                    .or_insert_with(|| ChannelShares::new(account.clone(), now_ms))
+                    .observe(*weight, now_ms);
+                let idle_since = now_ms.saturating_sub(CHANNEL_IDLE_SECS * 1000);
+                self.channels
+                    .retain(|_, channel| channel.active_at_ms >= idle_since);
+                self.shares_accepted += 1;
+                self.share_weight = self.share_weight.saturating_add(*weight);
+                let worker = self.workers.entry(account.clone()).or_default();
+                worker.shares += 1;
+                worker.weight = worker.weight.saturating_add(*weight);
+                worker.last_share_at = now;
+                match self.share_window.back_mut() {
+                    Some((at, second)) if *at == now => *second = second.saturating_add(*weight),
+                    _ => self.share_window.push_back((now, *weight)),
+                }
+                let since = now.saturating_sub(HASHRATE_WINDOW_SECS);
+                while matches!(self.share_window.front(), Some((at, _)) if *at <= since) {
+                    self.share_window.pop_front();
+                }
+            }
+            Event::ShareRejected { .. } => self.shares_rejected += 1,
+            Event::MinerConnected { listener, .. } => {
+                *self.miners.entry(*listener).or_default() += 1;
+            }
+            Event::MinerDisconnected { listener, .. } => {
+                let miners = self.miners.entry(*listener).or_default();
+                *miners = miners.saturating_sub(1);
+            }
+            Event::BlockFound {
+                coinbase_txid,
+                reward,
+            } => {
+                self.blocks_found += 1;
+                let block = FoundBlock {
+                    at: now,
+                    coinbase_txid: coinbase_txid.clone(),
+                    reward: *reward,
+                };
+                push_recent(&mut self.recent_blocks, block, RECENT_BLOCKS);
+            }
+            Event::UpstreamDown { upstream, reason } => {
+                if *upstream == Upstream::TemplateProvider {
+                    self.node_ready = false;
+                }
+                self.upstreams_connected.remove(upstream);
+                if *upstream == Upstream::Pool {
+                    // the translator drops its miners to reconnect
+                    self.miners.remove(&Listener::Translator);
+                }
+                let outage = Outage {
+                    at: now,
+                    reason: reason.clone(),
+                };
+                self.upstreams_down.insert(*upstream, outage);
+            }
+            Event::UpstreamConnected { upstream, .. } => {
+                self.upstreams_connected.insert(*upstream);
+            }
+            Event::ListenerBound { listener, address } => {
+                self.listeners.insert(*listener, address.clone());
+            }
+            Event::MintIssued { unit, amount, .. } => {
+                let issued = self.issued.entry(unit.clone()).or_default();
+                *issued = issued.saturating_add(*amount);
+            }
+            // issued with `MintIssued` if minted by the pool
+            Event::PayoutComputed { .. } => {}
+            Event::Error {
+                target,
+                code,
+                message,
+            } => {
+                let error = LoggedError {
+                    at: now,
+                    target: target.clone(),
+                    code: code.clone(),
+                    message: message.clone(),
+                };
+                push_recent(&mut self.recent_errors, error, RECENT_ERRORS);
+            }
+            Event::SubsystemFailed {
+                subsystem,
+                restarting,
+                ..
+            } => {
+                if *restarting {
+                    *self.restarts.entry(subsystem.clone()).or_default() += 1;
+                }
+            }
+            Event::ComponentStalled { component, .. } => {
+                self.stalled.insert(component.clone());
+            }
+            Event::ComponentRecovered { component } => {
+                self.stalled.remove(component);
+            }
+            Event::MemoryPressure { pressure, .. } => self.memory_pressure = *pressure,
+            Event::NodeBehind { .. } => self.node_behind = true,
+            Event::NodeCaughtUp { .. } => self.node_behind = false,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct EventBus {
+    events: broadcast::Sender<Event>,
+    snapshot: Mutex<Snapshot>,
+}
+
+impl EventBus {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            events: broadcast::channel(capacity).0,
+            snapshot: Mutex::new(Snapshot::default()),
+        }
+    }
+
+    pub fn publish(&self, event: Event) {
+        let now_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+        let _ = self.snapshot.safe_lock(|s| s.apply(&event, now_ms));
+        // no subscriber is fine
+        let _ = self.events.send(event);
+    }
+
+    /// Receives the events published from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.events.subscribe()
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        self.snapshot.safe_lock(|s| s.clone()).unwrap_or_default()
+    }
+}
+
+/// Publishes `event` on the bus of the process.
+pub fn publish(event: Event) {
+    BUS.publish(event)
+}
+
+pub fn subscribe() -> broadcast::Receiver<Event> {
+    BUS.subscribe()
+}
+
+pub fn snapshot() -> Snapshot {
+    BUS.snapshot()
+}
+
+/// Unix time in seconds, the one of the snapshot.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Publishes every error logged as an `Event::Error`.
+pub struct ErrorEvents;
+
+impl<S: Subscriber> Layer<S> for ErrorEvents {
+    fn on_event(&self, event: &tracing::Event<'_>, _ctx: Context<'_, S>) {
+        if *event.metadata().level() != Level::ERROR {
+            return;
+        }
+        let mut message = Message::default();
+        event.record(&mut message);
+        publish(Event::Error {
+            target: event.metadata().target().to_string(),
+            code: message.code,
+            message: message.message,
+        });
+    }
+}
+
+/// The message of an error logged, and its code given as `code = e.code()`.
+#[derive(Default)]
+struct Message {
+    code: Option<String>,
+    message: String,
+}
+
+impl Visit for Message {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "code" => self.code = Some(value.to_string()),
+            _ => self.record_debug(field, &value),
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            self.message = format!("{:?}", value);
+        }
+    }
+}
+
+/// Logs every event at debug level until `cancel_token` is cancelled.
+pub async fn log_events(cancel_token: CancellationToken) {
+    let mut events = subscribe();
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) => debug!("Event: {:?}", event),
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    debug!("Event log missed {} events", missed)
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            _ = cancel_token.cancelled() => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn keeps_a_snapshot_of_published_events() {
+        let bus = EventBus::new(2);
+        let mut events = bus.subscribe();
+        bus.publish(Event::NodeReady {
+            address: "127.0.0.1:8442".into(),
+        });
+        bus.publish(Event::ShareAccepted {
+            channel_id: 1,
+            account: "alice".into(),
+            weight: 3,
+        });
+        bus.publish(Event::MintIssued {
+            account: "alice".into(),
+            unit: "ehash".into(),
+            amount: 3,
+        });
+        bus.publish(Event::UpstreamDown {
+            upstream: Upstream::TemplateProvider,
+            reason: "connection reset".into(),
+        });
+
+        let snapshot = bus.snapshot();
+        assert!(!snapshot.node_ready);
+        assert_eq!((snapshot.shares_accepted, snapshot.share_weight), (1, 3));
+        assert_eq!(snapshot.issued.get("ehash"), Some(&3));
+        assert_eq!(
+            snapshot.upstreams_down[&Upstream::TemplateProvider].reason,
+            "connection reset"
+        );
+        // the subscriber lagged behind the two oldest events
+        assert!(matches!(
+            events.try_recv(),
+            Err(broadcast::error::TryRecvError::Lagged(2))
+        ));
+        assert!(matches!(
+            events.try_recv(),
+            Ok(Event::MintIssued { amount: 3, .. })
+        ));
+    }
+
+    #[test]
+    fn publishes_errors_logged_with_their_code() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let mut events = subscribe();
+        let subscriber = tracing_subscriber::registry().with(ErrorEvents);
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::error!(code = "noise", "Handshake failed");
+            tracing::error!("No code");
+        });
+        let mut logged = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            if let Event::Error {
+                target,
+                code,
+                message,
+            } = event
+            {
+                if target == module_path!() {
+                    logged.push((code, message));
+                }
+            }
+        }
+        assert_eq!(
+            logged,
+            vec![
+                (Some("noise".to_string()), "Handshake failed".to_string()),
+                (None, "No code".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,283 @@
+//! Explorer pages of the status API, plain HTML for operators and miners to browse from the
+//! accounting database of the mint:
+//!
+//! - `/explorer/blocks`, the blocks found by the pool, newest first: height, hash, reward, state
+//!   and the account whose share found it;
+//! - `/explorer/workers`, the accounts credited by the mint with their balances;
+//! - `/explorer/workers/{account}`, the rounds the account has shares in, newest first, with the
+//!   weight of its shares and their part of the round.
+//!
+//! Every page lists `PAGE_SIZE` rows, `?page=2` the next ones. The height and hash of a block are
+//! known once it was seen in the best chain, see `maturity`.
+use super::server::duration;
+use crate::{
+    error::MintResult,
+    pool_mint::mint::{
+        rounds::{Round, RoundState},
+        Mint,
+    },
+};
+use serde::Deserialize;
+use std::fmt::Write;
+
+/// Rows listed per page.
+pub const PAGE_SIZE: u64 = 50;
+
+/// Query of the explorer pages.
+#[derive(Debug, Default, Deserialize)]
+pub struct PageQuery {
+    /// Page listed, from 1.
+    #[serde(default)]
+    pub page: u64,
+}
+
+impl PageQuery {
+    fn page(&self) -> u64 {
+        self.page.max(1)
+    }
+
+    fn offset(&self) -> u64 {
+        (self.page() - 1) * PAGE_SIZE
+    }
+}
+
+/// The page of the blocks found by the pool.
+pub fn blocks(mint: &Mint, query: &PageQuery, now: u64) -> MintResult<String> {
+    // one more than listed, telling whether there is a next page
+    let mut rounds = mint.found_blocks(query.offset(), PAGE_SIZE + 1)?;
+    let has_next = rounds.len() as u64 > PAGE_SIZE;
+    rounds.truncate(PAGE_SIZE as usize);
+
+    let mut body = String::from(
+        "<table>\n<tr><th>Round</th><th>Height</th><th>Hash</th><th>Reward</th>\
+         <th>State</th><th>Finder</th><th>Found</th></tr>\n",
+    );
+    for round in &rounds {
+        let finder = match &round.finder {
+            Some(finder) => worker_link(finder),
+            None => "-".to_string(),
+        };
+        let _ = writeln!(
+            body,
+            "<tr><td>{}</td><td>{}</td><td><code>{}</code></td><td>{}</td><td>{}</td>\
+             <td>{}</td><td>{}</td></tr>",
+            round.id,
+            or_dash(round.height),
+            escape(round.block_hash.as_deref().unwrap_or("-")),
+            round
+                .reward
+                .map_or_else(|| "-".to_string(), |reward| format!("{} sat", reward)),
+            state(round.state),
+            finder,
+            ago(round.ended_at, now),
+        );
+    }
+    body.push_str("</table>\n");
+    if rounds.is_empty() {
+        body.push_str("<p>No blocks found yet.</p>\n");
+    }
+    body.push_str(&pagination("/explorer/blocks", query.page(), has_next));
+    Ok(page("Blocks found", &body))
+}
+
+/// The page of the accounts credited by the mint.
+pub fn workers(mint: &Mint, query: &PageQuery) -> MintResult<String> {
+    let accounts = mint.accounts()?;
+    let listed: Vec<_> = accounts
+        .iter()
+        .skip(query.offset() as usize)
+        .take(PAGE_SIZE as usize)
+        .collect();
+    let has_next = accounts.len() as u64 > query.offset() + PAGE_SIZE;
+
+    let mut body = String::from(
+        "<table>\n<tr><th>Account</th><th>Pending shares</th><th>Balances</th></tr>\n",
+    );
+    for account in listed {
+        let statement = mint.account_statement(account)?;
+        let balances: Vec<_> = statement
+            .ledgers
+            .iter()
+            .map(|(unit, ledger)| format!("{} {}", ledger.balance, escape(unit)))
+            .collect();
+        let _ = writeln!(
+            body,
+            "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+            worker_link(account),
+            statement.pending_shares,
+            balances.join(", "),
+        );
+    }
+    body.push_str("</table>\n");
+    body.push_str(&pagination("/explorer/workers", query.page(), has_next));
+    Ok(page("Workers", &body))
+}
+
+/// The page of the share history of `account`.
+pub fn worker(mint: &Mint, account: &str, query: &PageQuery, now: u64) -> MintResult<String> {
+    let mut rounds = mint.account_rounds(account, query.offset(), PAGE_SIZE + 1)?;
+    let has_next = rounds.len() as u64 > PAGE_SIZE;
+    rounds.truncate(PAGE_SIZE as usize);
+
+    let mut body = String::from(
+        "<table>\n<tr><th>Round</th><th>State</th><th>Height</th><th>Shares</th>\
+         <th>Part of the round</th><th>Reward</th><th>Ended</th></tr>\n",
+    );
+    for share in &rounds {
+        let part = match share.round_weight {
+            0 => 0.0,
+            round_weight => share.weight as f64 / round_weight as f64,
+        };
+        let _ = writeln!(
+            body,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{:.2}%</td><td>{}</td>\
+             <td>{}</td></tr>",
+            share.round.id,
+            state(share.round.state),
+            or_dash(share.round.height),
+            share.weight,
+            part * 100.0,
+            reward_share(&share.round, part),
+            ago(share.round.ended_at, now),
+        );
+    }
+    body.push_str("</table>\n");
+    if rounds.is_empty() {
+        body.push_str("<p>No shares.</p>\n");
+    }
+    let path = format!("/explorer/workers/{}", percent_encode(account));
+    body.push_str(&pagination(&path, query.page(), has_next));
+    Ok(page(&format!("Shares of {}", account), &body))
+}
+
+/// The page titled `title` around `body`, with the links between pages.
+fn page(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>potato: {title}</title>\
+         <style>body{{font-family:monospace}}td,th{{padding:2px 8px;text-align:left}}</style>\
+         </head>\n<body>\n<p><a href=\"/explorer/blocks\">Blocks</a> | \
+         <a href=\"/explorer/workers\">Workers</a></p>\n<h1>{title}</h1>\n{body}</body>\n</html>\n",
+        title = escape(title),
+        body = body
+    )
+}
+
+/// Links to the pages before and after `page` of `path`.
+fn pagination(path: &str, page: u64, has_next: bool) -> String {
+    let mut links = Vec::new();
+    if page > 1 {
+        links.push(format!("<a href=\"{}?page={}\">newer</a>", path, page - 1));
+    }
+    links.push(format!("page {}", page));
+    if has_next {
+        links.push(format!("<a href=\"{}?page={}\">older</a>", path, page + 1));
+    }
+    format!("<p>{}</p>\n", links.join(" | "))
+}
+
+fn worker_link(account: &str) -> String {
+    format!(
+        "<a href=\"/explorer/workers/{}\">{}</a>",
+        percent_encode(account),
+        escape(account)
+    )
+}
+
+/// What a share of `part` of `round` was paid, once the reward is known.
+fn reward_share(round: &Round, part: f64) -> String {
+    match (round.state, round.reward) {
+        (RoundState::Orphaned, _) => "none".to_string(),
+        (_, Some(reward)) => format!("{} sat", (reward as f64 * part) as u64),
+        (_, None) => "-".to_string(),
+    }
+}
+
+fn state(state: RoundState) -> &'static str {
+    match state {
+        RoundState::Open => "open",
+        RoundState::Immature => "immature",
+        RoundState::Matured => "matured",
+        RoundState::Orphaned => "orphaned",
+    }
+}
+
+fn ago(at: Option<u64>, now: u64) -> String {
+    at.map_or_else(
+        || "-".to_string(),
+        |at| format!("{} ago", duration(now.saturating_sub(at))),
+    )
+}
+
+fn or_dash(value: Option<u64>) -> String {
+    value.map_or_else(|| "-".to_string(), |value| value.to_string())
+}
+
+/// `text` safe to put in HTML.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// `segment` safe to put in a URL path.
+fn percent_encode(segment: &str) -> String {
+    let mut encoded = String::with_capacity(segment.len());
+    for byte in segment.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            byte => {
+                let _ = write!(encoded, "%{:02X}", byte);
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{pool_mint::mint::MintConfig, status::events};
+
+    #[test]
+    fn lists_blocks_and_shares_by_page() {
+        let mut mint = Mint::from_master_secret(&[3; 32], &MintConfig::default()).unwrap();
+        mint.credit_share("alice", 3).unwrap();
+        mint.credit_share("<bob>", 1).unwrap();
+        let round = mint.found_block("coinbase", 100, Some("alice")).unwrap();
+        mint.set_round_block(round, 840_000, "00000000beef")
+            .unwrap();
+        for _ in 0..PAGE_SIZE {
+            mint.credit_share("alice", 1).unwrap();
+            mint.found_block("coinbase", 10, None).unwrap();
+        }
+        let now = events::now();
+
+        let first = blocks(&mint, &PageQuery::default(), now).unwrap();
+        assert!(first.contains("<a href=\"/explorer/blocks?page=2\">older</a>"));
+        assert!(!first.contains("840000"));
+        let second = blocks(&mint, &PageQuery { page: 2 }, now).unwrap();
+        assert!(second.contains(
+            "<tr><td>1</td><td>840000</td><td><code>00000000beef</code></td><td>100 sat</td>\
+             <td>immature</td><td><a href=\"/explorer/workers/alice\">alice</a></td>"
+        ));
+        assert!(second.contains("<a href=\"/explorer/blocks?page=1\">newer</a> | page 2</p>"));
+
+        let workers = workers(&mint, &PageQuery::default()).unwrap();
+        assert!(workers.contains("<a href=\"/explorer/workers/%3Cbob%3E\">&lt;bob&gt;</a>"));
+        let history = worker(&mint, "<bob>", &PageQuery::default(), now).unwrap();
+        assert!(history.contains("<h1>Shares of &lt;bob&gt;</h1>"));
+        assert!(history.contains("<td>1</td><td>25.00%</td><td>25 sat</td>"));
+        assert!(!history.contains("older"));
+    }
+}
@@ -0,0 +1,141 @@
+//! Health of the process, served for Kubernetes probes and uptime monitors (see `server`):
+//!
+//! - `/healthz` answers 200 as long as the process serves requests, with the checks below;
+//! - `/readyz` answers 200 once every check passes, 503 until then.
+//!
+//! The checks: `node`, the template provider connected and announced the chain tip; `upstream`,
+//! the translator connected to the pool; `listeners`, the pool and the translator accept miners;
+//! `mint`, its database answers and holds no journaled credits. All but `mint` are read from the
+//! event bus snapshot.
+use super::events::{Listener, Snapshot, Upstream};
+use crate::error::MintResult;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Listeners miners connect to, bound before the process is ready.
+const MINER_LISTENERS: [Listener; 2] = [Listener::Pool, Listener::Translator];
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Check {
+    pub ok: bool,
+    pub detail: String,
+}
+
+impl Check {
+    fn new(ok: bool, detail: impl Into<String>) -> Self {
+        Self {
+            ok,
+            detail: detail.into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Health {
+    /// Whether every check passes.
+    pub ready: bool,
+    pub checks: BTreeMap<&'static str, Check>,
+}
+
+/// Health of the process after `snapshot`, and `mint` as its `database_health` answered, none
+/// before the mint started.
+pub fn health(snapshot: &Snapshot, mint: Option<MintResult<usize>>) -> Health {
+    let mut checks = BTreeMap::new();
+    let node = match (&snapshot.last_block, snapshot.node_ready) {
+        (Some(block), true) => Check::new(true, format!("chain tip {}", block)),
+        (None, true) => Check::new(false, "no chain tip announced yet"),
+        (_, false) => match snapshot.upstreams_down.get(&Upstream::TemplateProvider) {
+            Some(outage) => Check::new(false, format!("template provider down: {}", outage.reason)),
+            None => Check::new(false, "template provider not connected"),
+        },
+    };
+    checks.insert("node", node);
+    let upstream = if snapshot.upstreams_connected.contains(&Upstream::Pool) {
+        Check::new(true, "translator connected to the pool")
+    } else {
+        match snapshot.upstreams_down.get(&Upstream::Pool) {
+            Some(outage) => Check::new(false, format!("pool down: {}", outage.reason)),
+            None => Check::new(false, "translator not connected to the pool"),
+        }
+    };
+    checks.insert("upstream", upstream);
+    let unbound: Vec<_> = MINER_LISTENERS
+        .iter()
+        .filter(|listener| !snapshot.listeners.contains_key(listener))
+        .map(|listener| format!("{:?}", listener).to_lowercase())
+        .collect();
+    let listeners = if unbound.is_empty() {
+        let bound: Vec<_> = snapshot.listeners.values().cloned().collect();
+        Check::new(true, format!("listening on {}", bound.join(", ")))
+    } else {
+        Check::new(false, format!("not listening yet: {}", unbound.join(", ")))
+    };
+    checks.insert("listeners", listeners);
+    let mint = match mint {
+        None => Check::new(false, "mint not started"),
+        Some(Ok(0)) => Check::new(true, "database answers"),
+        Some(Ok(journaled)) => Check::new(
+            false,
+            format!(
+                "{} credits journaled until the database recovers",
+                journaled
+            ),
+        ),
+        Some(Err(e)) => Check::new(false, e.to_string()),
+    };
+    checks.insert("mint", mint);
+    Health {
+        ready: checks.values().all(|check| check.ok),
+        checks,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::status::events::{Event, EventBus};
+
+    #[test]
+    fn is_ready_once_every_check_passes() {
+        let bus = EventBus::new(16);
+        let not_ready = health(&bus.snapshot(), None);
+        assert!(!not_ready.ready);
+        assert!(not_ready.checks.values().all(|check| !check.ok));
+
+        bus.publish(Event::NodeReady {
+            address: "127.0.0.1:8442".into(),
+        });
+        bus.publish(Event::NewBlock {
+            prev_hash: "00".repeat(32),
+        });
+        bus.publish(Event::UpstreamConnected {
+            upstream: Upstream::Pool,
+            address: "127.0.0.1:34254".into(),
+        });
+        for (listener, address) in [
+            (Listener::Pool, "0.0.0.0:34254"),
+            (Listener::Translator, "0.0.0.0:34255"),
+        ] {
+            bus.publish(Event::ListenerBound {
+                listener,
+                address: address.into(),
+            });
+        }
+        assert!(health(&bus.snapshot(), Some(Ok(0))).ready);
+
+        let journaling = health(&bus.snapshot(), Some(Ok(2)));
+        assert!(!journaling.ready);
+        assert!(!journaling.checks["mint"].ok);
+
+        bus.publish(Event::UpstreamDown {
+            upstream: Upstream::Pool,
+            reason: "connection reset".into(),
+        });
+        let upstream_down = health(&bus.snapshot(), Some(Ok(0)));
+        assert!(!upstream_down.ready);
+        assert_eq!(
+            upstream_down.checks["upstream"].detail,
+            "pool down: connection reset"
+        );
+    }
+}
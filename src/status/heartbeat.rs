@@ -0,0 +1,273 @@
+//! Heartbeats of the long running tasks of the process, to tell a stuck one from an idle one.
+//! Each task holds a `Heartbeat` beating every `BEAT_INTERVAL` while it waits for work (see
+//! `Heartbeat::beating`), but not while it handles it: a task deadlocked on a lock or blocked on
+//! a full channel stops beating. The components:
+//!
+//! - `pool_acceptor`, the pool accepting miners;
+//! - `job_distributor`, the pool sending the jobs of new templates to its channels;
+//! - `translator_bridge`, the translator passing shares from its miners to the pool;
+//! - `mint`, whose lock is taken every `BEAT_INTERVAL` (see `watch_lock`).
+//!
+//! `watch` publishes an `Event::ComponentStalled` once a component has not beaten for
+//! `STALL_AFTER`, raising the `component_stalled` alert (see `alerts`), and an
+//! `Event::ComponentRecovered` once it beats again or its task ends.
+use super::events::{self, Event};
+use once_cell::sync::Lazy;
+use roles_logic_sv2::utils::Mutex;
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
+
+/// How often a waiting task beats.
+pub const BEAT_INTERVAL: Duration = Duration::from_secs(5);
+/// Time without a beat after which a component is stalled.
+pub const STALL_AFTER: Duration = Duration::from_secs(30);
+
+static HEARTBEATS: Lazy<Heartbeats> = Lazy::new(Heartbeats::new);
+
+#[derive(Debug)]
+struct Beat {
+    component: &'static str,
+    at: Instant,
+    stalled: bool,
+}
+
+/// Last beat of every task holding a `Heartbeat`.
+#[derive(Debug)]
+struct Heartbeats {
+    beats: Mutex<BTreeMap<u64, Beat>>,
+    next_id: AtomicU64,
+}
+
+impl Heartbeats {
+    fn new() -> Self {
+        Self {
+            beats: Mutex::new(BTreeMap::new()),
+            next_id: AtomicU64::new(0),
+        }
+    }
+
+    fn register(&self, component: &'static str, now: Instant) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let beat = Beat {
+            component,
+            at: now,
+            stalled: false,
+        };
+        let _ = self.beats.safe_lock(|beats| beats.insert(id, beat));
+        id
+    }
+
+    fn beat(&self, id: u64, now: Instant) {
+        let _ = self.beats.safe_lock(|beats| {
+            if let Some(beat) = beats.get_mut(&id) {
+                beat.at = now;
+            }
+        });
+    }
+
+    /// Forgets the task `id`, the event telling its component recovered if it was stalled.
+    fn deregister(&self, id: u64) -> Option<Event> {
+        let beat = self.beats.safe_lock(|beats| beats.remove(&id)).ok()??;
+        beat.stalled.then(|| Event::ComponentRecovered {
+            component: beat.component.to_string(),
+        })
+    }
+
+    /// Events of the components stalled or recovered since the last check.
+    fn check(&self, now: Instant) -> Vec<Event> {
+        self.beats
+            .safe_lock(|beats| {
+                beats
+                    .values_mut()
+                    .filter_map(|beat| {
+                        let silent = now.saturating_duration_since(beat.at);
+                        match (silent >= STALL_AFTER, beat.stalled) {
+                            (true, false) => {
+                                beat.stalled = true;
+                                Some(Event::ComponentStalled {
+                                    component: beat.component.to_string(),
+                                    silent_secs: silent.as_secs(),
+                                })
+                            }
+                            (false, true) => {
+                                beat.stalled = false;
+                                Some(Event::ComponentRecovered {
+                                    component: beat.component.to_string(),
+                                })
+                            }
+                            _ => None,
+                        }
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// Last beat of a task, see `tasks`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TaskBeat {
+    pub component: &'static str,
+    pub silent_secs: u64,
+    pub stalled: bool,
+}
+
+/// Last beat of every task holding a `Heartbeat`, by component.
+pub fn tasks() -> Vec<TaskBeat> {
+    let now = Instant::now();
+    let mut tasks: Vec<_> = HEARTBEATS
+        .beats
+        .safe_lock(|beats| {
+            beats
+                .values()
+                .map(|beat| TaskBeat {
+                    component: beat.component,
+                    silent_secs: now.saturating_duration_since(beat.at).as_secs(),
+                    stalled: beat.stalled,
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    tasks.sort_by_key(|task| task.component);
+    tasks
+}
+
+/// Heartbeat of a task of `component`, watched until dropped.
+#[derive(Debug)]
+pub struct Heartbeat {
+    id: u64,
+}
+
+impl Heartbeat {
+    pub fn start(component: &'static str) -> Self {
+        Self {
+            id: HEARTBEATS.register(component, Instant::now()),
+        }
+    }
+
+    pub fn beat(&self) {
+        HEARTBEATS.beat(self.id, Instant::now());
+    }
+
+    /// Awaits `future`, beating every `BEAT_INTERVAL` until it completes: the task is waiting,
+    /// not stuck.
+    pub async fn beating<F: Future>(&self, future: F) -> F::Output {
+        tokio::pin!(future);
+        let mut beats = tokio::time::interval(BEAT_INTERVAL);
+        loop {
+            tokio::select! {
+                output = &mut future => {
+                    self.beat();
+                    return output;
+                }
+                _ = beats.tick() => self.beat(),
+            }
+        }
+    }
+}
+
+impl Drop for Heartbeat {
+    fn drop(&mut self) {
+        if let Some(event) = HEARTBEATS.deregister(self.id) {
+            events::publish(event);
+        }
+    }
+}
+
+/// Beats for `component` every `BEAT_INTERVAL` it takes `lock`, until `cancel_token` is
+/// cancelled. A deadlock holding the lock stops the beats.
+pub async fn watch_lock<T: Send + 'static>(
+    component: &'static str,
+    lock: Arc<Mutex<T>>,
+    cancel_token: CancellationToken,
+) {
+    let heartbeat = Heartbeat::start(component);
+    let mut beats = tokio::time::interval(BEAT_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = beats.tick() => {}
+            _ = cancel_token.cancelled() => break,
+        }
+        // blocks the thread for as long as the lock is held
+        let lock = lock.clone();
+        let taken = tokio::task::spawn_blocking(move || lock.safe_lock(|_| ()).is_ok());
+        tokio::select! {
+            taken = taken => {
+                // a poisoned lock is as stuck as a deadlocked one
+                if let Ok(true) = taken {
+                    heartbeat.beat();
+                }
+            }
+            _ = cancel_token.cancelled() => break,
+        }
+    }
+}
+
+/// Publishes the components stalled or recovered until `cancel_token` is cancelled.
+pub async fn watch(cancel_token: CancellationToken) {
+    let mut checks = tokio::time::interval(BEAT_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = checks.tick() => {}
+            _ = cancel_token.cancelled() => break,
+        }
+        for event in HEARTBEATS.check(Instant::now()) {
+            match &event {
+                Event::ComponentStalled {
+                    component,
+                    silent_secs,
+                } => error!(
+                    "{} stalled, no heartbeat for {} seconds",
+                    component, silent_secs
+                ),
+                Event::ComponentRecovered { component } => info!("{} beats again", component),
+                _ => {}
+            }
+            events::publish(event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn tells_a_stalled_component_once_until_it_beats() {
+        let heartbeats = Heartbeats::new();
+        let start = Instant::now();
+        let mint = heartbeats.register("mint", start);
+        let acceptor = heartbeats.register("pool_acceptor", start);
+        heartbeats.beat(acceptor, start + STALL_AFTER);
+        assert!(heartbeats.check(start + STALL_AFTER / 2).is_empty());
+
+        let stalled = Event::ComponentStalled {
+            component: "mint".into(),
+            silent_secs: STALL_AFTER.as_secs(),
+        };
+        assert_eq!(heartbeats.check(start + STALL_AFTER), vec![stalled]);
+        // the acceptor stalls in turn
+        assert_eq!(heartbeats.check(start + STALL_AFTER * 2).len(), 1);
+        let later = start + STALL_AFTER * 2;
+        heartbeats.beat(mint, later);
+        let recovered = Event::ComponentRecovered {
+            component: "mint".into(),
+        };
+        assert_eq!(heartbeats.check(later), vec![recovered.clone()]);
+
+        // a task ending while stalled recovers its component
+        heartbeats.check(later + STALL_AFTER);
+        assert_eq!(heartbeats.deregister(mint), Some(recovered));
+        assert_eq!(heartbeats.deregister(mint), None);
+    }
+}
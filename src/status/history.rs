@@ -0,0 +1,237 @@
+//! History of the events of the bus, kept in SQLite so what happened around an incident can be
+//! told after the logs rotated. Every event is written with the unix time in milliseconds it
+//! was received at, the oldest dropped past `capacity` events. `potato events query` reads them
+//! back as JSON lines, e.g. `potato events query --since 2h --type share_rejected`.
+//!
+//! Events are written in batches every `FLUSH_INTERVAL`, so an abrupt exit loses the last second
+//! of them at most.
+use super::events::{self, Event};
+use rusqlite::{params, Connection};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast::error::RecvError;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+/// How often the events received are written.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct HistoryConfig {
+    #[serde(default = "HistoryConfig::default_path")]
+    pub path: String,
+    /// Events kept, the oldest dropped first.
+    #[serde(default = "HistoryConfig::default_capacity")]
+    pub capacity: u64,
+}
+
+impl HistoryConfig {
+    fn default_path() -> String {
+        "events.sqlite".to_string()
+    }
+
+    fn default_capacity() -> u64 {
+        1_000_000
+    }
+}
+
+fn open(path: &str) -> rusqlite::Result<Connection> {
+    let conn = Connection::open(path)?;
+    conn.execute_batch(
+        "PRAGMA journal_mode = WAL;
+        CREATE TABLE IF NOT EXISTS events (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            at_ms INTEGER NOT NULL,
+            type TEXT NOT NULL,
+            event TEXT NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS events_at_ms ON events (at_ms);",
+    )?;
+    Ok(conn)
+}
+
+/// Writes `events`, each with the time it was received at, then drops the oldest past
+/// `capacity`.
+fn append(conn: &mut Connection, events: &[(u64, Event)], capacity: u64) -> rusqlite::Result<()> {
+    let tx = conn.transaction()?;
+    {
+        let mut insert =
+            tx.prepare_cached("INSERT INTO events (at_ms, type, event) VALUES (?1, ?2, ?3)")?;
+        for (at_ms, event) in events {
+            let Ok(json) = serde_json::to_value(event) else {
+                continue;
+            };
+            let kind = json["type"].as_str().unwrap_or_default();
+            insert.execute(params![*at_ms as i64, kind, json.to_string()])?;
+        }
+    }
+    tx.execute(
+        "DELETE FROM events WHERE id <= last_insert_rowid() - ?1",
+        params![capacity as i64],
+    )?;
+    tx.commit()
+}
+
+/// Records the events of the bus to `config.path` until `cancel_token` is cancelled.
+pub async fn run(config: HistoryConfig, cancel_token: CancellationToken) {
+    let mut conn = match open(&config.path) {
+        Ok(conn) => conn,
+        Err(e) => {
+            warn!("Event history {} disabled: {}", config.path, e);
+            return;
+        }
+    };
+    info!("Recording events to {}", config.path);
+    let mut receiver = events::subscribe();
+    let mut flushes = tokio::time::interval(FLUSH_INTERVAL);
+    let mut pending = vec![];
+    loop {
+        let stop = tokio::select! {
+            event = receiver.recv() => match event {
+                Ok(event) => {
+                    pending.push((now_ms(), event));
+                    continue;
+                }
+                Err(RecvError::Lagged(missed)) => {
+                    warn!("Event history missed {} events", missed);
+                    continue;
+                }
+                Err(RecvError::Closed) => true,
+            },
+            _ = flushes.tick() => false,
+            _ = cancel_token.cancelled() => true,
+        };
+        if !pending.is_empty() {
+            let events = std::mem::take(&mut pending);
+            let capacity = config.capacity;
+            let written = tokio::task::spawn_blocking(move || {
+                let result = append(&mut conn, &events, capacity);
+                (conn, result)
+            })
+            .await;
+            match written {
+                Ok((returned, result)) => {
+                    conn = returned;
+                    if let Err(e) = result {
+                        warn!("Failed to record events to {}: {}", config.path, e);
+                    }
+                }
+                Err(_) => return,
+            }
+        }
+        if stop {
+            return;
+        }
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Events recorded at `path` from `since_ms` until `until_ms`, of `types` if any, oldest first
+/// and at most `limit` of them. Each is the JSON of the event with the time it was received at,
+/// `at_ms`.
+pub fn query(
+    path: &str,
+    since_ms: u64,
+    until_ms: Option<u64>,
+    types: &[String],
+    limit: u64,
+) -> rusqlite::Result<Vec<Value>> {
+    let conn = Connection::open_with_flags(path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let mut statement = conn.prepare(
+        "SELECT at_ms, event FROM events WHERE at_ms >= ?1 AND at_ms <= ?2
+        AND (?3 = '' OR instr(',' || ?3 || ',', ',' || type || ',') > 0) ORDER BY id LIMIT ?4",
+    )?;
+    let rows = statement.query_map(
+        params![
+            since_ms as i64,
+            until_ms.unwrap_or(i64::MAX as u64) as i64,
+            types.join(","),
+            limit as i64
+        ],
+        |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)),
+    )?;
+    let mut events = vec![];
+    for row in rows {
+        let (at_ms, event) = row?;
+        let mut json = Map::new();
+        json.insert("at_ms".to_string(), at_ms.into());
+        if let Ok(Value::Object(event)) = serde_json::from_str(&event) {
+            json.extend(event);
+        }
+        events.push(Value::Object(json));
+    }
+    Ok(events)
+}
+
+/// Unix time in milliseconds `since` names, either a unix time in seconds or a time ago such as
+/// `90s`, `30m`, `2h` or `7d`.
+pub fn parse_since(since: &str, now_ms: u64) -> Result<u64, String> {
+    if let Ok(secs) = since.parse::<u64>() {
+        return Ok(secs.saturating_mul(1000));
+    }
+    let unit = match since.chars().last() {
+        Some('s') => 1,
+        Some('m') => 60,
+        Some('h') => 3600,
+        Some('d') => 86400,
+        _ => {
+            return Err(format!(
+                "invalid time {:?}, e.g. 1760000000, 30m or 2h",
+                since
+            ))
+        }
+    };
+    let amount: u64 = since[..since.len() - 1]
+        .parse()
+        .map_err(|_| format!("invalid time {:?}, e.g. 1760000000, 30m or 2h", since))?;
+    Ok(now_ms.saturating_sub(amount * unit * 1000))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn keeps_the_latest_events_and_queries_them() {
+        let dir = std::env::temp_dir().join(format!("potato-events-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("events.sqlite").to_string_lossy().to_string();
+        let mut conn = open(&path).unwrap();
+        let events: Vec<_> = (0..5)
+            .map(|i| {
+                let event = match i % 2 {
+                    0 => Event::ShareRejected {
+                        channel_id: i,
+                        reason: "stale-share".into(),
+                    },
+                    _ => Event::NewBlock {
+                        prev_hash: "00".repeat(32),
+                    },
+                };
+                (1000 * i as u64, event)
+            })
+            .collect();
+        append(&mut conn, &events, 4).unwrap();
+
+        // the first event was dropped
+        let all = query(&path, 0, None, &[], 100).unwrap();
+        assert_eq!(all.len(), 4);
+        assert_eq!(all[0]["at_ms"], 1000);
+        assert_eq!(all[0]["type"], "new_block");
+        let rejected = query(&path, 2000, Some(3000), &["share_rejected".into()], 100).unwrap();
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(rejected[0]["channel_id"], 2);
+
+        assert_eq!(parse_since("1760000000", 0), Ok(1_760_000_000_000));
+        assert_eq!(parse_since("2h", 10_000_000), Ok(2_800_000));
+        assert!(parse_since("yesterday", 0).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,336 @@
+//! Hooks, custom accounting or notifications run on the events of the pool without forking it.
+//! Every hook `register`ed is called on shares accepted, blocks found, miners connected and
+//! disconnected, and payouts computed, once published to the event bus (see `events`):
+//!
+//! - in process, a [`Hook`] of a binary embedding the library;
+//! - out of process, a command of `[[hooks]]` in the configuration, fed one event per line as
+//!   JSON on its stdin, e.g. `{"type":"share_accepted","channel_id":1,"account":"alice",
+//!   "weight":1024}`. Lines it writes to its stdout are logged, at `level` if they are JSON of the
+//!   form `{"level":"warn","message":"..."}`. It is restarted `RESTART_DELAY` after it exits.
+//!
+//! Hooks are called in turn by a task of their own and must not block it: a hook with slow work
+//! to do hands it to a task, as commands do with a queue of at most `QUEUE_CAPACITY` events. A
+//! hook falling behind the bus misses events rather than slowing the pool down.
+use super::events::{self, Event, Listener};
+use once_cell::sync::Lazy;
+use roles_logic_sv2::utils::Mutex;
+use serde::Deserialize;
+use std::{process::Stdio, sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    process::Command,
+    sync::{broadcast::error::RecvError, mpsc},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
+
+/// Events queued for a command before new ones are dropped.
+pub const QUEUE_CAPACITY: usize = 10_000;
+/// Delay before a command that exited is started again.
+pub const RESTART_DELAY: Duration = Duration::from_secs(5);
+/// Types of the events hooks are called on, as serialized.
+pub const HOOKED_EVENTS: [&str; 5] = [
+    "share_accepted",
+    "block_found",
+    "miner_connected",
+    "miner_disconnected",
+    "payout_computed",
+];
+
+static HOOKS: Lazy<Mutex<Vec<Arc<dyn Hook>>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+/// Called on the events of the pool once registered, every method doing nothing unless
+/// implemented.
+pub trait Hook: Send + Sync {
+    /// A share of `weight` accepted on `channel_id`, credited to `account`.
+    fn on_share_accepted(&self, _channel_id: u32, _account: &str, _weight: u64) {}
+
+    /// A block found, its coinbase paying `reward` sats.
+    fn on_block_found(&self, _coinbase_txid: &str, _reward: u64) {}
+
+    fn on_miner_connected(&self, _listener: Listener, _connection_id: u32) {}
+
+    fn on_miner_disconnected(&self, _listener: Listener, _connection_id: u32) {}
+
+    /// A payout of `amount` of the `unit` balance of `account` computed, held for delivery.
+    fn on_payout_computed(&self, _payout_id: &str, _account: &str, _unit: &str, _amount: u64) {}
+
+    /// Called on every event of the bus, calling the method of those hooked.
+    fn on_event(&self, event: &Event) {
+        match event {
+            Event::ShareAccepted {
+                channel_id,
+                account,
+                weight,
+            } => self.on_share_accepted(*channel_id, account, *weight),
+            Event::BlockFound {
+                coinbase_txid,
+                reward,
+            } => self.on_block_found(coinbase_txid, *reward),
+            Event::MinerConnected {
+                listener,
+                connection_id,
+            } => self.on_miner_connected(*listener, *connection_id),
+            Event::MinerDisconnected {
+                listener,
+                connection_id,
+            } => self.on_miner_disconnected(*listener, *connection_id),
+            Event::PayoutComputed {
+                payout_id,
+                account,
+                unit,
+                amount,
+            } => self.on_payout_computed(payout_id, account, unit, *amount),
+            _ => {}
+        }
+    }
+}
+
+/// Calls `hook` on the events published from now on, once `run` runs.
+pub fn register(hook: Arc<dyn Hook>) {
+    let _ = HOOKS.safe_lock(|hooks| hooks.push(hook));
+}
+
+/// Calls every hook registered on `event`.
+pub fn dispatch(event: &Event) {
+    let hooks = HOOKS.safe_lock(|hooks| hooks.clone()).unwrap_or_default();
+    for hook in hooks {
+        hook.on_event(event);
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CommandHookConfig {
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Types of the events fed to the command, of `HOOKED_EVENTS`. All of them if empty.
+    #[serde(default)]
+    pub events: Vec<String>,
+}
+
+/// A command fed the events of the pool, see the module.
+#[derive(Debug)]
+pub struct CommandHook {
+    events: Vec<String>,
+    queue: mpsc::Sender<String>,
+}
+
+impl CommandHook {
+    /// Starts the command of `config`, fed until `cancel_token` is cancelled.
+    pub fn start(config: CommandHookConfig, cancel_token: CancellationToken) -> Self {
+        let (queue, lines) = mpsc::channel(QUEUE_CAPACITY);
+        let events = config.events.clone();
+        tokio::spawn(run_command(config, lines, cancel_token));
+        Self { events, queue }
+    }
+}
+
+impl Hook for CommandHook {
+    fn on_event(&self, event: &Event) {
+        let Ok(event) = serde_json::to_value(event) else {
+            return;
+        };
+        let Some(kind) = event["type"].as_str() else {
+            return;
+        };
+        let hooked = match self.events.is_empty() {
+            true => HOOKED_EVENTS.contains(&kind),
+            false => self.events.iter().any(|event| event == kind),
+        };
+        if hooked && self.queue.try_send(event.to_string()).is_err() {
+            warn!("Hook queue full, dropped an event");
+        }
+    }
+}
+
+/// Feeds `lines` to the command of `config`, started again whenever it exits, until
+/// `cancel_token` is cancelled.
+async fn run_command(
+    config: CommandHookConfig,
+    mut lines: mpsc::Receiver<String>,
+    cancel_token: CancellationToken,
+) {
+    loop {
+        let child = Command::new(&config.command)
+            .args(&config.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn();
+        match child {
+            Ok(mut child) => {
+                info!("Hook {} started", config.command);
+                let (Some(mut stdin), Some(stdout)) = (child.stdin.take(), child.stdout.take())
+                else {
+                    return;
+                };
+                let mut output = BufReader::new(stdout).lines();
+                let mut reading = true;
+                loop {
+                    tokio::select! {
+                        line = lines.recv() => {
+                            let Some(mut line) = line else {
+                                return;
+                            };
+                            line.push('\n');
+                            if let Err(e) = stdin.write_all(line.as_bytes()).await {
+                                warn!("Hook {}: {}", config.command, e);
+                                break;
+                            }
+                        }
+                        line = output.next_line(), if reading => match line {
+                            Ok(Some(line)) => log_output(&config.command, &line),
+                            // closed its stdout, still fed
+                            Ok(None) => reading = false,
+                            Err(e) => {
+                                warn!("Hook {}: {}", config.command, e);
+                                reading = false;
+                            }
+                        },
+                        status = child.wait() => {
+                            if let Ok(status) = status {
+                                warn!("Hook {} exited with {}", config.command, status);
+                            }
+                            break;
+                        }
+                        _ = cancel_token.cancelled() => {
+                            // end of input, the command exits once done with it
+                            drop(stdin);
+                            let _ = tokio::time::timeout(RESTART_DELAY, child.wait()).await;
+                            return;
+                        }
+                    }
+                }
+                let _ = child.start_kill();
+                warn!(
+                    "Hook {} stopped, restarting in {}s",
+                    config.command,
+                    RESTART_DELAY.as_secs()
+                );
+            }
+            Err(e) => warn!(
+                "Hook {} failed to start, retrying in {}s: {}",
+                config.command,
+                RESTART_DELAY.as_secs(),
+                e
+            ),
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(RESTART_DELAY) => {}
+            _ = cancel_token.cancelled() => return,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Output {
+    #[serde(default)]
+    level: String,
+    message: String,
+}
+
+fn log_output(command: &str, line: &str) {
+    match serde_json::from_str::<Output>(line) {
+        Ok(output) => match output.level.as_str() {
+            "error" => error!("Hook {}: {}", command, output.message),
+            "warn" => warn!("Hook {}: {}", command, output.message),
+            "debug" => debug!("Hook {}: {}", command, output.message),
+            _ => info!("Hook {}: {}", command, output.message),
+        },
+        Err(_) => info!("Hook {}: {}", command, line),
+    }
+}
+
+/// Starts the commands of `commands`, then calls every hook registered on the events of the bus
+/// until `cancel_token` is cancelled.
+pub async fn run(commands: Vec<CommandHookConfig>, cancel_token: CancellationToken) {
+    let mut events = events::subscribe();
+    for config in commands {
+        register(Arc::new(CommandHook::start(config, cancel_token.clone())));
+    }
+    loop {
+        let event = tokio::select! {
+            event = events.recv() => event,
+            _ = cancel_token.cancelled() => break,
+        };
+        match event {
+            Ok(event) => dispatch(&event),
+            Err(RecvError::Lagged(missed)) => warn!("Hooks missed {} events", missed),
+            Err(RecvError::Closed) => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    #[derive(Default)]
+    struct Weights(AtomicU64);
+
+    impl Hook for Weights {
+        fn on_share_accepted(&self, _channel_id: u32, account: &str, weight: u64) {
+            if account == "hooked" {
+                self.0.fetch_add(weight, Ordering::Relaxed);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn calls_hooks_and_feeds_commands_the_events() {
+        let weights = Arc::new(Weights::default());
+        register(weights.clone());
+        let share = Event::ShareAccepted {
+            channel_id: 1,
+            account: "hooked".to_string(),
+            weight: 1024,
+        };
+        dispatch(&share);
+        dispatch(&Event::NodeCaughtUp { height: 1 });
+        dispatch(&share);
+        assert_eq!(weights.0.load(Ordering::Relaxed), 2048);
+
+        let dir = std::env::temp_dir().join(format!("potato-hooks-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("events.jsonl");
+        let cancel_token = CancellationToken::new();
+        let hook = CommandHook::start(
+            CommandHookConfig {
+                command: "sh".to_string(),
+                args: vec![
+                    "-c".to_string(),
+                    "cat > \"$0\"".to_string(),
+                    path.to_str().unwrap().to_string(),
+                ],
+                events: vec!["payout_computed".to_string()],
+            },
+            cancel_token.clone(),
+        );
+        hook.on_event(&share);
+        hook.on_event(&Event::PayoutComputed {
+            payout_id: "p1".to_string(),
+            account: "alice".to_string(),
+            unit: "ehash".to_string(),
+            amount: 2048,
+        });
+        let mut lines = vec![];
+        for _ in 0..100 {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            lines = std::fs::read_to_string(&path)
+                .unwrap_or_default()
+                .lines()
+                .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+                .collect();
+            if !lines.is_empty() {
+                break;
+            }
+        }
+        cancel_token.cancel();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0]["type"], "payout_computed");
+        assert_eq!(lines[0]["amount"], 2048);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
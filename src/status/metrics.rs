@@ -0,0 +1,374 @@
+//! Prometheus metrics of the process, served at `/metrics` by the status server (see `server`)
+//! from the event bus snapshot. Besides the share, channel and mint counters, every channel of
+//! the pool gets two histograms, to check vardiff holds the shares per minute it targets:
+//!
+//! - `potato_share_interval_seconds`, the time between two shares accepted on the channel;
+//! - `potato_share_difficulty`, the difficulty of every share accepted on the channel.
+//!
+//! Channels without a share for `CHANNEL_IDLE_SECS` are dropped from them. Requests refused by
+//! the rate limits are counted per limiter, see `crate::ratelimit`. The metrics of the mint
+//! follow, see `pool_mint::mint::metrics`.
+use super::events::Snapshot;
+use crate::ratelimit;
+use serde::Serialize;
+use std::fmt::{Display, Write};
+
+/// Upper bounds in milliseconds of the buckets of the time between two shares.
+pub const SHARE_INTERVAL_BUCKETS_MS: [u64; 13] = [
+    1_000, 2_000, 5_000, 10_000, 15_000, 20_000, 30_000, 45_000, 60_000, 90_000, 120_000, 300_000,
+    600_000,
+];
+/// Upper bounds of the buckets of the share difficulty, powers of 4.
+pub const SHARE_DIFFICULTY_BUCKETS: [u64; 17] = [
+    1,
+    4,
+    16,
+    64,
+    256,
+    1_024,
+    4_096,
+    16_384,
+    65_536,
+    262_144,
+    1_048_576,
+    4_194_304,
+    16_777_216,
+    67_108_864,
+    268_435_456,
+    1_073_741_824,
+    4_294_967_296,
+];
+/// Seconds without a share after which a channel is dropped from the histograms.
+pub const CHANNEL_IDLE_SECS: u64 = 3600;
+
+/// Counts of the values observed at or below each bound, the values above all of them only
+/// counted in `count`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Histogram {
+    bounds: &'static [u64],
+    counts: Vec<u64>,
+    pub sum: u64,
+    pub count: u64,
+}
+
+impl Histogram {
+    pub fn new(bounds: &'static [u64]) -> Self {
+        Self {
+            bounds,
+            counts: vec![0; bounds.len()],
+            sum: 0,
+            count: 0,
+        }
+    }
+
+    pub fn observe(&mut self, value: u64) {
+        if let Some(bucket) = self.bounds.iter().position(|bound| value <= *bound) {
+            self.counts[bucket] += 1;
+        }
+        self.sum = self.sum.saturating_add(value);
+        self.count += 1;
+    }
+
+    /// Upper bound and count of the values at or below it, of every bucket.
+    fn cumulative(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+        self.bounds
+            .iter()
+            .zip(&self.counts)
+            .scan(0, |total, (bound, count)| {
+                *total += count;
+                Some((*bound, *total))
+            })
+    }
+}
+
+/// Shares accepted on one channel of the pool.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ChannelShares {
+    pub account: String,
+    /// Unix time in milliseconds the channel opened or got its last share.
+    pub active_at_ms: u64,
+    last_share_at_ms: Option<u64>,
+    /// Milliseconds between two shares.
+    pub interval: Histogram,
+    pub difficulty: Histogram,
+}
+
+impl ChannelShares {
+    pub fn new(account: String, now_ms: u64) -> Self {
+        Self {
+            account,
+            active_at_ms: now_ms,
+            last_share_at_ms: None,
+            interval: Histogram::new(&SHARE_INTERVAL_BUCKETS_MS),
+            difficulty: Histogram::new(&SHARE_DIFFICULTY_BUCKETS),
+        }
+    }
+
+    pub fn observe(&mut self, difficulty: u64, now_ms: u64) {
+        if let Some(last_share_at_ms) = self.last_share_at_ms {
+            self.interval
+                .observe(now_ms.saturating_sub(last_share_at_ms));
+        }
+        self.last_share_at_ms = Some(now_ms);
+        self.active_at_ms = now_ms;
+        self.difficulty.observe(difficulty);
+    }
+}
+
+/// Whether a metric only ever grows, or goes up and down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Counter,
+    Gauge,
+}
+
+/// A counter or gauge of the process, exported by `render` and the StatsD exporter (see
+/// `statsd`). Counters are named without the `_total` Prometheus adds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metric {
+    pub name: &'static str,
+    pub kind: Kind,
+    pub help: &'static str,
+    /// Values of the metric, each with the labels telling it from the others.
+    pub samples: Vec<(Vec<(&'static str, String)>, f64)>,
+}
+
+impl Metric {
+    pub fn new(name: &'static str, kind: Kind, help: &'static str) -> Self {
+        Self {
+            name,
+            kind,
+            help,
+            samples: vec![],
+        }
+    }
+
+    pub fn with(mut self, labels: Vec<(&'static str, String)>, value: f64) -> Self {
+        self.samples.push((labels, value));
+        self
+    }
+}
+
+/// The counters and gauges of `snapshot`, `now` the unix time.
+pub fn metrics(snapshot: &Snapshot, now: u64) -> Vec<Metric> {
+    let mut miners = Metric::new("miners", Kind::Gauge, "Miners connected to each listener");
+    for (listener, count) in &snapshot.miners {
+        let listener = format!("{:?}", listener).to_lowercase();
+        miners = miners.with(vec![("listener", listener)], *count as f64);
+    }
+    let mut issued = Metric::new(
+        "mint_issued",
+        Kind::Counter,
+        "Tokens issued by the mint per unit",
+    );
+    for (unit, amount) in &snapshot.issued {
+        issued = issued.with(vec![("unit", unit.clone())], *amount as f64);
+    }
+    let mut rate_limited = Metric::new(
+        "rate_limited",
+        Kind::Counter,
+        "Requests refused by the rate limits",
+    );
+    for ((limiter, scope), count) in ratelimit::refused() {
+        let labels = vec![
+            ("limiter", limiter.to_string()),
+            ("scope", format!("{:?}", scope).to_lowercase()),
+        ];
+        rate_limited = rate_limited.with(labels, count as f64);
+    }
+    vec![
+        Metric::new(
+            "shares_accepted",
+            Kind::Counter,
+            "Shares accepted by the pool",
+        )
+        .with(vec![], snapshot.shares_accepted as f64),
+        Metric::new(
+            "shares_rejected",
+            Kind::Counter,
+            "Shares refused by the pool",
+        )
+        .with(vec![], snapshot.shares_rejected as f64),
+        Metric::new(
+            "share_weight",
+            Kind::Counter,
+            "Total difficulty of the shares accepted",
+        )
+        .with(vec![], snapshot.share_weight as f64),
+        Metric::new(
+            "channels_opened",
+            Kind::Counter,
+            "Channels opened on the pool",
+        )
+        .with(vec![], snapshot.channels_opened as f64),
+        Metric::new(
+            "node_ready",
+            Kind::Gauge,
+            "Whether the template provider is connected",
+        )
+        .with(vec![], snapshot.node_ready as u8 as f64),
+        Metric::new(
+            "hashrate",
+            Kind::Gauge,
+            "Hashes per second of the shares accepted over the last ten minutes",
+        )
+        .with(vec![], snapshot.hashrate(now)),
+        miners,
+        issued,
+        rate_limited,
+    ]
+}
+
+/// `snapshot` in the Prometheus text format, `now` the unix time.
+pub fn render(snapshot: &Snapshot, now: u64) -> String {
+    let mut out = String::new();
+    write(&mut out, metrics(snapshot, now));
+    family(
+        &mut out,
+        "potato_share_interval_seconds",
+        "histogram",
+        "Time between two shares accepted on a channel",
+    );
+    for (channel_id, channel) in &snapshot.channels {
+        let channel_id = channel_id.to_string();
+        let labels = [
+            ("channel_id", channel_id.as_str()),
+            ("account", &channel.account),
+        ];
+        histogram(
+            &mut out,
+            "potato_share_interval_seconds",
+            &labels,
+            &channel.interval,
+            1000,
+        );
+    }
+    family(
+        &mut out,
+        "potato_share_difficulty",
+        "histogram",
+        "Difficulty of the shares accepted on a channel",
+    );
+    for (channel_id, channel) in &snapshot.channels {
+        let channel_id = channel_id.to_string();
+        let labels = [
+            ("channel_id", channel_id.as_str()),
+            ("account", &channel.account),
+        ];
+        histogram(
+            &mut out,
+            "potato_share_difficulty",
+            &labels,
+            &channel.difficulty,
+            1,
+        );
+    }
+    out
+}
+
+/// `metrics` in the Prometheus text format, named `potato_<name>`.
+pub fn write(out: &mut String, metrics: Vec<Metric>) {
+    for metric in metrics {
+        let (name, kind) = match metric.kind {
+            Kind::Counter => (format!("potato_{}_total", metric.name), "counter"),
+            Kind::Gauge => (format!("potato_{}", metric.name), "gauge"),
+        };
+        family(out, &name, kind, metric.help);
+        for (labels, value) in &metric.samples {
+            let labels: Vec<_> = labels
+                .iter()
+                .map(|(label, value)| (*label, value.as_str()))
+                .collect();
+            sample(out, &name, &labels, value);
+        }
+    }
+}
+
+pub fn family(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn sample(out: &mut String, name: &str, labels: &[(&str, &str)], value: impl Display) {
+    let labels: Vec<_> = labels
+        .iter()
+        .map(|(name, value)| format!("{}=\"{}\"", name, escape(value)))
+        .collect();
+    if labels.is_empty() {
+        let _ = writeln!(out, "{} {}", name, value);
+    } else {
+        let _ = writeln!(out, "{}{{{}}} {}", name, labels.join(","), value);
+    }
+}
+
+/// Samples of `histogram`, its values divided by `scale`.
+pub fn histogram(
+    out: &mut String,
+    name: &str,
+    labels: &[(&str, &str)],
+    histogram: &Histogram,
+    scale: u64,
+) {
+    let scaled = |value: u64| value as f64 / scale as f64;
+    for (bound, count) in histogram.cumulative() {
+        let le = scaled(bound).to_string();
+        let labels: Vec<_> = labels
+            .iter()
+            .copied()
+            .chain([("le", le.as_str())])
+            .collect();
+        sample(out, &format!("{}_bucket", name), &labels, count);
+    }
+    let labels_inf: Vec<_> = labels.iter().copied().chain([("le", "+Inf")]).collect();
+    sample(
+        out,
+        &format!("{}_bucket", name),
+        &labels_inf,
+        histogram.count,
+    );
+    sample(out, &format!("{}_sum", name), labels, scaled(histogram.sum));
+    sample(out, &format!("{}_count", name), labels, histogram.count);
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::status::events::{Event, EventBus};
+
+    #[test]
+    fn renders_the_share_histograms_of_every_channel() {
+        let mut channel = ChannelShares::new("alice".into(), 0);
+        for (difficulty, at_ms) in [(16, 10_000), (16, 20_000), (64, 26_000)] {
+            channel.observe(difficulty, at_ms);
+        }
+        assert_eq!(channel.interval.count, 2);
+        assert_eq!(channel.interval.sum, 16_000);
+        assert_eq!(channel.difficulty.sum, 96);
+
+        let bus = EventBus::new(16);
+        bus.publish(Event::ShareAccepted {
+            channel_id: 7,
+            account: "al\"ice".into(),
+            weight: 16,
+        });
+        let metrics = render(&bus.snapshot(), 0);
+        assert!(metrics.contains("potato_shares_accepted_total 1\n"));
+        assert!(metrics.contains(
+            "potato_share_difficulty_bucket{channel_id=\"7\",account=\"al\\\"ice\",le=\"16\"} 1\n"
+        ));
+        assert!(metrics.contains(
+            "potato_share_difficulty_bucket{channel_id=\"7\",account=\"al\\\"ice\",le=\"4\"} 0\n"
+        ));
+        // no interval until the second share
+        assert!(metrics.contains(
+            "potato_share_interval_seconds_count{channel_id=\"7\",account=\"al\\\"ice\"} 0\n"
+        ));
+    }
+}
@@ -1,12 +1,37 @@
-use crate::error::{self, Error, PoolError};
+#[cfg(feature = "mint")]
+pub mod alerts;
+#[cfg(feature = "mint")]
+pub mod anomaly;
+pub mod audit;
+pub mod diagnostics;
+pub mod events;
+#[cfg(feature = "mint")]
+pub mod explorer;
+pub mod health;
+pub mod heartbeat;
+#[cfg(feature = "mint")]
+pub mod history;
+pub mod hooks;
+pub mod metrics;
+#[cfg(feature = "mint")]
+pub mod nostr;
+#[cfg(feature = "mint")]
+pub mod report;
+#[cfg(feature = "mint")]
+pub mod server;
+pub mod statsd;
+#[cfg(feature = "pool")]
+pub mod tip;
+
+use crate::error::{self, Error, PoolError, Sv2MiningError};
 
 #[derive(Debug)]
 pub enum Sender {
-    Downstream(async_channel::Sender<Status<'static>>),
-    DownstreamListener(async_channel::Sender<Status<'static>>),
-    Bridge(async_channel::Sender<Status<'static>>),
-    Upstream(async_channel::Sender<Status<'static>>),
-    TemplateReceiver(async_channel::Sender<Status<'static>>),
+    Downstream(async_channel::Sender<Status>),
+    DownstreamListener(async_channel::Sender<Status>),
+    Bridge(async_channel::Sender<Status>),
+    Upstream(async_channel::Sender<Status>),
+    TemplateReceiver(async_channel::Sender<Status>),
 }
 
 impl Sender {
@@ -20,10 +45,7 @@ impl Sender {
         }
     }
 
-    pub async fn send(
-        &self,
-        status: Status<'static>,
-    ) -> Result<(), async_channel::SendError<Status<'_>>> {
+    pub async fn send(&self, status: Status) -> Result<(), async_channel::SendError<Status>> {
         match self {
             Self::Downstream(inner) => inner.send(status).await,
             Self::DownstreamListener(inner) => inner.send(status).await,
@@ -47,11 +69,11 @@ impl Clone for Sender {
 }
 
 #[derive(Debug)]
-pub enum State<'a> {
-    DownstreamShutdown(Error<'a>),
-    BridgeShutdown(Error<'a>),
-    UpstreamShutdown(Error<'a>),
-    UpstreamTryReconnect(Error<'a>),
+pub enum State {
+    DownstreamShutdown(Error),
+    BridgeShutdown(Error),
+    UpstreamShutdown(Error),
+    UpstreamTryReconnect(Error),
     DownstreamShutdownPool(PoolError),
     TemplateProviderShutdown(PoolError),
     DownstreamInstanceDropped(u32),
@@ -59,13 +81,13 @@ pub enum State<'a> {
 }
 
 #[derive(Debug)]
-pub struct Status<'a> {
-    pub state: State<'a>,
+pub struct Status {
+    pub state: State,
 }
 
 async fn send_status(
     sender: &Sender,
-    e: crate::error::Error<'static>,
+    e: crate::error::Error,
     outcome: error_handling::ErrorBranch,
 ) -> error_handling::ErrorBranch {
     match sender {
@@ -118,11 +140,8 @@ async fn send_status(
 }
 
 // this is called by `error_handling::handle_result!`
-pub async fn handle_error(
-    sender: &Sender,
-    e: error::Error<'static>,
-) -> error_handling::ErrorBranch {
-    tracing::error!("Error: {:?}", &e);
+pub async fn handle_error(sender: &Sender, e: error::Error) -> error_handling::ErrorBranch {
+    tracing::error!(code = e.code(), "Error: {:?}", &e);
     match e {
         Error::VecToSlice32(_) => send_status(sender, e, error_handling::ErrorBranch::Break).await,
         // Errors on bad CLI argument input.
@@ -137,6 +156,8 @@ pub async fn handle_error(
         Error::BinarySv2(_) => send_status(sender, e, error_handling::ErrorBranch::Break).await,
         // Errors on bad noise handshake.
         Error::CodecNoise(_) => send_status(sender, e, error_handling::ErrorBranch::Break).await,
+        // Errors on a handshake with no trusted authority key.
+        Error::Handshake(_) => send_status(sender, e, error_handling::ErrorBranch::Break).await,
         // Errors from `framing_sv2` crate.
         Error::FramingSv2(_) => send_status(sender, e, error_handling::ErrorBranch::Break).await,
         //If the pool sends the tproxy an invalid extranonce
@@ -180,9 +201,7 @@ pub async fn handle_error(
         Error::Sv2ProtocolError(ref inner) => {
             match inner {
                 // don't notify main thread just continue
-                roles_logic_sv2::parsers::Mining::SubmitSharesError(_) => {
-                    error_handling::ErrorBranch::Continue
-                }
+                Sv2MiningError::SubmitShares { .. } => error_handling::ErrorBranch::Continue,
                 _ => send_status(sender, e, error_handling::ErrorBranch::Break).await,
             }
         }
@@ -192,6 +211,9 @@ pub async fn handle_error(
         Error::Sv1MessageTooLong => {
             send_status(sender, e, error_handling::ErrorBranch::Break).await
         }
+        Error::Unreachable(_) => send_status(sender, e, error_handling::ErrorBranch::Break).await,
+        // The listener of the miners can't bind its address.
+        Error::Bind(_) => send_status(sender, e, error_handling::ErrorBranch::Break).await,
         Error::MiningPoolError(pool_error) => {
             send_status(
                 sender,
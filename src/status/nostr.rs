@@ -0,0 +1,163 @@
+//! Public status of the pool posted to Nostr relays under the operator's key, for anyone to
+//! follow what the pool does: a note every `interval_secs` with its hashrate, workers and blocks
+//! found, and one for every block found as it happens.
+use super::{
+    events::{self, Event, Snapshot, HASHRATE_WINDOW_SECS},
+    server::{duration, hashrate},
+};
+use crate::pool_mint::mint::nostr::{NostrConfig, NostrDelivery};
+use serde::Deserialize;
+use std::{sync::Arc, time::Duration};
+use tokio::sync::broadcast::error::RecvError;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct NostrStatusConfig {
+    #[serde(default = "NostrStatusConfig::default_interval_secs")]
+    pub interval_secs: u64,
+    /// Posts a note for every block found.
+    #[serde(default = "NostrStatusConfig::default_block_found")]
+    pub block_found: bool,
+    /// Relays notes are posted to, and the operator's key they are signed with.
+    #[serde(flatten)]
+    pub relays: NostrConfig,
+}
+
+impl NostrStatusConfig {
+    fn default_interval_secs() -> u64 {
+        3600
+    }
+
+    fn default_block_found() -> bool {
+        true
+    }
+}
+
+/// The periodic note of `snapshot`, `now` the unix time.
+fn status_note(snapshot: &Snapshot, now: u64) -> String {
+    let since = now.saturating_sub(HASHRATE_WINDOW_SECS);
+    let workers = snapshot
+        .workers
+        .values()
+        .filter(|worker| worker.last_share_at > since)
+        .count();
+    let mut note = format!(
+        "Pool status: hashrate {}, workers {}, blocks found {}",
+        hashrate(snapshot.hashrate(now)),
+        workers,
+        snapshot.blocks_found
+    );
+    if let Some(block) = snapshot.recent_blocks.back() {
+        note.push_str(&format!(
+            ", last block {} ago",
+            duration(now.saturating_sub(block.at))
+        ));
+    }
+    note
+}
+
+/// The note of `event`, if one is posted for it.
+fn event_note(config: &NostrStatusConfig, event: &Event) -> Option<String> {
+    match event {
+        Event::BlockFound {
+            coinbase_txid,
+            reward,
+        } if config.block_found => Some(format!(
+            "Block found by the pool! {} sat reward, coinbase {}",
+            reward, coinbase_txid
+        )),
+        _ => None,
+    }
+}
+
+/// Posts the notes of `config` until `cancel_token` is cancelled.
+pub async fn run(config: NostrStatusConfig, cancel_token: CancellationToken) {
+    let delivery = match NostrDelivery::new(&config.relays) {
+        Ok(delivery) => Arc::new(delivery),
+        Err(e) => {
+            error!("Nostr status: {}", e);
+            return;
+        }
+    };
+    info!(
+        "Posting the pool status to {} Nostr relays as {}",
+        config.relays.relays.len(),
+        delivery.pubkey()
+    );
+    let mut events = events::subscribe();
+    let mut notes = tokio::time::interval(Duration::from_secs(config.interval_secs.max(60)));
+    // the first tick is immediate, before anything happened
+    notes.tick().await;
+    loop {
+        let note = tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) => event_note(&config, &event),
+                Err(RecvError::Lagged(_)) => None,
+                Err(RecvError::Closed) => break,
+            },
+            _ = notes.tick() => Some(status_note(&events::snapshot(), events::now())),
+            _ = cancel_token.cancelled() => break,
+        };
+        let Some(note) = note else {
+            continue;
+        };
+        let delivery = delivery.clone();
+        tokio::spawn(async move {
+            if let Err(e) = delivery.post(&note).await {
+                warn!("Nostr status: failed to post: {}", e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::status::events::EventBus;
+
+    #[test]
+    fn notes_the_hashrate_workers_and_blocks() {
+        let bus = EventBus::new(16);
+        bus.publish(Event::ShareAccepted {
+            channel_id: 1,
+            account: "alice".into(),
+            weight: 600,
+        });
+        let block = Event::BlockFound {
+            coinbase_txid: "ab".repeat(32),
+            reward: 312_500_000,
+        };
+        bus.publish(block.clone());
+        let mut snapshot = bus.snapshot();
+        snapshot.started_at = Some(0);
+        let now = snapshot.recent_blocks[0].at + 120;
+        assert_eq!(
+            status_note(&snapshot, now),
+            format!(
+                "Pool status: hashrate {}, workers 1, blocks found 1, last block 2m 0s ago",
+                hashrate(snapshot.hashrate(now))
+            )
+        );
+        // workers idle for longer than the hashrate window are left out
+        let later = now + HASHRATE_WINDOW_SECS;
+        assert!(
+            status_note(&snapshot, later).starts_with("Pool status: hashrate 0.00 H/s, workers 0")
+        );
+
+        let mut config = NostrStatusConfig {
+            interval_secs: 3600,
+            block_found: true,
+            relays: NostrConfig {
+                relays: vec!["wss://relay.example".into()],
+                secret_key_path: "".into(),
+                timeout_secs: 10,
+            },
+        };
+        assert!(event_note(&config, &block)
+            .unwrap()
+            .contains("312500000 sat reward"));
+        config.block_found = false;
+        assert_eq!(event_note(&config, &block), None);
+    }
+}
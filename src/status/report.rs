@@ -0,0 +1,283 @@
+//! Uptime and performance of the pool over a period, `potato report --last 7d`, from the events
+//! recorded in the event history (see `history`) and the payouts of the mint database.
+//!
+//! The pool is up from its template provider getting ready until it goes down, the pool fails or
+//! the process starts again. A process that exited is up until the last event it recorded.
+//! Effective hashrate is that of the shares accepted over the time up, and luck the blocks found
+//! over those the weight of the shares accepted was expected to find at the network difficulty.
+use super::{
+    history,
+    server::{duration, hashrate},
+};
+use crate::pool_mint::mint::payout::PayoutTotal;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// Events telling whether the pool was up, read from before the period too.
+pub const STATE_EVENTS: [&str; 4] = ["started", "node_ready", "upstream_down", "subsystem_failed"];
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Report {
+    /// Unix times in seconds the period starts and ends at.
+    pub since: u64,
+    pub until: u64,
+    pub uptime_secs: u64,
+    /// Part of the period the pool was up.
+    pub uptime_ratio: f64,
+    /// Times the process started, and the pool failed.
+    pub starts: u64,
+    pub pool_failures: u64,
+    pub shares_accepted: u64,
+    pub shares_rejected: u64,
+    pub acceptance_ratio: Option<f64>,
+    /// Shares rejected per error code.
+    pub rejections: BTreeMap<String, u64>,
+    pub share_weight: u64,
+    /// Hashes per second of the shares accepted while the pool was up.
+    pub effective_hashrate: f64,
+    pub blocks_found: u64,
+    pub network_difficulty: Option<f64>,
+    pub expected_blocks: Option<f64>,
+    /// Blocks found per block expected, unknown without the network difficulty.
+    pub luck: Option<f64>,
+    pub payouts: Vec<PayoutTotal>,
+}
+
+impl Report {
+    /// The report from `since` until `until`, unix times in seconds, of `events` as read by
+    /// `history::query`, oldest first. Events before `since` only tell whether the pool was up
+    /// when the period started.
+    pub fn new(
+        since: u64,
+        until: u64,
+        events: &[Value],
+        network_difficulty: Option<f64>,
+        payouts: Vec<PayoutTotal>,
+    ) -> Self {
+        let (since_ms, until_ms) = (since * 1000, until * 1000);
+        // the time from `from` until `to` within the period
+        let within = |from: u64, to: u64| to.min(until_ms).saturating_sub(from.max(since_ms));
+        let mut report = Self {
+            since,
+            until,
+            uptime_secs: 0,
+            uptime_ratio: 0.0,
+            starts: 0,
+            pool_failures: 0,
+            shares_accepted: 0,
+            shares_rejected: 0,
+            acceptance_ratio: None,
+            rejections: BTreeMap::new(),
+            share_weight: 0,
+            effective_hashrate: 0.0,
+            blocks_found: 0,
+            network_difficulty,
+            expected_blocks: None,
+            luck: None,
+            payouts,
+        };
+        let mut uptime_ms = 0;
+        let mut up_since = None;
+        let mut last_at = 0;
+        for event in events {
+            let at = event["at_ms"].as_u64().unwrap_or_default();
+            let in_period = (since_ms..=until_ms).contains(&at);
+            match event["type"].as_str().unwrap_or_default() {
+                "started" => {
+                    // the previous process exited after its last event
+                    if let Some(from) = up_since.take() {
+                        uptime_ms += within(from, last_at);
+                    }
+                    report.starts += in_period as u64;
+                }
+                "node_ready" => {
+                    up_since.get_or_insert(at);
+                }
+                "upstream_down" if event["upstream"] == "template_provider" => {
+                    if let Some(from) = up_since.take() {
+                        uptime_ms += within(from, at);
+                    }
+                }
+                "subsystem_failed" if event["subsystem"] == "pool" => {
+                    if let Some(from) = up_since.take() {
+                        uptime_ms += within(from, at);
+                    }
+                    report.pool_failures += in_period as u64;
+                }
+                "share_accepted" if in_period => {
+                    report.shares_accepted += 1;
+                    report.share_weight += event["weight"].as_u64().unwrap_or_default();
+                }
+                "share_rejected" if in_period => {
+                    report.shares_rejected += 1;
+                    let reason = event["reason"].as_str().unwrap_or_default().to_string();
+                    *report.rejections.entry(reason).or_default() += 1;
+                }
+                "block_found" if in_period => report.blocks_found += 1,
+                _ => {}
+            }
+            last_at = at;
+        }
+        if let Some(from) = up_since {
+            uptime_ms += within(from, last_at);
+        }
+
+        report.uptime_secs = uptime_ms / 1000;
+        if until > since {
+            report.uptime_ratio = report.uptime_secs as f64 / (until - since) as f64;
+        }
+        let submitted = report.shares_accepted + report.shares_rejected;
+        if submitted > 0 {
+            report.acceptance_ratio = Some(report.shares_accepted as f64 / submitted as f64);
+        }
+        // a share of weight 1 takes 2^32 hashes on average, a block of difficulty D as many times
+        if report.uptime_secs > 0 {
+            report.effective_hashrate =
+                report.share_weight as f64 * 2f64.powi(32) / report.uptime_secs as f64;
+        }
+        if let Some(difficulty) = network_difficulty.filter(|difficulty| *difficulty > 0.0) {
+            let expected = report.share_weight as f64 / difficulty;
+            report.expected_blocks = Some(expected);
+            if expected > 0.0 {
+                report.luck = Some(report.blocks_found as f64 / expected);
+            }
+        }
+        report
+    }
+
+    /// The report in a few lines, to print.
+    pub fn summary(&self) -> String {
+        let mut lines = vec![format!(
+            "Report of the last {}",
+            duration(self.until.saturating_sub(self.since))
+        )];
+        lines.push(format!(
+            "Uptime      {} ({:.2}%), {} starts, {} pool failures",
+            duration(self.uptime_secs),
+            self.uptime_ratio * 100.0,
+            self.starts,
+            self.pool_failures
+        ));
+        lines.push(format!(
+            "Hashrate    {} effective",
+            hashrate(self.effective_hashrate)
+        ));
+        let accepted = match self.acceptance_ratio {
+            Some(ratio) => format!("{:.2}% accepted", ratio * 100.0),
+            None => "none submitted".to_string(),
+        };
+        lines.push(format!(
+            "Shares      {} accepted, {} rejected ({})",
+            self.shares_accepted, self.shares_rejected, accepted
+        ));
+        if !self.rejections.is_empty() {
+            let rejections: Vec<_> = self
+                .rejections
+                .iter()
+                .map(|(reason, count)| format!("{} {}", count, reason))
+                .collect();
+            lines.push(format!("Rejected    {}", rejections.join(", ")));
+        }
+        let luck = match (self.expected_blocks, self.luck) {
+            (Some(expected), Some(luck)) => {
+                format!("{:.4} expected, luck {:.1}%", expected, luck * 100.0)
+            }
+            (Some(expected), None) => format!("{:.4} expected", expected),
+            _ => "network difficulty unknown, no luck".to_string(),
+        };
+        lines.push(format!("Blocks      {} found, {}", self.blocks_found, luck));
+        let payouts: Vec<_> = self
+            .payouts
+            .iter()
+            .map(|total| {
+                format!(
+                    "{} {} in {} payouts ({} delivered)",
+                    total.amount, total.unit, total.count, total.delivered
+                )
+            })
+            .collect();
+        match payouts.is_empty() {
+            true => lines.push("Payouts     none".to_string()),
+            false => lines.push(format!("Payouts     {}", payouts.join(", "))),
+        }
+        lines.join("\n")
+    }
+}
+
+/// The events of the report from `since_ms` until `until_ms` in the event history at `path`:
+/// those telling whether the pool was up from before the period, then all of the period.
+pub fn events(path: &str, since_ms: u64, until_ms: u64) -> rusqlite::Result<Vec<Value>> {
+    let state: Vec<String> = STATE_EVENTS.iter().map(|kind| kind.to_string()).collect();
+    let mut events = history::query(
+        path,
+        0,
+        Some(since_ms.saturating_sub(1)),
+        &state,
+        i64::MAX as u64,
+    )?;
+    events.extend(history::query(
+        path,
+        since_ms,
+        Some(until_ms),
+        &[],
+        i64::MAX as u64,
+    )?);
+    Ok(events)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn adds_up_uptime_shares_and_luck() {
+        let day = 86_400_000;
+        let events = vec![
+            // up since before the period
+            json!({"at_ms": 0, "type": "started"}),
+            json!({"at_ms": 1000, "type": "node_ready"}),
+            json!({"at_ms": day + 1000, "type": "share_accepted", "weight": 300}),
+            json!({"at_ms": day + 2000, "type": "share_accepted", "weight": 300}),
+            json!({"at_ms": day + 3000, "type": "share_rejected", "reason": "stale-share"}),
+            json!({"at_ms": day + 4000, "type": "block_found"}),
+            json!({"at_ms": 2 * day, "type": "upstream_down", "upstream": "pool"}),
+            // down for the first quarter of the third day
+            json!({
+                "at_ms": 2 * day + 3000,
+                "type": "upstream_down",
+                "upstream": "template_provider"
+            }),
+            json!({"at_ms": 2 * day + day / 4, "type": "node_ready"}),
+            json!({"at_ms": 2 * day + day / 2, "type": "share_accepted", "weight": 400}),
+            // exited unnoticed after its last event
+            json!({"at_ms": 3 * day, "type": "started"}),
+            json!({"at_ms": 3 * day + day / 2, "type": "node_ready"}),
+            json!({"at_ms": 4 * day, "type": "share_accepted", "weight": 0}),
+        ];
+        let payouts = vec![PayoutTotal {
+            unit: "sat".into(),
+            count: 2,
+            amount: 5000,
+            delivered: 1,
+        }];
+        let since = 86_400;
+        let report = Report::new(since, 4 * since, &events, Some(500.0), payouts);
+        assert_eq!(report.starts, 1);
+        assert_eq!(report.uptime_secs, 86_403 + 21_600 + 43_200);
+        assert_eq!(report.shares_accepted, 4);
+        assert_eq!(report.share_weight, 1000);
+        assert_eq!(report.acceptance_ratio, Some(0.8));
+        assert_eq!(report.rejections["stale-share"], 1);
+        assert_eq!(report.expected_blocks, Some(2.0));
+        assert_eq!(report.luck, Some(0.5));
+        let summary = report.summary();
+        assert!(summary.contains("Blocks      1 found, 2.0000 expected, luck 50.0%"));
+        assert!(summary.contains("Payouts     5000 sat in 2 payouts (1 delivered)"));
+
+        let report = Report::new(since, 4 * since, &events, None, vec![]);
+        assert_eq!(report.luck, None);
+        assert!(report.summary().contains("no luck"));
+    }
+}
@@ -0,0 +1,451 @@
+//! Read-only HTTP server of the process status, served on `status_address`:
+//!
+//! - `/healthz` and `/readyz`, see `health`;
+//! - `/v1/status`, the runtime state of the process for tools to read: uptime, version,
+//!   network, chain tip, miners connected, hashrate, shares and tokens issued, recent blocks
+//!   found and errors logged, also rendered by `potato tui`;
+//! - `/metrics`, the metrics of the process for Prometheus to scrape, see `metrics`, followed by
+//!   those of the mint once it started;
+//! - `/v1/events`, a WebSocket streaming the events of the bus as they are published, one JSON
+//!   object per text message, for dashboards and bots. `?types=share_accepted,block_found` only
+//!   streams events of those types. A subscriber lagging behind is told how many events it missed
+//!   with `{"type":"lagged","missed":n}`;
+//! - `/v1/diagnostics`, the tasks of the runtime and how full the channels of the share pipeline
+//!   are, see `diagnostics`;
+//! - `/explorer`, pages of the blocks found and the share history of every worker, see
+//!   `explorer`.
+use super::{
+    diagnostics::Diagnostics,
+    events::{self, Event, FoundBlock, Listener, LoggedError, Snapshot, Worker},
+    explorer::{self, PageQuery},
+    health::{health, Health},
+    metrics,
+};
+use crate::{
+    error::{MintError, MintResult},
+    net,
+    pool_mint::mint::{metrics as mint_metrics, Mint},
+};
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Path, Query, State,
+    },
+    http::{header, StatusCode},
+    response::{Html, Response},
+    routing::get,
+    Json, Router,
+};
+use roles_logic_sv2::utils::Mutex;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io,
+    sync::{Arc, OnceLock},
+};
+use tokio::sync::broadcast::error::RecvError;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+/// Answer of `/v1/status`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StatusReport {
+    pub version: String,
+    pub network: Option<String>,
+    pub uptime_secs: u64,
+    /// Whether the template provider is connected.
+    pub node_ready: bool,
+    /// Previous block hash of the chain tip announced last, and when.
+    pub tip: Option<String>,
+    pub tip_at: Option<u64>,
+    /// Miners connected to the pool and to the translator.
+    pub miners: BTreeMap<Listener, u64>,
+    /// Hashes per second of the shares accepted over the last ten minutes.
+    pub hashrate: f64,
+    pub shares_accepted: u64,
+    pub shares_rejected: u64,
+    /// Shares accepted per account.
+    pub workers: BTreeMap<String, Worker>,
+    /// Tokens issued per unit.
+    pub issued: BTreeMap<String, u64>,
+    pub recent_blocks: VecDeque<FoundBlock>,
+    pub recent_errors: VecDeque<LoggedError>,
+}
+
+impl StatusReport {
+    pub fn new(snapshot: Snapshot, now: u64) -> Self {
+        let mut miners = snapshot.miners.clone();
+        for listener in [Listener::Pool, Listener::Translator] {
+            miners.entry(listener).or_default();
+        }
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            network: snapshot.network.clone(),
+            uptime_secs: snapshot
+                .started_at
+                .map_or(0, |started_at| now.saturating_sub(started_at)),
+            node_ready: snapshot.node_ready,
+            tip: snapshot.last_block.clone(),
+            tip_at: snapshot.last_block_at,
+            miners,
+            hashrate: snapshot.hashrate(now),
+            shares_accepted: snapshot.shares_accepted,
+            shares_rejected: snapshot.shares_rejected,
+            workers: snapshot.workers,
+            issued: snapshot.issued,
+            recent_blocks: snapshot.recent_blocks,
+            recent_errors: snapshot.recent_errors,
+        }
+    }
+
+    /// The report for humans, as printed by `potato status`.
+    pub fn summary(&self, now: u64) -> String {
+        let mut lines = vec![format!(
+            "potato {} on {}, up {}",
+            self.version,
+            self.network.as_deref().unwrap_or("unknown network"),
+            duration(self.uptime_secs)
+        )];
+        let node = match (self.node_ready, &self.tip, self.tip_at) {
+            (false, _, _) => "disconnected".to_string(),
+            (true, Some(tip), Some(at)) => format!(
+                "connected, tip {} announced {} ago",
+                tip,
+                duration(now.saturating_sub(at))
+            ),
+            (true, _, _) => "connected, no tip announced yet".to_string(),
+        };
+        lines.push(format!("Node        {}", node));
+        let miners: Vec<_> = self
+            .miners
+            .iter()
+            .map(|(listener, miners)| format!("{} {:?}", miners, listener).to_lowercase())
+            .collect();
+        lines.push(format!("Miners      {}", miners.join(", ")));
+        lines.push(format!("Hashrate    {}", hashrate(self.hashrate)));
+        let submitted = self.shares_accepted + self.shares_rejected;
+        let rejected = match submitted {
+            0 => 0.0,
+            submitted => self.shares_rejected as f64 * 100.0 / submitted as f64,
+        };
+        lines.push(format!(
+            "Shares      {} accepted, {} rejected ({:.1}%)",
+            self.shares_accepted, self.shares_rejected, rejected
+        ));
+        let last_block = match self.recent_blocks.back() {
+            Some(block) => format!(
+                "{} ago, {} sat, coinbase {}",
+                duration(now.saturating_sub(block.at)),
+                block.reward,
+                block.coinbase_txid
+            ),
+            None => "none found yet".to_string(),
+        };
+        lines.push(format!("Last block  {}", last_block));
+        let issued: Vec<_> = self
+            .issued
+            .iter()
+            .map(|(unit, amount)| format!("{} {}", amount, unit))
+            .collect();
+        if !issued.is_empty() {
+            lines.push(format!("Issued      {}", issued.join(", ")));
+        }
+        if let Some(error) = self.recent_errors.back() {
+            lines.push(format!(
+                "Last error  {} ago, {}: {}",
+                duration(now.saturating_sub(error.at)),
+                error.target,
+                error.message
+            ));
+        }
+        lines.join("\n")
+    }
+}
+
+/// `hashes_per_second` in the largest unit keeping it above 1.
+pub fn hashrate(hashes_per_second: f64) -> String {
+    const UNITS: [&str; 7] = ["H/s", "kH/s", "MH/s", "GH/s", "TH/s", "PH/s", "EH/s"];
+    let mut rate = hashes_per_second;
+    let mut unit = 0;
+    while rate >= 1000.0 && unit < UNITS.len() - 1 {
+        rate /= 1000.0;
+        unit += 1;
+    }
+    format!("{:.2} {}", rate, UNITS[unit])
+}
+
+/// `secs` in its two largest units, e.g. `2h 5m`.
+pub fn duration(secs: u64) -> String {
+    let (days, hours, minutes) = (secs / 86400, secs / 3600 % 24, secs / 60 % 60);
+    match (days, hours, minutes) {
+        (0, 0, 0) => format!("{}s", secs),
+        (0, 0, _) => format!("{}m {}s", minutes, secs % 60),
+        (0, _, _) => format!("{}h {}m", hours, minutes),
+        _ => format!("{}d {}h", days, hours),
+    }
+}
+
+/// Serves the status of the process. Starts before the mint, which is handed over with
+/// `set_mint` once started.
+#[derive(Debug, Clone, Default)]
+pub struct StatusServer {
+    mint: Arc<OnceLock<Arc<Mutex<Mint>>>>,
+}
+
+impl StatusServer {
+    pub fn set_mint(&self, mint: Arc<Mutex<Mint>>) {
+        let _ = self.mint.set(mint);
+    }
+
+    fn health(&self) -> Health {
+        let mint = self.mint.get().map(|mint| {
+            mint.safe_lock(|m| m.database_health())
+                .map_err(MintError::from)
+                .and_then(|health| health)
+        });
+        health(&events::snapshot(), mint)
+    }
+
+    /// The explorer page rendered from the mint, unavailable until the mint started.
+    fn explorer(
+        &self,
+        render: impl FnOnce(&Mint) -> MintResult<String>,
+    ) -> (StatusCode, Html<String>) {
+        let Some(mint) = self.mint.get() else {
+            return (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Html("The mint has not started yet.".to_string()),
+            );
+        };
+        match mint.safe_lock(|m| render(m)) {
+            Ok(Ok(page)) => (StatusCode::OK, Html(page)),
+            Ok(Err(e)) => {
+                warn!("Status API: failed to render an explorer page: {}", e);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Html("Failed to read the mint database.".to_string()),
+                )
+            }
+            Err(e) => {
+                warn!("Status API: failed to render an explorer page: {}", e);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Html("The mint is unavailable.".to_string()),
+                )
+            }
+        }
+    }
+
+    pub async fn serve(self, address: &str, cancel_token: CancellationToken) -> io::Result<()> {
+        let listener = net::listen(address)?;
+        let address = listener.local_addr()?;
+        info!("Status API listening on {}", address);
+        events::publish(events::Event::ListenerBound {
+            listener: Listener::Status,
+            address: address.to_string(),
+        });
+        let router = Router::new()
+            .route("/healthz", get(get_healthz))
+            .route("/readyz", get(get_readyz))
+            .route("/v1/status", get(get_status))
+            .route("/v1/events", get(get_events))
+            .route("/v1/diagnostics", get(get_diagnostics))
+            .route("/metrics", get(get_metrics))
+            .route("/explorer", get(get_explorer_blocks))
+            .route("/explorer/blocks", get(get_explorer_blocks))
+            .route("/explorer/workers", get(get_explorer_workers))
+            .route("/explorer/workers/:account", get(get_explorer_worker))
+            .with_state(self);
+        axum::serve(listener, router)
+            .with_graceful_shutdown(cancel_token.cancelled_owned())
+            .await
+    }
+}
+
+async fn get_healthz(State(server): State<StatusServer>) -> Json<Health> {
+    Json(server.health())
+}
+
+async fn get_readyz(State(server): State<StatusServer>) -> (StatusCode, Json<Health>) {
+    let health = server.health();
+    let status = if health.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(health))
+}
+
+async fn get_status() -> Json<StatusReport> {
+    Json(StatusReport::new(events::snapshot(), events::now()))
+}
+
+async fn get_diagnostics() -> Json<Diagnostics> {
+    Json(Diagnostics::current())
+}
+
+async fn get_metrics(
+    State(server): State<StatusServer>,
+) -> ([(header::HeaderName, &'static str); 1], String) {
+    let mut metrics = metrics::render(&events::snapshot(), events::now());
+    if let Some(mint) = server.mint.get() {
+        match mint.safe_lock(|m| mint_metrics::render(m)) {
+            Ok(Ok(mint_metrics)) => metrics.push_str(&mint_metrics),
+            Ok(Err(e)) => warn!("Status API: failed to read the mint metrics: {}", e),
+            Err(e) => warn!("Status API: failed to read the mint metrics: {}", e),
+        }
+    }
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics,
+    )
+}
+
+async fn get_explorer_blocks(
+    State(server): State<StatusServer>,
+    Query(query): Query<PageQuery>,
+) -> (StatusCode, Html<String>) {
+    server.explorer(|mint| explorer::blocks(mint, &query, events::now()))
+}
+
+async fn get_explorer_workers(
+    State(server): State<StatusServer>,
+    Query(query): Query<PageQuery>,
+) -> (StatusCode, Html<String>) {
+    server.explorer(|mint| explorer::workers(mint, &query))
+}
+
+async fn get_explorer_worker(
+    State(server): State<StatusServer>,
+    Path(account): Path<String>,
+    Query(query): Query<PageQuery>,
+) -> (StatusCode, Html<String>) {
+    server.explorer(|mint| explorer::worker(mint, &account, &query, events::now()))
+}
+
+/// Query of `/v1/events`.
+#[derive(Debug, Default, Deserialize)]
+struct EventsQuery {
+    /// Comma separated types of the events to stream, all if unset.
+    types: Option<String>,
+}
+
+impl EventsQuery {
+    /// The message streamed for `event`, none if filtered out.
+    fn message(&self, event: &Event) -> Option<String> {
+        let event = serde_json::to_value(event).ok()?;
+        if let Some(types) = &self.types {
+            let kind = event["type"].as_str()?;
+            if !types.split(',').any(|t| t.trim() == kind) {
+                return None;
+            }
+        }
+        Some(event.to_string())
+    }
+}
+
+async fn get_events(Query(query): Query<EventsQuery>, ws: WebSocketUpgrade) -> Response {
+    ws.on_upgrade(move |socket| stream_events(query, socket))
+}
+
+/// Pushes the events matching `query` to one WebSocket connection, until either side closes it.
+async fn stream_events(query: EventsQuery, mut socket: WebSocket) {
+    let mut events = events::subscribe();
+    loop {
+        let message = tokio::select! {
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => continue,
+            },
+            event = events.recv() => match event {
+                Ok(event) => match query.message(&event) {
+                    Some(message) => message,
+                    None => continue,
+                },
+                Err(RecvError::Lagged(missed)) => {
+                    debug!("Status API: event subscriber missed {} events", missed);
+                    json!({ "type": "lagged", "missed": missed }).to_string()
+                }
+                Err(RecvError::Closed) => break,
+            },
+        };
+        if socket.send(Message::Text(message)).await.is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::status::events::{Event, EventBus, HASHRATE_WINDOW_SECS};
+
+    #[test]
+    fn reports_the_runtime_state() {
+        let bus = EventBus::new(16);
+        bus.publish(Event::Started {
+            version: "0.1.0".into(),
+            network: "testnet4".into(),
+        });
+        bus.publish(Event::MinerConnected {
+            listener: Listener::Translator,
+            connection_id: 1,
+        });
+        bus.publish(Event::ShareAccepted {
+            channel_id: 1,
+            account: "alice".into(),
+            weight: 600,
+        });
+        bus.publish(Event::BlockFound {
+            coinbase_txid: "ab".repeat(32),
+            reward: 312_500_000,
+        });
+        let mut snapshot = bus.snapshot();
+        let started_at = snapshot.started_at.unwrap();
+        // pretend the process ran for the whole window
+        snapshot.started_at = Some(started_at - HASHRATE_WINDOW_SECS);
+
+        let report = StatusReport::new(snapshot, started_at + 1);
+        assert_eq!(report.uptime_secs, HASHRATE_WINDOW_SECS + 1);
+        assert_eq!(report.network.as_deref(), Some("testnet4"));
+        assert_eq!(report.miners[&Listener::Pool], 0);
+        assert_eq!(report.miners[&Listener::Translator], 1);
+        // 600 difficulty 1 shares over 600 seconds
+        assert_eq!(report.hashrate, 2f64.powi(32));
+        assert_eq!(report.recent_blocks[0].reward, 312_500_000);
+        // as `potato tui` reads it back
+        let json = serde_json::to_string(&report).unwrap();
+        assert_eq!(serde_json::from_str::<StatusReport>(&json).unwrap(), report);
+
+        let summary = report.summary(started_at + 61);
+        assert!(summary.contains("Node        disconnected"));
+        assert!(summary.contains("Miners      0 pool, 1 translator"));
+        assert!(summary.contains("Hashrate    4.29 GH/s"));
+        assert!(summary.contains("Shares      1 accepted, 0 rejected (0.0%)"));
+        assert!(summary.contains("Last block  1m 1s ago, 312500000 sat"));
+    }
+
+    #[test]
+    fn filters_the_streamed_events_by_type() {
+        let block = Event::BlockFound {
+            coinbase_txid: "ab".repeat(32),
+            reward: 312_500_000,
+        };
+        let share = Event::ShareAccepted {
+            channel_id: 1,
+            account: "alice".into(),
+            weight: 1,
+        };
+        let all = EventsQuery::default();
+        assert!(all.message(&share).is_some());
+        let blocks = EventsQuery {
+            types: Some("block_found, channel_opened".into()),
+        };
+        assert!(blocks.message(&share).is_none());
+        let message: serde_json::Value =
+            serde_json::from_str(&blocks.message(&block).unwrap()).unwrap();
+        assert_eq!(message["type"], "block_found");
+        assert_eq!(message["reward"], 312_500_000);
+    }
+}
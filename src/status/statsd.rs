@@ -0,0 +1,187 @@
+//! StatsD exporter, pushing the counters and gauges of `/metrics` (see `metrics`) over UDP every
+//! `interval_secs`, for setups without Prometheus. Metrics are named `<prefix>.<name>`, counters
+//! sent as their increase since the last push.
+//!
+//! Plain StatsD has no tags, so the labels of a metric are appended to its name, e.g.
+//! `potato.miners.pool`. With `dogstatsd` they are sent as DogStatsD tags instead, along with the
+//! configured `tags`.
+use super::{
+    events,
+    metrics::{self, Kind, Metric},
+};
+use serde::Deserialize;
+use std::{collections::HashMap, time::Duration};
+use tokio::net::UdpSocket;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+/// Largest datagram sent, fitting the MTU of most networks.
+const MAX_DATAGRAM: usize = 1432;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct StatsdConfig {
+    /// Address of the StatsD server, e.g. `127.0.0.1:8125`.
+    pub address: String,
+    #[serde(default = "StatsdConfig::default_prefix")]
+    pub prefix: String,
+    #[serde(default = "StatsdConfig::default_interval_secs")]
+    pub interval_secs: u64,
+    /// Sends labels and `tags` as DogStatsD tags.
+    #[serde(default)]
+    pub dogstatsd: bool,
+    /// Tags of every metric, e.g. `["env:prod"]`. DogStatsD only.
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+impl StatsdConfig {
+    fn default_prefix() -> String {
+        "potato".to_string()
+    }
+
+    fn default_interval_secs() -> u64 {
+        10
+    }
+}
+
+/// Lines of the metrics of one push, and the counters they were taken at for the next.
+#[derive(Debug, Default)]
+struct Encoder {
+    /// Last value sent of every counter, by its line name.
+    counters: HashMap<String, f64>,
+}
+
+impl Encoder {
+    fn encode(&mut self, config: &StatsdConfig, metrics: &[Metric]) -> Vec<String> {
+        let mut lines = vec![];
+        for metric in metrics {
+            for (labels, value) in &metric.samples {
+                let mut name = format!("{}.{}", config.prefix, metric.name);
+                let mut tags = config.tags.clone();
+                for (label, label_value) in labels {
+                    if config.dogstatsd {
+                        tags.push(format!("{}:{}", label, label_value));
+                    } else {
+                        name = format!("{}.{}", name, sanitize(label_value));
+                    }
+                }
+                let (value, kind) = match metric.kind {
+                    Kind::Counter => {
+                        let last = self.counters.insert(name.clone(), *value).unwrap_or(0.0);
+                        (value - last, "c")
+                    }
+                    Kind::Gauge => (*value, "g"),
+                };
+                if metric.kind == Kind::Counter && value <= 0.0 {
+                    continue;
+                }
+                let mut line = format!("{}:{}|{}", name, value, kind);
+                if config.dogstatsd && !tags.is_empty() {
+                    line = format!("{}|#{}", line, tags.join(","));
+                }
+                lines.push(line);
+            }
+        }
+        lines
+    }
+}
+
+/// `value` usable in a metric name, the characters StatsD gives a meaning replaced.
+fn sanitize(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| match c {
+            ':' | '|' | '@' | '#' | ',' | '.' | ' ' | '\n' => '_',
+            c => c,
+        })
+        .collect()
+}
+
+/// `lines` joined into datagrams of at most `MAX_DATAGRAM` bytes.
+fn datagrams(lines: &[String]) -> Vec<String> {
+    let mut datagrams: Vec<String> = vec![];
+    for line in lines {
+        match datagrams.last_mut() {
+            Some(datagram) if datagram.len() + 1 + line.len() <= MAX_DATAGRAM => {
+                datagram.push('\n');
+                datagram.push_str(line);
+            }
+            _ => datagrams.push(line.clone()),
+        }
+    }
+    datagrams
+}
+
+/// Pushes the metrics to the server of `config` until `cancel_token` is cancelled.
+pub async fn run(config: StatsdConfig, cancel_token: CancellationToken) {
+    let socket = match UdpSocket::bind("0.0.0.0:0").await {
+        Ok(socket) => socket,
+        Err(e) => {
+            warn!("StatsD: failed to open a UDP socket: {}", e);
+            return;
+        }
+    };
+    if let Err(e) = socket.connect(&config.address).await {
+        warn!("StatsD: failed to resolve {}: {}", config.address, e);
+        return;
+    }
+    info!("Pushing StatsD metrics to {}", config.address);
+    let mut encoder = Encoder::default();
+    let mut pushes = tokio::time::interval(Duration::from_secs(config.interval_secs.max(1)));
+    loop {
+        tokio::select! {
+            _ = pushes.tick() => {}
+            _ = cancel_token.cancelled() => break,
+        }
+        let metrics = metrics::metrics(&events::snapshot(), events::now());
+        for datagram in datagrams(&encoder.encode(&config, &metrics)) {
+            // the server may well be down for a while, the next push tries again
+            if let Err(e) = socket.send(datagram.as_bytes()).await {
+                warn!("StatsD: failed to push to {}: {}", config.address, e);
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::status::events::{Event, EventBus, Listener};
+
+    #[test]
+    fn sends_counter_increases_and_gauges() {
+        let bus = EventBus::new(16);
+        bus.publish(Event::MinerConnected {
+            listener: Listener::Translator,
+            connection_id: 1,
+        });
+        bus.publish(Event::ShareAccepted {
+            channel_id: 1,
+            account: "alice".into(),
+            weight: 16,
+        });
+        let mut config = StatsdConfig {
+            address: "127.0.0.1:8125".into(),
+            prefix: "pool".into(),
+            interval_secs: 10,
+            dogstatsd: false,
+            tags: vec!["env:test".into()],
+        };
+        let mut encoder = Encoder::default();
+        let snapshot = bus.snapshot();
+        let lines = encoder.encode(&config, &metrics::metrics(&snapshot, 0));
+        assert!(lines.contains(&"pool.shares_accepted:1|c".to_string()));
+        assert!(lines.contains(&"pool.miners.translator:1|g".to_string()));
+        // nothing new to count
+        let lines = encoder.encode(&config, &metrics::metrics(&snapshot, 0));
+        assert!(!lines
+            .iter()
+            .any(|line| line.starts_with("pool.shares_accepted")));
+
+        config.dogstatsd = true;
+        let lines = Encoder::default().encode(&config, &metrics::metrics(&snapshot, 0));
+        assert!(lines.contains(&"pool.miners:1|g|#env:test,listener:translator".to_string()));
+        assert_eq!(datagrams(&lines).len(), 1);
+    }
+}
@@ -0,0 +1,250 @@
+//! Chain tip lag of the node the pool mines on. Every `interval_secs` the tip of the node, asked
+//! through `bitcoin_rpc`, is compared with those of the references: other nodes over RPC, or
+//! block explorers with an Esplora API such as mempool.space. A node `max_lag_blocks` behind the
+//! highest of them publishes `Event::NodeBehind`, alerted (see `alerts`), as every share mined on
+//! its stale tip is wasted, and `Event::NodeCaughtUp` once it is back.
+//!
+//! References that can't be reached are left out of the check, the node is only compared with
+//! those answering. Unlike the node, they are asked once and not retried.
+use super::events::{self, Event};
+use crate::{
+    pool_mint::maturity::{self, BitcoinRpcConfig},
+    retry::{Retry, RetryPolicy},
+};
+use bitcoincore_rpc::RpcApi;
+use serde::Deserialize;
+use std::{sync::Arc, time::Duration};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, info_span, warn};
+
+/// Seconds a reference has to answer.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TipLagConfig {
+    #[serde(default = "TipLagConfig::default_interval_secs")]
+    pub interval_secs: u64,
+    /// Blocks behind the highest reference the node is alerted at.
+    #[serde(default = "TipLagConfig::default_max_lag_blocks")]
+    pub max_lag_blocks: u64,
+    pub references: Vec<TipReference>,
+}
+
+impl TipLagConfig {
+    fn default_interval_secs() -> u64 {
+        300
+    }
+
+    fn default_max_lag_blocks() -> u64 {
+        2
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum TipReference {
+    /// Another Bitcoin Core node.
+    BitcoinRpc(BitcoinRpcConfig),
+    /// A block explorer with an Esplora API, e.g. `https://mempool.space/api`.
+    Esplora { url: String },
+}
+
+impl TipReference {
+    fn name(&self) -> &str {
+        match self {
+            TipReference::BitcoinRpc(config) => &config.url,
+            TipReference::Esplora { url } => url,
+        }
+    }
+
+    async fn tip(&self, http: &reqwest::Client) -> Result<Tip, String> {
+        match self {
+            TipReference::BitcoinRpc(config) => {
+                rpc_tip(config, &Retry::new("tip_reference", &RetryPolicy::once())).await
+            }
+            TipReference::Esplora { url } => {
+                let blocks: Vec<EsploraBlock> = http
+                    .get(format!("{}/blocks", url.trim_end_matches('/')))
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status())
+                    .map_err(|e| e.to_string())?
+                    .json()
+                    .await
+                    .map_err(|e| e.to_string())?;
+                // newest first
+                let tip = blocks.first().ok_or("no blocks")?;
+                Ok(Tip {
+                    height: tip.height,
+                    time: tip.timestamp,
+                })
+            }
+        }
+    }
+}
+
+/// The best block of a chain, its height and header time in unix seconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tip {
+    pub height: u64,
+    pub time: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct EsploraBlock {
+    height: u64,
+    timestamp: u64,
+}
+
+/// The reference `local` is at least `max_lag_blocks` behind, the highest of `references`.
+pub fn lag(
+    local: Tip,
+    references: &[(String, Tip)],
+    max_lag_blocks: u64,
+) -> Option<&(String, Tip)> {
+    references
+        .iter()
+        .max_by_key(|(_, tip)| tip.height)
+        .filter(|(_, tip)| tip.height.saturating_sub(local.height) >= max_lag_blocks.max(1))
+}
+
+async fn rpc_tip(config: &BitcoinRpcConfig, retry: &Retry) -> Result<Tip, String> {
+    let client = Arc::new(maturity::client(config).map_err(|e| e.to_string())?);
+    let _span = info_span!("bitcoind_rpc", call = "tip");
+    maturity::call(&client, retry, |client| {
+        let hash = client.get_best_block_hash().map_err(maturity::rpc)?;
+        let header = client.get_block_header_info(&hash).map_err(maturity::rpc)?;
+        Ok(Tip {
+            height: header.height as u64,
+            time: header.time as u64,
+        })
+    })
+    .await
+    .map_err(|e| e.to_string())
+}
+
+/// Compares the tip of the node at `local` with the references of `config` until
+/// `cancel_token` is cancelled.
+pub async fn run(config: TipLagConfig, local: BitcoinRpcConfig, cancel_token: CancellationToken) {
+    let http = match reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build() {
+        Ok(http) => http,
+        Err(e) => {
+            warn!("Chain tip lag: {}", e);
+            return;
+        }
+    };
+    info!(
+        "Comparing the chain tip with {} references",
+        config.references.len()
+    );
+    let mut ticker = tokio::time::interval(Duration::from_secs(config.interval_secs.max(10)));
+    let retry = local.retry();
+    let mut behind = false;
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = cancel_token.cancelled() => break,
+        }
+        let local_tip = match rpc_tip(&local, &retry).await {
+            Ok(tip) => tip,
+            Err(e) => {
+                warn!("Chain tip lag: the tip of the node is unknown: {}", e);
+                continue;
+            }
+        };
+        let mut references = vec![];
+        for reference in &config.references {
+            match reference.tip(&http).await {
+                Ok(tip) => references.push((reference.name().to_string(), tip)),
+                Err(e) => warn!("Chain tip lag: {} unreachable: {}", reference.name(), e),
+            }
+        }
+        match lag(local_tip, &references, config.max_lag_blocks) {
+            Some((reference, tip)) if !behind => {
+                behind = true;
+                warn!(
+                    "Node at height {} behind {} at {}",
+                    local_tip.height, reference, tip.height
+                );
+                events::publish(Event::NodeBehind {
+                    height: local_tip.height,
+                    reference: reference.clone(),
+                    reference_height: tip.height,
+                    stale_secs: tip.time.saturating_sub(local_tip.time),
+                });
+            }
+            None if behind && !references.is_empty() => {
+                behind = false;
+                info!("Node caught up at height {}", local_tip.height);
+                events::publish(Event::NodeCaughtUp {
+                    height: local_tip.height,
+                });
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn tells_how_far_behind_the_references_the_node_is() {
+        let blocks: Vec<EsploraBlock> = serde_json::from_str(
+            r#"[{"id":"00","height":870001,"timestamp":1730000600,"tx_count":3000},
+                {"id":"01","height":870000,"timestamp":1730000000,"tx_count":2000}]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            (blocks[0].height, blocks[0].timestamp),
+            (870_001, 1_730_000_600)
+        );
+
+        let local = Tip {
+            height: 870_000,
+            time: 1_730_000_000,
+        };
+        let mut references = vec![(
+            "https://mempool.space/api".to_string(),
+            Tip {
+                height: 870_001,
+                time: 1_730_000_600,
+            },
+        )];
+        assert_eq!(lag(local, &references, 2), None);
+        references.push((
+            "http://127.0.0.1:18443".to_string(),
+            Tip {
+                height: 870_002,
+                time: 1_730_001_200,
+            },
+        ));
+        let (reference, tip) = lag(local, &references, 2).unwrap();
+        assert_eq!(reference, "http://127.0.0.1:18443");
+        assert_eq!(tip.height, 870_002);
+        // ahead of the references
+        assert_eq!(
+            lag(
+                Tip {
+                    height: 870_003,
+                    ..local
+                },
+                &references,
+                2
+            ),
+            None
+        );
+
+        let config: TipLagConfig = serde_json::from_str(
+            r#"{"references": [
+                {"kind": "esplora", "url": "https://mempool.space/api"},
+                {"kind": "bitcoin_rpc", "url": "http://127.0.0.1:18443", "user": "u",
+                 "password": "p"}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(config.max_lag_blocks, 2);
+        assert_eq!(config.references[1].name(), "http://127.0.0.1:18443");
+    }
+}
@@ -1,6 +1,4 @@
-use roles_logic_sv2::parsers::Mining;
-
-use super::error::PoolError;
+use super::error::{PoolError, Sv2MiningError};
 
 /// Each sending side of the status channel
 /// should be wrapped with this enum to allow
@@ -66,7 +64,7 @@ async fn send_status(
 ) -> error_handling::ErrorBranch {
     match sender {
         Sender::Downstream(tx) => match e {
-            PoolError::Sv2ProtocolError((id, Mining::OpenMiningChannelError(_))) => {
+            PoolError::Sv2ProtocolError((id, Sv2MiningError::OpenMiningChannel { .. })) => {
                 tx.send(Status {
                     state: State::DownstreamInstanceDropped(id),
                 })
@@ -146,5 +144,9 @@ pub async fn handle_error(sender: &Sender, e: PoolError) -> error_handling::Erro
         PoolError::Sv2ProtocolError(_) => {
             send_status(sender, e, error_handling::ErrorBranch::Break).await
         }
+        PoolError::Bind(_) => send_status(sender, e, error_handling::ErrorBranch::Break).await,
+        PoolError::Unreachable(_) => {
+            send_status(sender, e, error_handling::ErrorBranch::Break).await
+        }
     }
 }
@@ -0,0 +1,306 @@
+//! Supervisor of the pool and the translator. A subsystem that fails or stops on its own is
+//! restarted after a backoff doubling from `INITIAL_BACKOFF` up to `MAX_BACKOFF`. Past
+//! `--max-restarts` failures in a row the supervisor gives up and cancels the whole process. A
+//! run lasting `STABLE_AFTER` resets the count, so rare failures are always restarted. A fatal
+//! failure, e.g. a bad configuration or a poisoned lock, is not restarted at all: it cancels the
+//! whole process right away, the other subsystem shutting down with it. The pool
+//! is also restarted on demand once its configuration is reloaded, see `supervise_restartable`.
+//!
+//! Every run gets a tokio runtime of its own, shut down once the run returns. No task of a
+//! failed run outlives it, so the next run can bind the same listeners and connect afresh.
+//! A run that panics fails like any other and is restarted, the crash hook having written its
+//! bundle (see `crash`). A panic in a task of the run, e.g. that of a connection, ends that task
+//! only.
+use crate::status::events::{self, Event};
+use std::{
+    any::Any,
+    future::Future,
+    panic::{self, AssertUnwindSafe},
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+use tokio::{
+    runtime,
+    sync::{oneshot, Notify},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+/// Wait before the first restart of a subsystem.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// How long a run has to last for its failure not to count towards giving up.
+const STABLE_AFTER: Duration = Duration::from_secs(300);
+/// How long the tasks of a run get to finish once it returns.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Why a run of a subsystem failed: the stable code of its error (see `crate::error`) and the
+/// error itself. A fatal failure, one restarts can't get past, shuts the whole process down.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Failure {
+    pub code: &'static str,
+    pub reason: String,
+    pub fatal: bool,
+}
+
+impl Failure {
+    pub fn new(code: &'static str, reason: impl ToString) -> Self {
+        Self {
+            code,
+            reason: reason.to_string(),
+            fatal: false,
+        }
+    }
+
+    pub fn fatal(code: &'static str, reason: impl ToString) -> Self {
+        Self {
+            fatal: true,
+            ..Self::new(code, reason)
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Supervisor {
+    max_restarts: u32,
+    initial_backoff: Duration,
+    cancel_token: CancellationToken,
+}
+
+impl Supervisor {
+    pub fn new(max_restarts: u32, cancel_token: CancellationToken) -> Self {
+        Self {
+            max_restarts,
+            initial_backoff: INITIAL_BACKOFF,
+            cancel_token,
+        }
+    }
+
+    /// Wait before restarting a subsystem after `failures` failures in a row.
+    fn backoff(&self, failures: u32) -> Duration {
+        let doublings = failures.saturating_sub(1).min(16);
+        self.initial_backoff
+            .saturating_mul(1 << doublings)
+            .min(MAX_BACKOFF)
+    }
+
+    /// Runs `subsystem` with `run` until the process is cancelled, restarting it whenever it
+    /// returns before. `run` is handed a token cancelled with the process, and returns why it
+    /// failed. Returns the failure `subsystem` was given up on, if it was.
+    pub async fn supervise<F, Fut>(self, subsystem: &'static str, run: F) -> Result<(), Failure>
+    where
+        F: Fn(CancellationToken) -> Fut,
+        Fut: Future<Output = Result<(), Failure>> + Send + 'static,
+    {
+        self.supervise_restartable(subsystem, Arc::new(Notify::new()), run)
+            .await
+    }
+
+    /// `supervise`, also restarting `subsystem` right away whenever `restart` is notified, e.g.
+    /// to apply a configuration reloaded. Such a restart is not a failure.
+    pub async fn supervise_restartable<F, Fut>(
+        self,
+        subsystem: &'static str,
+        restart: Arc<Notify>,
+        run: F,
+    ) -> Result<(), Failure>
+    where
+        F: Fn(CancellationToken) -> Fut,
+        Fut: Future<Output = Result<(), Failure>> + Send + 'static,
+    {
+        let mut failures = 0;
+        loop {
+            let started = Instant::now();
+            let run_token = self.cancel_token.child_token();
+            let running = run_isolated(subsystem, run(run_token.clone()));
+            tokio::pin!(running);
+            let result = tokio::select! {
+                result = &mut running => result,
+                _ = restart.notified() => {
+                    info!("Restarting {}", subsystem);
+                    run_token.cancel();
+                    if let Err(e) = running.await {
+                        warn!("{} stopped for its restart with: {}", subsystem, e.reason);
+                    }
+                    continue;
+                }
+            };
+            if self.cancel_token.is_cancelled() {
+                info!("{} stopped", subsystem);
+                return Ok(());
+            }
+            let failure = result
+                .err()
+                .unwrap_or_else(|| Failure::new("stopped", "stopped"));
+            let Failure {
+                code,
+                ref reason,
+                fatal,
+            } = failure;
+            if started.elapsed() >= STABLE_AFTER {
+                failures = 0;
+            }
+            failures += 1;
+            let restarting = !fatal && failures <= self.max_restarts;
+            events::publish(Event::SubsystemFailed {
+                subsystem: subsystem.to_string(),
+                code: code.to_string(),
+                reason: reason.clone(),
+                restarting,
+            });
+            if fatal {
+                error!(
+                    code,
+                    "{} failed for good, shutting down: {}", subsystem, reason
+                );
+                self.cancel_token.cancel();
+                return Err(failure);
+            }
+            if !restarting {
+                error!(
+                    code,
+                    "{} failed {} times in a row, shutting down: {}", subsystem, failures, reason
+                );
+                self.cancel_token.cancel();
+                return Err(failure);
+            }
+            let backoff = self.backoff(failures);
+            warn!(
+                "{} failed, restarting in {:?} ({}/{}): {}",
+                subsystem, backoff, failures, self.max_restarts, reason
+            );
+            tokio::select! {
+                _ = tokio::time::sleep(backoff) => {}
+                _ = self.cancel_token.cancelled() => return Ok(()),
+            }
+        }
+    }
+}
+
+/// Runs `run` to completion on a runtime of its own, shut down once it returns.
+async fn run_isolated<Fut>(subsystem: &'static str, run: Fut) -> Result<(), Failure>
+where
+    Fut: Future<Output = Result<(), Failure>> + Send + 'static,
+{
+    let (result_tx, result_rx) = oneshot::channel();
+    thread::Builder::new()
+        .name(format!("{}-supervised", subsystem))
+        .spawn(move || {
+            let runtime = runtime::Builder::new_multi_thread()
+                .enable_all()
+                .thread_name(format!("potato-{}", subsystem))
+                .build();
+            let result = match runtime {
+                Ok(runtime) => {
+                    let result = panic::catch_unwind(AssertUnwindSafe(|| runtime.block_on(run)))
+                        .unwrap_or_else(|payload| {
+                            Err(Failure::new("panic", panic_message(payload)))
+                        });
+                    runtime.shutdown_timeout(SHUTDOWN_TIMEOUT);
+                    result
+                }
+                Err(e) => Err(Failure::new(
+                    "io",
+                    format!("failed to start its runtime: {}", e),
+                )),
+            };
+            let _ = result_tx.send(result);
+        })
+        .map_err(|e| Failure::new("io", format!("failed to start its thread: {}", e)))?;
+    result_rx
+        .await
+        .unwrap_or_else(|_| Err(Failure::new("runtime_stopped", "its runtime stopped")))
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => payload
+            .downcast_ref::<&str>()
+            .map_or_else(|| "panicked".to_string(), |message| message.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[tokio::test]
+    async fn restarts_with_backoff_then_gives_up() {
+        let cancel_token = CancellationToken::new();
+        let supervisor = Supervisor {
+            max_restarts: 2,
+            initial_backoff: Duration::from_millis(1),
+            cancel_token: cancel_token.clone(),
+        };
+        assert_eq!(supervisor.backoff(1), Duration::from_millis(1));
+        assert_eq!(supervisor.backoff(3), Duration::from_millis(4));
+        assert_eq!(supervisor.backoff(u32::MAX), MAX_BACKOFF);
+
+        let runs = Arc::new(AtomicU32::new(0));
+        let runs_ = runs.clone();
+        let failure = supervisor
+            .clone()
+            .supervise("test", move |_| {
+                let runs = runs_.clone();
+                async move {
+                    // the run has a runtime of its own
+                    tokio::spawn(async {}).await.unwrap();
+                    runs.fetch_add(1, Ordering::SeqCst);
+                    Err(Failure::new("io", "connection refused"))
+                }
+            })
+            .await;
+        // the first run and two restarts
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+        assert!(cancel_token.is_cancelled());
+        // given up on the last failure
+        assert_eq!(failure, Err(Failure::new("io", "connection refused")));
+
+        // a fatal failure cancels the process right away
+        let cancel_token = CancellationToken::new();
+        let supervisor = Supervisor {
+            cancel_token: cancel_token.clone(),
+            ..supervisor
+        };
+        let runs = Arc::new(AtomicU32::new(0));
+        let runs_ = runs.clone();
+        let failure = supervisor
+            .clone()
+            .supervise("test", move |_| {
+                let runs = runs_.clone();
+                async move {
+                    runs.fetch_add(1, Ordering::SeqCst);
+                    Err(Failure::fatal("bad_config", "no listen_address"))
+                }
+            })
+            .await;
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert!(cancel_token.is_cancelled());
+        assert_eq!(failure.unwrap_err().code, "bad_config");
+
+        // a run that panics is a failure restarted, one of its tasks panicking is not
+        let cancel_token = CancellationToken::new();
+        let supervisor = Supervisor {
+            max_restarts: 1,
+            cancel_token: cancel_token.clone(),
+            ..supervisor
+        };
+        let runs = Arc::new(AtomicU32::new(0));
+        let runs_ = runs.clone();
+        let failure = supervisor
+            .supervise("test", move |_| {
+                let runs = runs_.clone();
+                async move {
+                    assert!(tokio::spawn(async { panic!("a miner") }).await.is_err());
+                    runs.fetch_add(1, Ordering::SeqCst);
+                    panic!("the run")
+                }
+            })
+            .await;
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+        assert_eq!(failure, Err(Failure::new("panic", "the run")));
+    }
+}
@@ -0,0 +1,236 @@
+//! Test support, for the integration tests of `tests/` and those of binaries embedding the
+//! library. [`Harness::start`] runs the whole pipeline in process: a regtest bitcoind serving
+//! templates over SV2 (see `crate::bitcoin_node` for the build it takes), the pool and the
+//! translator, on free ports and with their files in a directory of their own. [`Sv1Miner`]s then
+//! mine on the translator, and what comes of their shares is told by the event bus, see
+//! [`wait_for`].
+//!
+//! The miners are pinned to `MINER_DIFFICULTY`, a few thousand hashes a share, which every share
+//! meeting also meets the target of regtest: each of them accepted by the pool is a block too.
+use crate::{
+    bitcoin_node::BitcoinNode,
+    configuration::{
+        create_default_pool_config, create_default_proxy_config, ReloadablePoolConfig,
+    },
+    dirs::Dirs,
+    pool_mint::{mining_pool::PoolConfiguration, PoolSv2},
+    proxy_wallet::{
+        proxy_config::{PinnedDifficulty, ProxyConfig},
+        TranslatorSv2,
+    },
+    retry::{Retry, RetryPolicy},
+    shutdown, startup,
+    status::events::Event,
+    supervisor::{Failure, Supervisor},
+};
+use anyhow::Result;
+use bitcoincore_rpc::{
+    bitcoin::{Address, Network, PublicKey},
+    RpcApi,
+};
+use std::{
+    env, fs, io,
+    net::{Ipv4Addr, SocketAddr, TcpListener},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+use tokio::{
+    sync::broadcast::{self, error::RecvError},
+    task::JoinHandle,
+};
+use tokio_util::sync::CancellationToken;
+
+pub mod sv1_miner;
+pub use sv1_miner::Sv1Miner;
+
+/// Difficulty the simulated miners are pinned to.
+pub const MINER_DIFFICULTY: f64 = 0.000_001;
+/// Workers pinned to `MINER_DIFFICULTY`, e.g. `sim.1`.
+pub const MINER_WORKERS: &str = "sim*";
+/// How long the pool and the translator have to stop.
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+/// Restarts of the pool or the translator before the harness gives up on them.
+const MAX_RESTARTS: u32 = 3;
+/// Key the blocks mined by the node itself pay, the one of the default coinbase output.
+const COINBASE_PUBKEY: &str = "032a384861cb109a7b69b550601e4935ee30903be6b281f058a3c65c657938f8f8";
+
+/// A port free on the loopback interface, as far as the OS tells.
+fn free_port() -> io::Result<u16> {
+    Ok(TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?
+        .local_addr()?
+        .port())
+}
+
+fn local(port: u16) -> String {
+    format!("127.0.0.1:{}", port)
+}
+
+fn file(dir: &Path, name: &str) -> String {
+    dir.join(name).to_string_lossy().into_owned()
+}
+
+/// The node, the pool and the translator of a test, see the module.
+pub struct Harness {
+    pub node: BitcoinNode,
+    /// Where the node, the mint and the translator keep their files, removed by `stop`.
+    pub dir: PathBuf,
+    pub pool_config: PoolConfiguration,
+    pub proxy_config: ProxyConfig,
+    cancel_token: CancellationToken,
+    running: JoinHandle<()>,
+}
+
+impl Harness {
+    /// Starts the node, then the pool and the translator mining on it.
+    pub async fn start() -> Result<Self> {
+        let dir = env::temp_dir().join(format!(
+            "potato-harness-{}-{}",
+            std::process::id(),
+            rand::random::<u32>()
+        ));
+        let sv2_port = free_port()?;
+        // P2P and ZMQ on the ports after the RPC one
+        let node = BitcoinNode::regtest_with_template_provider(
+            dir.join("bitcoin"),
+            free_port()?,
+            sv2_port,
+        )
+        .await?;
+        node.wait_for_ready(false).await?;
+        // templates are only served past the initial block download
+        generate(&node, 1)?;
+
+        let pool_port = free_port()?;
+        let mut pool_config = create_default_pool_config();
+        pool_config.listen_address = local(pool_port);
+        pool_config.tp_address = local(sv2_port);
+        // the template provider of the node makes up its key
+        pool_config.tp_authority_public_key = None;
+        pool_config.control_address = local(free_port()?);
+        let mint = &mut pool_config.mint;
+        mint.api_address = local(free_port()?);
+        // absolute, not to pick files of the working directory up
+        for path in [
+            &mut mint.master_secret_path,
+            &mut mint.db_path,
+            &mut mint.keysets_path,
+            &mut mint.journal_path,
+        ] {
+            *path = file(&dir, path);
+        }
+
+        let mut proxy_config = create_default_proxy_config(&pool_config);
+        proxy_config.upstream_port = pool_port;
+        proxy_config.downstream_address = "127.0.0.1".to_string();
+        proxy_config.downstream_port = free_port()?;
+        proxy_config.payout_tokens_path = file(&dir, "payout_tokens.txt");
+        proxy_config.downstream_difficulty_config.pinned_workers =
+            vec![
+                PinnedDifficulty::new(MINER_WORKERS.to_string(), MINER_DIFFICULTY)
+                    .map_err(anyhow::Error::msg)?,
+            ];
+
+        let cancel_token = CancellationToken::new();
+        let supervisor = Supervisor::new(MAX_RESTARTS, cancel_token.clone());
+        let reload = ReloadablePoolConfig::new(
+            file(&dir, "pool-mint-config.toml"),
+            Dirs::new(Some(dir.clone()), Some(dir.clone())),
+            pool_config.clone(),
+        );
+        let pool = supervisor.clone().supervise("pool", move |cancel_token| {
+            let pool = reload
+                .current()
+                .map(|config| PoolSv2::new(config, reload.clone(), cancel_token))
+                .map_err(|e| Failure::fatal("poison_lock", e));
+            async move { pool?.start().await.map_err(Failure::from) }
+        });
+        let proxy = supervisor.supervise("translator", {
+            let proxy_config = proxy_config.clone();
+            move |cancel_token| {
+                let proxy = TranslatorSv2::new(proxy_config.clone(), cancel_token.clone());
+                async move {
+                    if !startup::wait_for_pool(&cancel_token).await {
+                        return Ok(());
+                    }
+                    proxy.start().await.map_err(Failure::from)
+                }
+            }
+        });
+        let running = tokio::spawn(async move {
+            // failures were logged and published as they happened
+            let _ = tokio::join!(pool, proxy);
+        });
+        Ok(Self {
+            node,
+            dir,
+            pool_config,
+            proxy_config,
+            cancel_token,
+            running,
+        })
+    }
+
+    /// A simulated miner of `worker` connected to the translator, once it listens.
+    pub async fn miner(&self, worker: &str) -> Result<Sv1Miner> {
+        let address = SocketAddr::from((Ipv4Addr::LOCALHOST, self.proxy_config.downstream_port));
+        // the translator listens once connected to the pool, itself connected to the node
+        let retry = Retry::new(
+            "translator",
+            &RetryPolicy {
+                max_attempts: 30,
+                initial_backoff_ms: 200,
+                max_backoff_ms: 2000,
+                breaker_threshold: 0,
+                ..RetryPolicy::default()
+            },
+        );
+        retry
+            .run(|| Sv1Miner::connect(address, worker))
+            .await
+            .map_err(|e| anyhow::anyhow!("Simulated miner {}: {}", worker, e))
+    }
+
+    /// Mines `blocks` blocks with the node itself.
+    pub fn generate(&self, blocks: u64) -> Result<()> {
+        generate(&self.node, blocks)
+    }
+
+    pub fn block_count(&self) -> Result<u64> {
+        Ok(self.node.client().get_block_count()?)
+    }
+
+    /// Stops the translator, the pool and the node, then removes their files.
+    pub async fn stop(self) -> Result<()> {
+        self.cancel_token.cancel();
+        shutdown::with_deadline(self.running, &self.cancel_token, STOP_TIMEOUT).await;
+        self.node.stop().await?;
+        fs::remove_dir_all(&self.dir)?;
+        Ok(())
+    }
+}
+
+fn generate(node: &BitcoinNode, blocks: u64) -> Result<()> {
+    let key = PublicKey::from_str(COINBASE_PUBKEY)?;
+    let address = Address::p2wpkh(&key, Network::Regtest)?;
+    node.client().generate_to_address(blocks, &address)?;
+    Ok(())
+}
+
+/// The first event of `events` `matches`, unless none comes within `timeout`.
+pub async fn wait_for(
+    events: &mut broadcast::Receiver<Event>,
+    timeout: Duration,
+    mut matches: impl FnMut(&Event) -> bool,
+) -> Option<Event> {
+    let first = async {
+        loop {
+            match events.recv().await {
+                Ok(event) if matches(&event) => return Some(event),
+                Ok(_) | Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    };
+    tokio::time::timeout(timeout, first).await.ok().flatten()
+}
@@ -0,0 +1,367 @@
+//! A simulated SV1 miner: subscribes and authorizes on the translator as a rig would, then hashes
+//! the jobs it is notified on the CPU for real, submitting the shares meeting the difficulty it is
+//! set. At a few hundred thousand hashes a second it only makes it to the difficulties of tests,
+//! such as `super::MINER_DIFFICULTY`, and to the blocks of regtest.
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::{collections::HashSet, io, net::SocketAddr, time::Duration};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+};
+
+/// Nonces tried between two looks for new jobs.
+const NONCES_PER_ROUND: u32 = 10_000;
+
+fn invalid(e: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+fn sha256d(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(Sha256::digest(data)).into()
+}
+
+/// A `mining.notify` job.
+#[derive(Debug, Clone)]
+struct Job {
+    id: String,
+    /// In the byte order of the header.
+    prev_hash: [u8; 32],
+    coinbase1: Vec<u8>,
+    coinbase2: Vec<u8>,
+    merkle_branch: Vec<Vec<u8>>,
+    version: u32,
+    bits: u32,
+    time: u32,
+}
+
+impl Job {
+    fn from_params(params: &Value) -> io::Result<Self> {
+        let string = |i: usize| {
+            params[i]
+                .as_str()
+                .ok_or_else(|| invalid("bad mining.notify"))
+        };
+        let bytes = |i: usize| string(i).and_then(|s| hex::decode(s).map_err(invalid));
+        let number = |i: usize| string(i).and_then(|s| u32::from_str_radix(s, 16).map_err(invalid));
+        // sent as 8 words of 4 bytes, each in reverse
+        let mut prev_hash: [u8; 32] = bytes(1)?
+            .try_into()
+            .map_err(|_| invalid("bad previous block hash"))?;
+        prev_hash.chunks_mut(4).for_each(|word| word.reverse());
+        let merkle_branch = params[4]
+            .as_array()
+            .ok_or_else(|| invalid("bad merkle branch"))?
+            .iter()
+            .map(|node| hex::decode(node.as_str().unwrap_or_default()).map_err(invalid))
+            .collect::<io::Result<_>>()?;
+        Ok(Self {
+            id: string(0)?.to_string(),
+            prev_hash,
+            coinbase1: bytes(2)?,
+            coinbase2: bytes(3)?,
+            merkle_branch,
+            version: number(5)?,
+            bits: number(6)?,
+            time: number(7)?,
+        })
+    }
+
+    /// The first 76 bytes of the header of the block of `extranonce`, all but the nonce.
+    fn header(&self, extranonce: &[u8]) -> Vec<u8> {
+        let coinbase = [&self.coinbase1[..], extranonce, &self.coinbase2[..]].concat();
+        let merkle_root = self
+            .merkle_branch
+            .iter()
+            .fold(sha256d(&coinbase), |root, node| {
+                sha256d(&[&root[..], node].concat())
+            });
+        let mut header = Vec::with_capacity(80);
+        header.extend_from_slice(&self.version.to_le_bytes());
+        header.extend_from_slice(&self.prev_hash);
+        header.extend_from_slice(&merkle_root);
+        header.extend_from_slice(&self.time.to_le_bytes());
+        header.extend_from_slice(&self.bits.to_le_bytes());
+        header
+    }
+}
+
+/// Whether `hash`, little endian, meets `difficulty` as the translator sets it: the target of
+/// difficulty 1 being `0x00000000ffff...ff`.
+fn meets(hash: &[u8; 32], difficulty: f64) -> bool {
+    let value = hash
+        .iter()
+        .rev()
+        .fold(0.0, |value, byte| value * 256.0 + *byte as f64);
+    value <= 2f64.powi(224) / difficulty
+}
+
+/// Whether `hash`, little endian, meets the target of the compact `bits` of a block header.
+fn meets_bits(hash: &[u8; 32], bits: u32) -> bool {
+    let exponent = (bits >> 24) as usize;
+    let mantissa = (bits & 0x007f_ffff).to_be_bytes();
+    // big endian, the 3 bytes of the mantissa followed by `exponent - 3` zeroes
+    let mut target = [0u8; 32];
+    for (i, byte) in mantissa[1..].iter().enumerate() {
+        if let Some(index) = (32 + i).checked_sub(exponent).filter(|index| *index < 32) {
+            target[index] = *byte;
+        }
+    }
+    let mut hash = *hash;
+    hash.reverse();
+    hash <= target
+}
+
+/// A simulated miner connected to the translator, see the module.
+#[derive(Debug)]
+pub struct Sv1Miner {
+    worker: String,
+    lines: Lines<BufReader<OwnedReadHalf>>,
+    writer: OwnedWriteHalf,
+    next_id: u64,
+    extranonce1: Vec<u8>,
+    extranonce2_size: usize,
+    difficulty: f64,
+    job: Option<Job>,
+    /// Ids of the shares submitted the translator hasn't answered yet.
+    submitted: HashSet<u64>,
+    /// Shares the translator accepted.
+    pub accepted: usize,
+    /// Shares the translator rejected.
+    pub rejected: usize,
+    /// Shares found meeting the target of their block too, accepted or not.
+    pub blocks: usize,
+}
+
+impl Sv1Miner {
+    /// Connects to the translator at `address`, then subscribes and authorizes as `worker`.
+    pub async fn connect(address: SocketAddr, worker: &str) -> io::Result<Self> {
+        let (reader, writer) = TcpStream::connect(address).await?.into_split();
+        let mut miner = Self {
+            worker: worker.to_string(),
+            lines: BufReader::new(reader).lines(),
+            writer,
+            next_id: 1,
+            extranonce1: vec![],
+            extranonce2_size: 0,
+            difficulty: 1.0,
+            job: None,
+            submitted: HashSet::new(),
+            accepted: 0,
+            rejected: 0,
+            blocks: 0,
+        };
+        let subscribed = miner
+            .call("mining.subscribe", json!(["potato-sim/0.1"]))
+            .await?;
+        // [subscriptions, extranonce1, extranonce2_size]
+        miner.extranonce1 =
+            hex::decode(subscribed[1].as_str().unwrap_or_default()).map_err(invalid)?;
+        miner.extranonce2_size = subscribed[2]
+            .as_u64()
+            .ok_or_else(|| invalid("no extranonce2 size"))?
+            as usize;
+        miner.call("mining.authorize", json!([worker, "x"])).await?;
+        Ok(miner)
+    }
+
+    /// Mines until `shares` shares are submitted and answered, returning how many the translator
+    /// accepted.
+    pub async fn mine(&mut self, shares: usize) -> io::Result<usize> {
+        let accepted = self.accepted;
+        let mut found = 0;
+        let mut job_id = String::new();
+        let mut extranonce2 = 0u64;
+        let mut nonce = 0u32;
+        let mut header = vec![];
+        while found < shares || !self.submitted.is_empty() {
+            // waiting for the first job, or the answers once done
+            let wait = self.job.is_none() || found == shares;
+            self.read(wait).await?;
+            let Some(job) = self.job.clone() else {
+                continue;
+            };
+            if found == shares {
+                continue;
+            }
+            if job.id != job_id || header.is_empty() {
+                job_id = job.id.clone();
+                extranonce2 += 1;
+                nonce = 0;
+                header = job.header(&self.extranonce(extranonce2));
+            }
+            let end = nonce.saturating_add(NONCES_PER_ROUND);
+            let share = (nonce..end).find_map(|nonce| {
+                let hash = sha256d(&[&header[..], &nonce.to_le_bytes()].concat());
+                meets(&hash, self.difficulty).then_some((nonce, hash))
+            });
+            nonce = end;
+            // hashing never waits, the other tasks of the runtime get to run between rounds
+            tokio::task::yield_now().await;
+            if let Some((share, hash)) = share {
+                if meets_bits(&hash, job.bits) {
+                    self.blocks += 1;
+                }
+                self.submit(&job, extranonce2, share).await?;
+                found += 1;
+                nonce = share.saturating_add(1);
+            }
+            if nonce == u32::MAX {
+                // out of nonces, on to the next extranonce
+                header.clear();
+            }
+        }
+        Ok(self.accepted - accepted)
+    }
+
+    /// `extranonce2` as the bytes of its size, little endian.
+    fn extranonce(&self, extranonce2: u64) -> Vec<u8> {
+        let mut extranonce = self.extranonce1.clone();
+        let mut extranonce2 = extranonce2.to_le_bytes().to_vec();
+        extranonce2.resize(self.extranonce2_size, 0);
+        extranonce.extend(extranonce2);
+        extranonce
+    }
+
+    async fn submit(&mut self, job: &Job, extranonce2: u64, nonce: u32) -> io::Result<()> {
+        let extranonce2 = &self.extranonce(extranonce2)[self.extranonce1.len()..];
+        let params = json!([
+            self.worker,
+            job.id,
+            hex::encode(extranonce2),
+            format!("{:08x}", job.time),
+            format!("{:08x}", nonce),
+        ]);
+        let id = self.send("mining.submit", params).await?;
+        self.submitted.insert(id);
+        Ok(())
+    }
+
+    async fn send(&mut self, method: &str, params: Value) -> io::Result<u64> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let mut line = json!({"id": id, "method": method, "params": params}).to_string();
+        line.push('\n');
+        self.writer.write_all(line.as_bytes()).await?;
+        Ok(id)
+    }
+
+    /// Sends the request `method`, returning the result it is answered with.
+    async fn call(&mut self, method: &str, params: Value) -> io::Result<Value> {
+        let id = self.send(method, params).await?;
+        loop {
+            let line = self.next_line().await?;
+            let message: Value = serde_json::from_str(&line).map_err(invalid)?;
+            if message["id"].as_u64() != Some(id) || message.get("method").is_some() {
+                self.handle(message);
+                continue;
+            }
+            if !message["error"].is_null() {
+                return Err(io::Error::other(format!(
+                    "{}: {}",
+                    method, message["error"]
+                )));
+            }
+            return Ok(message["result"].clone());
+        }
+    }
+
+    /// Handles the messages of the translator received, waiting for one if `wait`.
+    async fn read(&mut self, wait: bool) -> io::Result<()> {
+        let mut wait = wait;
+        loop {
+            let line = match wait {
+                true => self.next_line().await?,
+                // polled once, reading lines is cancel safe
+                false => match tokio::time::timeout(Duration::ZERO, self.next_line()).await {
+                    Ok(line) => line?,
+                    Err(_) => return Ok(()),
+                },
+            };
+            self.handle(serde_json::from_str(&line).map_err(invalid)?);
+            wait = false;
+        }
+    }
+
+    async fn next_line(&mut self) -> io::Result<String> {
+        self.lines
+            .next_line()
+            .await?
+            .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))
+    }
+
+    fn handle(&mut self, message: Value) {
+        match message["method"].as_str() {
+            Some("mining.set_difficulty") => {
+                if let Some(difficulty) = message["params"][0].as_f64().filter(|d| *d > 0.0) {
+                    self.difficulty = difficulty;
+                }
+            }
+            Some("mining.notify") => match Job::from_params(&message["params"]) {
+                Ok(job) => self.job = Some(job),
+                Err(e) => tracing::warn!("Simulated miner {}: {}", self.worker, e),
+            },
+            Some(_) => {}
+            None => {
+                let Some(id) = message["id"].as_u64() else {
+                    return;
+                };
+                if self.submitted.remove(&id) {
+                    match message["result"].as_bool() {
+                        Some(true) => self.accepted += 1,
+                        _ => self.rejected += 1,
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn builds_the_header_of_the_genesis_block_from_its_job() {
+        // the genesis block, its coinbase split around an empty extranonce
+        let coinbase = "01000000010000000000000000000000000000000000000000000000000000000000000000\
+            ffffffff4d04ffff001d0104455468652054696d65732030332f4a616e2f32303039204368616e63656c6c\
+            6f72206f6e206272696e6b206f66207365636f6e64206261696c6f757420666f722062616e6b73ffffffff\
+            0100f2052a01000000434104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61de\
+            b649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac00000000";
+        let params = json!([
+            "1",
+            "00".repeat(32),
+            &coinbase[..84],
+            &coinbase[84..],
+            [],
+            "00000001",
+            "1d00ffff",
+            "495fab29",
+            true
+        ]);
+        let job = Job::from_params(&params).unwrap();
+        let header = [job.header(&[]), 2_083_236_893u32.to_le_bytes().to_vec()].concat();
+        let mut hash = sha256d(&header);
+        hash.reverse();
+        assert_eq!(
+            hex::encode(hash),
+            "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f"
+        );
+        // difficulty 1 as far as the translator is concerned, not the difficulty 1 of bitcoin
+        hash.reverse();
+        assert!(meets(&hash, 1.0));
+        assert!(!meets(&[0xff; 32], 1.0));
+        // the difficulty 1 of bitcoin, and the highest target of regtest
+        assert!(meets_bits(&hash, 0x1d00ffff));
+        assert!(!meets_bits(&hash, 0x1b0404cb));
+        let mut target = [0; 32];
+        target[29..].copy_from_slice(&[0xff, 0xff, 0x7f]);
+        assert!(meets_bits(&target, 0x207fffff));
+        target[0] = 1;
+        assert!(!meets_bits(&target, 0x207fffff));
+    }
+}
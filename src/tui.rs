@@ -0,0 +1,316 @@
+//! `potato tui`, a live dashboard of a running pool in the terminal, for operators in SSH
+//! sessions. Polls the status API (`status_address`, see `status::server`) every second and
+//! renders the node, the miners and their hashrate, share outcomes, workers, tokens issued,
+//! blocks found and errors logged. `q`, Esc or Ctrl-C quits.
+use crate::status::server::{duration, hashrate, StatusReport};
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Style, Stylize},
+    text::Line,
+    widgets::{Block, Paragraph, Row, Table},
+    Frame,
+};
+use std::{
+    io,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Time between two polls of the status API.
+const REFRESH: Duration = Duration::from_secs(1);
+
+/// What the dashboard shows: the last report polled, and why the last poll failed if it did.
+#[derive(Debug, Default)]
+struct Dashboard {
+    url: String,
+    report: Option<StatusReport>,
+    error: Option<String>,
+}
+
+/// Renders the dashboard of the process serving its status API at `url` until quit.
+pub async fn run(url: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let client = reqwest::Client::builder().timeout(REFRESH).build()?;
+    let status_url = format!("{}/v1/status", url.trim_end_matches('/'));
+    let mut dashboard = Dashboard {
+        url: url.to_string(),
+        ..Default::default()
+    };
+    let mut terminal = ratatui::init();
+    let result: io::Result<()> = async {
+        loop {
+            match poll(&client, &status_url).await {
+                Ok(report) => {
+                    dashboard.report = Some(report);
+                    dashboard.error = None;
+                }
+                Err(e) => dashboard.error = Some(e.to_string()),
+            }
+            terminal.draw(|frame| draw(frame, &dashboard, unix_now()))?;
+            if tokio::task::block_in_place(quit_requested)? {
+                return Ok(());
+            }
+        }
+    }
+    .await;
+    ratatui::restore();
+    Ok(result?)
+}
+
+async fn poll(client: &reqwest::Client, url: &str) -> reqwest::Result<StatusReport> {
+    client
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await
+}
+
+/// Waits out `REFRESH`, whether a key quitting the dashboard was pressed meanwhile.
+fn quit_requested() -> io::Result<bool> {
+    let deadline = Instant::now() + REFRESH;
+    loop {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        if timeout.is_zero() || !event::poll(timeout)? {
+            return Ok(false);
+        }
+        if let Event::Key(key) = event::read()? {
+            let ctrl_c =
+                key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+            let quit = matches!(key.code, KeyCode::Char('q') | KeyCode::Esc) || ctrl_c;
+            if key.kind == KeyEventKind::Press && quit {
+                return Ok(true);
+            }
+        }
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn draw(frame: &mut Frame, dashboard: &Dashboard, now: u64) {
+    let [header, overview, workers, history] = Layout::vertical([
+        Constraint::Length(3),
+        Constraint::Length(6),
+        Constraint::Min(5),
+        Constraint::Length(8),
+    ])
+    .areas(frame.area());
+    draw_header(frame, header, dashboard);
+    let Some(report) = &dashboard.report else {
+        return;
+    };
+    let [node, shares, issued] = Layout::horizontal([
+        Constraint::Percentage(40),
+        Constraint::Percentage(40),
+        Constraint::Percentage(20),
+    ])
+    .areas(overview);
+    draw_node(frame, node, report, now);
+    draw_shares(frame, shares, report);
+    draw_issued(frame, issued, report);
+    draw_workers(frame, workers, report, now);
+    let [blocks, errors] =
+        Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)]).areas(history);
+    draw_blocks(frame, blocks, report, now);
+    draw_errors(frame, errors, report, now);
+}
+
+fn draw_header(frame: &mut Frame, area: Rect, dashboard: &Dashboard) {
+    let mut line = match &dashboard.report {
+        Some(report) => Line::from(format!(
+            "potato {} on {}, up {}",
+            report.version,
+            report.network.as_deref().unwrap_or("unknown network"),
+            duration(report.uptime_secs)
+        )),
+        None => Line::from("potato"),
+    };
+    if let Some(error) = &dashboard.error {
+        line.push_span(format!("  {} unreachable: {}", dashboard.url, error).red());
+    }
+    let block = Block::bordered().title(format!(" {} (q to quit) ", dashboard.url));
+    frame.render_widget(Paragraph::new(line).block(block), area);
+}
+
+fn draw_node(frame: &mut Frame, area: Rect, report: &StatusReport, now: u64) {
+    let connection = if report.node_ready {
+        "connected".green()
+    } else {
+        "disconnected".red()
+    };
+    let mut lines = vec![Line::from(vec!["Template provider ".into(), connection])];
+    match (&report.tip, report.tip_at) {
+        (Some(tip), Some(at)) => {
+            lines.push(Line::from(format!("Chain tip {}", tip)));
+            lines.push(Line::from(format!(
+                "announced {} ago",
+                duration(now.saturating_sub(at))
+            )));
+        }
+        _ => lines.push(Line::from("No chain tip announced yet")),
+    }
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title(" Node ")),
+        area,
+    );
+}
+
+fn draw_shares(frame: &mut Frame, area: Rect, report: &StatusReport) {
+    let miners: Vec<_> = report
+        .miners
+        .iter()
+        .map(|(listener, miners)| format!("{} {:?}", miners, listener).to_lowercase())
+        .collect();
+    let submitted = report.shares_accepted + report.shares_rejected;
+    let rejected = match submitted {
+        0 => 0.0,
+        submitted => report.shares_rejected as f64 * 100.0 / submitted as f64,
+    };
+    let lines = vec![
+        Line::from(format!("Miners {}", miners.join(", "))),
+        Line::from(format!("Hashrate {}", hashrate(report.hashrate))),
+        Line::from(format!("Accepted {}", report.shares_accepted)),
+        Line::from(format!(
+            "Rejected {} ({:.1}%)",
+            report.shares_rejected, rejected
+        )),
+    ];
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title(" Shares ")),
+        area,
+    );
+}
+
+fn draw_issued(frame: &mut Frame, area: Rect, report: &StatusReport) {
+    let lines: Vec<_> = report
+        .issued
+        .iter()
+        .map(|(unit, amount)| Line::from(format!("{} {}", amount, unit)))
+        .collect();
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title(" Issued ")),
+        area,
+    );
+}
+
+fn draw_workers(frame: &mut Frame, area: Rect, report: &StatusReport, now: u64) {
+    let rows = report.workers.iter().map(|(account, worker)| {
+        Row::new([
+            account.clone(),
+            worker.shares.to_string(),
+            worker.weight.to_string(),
+            format!("{} ago", duration(now.saturating_sub(worker.last_share_at))),
+        ])
+    });
+    let widths = [
+        Constraint::Fill(1),
+        Constraint::Length(10),
+        Constraint::Length(14),
+        Constraint::Length(12),
+    ];
+    let table = Table::new(rows, widths)
+        .header(
+            Row::new(["Account", "Shares", "Weight", "Last share"])
+                .style(Style::default().fg(Color::Yellow)),
+        )
+        .block(Block::bordered().title(" Workers "));
+    frame.render_widget(table, area);
+}
+
+fn draw_blocks(frame: &mut Frame, area: Rect, report: &StatusReport, now: u64) {
+    let lines: Vec<_> = report
+        .recent_blocks
+        .iter()
+        .rev()
+        .map(|block| {
+            Line::from(format!(
+                "{} ago, {} sat, coinbase {}",
+                duration(now.saturating_sub(block.at)),
+                block.reward,
+                block.coinbase_txid
+            ))
+        })
+        .collect();
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title(" Blocks found ")),
+        area,
+    );
+}
+
+fn draw_errors(frame: &mut Frame, area: Rect, report: &StatusReport, now: u64) {
+    let lines: Vec<_> = report
+        .recent_errors
+        .iter()
+        .rev()
+        .map(|error| {
+            Line::from(format!(
+                "{} ago, {}: {}",
+                duration(now.saturating_sub(error.at)),
+                error.target,
+                error.message
+            ))
+            .red()
+        })
+        .collect();
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title(" Errors ")),
+        area,
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::status::events::{Event, EventBus, Listener};
+    use ratatui::{backend::TestBackend, Terminal};
+
+    #[test]
+    fn renders_the_status_report() {
+        let bus = EventBus::new(16);
+        bus.publish(Event::Started {
+            version: "0.1.0".into(),
+            network: "testnet4".into(),
+        });
+        bus.publish(Event::MinerConnected {
+            listener: Listener::Pool,
+            connection_id: 1,
+        });
+        bus.publish(Event::ShareAccepted {
+            channel_id: 1,
+            account: "alice".into(),
+            weight: 300,
+        });
+        bus.publish(Event::ShareRejected {
+            channel_id: 1,
+            reason: "invalid-job-id".into(),
+        });
+        let snapshot = bus.snapshot();
+        let now = snapshot.started_at.unwrap() + 1;
+        let dashboard = Dashboard {
+            url: "http://127.0.0.1:34261".into(),
+            report: Some(StatusReport::new(snapshot, now)),
+            error: None,
+        };
+
+        let mut terminal = Terminal::new(TestBackend::new(100, 30)).unwrap();
+        terminal.draw(|frame| draw(frame, &dashboard, now)).unwrap();
+        let screen: String = terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|cell| cell.symbol())
+            .collect();
+        assert!(screen.contains("potato 0.1.0 on testnet4, up 1s"));
+        assert!(screen.contains("Miners 1 pool, 0 translator"));
+        assert!(screen.contains("Rejected 1 (50.0%)"));
+        assert!(screen.contains("alice"));
+        assert_eq!(hashrate(2f64.powi(32)), "4.29 GH/s");
+        assert_eq!(duration(7500), "2h 5m");
+    }
+}
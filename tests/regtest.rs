@@ -0,0 +1,58 @@
+//! The whole pipeline on regtest: shares of SV1 miners through the translator and the pool, up to
+//! the blocks they find. It takes a bitcoind with the SV2 template provider (see
+//! `potato::testing`), so it is ignored unless asked for:
+//! `BITCOIND=/path/to/bitcoind cargo test --test regtest -- --ignored`.
+use potato::{
+    status::events::{self, Event},
+    testing::{self, Harness},
+};
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(120);
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "needs a bitcoind with the SV2 template provider"]
+async fn shares_of_sv1_miners_reach_the_pool_and_mine_blocks() {
+    let harness = Harness::start().await.unwrap();
+    let mut events = events::subscribe();
+    let height = harness.block_count().unwrap();
+
+    let mut miners = vec![];
+    for worker in ["sim.1", "sim.2"] {
+        miners.push(harness.miner(worker).await.unwrap());
+    }
+    for miner in &mut miners {
+        let accepted = tokio::time::timeout(TIMEOUT, miner.mine(3))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(accepted > 0, "{} shares rejected", miner.rejected);
+    }
+
+    let share = testing::wait_for(
+        &mut events,
+        TIMEOUT,
+        |event| matches!(event, Event::ShareAccepted { weight, .. } if *weight > 0),
+    )
+    .await;
+    assert!(share.is_some(), "no share accepted by the pool");
+    let block = testing::wait_for(
+        &mut events,
+        TIMEOUT,
+        |event| matches!(event, Event::BlockFound { reward, .. } if *reward > 0),
+    )
+    .await;
+    assert!(block.is_some(), "no block found by the pool");
+    // submitted to the node by the pool, the blocks of the miners extend its chain
+    let mut mined = false;
+    for _ in 0..TIMEOUT.as_secs() {
+        if harness.block_count().unwrap() > height {
+            mined = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    assert!(mined, "the node is still at height {}", height);
+
+    harness.stop().await.unwrap();
+}